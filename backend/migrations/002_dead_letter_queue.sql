@@ -0,0 +1,20 @@
+-- FlowEx Dead-Letter Queue
+-- Version: 002
+-- Description: Park events whose handlers failed repeatedly so they can be inspected and replayed
+
+CREATE TABLE dead_letter_events (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    handler VARCHAR(20) NOT NULL CHECK (handler IN ('settlement', 'notification', 'projection', 'webhook')),
+    event_type VARCHAR(50) NOT NULL,
+    payload JSONB NOT NULL,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'replayed', 'discarded')),
+    first_failed_at TIMESTAMPTZ DEFAULT NOW(),
+    last_failed_at TIMESTAMPTZ DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_dead_letter_events_status ON dead_letter_events(status);
+CREATE INDEX idx_dead_letter_events_handler ON dead_letter_events(handler);
+CREATE INDEX idx_dead_letter_events_first_failed_at ON dead_letter_events(first_failed_at);
@@ -0,0 +1,8 @@
+-- FlowEx Trading Pair Minimum Notional
+-- Version: 003
+-- Description: Reject dust orders whose value (price * quantity) is below a per-pair minimum
+
+ALTER TABLE trading_pairs
+    ADD COLUMN min_notional DECIMAL(20,8) NOT NULL DEFAULT 0;
+
+UPDATE trading_pairs SET min_notional = 10 WHERE quote_asset = 'USDT';
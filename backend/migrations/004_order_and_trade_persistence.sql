@@ -0,0 +1,34 @@
+-- FlowEx Order and Trade Persistence
+-- Version: 004
+-- Description: Store every order field the matching engine uses and each trade's maker/taker detail,
+-- so the trading service can rebuild its books and serve history after a restart
+
+ALTER TABLE orders
+    ADD COLUMN post_only BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN display_quantity DECIMAL(20,8),
+    ADD COLUMN reduce_only BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN client_order_id VARCHAR(64);
+
+ALTER TABLE orders DROP CONSTRAINT orders_time_in_force_check;
+ALTER TABLE orders
+    ADD CONSTRAINT orders_time_in_force_check CHECK (time_in_force IN ('GTC', 'IOC', 'FOK', 'GTD'));
+
+ALTER TABLE trades
+    ADD COLUMN sequence BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN buyer_user_id UUID,
+    ADD COLUMN seller_user_id UUID,
+    ADD COLUMN is_buyer_maker BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN buyer_fee_currency VARCHAR(10),
+    ADD COLUMN seller_fee_currency VARCHAR(10);
+
+-- A retried client order id finds the original
+CREATE UNIQUE INDEX idx_orders_user_client_order_id ON orders(user_id, client_order_id)
+    WHERE client_order_id IS NOT NULL;
+-- Order history pages and the open orders reloaded on startup
+CREATE INDEX idx_orders_user_created_at ON orders(user_id, created_at DESC, id DESC);
+CREATE INDEX idx_orders_open ON orders(trading_pair, created_at)
+    WHERE status IN ('NEW', 'PARTIALLY_FILLED');
+-- Recent trades per symbol and each user's trade history
+CREATE INDEX idx_trades_symbol_sequence ON trades(symbol, sequence DESC);
+CREATE INDEX idx_trades_buyer_user_created_at ON trades(buyer_user_id, created_at DESC, id DESC);
+CREATE INDEX idx_trades_seller_user_created_at ON trades(seller_user_id, created_at DESC, id DESC);
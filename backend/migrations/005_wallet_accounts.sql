@@ -0,0 +1,48 @@
+-- FlowEx Wallet Accounts
+-- Version: 005
+-- Description: Give every user the wallet service sees an account of their own, provisioned on first use,
+-- and store the order reservations, settled trades and deposit/withdrawal detail the wallet rebuilds its ledger from
+
+CREATE TABLE wallet_accounts (
+    user_id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO wallet_accounts (user_id)
+    SELECT user_id FROM balances
+    UNION
+    SELECT user_id FROM transactions
+    ON CONFLICT DO NOTHING;
+
+-- Balances and transactions belong to wallet accounts, which need not have a registered user
+ALTER TABLE balances DROP CONSTRAINT balances_user_id_fkey;
+ALTER TABLE balances
+    ADD CONSTRAINT balances_user_id_fkey FOREIGN KEY (user_id) REFERENCES wallet_accounts(user_id) ON DELETE CASCADE;
+ALTER TABLE transactions DROP CONSTRAINT transactions_user_id_fkey;
+ALTER TABLE transactions
+    ADD CONSTRAINT transactions_user_id_fkey FOREIGN KEY (user_id) REFERENCES wallet_accounts(user_id) ON DELETE CASCADE;
+
+-- Withdrawal destination and why a withdrawal failed; external_id holds the on-chain transaction hash
+ALTER TABLE transactions
+    ADD COLUMN address VARCHAR(128),
+    ADD COLUMN failure_reason TEXT;
+
+-- A deposit is credited once per on-chain transaction
+CREATE INDEX idx_transactions_type_external_id ON transactions(transaction_type, external_id)
+    WHERE external_id IS NOT NULL;
+
+-- Funds still locked for each open order
+CREATE TABLE wallet_reservations (
+    order_id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES wallet_accounts(user_id) ON DELETE CASCADE,
+    currency VARCHAR(10) NOT NULL,
+    amount DECIMAL(20,8) NOT NULL CHECK (amount >= 0)
+);
+
+CREATE INDEX idx_wallet_reservations_user_id ON wallet_reservations(user_id);
+
+-- Trades already settled, so a replayed settlement is applied once
+CREATE TABLE wallet_settled_trades (
+    trade_id UUID PRIMARY KEY,
+    settled_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
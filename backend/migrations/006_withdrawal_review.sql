@@ -0,0 +1,15 @@
+-- FlowEx Withdrawal Review
+-- Version: 006
+-- Description: Record where each withdrawal stands in review, the admin approvals it needs and who approved or rejected it
+
+ALTER TABLE transactions
+    ADD COLUMN review_status VARCHAR(20) CHECK (review_status IN ('pending', 'approved', 'rejected')),
+    ADD COLUMN required_approvals INTEGER,
+    ADD COLUMN approved_by UUID[],
+    ADD COLUMN rejected_by UUID;
+
+-- The admin review queue
+CREATE INDEX idx_transactions_review_status ON transactions(review_status, created_at)
+    WHERE review_status = 'pending';
+-- Each user's withdrawals against their daily limits
+CREATE INDEX idx_transactions_user_type_created_at ON transactions(user_id, transaction_type, created_at DESC);
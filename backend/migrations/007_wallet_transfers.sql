@@ -0,0 +1,37 @@
+-- FlowEx Wallet Transfers
+-- Version: 007
+-- Description: Address accounts by the email their user last authenticated with, and book internal transfers
+-- between accounts as double-entry postings
+
+ALTER TABLE wallet_accounts ADD COLUMN email VARCHAR(255);
+
+CREATE INDEX idx_wallet_accounts_email ON wallet_accounts(LOWER(email));
+
+CREATE TABLE wallet_transfers (
+    id UUID PRIMARY KEY,
+    from_user_id UUID NOT NULL REFERENCES wallet_accounts(user_id) ON DELETE CASCADE,
+    to_user_id UUID NOT NULL REFERENCES wallet_accounts(user_id) ON DELETE CASCADE,
+    currency VARCHAR(10) NOT NULL,
+    amount DECIMAL(20,8) NOT NULL CHECK (amount > 0),
+    memo VARCHAR(256),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (from_user_id <> to_user_id)
+);
+
+CREATE INDEX idx_wallet_transfers_from_user ON wallet_transfers(from_user_id, created_at DESC);
+CREATE INDEX idx_wallet_transfers_to_user ON wallet_transfers(to_user_id, created_at DESC);
+
+-- Entries of each transfer: the debit of the sender and the credit of the recipient, summing to zero
+CREATE TABLE wallet_postings (
+    transfer_id UUID NOT NULL REFERENCES wallet_transfers(id) ON DELETE CASCADE,
+    entry INTEGER NOT NULL,
+    user_id UUID NOT NULL REFERENCES wallet_accounts(user_id) ON DELETE CASCADE,
+    currency VARCHAR(10) NOT NULL,
+    amount DECIMAL(20,8) NOT NULL,
+    PRIMARY KEY (transfer_id, entry)
+);
+
+ALTER TABLE transactions DROP CONSTRAINT transactions_transaction_type_check;
+ALTER TABLE transactions
+    ADD CONSTRAINT transactions_transaction_type_check
+    CHECK (transaction_type IN ('deposit', 'withdrawal', 'trade', 'fee', 'transfer'));
@@ -0,0 +1,26 @@
+-- FlowEx Wallet Assets
+-- Version: 008
+-- Description: Register the currencies the wallet accepts with their precision, withdrawal minimum and fee,
+-- funding switches and network, and record the fee taken from each withdrawal
+
+CREATE TABLE wallet_assets (
+    currency VARCHAR(10) PRIMARY KEY,
+    name VARCHAR(64) NOT NULL,
+    precision INTEGER NOT NULL CHECK (precision BETWEEN 0 AND 8),
+    min_withdrawal DECIMAL(20,8) NOT NULL CHECK (min_withdrawal > 0),
+    withdrawal_fee DECIMAL(20,8) NOT NULL CHECK (withdrawal_fee >= 0 AND withdrawal_fee < min_withdrawal),
+    deposit_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    withdrawal_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    network VARCHAR(64) NOT NULL,
+    confirmations INTEGER NOT NULL DEFAULT 0 CHECK (confirmations >= 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO wallet_assets (currency, name, precision, min_withdrawal, withdrawal_fee, network, confirmations) VALUES
+    ('BTC', 'Bitcoin', 8, 0.001, 0.0005, 'BTC', 2),
+    ('ETH', 'Ethereum', 8, 0.01, 0.005, 'ERC20', 12),
+    ('USDT', 'Tether USD', 6, 10, 1, 'ERC20', 12);
+
+-- Part of a withdrawal's amount taken as its fee
+ALTER TABLE transactions
+    ADD COLUMN fee DECIMAL(20,8) NOT NULL DEFAULT 0 CHECK (fee >= 0);
@@ -0,0 +1,34 @@
+-- FlowEx Wallet Reconciliation
+-- Version: 009
+-- Description: Keep every account's balances at the end of each business day and the report of reconciling
+-- the ledger against them
+
+CREATE TABLE wallet_balance_snapshots (
+    id UUID PRIMARY KEY,
+    business_date DATE NOT NULL,
+    taken_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_wallet_balance_snapshots_business_date ON wallet_balance_snapshots(business_date);
+
+CREATE TABLE wallet_snapshot_balances (
+    snapshot_id UUID NOT NULL REFERENCES wallet_balance_snapshots(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL,
+    currency VARCHAR(10) NOT NULL,
+    available DECIMAL(20,8) NOT NULL,
+    locked DECIMAL(20,8) NOT NULL,
+    PRIMARY KEY (snapshot_id, user_id, currency)
+);
+
+-- Totals by currency and the mismatches found, as JSON
+CREATE TABLE wallet_reconciliation_reports (
+    id UUID PRIMARY KEY,
+    snapshot_id UUID NOT NULL REFERENCES wallet_balance_snapshots(id) ON DELETE CASCADE,
+    business_date DATE NOT NULL,
+    taken_at TIMESTAMPTZ NOT NULL,
+    balanced BOOLEAN NOT NULL,
+    totals JSONB NOT NULL,
+    mismatches JSONB NOT NULL
+);
+
+CREATE INDEX idx_wallet_reconciliation_reports_taken_at ON wallet_reconciliation_reports(taken_at);
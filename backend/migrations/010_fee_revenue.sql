@@ -0,0 +1,23 @@
+-- FlowEx Fee Revenue
+-- Version: 010
+-- Description: Open the system accounts trading and withdrawal fees are paid into, and journal every fee paid
+-- into them with the trade or withdrawal it was charged on
+
+INSERT INTO wallet_accounts (user_id) VALUES
+    ('00000000-0000-0000-0000-000000000001'),
+    ('00000000-0000-0000-0000-000000000002')
+    ON CONFLICT DO NOTHING;
+
+-- A negative amount is a rebate paid out of the account
+CREATE TABLE wallet_fee_revenue (
+    id UUID PRIMARY KEY,
+    source VARCHAR(20) NOT NULL CHECK (source IN ('trading', 'withdrawal')),
+    currency VARCHAR(10) NOT NULL,
+    amount DECIMAL(20,8) NOT NULL,
+    user_id UUID NOT NULL REFERENCES wallet_accounts(user_id) ON DELETE CASCADE,
+    reference_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_wallet_fee_revenue_created_at ON wallet_fee_revenue(created_at);
+CREATE INDEX idx_wallet_fee_revenue_reference_id ON wallet_fee_revenue(reference_id);
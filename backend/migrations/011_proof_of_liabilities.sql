@@ -0,0 +1,22 @@
+-- FlowEx Proof of Liabilities
+-- Version: 011
+-- Description: Keep each published liability report, with the Merkle root and total of every currency, and
+-- the blinded user balances committed to it so inclusion proofs can be served after a restart
+
+CREATE TABLE wallet_liability_reports (
+    id UUID PRIMARY KEY,
+    taken_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    roots JSONB NOT NULL
+);
+
+CREATE INDEX idx_wallet_liability_reports_taken_at ON wallet_liability_reports(taken_at);
+
+-- The nonce blinds each balance; it is only disclosed to the balance's owner, in their proof
+CREATE TABLE wallet_liability_leaves (
+    report_id UUID NOT NULL REFERENCES wallet_liability_reports(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL,
+    currency VARCHAR(10) NOT NULL,
+    balance DECIMAL(20,8) NOT NULL CHECK (balance > 0),
+    nonce VARCHAR(64) NOT NULL,
+    PRIMARY KEY (report_id, user_id, currency)
+);
@@ -0,0 +1,37 @@
+-- FlowEx Treasury
+-- Version: 012
+-- Description: Open the hot and cold wallet accounts that track what the exchange holds in custody, record the
+-- funds admins move between them and the hot wallet float thresholds that call for a sweep or a refill
+
+INSERT INTO wallet_accounts (user_id) VALUES
+    ('00000000-0000-0000-0000-000000000003'),
+    ('00000000-0000-0000-0000-000000000004')
+    ON CONFLICT DO NOTHING;
+
+-- Custody starts out as everything the ledger holds, all of it in the hot wallet until admins record sweeps
+INSERT INTO balances (user_id, currency, available, locked)
+    SELECT '00000000-0000-0000-0000-000000000003', currency, SUM(available + locked), 0
+    FROM balances
+    GROUP BY currency
+    ON CONFLICT (user_id, currency) DO NOTHING;
+
+CREATE TABLE wallet_treasury_movements (
+    id UUID PRIMARY KEY,
+    from_wallet VARCHAR(10) NOT NULL CHECK (from_wallet IN ('hot', 'cold')),
+    to_wallet VARCHAR(10) NOT NULL CHECK (to_wallet IN ('hot', 'cold') AND to_wallet <> from_wallet),
+    currency VARCHAR(10) NOT NULL,
+    amount DECIMAL(20,8) NOT NULL CHECK (amount > 0),
+    tx_hash VARCHAR(128),
+    note VARCHAR(256),
+    recorded_by UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_wallet_treasury_movements_created_at ON wallet_treasury_movements(created_at);
+
+CREATE TABLE wallet_float_thresholds (
+    currency VARCHAR(10) PRIMARY KEY,
+    sweep_above DECIMAL(20,8) NOT NULL,
+    refill_below DECIMAL(20,8) NOT NULL DEFAULT 0 CHECK (refill_below >= 0 AND sweep_above > refill_below),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
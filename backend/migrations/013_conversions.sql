@@ -0,0 +1,28 @@
+-- FlowEx Conversions
+-- Version: 013
+-- Description: Open the conversion fee revenue account and the conversion desk's account, and record every
+-- conversion users execute against the desk
+
+INSERT INTO wallet_accounts (user_id) VALUES
+    ('00000000-0000-0000-0000-000000000005'),
+    ('00000000-0000-0000-0000-000000000006')
+    ON CONFLICT DO NOTHING;
+
+ALTER TABLE wallet_fee_revenue DROP CONSTRAINT wallet_fee_revenue_source_check;
+ALTER TABLE wallet_fee_revenue ADD CONSTRAINT wallet_fee_revenue_source_check
+    CHECK (source IN ('trading', 'withdrawal', 'conversion'));
+
+CREATE TABLE wallet_conversions (
+    id UUID PRIMARY KEY,
+    quote_id UUID NOT NULL,
+    user_id UUID NOT NULL REFERENCES wallet_accounts(user_id) ON DELETE CASCADE,
+    from_currency VARCHAR(10) NOT NULL,
+    to_currency VARCHAR(10) NOT NULL,
+    amount DECIMAL(20,8) NOT NULL CHECK (amount > 0),
+    rate DECIMAL(38,18) NOT NULL,
+    fee DECIMAL(20,8) NOT NULL,
+    receive DECIMAL(20,8) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_wallet_conversions_user_id ON wallet_conversions(user_id, created_at);
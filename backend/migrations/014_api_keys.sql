@@ -0,0 +1,19 @@
+-- FlowEx API Keys
+-- Version: 014
+-- Description: API keys users create for programmatic access, with the permissions they act with and the
+-- addresses they may be used from
+
+-- The secret is kept as issued: verifying a request's HMAC signature needs it
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    api_key VARCHAR(64) UNIQUE NOT NULL,
+    secret VARCHAR(128) NOT NULL,
+    label VARCHAR(64) NOT NULL,
+    permissions TEXT[] NOT NULL,
+    ip_allowlist TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_api_keys_user_id ON api_keys(user_id);
@@ -0,0 +1,27 @@
+-- FlowEx Roles
+-- Version: 015
+-- Description: Custom roles administrators define with the permissions they grant, and the roles assigned
+-- to each user
+
+-- Built-in roles are not stored; their names cannot be taken by custom roles
+CREATE TABLE roles (
+    name VARCHAR(50) PRIMARY KEY,
+    description TEXT NOT NULL DEFAULT '',
+    permissions TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Built-in or custom roles of each user; users.role is the role they registered with
+CREATE TABLE user_roles (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role VARCHAR(50) NOT NULL,
+    granted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, role)
+);
+
+CREATE INDEX idx_user_roles_role ON user_roles(role);
+
+INSERT INTO user_roles (user_id, role)
+SELECT id, role FROM users WHERE role IS NOT NULL
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,30 @@
+-- FlowEx Authentication Audit
+-- Version: 016
+-- Description: Append-only log of security events on user accounts, with where the request came from
+
+-- Not tied to users(id): entries outlive the account they concern, and failed logins may name no account
+CREATE TABLE auth_audit_events (
+    id UUID PRIMARY KEY,
+    user_id UUID,
+    actor_id UUID,
+    event VARCHAR(50) NOT NULL,
+    email VARCHAR(255),
+    ip_address VARCHAR(45),
+    user_agent TEXT,
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_auth_audit_events_user_id ON auth_audit_events(user_id, created_at DESC);
+CREATE INDEX idx_auth_audit_events_created_at ON auth_audit_events(created_at DESC);
+
+-- Entries are only ever added
+CREATE FUNCTION reject_auth_audit_change() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'auth_audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER auth_audit_events_append_only
+    BEFORE UPDATE OR DELETE ON auth_audit_events
+    FOR EACH ROW EXECUTE FUNCTION reject_auth_audit_change();
@@ -0,0 +1,15 @@
+-- FlowEx OAuth Identities
+-- Version: 017
+-- Description: Accounts at OAuth2 / OpenID Connect providers users sign in with, linked to their FlowEx user
+
+-- The subject is the provider's stable id of the account; the email is the one it had when linked
+CREATE TABLE oauth_identities (
+    provider VARCHAR(50) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    email VARCHAR(255),
+    linked_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX idx_oauth_identities_user_id ON oauth_identities(user_id);
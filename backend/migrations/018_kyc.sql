@@ -0,0 +1,34 @@
+-- FlowEx KYC
+-- Version: 018
+-- Description: Identity documents users upload and their applications to be verified to a KYC level
+
+CREATE TABLE kyc_documents (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    document_type VARCHAR(30) NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    size INTEGER NOT NULL,
+    content BYTEA NOT NULL,
+    uploaded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_kyc_documents_user_id ON kyc_documents(user_id);
+
+-- A user's level is the highest one an application of theirs was approved for
+CREATE TABLE kyc_applications (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    level VARCHAR(2) NOT NULL CHECK (level IN ('L1', 'L2')),
+    details JSONB NOT NULL,
+    document_ids UUID[] NOT NULL DEFAULT '{}',
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+    provider VARCHAR(50) NOT NULL,
+    provider_reference VARCHAR(255),
+    rejection_reason TEXT,
+    reviewed_by UUID REFERENCES users(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_kyc_applications_user_id ON kyc_applications(user_id);
+CREATE INDEX idx_kyc_applications_pending ON kyc_applications(created_at) WHERE status = 'pending';
@@ -0,0 +1,25 @@
+-- FlowEx Account Security
+-- Version: 019
+-- Description: Anti-phishing codes users set to recognise genuine notifications, and the addresses they whitelist
+-- for withdrawals, each usable from its activation time
+
+CREATE TABLE wallet_anti_phishing_codes (
+    user_id UUID PRIMARY KEY,
+    code VARCHAR(20) NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Withdrawals go only to active entries; an entry is removed rather than disabled, so adding it again restarts
+-- the activation delay
+CREATE TABLE wallet_address_whitelist (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL,
+    currency VARCHAR(10) NOT NULL,
+    address VARCHAR(128) NOT NULL,
+    label VARCHAR(64),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    active_at TIMESTAMPTZ NOT NULL CHECK (active_at >= created_at),
+    UNIQUE (user_id, currency, address)
+);
+
+CREATE INDEX idx_wallet_address_whitelist_user_id ON wallet_address_whitelist(user_id);
@@ -0,0 +1,16 @@
+-- FlowEx Sub-accounts
+-- Version: 020
+-- Description: Accounts masters open under them, each a user of its own, with the permissions delegated to the
+-- API keys masters create for them
+
+-- Sub-accounts cannot be masters themselves; the auth service refuses to nest them
+CREATE TABLE sub_accounts (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    master_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE CHECK (master_id <> user_id),
+    label VARCHAR(64) NOT NULL,
+    permissions TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_sub_accounts_master_label ON sub_accounts(master_id, LOWER(label));
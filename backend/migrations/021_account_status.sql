@@ -0,0 +1,8 @@
+-- FlowEx Account Status
+-- Version: 021
+-- Description: Whether a user's account is frozen, and the version their tokens are signed with, raised to log
+-- every token out
+
+ALTER TABLE users
+    ADD COLUMN status VARCHAR(10) NOT NULL DEFAULT 'active' CHECK (status IN ('active', 'frozen')),
+    ADD COLUMN token_version BIGINT NOT NULL DEFAULT 0 CHECK (token_version >= 0);
@@ -0,0 +1,10 @@
+-- FlowEx Order Limit Overrides
+-- Version: 023
+-- Description: Order limits an admin set for individual accounts in place of the service defaults
+
+CREATE TABLE order_limit_overrides (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    max_open_orders INTEGER NOT NULL CHECK (max_open_orders > 0),
+    max_symbol_exposure DECIMAL(20,8) NOT NULL CHECK (max_symbol_exposure > 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,250 @@
+//! API keys
+//!
+//! Users create API keys for programmatic trading, each scoped to some of
+//! `API_KEY_PERMISSIONS` and optionally to an allowlist of addresses. The
+//! key's secret is shown once, when it is created; services verify the
+//! requests it signs with `flowex_middleware::api_key`, fetching the
+//! secret and scopes from `GET /internal/api-keys/:api_key`. A revoked key
+//! is listed still but no longer verifies.
+
+use chrono::{DateTime, Utc};
+use flowex_middleware::api_key::ApiKeyCredential;
+use flowex_types::{FlowExError, FlowExResult, Permission};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::store::UserStore;
+
+/// Permissions an API key may act with
+pub const API_KEY_PERMISSIONS: [Permission; 3] = [Permission::TradingRead, Permission::TradingWrite, Permission::WalletRead];
+
+/// Most active keys a user may hold
+const MAX_KEYS_PER_USER: usize = 20;
+
+/// Longest key label
+const MAX_LABEL_LEN: usize = 64;
+
+/// API key creation request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub label: String,
+    pub permissions: Vec<String>,
+    /// Addresses the key may be used from; any if empty
+    #[serde(default)]
+    pub ip_allowlist: Vec<IpAddr>,
+}
+
+/// An API key, without its secret
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// Public identifier sent with every request
+    pub api_key: String,
+    pub label: String,
+    pub permissions: Vec<String>,
+    pub ip_allowlist: Vec<IpAddr>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// An API key with its secret and its owner's email
+#[derive(Debug, Clone)]
+pub struct ApiKeyRecord {
+    pub key: ApiKey,
+    pub email: String,
+    pub secret: String,
+}
+
+/// A key just created, with the secret it signs requests with
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    pub secret: String,
+}
+
+/// API keys of every user, written through to the store
+#[derive(Clone)]
+pub struct ApiKeys {
+    store: UserStore,
+    /// By public key
+    keys: Arc<RwLock<HashMap<String, ApiKeyRecord>>>,
+}
+
+impl ApiKeys {
+    pub fn new(store: UserStore, records: Vec<ApiKeyRecord>) -> Self {
+        let keys = records
+            .into_iter()
+            .map(|record| (record.key.api_key.clone(), record))
+            .collect();
+        Self {
+            store,
+            keys: Arc::new(RwLock::new(keys)),
+        }
+    }
+
+    /// Create a key for `user_id`
+    pub async fn create(&self, user_id: Uuid, email: &str, request: CreateApiKeyRequest) -> FlowExResult<CreatedApiKey> {
+        let label = request.label.trim().to_string();
+        if label.is_empty() || label.chars().count() > MAX_LABEL_LEN {
+            return Err(FlowExError::Validation(format!(
+                "Label must be 1 to {} characters",
+                MAX_LABEL_LEN
+            )));
+        }
+        let permissions = validate_permissions(request.permissions)?;
+        let mut ip_allowlist = request.ip_allowlist;
+        ip_allowlist.sort();
+        ip_allowlist.dedup();
+
+        let record = ApiKeyRecord {
+            key: ApiKey {
+                id: Uuid::new_v4(),
+                user_id,
+                api_key: random_hex(16),
+                label,
+                permissions,
+                ip_allowlist,
+                created_at: Utc::now(),
+                revoked_at: None,
+            },
+            email: email.to_string(),
+            secret: random_hex(32),
+        };
+        let mut keys = self.keys.write().await;
+        let active = keys
+            .values()
+            .filter(|record| record.key.user_id == user_id && record.key.revoked_at.is_none())
+            .count();
+        if active >= MAX_KEYS_PER_USER {
+            return Err(FlowExError::Validation(format!(
+                "A user may hold at most {} API keys",
+                MAX_KEYS_PER_USER
+            )));
+        }
+        self.store.insert_api_key(&record).await?;
+        info!("Created API key {} for user {}", record.key.id, user_id);
+        keys.insert(record.key.api_key.clone(), record.clone());
+        Ok(CreatedApiKey {
+            key: record.key,
+            secret: record.secret,
+        })
+    }
+
+    /// `user_id`'s keys, revoked ones included, newest first
+    pub async fn list(&self, user_id: Uuid) -> Vec<ApiKey> {
+        let mut keys: Vec<ApiKey> = self
+            .keys
+            .read()
+            .await
+            .values()
+            .filter(|record| record.key.user_id == user_id)
+            .map(|record| record.key.clone())
+            .collect();
+        keys.sort_by_key(|key| std::cmp::Reverse(key.created_at));
+        keys
+    }
+
+    /// Revoke one of `user_id`'s keys; `None` if they hold no such key
+    pub async fn revoke(&self, user_id: Uuid, id: Uuid) -> FlowExResult<Option<ApiKey>> {
+        let mut keys = self.keys.write().await;
+        let Some(record) = keys
+            .values_mut()
+            .find(|record| record.key.id == id && record.key.user_id == user_id)
+        else {
+            return Ok(None);
+        };
+        if record.key.revoked_at.is_none() {
+            let revoked_at = Utc::now();
+            self.store.revoke_api_key(id, revoked_at).await?;
+            record.key.revoked_at = Some(revoked_at);
+            info!("Revoked API key {} of user {}", id, user_id);
+        }
+        Ok(Some(record.key.clone()))
+    }
+
+    /// What services need to verify requests signed with `api_key`; `None`
+    /// if it is unknown or revoked
+    pub async fn credential(&self, api_key: &str) -> Option<ApiKeyCredential> {
+        let keys = self.keys.read().await;
+        let record = keys.get(api_key).filter(|record| record.key.revoked_at.is_none())?;
+        Some(ApiKeyCredential {
+            api_key: record.key.api_key.clone(),
+            user_id: record.key.user_id,
+            email: record.email.clone(),
+            secret: record.secret.clone(),
+            permissions: record.key.permissions.clone(),
+            ip_allowlist: record.key.ip_allowlist.clone(),
+        })
+    }
+}
+
+/// `permissions` without duplicates, if there is at least one and an API
+/// key may hold each
+pub fn validate_permissions(permissions: Vec<String>) -> FlowExResult<Vec<String>> {
+    if permissions.is_empty() {
+        return Err(FlowExError::Validation("At least one permission is needed".to_string()));
+    }
+    let mut validated = Vec::new();
+    for permission in permissions {
+        if !API_KEY_PERMISSIONS.iter().any(|allowed| allowed.as_str() == permission) {
+            return Err(FlowExError::Validation(format!(
+                "API keys may not hold the {} permission",
+                permission
+            )));
+        }
+        if !validated.contains(&permission) {
+            validated.push(permission);
+        }
+    }
+    Ok(validated)
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buffer);
+    hex::encode(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：创建 API 密钥时校验权限范围，撤销后不再返回凭据，用户只能撤销自己的密钥
+    #[tokio::test]
+    async fn test_create_and_revoke_api_keys() {
+        let keys = ApiKeys::new(UserStore::Memory, Vec::new());
+        let user_id = Uuid::new_v4();
+        let request = |permissions: &[&str]| CreateApiKeyRequest {
+            label: "bot".to_string(),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            ip_allowlist: vec!["10.0.0.1".parse().unwrap()],
+        };
+
+        let created = keys
+            .create(user_id, "bot@example.com", request(&["trading:read", "trading:write", "trading:read"]))
+            .await
+            .unwrap();
+        assert_eq!(created.key.permissions, vec!["trading:read", "trading:write"]);
+        assert_eq!((created.key.api_key.len(), created.secret.len()), (32, 64));
+        assert!(keys.create(user_id, "bot@example.com", request(&["wallet:withdraw"])).await.is_err());
+        assert!(keys.create(user_id, "bot@example.com", request(&[])).await.is_err());
+
+        let credential = keys.credential(&created.key.api_key).await.unwrap();
+        assert_eq!((credential.user_id, credential.secret.as_str()), (user_id, created.secret.as_str()));
+        assert_eq!(keys.list(user_id).await.len(), 1);
+
+        assert!(keys.revoke(Uuid::new_v4(), created.key.id).await.unwrap().is_none());
+        let revoked = keys.revoke(user_id, created.key.id).await.unwrap().unwrap();
+        assert!(revoked.revoked_at.is_some());
+        assert!(keys.credential(&created.key.api_key).await.is_none());
+        assert!(keys.list(user_id).await[0].revoked_at.is_some());
+    }
+}
@@ -0,0 +1,262 @@
+//! Security audit log
+//!
+//! Every security-relevant event on an account — registering, logging in
+//! or failing to, linking an OAuth identity, changing the password,
+//! creating or revoking an API key, roles being defined or assigned, and
+//! applying for identity verification and its review — is appended to the
+//! audit log with the address and user agent of the request that caused
+//! it. Entries are never changed or removed: in
+//! PostgreSQL the `auth_audit_events` table refuses updates and deletes.
+//! Without a database the latest `MAX_MEMORY_ENTRIES` are kept in memory.
+//!
+//! An event that cannot be written is logged as an error rather than
+//! failing the request it records. There is no two-factor authentication
+//! to audit changes of yet.
+
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use flowex_middleware::api_key::client_ip;
+use flowex_types::{FlowExError, FlowExResult};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::store::UserStore;
+
+/// Entries kept without a database, oldest dropped first
+pub const MAX_MEMORY_ENTRIES: usize = 10_000;
+
+/// Kind of an audited event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    Registered,
+    Login,
+    LoginFailed,
+    IdentityLinked,
+    PasswordChanged,
+    ApiKeyCreated,
+    ApiKeyRevoked,
+    RoleDefined,
+    RoleDeleted,
+    RolesAssigned,
+    KycSubmitted,
+    KycReviewed,
+    SubAccountCreated,
+    SubAccountPermissionsChanged,
+    AccountStatusChanged,
+    ForcedLogout,
+}
+
+impl AuditEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::Registered => "registered",
+            AuditEvent::Login => "login",
+            AuditEvent::LoginFailed => "login_failed",
+            AuditEvent::IdentityLinked => "identity_linked",
+            AuditEvent::PasswordChanged => "password_changed",
+            AuditEvent::ApiKeyCreated => "api_key_created",
+            AuditEvent::ApiKeyRevoked => "api_key_revoked",
+            AuditEvent::RoleDefined => "role_defined",
+            AuditEvent::RoleDeleted => "role_deleted",
+            AuditEvent::RolesAssigned => "roles_assigned",
+            AuditEvent::KycSubmitted => "kyc_submitted",
+            AuditEvent::KycReviewed => "kyc_reviewed",
+            AuditEvent::SubAccountCreated => "sub_account_created",
+            AuditEvent::SubAccountPermissionsChanged => "sub_account_permissions_changed",
+            AuditEvent::AccountStatusChanged => "account_status_changed",
+            AuditEvent::ForcedLogout => "forced_logout",
+        }
+    }
+}
+
+impl FromStr for AuditEvent {
+    type Err = FlowExError;
+
+    fn from_str(s: &str) -> FlowExResult<Self> {
+        match s {
+            "registered" => Ok(AuditEvent::Registered),
+            "login" => Ok(AuditEvent::Login),
+            "login_failed" => Ok(AuditEvent::LoginFailed),
+            "identity_linked" => Ok(AuditEvent::IdentityLinked),
+            "password_changed" => Ok(AuditEvent::PasswordChanged),
+            "api_key_created" => Ok(AuditEvent::ApiKeyCreated),
+            "api_key_revoked" => Ok(AuditEvent::ApiKeyRevoked),
+            "role_defined" => Ok(AuditEvent::RoleDefined),
+            "role_deleted" => Ok(AuditEvent::RoleDeleted),
+            "roles_assigned" => Ok(AuditEvent::RolesAssigned),
+            "kyc_submitted" => Ok(AuditEvent::KycSubmitted),
+            "kyc_reviewed" => Ok(AuditEvent::KycReviewed),
+            "sub_account_created" => Ok(AuditEvent::SubAccountCreated),
+            "sub_account_permissions_changed" => Ok(AuditEvent::SubAccountPermissionsChanged),
+            "account_status_changed" => Ok(AuditEvent::AccountStatusChanged),
+            "forced_logout" => Ok(AuditEvent::ForcedLogout),
+            _ => Err(FlowExError::Validation(format!("Invalid audit event: {}", s))),
+        }
+    }
+}
+
+/// Where a request came from
+#[derive(Debug, Clone, Default)]
+pub struct RequestMetadata {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl RequestMetadata {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            ip_address: client_ip(headers).map(|ip| ip.to_string()),
+            user_agent: headers
+                .get("user-agent")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// An audited event
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    /// Account the event concerns, if it names a registered one
+    pub user_id: Option<Uuid>,
+    /// Administrator who acted on the account, if not its user
+    pub actor_id: Option<Uuid>,
+    pub event: AuditEvent,
+    /// Email the request gave or the account has
+    pub email: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// What changed, by event
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuditEntry {
+    /// `event`, caused by a request from `metadata`
+    pub fn new(event: AuditEvent, metadata: &RequestMetadata) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            user_id: None,
+            actor_id: None,
+            event,
+            email: None,
+            ip_address: metadata.ip_address.clone(),
+            user_agent: metadata.user_agent.clone(),
+            details: serde_json::Value::Object(Default::default()),
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Concerning the account `user_id`, if known, with `email`
+    pub fn user(mut self, user_id: Option<Uuid>, email: &str) -> Self {
+        self.user_id = user_id;
+        self.email = Some(email.to_string());
+        self
+    }
+
+    /// Done to the account by the administrator `actor_id`
+    pub fn actor(mut self, actor_id: Uuid) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// Append-only log of audited events
+#[derive(Clone)]
+pub struct AuditLog {
+    store: UserStore,
+    /// Oldest first, without a database
+    entries: Arc<RwLock<VecDeque<AuditEntry>>>,
+}
+
+impl AuditLog {
+    pub fn new(store: UserStore) -> Self {
+        Self {
+            store,
+            entries: Arc::new(RwLock::new(VecDeque::new())),
+        }
+    }
+
+    /// Append `entry`
+    pub async fn record(&self, entry: AuditEntry) {
+        if let UserStore::Memory = self.store {
+            let mut entries = self.entries.write().await;
+            if entries.len() == MAX_MEMORY_ENTRIES {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        } else if let Err(e) = self.store.append_audit(&entry).await {
+            error!("Failed to record {} audit event {}: {}", entry.event.as_str(), entry.id, e);
+        }
+    }
+
+    /// Latest entries, concerning `user_id` only if given, newest first
+    pub async fn list(&self, user_id: Option<Uuid>, limit: usize) -> FlowExResult<Vec<AuditEntry>> {
+        if let Some(entries) = self.store.load_audit(user_id, limit).await? {
+            return Ok(entries);
+        }
+        Ok(self
+            .entries
+            .read()
+            .await
+            .iter()
+            .rev()
+            .filter(|entry| user_id.is_none() || entry.user_id == user_id)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：审计记录按时间倒序列出，可按用户过滤，内存中只保留有限条数
+    #[tokio::test]
+    async fn test_record_and_list_audit_entries() {
+        let audit = AuditLog::new(UserStore::Memory);
+        let user_id = Uuid::new_v4();
+        let mut headers = HeaderMap::new();
+        headers.insert("user-agent", "flowex-test".parse().unwrap());
+        let metadata = RequestMetadata::from_headers(&headers);
+
+        audit
+            .record(AuditEntry::new(AuditEvent::LoginFailed, &metadata).user(None, "nobody@example.com"))
+            .await;
+        audit.record(AuditEntry::new(AuditEvent::Login, &metadata).user(Some(user_id), "audit@example.com")).await;
+        audit
+            .record(
+                AuditEntry::new(AuditEvent::RolesAssigned, &metadata)
+                    .user(Some(user_id), "audit@example.com")
+                    .actor(Uuid::new_v4())
+                    .details(serde_json::json!({ "roles": ["trader"] })),
+            )
+            .await;
+
+        let own = audit.list(Some(user_id), 10).await.unwrap();
+        assert_eq!(own.iter().map(|entry| entry.event).collect::<Vec<_>>(), vec![AuditEvent::RolesAssigned, AuditEvent::Login]);
+        assert_eq!(own[1].user_agent.as_deref(), Some("flowex-test"));
+        assert_eq!(audit.list(None, 10).await.unwrap().len(), 3);
+        assert_eq!(audit.list(None, 1).await.unwrap()[0].event, AuditEvent::RolesAssigned);
+        assert_eq!("roles_assigned".parse::<AuditEvent>().unwrap(), AuditEvent::RolesAssigned);
+
+        for _ in 0..MAX_MEMORY_ENTRIES {
+            audit.record(AuditEntry::new(AuditEvent::Registered, &metadata)).await;
+        }
+        assert_eq!(audit.list(None, usize::MAX).await.unwrap().len(), MAX_MEMORY_ENTRIES);
+        assert!(audit.list(Some(user_id), 10).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,459 @@
+//! Identity verification
+//!
+//! Users upload their documents, base64 encoded, and apply to be verified
+//! to a `KycLevel` with their personal details and the documents the level
+//! requires (see `flowex_kyc::check_requirements`). The application goes to
+//! the configured `KycProvider`, which approves or rejects it or leaves it
+//! pending for an administrator to review. A user has one pending
+//! application at a time; their level is the highest one an application of
+//! theirs was approved for, and other services learn it at
+//! `/internal/kyc/:user_id`.
+//!
+//! Documents and applications are written through to the store. Only what
+//! documents were uploaded is kept in memory; their content is read back
+//! from the store to submit them to the provider.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use flowex_kyc::{
+    check_requirements, verified_level, ApplicationStatus, DocumentType, KycApplication, KycDocument, KycLevel,
+    KycProvider, ManualReview, PersonalDetails, ProviderDecision, ProviderVerdict,
+};
+use flowex_types::{FlowExError, FlowExResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::store::UserStore;
+
+/// Largest document accepted, in bytes
+pub const MAX_DOCUMENT_BYTES: usize = 1024 * 1024;
+
+/// Document upload request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadDocumentRequest {
+    pub document_type: DocumentType,
+    /// Base64 of the file
+    pub content: String,
+}
+
+/// Verification application request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApplyRequest {
+    pub level: KycLevel,
+    pub details: PersonalDetails,
+    #[serde(default)]
+    pub document_ids: Vec<Uuid>,
+}
+
+/// Application rejection request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct RejectApplicationRequest {
+    pub reason: String,
+}
+
+/// A user's level, applications and documents
+#[derive(Debug, Clone, Serialize)]
+pub struct KycOverview {
+    pub level: KycLevel,
+    /// Newest first
+    pub applications: Vec<KycApplication>,
+    pub documents: Vec<KycDocument>,
+}
+
+/// Documents and applications of every user, written through to the store
+#[derive(Clone)]
+pub struct Kyc {
+    store: UserStore,
+    provider: Arc<dyn KycProvider>,
+    /// By id
+    documents: Arc<RwLock<HashMap<Uuid, KycDocument>>>,
+    /// By id
+    applications: Arc<RwLock<HashMap<Uuid, KycApplication>>>,
+}
+
+impl Kyc {
+    /// Applications reviewed manually, the documents and applications in
+    /// `store`, starting from `documents` and `applications`
+    pub fn new(store: UserStore, documents: Vec<KycDocument>, applications: Vec<KycApplication>) -> Self {
+        Self {
+            store,
+            provider: Arc::new(ManualReview),
+            documents: Arc::new(RwLock::new(
+                documents.into_iter().map(|document| (document.id, document)).collect(),
+            )),
+            applications: Arc::new(RwLock::new(
+                applications.into_iter().map(|application| (application.id, application)).collect(),
+            )),
+        }
+    }
+
+    /// Submit applications to `provider`
+    pub fn with_provider(mut self, provider: Arc<dyn KycProvider>) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// Level `user_id` is verified to
+    pub async fn level(&self, user_id: Uuid) -> KycLevel {
+        verified_level(
+            self.applications
+                .read()
+                .await
+                .values()
+                .filter(|application| application.user_id == user_id),
+        )
+    }
+
+    /// Level, applications and documents of `user_id`
+    pub async fn overview(&self, user_id: Uuid) -> KycOverview {
+        let mut applications: Vec<KycApplication> = self
+            .applications
+            .read()
+            .await
+            .values()
+            .filter(|application| application.user_id == user_id)
+            .cloned()
+            .collect();
+        applications.sort_by_key(|application| std::cmp::Reverse(application.created_at));
+        let mut documents: Vec<KycDocument> = self
+            .documents
+            .read()
+            .await
+            .values()
+            .filter(|document| document.user_id == user_id)
+            .cloned()
+            .collect();
+        documents.sort_by_key(|document| document.uploaded_at);
+        KycOverview {
+            level: verified_level(&applications),
+            applications,
+            documents,
+        }
+    }
+
+    /// Upload a document for `user_id`
+    pub async fn upload(&self, user_id: Uuid, request: UploadDocumentRequest) -> FlowExResult<KycDocument> {
+        let content = STANDARD
+            .decode(request.content.trim())
+            .map_err(|_| FlowExError::Validation("Document content is not valid base64".to_string()))?;
+        if content.is_empty() || content.len() > MAX_DOCUMENT_BYTES {
+            return Err(FlowExError::Validation(format!(
+                "Documents must be 1 to {} bytes long",
+                MAX_DOCUMENT_BYTES
+            )));
+        }
+        let mut document = KycDocument {
+            id: Uuid::new_v4(),
+            user_id,
+            document_type: request.document_type,
+            sha256: hex::encode(Sha256::digest(&content)),
+            size: content.len(),
+            uploaded_at: Utc::now(),
+            content,
+        };
+        self.store.insert_kyc_document(&document).await?;
+        if !matches!(self.store, UserStore::Memory) {
+            document.content = Vec::new();
+        }
+        info!("User {} uploaded {} document {}", user_id, document.document_type.as_str(), document.id);
+        self.documents.write().await.insert(document.id, document.clone());
+        Ok(document)
+    }
+
+    /// Apply for `user_id` to be verified to a level, submitting the
+    /// application to the provider. A provider that fails leaves it pending
+    /// for review.
+    pub async fn apply(&self, user_id: Uuid, request: ApplyRequest) -> FlowExResult<KycApplication> {
+        request.details.validate(Utc::now().date_naive())?;
+        let documents = self.documents_of(user_id, &request.document_ids).await?;
+        let application = {
+            let mut applications = self.applications.write().await;
+            let own = || applications.values().filter(|application| application.user_id == user_id);
+            if own().any(|application| application.status == ApplicationStatus::Pending) {
+                return Err(FlowExError::Validation("An application is already pending".to_string()));
+            }
+            check_requirements(request.level, verified_level(own()), &documents)?;
+
+            let now = Utc::now();
+            let application = KycApplication {
+                id: Uuid::new_v4(),
+                user_id,
+                level: request.level,
+                details: request.details,
+                document_ids: documents.iter().map(|document| document.id).collect(),
+                status: ApplicationStatus::Pending,
+                provider: self.provider.name().to_string(),
+                provider_reference: None,
+                rejection_reason: None,
+                reviewed_by: None,
+                created_at: now,
+                updated_at: now,
+            };
+            self.store.upsert_kyc_application(&application).await?;
+            applications.insert(application.id, application.clone());
+            application
+        };
+        info!("User {} applied for KYC {} ({})", user_id, application.level.as_str(), application.id);
+
+        let documents = self.with_content(documents).await?;
+        match self.provider.verify(&application, &documents).await {
+            Ok(verdict) => self.apply_verdict(application, verdict).await,
+            Err(e) => {
+                warn!("KYC provider {} failed on application {}: {}", self.provider.name(), application.id, e);
+                Ok(application)
+            }
+        }
+    }
+
+    /// Pending applications, oldest first
+    pub async fn review_queue(&self) -> Vec<KycApplication> {
+        let mut pending: Vec<KycApplication> = self
+            .applications
+            .read()
+            .await
+            .values()
+            .filter(|application| application.status == ApplicationStatus::Pending)
+            .cloned()
+            .collect();
+        pending.sort_by_key(|application| application.created_at);
+        pending
+    }
+
+    /// Approve a pending application as the administrator `reviewer`;
+    /// `None` if there is no such application
+    pub async fn approve(&self, id: Uuid, reviewer: Uuid) -> FlowExResult<Option<KycApplication>> {
+        self.decide(id, ApplicationStatus::Approved, None, Some(reviewer), None).await
+    }
+
+    /// Reject a pending application as the administrator `reviewer`;
+    /// `None` if there is no such application
+    pub async fn reject(&self, id: Uuid, reviewer: Uuid, reason: String) -> FlowExResult<Option<KycApplication>> {
+        if reason.trim().is_empty() {
+            return Err(FlowExError::Validation("A rejection needs a reason".to_string()));
+        }
+        self.decide(id, ApplicationStatus::Rejected, Some(reason), Some(reviewer), None).await
+    }
+
+    async fn apply_verdict(&self, application: KycApplication, verdict: ProviderVerdict) -> FlowExResult<KycApplication> {
+        let (status, reason) = match verdict.decision {
+            ProviderDecision::Approved => (ApplicationStatus::Approved, None),
+            ProviderDecision::Rejected { reason } => (ApplicationStatus::Rejected, Some(reason)),
+            ProviderDecision::Review => (ApplicationStatus::Pending, None),
+        };
+        if status == ApplicationStatus::Pending && verdict.reference.is_none() {
+            return Ok(application);
+        }
+        Ok(self
+            .decide(application.id, status, reason, None, verdict.reference)
+            .await?
+            .unwrap_or(application))
+    }
+
+    /// Record the decision on a pending application
+    async fn decide(
+        &self,
+        id: Uuid,
+        status: ApplicationStatus,
+        reason: Option<String>,
+        reviewer: Option<Uuid>,
+        reference: Option<String>,
+    ) -> FlowExResult<Option<KycApplication>> {
+        let mut applications = self.applications.write().await;
+        let Some(current) = applications.get(&id) else {
+            return Ok(None);
+        };
+        if current.status != ApplicationStatus::Pending {
+            return Err(FlowExError::Validation(format!(
+                "Application {} was already {}",
+                id,
+                current.status.as_str()
+            )));
+        }
+        let mut application = current.clone();
+        application.status = status;
+        application.rejection_reason = reason;
+        application.reviewed_by = reviewer;
+        application.provider_reference = reference.or(application.provider_reference);
+        application.updated_at = Utc::now();
+        self.store.upsert_kyc_application(&application).await?;
+        if status != ApplicationStatus::Pending {
+            info!(
+                "KYC application {} of user {} for {} {}",
+                id,
+                application.user_id,
+                application.level.as_str(),
+                status.as_str()
+            );
+        }
+        applications.insert(id, application.clone());
+        Ok(Some(application))
+    }
+
+    /// Documents `ids` of `user_id`, each once
+    async fn documents_of(&self, user_id: Uuid, ids: &[Uuid]) -> FlowExResult<Vec<KycDocument>> {
+        let documents = self.documents.read().await;
+        let mut found: Vec<KycDocument> = Vec::new();
+        for id in ids {
+            match documents.get(id) {
+                Some(document) if document.user_id == user_id => {
+                    if !found.iter().any(|document| document.id == *id) {
+                        found.push(document.clone());
+                    }
+                }
+                _ => return Err(FlowExError::Validation(format!("Unknown document {}", id))),
+            }
+        }
+        Ok(found)
+    }
+
+    /// `documents`, with their content read back from the store
+    async fn with_content(&self, mut documents: Vec<KycDocument>) -> FlowExResult<Vec<KycDocument>> {
+        let missing: Vec<Uuid> = documents
+            .iter()
+            .filter(|document| document.content.is_empty())
+            .map(|document| document.id)
+            .collect();
+        if missing.is_empty() {
+            return Ok(documents);
+        }
+        let mut contents: HashMap<Uuid, Vec<u8>> = self.store.kyc_document_contents(&missing).await?.into_iter().collect();
+        for document in &mut documents {
+            if let Some(content) = contents.remove(&document.id) {
+                document.content = content;
+            }
+        }
+        Ok(documents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Provider approving L1 and rejecting everything else
+    #[derive(Debug)]
+    struct L1Only;
+
+    #[async_trait::async_trait]
+    impl KycProvider for L1Only {
+        fn name(&self) -> &str {
+            "l1-only"
+        }
+
+        async fn verify(&self, application: &KycApplication, documents: &[KycDocument]) -> FlowExResult<ProviderVerdict> {
+            assert!(documents.iter().all(|document| !document.content.is_empty()));
+            let decision = match application.level {
+                KycLevel::L1 => ProviderDecision::Approved,
+                _ => ProviderDecision::Rejected {
+                    reason: "unreadable document".to_string(),
+                },
+            };
+            Ok(ProviderVerdict {
+                decision,
+                reference: Some(format!("chk_{}", application.id)),
+            })
+        }
+    }
+
+    fn details() -> PersonalDetails {
+        PersonalDetails {
+            first_name: "Jane".to_string(),
+            last_name: "Doe".to_string(),
+            date_of_birth: chrono::NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+            country: "DE".to_string(),
+            address: None,
+        }
+    }
+
+    /// 测试：上传证件并申请认证，管理员审核通过后级别提升，待审申请只能有一个
+    #[tokio::test]
+    async fn test_apply_and_review() {
+        let kyc = Kyc::new(UserStore::Memory, Vec::new(), Vec::new());
+        let user_id = Uuid::new_v4();
+        let admin_id = Uuid::new_v4();
+        assert_eq!(kyc.level(user_id).await, KycLevel::L0);
+
+        let upload = |document_type, content: &[u8]| UploadDocumentRequest {
+            document_type,
+            content: STANDARD.encode(content),
+        };
+        assert!(kyc.upload(user_id, upload(DocumentType::Passport, b"")).await.is_err());
+        let passport = kyc.upload(user_id, upload(DocumentType::Passport, b"passport scan")).await.unwrap();
+        assert_eq!(passport.sha256, hex::encode(Sha256::digest(b"passport scan")));
+        let selfie = kyc.upload(user_id, upload(DocumentType::Selfie, b"selfie")).await.unwrap();
+
+        let apply = |level, document_ids| ApplyRequest {
+            level,
+            details: details(),
+            document_ids,
+        };
+        // L2 needs a selfie or proof of address besides the passport
+        assert!(kyc.apply(user_id, apply(KycLevel::L2, vec![passport.id])).await.is_err());
+        // Documents of other users are not theirs to submit
+        assert!(kyc.apply(Uuid::new_v4(), apply(KycLevel::L2, vec![passport.id, selfie.id])).await.is_err());
+
+        let application = kyc.apply(user_id, apply(KycLevel::L2, vec![passport.id, selfie.id])).await.unwrap();
+        assert_eq!(application.status, ApplicationStatus::Pending);
+        assert_eq!(application.provider, "manual");
+        assert!(kyc.apply(user_id, apply(KycLevel::L1, Vec::new())).await.is_err());
+        assert_eq!(kyc.review_queue().await.len(), 1);
+
+        let approved = kyc.approve(application.id, admin_id).await.unwrap().unwrap();
+        assert_eq!(approved.status, ApplicationStatus::Approved);
+        assert_eq!(approved.reviewed_by, Some(admin_id));
+        assert_eq!(kyc.level(user_id).await, KycLevel::L2);
+        assert!(kyc.review_queue().await.is_empty());
+        assert!(kyc.reject(application.id, admin_id, "late".to_string()).await.is_err());
+        assert!(kyc.approve(Uuid::new_v4(), admin_id).await.unwrap().is_none());
+
+        let overview = kyc.overview(user_id).await;
+        assert_eq!(overview.level, KycLevel::L2);
+        assert_eq!((overview.applications.len(), overview.documents.len()), (1, 2));
+        // Nothing left to apply for
+        assert!(kyc.apply(user_id, apply(KycLevel::L2, vec![passport.id, selfie.id])).await.is_err());
+    }
+
+    /// 测试：供应商自动审核的结论直接生效
+    #[tokio::test]
+    async fn test_provider_verdicts() {
+        let kyc = Kyc::new(UserStore::Memory, Vec::new(), Vec::new()).with_provider(Arc::new(L1Only));
+        let user_id = Uuid::new_v4();
+        let upload = |document_type| UploadDocumentRequest {
+            document_type,
+            content: STANDARD.encode(b"scan"),
+        };
+        let passport = kyc.upload(user_id, upload(DocumentType::Passport)).await.unwrap();
+        let address = kyc.upload(user_id, upload(DocumentType::ProofOfAddress)).await.unwrap();
+
+        let application = ApplyRequest {
+            level: KycLevel::L1,
+            details: details(),
+            document_ids: Vec::new(),
+        };
+        let approved = kyc.apply(user_id, application.clone()).await.unwrap();
+        assert_eq!(approved.status, ApplicationStatus::Approved);
+        assert_eq!(approved.provider_reference, Some(format!("chk_{}", approved.id)));
+        assert_eq!(kyc.level(user_id).await, KycLevel::L1);
+
+        let rejected = kyc
+            .apply(
+                user_id,
+                ApplyRequest {
+                    level: KycLevel::L2,
+                    document_ids: vec![passport.id, address.id],
+                    ..application
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(rejected.status, ApplicationStatus::Rejected);
+        assert_eq!(rejected.rejection_reason.as_deref(), Some("unreadable document"));
+        assert_eq!(kyc.level(user_id).await, KycLevel::L1);
+    }
+}
@@ -2,54 +2,233 @@
 //! 
 //! Enterprise-grade authentication service with JWT tokens,
 //! password hashing, and comprehensive security features.
-
+//!
+//! Users register with a password whose bcrypt hash is all that is kept,
+//! and log in by proving it (see `users`). Users are kept in PostgreSQL
+//! when `FLOWEX_DATABASE_URL` is set, otherwise in memory with a demo user
+//! (see `store`).
+//!
+//! Logging in or registering returns an access token and a refresh token,
+//! which `POST /api/auth/refresh` rotates for a new pair and
+//! `POST /api/auth/logout` revokes (see `tokens`). Tokens are signed with
+//! RS256 keys read from the PEM files listed in `JWT_PRIVATE_KEY_PATHS`,
+//! the last signing, or else generated by the instance and rotated every
+//! `JWT_KEY_ROTATION_DAYS`; services verify them against
+//! `/.well-known/jwks.json`. Each login is a session
+//! users list under `/api/auth/sessions` and revoke one by one, or all at
+//! once by changing their password at `POST /api/auth/password` (see
+//! `sessions`).
+//!
+//! Users holding an access token manage their API keys under
+//! `/api/auth/api-keys`; other services look the keys up at the internal
+//! `/internal/api-keys/:api_key` to verify the requests they sign (see
+//! `api_keys`).
+//!
+//! Administrators define custom roles with the permissions they grant under
+//! `/api/admin/roles` and assign roles to users at
+//! `/api/admin/users/:user_id/roles` (see `roles`).
+//!
+//! Access tokens carry the user's account status, KYC level and token
+//! version. Administrators freeze and unfreeze accounts at
+//! `/api/admin/users/:user_id/status`; a frozen account cannot sign in or
+//! refresh its tokens. They log every token of an account out at
+//! `/api/admin/users/:user_id/logout`, raising its token version and
+//! revoking its sessions. Services look an account's current state up at
+//! the internal `/internal/accounts/:user_id` to refuse tokens of frozen
+//! accounts and stale versions (see `users`).
+//!
+//! Users also sign in with their account at an OAuth2 / OpenID Connect
+//! provider configured in `flowex_config::OAuthConfig`, starting at
+//! `/api/auth/oauth/:provider/authorize`; the account is linked to the user
+//! with its verified email, registering them if there is none, and signed
+//! in users link further providers at `/api/auth/oauth/:provider/link` (see
+//! `oauth`).
+//!
+//! Users verify their identity to a KYC level under `/api/kyc`, uploading
+//! documents and applying with them; the application goes to the
+//! verification service at `KYC_PROVIDER_URL`, if set, and otherwise waits
+//! for an administrator's review under `/api/admin/kyc/applications`.
+//! Other services look users' levels up at the internal
+//! `/internal/kyc/:user_id` (see `kyc`).
+//!
+//! Users open sub-accounts under `/api/auth/subaccounts`, delegating some
+//! API key permissions to each, and act for them with API keys created at
+//! `/api/auth/subaccounts/:id/api-keys`. Other services look a master's
+//! sub-accounts up at the internal `/internal/subaccounts/:master_id` (see
+//! `subaccounts`).
+//!
+//! Logins, failed logins, password changes, API key, role and sub-account
+//! changes, account freezes, forced logouts and KYC applications and
+//! reviews are appended to the audit log, which users read for their own account at
+//! `GET /api/auth/audit` and administrators for any (see `audit`).
+
+mod api_keys;
+mod audit;
+mod kyc;
+mod oauth;
+mod roles;
+mod sessions;
+mod store;
+mod subaccounts;
+mod tokens;
+mod users;
+
+use api_keys::{ApiKey, ApiKeys, CreateApiKeyRequest, CreatedApiKey};
+use audit::{AuditEntry, AuditEvent, AuditLog, RequestMetadata};
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
     response::Json,
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, post, put},
     Router,
 };
+use flowex_auth::keys::KeyRing;
+use flowex_auth::PasswordManager;
+use flowex_cache::SessionData;
+use flowex_config::OAuthConfig;
+use flowex_kyc::{HttpKycProvider, KycApplication, KycDocument, KycStatus};
+use flowex_middleware::api_key::ApiKeyCredential;
+use flowex_middleware::auth::{jwt_auth_middleware, require_permission_middleware};
 use flowex_types::{
-    ApiResponse, FlowExError, FlowExResult, HealthResponse, LoginRequest, LoginResponse,
-    RegisterRequest, User,
+    AccountClaims, AccountStatus, ApiResponse, AuthContext, ChangePasswordRequest, FlowExError, FlowExResult, HealthResponse, LoginRequest,
+    LoginResponse, Permission, RefreshTokenRequest, RegisterRequest, Role, SubAccount, User,
 };
-use serde_json::Value;
-use std::{collections::HashMap, sync::Arc, time::SystemTime};
-use tokio::sync::RwLock;
+use roles::{AssignRolesRequest, DefineRoleRequest, RoleDefinition, Roles};
+use sessions::{Session, Sessions};
+use std::collections::HashMap;
+use jsonwebtoken::jwk::JwkSet;
+use kyc::{ApplyRequest, Kyc, KycOverview, RejectApplicationRequest, UploadDocumentRequest};
+use oauth::{LinkedIdentity, OAuth, OAuthAuthorization, OAuthCallback};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use store::{UserRecord, UserStore};
+use subaccounts::{check_delegated, CreateSubAccountRequest, SubAccountPermissionsRequest, SubAccounts};
+use tokens::{key_retention, RevokedTokens, Tokens, ACCESS_TOKEN_HOURS};
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 use tracing::{info, warn};
+use users::{check_active, Users};
 use uuid::Uuid;
 
 /// Application state
 #[derive(Clone)]
 pub struct AppState {
-    pub users: Arc<RwLock<HashMap<String, User>>>,
-    pub jwt_secret: String,
+    pub users: Users,
+    /// API keys users created for programmatic access
+    pub api_keys: ApiKeys,
+    /// Access and refresh tokens issued, rotated and revoked
+    pub tokens: Tokens,
+    /// Login sessions of every user
+    pub sessions: Sessions,
+    /// Custom roles administrators defined
+    pub roles: Roles,
+    /// Security events on every account
+    pub audit: AuditLog,
+    /// Providers users sign in with and the identities linked at them
+    pub oauth: OAuth,
+    /// Identity documents and verification applications of every user
+    pub kyc: Kyc,
+    /// Sub-accounts masters opened and the permissions delegated to them
+    pub sub_accounts: SubAccounts,
     pub start_time: SystemTime,
 }
 
+/// Email of the user `AppState::new` starts with
+const DEMO_EMAIL: &str = "demo@flowex.com";
+
+/// Password of the demo user
+const DEMO_PASSWORD: &str = "demo123";
+
+/// Audit entries returned unless the request sets a limit
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+
+/// Most audit entries a request may ask for
+const MAX_AUDIT_LIMIT: usize = 1000;
+
+/// Age at which a generated signing key is replaced, in days, unless
+/// `JWT_KEY_ROTATION_DAYS` says otherwise
+const DEFAULT_KEY_ROTATION_DAYS: i64 = 7;
+
+/// How often generated signing keys are checked for rotation
+const KEY_ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 impl AppState {
+    /// State kept in memory only, with the demo user
     pub fn new() -> Self {
-        let mut users = HashMap::new();
-        
-        // Add demo user
-        let demo_user = User {
-            id: Uuid::new_v4(),
-            email: "demo@flowex.com".to_string(),
-            first_name: "Demo".to_string(),
-            last_name: "User".to_string(),
-            is_verified: true,
-            created_at: chrono::Utc::now(),
-            updated_at: chrono::Utc::now(),
+        let now = chrono::Utc::now();
+        let demo_user = UserRecord {
+            user: User {
+                id: Uuid::new_v4(),
+                email: DEMO_EMAIL.to_string(),
+                first_name: "Demo".to_string(),
+                last_name: "User".to_string(),
+                is_verified: true,
+                created_at: now,
+                updated_at: now,
+            },
+            // Hashed directly: the demo password is below the strength registration requires
+            password_hash: bcrypt::hash(DEMO_PASSWORD, bcrypt::DEFAULT_COST).expect("hash the demo password"),
+            roles: vec![Role::User.as_str().to_string()],
+            status: AccountStatus::Active,
+            token_version: 0,
         };
-        
-        users.insert("demo@flowex.com".to_string(), demo_user);
-        
+        let users = Users::new(UserStore::Memory, PasswordManager::new(None), vec![demo_user]);
+        Self::with_users(
+            users,
+            ApiKeys::new(UserStore::Memory, Vec::new()),
+            Roles::new(UserStore::Memory, Vec::new()),
+            AuditLog::new(UserStore::Memory),
+            OAuth::new(UserStore::Memory, Vec::new()),
+            Kyc::new(UserStore::Memory, Vec::new(), Vec::new()),
+            SubAccounts::new(UserStore::Memory, Vec::new()),
+        )
+    }
+
+    /// State kept in `store`, restored from what it holds
+    pub async fn with_store(store: UserStore) -> FlowExResult<Self> {
+        let records = store.load().await?;
+        let api_keys = ApiKeys::new(store.clone(), store.load_api_keys().await?);
+        let roles = Roles::new(store.clone(), store.load_roles().await?);
+        let audit = AuditLog::new(store.clone());
+        let oauth = OAuth::new(store.clone(), store.load_identities().await?);
+        let kyc = Kyc::new(store.clone(), store.load_kyc_documents().await?, store.load_kyc_applications().await?);
+        let sub_accounts = SubAccounts::new(store.clone(), store.load_sub_accounts().await?);
+        Ok(Self::with_users(
+            Users::new(store, PasswordManager::new(None), records),
+            api_keys,
+            roles,
+            audit,
+            oauth,
+            kyc,
+            sub_accounts,
+        ))
+    }
+
+    fn with_users(
+        users: Users,
+        api_keys: ApiKeys,
+        roles: Roles,
+        audit: AuditLog,
+        oauth: OAuth,
+        kyc: Kyc,
+        sub_accounts: SubAccounts,
+    ) -> Self {
         Self {
-            users: Arc::new(RwLock::new(users)),
-            jwt_secret: "flowex_enterprise_secret_key_2024".to_string(),
+            users,
+            api_keys,
+            tokens: Tokens::new(
+                KeyRing::generate(key_rotation(), key_retention()).expect("generate a token signing key"),
+                roles.directory(),
+                RevokedTokens::in_memory(),
+            ),
+            sessions: Sessions::in_memory(),
+            roles,
+            audit,
+            oauth,
+            kyc,
+            sub_accounts,
             start_time: SystemTime::now(),
         }
     }
@@ -75,71 +254,307 @@ async fn health_check(State(state): State<AppState>) -> Json<HealthResponse> {
 /// User login endpoint
 async fn login(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(request): Json<LoginRequest>,
 ) -> Result<Json<ApiResponse<LoginResponse>>, StatusCode> {
     info!("Login attempt for email: {}", request.email);
-
-    let users = state.users.read().await;
-    
-    if let Some(user) = users.get(&request.email) {
-        // In a real implementation, you would verify the password hash
-        if request.email == "demo@flowex.com" && request.password == "demo123" {
-            let token = generate_jwt_token(&user.id, &state.jwt_secret)?;
-            
-            let response = LoginResponse {
-                token,
-                user: user.clone(),
-                expires_in: 3600, // 1 hour
-            };
-            
-            info!("Successful login for user: {}", user.email);
-            Ok(Json(ApiResponse::success(response)))
-        } else {
-            warn!("Invalid password for user: {}", request.email);
-            Err(StatusCode::UNAUTHORIZED)
+    let metadata = RequestMetadata::from_headers(&headers);
+
+    match state.users.authenticate(&request.email, &request.password).await {
+        Ok(record) => {
+            info!("Successful login for user: {}", record.user.email);
+            state
+                .audit
+                .record(AuditEntry::new(AuditEvent::Login, &metadata).user(Some(record.user.id), &record.user.email))
+                .await;
+            open_session(&state, record, &metadata).await
+        }
+        Err(e) => {
+            warn!("Login refused for {}: {}", request.email, e);
+            let user_id = state.users.get(&request.email).await.map(|record| record.user.id);
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::LoginFailed, &metadata)
+                        .user(user_id, &request.email)
+                        .details(serde_json::json!({ "reason": e.to_string() })),
+                )
+                .await;
+            Err(rejection_status(&e))
         }
-    } else {
-        warn!("User not found: {}", request.email);
-        Err(StatusCode::UNAUTHORIZED)
     }
 }
 
 /// User registration endpoint
 async fn register(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(request): Json<RegisterRequest>,
 ) -> Result<Json<ApiResponse<LoginResponse>>, StatusCode> {
     info!("Registration attempt for email: {}", request.email);
 
-    let mut users = state.users.write().await;
-    
-    if users.contains_key(&request.email) {
-        warn!("User already exists: {}", request.email);
-        return Err(StatusCode::CONFLICT);
-    }
-
-    let new_user = User {
-        id: Uuid::new_v4(),
-        email: request.email.clone(),
-        first_name: request.first_name,
-        last_name: request.last_name,
-        is_verified: false,
-        created_at: chrono::Utc::now(),
-        updated_at: chrono::Utc::now(),
+    let email = request.email.clone();
+    let record = match state.users.register(request).await {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            warn!("User already exists: {}", email);
+            return Err(StatusCode::CONFLICT);
+        }
+        Err(e) => {
+            warn!("Registration refused for {}: {}", email, e);
+            return Err(rejection_status(&e));
+        }
     };
 
-    let token = generate_jwt_token(&new_user.id, &state.jwt_secret)?;
-    
-    let response = LoginResponse {
-        token,
-        user: new_user.clone(),
-        expires_in: 3600,
+    info!("Successful registration for user: {}", email);
+    let metadata = RequestMetadata::from_headers(&headers);
+    state
+        .audit
+        .record(AuditEntry::new(AuditEvent::Registered, &metadata).user(Some(record.user.id), &record.user.email))
+        .await;
+    open_session(&state, record, &metadata).await
+}
+
+/// Exchange a refresh token for a new token pair, revoking it
+async fn refresh(
+    State(state): State<AppState>,
+    Json(request): Json<RefreshTokenRequest>,
+) -> Result<Json<ApiResponse<LoginResponse>>, StatusCode> {
+    let claims = state.tokens.redeem(&request.refresh_token).await.map_err(|e| {
+        warn!("Token refresh refused: {}", e);
+        rejection_status(&e)
+    })?;
+    match state.sessions.touch(&claims.sid).await {
+        Ok(Some(session)) if session.user_id.to_string() == claims.sub => {}
+        Ok(_) => {
+            warn!("Token refresh refused: session {} was revoked or has expired", claims.sid);
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Err(e) => return Err(rejection_status(&e)),
+    }
+    match state.users.get(&claims.email).await {
+        Some(record) if record.user.id.to_string() == claims.sub => {
+            check_active(&record).map_err(|e| {
+                warn!("Token refresh refused for {}: {}", record.user.id, e);
+                rejection_status(&e)
+            })?;
+            issue_tokens(&state, record, &claims.sid).await
+        }
+        _ => {
+            warn!("Token refresh refused: user {} no longer exists", claims.sub);
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+/// Revoke a refresh token and end its session
+async fn logout(
+    State(state): State<AppState>,
+    Json(request): Json<RefreshTokenRequest>,
+) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    let claims = state.tokens.revoke(&request.refresh_token).await.map_err(|e| {
+        warn!("Logout refused: {}", e);
+        rejection_status(&e)
+    })?;
+    if let Ok(user_id) = claims.sub.parse() {
+        state.sessions.revoke(user_id, &claims.sid).await.map_err(|e| rejection_status(&e))?;
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// Start signing in with an OAuth provider
+async fn oauth_authorize(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+) -> Result<Json<ApiResponse<OAuthAuthorization>>, StatusCode> {
+    if !state.oauth.has_provider(&provider) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    match state.oauth.authorize(&provider, None).await {
+        Ok(authorization) => Ok(Json(ApiResponse::success(authorization))),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// Start linking an OAuth provider to the user's account
+async fn oauth_link(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(provider): Path<String>,
+) -> Result<Json<ApiResponse<OAuthAuthorization>>, StatusCode> {
+    if !state.oauth.has_provider(&provider) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    match state.oauth.authorize(&provider, Some(auth.user_id)).await {
+        Ok(authorization) => Ok(Json(ApiResponse::success(authorization))),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// OAuth identities linked to the user's account
+async fn get_oauth_identities(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Json<ApiResponse<Vec<LinkedIdentity>>> {
+    Json(ApiResponse::success(state.oauth.identities_of(auth.user_id).await))
+}
+
+/// Where an OAuth provider sends the user back to: sign them in
+async fn oauth_callback(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    Query(callback): Query<OAuthCallback>,
+) -> Result<Json<ApiResponse<LoginResponse>>, StatusCode> {
+    if !state.oauth.has_provider(&provider) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let metadata = RequestMetadata::from_headers(&headers);
+    let record = match sign_in_with(&state, &provider, callback, &metadata).await {
+        Ok(record) => record,
+        Err(e) => {
+            warn!("Sign-in with {} refused: {}", provider, e);
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::LoginFailed, &metadata)
+                        .details(serde_json::json!({ "provider": provider, "reason": e.to_string() })),
+                )
+                .await;
+            return Err(rejection_status(&e));
+        }
     };
+    info!("Successful sign-in with {} for user: {}", provider, record.user.email);
+    state
+        .audit
+        .record(
+            AuditEntry::new(AuditEvent::Login, &metadata)
+                .user(Some(record.user.id), &record.user.email)
+                .details(serde_json::json!({ "provider": provider })),
+        )
+        .await;
+    open_session(&state, record, &metadata).await
+}
 
-    users.insert(request.email.clone(), new_user);
-    
-    info!("Successful registration for user: {}", request.email);
-    Ok(Json(ApiResponse::success(response)))
+/// User whose account at `provider` the callback is for: the one it is
+/// linked to, else the one linking it, the one with its verified email, or
+/// a user newly registered with that email
+async fn sign_in_with(
+    state: &AppState,
+    provider: &str,
+    callback: OAuthCallback,
+    metadata: &RequestMetadata,
+) -> FlowExResult<UserRecord> {
+    let (profile, linking) = state.oauth.complete(provider, callback).await?;
+    let gone = || FlowExError::Authentication("User no longer exists".to_string());
+    if let Some(identity) = state.oauth.identity(provider, &profile.subject).await {
+        if linking.is_some_and(|user_id| user_id != identity.user_id) {
+            return Err(FlowExError::Authorization(format!(
+                "This {} account is linked to another user",
+                provider
+            )));
+        }
+        return state.users.get_by_id(identity.user_id).await.ok_or_else(gone);
+    }
+
+    let record = match linking {
+        Some(user_id) => state.users.get_by_id(user_id).await.ok_or_else(gone)?,
+        None => {
+            let email = profile.verified_email().ok_or_else(|| {
+                FlowExError::Authentication(format!("{} has not verified an email for this account", provider))
+            })?;
+            match state.users.get(email).await {
+                Some(record) => record,
+                None => match state
+                    .users
+                    .register_external(email, profile.first_name.clone(), profile.last_name.clone())
+                    .await?
+                {
+                    Some(record) => {
+                        state
+                            .audit
+                            .record(
+                                AuditEntry::new(AuditEvent::Registered, metadata)
+                                    .user(Some(record.user.id), &record.user.email)
+                                    .details(serde_json::json!({ "provider": provider })),
+                            )
+                            .await;
+                        record
+                    }
+                    // Registered meanwhile
+                    None => state.users.get(email).await.ok_or_else(gone)?,
+                },
+            }
+        }
+    };
+    let identity = state.oauth.link(provider, &profile, record.user.id).await?;
+    state
+        .audit
+        .record(
+            AuditEntry::new(AuditEvent::IdentityLinked, metadata)
+                .user(Some(record.user.id), &record.user.email)
+                .details(serde_json::json!({ "provider": provider, "subject": identity.subject })),
+        )
+        .await;
+    Ok(record)
+}
+
+/// Open a session for the user on the device the request came from, with
+/// a new token pair
+async fn open_session(
+    state: &AppState,
+    record: UserRecord,
+    metadata: &RequestMetadata,
+) -> Result<Json<ApiResponse<LoginResponse>>, StatusCode> {
+    check_active(&record).map_err(|e| {
+        warn!("Sign-in refused for {}: {}", record.user.id, e);
+        rejection_status(&e)
+    })?;
+    let device = SessionData {
+        ip_address: metadata.ip_address.clone(),
+        user_agent: metadata.user_agent.clone(),
+        roles: record.roles.clone(),
+        permissions: Vec::new(),
+        metadata: HashMap::new(),
+    };
+    let session_id = state.sessions.open(record.user.id, device).await.map_err(|e| {
+        warn!("Failed to open a session for {}: {}", record.user.id, e);
+        rejection_status(&e)
+    })?;
+    issue_tokens(state, record, &session_id).await
+}
+
+/// A new token pair for the user in the session `session_id`
+async fn issue_tokens(
+    state: &AppState,
+    record: UserRecord,
+    session_id: &str,
+) -> Result<Json<ApiResponse<LoginResponse>>, StatusCode> {
+    let account = account_claims(state, &record).await;
+    let pair = state.tokens.issue(&record.user, record.roles.clone(), account, session_id).map_err(|e| {
+        warn!("Failed to issue tokens for {}: {}", record.user.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(ApiResponse::success(LoginResponse {
+        token: pair.access_token,
+        refresh_token: pair.refresh_token,
+        user: record.user,
+        expires_in: ACCESS_TOKEN_HOURS * 3600,
+    })))
+}
+
+/// Current state of the user's account, as signed into their tokens
+async fn account_claims(state: &AppState, record: &UserRecord) -> AccountClaims {
+    AccountClaims {
+        account_status: record.status,
+        kyc_tier: Some(state.kyc.level(record.user.id).await.as_str().to_string()),
+        token_version: record.token_version,
+    }
+}
+
+/// Public keys tokens are verified with
+async fn get_jwks(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.tokens.jwks())
 }
 
 /// Get current user endpoint
@@ -147,49 +562,702 @@ async fn get_me(
     State(state): State<AppState>,
     // In a real implementation, you would extract the JWT token from headers
 ) -> Json<ApiResponse<User>> {
-    let users = state.users.read().await;
-    
-    if let Some(user) = users.get("demo@flowex.com") {
-        Json(ApiResponse::success(user.clone()))
+    if let Some(record) = state.users.get(DEMO_EMAIL).await {
+        Json(ApiResponse::success(record.user))
     } else {
         Json(ApiResponse::error("User not found".to_string()))
     }
 }
 
-/// Generate JWT token
-fn generate_jwt_token(user_id: &Uuid, secret: &str) -> Result<String, StatusCode> {
-    use jsonwebtoken::{encode, EncodingKey, Header};
-    use serde::{Deserialize, Serialize};
+/// Change the user's password, revoking all their sessions
+async fn change_password(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    let record = state
+        .users
+        .change_password(&auth.email, &request.current_password, &request.new_password)
+        .await
+        .map_err(|e| {
+            warn!("Password change refused for {}: {}", auth.user_id, e);
+            rejection_status(&e)
+        })?;
+    state
+        .audit
+        .record(
+            AuditEntry::new(AuditEvent::PasswordChanged, &RequestMetadata::from_headers(&headers))
+                .user(Some(record.user.id), &record.user.email),
+        )
+        .await;
+    state.sessions.revoke_all(record.user.id).await.map_err(|e| rejection_status(&e))?;
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// List the user's sessions
+async fn get_sessions(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<Vec<Session>>>, StatusCode> {
+    match state.sessions.list(auth.user_id).await {
+        Ok(sessions) => Ok(Json(ApiResponse::success(sessions))),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// Revoke one of the user's sessions
+async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<String>,
+) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    match state.sessions.revoke(auth.user_id, &id).await {
+        Ok(true) => Ok(Json(ApiResponse::success(()))),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// Create an API key for the user; its secret is only ever shown here
+async fn create_api_key(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> Result<Json<ApiResponse<CreatedApiKey>>, StatusCode> {
+    match state.api_keys.create(auth.user_id, &auth.email, request).await {
+        Ok(created) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::ApiKeyCreated, &RequestMetadata::from_headers(&headers))
+                        .user(Some(auth.user_id), &auth.email)
+                        .details(serde_json::json!({
+                            "api_key_id": created.key.id,
+                            "permissions": created.key.permissions,
+                            "ip_allowlist": created.key.ip_allowlist,
+                        })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(created)))
+        }
+        Err(e) => {
+            warn!("API key creation refused for {}: {}", auth.user_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// List the user's API keys
+async fn get_api_keys(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Json<ApiResponse<Vec<ApiKey>>> {
+    Json(ApiResponse::success(state.api_keys.list(auth.user_id).await))
+}
+
+/// Revoke one of the user's API keys
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<ApiKey>>, StatusCode> {
+    match state.api_keys.revoke(auth.user_id, id).await {
+        Ok(Some(key)) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::ApiKeyRevoked, &RequestMetadata::from_headers(&headers))
+                        .user(Some(auth.user_id), &auth.email)
+                        .details(serde_json::json!({ "api_key_id": key.id })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(key)))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// Secret and scopes of an active API key, for services verifying the
+/// requests it signs
+async fn get_api_key_credential(
+    State(state): State<AppState>,
+    Path(api_key): Path<String>,
+) -> Result<Json<ApiResponse<ApiKeyCredential>>, StatusCode> {
+    let mut credential = state.api_keys.credential(&api_key).await.ok_or(StatusCode::NOT_FOUND)?;
+    let frozen = state
+        .users
+        .get_by_id(credential.user_id)
+        .await
+        .is_none_or(|record| check_active(&record).is_err());
+    if frozen {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    state.sub_accounts.restrict(&mut credential).await;
+    Ok(Json(ApiResponse::success(credential)))
+}
+
+/// List the user's sub-accounts, oldest first
+async fn get_sub_accounts(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Json<ApiResponse<Vec<SubAccount>>> {
+    Json(ApiResponse::success(state.sub_accounts.list(auth.user_id).await))
+}
+
+/// Open a sub-account under the user
+async fn create_sub_account(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Json(request): Json<CreateSubAccountRequest>,
+) -> Result<Json<ApiResponse<SubAccount>>, StatusCode> {
+    match state.sub_accounts.create(&state.users, auth.user_id, request).await {
+        Ok(account) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::SubAccountCreated, &RequestMetadata::from_headers(&headers))
+                        .user(Some(auth.user_id), &auth.email)
+                        .details(serde_json::json!({
+                            "sub_account_id": account.user_id,
+                            "permissions": account.permissions,
+                        })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(account)))
+        }
+        Err(e) => {
+            warn!("Sub-account creation refused for {}: {}", auth.user_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Replace the permissions delegated to one of the user's sub-accounts
+async fn set_sub_account_permissions(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(request): Json<SubAccountPermissionsRequest>,
+) -> Result<Json<ApiResponse<SubAccount>>, StatusCode> {
+    match state.sub_accounts.set_permissions(auth.user_id, id, request.permissions).await {
+        Ok(Some(account)) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::SubAccountPermissionsChanged, &RequestMetadata::from_headers(&headers))
+                        .user(Some(auth.user_id), &auth.email)
+                        .details(serde_json::json!({
+                            "sub_account_id": account.user_id,
+                            "permissions": account.permissions,
+                        })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(account)))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            warn!("Sub-account permissions refused for {}: {}", auth.user_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Create an API key acting as one of the user's sub-accounts, within the
+/// permissions delegated to it; its secret is only ever shown here
+async fn create_sub_account_api_key(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> Result<Json<ApiResponse<CreatedApiKey>>, StatusCode> {
+    let account = state.sub_accounts.get_of(auth.user_id, id).await.ok_or(StatusCode::NOT_FOUND)?;
+    let created = match check_delegated(&account, &request.permissions) {
+        Ok(()) => state.api_keys.create(account.user_id, &account.email, request).await,
+        Err(e) => Err(e),
+    };
+    match created {
+        Ok(created) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::ApiKeyCreated, &RequestMetadata::from_headers(&headers))
+                        .user(Some(auth.user_id), &auth.email)
+                        .details(serde_json::json!({
+                            "api_key_id": created.key.id,
+                            "sub_account_id": account.user_id,
+                            "permissions": created.key.permissions,
+                            "ip_allowlist": created.key.ip_allowlist,
+                        })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(created)))
+        }
+        Err(e) => {
+            warn!("API key creation refused for sub-account {}: {}", account.user_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
 
-    #[derive(Debug, Serialize, Deserialize)]
-    struct Claims {
-        sub: String,
-        exp: usize,
-        iat: usize,
+/// List the API keys of one of the user's sub-accounts
+async fn get_sub_account_api_keys(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<Vec<ApiKey>>>, StatusCode> {
+    let account = state.sub_accounts.get_of(auth.user_id, id).await.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(ApiResponse::success(state.api_keys.list(account.user_id).await)))
+}
+
+/// Revoke an API key of one of the user's sub-accounts
+async fn revoke_sub_account_api_key(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path((id, key_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<ApiResponse<ApiKey>>, StatusCode> {
+    let account = state.sub_accounts.get_of(auth.user_id, id).await.ok_or(StatusCode::NOT_FOUND)?;
+    match state.api_keys.revoke(account.user_id, key_id).await {
+        Ok(Some(key)) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::ApiKeyRevoked, &RequestMetadata::from_headers(&headers))
+                        .user(Some(auth.user_id), &auth.email)
+                        .details(serde_json::json!({ "api_key_id": key.id, "sub_account_id": account.user_id })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(key)))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(rejection_status(&e)),
     }
+}
 
-    let now = chrono::Utc::now();
-    let claims = Claims {
-        sub: user_id.to_string(),
-        exp: (now + chrono::Duration::hours(1)).timestamp() as usize,
-        iat: now.timestamp() as usize,
+/// Sub-accounts of a master, for services reporting on them together
+async fn get_master_sub_accounts(
+    State(state): State<AppState>,
+    Path(master_id): Path<Uuid>,
+) -> Json<ApiResponse<Vec<SubAccount>>> {
+    Json(ApiResponse::success(state.sub_accounts.list(master_id).await))
+}
+
+/// List built-in and custom roles
+async fn get_roles(State(state): State<AppState>) -> Json<ApiResponse<Vec<RoleDefinition>>> {
+    Json(ApiResponse::success(state.roles.list().await))
+}
+
+/// Define or redefine a custom role
+async fn define_role(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(request): Json<DefineRoleRequest>,
+) -> Result<Json<ApiResponse<RoleDefinition>>, StatusCode> {
+    match state.roles.define(&name, request, &auth.permissions).await {
+        Ok(role) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::RoleDefined, &RequestMetadata::from_headers(&headers))
+                        .user(Some(auth.user_id), &auth.email)
+                        .details(serde_json::json!({ "role": role.name, "permissions": role.permissions })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(role)))
+        }
+        Err(e) => {
+            warn!("Role definition refused for {}: {}", auth.user_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Delete a custom role, taking it from every user holding it
+async fn delete_role(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    match state.roles.delete(&name, &auth.permissions).await {
+        Ok(true) => {
+            state.users.forget_role(&name).await;
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::RoleDeleted, &RequestMetadata::from_headers(&headers))
+                        .user(Some(auth.user_id), &auth.email)
+                        .details(serde_json::json!({ "role": name })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(())))
+        }
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            warn!("Role deletion refused for {}: {}", auth.user_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Replace the roles of a user; they apply from the user's next login or
+/// refresh
+async fn assign_roles(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+    Json(request): Json<AssignRolesRequest>,
+) -> Result<Json<ApiResponse<Vec<String>>>, StatusCode> {
+    if let Err(e) = state.roles.check_assignable(&request.roles, &auth.permissions) {
+        warn!("Role assignment refused for {}: {}", auth.user_id, e);
+        return Err(rejection_status(&e));
+    }
+    match state.users.set_roles(user_id, request.roles).await {
+        Ok(Some(record)) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::RolesAssigned, &RequestMetadata::from_headers(&headers))
+                        .user(Some(user_id), &record.user.email)
+                        .actor(auth.user_id)
+                        .details(serde_json::json!({ "roles": record.roles })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(record.roles)))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// Account status request body
+#[derive(Debug, Deserialize)]
+struct AccountStatusRequest {
+    status: AccountStatus,
+}
+
+/// Freeze or unfreeze a user's account
+async fn set_account_status(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+    Json(request): Json<AccountStatusRequest>,
+) -> Result<Json<ApiResponse<AccountClaims>>, StatusCode> {
+    match state.users.set_status(user_id, request.status).await {
+        Ok(Some(record)) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::AccountStatusChanged, &RequestMetadata::from_headers(&headers))
+                        .user(Some(user_id), &record.user.email)
+                        .actor(auth.user_id)
+                        .details(serde_json::json!({ "status": record.status })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(account_claims(&state, &record).await)))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// Log every token of a user out, raising their token version and
+/// revoking their sessions
+async fn force_logout(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<AccountClaims>>, StatusCode> {
+    let record = match state.users.force_logout(user_id).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => return Err(rejection_status(&e)),
     };
+    state.sessions.revoke_all(user_id).await.map_err(|e| rejection_status(&e))?;
+    state
+        .audit
+        .record(
+            AuditEntry::new(AuditEvent::ForcedLogout, &RequestMetadata::from_headers(&headers))
+                .user(Some(user_id), &record.user.email)
+                .actor(auth.user_id)
+                .details(serde_json::json!({ "token_version": record.token_version })),
+        )
+        .await;
+    Ok(Json(ApiResponse::success(account_claims(&state, &record).await)))
+}
+
+/// Current state of a user's account, for services refusing tokens of
+/// frozen accounts and stale versions
+async fn get_account_state(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<AccountClaims>>, StatusCode> {
+    let record = state.users.get_by_id(user_id).await.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(ApiResponse::success(account_claims(&state, &record).await)))
+}
+
+/// The user's KYC level, applications and documents
+async fn get_kyc(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Json<ApiResponse<KycOverview>> {
+    Json(ApiResponse::success(state.kyc.overview(auth.user_id).await))
+}
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_ref()),
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+/// Upload a document for the user's KYC applications
+async fn upload_kyc_document(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<UploadDocumentRequest>,
+) -> Result<Json<ApiResponse<KycDocument>>, StatusCode> {
+    match state.kyc.upload(auth.user_id, request).await {
+        Ok(document) => Ok(Json(ApiResponse::success(document))),
+        Err(e) => {
+            warn!("KYC document refused for {}: {}", auth.user_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Apply for the user to be verified to a KYC level
+async fn apply_kyc(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Json(request): Json<ApplyRequest>,
+) -> Result<Json<ApiResponse<KycApplication>>, StatusCode> {
+    match state.kyc.apply(auth.user_id, request).await {
+        Ok(application) => {
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::KycSubmitted, &RequestMetadata::from_headers(&headers))
+                        .user(Some(auth.user_id), &auth.email)
+                        .details(serde_json::json!({
+                            "application_id": application.id,
+                            "level": application.level,
+                            "provider": application.provider,
+                            "status": application.status,
+                        })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(application)))
+        }
+        Err(e) => {
+            warn!("KYC application refused for {}: {}", auth.user_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Pending KYC applications, oldest first
+async fn get_kyc_review_queue(State(state): State<AppState>) -> Json<ApiResponse<Vec<KycApplication>>> {
+    Json(ApiResponse::success(state.kyc.review_queue().await))
+}
+
+/// Approve a pending KYC application
+async fn approve_kyc_application(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<KycApplication>>, StatusCode> {
+    let reviewed = state.kyc.approve(id, auth.user_id).await;
+    kyc_reviewed(&state, &auth, &headers, reviewed).await
+}
+
+/// Reject a pending KYC application
+async fn reject_kyc_application(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Json(request): Json<RejectApplicationRequest>,
+) -> Result<Json<ApiResponse<KycApplication>>, StatusCode> {
+    let reviewed = state.kyc.reject(id, auth.user_id, request.reason).await;
+    kyc_reviewed(&state, &auth, &headers, reviewed).await
+}
+
+/// Audit and answer the review of a KYC application
+async fn kyc_reviewed(
+    state: &AppState,
+    auth: &AuthContext,
+    headers: &HeaderMap,
+    reviewed: FlowExResult<Option<KycApplication>>,
+) -> Result<Json<ApiResponse<KycApplication>>, StatusCode> {
+    match reviewed {
+        Ok(Some(application)) => {
+            let email = state
+                .users
+                .get_by_id(application.user_id)
+                .await
+                .map(|record| record.user.email)
+                .unwrap_or_default();
+            state
+                .audit
+                .record(
+                    AuditEntry::new(AuditEvent::KycReviewed, &RequestMetadata::from_headers(headers))
+                        .user(Some(application.user_id), &email)
+                        .actor(auth.user_id)
+                        .details(serde_json::json!({
+                            "application_id": application.id,
+                            "level": application.level,
+                            "status": application.status,
+                            "reason": application.rejection_reason,
+                        })),
+                )
+                .await;
+            Ok(Json(ApiResponse::success(application)))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            warn!("KYC review refused for {}: {}", auth.user_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// KYC level of a user, for services enforcing the limits it lifts
+async fn get_kyc_status(State(state): State<AppState>, Path(user_id): Path<Uuid>) -> Json<ApiResponse<KycStatus>> {
+    let level = state.kyc.level(user_id).await;
+    Json(ApiResponse::success(KycStatus { user_id, level }))
+}
+
+/// Query parameters of `GET /api/auth/audit`
+#[derive(Debug, Deserialize)]
+struct AuditQuery {
+    /// Account whose entries to list, the caller's unless set
+    user_id: Option<Uuid>,
+    /// Whether to list every account's entries
+    #[serde(default)]
+    all: bool,
+    limit: Option<usize>,
+}
+
+/// Latest audit entries of the user's account, newest first; those of any
+/// or every account for administrators
+async fn get_audit(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<ApiResponse<Vec<AuditEntry>>>, StatusCode> {
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT);
+    if limit == 0 || limit > MAX_AUDIT_LIMIT {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user_id = match query.user_id {
+        _ if query.all => None,
+        Some(user_id) => Some(user_id),
+        None => Some(auth.user_id),
+    };
+    if user_id != Some(auth.user_id) && !auth.permissions.iter().any(|p| p == Permission::AdminRead.as_str()) {
+        warn!("Audit log of {:?} refused to {}", user_id, auth.user_id);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.audit.list(user_id, limit).await {
+        Ok(entries) => Ok(Json(ApiResponse::success(entries))),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// HTTP status for an auth request that was refused
+fn rejection_status(error: &FlowExError) -> StatusCode {
+    match error {
+        FlowExError::Authentication(_) => StatusCode::UNAUTHORIZED,
+        FlowExError::Authorization(_) => StatusCode::FORBIDDEN,
+        FlowExError::Validation(_) => StatusCode::BAD_REQUEST,
+        FlowExError::Database(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Age at which generated signing keys are replaced
+fn key_rotation() -> chrono::Duration {
+    let days = std::env::var("JWT_KEY_ROTATION_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(DEFAULT_KEY_ROTATION_DAYS);
+    chrono::Duration::days(days)
+}
+
+/// Signing keys from the PEM files listed in `paths`, comma separated
+fn load_signing_keys(paths: &str) -> anyhow::Result<KeyRing> {
+    let pems = paths
+        .split(',')
+        .map(|path| std::fs::read_to_string(path.trim()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(KeyRing::from_pems(&pems, key_retention())?)
 }
 
 /// Create the application router
 fn create_app(state: AppState) -> Router {
+    let admin_read = Router::new()
+        .route("/api/admin/roles", get(get_roles))
+        .route("/api/admin/kyc/applications", get(get_kyc_review_queue))
+        .route_layer(middleware::from_fn(|request, next| {
+            require_permission_middleware(Permission::AdminRead, request, next)
+        }));
+    let admin_write = Router::new()
+        .route("/api/admin/roles/:name", put(define_role).delete(delete_role))
+        .route("/api/admin/users/:user_id/roles", put(assign_roles))
+        .route("/api/admin/users/:user_id/status", put(set_account_status))
+        .route("/api/admin/users/:user_id/logout", post(force_logout))
+        .route("/api/admin/kyc/applications/:id/approve", post(approve_kyc_application))
+        .route("/api/admin/kyc/applications/:id/reject", post(reject_kyc_application))
+        .route_layer(middleware::from_fn(|request, next| {
+            require_permission_middleware(Permission::AdminWrite, request, next)
+        }));
+
+    let authenticated = Router::new()
+        .route("/api/auth/password", post(change_password))
+        .route("/api/auth/sessions", get(get_sessions))
+        .route("/api/auth/sessions/:id", delete(revoke_session))
+        .route("/api/auth/audit", get(get_audit))
+        .route("/api/auth/oauth/identities", get(get_oauth_identities))
+        .route("/api/auth/oauth/:provider/link", post(oauth_link))
+        .route("/api/auth/api-keys", post(create_api_key).get(get_api_keys))
+        .route("/api/auth/api-keys/:id", delete(revoke_api_key))
+        .route("/api/auth/subaccounts", post(create_sub_account).get(get_sub_accounts))
+        .route("/api/auth/subaccounts/:id/permissions", put(set_sub_account_permissions))
+        .route(
+            "/api/auth/subaccounts/:id/api-keys",
+            post(create_sub_account_api_key).get(get_sub_account_api_keys),
+        )
+        .route("/api/auth/subaccounts/:id/api-keys/:key_id", delete(revoke_sub_account_api_key))
+        .route("/api/kyc", get(get_kyc))
+        .route("/api/kyc/documents", post(upload_kyc_document))
+        .route("/api/kyc/applications", post(apply_kyc))
+        .merge(admin_read)
+        .merge(admin_write)
+        .route_layer(middleware::from_fn(jwt_auth_middleware));
+
     Router::new()
         .route("/health", get(health_check))
+        .route("/.well-known/jwks.json", get(get_jwks))
         .route("/api/auth/login", post(login))
         .route("/api/auth/register", post(register))
+        .route("/api/auth/refresh", post(refresh))
+        .route("/api/auth/logout", post(logout))
         .route("/api/auth/me", get(get_me))
+        .route("/api/auth/oauth/:provider/authorize", get(oauth_authorize))
+        .route("/api/auth/oauth/:provider/callback", get(oauth_callback))
+        .route("/internal/api-keys/:api_key", get(get_api_key_credential))
+        .route("/internal/kyc/:user_id", get(get_kyc_status))
+        .route("/internal/accounts/:user_id", get(get_account_state))
+        .route("/internal/subaccounts/:master_id", get(get_master_sub_accounts))
+        .merge(authenticated)
         .layer(
             ServiceBuilder::new()
                 .layer(CorsLayer::permissive())
@@ -208,7 +1276,55 @@ async fn main() -> anyhow::Result<()> {
 
     info!("Starting FlowEx Authentication Service");
 
-    let state = AppState::new();
+    let mut state = match std::env::var("FLOWEX_DATABASE_URL") {
+        Ok(database_url) => AppState::with_store(UserStore::connect(&database_url).await?).await?,
+        Err(_) => {
+            warn!("FLOWEX_DATABASE_URL is not set; users are kept in memory only");
+            AppState::new()
+        }
+    };
+    let providers = OAuthConfig::load()?.providers;
+    if !providers.is_empty() {
+        info!("Users sign in with OAuth providers {:?}", providers.keys().collect::<Vec<_>>());
+    }
+    state.oauth = state.oauth.with_providers(providers);
+    match std::env::var("KYC_PROVIDER_URL") {
+        Ok(url) => {
+            info!("KYC applications are submitted to {}", url);
+            state.kyc = state.kyc.with_provider(Arc::new(HttpKycProvider::new("external", &url)));
+        }
+        Err(_) => warn!("KYC_PROVIDER_URL is not set; KYC applications wait for an administrator's review"),
+    }
+    let keys = match std::env::var("JWT_PRIVATE_KEY_PATHS") {
+        Ok(paths) => load_signing_keys(&paths)?,
+        Err(_) => {
+            warn!("JWT_PRIVATE_KEY_PATHS is not set; tokens are signed with keys of this instance only");
+            let keys = state.tokens.keys().clone();
+            let rotating = keys.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(KEY_ROTATION_CHECK_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    // Generating a key takes a while
+                    let keys = rotating.clone();
+                    if let Ok(Err(e)) = tokio::task::spawn_blocking(move || keys.rotate_if_due()).await {
+                        warn!("Failed to rotate the token signing key: {}", e);
+                    }
+                }
+            });
+            keys
+        }
+    };
+    match std::env::var("REDIS_URL") {
+        Ok(redis_url) => {
+            state.tokens = Tokens::new(keys, state.roles.directory(), RevokedTokens::connect(&redis_url).await?);
+            state.sessions = Sessions::connect(&redis_url).await?;
+        }
+        Err(_) => {
+            warn!("REDIS_URL is not set; sessions and revoked refresh tokens are kept by this instance only");
+            state.tokens = Tokens::new(keys, state.roles.directory(), RevokedTokens::in_memory());
+        }
+    }
     let app = create_app(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8001").await?;
@@ -222,8 +1338,41 @@ async fn main() -> anyhow::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use axum::http::StatusCode;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
     use tower::ServiceExt;
+    use std::sync::Once;
+
+    static INIT: Once = Once::new();
+
+    /// 测试用户的密码，满足注册的强度要求
+    const TEST_PASSWORD: &str = "SecurePassword123!";
+
+    /// 初始化测试环境
+    fn init_test_env() {
+        INIT.call_once(|| {
+            let _ = tracing_subscriber::fmt()
+                .with_test_writer()
+                .with_env_filter("debug")
+                .try_init();
+        });
+    }
+
+    /// 创建测试用的应用状态，只有test@example.com一个用户，密码以低成本哈希以加快测试
+    async fn create_test_app_state() -> AppState {
+        let mut state = AppState::new();
+        state.users = Users::new(UserStore::Memory, PasswordManager::new(Some(4)), Vec::new());
+        let request = RegisterRequest {
+            email: "test@example.com".to_string(),
+            password: TEST_PASSWORD.to_string(),
+            first_name: "Test".to_string(),
+            last_name: "User".to_string(),
+        };
+        state.users.register(request).await.unwrap().unwrap();
+        state
+    }
 
     #[tokio::test]
     async fn test_health_check() {
@@ -302,7 +1451,7 @@ mod tests {
     async fn test_user_registration() {
         init_test_env();
 
-        let app_state = create_test_app_state();
+        let app_state = create_test_app_state().await;
         let app = create_app(app_state);
 
         let register_request = RegisterRequest {
@@ -324,15 +1473,18 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        let api_response: ApiResponse<User> = serde_json::from_slice(&body).unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let api_response: ApiResponse<serde_json::Value> = serde_json::from_slice(&body).unwrap();
 
         assert!(api_response.success);
         assert!(api_response.data.is_some());
 
-        let user = api_response.data.unwrap();
+        // 注册成功即登录，返回令牌和用户信息
+        let login = api_response.data.unwrap();
+        assert!(login["token"].as_str().is_some_and(|token| !token.is_empty()));
+        let user: User = serde_json::from_value(login["user"].clone()).unwrap();
         assert_eq!(user.email, "newuser@example.com");
         assert_eq!(user.first_name, "New");
         assert_eq!(user.last_name, "User");
@@ -344,7 +1496,7 @@ mod tests {
     async fn test_duplicate_email_registration() {
         init_test_env();
 
-        let app_state = create_test_app_state();
+        let app_state = create_test_app_state().await;
         let app = create_app(app_state);
 
         let register_request = RegisterRequest {
@@ -374,7 +1526,7 @@ mod tests {
     async fn test_invalid_password_registration() {
         init_test_env();
 
-        let app_state = create_test_app_state();
+        let app_state = create_test_app_state().await;
         let app = create_app(app_state);
 
         let weak_password_request = RegisterRequest {
@@ -404,7 +1556,7 @@ mod tests {
     async fn test_invalid_email_registration() {
         init_test_env();
 
-        let app_state = create_test_app_state();
+        let app_state = create_test_app_state().await;
         let app = create_app(app_state);
 
         let invalid_email_request = RegisterRequest {
@@ -444,12 +1596,14 @@ mod tests {
             updated_at: chrono::Utc::now(),
         };
 
-        let secret = "test_jwt_secret_key_for_testing";
-        let token_result = generate_jwt_token(&user, secret);
+        let state = AppState::new();
+        let token_result = state
+            .tokens
+            .issue(&user, vec![Role::User.as_str().to_string()], AccountClaims::default(), "session-1");
 
         assert!(token_result.is_ok(), "JWT令牌生成应该成功");
 
-        let token = token_result.unwrap();
+        let token = token_result.unwrap().access_token;
         assert!(!token.is_empty(), "JWT令牌不应该为空");
         assert!(token.contains('.'), "JWT令牌应该包含点分隔符");
 
@@ -473,12 +1627,31 @@ mod tests {
             updated_at: chrono::Utc::now(),
         };
 
-        let secret = "test_jwt_secret_key_for_testing";
-        let token = generate_jwt_token(&user, secret).unwrap();
-
-        // 验证令牌（这里需要实现令牌验证函数）
-        // 在实际实现中，应该有一个验证JWT令牌的函数
-        assert!(!token.is_empty());
+        let state = AppState::new();
+        let token = state
+            .tokens
+            .issue(&user, vec![Role::User.as_str().to_string()], AccountClaims::default(), "session-1")
+            .unwrap()
+            .access_token;
+
+        // 用JWKS中的公钥验证令牌
+        let verify = |token: &str| {
+            let header = jsonwebtoken::decode_header(token)?;
+            let jwks = state.tokens.jwks();
+            let jwk = jwks.find(header.kid.as_deref().unwrap_or_default()).expect("signing key is published");
+            let mut validation = jsonwebtoken::Validation::new(header.alg);
+            validation.set_issuer(&["flowex"]);
+            validation.set_audience(&["flowex-users"]);
+            jsonwebtoken::decode::<flowex_types::JwtClaims>(token, &jsonwebtoken::DecodingKey::from_jwk(jwk)?, &validation)
+        };
+        let claims = verify(&token).unwrap().claims;
+        assert_eq!(claims.sub, user.id.to_string());
+        assert_eq!(claims.email, "validation@example.com");
+
+        // 篡改过的令牌验证失败
+        let (signed, signature) = token.rsplit_once('.').unwrap();
+        let tampered = format!("{}.{}", signed, signature.chars().rev().collect::<String>());
+        assert!(verify(&tampered).is_err());
     }
 
     /// 测试：密码哈希和验证
@@ -524,7 +1697,7 @@ mod tests {
         // 测试时间戳
         let now = chrono::Utc::now();
         let time_diff = (now - valid_user.created_at).num_seconds();
-        assert!(time_diff >= 0 && time_diff < 5, "创建时间应该在当前时间附近");
+        assert!((0..5).contains(&time_diff), "创建时间应该在当前时间附近");
     }
 
     /// 测试：并发登录请求
@@ -532,7 +1705,7 @@ mod tests {
     async fn test_concurrent_login_requests() {
         init_test_env();
 
-        let app_state = create_test_app_state();
+        let app_state = create_test_app_state().await;
 
         let mut handles = vec![];
 
@@ -544,7 +1717,7 @@ mod tests {
 
                 let login_request = LoginRequest {
                     email: "test@example.com".to_string(),
-                    password: "password123".to_string(),
+                    password: TEST_PASSWORD.to_string(),
                 };
 
                 let response = app
@@ -576,7 +1749,7 @@ mod tests {
     async fn test_performance_benchmark() {
         init_test_env();
 
-        let app_state = create_test_app_state();
+        let app_state = create_test_app_state().await;
         let start = std::time::Instant::now();
 
         // 执行大量认证操作
@@ -585,7 +1758,7 @@ mod tests {
 
             let login_request = LoginRequest {
                 email: "test@example.com".to_string(),
-                password: "password123".to_string(),
+                password: TEST_PASSWORD.to_string(),
             };
 
             let _response = app
@@ -613,9 +1786,10 @@ mod tests {
     async fn test_memory_usage_optimization() {
         init_test_env();
 
-        let app_state = create_test_app_state();
+        let app_state = create_test_app_state().await;
 
         // 创建大量用户数据
+        assert!(app_state.users.get("test@example.com").await.is_some());
         let mut users = Vec::new();
         for i in 0..1000 {
             let user = User {
@@ -634,7 +1808,6 @@ mod tests {
 
         // 清理内存
         drop(users);
-        assert!(true, "内存使用优化测试完成");
     }
 
     /// 测试：错误处理边界情况
@@ -642,7 +1815,7 @@ mod tests {
     async fn test_error_handling_edge_cases() {
         init_test_env();
 
-        let app_state = create_test_app_state();
+        let app_state = create_test_app_state().await;
         let app = create_app(app_state);
 
         // 测试空请求体
@@ -666,7 +1839,7 @@ mod tests {
     async fn test_security_validation() {
         init_test_env();
 
-        let app_state = create_test_app_state();
+        let app_state = create_test_app_state().await;
         let app = create_app(app_state);
 
         // 测试SQL注入尝试
@@ -0,0 +1,466 @@
+//! Sign-in with OAuth2 / OpenID Connect providers
+//!
+//! Users sign in with an account at a provider configured in
+//! `flowex_config::OAuthConfig`, Google or GitHub say, through the
+//! authorization code flow with PKCE: `authorize` sends them to the
+//! provider with a one-time `state`, and `complete` takes the code the
+//! provider sends them back with, exchanges it for an access token and
+//! reads their profile. Pending authorizations are kept by the instance
+//! that started them for `PENDING_AUTHORIZATION_MINUTES`.
+//!
+//! Each account at a provider, its `subject`, is linked to one FlowEx user.
+//! Users link further providers to their account while signed in; an
+//! account at a provider whose email matches an existing user's is linked
+//! to that user only if the provider verified the email.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use flowex_config::OAuthProviderConfig;
+use flowex_types::{FlowExError, FlowExResult};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::store::UserStore;
+
+/// Time users have to come back from the provider
+const PENDING_AUTHORIZATION_MINUTES: i64 = 10;
+
+/// Timeout of requests to providers
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// An account at a provider, linked to a FlowEx user
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkedIdentity {
+    pub provider: String,
+    /// The provider's id of the account
+    pub subject: String,
+    pub user_id: Uuid,
+    /// Email of the account when it was linked
+    pub email: Option<String>,
+    pub linked_at: DateTime<Utc>,
+}
+
+/// Where to send the user to authorize FlowEx at the provider
+#[derive(Debug, Clone, Serialize)]
+pub struct OAuthAuthorization {
+    pub authorization_url: String,
+}
+
+/// Query the provider sends the user back with
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthCallback {
+    pub code: String,
+    pub state: String,
+}
+
+/// Profile of the account a user signed in with
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalProfile {
+    pub subject: String,
+    pub email: Option<String>,
+    /// Whether the provider verified the user owns `email`
+    pub email_verified: bool,
+    pub first_name: String,
+    pub last_name: String,
+}
+
+impl ExternalProfile {
+    /// Email the provider verified the user owns
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+}
+
+/// An authorization the user has yet to come back from
+struct PendingAuthorization {
+    provider: String,
+    code_verifier: String,
+    /// User linking the account to theirs, if signed in
+    link_user_id: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProviderEmail {
+    email: String,
+    #[serde(default)]
+    primary: bool,
+    #[serde(default)]
+    verified: bool,
+}
+
+/// Configured providers and the identities linked at them
+#[derive(Clone)]
+pub struct OAuth {
+    http: reqwest::Client,
+    providers: Arc<HashMap<String, OAuthProviderConfig>>,
+    store: UserStore,
+    /// By state
+    pending: Arc<RwLock<HashMap<String, PendingAuthorization>>>,
+    /// By provider and subject
+    identities: Arc<RwLock<HashMap<(String, String), LinkedIdentity>>>,
+}
+
+impl OAuth {
+    /// No providers yet, the identities in `store`, starting from
+    /// `identities`
+    pub fn new(store: UserStore, identities: Vec<LinkedIdentity>) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent("FlowEx")
+            .build()
+            .unwrap_or_default();
+        Self {
+            http,
+            providers: Arc::new(HashMap::new()),
+            store,
+            pending: Arc::new(RwLock::new(HashMap::new())),
+            identities: Arc::new(RwLock::new(
+                identities
+                    .into_iter()
+                    .map(|identity| ((identity.provider.clone(), identity.subject.clone()), identity))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Sign in with `providers`, by name
+    pub fn with_providers(mut self, providers: HashMap<String, OAuthProviderConfig>) -> Self {
+        self.providers = Arc::new(providers);
+        self
+    }
+
+    /// Whether `provider` is configured
+    pub fn has_provider(&self, provider: &str) -> bool {
+        self.providers.contains_key(provider)
+    }
+
+    /// Start signing in with `provider`, or linking it to the account of
+    /// `link_user_id`
+    pub async fn authorize(&self, provider: &str, link_user_id: Option<Uuid>) -> FlowExResult<OAuthAuthorization> {
+        let config = self.provider(provider)?;
+        let state = random_token();
+        let code_verifier = random_token();
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+        let authorization_url = reqwest::Url::parse_with_params(
+            &config.authorize_url,
+            &[
+                ("response_type", "code"),
+                ("client_id", config.client_id.as_str()),
+                ("redirect_uri", config.redirect_url.as_str()),
+                ("scope", config.scopes.as_str()),
+                ("state", state.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| FlowExError::Internal(format!("Invalid authorize URL of {}: {}", provider, e)))?;
+
+        let now = Utc::now();
+        let mut pending = self.pending.write().await;
+        pending.retain(|_, authorization| authorization.expires_at > now);
+        pending.insert(
+            state,
+            PendingAuthorization {
+                provider: provider.to_string(),
+                code_verifier,
+                link_user_id,
+                expires_at: now + Duration::minutes(PENDING_AUTHORIZATION_MINUTES),
+            },
+        );
+        Ok(OAuthAuthorization {
+            authorization_url: authorization_url.to_string(),
+        })
+    }
+
+    /// Finish an authorization the user came back from with `callback`:
+    /// their profile at `provider`, and the user linking it if any
+    pub async fn complete(&self, provider: &str, callback: OAuthCallback) -> FlowExResult<(ExternalProfile, Option<Uuid>)> {
+        let config = self.provider(provider)?;
+        let pending = self
+            .pending
+            .write()
+            .await
+            .remove(&callback.state)
+            .filter(|pending| pending.provider == provider && pending.expires_at > Utc::now())
+            .ok_or_else(|| FlowExError::Authentication("Unknown or expired OAuth state".to_string()))?;
+
+        let token: TokenResponse = self
+            .http
+            .post(&config.token_url)
+            .header("accept", "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", callback.code.as_str()),
+                ("redirect_uri", config.redirect_url.as_str()),
+                ("client_id", config.client_id.as_str()),
+                ("client_secret", config.client_secret.as_str()),
+                ("code_verifier", pending.code_verifier.as_str()),
+            ])
+            .send()
+            .await
+            .map_err(|e| provider_error(provider, e))?
+            .json()
+            .await
+            .map_err(|e| provider_error(provider, e))?;
+        let Some(access_token) = token.access_token else {
+            return Err(FlowExError::Authentication(format!(
+                "{} refused the authorization code: {}",
+                provider,
+                token.error_description.or(token.error).unwrap_or_default()
+            )));
+        };
+
+        let profile = self.profile(provider, config, &access_token).await?;
+        Ok((profile, pending.link_user_id))
+    }
+
+    /// User the account `subject` at `provider` is linked to
+    pub async fn identity(&self, provider: &str, subject: &str) -> Option<LinkedIdentity> {
+        self.identities
+            .read()
+            .await
+            .get(&(provider.to_string(), subject.to_string()))
+            .cloned()
+    }
+
+    /// Identities linked to the user `user_id`
+    pub async fn identities_of(&self, user_id: Uuid) -> Vec<LinkedIdentity> {
+        let mut identities: Vec<LinkedIdentity> = self
+            .identities
+            .read()
+            .await
+            .values()
+            .filter(|identity| identity.user_id == user_id)
+            .cloned()
+            .collect();
+        identities.sort_by_key(|identity| identity.linked_at);
+        identities
+    }
+
+    /// Link the account of `profile` at `provider` to the user `user_id`
+    pub async fn link(&self, provider: &str, profile: &ExternalProfile, user_id: Uuid) -> FlowExResult<LinkedIdentity> {
+        let key = (provider.to_string(), profile.subject.clone());
+        let mut identities = self.identities.write().await;
+        if let Some(existing) = identities.get(&key) {
+            if existing.user_id != user_id {
+                return Err(FlowExError::Authorization(format!(
+                    "This {} account is linked to another user",
+                    provider
+                )));
+            }
+            return Ok(existing.clone());
+        }
+        let identity = LinkedIdentity {
+            provider: provider.to_string(),
+            subject: profile.subject.clone(),
+            user_id,
+            email: profile.email.clone(),
+            linked_at: Utc::now(),
+        };
+        self.store.insert_identity(&identity).await?;
+        info!("Linked {} account {} to user {}", provider, identity.subject, user_id);
+        identities.insert(key, identity.clone());
+        Ok(identity)
+    }
+
+    fn provider(&self, provider: &str) -> FlowExResult<&OAuthProviderConfig> {
+        self.providers
+            .get(provider)
+            .ok_or_else(|| FlowExError::Validation(format!("Unknown OAuth provider: {}", provider)))
+    }
+
+    /// Profile of the account `access_token` was issued for
+    async fn profile(&self, provider: &str, config: &OAuthProviderConfig, access_token: &str) -> FlowExResult<ExternalProfile> {
+        let userinfo: serde_json::Value = self
+            .http
+            .get(&config.userinfo_url)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| provider_error(provider, e))?
+            .json()
+            .await
+            .map_err(|e| provider_error(provider, e))?;
+        let mut profile = profile_from_userinfo(&userinfo)
+            .ok_or_else(|| FlowExError::Authentication(format!("{} returned a profile without a subject", provider)))?;
+
+        // The profile's email is not known to be verified; the list says
+        if let Some(emails_url) = &config.emails_url {
+            let emails: Vec<ProviderEmail> = self
+                .http
+                .get(emails_url)
+                .bearer_auth(access_token)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| provider_error(provider, e))?
+                .json()
+                .await
+                .map_err(|e| provider_error(provider, e))?;
+            let primary = emails.into_iter().find(|email| email.primary && email.verified);
+            profile.email_verified = primary.is_some();
+            profile.email = primary.map(|email| email.email).or(profile.email);
+        }
+        Ok(profile)
+    }
+}
+
+/// Profile from an OpenID Connect userinfo response, or a provider's
+/// like it
+fn profile_from_userinfo(userinfo: &serde_json::Value) -> Option<ExternalProfile> {
+    let subject = match userinfo.get("sub").or_else(|| userinfo.get("id"))? {
+        serde_json::Value::String(subject) => subject.clone(),
+        serde_json::Value::Number(subject) => subject.to_string(),
+        _ => return None,
+    };
+    let field = |name: &str| userinfo.get(name).and_then(|value| value.as_str()).map(str::to_string);
+    let (first_name, last_name) = match (field("given_name"), field("family_name")) {
+        (Some(first_name), last_name) => (first_name, last_name.unwrap_or_default()),
+        (None, _) => {
+            let name = field("name").or_else(|| field("login")).unwrap_or_default();
+            match name.split_once(' ') {
+                Some((first_name, last_name)) => (first_name.to_string(), last_name.to_string()),
+                None => (name, String::new()),
+            }
+        }
+    };
+    Some(ExternalProfile {
+        subject,
+        email: field("email").map(|email| email.trim().to_lowercase()),
+        email_verified: userinfo.get("email_verified").and_then(|value| value.as_bool()).unwrap_or(false),
+        first_name,
+        last_name,
+    })
+}
+
+fn provider_error(provider: &str, error: reqwest::Error) -> FlowExError {
+    FlowExError::Internal(format!("OAuth request to {} failed: {}", provider, error))
+}
+
+/// Random URL-safe token for states and PKCE verifiers
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::Form,
+        http::HeaderMap,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    async fn token(Form(form): Form<HashMap<String, String>>) -> Json<serde_json::Value> {
+        if form.get("code").map(String::as_str) == Some("good-code") && form.contains_key("code_verifier") {
+            Json(serde_json::json!({ "access_token": "provider-token", "token_type": "Bearer" }))
+        } else {
+            Json(serde_json::json!({ "error": "invalid_grant" }))
+        }
+    }
+
+    async fn userinfo(headers: HeaderMap) -> Json<serde_json::Value> {
+        assert_eq!(headers["authorization"], "Bearer provider-token");
+        Json(serde_json::json!({
+            "sub": "10769150350006150715113082367",
+            "email": "Jane@Example.com",
+            "email_verified": true,
+            "given_name": "Jane",
+            "family_name": "Doe",
+        }))
+    }
+
+    /// 测试：授权码流程交换令牌并读取资料，state 只能使用一次，外部账号只能关联一个用户
+    #[tokio::test]
+    async fn test_sign_in_and_link_identity() {
+        let app = Router::new().route("/token", post(token)).route("/userinfo", get(userinfo));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let provider = OAuthProviderConfig {
+            client_id: "flowex".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: "https://flowex.com/api/auth/oauth/corporate/callback".to_string(),
+            authorize_url: format!("http://{}/authorize", address),
+            token_url: format!("http://{}/token", address),
+            userinfo_url: format!("http://{}/userinfo", address),
+            emails_url: None,
+            scopes: "openid email profile".to_string(),
+        };
+        let oauth = OAuth::new(UserStore::Memory, Vec::new())
+            .with_providers(HashMap::from([("corporate".to_string(), provider)]));
+        assert!(oauth.authorize("unknown", None).await.is_err());
+
+        let user_id = Uuid::new_v4();
+        let authorization = oauth.authorize("corporate", Some(user_id)).await.unwrap();
+        let url = reqwest::Url::parse(&authorization.authorization_url).unwrap();
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["client_id"], "flowex");
+        assert_eq!(query["code_challenge_method"], "S256");
+        let callback = |code: &str| OAuthCallback {
+            code: code.to_string(),
+            state: query["state"].clone(),
+        };
+
+        let (profile, linking) = oauth.complete("corporate", callback("good-code")).await.unwrap();
+        assert_eq!(linking, Some(user_id));
+        assert_eq!(profile.subject, "10769150350006150715113082367");
+        assert_eq!(profile.verified_email(), Some("jane@example.com"));
+        assert_eq!((profile.first_name.as_str(), profile.last_name.as_str()), ("Jane", "Doe"));
+        // The state was used up
+        assert!(matches!(
+            oauth.complete("corporate", callback("good-code")).await,
+            Err(FlowExError::Authentication(_))
+        ));
+
+        let state = oauth.authorize("corporate", None).await.unwrap().authorization_url;
+        let state = reqwest::Url::parse(&state).unwrap().query_pairs().find(|(k, _)| k == "state").unwrap().1.to_string();
+        let refused = oauth.complete("corporate", OAuthCallback { code: "bad-code".to_string(), state }).await;
+        assert!(matches!(refused, Err(FlowExError::Authentication(_))));
+
+        oauth.link("corporate", &profile, user_id).await.unwrap();
+        assert_eq!(oauth.identity("corporate", &profile.subject).await.unwrap().user_id, user_id);
+        assert_eq!(oauth.identities_of(user_id).await.len(), 1);
+        assert!(oauth.link("corporate", &profile, user_id).await.is_ok());
+        assert!(matches!(
+            oauth.link("corporate", &profile, Uuid::new_v4()).await,
+            Err(FlowExError::Authorization(_))
+        ));
+    }
+
+    /// 测试：解析不同提供方的用户资料，GitHub 的数字 id 和全名
+    #[test]
+    fn test_profile_from_userinfo() {
+        let github = profile_from_userinfo(&serde_json::json!({
+            "id": 583231,
+            "login": "octocat",
+            "name": "The Octocat",
+            "email": null,
+        }))
+        .unwrap();
+        assert_eq!(github.subject, "583231");
+        assert_eq!((github.first_name.as_str(), github.last_name.as_str()), ("The", "Octocat"));
+        assert!(github.verified_email().is_none());
+        assert!(profile_from_userinfo(&serde_json::json!({ "email": "x@example.com" })).is_none());
+    }
+}
@@ -0,0 +1,228 @@
+//! Role administration
+//!
+//! Administrators define custom roles, each granting a set of permissions,
+//! next to the built-in ones, and assign roles to users. Custom roles are
+//! written through to the store and kept in the `RoleDirectory` tokens
+//! resolve their permissions from, so a role's new permissions reach its
+//! users' tokens as they next log in or refresh.
+//!
+//! An administrator only grants what they hold: a role they define or
+//! assign may not carry a permission their own token lacks.
+
+use chrono::{DateTime, Utc};
+use flowex_auth::roles::RoleDirectory;
+use flowex_types::{FlowExError, FlowExResult, Permission, Role};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::store::UserStore;
+
+/// Longest role name
+const MAX_NAME_LEN: usize = 50;
+
+/// A role with the permissions it grants
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoleDefinition {
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<String>,
+    /// Whether the role is built in, and so cannot be changed
+    pub builtin: bool,
+    /// When a custom role was last defined
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Custom role definition request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct DefineRoleRequest {
+    #[serde(default)]
+    pub description: String,
+    pub permissions: Vec<String>,
+}
+
+/// Role assignment request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssignRolesRequest {
+    pub roles: Vec<String>,
+}
+
+/// Custom roles, written through to the store
+#[derive(Clone)]
+pub struct Roles {
+    store: UserStore,
+    directory: RoleDirectory,
+    /// By name
+    custom: Arc<RwLock<HashMap<String, RoleDefinition>>>,
+}
+
+impl Roles {
+    pub fn new(store: UserStore, custom: Vec<RoleDefinition>) -> Self {
+        let directory = RoleDirectory::new();
+        for role in &custom {
+            directory.define(&role.name, parse_permissions(&role.permissions).unwrap_or_default());
+        }
+        Self {
+            store,
+            directory,
+            custom: Arc::new(RwLock::new(
+                custom.into_iter().map(|role| (role.name.clone(), role)).collect(),
+            )),
+        }
+    }
+
+    /// Directory tokens resolve custom roles' permissions from
+    pub fn directory(&self) -> RoleDirectory {
+        self.directory.clone()
+    }
+
+    /// Built-in roles, then custom roles by name
+    pub async fn list(&self) -> Vec<RoleDefinition> {
+        let mut custom: Vec<RoleDefinition> = self.custom.read().await.values().cloned().collect();
+        custom.sort_by(|a, b| a.name.cmp(&b.name));
+        Role::ALL
+            .iter()
+            .map(|role| RoleDefinition {
+                name: role.as_str().to_string(),
+                description: "Built-in role".to_string(),
+                permissions: role.permissions().iter().map(|p| p.as_str().to_string()).collect(),
+                builtin: true,
+                updated_at: None,
+            })
+            .chain(custom)
+            .collect()
+    }
+
+    /// Define or redefine the custom role `name`, by an administrator
+    /// holding `granter_permissions`
+    pub async fn define(
+        &self,
+        name: &str,
+        request: DefineRoleRequest,
+        granter_permissions: &[String],
+    ) -> FlowExResult<RoleDefinition> {
+        if name.parse::<Role>().is_ok() {
+            return Err(FlowExError::Validation(format!("{} is a built-in role", name)));
+        }
+        let valid_name = name.len() <= MAX_NAME_LEN
+            && name.starts_with(|c: char| c.is_ascii_lowercase())
+            && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid_name {
+            return Err(FlowExError::Validation(format!(
+                "Role names are lowercase letters, digits and underscores, at most {} long",
+                MAX_NAME_LEN
+            )));
+        }
+        let parsed = parse_permissions(&request.permissions)?;
+        let mut permissions: Vec<String> = parsed.iter().map(|p| p.as_str().to_string()).collect();
+        permissions.sort();
+        permissions.dedup();
+        check_grantable(&permissions, granter_permissions)?;
+
+        let role = RoleDefinition {
+            name: name.to_string(),
+            description: request.description.trim().to_string(),
+            permissions,
+            builtin: false,
+            updated_at: Some(Utc::now()),
+        };
+        let mut custom = self.custom.write().await;
+        if let Some(existing) = custom.get(name) {
+            check_grantable(&existing.permissions, granter_permissions)?;
+        }
+        self.store.upsert_role(&role).await?;
+        self.directory.define(name, parsed);
+        custom.insert(name.to_string(), role.clone());
+        info!("Defined role {} granting {:?}", name, role.permissions);
+        Ok(role)
+    }
+
+    /// Delete the custom role `name`, by an administrator holding
+    /// `granter_permissions`; false if there is no such custom role
+    pub async fn delete(&self, name: &str, granter_permissions: &[String]) -> FlowExResult<bool> {
+        let mut custom = self.custom.write().await;
+        let Some(existing) = custom.get(name) else {
+            return Ok(false);
+        };
+        check_grantable(&existing.permissions, granter_permissions)?;
+        self.store.delete_role(name).await?;
+        self.directory.remove(name);
+        custom.remove(name);
+        info!("Deleted role {}", name);
+        Ok(true)
+    }
+
+    /// Check `roles` may be assigned by an administrator holding
+    /// `granter_permissions`
+    pub fn check_assignable(&self, roles: &[String], granter_permissions: &[String]) -> FlowExResult<()> {
+        if roles.is_empty() {
+            return Err(FlowExError::Validation("A user needs at least one role".to_string()));
+        }
+        if let Some(unknown) = roles.iter().find(|role| !self.directory.exists(role)) {
+            return Err(FlowExError::Validation(format!("Unknown role: {}", unknown)));
+        }
+        check_grantable(&self.directory.permissions_for_roles(roles), granter_permissions)
+    }
+}
+
+fn parse_permissions(permissions: &[String]) -> FlowExResult<Vec<Permission>> {
+    permissions.iter().map(|permission| permission.parse()).collect()
+}
+
+/// Refuse granting any of `permissions` the granter does not hold
+fn check_grantable(permissions: &[String], granter_permissions: &[String]) -> FlowExResult<()> {
+    match permissions.iter().find(|permission| !granter_permissions.contains(permission)) {
+        Some(permission) => Err(FlowExError::Authorization(format!(
+            "Cannot grant the {} permission without holding it",
+            permission
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admin_permissions() -> Vec<String> {
+        Role::Admin.permissions().iter().map(|p| p.as_str().to_string()).collect()
+    }
+
+    /// 测试：管理员定义自定义角色并分配，不能授予自己没有的权限，不能覆盖内置角色
+    #[tokio::test]
+    async fn test_define_and_assign_roles() {
+        let roles = Roles::new(UserStore::Memory, Vec::new());
+        let request = |permissions: &[&str]| DefineRoleRequest {
+            description: "Quotes both sides".to_string(),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+        };
+
+        let maker = roles
+            .define("market_maker", request(&["trading:write", "trading:read", "trading:write"]), &admin_permissions())
+            .await
+            .unwrap();
+        assert_eq!(maker.permissions, vec!["trading:read", "trading:write"]);
+        assert_eq!(roles.list().await.len(), Role::ALL.len() + 1);
+        assert_eq!(roles.directory().permissions_for_roles(&["market_maker".to_string()]), maker.permissions);
+
+        assert!(matches!(
+            roles.define("operator", request(&["system:maintenance"]), &admin_permissions()).await,
+            Err(FlowExError::Authorization(_))
+        ));
+        assert!(roles.define("admin", request(&["user:read"]), &admin_permissions()).await.is_err());
+        assert!(roles.define("Bad Name", request(&["user:read"]), &admin_permissions()).await.is_err());
+        assert!(roles.define("reader", request(&["not:a_permission"]), &admin_permissions()).await.is_err());
+
+        let assigned = vec!["trader".to_string(), "market_maker".to_string()];
+        assert!(roles.check_assignable(&assigned, &admin_permissions()).is_ok());
+        assert!(roles.check_assignable(&["super_admin".to_string()], &admin_permissions()).is_err());
+        assert!(roles.check_assignable(&["unknown".to_string()], &admin_permissions()).is_err());
+        assert!(roles.check_assignable(&[], &admin_permissions()).is_err());
+
+        assert!(roles.delete("market_maker", &admin_permissions()).await.unwrap());
+        assert!(!roles.delete("market_maker", &admin_permissions()).await.unwrap());
+        assert!(roles.check_assignable(&assigned, &admin_permissions()).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Login sessions
+//!
+//! Logging in or registering opens a session for the device, recording the
+//! address it came from and its user agent; its refresh tokens carry the
+//! session's id and each refresh marks it active again. Users list their
+//! sessions and revoke any of them, after which the session's refresh token
+//! is refused; access tokens it was issued run out within the hour.
+//! Changing the password revokes every session of the user.
+//!
+//! Sessions are kept by the shared `flowex_cache::SessionManager` in Redis
+//! when the service runs with `REDIS_URL`, otherwise in memory. Either way
+//! a session unused for as long as a refresh token lives expires.
+
+use chrono::{DateTime, Utc};
+use flowex_cache::{CacheError, CacheManager, SessionData, SessionManager, UserSession};
+use flowex_types::{FlowExError, FlowExResult};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::tokens::REFRESH_TOKEN_DAYS;
+
+/// A session as its user sees it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// When the session last logged in or refreshed its tokens
+    pub last_active_at: DateTime<Utc>,
+}
+
+impl From<UserSession> for Session {
+    fn from(session: UserSession) -> Self {
+        Self {
+            id: session.id,
+            ip_address: session.data.ip_address,
+            user_agent: session.data.user_agent,
+            created_at: session.created_at,
+            last_active_at: session.last_accessed,
+        }
+    }
+}
+
+/// Where sessions are kept
+#[derive(Clone)]
+pub enum Sessions {
+    /// By session id
+    Memory(Arc<RwLock<HashMap<String, UserSession>>>),
+    Redis(Box<SessionManager>),
+}
+
+impl Sessions {
+    /// Sessions local to this instance
+    pub fn in_memory() -> Self {
+        Sessions::Memory(Arc::new(RwLock::new(HashMap::new())))
+    }
+
+    /// Sessions shared by every instance through Redis
+    pub async fn connect(redis_url: &str) -> Result<Self, CacheError> {
+        let cache = CacheManager::new(redis_url, session_ttl()).await?;
+        Ok(Sessions::Redis(Box::new(SessionManager::new(cache, session_ttl()))))
+    }
+
+    /// Open a session for `user_id` on the device `data` describes; its id
+    pub async fn open(&self, user_id: Uuid, data: SessionData) -> FlowExResult<String> {
+        let session_id = match self {
+            Sessions::Memory(sessions) => {
+                let now = Utc::now();
+                let session = UserSession {
+                    id: Uuid::new_v4().to_string(),
+                    user_id,
+                    data,
+                    created_at: now,
+                    last_accessed: now,
+                };
+                let mut sessions = sessions.write().await;
+                prune(&mut sessions);
+                sessions.insert(session.id.clone(), session.clone());
+                session.id
+            }
+            Sessions::Redis(manager) => manager.create_session(user_id, data).await.map_err(session_error)?,
+        };
+        info!("Opened session {} for user {}", session_id, user_id);
+        Ok(session_id)
+    }
+
+    /// Mark a session active; `None` if it was revoked or has expired
+    pub async fn touch(&self, session_id: &str) -> FlowExResult<Option<UserSession>> {
+        match self {
+            Sessions::Memory(sessions) => {
+                let mut sessions = sessions.write().await;
+                prune(&mut sessions);
+                Ok(sessions.get_mut(session_id).map(|session| {
+                    session.last_accessed = Utc::now();
+                    session.clone()
+                }))
+            }
+            Sessions::Redis(manager) => manager.get_session(session_id).await.map_err(session_error),
+        }
+    }
+
+    /// `user_id`'s sessions, the most recently active first
+    pub async fn list(&self, user_id: Uuid) -> FlowExResult<Vec<Session>> {
+        let mut sessions: Vec<Session> = match self {
+            Sessions::Memory(sessions) => {
+                let mut sessions = sessions.write().await;
+                prune(&mut sessions);
+                sessions
+                    .values()
+                    .filter(|session| session.user_id == user_id)
+                    .cloned()
+                    .map(Session::from)
+                    .collect()
+            }
+            Sessions::Redis(manager) => manager
+                .list_user_sessions(user_id)
+                .await
+                .map_err(session_error)?
+                .into_iter()
+                .map(Session::from)
+                .collect(),
+        };
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_active_at));
+        Ok(sessions)
+    }
+
+    /// Revoke one of `user_id`'s sessions; false if they have no such session
+    pub async fn revoke(&self, user_id: Uuid, session_id: &str) -> FlowExResult<bool> {
+        let revoked = match self {
+            Sessions::Memory(sessions) => {
+                let mut sessions = sessions.write().await;
+                match sessions.get(session_id) {
+                    Some(session) if session.user_id == user_id => sessions.remove(session_id).is_some(),
+                    _ => false,
+                }
+            }
+            Sessions::Redis(manager) => match manager.get_session(session_id).await.map_err(session_error)? {
+                Some(session) if session.user_id == user_id => {
+                    manager.delete_session(session_id).await.map_err(session_error)?
+                }
+                _ => false,
+            },
+        };
+        if revoked {
+            info!("Revoked session {} of user {}", session_id, user_id);
+        }
+        Ok(revoked)
+    }
+
+    /// Revoke every session of `user_id`; how many there were
+    pub async fn revoke_all(&self, user_id: Uuid) -> FlowExResult<u32> {
+        let revoked = match self {
+            Sessions::Memory(sessions) => {
+                let mut sessions = sessions.write().await;
+                let before = sessions.len();
+                sessions.retain(|_, session| session.user_id != user_id);
+                (before - sessions.len()) as u32
+            }
+            Sessions::Redis(manager) => manager.delete_user_sessions(user_id).await.map_err(session_error)?,
+        };
+        info!("Revoked {} sessions of user {}", revoked, user_id);
+        Ok(revoked)
+    }
+}
+
+/// How long a session lives unused
+fn session_ttl() -> Duration {
+    Duration::from_secs(REFRESH_TOKEN_DAYS as u64 * 24 * 60 * 60)
+}
+
+/// Drop sessions unused for longer than they live
+fn prune(sessions: &mut HashMap<String, UserSession>) {
+    let cutoff = Utc::now() - chrono::Duration::days(REFRESH_TOKEN_DAYS);
+    sessions.retain(|_, session| session.last_accessed > cutoff);
+}
+
+fn session_error(error: CacheError) -> FlowExError {
+    FlowExError::Internal(format!("Session store failed: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(user_agent: &str) -> SessionData {
+        SessionData {
+            ip_address: Some("10.0.0.1".to_string()),
+            user_agent: Some(user_agent.to_string()),
+            roles: vec!["user".to_string()],
+            permissions: Vec::new(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// 测试：登录会话可以列出和撤销，用户只能撤销自己的会话，修改密码时撤销全部会话
+    #[tokio::test]
+    async fn test_list_and_revoke_sessions() {
+        let sessions = Sessions::in_memory();
+        let user_id = Uuid::new_v4();
+        let other_id = Uuid::new_v4();
+
+        let laptop = sessions.open(user_id, device("laptop")).await.unwrap();
+        let phone = sessions.open(user_id, device("phone")).await.unwrap();
+        sessions.open(other_id, device("tablet")).await.unwrap();
+        sessions.touch(&laptop).await.unwrap().unwrap();
+
+        let listed = sessions.list(user_id).await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!((listed[0].id.as_str(), listed[0].user_agent.as_deref()), (laptop.as_str(), Some("laptop")));
+        assert_eq!(listed[0].ip_address.as_deref(), Some("10.0.0.1"));
+
+        assert!(!sessions.revoke(other_id, &phone).await.unwrap());
+        assert!(sessions.revoke(user_id, &phone).await.unwrap());
+        assert!(sessions.touch(&phone).await.unwrap().is_none());
+        assert_eq!(sessions.list(user_id).await.unwrap().len(), 1);
+
+        assert_eq!(sessions.revoke_all(user_id).await.unwrap(), 1);
+        assert!(sessions.touch(&laptop).await.unwrap().is_none());
+        assert_eq!(sessions.list(other_id).await.unwrap().len(), 1);
+    }
+}
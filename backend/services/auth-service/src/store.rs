@@ -0,0 +1,679 @@
+//! User storage
+//!
+//! Users are kept in memory by `Users`; `UserStore` makes them durable. In
+//! PostgreSQL, through `flowex-database`, each user is a row of the `users`
+//! table with their bcrypt password hash, written as they register or
+//! change their password, their roles rows of `user_roles`, and their API
+//! keys rows of `api_keys`, and the accounts at OAuth providers linked to
+//! them rows of `oauth_identities`; custom roles are rows of `roles`. Their
+//! KYC documents are rows of `kyc_documents` and their applications to be
+//! verified rows of `kyc_applications`. Sub-accounts are users too, with a
+//! row of `sub_accounts` naming their master. On startup active users,
+//! their keys, identities, sub-accounts and KYC applications, the custom
+//! roles and what
+//! documents were uploaded are loaded back from it; the documents' content
+//! is read only to submit them for verification.
+//! Audit entries are appended to `auth_audit_events` and read back from
+//! there, never loaded whole.
+//! Without a database nothing outlives the process.
+
+use chrono::{DateTime, Utc};
+use flowex_database::DatabasePool;
+use flowex_kyc::{KycApplication, KycDocument};
+use flowex_types::{AccountStatus, FlowExError, FlowExResult, Role, SubAccount, User};
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::api_keys::{ApiKey, ApiKeyRecord};
+use crate::audit::AuditEntry;
+use crate::oauth::LinkedIdentity;
+use crate::roles::RoleDefinition;
+
+/// A user with the hash of their password
+#[derive(Debug, Clone)]
+pub struct UserRecord {
+    pub user: User,
+    /// bcrypt hash, salt and cost included
+    pub password_hash: String,
+    /// Roles granted to the user's tokens
+    pub roles: Vec<String>,
+    pub status: AccountStatus,
+    /// Version the user's tokens are signed with
+    pub token_version: u64,
+}
+
+/// Where users are kept
+#[derive(Clone, Default)]
+pub enum UserStore {
+    /// Only in memory, lost on restart
+    #[default]
+    Memory,
+    /// In PostgreSQL
+    Postgres(DatabasePool),
+}
+
+impl UserStore {
+    /// Store backed by the PostgreSQL database at `database_url`
+    pub async fn connect(database_url: &str) -> FlowExResult<Self> {
+        let pool = DatabasePool::new(database_url).await.map_err(database_error)?;
+        Ok(UserStore::Postgres(pool))
+    }
+
+    /// Every active user stored
+    pub async fn load(&self) -> FlowExResult<Vec<UserRecord>> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(Vec::new());
+        };
+        sqlx::query(
+            "SELECT u.id, u.email, u.password_hash, u.first_name, u.last_name, u.is_verified, u.role, u.status, \
+             u.token_version, u.created_at, u.updated_at, COALESCE(ARRAY_AGG(r.role) FILTER (WHERE r.role IS NOT NULL), '{}') AS roles \
+             FROM users u LEFT JOIN user_roles r ON r.user_id = u.id WHERE u.is_active GROUP BY u.id",
+        )
+        .fetch_all(pool.pool())
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(user_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)
+    }
+
+    /// Replace a user's password hash
+    pub async fn update_password(&self, user_id: Uuid, password_hash: &str, updated_at: DateTime<Utc>) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query("UPDATE users SET password_hash = $2, updated_at = $3 WHERE id = $1")
+            .bind(user_id)
+            .bind(password_hash)
+            .bind(updated_at)
+            .execute(pool.pool())
+            .await
+            .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Freeze or unfreeze a user's account
+    pub async fn set_account_status(&self, user_id: Uuid, status: AccountStatus, updated_at: DateTime<Utc>) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query("UPDATE users SET status = $2, updated_at = $3 WHERE id = $1")
+            .bind(user_id)
+            .bind(status.as_str())
+            .bind(updated_at)
+            .execute(pool.pool())
+            .await
+            .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Replace the version a user's tokens are signed with
+    pub async fn set_token_version(&self, user_id: Uuid, token_version: u64) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query("UPDATE users SET token_version = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(token_version as i64)
+            .execute(pool.pool())
+            .await
+            .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// API keys of every active user
+    pub async fn load_api_keys(&self) -> FlowExResult<Vec<ApiKeyRecord>> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(Vec::new());
+        };
+        sqlx::query(
+            "SELECT k.id, k.user_id, u.email, k.api_key, k.secret, k.label, k.permissions, k.ip_allowlist, \
+             k.created_at, k.revoked_at FROM api_keys k JOIN users u ON u.id = k.user_id WHERE u.is_active",
+        )
+        .fetch_all(pool.pool())
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(api_key_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)
+    }
+
+    /// Write a newly created API key
+    pub async fn insert_api_key(&self, record: &ApiKeyRecord) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        let key = &record.key;
+        let ip_allowlist: Vec<String> = key.ip_allowlist.iter().map(|ip| ip.to_string()).collect();
+        sqlx::query(
+            "INSERT INTO api_keys (id, user_id, api_key, secret, label, permissions, ip_allowlist, created_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(key.id)
+        .bind(key.user_id)
+        .bind(&key.api_key)
+        .bind(&record.secret)
+        .bind(&key.label)
+        .bind(&key.permissions)
+        .bind(&ip_allowlist)
+        .bind(key.created_at)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Mark an API key revoked
+    pub async fn revoke_api_key(&self, id: Uuid, revoked_at: DateTime<Utc>) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query("UPDATE api_keys SET revoked_at = $2 WHERE id = $1")
+            .bind(id)
+            .bind(revoked_at)
+            .execute(pool.pool())
+            .await
+            .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Sub-accounts of every active master
+    pub async fn load_sub_accounts(&self) -> FlowExResult<Vec<SubAccount>> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(Vec::new());
+        };
+        sqlx::query(
+            "SELECT s.user_id, s.master_id, s.label, u.email, s.permissions, s.created_at, s.updated_at \
+             FROM sub_accounts s JOIN users u ON u.id = s.user_id JOIN users m ON m.id = s.master_id \
+             WHERE u.is_active AND m.is_active",
+        )
+        .fetch_all(pool.pool())
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(sub_account_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)
+    }
+
+    /// Write a newly opened sub-account, whose user is already written
+    pub async fn insert_sub_account(&self, account: &SubAccount) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO sub_accounts (user_id, master_id, label, permissions, created_at, updated_at) \
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(account.user_id)
+        .bind(account.master_id)
+        .bind(&account.label)
+        .bind(&account.permissions)
+        .bind(account.created_at)
+        .bind(account.updated_at)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Replace the permissions delegated to a sub-account
+    pub async fn update_sub_account_permissions(
+        &self,
+        user_id: Uuid,
+        permissions: &[String],
+        updated_at: DateTime<Utc>,
+    ) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query("UPDATE sub_accounts SET permissions = $2, updated_at = $3 WHERE user_id = $1")
+            .bind(user_id)
+            .bind(permissions)
+            .bind(updated_at)
+            .execute(pool.pool())
+            .await
+            .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Write a newly registered user; false if their email is already
+    /// registered
+    pub async fn insert(&self, record: &UserRecord) -> FlowExResult<bool> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(true);
+        };
+        let user = &record.user;
+        let role = record.roles.first().map(String::as_str).unwrap_or(Role::User.as_str());
+        let mut tx = pool.begin_transaction().await.map_err(database_error)?;
+        let result = sqlx::query(
+            "INSERT INTO users (id, email, password_hash, first_name, last_name, is_verified, role, created_at, \
+             updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT (email) DO NOTHING",
+        )
+        .bind(user.id)
+        .bind(&user.email)
+        .bind(&record.password_hash)
+        .bind(&user.first_name)
+        .bind(&user.last_name)
+        .bind(user.is_verified)
+        .bind(role)
+        .bind(user.created_at)
+        .bind(user.updated_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("INSERT INTO user_roles (user_id, role) SELECT $1, UNNEST($2::VARCHAR[])")
+            .bind(user.id)
+            .bind(&record.roles)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        tx.commit().await.map_err(database_error)?;
+        Ok(true)
+    }
+
+    /// Replace the roles of a user
+    pub async fn set_roles(&self, user_id: Uuid, roles: &[String]) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        let mut tx = pool.begin_transaction().await.map_err(database_error)?;
+        sqlx::query("DELETE FROM user_roles WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        sqlx::query("INSERT INTO user_roles (user_id, role) SELECT $1, UNNEST($2::VARCHAR[])")
+            .bind(user_id)
+            .bind(roles)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        tx.commit().await.map_err(database_error)
+    }
+
+    /// Every custom role
+    pub async fn load_roles(&self) -> FlowExResult<Vec<RoleDefinition>> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(Vec::new());
+        };
+        sqlx::query("SELECT name, description, permissions, updated_at FROM roles")
+            .fetch_all(pool.pool())
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| {
+                Ok(RoleDefinition {
+                    name: row.try_get("name")?,
+                    description: row.try_get("description")?,
+                    permissions: row.try_get("permissions")?,
+                    builtin: false,
+                    updated_at: Some(row.try_get("updated_at")?),
+                })
+            })
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(database_error)
+    }
+
+    /// Write a custom role, new or redefined
+    pub async fn upsert_role(&self, role: &RoleDefinition) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO roles (name, description, permissions, updated_at) VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (name) DO UPDATE SET description = $2, permissions = $3, updated_at = $4",
+        )
+        .bind(&role.name)
+        .bind(&role.description)
+        .bind(&role.permissions)
+        .bind(role.updated_at)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Delete a custom role, unassigning it from every user
+    pub async fn delete_role(&self, name: &str) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        let mut tx = pool.begin_transaction().await.map_err(database_error)?;
+        sqlx::query("DELETE FROM user_roles WHERE role = $1")
+            .bind(name)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        sqlx::query("DELETE FROM roles WHERE name = $1")
+            .bind(name)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        tx.commit().await.map_err(database_error)
+    }
+
+    /// Identities at OAuth providers of every active user
+    pub async fn load_identities(&self) -> FlowExResult<Vec<LinkedIdentity>> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(Vec::new());
+        };
+        sqlx::query(
+            "SELECT i.provider, i.subject, i.user_id, i.email, i.linked_at FROM oauth_identities i \
+             JOIN users u ON u.id = i.user_id WHERE u.is_active",
+        )
+        .fetch_all(pool.pool())
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(|row| {
+            Ok(LinkedIdentity {
+                provider: row.try_get("provider")?,
+                subject: row.try_get("subject")?,
+                user_id: row.try_get("user_id")?,
+                email: row.try_get("email")?,
+                linked_at: row.try_get("linked_at")?,
+            })
+        })
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)
+    }
+
+    /// Write a newly linked identity
+    pub async fn insert_identity(&self, identity: &LinkedIdentity) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO oauth_identities (provider, subject, user_id, email, linked_at) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&identity.provider)
+        .bind(&identity.subject)
+        .bind(identity.user_id)
+        .bind(&identity.email)
+        .bind(identity.linked_at)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// KYC documents of every active user, without their content
+    pub async fn load_kyc_documents(&self) -> FlowExResult<Vec<KycDocument>> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(Vec::new());
+        };
+        sqlx::query(
+            "SELECT d.id, d.user_id, d.document_type, d.sha256, d.size, d.uploaded_at FROM kyc_documents d \
+             JOIN users u ON u.id = d.user_id WHERE u.is_active",
+        )
+        .fetch_all(pool.pool())
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(kyc_document_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)
+    }
+
+    /// Content of the KYC documents `ids`, by id
+    pub async fn kyc_document_contents(&self, ids: &[Uuid]) -> FlowExResult<Vec<(Uuid, Vec<u8>)>> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(Vec::new());
+        };
+        sqlx::query("SELECT id, content FROM kyc_documents WHERE id = ANY($1)")
+            .bind(ids)
+            .fetch_all(pool.pool())
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| Ok((row.try_get("id")?, row.try_get("content")?)))
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(database_error)
+    }
+
+    /// Write a newly uploaded KYC document
+    pub async fn insert_kyc_document(&self, document: &KycDocument) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO kyc_documents (id, user_id, document_type, sha256, size, content, uploaded_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(document.id)
+        .bind(document.user_id)
+        .bind(document.document_type.as_str())
+        .bind(&document.sha256)
+        .bind(document.size as i32)
+        .bind(&document.content)
+        .bind(document.uploaded_at)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// KYC applications of every active user
+    pub async fn load_kyc_applications(&self) -> FlowExResult<Vec<KycApplication>> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(Vec::new());
+        };
+        sqlx::query(
+            "SELECT a.id, a.user_id, a.level, a.details, a.document_ids, a.status, a.provider, a.provider_reference, \
+             a.rejection_reason, a.reviewed_by, a.created_at, a.updated_at FROM kyc_applications a \
+             JOIN users u ON u.id = a.user_id WHERE u.is_active",
+        )
+        .fetch_all(pool.pool())
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(kyc_application_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)
+    }
+
+    /// Write a new KYC application, or the decision on one
+    pub async fn upsert_kyc_application(&self, application: &KycApplication) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        let details = serde_json::to_value(&application.details)
+            .map_err(|e| FlowExError::Internal(format!("Failed to serialize KYC details: {}", e)))?;
+        sqlx::query(
+            "INSERT INTO kyc_applications (id, user_id, level, details, document_ids, status, provider, \
+             provider_reference, rejection_reason, reviewed_by, created_at, updated_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) \
+             ON CONFLICT (id) DO UPDATE SET status = $6, provider_reference = $8, rejection_reason = $9, \
+             reviewed_by = $10, updated_at = $12",
+        )
+        .bind(application.id)
+        .bind(application.user_id)
+        .bind(application.level.as_str())
+        .bind(details)
+        .bind(&application.document_ids)
+        .bind(application.status.as_str())
+        .bind(&application.provider)
+        .bind(&application.provider_reference)
+        .bind(&application.rejection_reason)
+        .bind(application.reviewed_by)
+        .bind(application.created_at)
+        .bind(application.updated_at)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Append an audit entry
+    pub async fn append_audit(&self, entry: &AuditEntry) -> FlowExResult<()> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO auth_audit_events (id, user_id, actor_id, event, email, ip_address, user_agent, details, \
+             created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(entry.id)
+        .bind(entry.user_id)
+        .bind(entry.actor_id)
+        .bind(entry.event.as_str())
+        .bind(&entry.email)
+        .bind(&entry.ip_address)
+        .bind(&entry.user_agent)
+        .bind(&entry.details)
+        .bind(entry.created_at)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Latest audit entries, concerning `user_id` only if given, newest
+    /// first; `None` without a database
+    pub async fn load_audit(&self, user_id: Option<Uuid>, limit: usize) -> FlowExResult<Option<Vec<AuditEntry>>> {
+        let UserStore::Postgres(pool) = self else {
+            return Ok(None);
+        };
+        sqlx::query(
+            "SELECT id, user_id, actor_id, event, email, ip_address, user_agent, details, created_at \
+             FROM auth_audit_events WHERE $1::UUID IS NULL OR user_id = $1 ORDER BY created_at DESC LIMIT $2",
+        )
+        .bind(user_id)
+        .bind(limit as i64)
+        .fetch_all(pool.pool())
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(audit_entry_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map(Some)
+        .map_err(database_error)
+    }
+}
+
+fn database_error(error: sqlx::Error) -> FlowExError {
+    FlowExError::Database(error.to_string())
+}
+
+fn user_from_row(row: &PgRow) -> Result<UserRecord, sqlx::Error> {
+    let user = User {
+        id: row.try_get("id")?,
+        email: row.try_get("email")?,
+        first_name: row.try_get("first_name")?,
+        last_name: row.try_get("last_name")?,
+        is_verified: row.try_get::<Option<bool>, _>("is_verified")?.unwrap_or_default(),
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    };
+    let mut roles: Vec<String> = row.try_get("roles")?;
+    if roles.is_empty() {
+        // No roles assigned: the one they registered with
+        roles.push(
+            row.try_get::<Option<String>, _>("role")?
+                .unwrap_or_else(|| Role::User.as_str().to_string()),
+        );
+    }
+    let status = row.try_get::<String, _>("status")?;
+    Ok(UserRecord {
+        user,
+        password_hash: row.try_get("password_hash")?,
+        roles,
+        status: status
+            .parse()
+            .map_err(|_| sqlx::Error::Decode(format!("invalid account status {:?}", status).into()))?,
+        token_version: row.try_get::<i64, _>("token_version")?.max(0) as u64,
+    })
+}
+
+fn api_key_from_row(row: &PgRow) -> Result<ApiKeyRecord, sqlx::Error> {
+    let ip_allowlist = row
+        .try_get::<Vec<String>, _>("ip_allowlist")?
+        .iter()
+        .map(|ip| ip.parse().map_err(|_| sqlx::Error::Decode(format!("invalid address {:?}", ip).into())))
+        .collect::<Result<_, _>>()?;
+    Ok(ApiKeyRecord {
+        key: ApiKey {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            api_key: row.try_get("api_key")?,
+            label: row.try_get("label")?,
+            permissions: row.try_get("permissions")?,
+            ip_allowlist,
+            created_at: row.try_get("created_at")?,
+            revoked_at: row.try_get("revoked_at")?,
+        },
+        email: row.try_get("email")?,
+        secret: row.try_get("secret")?,
+    })
+}
+
+fn sub_account_from_row(row: &PgRow) -> Result<SubAccount, sqlx::Error> {
+    Ok(SubAccount {
+        user_id: row.try_get("user_id")?,
+        master_id: row.try_get("master_id")?,
+        label: row.try_get("label")?,
+        email: row.try_get("email")?,
+        permissions: row.try_get("permissions")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+fn audit_entry_from_row(row: &PgRow) -> Result<AuditEntry, sqlx::Error> {
+    let event: String = row.try_get("event")?;
+    Ok(AuditEntry {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        actor_id: row.try_get("actor_id")?,
+        event: event
+            .parse()
+            .map_err(|_| sqlx::Error::Decode(format!("invalid audit event {:?}", event).into()))?,
+        email: row.try_get("email")?,
+        ip_address: row.try_get("ip_address")?,
+        user_agent: row.try_get("user_agent")?,
+        details: row.try_get("details")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn kyc_document_from_row(row: &PgRow) -> Result<KycDocument, sqlx::Error> {
+    let document_type: String = row.try_get("document_type")?;
+    Ok(KycDocument {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        document_type: document_type
+            .parse()
+            .map_err(|_| sqlx::Error::Decode(format!("invalid document type {:?}", document_type).into()))?,
+        sha256: row.try_get("sha256")?,
+        size: row.try_get::<i32, _>("size")? as usize,
+        uploaded_at: row.try_get("uploaded_at")?,
+        content: Vec::new(),
+    })
+}
+
+fn kyc_application_from_row(row: &PgRow) -> Result<KycApplication, sqlx::Error> {
+    let level: String = row.try_get("level")?;
+    let status: String = row.try_get("status")?;
+    let invalid = |what: &str, value: &str| sqlx::Error::Decode(format!("invalid {} {:?}", what, value).into());
+    Ok(KycApplication {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        level: level.parse().map_err(|_| invalid("KYC level", &level))?,
+        details: serde_json::from_value(row.try_get("details")?).map_err(|e| sqlx::Error::Decode(e.into()))?,
+        document_ids: row.try_get("document_ids")?,
+        status: status.parse().map_err(|_| invalid("application status", &status))?,
+        provider: row.try_get("provider")?,
+        provider_reference: row.try_get("provider_reference")?,
+        rejection_reason: row.try_get("rejection_reason")?,
+        reviewed_by: row.try_get("reviewed_by")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
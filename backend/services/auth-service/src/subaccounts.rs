@@ -0,0 +1,245 @@
+//! Sub-accounts
+//!
+//! A master account may open sub-accounts under it, each a user of its own
+//! and so with balances, orders and limits of its own at every service.
+//! Nobody signs in to a sub-account: it gets a generated email and a
+//! password nobody knows, and its master acts for it with API keys created
+//! for it. The master delegates some of `API_KEY_PERMISSIONS` to each
+//! sub-account, and its keys may hold no others; narrowing the delegated
+//! permissions narrows the keys already created at once. A sub-account
+//! cannot open sub-accounts of its own.
+//!
+//! Services look a master's sub-accounts up at the internal
+//! `/internal/subaccounts/:master_id` to report on them together.
+
+use chrono::Utc;
+use flowex_middleware::api_key::ApiKeyCredential;
+use flowex_types::{FlowExError, FlowExResult, SubAccount};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::api_keys::validate_permissions;
+use crate::store::UserStore;
+use crate::users::Users;
+
+/// Domain of the emails sub-accounts are registered with
+const SUB_ACCOUNT_EMAIL_DOMAIN: &str = "subaccounts.flowex.com";
+
+/// Most sub-accounts a master may open
+const MAX_SUB_ACCOUNTS: usize = 50;
+
+/// Longest sub-account label
+const MAX_LABEL_LEN: usize = 64;
+
+/// Sub-account creation request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateSubAccountRequest {
+    pub label: String,
+    /// Permissions delegated to the sub-account's API keys
+    pub permissions: Vec<String>,
+}
+
+/// Delegated permissions update request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubAccountPermissionsRequest {
+    pub permissions: Vec<String>,
+}
+
+/// Sub-accounts of every master, written through to the store
+#[derive(Clone)]
+pub struct SubAccounts {
+    store: UserStore,
+    /// By the sub-account's user id
+    accounts: Arc<RwLock<HashMap<Uuid, SubAccount>>>,
+}
+
+impl SubAccounts {
+    pub fn new(store: UserStore, accounts: Vec<SubAccount>) -> Self {
+        let accounts = accounts.into_iter().map(|account| (account.user_id, account)).collect();
+        Self {
+            store,
+            accounts: Arc::new(RwLock::new(accounts)),
+        }
+    }
+
+    /// The sub-account `user_id`, if it is one
+    pub async fn get(&self, user_id: Uuid) -> Option<SubAccount> {
+        self.accounts.read().await.get(&user_id).cloned()
+    }
+
+    /// The sub-account `user_id`, if it is one of `master_id`'s
+    pub async fn get_of(&self, master_id: Uuid, user_id: Uuid) -> Option<SubAccount> {
+        self.get(user_id).await.filter(|account| account.master_id == master_id)
+    }
+
+    /// Sub-accounts of `master_id`, oldest first
+    pub async fn list(&self, master_id: Uuid) -> Vec<SubAccount> {
+        let mut accounts: Vec<SubAccount> = self
+            .accounts
+            .read()
+            .await
+            .values()
+            .filter(|account| account.master_id == master_id)
+            .cloned()
+            .collect();
+        accounts.sort_by_key(|account| account.created_at);
+        accounts
+    }
+
+    /// Open a sub-account under `master_id`, registering its user in
+    /// `users`
+    pub async fn create(&self, users: &Users, master_id: Uuid, request: CreateSubAccountRequest) -> FlowExResult<SubAccount> {
+        let label = request.label.trim().to_string();
+        if label.is_empty() || label.chars().count() > MAX_LABEL_LEN {
+            return Err(FlowExError::Validation(format!(
+                "Label must be 1 to {} characters",
+                MAX_LABEL_LEN
+            )));
+        }
+        let permissions = validate_permissions(request.permissions)?;
+
+        let mut accounts = self.accounts.write().await;
+        if accounts.contains_key(&master_id) {
+            return Err(FlowExError::Authorization(
+                "A sub-account cannot open sub-accounts".to_string(),
+            ));
+        }
+        let mut siblings = accounts.values().filter(|account| account.master_id == master_id);
+        if siblings.clone().any(|account| account.label.eq_ignore_ascii_case(&label)) {
+            return Err(FlowExError::Validation(format!("A sub-account is already labelled {:?}", label)));
+        }
+        if siblings.nth(MAX_SUB_ACCOUNTS - 1).is_some() {
+            return Err(FlowExError::Validation(format!(
+                "A master may open at most {} sub-accounts",
+                MAX_SUB_ACCOUNTS
+            )));
+        }
+
+        let email = format!("{}@{}", Uuid::new_v4().simple(), SUB_ACCOUNT_EMAIL_DOMAIN);
+        let record = users
+            .register_external(&email, label.clone(), "Sub-account".to_string())
+            .await?
+            .ok_or_else(|| FlowExError::Internal(format!("Sub-account email {} is taken", email)))?;
+        let account = SubAccount {
+            user_id: record.user.id,
+            master_id,
+            label,
+            email: record.user.email,
+            permissions,
+            created_at: record.user.created_at,
+            updated_at: record.user.created_at,
+        };
+        self.store.insert_sub_account(&account).await?;
+        info!("User {} opened sub-account {}", master_id, account.user_id);
+        accounts.insert(account.user_id, account.clone());
+        Ok(account)
+    }
+
+    /// Replace the permissions delegated to one of `master_id`'s
+    /// sub-accounts; `None` if they have no such sub-account
+    pub async fn set_permissions(
+        &self,
+        master_id: Uuid,
+        user_id: Uuid,
+        permissions: Vec<String>,
+    ) -> FlowExResult<Option<SubAccount>> {
+        let permissions = validate_permissions(permissions)?;
+        let mut accounts = self.accounts.write().await;
+        let Some(account) = accounts.get_mut(&user_id).filter(|account| account.master_id == master_id) else {
+            return Ok(None);
+        };
+        let updated_at = Utc::now();
+        self.store.update_sub_account_permissions(user_id, &permissions, updated_at).await?;
+        account.permissions = permissions;
+        account.updated_at = updated_at;
+        info!("User {} delegated {:?} to sub-account {}", master_id, account.permissions, user_id);
+        Ok(Some(account.clone()))
+    }
+
+    /// Take from `credential` the permissions its sub-account no longer
+    /// delegates, if it is a sub-account's
+    pub async fn restrict(&self, credential: &mut ApiKeyCredential) {
+        if let Some(account) = self.accounts.read().await.get(&credential.user_id) {
+            credential.permissions.retain(|permission| account.permissions.contains(permission));
+        }
+    }
+}
+
+/// Check that `account` delegates every one of `permissions`
+pub fn check_delegated(account: &SubAccount, permissions: &[String]) -> FlowExResult<()> {
+    match permissions.iter().find(|permission| !account.permissions.contains(permission)) {
+        Some(permission) => Err(FlowExError::Authorization(format!(
+            "Sub-account {} is not delegated the {} permission",
+            account.user_id, permission
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_keys::{ApiKeys, CreateApiKeyRequest};
+    use flowex_auth::PasswordManager;
+
+    /// 测试：主账户创建子账户并委派权限，子账户不能再创建子账户，收窄权限后已有 API 密钥随之收窄
+    #[tokio::test]
+    async fn test_sub_accounts_and_delegated_permissions() {
+        let users = Users::new(UserStore::Memory, PasswordManager::new(Some(4)), Vec::new());
+        let sub_accounts = SubAccounts::new(UserStore::Memory, Vec::new());
+        let master = Uuid::new_v4();
+        let request = |label: &str, permissions: &[&str]| CreateSubAccountRequest {
+            label: label.to_string(),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+        };
+
+        let desk = sub_accounts
+            .create(&users, master, request(" Desk A ", &["trading:read", "trading:write"]))
+            .await
+            .unwrap();
+        assert_eq!((desk.master_id, desk.label.as_str()), (master, "Desk A"));
+        assert!(desk.email.ends_with(SUB_ACCOUNT_EMAIL_DOMAIN));
+        assert_eq!(users.get(&desk.email).await.unwrap().user.id, desk.user_id);
+        assert!(sub_accounts.create(&users, master, request("desk a", &["trading:read"])).await.is_err());
+        assert!(sub_accounts.create(&users, master, request("Desk B", &["wallet:withdraw"])).await.is_err());
+        assert!(matches!(
+            sub_accounts.create(&users, desk.user_id, request("Nested", &["trading:read"])).await,
+            Err(FlowExError::Authorization(_))
+        ));
+        assert_eq!(sub_accounts.list(master).await, vec![desk.clone()]);
+        assert!(sub_accounts.get_of(Uuid::new_v4(), desk.user_id).await.is_none());
+
+        assert!(check_delegated(&desk, &["wallet:read".to_string()]).is_err());
+        let keys = ApiKeys::new(UserStore::Memory, Vec::new());
+        let created = keys
+            .create(
+                desk.user_id,
+                &desk.email,
+                CreateApiKeyRequest {
+                    label: "desk bot".to_string(),
+                    permissions: desk.permissions.clone(),
+                    ip_allowlist: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(sub_accounts
+            .set_permissions(Uuid::new_v4(), desk.user_id, vec!["trading:read".to_string()])
+            .await
+            .unwrap()
+            .is_none());
+        sub_accounts
+            .set_permissions(master, desk.user_id, vec!["trading:read".to_string()])
+            .await
+            .unwrap()
+            .unwrap();
+        let mut credential = keys.credential(&created.key.api_key).await.unwrap();
+        sub_accounts.restrict(&mut credential).await;
+        assert_eq!(credential.permissions, vec!["trading:read"]);
+    }
+}
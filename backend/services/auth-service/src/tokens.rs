@@ -0,0 +1,202 @@
+//! Access and refresh tokens
+//!
+//! Logging in issues a short-lived access token, which services check with
+//! the shared `jwt_auth_middleware`, and a refresh token that buys a new
+//! pair at `POST /api/auth/refresh`. Both are signed by the shared
+//! `flowex_auth::JwtManager` with RS256 keys of a `KeyRing`, whose public
+//! halves are published at `/.well-known/jwks.json` for services to verify
+//! tokens with. Refresh tokens name the login session they renew (see
+//! `sessions`). Access tokens carry the state of the user's account: its
+//! status, KYC level and the version its tokens are signed with, which an
+//! administrator raises to log every token of it out (see `users`).
+//!
+//! Refresh tokens rotate: each is redeemed once, its `jti` revoked as it
+//! is, so a refresh token stolen and replayed after its owner used it is
+//! refused, and one replayed first locks its owner out on their next
+//! refresh. Logging out revokes the refresh token too. Revoked ids are
+//! kept until the token would have expired anyway, in Redis when the
+//! service runs with `REDIS_URL` so every instance sees them, otherwise in
+//! memory.
+
+use chrono::Utc;
+use flowex_auth::keys::KeyRing;
+use flowex_auth::roles::RoleDirectory;
+use flowex_auth::{JwtManager, RefreshTokenClaims};
+use flowex_cache::{CacheError, CacheManager};
+use flowex_types::{AccountClaims, FlowExError, FlowExResult, User};
+use jsonwebtoken::jwk::JwkSet;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+/// Lifetime of an access token, in hours
+pub const ACCESS_TOKEN_HOURS: i64 = 1;
+
+/// Lifetime of a refresh token, in days
+pub const REFRESH_TOKEN_DAYS: i64 = 30;
+
+/// Redis key prefix of revoked refresh token ids
+const KEY_PREFIX: &str = "flowex:auth:revoked";
+
+/// An access token with the refresh token that renews it
+#[derive(Debug, Clone)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Ids of refresh tokens that may no longer be redeemed
+#[derive(Clone)]
+pub enum RevokedTokens {
+    /// Expiry, as a Unix timestamp, by token id
+    Memory(Arc<RwLock<HashMap<String, i64>>>),
+    Redis(Box<CacheManager>),
+}
+
+impl RevokedTokens {
+    /// Revocations local to this instance
+    pub fn in_memory() -> Self {
+        RevokedTokens::Memory(Arc::new(RwLock::new(HashMap::new())))
+    }
+
+    /// Revocations shared by every instance through Redis
+    pub async fn connect(redis_url: &str) -> Result<Self, CacheError> {
+        let ttl = Duration::from_secs(REFRESH_TOKEN_DAYS as u64 * 24 * 60 * 60);
+        Ok(RevokedTokens::Redis(Box::new(CacheManager::new(redis_url, ttl).await?)))
+    }
+
+    /// Revoke the token `claims` belongs to; false if it already was
+    async fn revoke(&self, claims: &RefreshTokenClaims) -> FlowExResult<bool> {
+        let now = Utc::now().timestamp();
+        match self {
+            RevokedTokens::Memory(revoked) => {
+                let mut revoked = revoked.write().await;
+                revoked.retain(|_, expires_at| *expires_at > now);
+                Ok(revoked.insert(claims.jti.clone(), claims.exp as i64).is_none())
+            }
+            RevokedTokens::Redis(cache) => {
+                // Kept until the token expires, and a minute of leeway after
+                let ttl = Duration::from_secs((claims.exp as i64 - now).max(0) as u64 + 60);
+                cache
+                    .set_if_absent(&format!("{}:{}", KEY_PREFIX, claims.jti), &claims.sub, Some(ttl))
+                    .await
+                    .map_err(|e| FlowExError::Internal(format!("Failed to revoke refresh token: {}", e)))
+            }
+        }
+    }
+}
+
+/// How long a signing key keeps verifying tokens once replaced: as long
+/// as the refresh tokens it signed live, and a day of leeway
+pub fn key_retention() -> chrono::Duration {
+    chrono::Duration::days(REFRESH_TOKEN_DAYS + 1)
+}
+
+/// Issues, rotates and revokes tokens
+#[derive(Clone)]
+pub struct Tokens {
+    jwt: JwtManager,
+    keys: KeyRing,
+    revoked: RevokedTokens,
+}
+
+impl Tokens {
+    /// Tokens signed with `keys`, granting the permissions `roles` lists
+    /// for custom roles, revoked in `revoked`
+    pub fn new(keys: KeyRing, roles: RoleDirectory, revoked: RevokedTokens) -> Self {
+        Self {
+            jwt: JwtManager::with_keys(
+                keys.clone(),
+                "flowex".to_string(),
+                "flowex-users".to_string(),
+                ACCESS_TOKEN_HOURS,
+                REFRESH_TOKEN_DAYS,
+            )
+            .with_roles(roles),
+            keys,
+            revoked,
+        }
+    }
+
+    /// Keys tokens are signed with
+    pub fn keys(&self) -> &KeyRing {
+        &self.keys
+    }
+
+    /// Public keys verifying the tokens issued
+    pub fn jwks(&self) -> JwkSet {
+        self.keys.jwks()
+    }
+
+    /// A new token pair for `user` with `roles` and the state `account` of
+    /// their account, in the session `session_id`
+    pub fn issue(&self, user: &User, roles: Vec<String>, account: AccountClaims, session_id: &str) -> FlowExResult<TokenPair> {
+        Ok(TokenPair {
+            access_token: self.jwt.generate_account_token(user, roles, account)?,
+            refresh_token: self.jwt.generate_refresh_token(user, session_id)?,
+        })
+    }
+
+    /// Redeem a refresh token, revoking it; its claims name the user to
+    /// issue a new pair to
+    pub async fn redeem(&self, refresh_token: &str) -> FlowExResult<RefreshTokenClaims> {
+        let claims = self.validate(refresh_token)?;
+        if !self.revoked.revoke(&claims).await? {
+            warn!("Revoked refresh token {} of user {} was replayed", claims.jti, claims.sub);
+            return Err(FlowExError::Authentication("Refresh token has been revoked".to_string()));
+        }
+        Ok(claims)
+    }
+
+    /// Revoke a refresh token, whether or not it already was; its claims
+    pub async fn revoke(&self, refresh_token: &str) -> FlowExResult<RefreshTokenClaims> {
+        let claims = self.validate(refresh_token)?;
+        self.revoked.revoke(&claims).await?;
+        Ok(claims)
+    }
+
+    fn validate(&self, refresh_token: &str) -> FlowExResult<RefreshTokenClaims> {
+        let claims = self.jwt.validate_refresh_token(refresh_token)?;
+        if claims.token_type != "refresh" {
+            return Err(FlowExError::Authentication("Not a refresh token".to_string()));
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    /// 测试：刷新令牌只能兑换一次，登出后被吊销，访问令牌不能当作刷新令牌使用
+    #[tokio::test]
+    async fn test_rotate_and_revoke_refresh_tokens() {
+        let keys = KeyRing::generate(chrono::Duration::days(7), key_retention()).unwrap();
+        let tokens = Tokens::new(keys, RoleDirectory::new(), RevokedTokens::in_memory());
+        let user = User {
+            id: Uuid::new_v4(),
+            email: "rotate@example.com".to_string(),
+            first_name: "Rotate".to_string(),
+            last_name: "User".to_string(),
+            is_verified: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        let pair = tokens.issue(&user, vec!["user".to_string()], AccountClaims::default(), "session-1").unwrap();
+        assert!(tokens.redeem(&pair.access_token).await.is_err());
+        let claims = tokens.redeem(&pair.refresh_token).await.unwrap();
+        assert_eq!((claims.sub, claims.sid), (user.id.to_string(), "session-1".to_string()));
+        assert!(matches!(tokens.redeem(&pair.refresh_token).await, Err(FlowExError::Authentication(_))));
+
+        let rotated = tokens.issue(&user, vec!["user".to_string()], AccountClaims::default(), "session-1").unwrap();
+        assert_ne!(rotated.refresh_token, pair.refresh_token);
+        tokens.revoke(&rotated.refresh_token).await.unwrap();
+        tokens.revoke(&rotated.refresh_token).await.unwrap();
+        assert!(tokens.redeem(&rotated.refresh_token).await.is_err());
+        assert!(tokens.revoke("not-a-token").await.is_err());
+    }
+}
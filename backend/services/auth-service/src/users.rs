@@ -0,0 +1,354 @@
+//! Registered users and their passwords
+//!
+//! Passwords are never kept, only their bcrypt hashes, made and checked by
+//! the shared `flowex_auth::PasswordManager` off the async runtime since
+//! bcrypt is deliberately slow. Registration refuses malformed emails and
+//! passwords the manager judges too weak, and so does changing a password,
+//! which takes the current one. Emails are matched case-insensitively.
+//!
+//! Users signing up through an OAuth provider get a random password
+//! nobody knows; they sign in through the provider. So do sub-accounts,
+//! which nobody signs in to (see `subaccounts`).
+//!
+//! An administrator may freeze an account, which then cannot sign in and
+//! whose tokens are refused, or log every token of it out by raising the
+//! version its tokens are signed with.
+
+use chrono::Utc;
+use flowex_auth::PasswordManager;
+use flowex_types::{AccountStatus, FlowExError, FlowExResult, RegisterRequest, Role, User};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::store::{UserRecord, UserStore};
+
+/// Users by email, written through to the store
+#[derive(Clone)]
+pub struct Users {
+    store: UserStore,
+    passwords: Arc<PasswordManager>,
+    records: Arc<RwLock<HashMap<String, UserRecord>>>,
+}
+
+impl Users {
+    /// Users in `store`, starting from `records`, their passwords hashed
+    /// by `passwords`
+    pub fn new(store: UserStore, passwords: PasswordManager, records: Vec<UserRecord>) -> Self {
+        let records = records
+            .into_iter()
+            .map(|record| (normalize_email(&record.user.email), record))
+            .collect();
+        Self {
+            store,
+            passwords: Arc::new(passwords),
+            records: Arc::new(RwLock::new(records)),
+        }
+    }
+
+    /// The user registered with `email`
+    pub async fn get(&self, email: &str) -> Option<UserRecord> {
+        self.records.read().await.get(&normalize_email(email)).cloned()
+    }
+
+    /// The user `user_id`
+    pub async fn get_by_id(&self, user_id: Uuid) -> Option<UserRecord> {
+        self.records.read().await.values().find(|record| record.user.id == user_id).cloned()
+    }
+
+    /// Register a new user with the hash of their password; `None` if the
+    /// email is already registered
+    pub async fn register(&self, request: RegisterRequest) -> FlowExResult<Option<UserRecord>> {
+        let email = normalize_email(&request.email);
+        if !is_valid_email(&email) {
+            return Err(FlowExError::Validation(format!("Invalid email address: {}", request.email)));
+        }
+        if self.records.read().await.contains_key(&email) {
+            return Ok(None);
+        }
+        let passwords = self.passwords.clone();
+        let password = request.password;
+        let password_hash = tokio::task::spawn_blocking(move || passwords.hash_password(&password))
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Password hashing failed: {}", e)))??;
+        self.insert(email, request.first_name, request.last_name, password_hash, false).await
+    }
+
+    /// Register a new user who does not sign in with a password: one who
+    /// signed in through an OAuth provider, with an email it verified, or a
+    /// sub-account; `None` if the email is already registered
+    pub async fn register_external(&self, email: &str, first_name: String, last_name: String) -> FlowExResult<Option<UserRecord>> {
+        let email = normalize_email(email);
+        if self.records.read().await.contains_key(&email) {
+            return Ok(None);
+        }
+        let mut password = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut password);
+        let password_hash = tokio::task::spawn_blocking(move || bcrypt::hash(hex::encode(password), bcrypt::DEFAULT_COST))
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Password hashing failed: {}", e)))?
+            .map_err(|e| FlowExError::Internal(format!("Password hashing failed: {}", e)))?;
+        self.insert(email, first_name, last_name, password_hash, true).await
+    }
+
+    async fn insert(
+        &self,
+        email: String,
+        first_name: String,
+        last_name: String,
+        password_hash: String,
+        is_verified: bool,
+    ) -> FlowExResult<Option<UserRecord>> {
+        let now = Utc::now();
+        let record = UserRecord {
+            user: User {
+                id: Uuid::new_v4(),
+                email: email.clone(),
+                first_name,
+                last_name,
+                is_verified,
+                created_at: now,
+                updated_at: now,
+            },
+            password_hash,
+            roles: vec![Role::User.as_str().to_string()],
+            status: AccountStatus::Active,
+            token_version: 0,
+        };
+        let mut records = self.records.write().await;
+        if records.contains_key(&email) || !self.store.insert(&record).await? {
+            return Ok(None);
+        }
+        info!("Registered user {}", record.user.id);
+        records.insert(email, record.clone());
+        Ok(Some(record))
+    }
+
+    /// The user registered with `email`, if `password` is theirs and their
+    /// account is not frozen
+    pub async fn authenticate(&self, email: &str, password: &str) -> FlowExResult<UserRecord> {
+        let record = self
+            .records
+            .read()
+            .await
+            .get(&normalize_email(email))
+            .cloned()
+            .ok_or_else(|| FlowExError::Authentication("Invalid email or password".to_string()))?;
+
+        let passwords = self.passwords.clone();
+        let password = password.to_string();
+        let hash = record.password_hash.clone();
+        let verified = tokio::task::spawn_blocking(move || passwords.verify_password(&password, &hash))
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Password verification failed: {}", e)))??;
+        if !verified {
+            return Err(FlowExError::Authentication("Invalid email or password".to_string()));
+        }
+        check_active(&record)?;
+        Ok(record)
+    }
+
+    /// Replace the password of the user registered with `email`, if
+    /// `current_password` is theirs
+    pub async fn change_password(&self, email: &str, current_password: &str, new_password: &str) -> FlowExResult<UserRecord> {
+        let record = self.authenticate(email, current_password).await?;
+        let passwords = self.passwords.clone();
+        let password = new_password.to_string();
+        let password_hash = tokio::task::spawn_blocking(move || passwords.hash_password(&password))
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Password hashing failed: {}", e)))??;
+
+        let updated_at = Utc::now();
+        self.store.update_password(record.user.id, &password_hash, updated_at).await?;
+        let mut records = self.records.write().await;
+        let record = records
+            .get_mut(&normalize_email(email))
+            .ok_or_else(|| FlowExError::Authentication("Invalid email or password".to_string()))?;
+        record.password_hash = password_hash;
+        record.user.updated_at = updated_at;
+        info!("Changed password of user {}", record.user.id);
+        Ok(record.clone())
+    }
+
+    /// Replace the roles of the user `user_id`; `None` if there is no such
+    /// user
+    pub async fn set_roles(&self, user_id: Uuid, roles: Vec<String>) -> FlowExResult<Option<UserRecord>> {
+        let mut records = self.records.write().await;
+        let Some(record) = records.values_mut().find(|record| record.user.id == user_id) else {
+            return Ok(None);
+        };
+        self.store.set_roles(user_id, &roles).await?;
+        record.roles = roles;
+        info!("Set roles of user {} to {:?}", user_id, record.roles);
+        Ok(Some(record.clone()))
+    }
+
+    /// Freeze or unfreeze the account of the user `user_id`; `None` if
+    /// there is no such user
+    pub async fn set_status(&self, user_id: Uuid, status: AccountStatus) -> FlowExResult<Option<UserRecord>> {
+        let mut records = self.records.write().await;
+        let Some(record) = records.values_mut().find(|record| record.user.id == user_id) else {
+            return Ok(None);
+        };
+        let updated_at = Utc::now();
+        self.store.set_account_status(user_id, status, updated_at).await?;
+        record.status = status;
+        record.user.updated_at = updated_at;
+        info!("Set account of user {} {}", user_id, status.as_str());
+        Ok(Some(record.clone()))
+    }
+
+    /// Raise the version the tokens of the user `user_id` are signed with,
+    /// so every token issued before is refused; `None` if there is no such
+    /// user
+    pub async fn force_logout(&self, user_id: Uuid) -> FlowExResult<Option<UserRecord>> {
+        let mut records = self.records.write().await;
+        let Some(record) = records.values_mut().find(|record| record.user.id == user_id) else {
+            return Ok(None);
+        };
+        let token_version = record.token_version + 1;
+        self.store.set_token_version(user_id, token_version).await?;
+        record.token_version = token_version;
+        info!("Logged out every token of user {}, now at version {}", user_id, token_version);
+        Ok(Some(record.clone()))
+    }
+
+    /// Take the deleted role `name` from every user holding it, as the
+    /// store does when deleting it; users left without roles are plain
+    /// users
+    pub async fn forget_role(&self, name: &str) {
+        for record in self.records.write().await.values_mut() {
+            record.roles.retain(|role| role != name);
+            if record.roles.is_empty() {
+                record.roles.push(Role::User.as_str().to_string());
+            }
+        }
+    }
+}
+
+/// Check that the account of `record` is not frozen
+pub fn check_active(record: &UserRecord) -> FlowExResult<()> {
+    match record.status {
+        AccountStatus::Active => Ok(()),
+        AccountStatus::Frozen => Err(FlowExError::Authorization("Account is frozen".to_string())),
+    }
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Whether `email` has a local part and a dotted domain, without spaces
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !email.contains(char::is_whitespace)
+                && !domain.contains('@')
+                && domain.split('.').count() > 1
+                && domain.split('.').all(|label| !label.is_empty())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：注册时保存密码哈希，登录时校验哈希，弱密码和重复邮箱被拒绝
+    #[tokio::test]
+    async fn test_register_and_authenticate() {
+        let users = Users::new(UserStore::Memory, PasswordManager::new(Some(4)), Vec::new());
+        let request = |email: &str, password: &str| RegisterRequest {
+            email: email.to_string(),
+            password: password.to_string(),
+            first_name: "New".to_string(),
+            last_name: "User".to_string(),
+        };
+
+        let user = users.register(request("New@Example.com", "SecurePassword123!")).await.unwrap().unwrap().user;
+        assert_eq!(user.email, "new@example.com");
+        assert!(!user.is_verified);
+        let record = users.records.read().await.get("new@example.com").cloned().unwrap();
+        assert_ne!(record.password_hash, "SecurePassword123!");
+        assert!(record.password_hash.starts_with("$2"));
+
+        assert_eq!(users.authenticate("new@example.com", "SecurePassword123!").await.unwrap().user.id, user.id);
+        assert!(matches!(
+            users.authenticate("new@example.com", "WrongPassword1!").await,
+            Err(FlowExError::Authentication(_))
+        ));
+        assert!(users.authenticate("nobody@example.com", "SecurePassword123!").await.is_err());
+
+        assert!(users.register(request("new@example.com", "OtherPassword123!")).await.unwrap().is_none());
+        assert!(matches!(users.register(request("weak@example.com", "123")).await, Err(FlowExError::Validation(_))));
+        assert!(users.get("weak@example.com").await.is_none());
+        for email in ["invalid-email", "@example.com", "user@example", "user@.com", "a b@example.com"] {
+            assert!(matches!(
+                users.register(request(email, "SecurePassword123!")).await,
+                Err(FlowExError::Validation(_))
+            ));
+        }
+    }
+
+    /// 测试：修改密码需要当前密码，修改后旧密码失效
+    #[tokio::test]
+    async fn test_change_password() {
+        let users = Users::new(UserStore::Memory, PasswordManager::new(Some(4)), Vec::new());
+        users
+            .register(RegisterRequest {
+                email: "change@example.com".to_string(),
+                password: "OldPassword123!".to_string(),
+                first_name: "Change".to_string(),
+                last_name: "User".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(users.change_password("change@example.com", "WrongPassword1!", "NewPassword123!").await.is_err());
+        assert!(matches!(
+            users.change_password("change@example.com", "OldPassword123!", "weak").await,
+            Err(FlowExError::Validation(_))
+        ));
+        users.change_password("Change@Example.com", "OldPassword123!", "NewPassword123!").await.unwrap();
+        assert!(users.authenticate("change@example.com", "OldPassword123!").await.is_err());
+        assert!(users.authenticate("change@example.com", "NewPassword123!").await.is_ok());
+    }
+
+    /// 测试：冻结的账户不能登录，解冻后恢复；强制登出提升令牌版本
+    #[tokio::test]
+    async fn test_freeze_and_force_logout() {
+        let users = Users::new(UserStore::Memory, PasswordManager::new(Some(4)), Vec::new());
+        let user = users
+            .register(RegisterRequest {
+                email: "frozen@example.com".to_string(),
+                password: "SecurePassword123!".to_string(),
+                first_name: "Frozen".to_string(),
+                last_name: "User".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap()
+            .user;
+
+        let record = users.set_status(user.id, AccountStatus::Frozen).await.unwrap().unwrap();
+        assert_eq!(record.status, AccountStatus::Frozen);
+        assert!(matches!(
+            users.authenticate("frozen@example.com", "SecurePassword123!").await,
+            Err(FlowExError::Authorization(_))
+        ));
+        users.set_status(user.id, AccountStatus::Active).await.unwrap().unwrap();
+        assert!(users.authenticate("frozen@example.com", "SecurePassword123!").await.is_ok());
+        assert!(users.set_status(Uuid::new_v4(), AccountStatus::Frozen).await.unwrap().is_none());
+
+        assert_eq!(users.force_logout(user.id).await.unwrap().unwrap().token_version, 1);
+        assert_eq!(users.force_logout(user.id).await.unwrap().unwrap().token_version, 2);
+        assert_eq!(users.get_by_id(user.id).await.unwrap().token_version, 2);
+        assert!(users.force_logout(Uuid::new_v4()).await.unwrap().is_none());
+    }
+}
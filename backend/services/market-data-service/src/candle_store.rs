@@ -0,0 +1,155 @@
+//! Persisted candle storage
+//!
+//! `FileCandleStore` appends every closed candle to a JSON lines file per
+//! symbol and interval, `<dir>/<symbol>/<interval>.jsonl`, so the candle
+//! history outlives the in-memory ring buffers and a restart. Candles are
+//! written in the order they close, which is open time order.
+
+use chrono::{DateTime, Utc};
+use flowex_matching_engine::candles::{take_candles, CandleStore};
+use flowex_types::{Candle, CandleInterval, FlowExError, FlowExResult};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Closed candles kept in JSON lines files under a directory
+#[derive(Debug)]
+pub struct FileCandleStore {
+    dir: PathBuf,
+    /// Serializes appends, so concurrent writers never interleave lines
+    write_lock: Mutex<()>,
+}
+
+impl FileCandleStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    fn path(&self, symbol: &str, interval: CandleInterval) -> FlowExResult<PathBuf> {
+        if symbol.is_empty() || !symbol.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(FlowExError::Validation(format!("Invalid candle symbol {:?}", symbol)));
+        }
+        Ok(self.dir.join(symbol).join(format!("{}.jsonl", interval.as_str())))
+    }
+
+    /// Every stored candle of a symbol and interval, oldest first
+    fn read(&self, symbol: &str, interval: CandleInterval) -> FlowExResult<Vec<Candle>> {
+        let path = self.path(symbol, interval)?;
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(store_error(&path, e)),
+        };
+
+        let mut candles = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| store_error(&path, e))?;
+            match serde_json::from_str(&line) {
+                Ok(candle) => candles.push(candle),
+                Err(e) => warn!("Skipping unreadable candle in {}: {}", path.display(), e),
+            }
+        }
+        Ok(candles)
+    }
+}
+
+impl CandleStore for FileCandleStore {
+    fn save(&self, candle: &Candle) -> FlowExResult<()> {
+        let path = self.path(&candle.symbol, candle.interval)?;
+        let line = serde_json::to_string(candle)
+            .map_err(|e| FlowExError::Internal(format!("Failed to encode candle: {}", e)))?;
+
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| store_error(parent, e))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| store_error(&path, e))?;
+        writeln!(file, "{}", line).map_err(|e| store_error(&path, e))
+    }
+
+    fn load(&self, symbol: &str, interval: CandleInterval, limit: usize) -> FlowExResult<Vec<Candle>> {
+        Ok(take_candles(self.read(symbol, interval)?, false, limit))
+    }
+
+    fn range(
+        &self,
+        symbol: &str,
+        interval: CandleInterval,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> FlowExResult<Vec<Candle>> {
+        let candles = self
+            .read(symbol, interval)?
+            .into_iter()
+            .filter(|candle| start.is_none_or(|start| candle.open_time >= start))
+            .filter(|candle| end.is_none_or(|end| candle.open_time < end))
+            .collect();
+        Ok(take_candles(candles, start.is_some(), limit))
+    }
+}
+
+fn store_error(path: &Path, e: std::io::Error) -> FlowExError {
+    FlowExError::Internal(format!("Candle store I/O error on {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use rust_decimal::Decimal;
+
+    fn candle(open_time: DateTime<Utc>, close: i64) -> Candle {
+        Candle {
+            symbol: "BTC-USDT".to_string(),
+            interval: CandleInterval::OneMinute,
+            open_time,
+            close_time: open_time + Duration::minutes(1),
+            open: Decimal::new(close, 0),
+            high: Decimal::new(close, 0),
+            low: Decimal::new(close, 0),
+            close: Decimal::new(close, 0),
+            volume: Decimal::ONE,
+            quote_volume: Decimal::new(close, 0),
+            trade_count: 1,
+        }
+    }
+
+    /// 测试：K线写入文件后可按数量和时间范围读回
+    #[test]
+    fn test_save_and_range() {
+        let dir = std::env::temp_dir().join(format!("flowex-candles-{}", uuid::Uuid::new_v4()));
+        let store = FileCandleStore::new(&dir);
+        let start = CandleInterval::OneMinute.open_time(Utc::now());
+        for i in 0..5 {
+            store.save(&candle(start + Duration::minutes(i), 100 + i)).unwrap();
+        }
+        let closes = |candles: Vec<Candle>| candles.iter().map(|candle| candle.close).collect::<Vec<_>>();
+
+        let latest = store.load("BTC-USDT", CandleInterval::OneMinute, 2).unwrap();
+        assert_eq!(closes(latest), vec![Decimal::new(103, 0), Decimal::new(104, 0)]);
+        let range = store
+            .range(
+                "BTC-USDT",
+                CandleInterval::OneMinute,
+                Some(start + Duration::minutes(1)),
+                Some(start + Duration::minutes(3)),
+                10,
+            )
+            .unwrap();
+        assert_eq!(closes(range), vec![Decimal::new(101, 0), Decimal::new(102, 0)]);
+        assert!(store.load("ETH-USDT", CandleInterval::OneMinute, 10).unwrap().is_empty());
+        assert!(store.load("../BTC-USDT", CandleInterval::OneMinute, 10).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
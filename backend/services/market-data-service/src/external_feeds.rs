@@ -0,0 +1,392 @@
+//! External exchange feeds and index prices
+//!
+//! A feed handler holds a WebSocket to each configured external exchange
+//! (`EXTERNAL_FEEDS`, e.g. `binance,coinbase`), subscribes to the tickers of
+//! the index symbols and normalizes every ticker message into a FlowEx
+//! `Ticker`. A symbol's index price is the median of the latest prices of
+//! the exchanges that quoted it within `MAX_QUOTE_AGE`, so a single exchange
+//! with a bad or stale price cannot move it far. Every new index price is
+//! published on the `index.<symbol>` WebSocket channel, and the latest is
+//! served by `GET /api/market-data/index/:symbol`, for price protection and
+//! display.
+
+use chrono::{DateTime, Utc};
+use flowex_types::{IndexComponent, IndexPrice, Ticker};
+use flowex_websocket::WebSocketManager;
+use futures_util::{SinkExt, StreamExt};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::net::TcpStream;
+use tokio::sync::RwLock;
+use tokio_tungstenite::tungstenite::{Error as TungsteniteError, Message};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, warn};
+
+/// Oldest exchange price still counted in an index
+pub const MAX_QUOTE_AGE: Duration = Duration::from_secs(30);
+
+/// Delay before the first reconnect attempt, doubled after each failure
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest delay between reconnect attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// External exchange an index takes prices from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Exchange {
+    Binance,
+    Coinbase,
+}
+
+impl Exchange {
+    /// Exchange named `name`, as in `EXTERNAL_FEEDS`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "binance" => Some(Self::Binance),
+            "coinbase" => Some(Self::Coinbase),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Binance => "binance",
+            Self::Coinbase => "coinbase",
+        }
+    }
+
+    /// The exchange's market for a FlowEx symbol such as `BTC-USDT`
+    fn market(&self, symbol: &str) -> String {
+        match self {
+            Self::Binance => symbol.replace('-', ""),
+            Self::Coinbase => symbol.to_string(),
+        }
+    }
+
+    /// WebSocket endpoint streaming the tickers of `symbols`
+    fn url(&self, symbols: &[String]) -> String {
+        match self {
+            Self::Binance => {
+                let streams: Vec<String> = symbols
+                    .iter()
+                    .map(|symbol| format!("{}@ticker", self.market(symbol).to_ascii_lowercase()))
+                    .collect();
+                format!("wss://stream.binance.com:9443/stream?streams={}", streams.join("/"))
+            }
+            Self::Coinbase => "wss://ws-feed.exchange.coinbase.com".to_string(),
+        }
+    }
+
+    /// Message subscribing to the tickers of `symbols` once connected, if
+    /// the endpoint needs one
+    fn subscribe_message(&self, symbols: &[String]) -> Option<String> {
+        match self {
+            Self::Binance => None,
+            Self::Coinbase => {
+                let product_ids: Vec<String> = symbols.iter().map(|symbol| self.market(symbol)).collect();
+                let request = serde_json::json!({
+                    "type": "subscribe",
+                    "product_ids": product_ids,
+                    "channels": ["ticker"],
+                });
+                Some(request.to_string())
+            }
+        }
+    }
+
+    /// Normalize a ticker message of the exchange into a `Ticker` of the
+    /// one of `symbols` it quotes. `None` for any other message.
+    pub fn parse_ticker(&self, text: &str, symbols: &[String]) -> Option<Ticker> {
+        let symbol_of = |market: &str| symbols.iter().find(|symbol| self.market(symbol) == market).cloned();
+        match self {
+            Self::Binance => {
+                let message: BinanceMessage = serde_json::from_str(text).ok()?;
+                let ticker = message.data;
+                Some(Ticker {
+                    symbol: symbol_of(&ticker.symbol)?,
+                    price: decimal(&ticker.last)?,
+                    change: decimal(&ticker.change)?,
+                    change_percent: decimal(&ticker.change_percent)?,
+                    high: decimal(&ticker.high)?,
+                    low: decimal(&ticker.low)?,
+                    volume: decimal(&ticker.volume)?,
+                    vwap: decimal(&ticker.vwap),
+                    timestamp: DateTime::from_timestamp_millis(ticker.event_time)?,
+                })
+            }
+            Self::Coinbase => {
+                let ticker: CoinbaseTicker = serde_json::from_str(text).ok()?;
+                if ticker.kind != "ticker" {
+                    return None;
+                }
+                let price = decimal(&ticker.price)?;
+                let open = decimal(&ticker.open_24h)?;
+                let change = price - open;
+                Some(Ticker {
+                    symbol: symbol_of(&ticker.product_id)?,
+                    price,
+                    change,
+                    change_percent: if open.is_zero() {
+                        Decimal::ZERO
+                    } else {
+                        change / open * Decimal::ONE_HUNDRED
+                    },
+                    high: decimal(&ticker.high_24h)?,
+                    low: decimal(&ticker.low_24h)?,
+                    volume: decimal(&ticker.volume_24h)?,
+                    vwap: None,
+                    timestamp: ticker.time,
+                })
+            }
+        }
+    }
+}
+
+/// Binance combined stream message carrying a 24h ticker
+#[derive(Debug, Deserialize)]
+struct BinanceMessage {
+    data: BinanceTicker,
+}
+
+#[derive(Debug, Deserialize)]
+struct BinanceTicker {
+    #[serde(rename = "E")]
+    event_time: i64,
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "c")]
+    last: String,
+    #[serde(rename = "p")]
+    change: String,
+    #[serde(rename = "P")]
+    change_percent: String,
+    #[serde(rename = "h")]
+    high: String,
+    #[serde(rename = "l")]
+    low: String,
+    #[serde(rename = "v")]
+    volume: String,
+    #[serde(rename = "w")]
+    vwap: String,
+}
+
+/// Coinbase ticker channel message
+#[derive(Debug, Deserialize)]
+struct CoinbaseTicker {
+    #[serde(rename = "type")]
+    kind: String,
+    product_id: String,
+    price: String,
+    open_24h: String,
+    volume_24h: String,
+    low_24h: String,
+    high_24h: String,
+    time: DateTime<Utc>,
+}
+
+/// Exchanges quote decimals as strings
+fn decimal(value: &str) -> Option<Decimal> {
+    value.parse().ok()
+}
+
+/// Latest external ticker of each symbol on each exchange
+#[derive(Clone, Default)]
+pub struct IndexPrices {
+    quotes: Arc<RwLock<HashMap<String, HashMap<Exchange, Ticker>>>>,
+}
+
+impl IndexPrices {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an exchange's ticker and return its symbol's index price
+    /// as of the ticker
+    pub async fn update(&self, exchange: Exchange, ticker: Ticker) -> Option<IndexPrice> {
+        let mut quotes = self.quotes.write().await;
+        let (symbol, now) = (ticker.symbol.clone(), ticker.timestamp);
+        let symbol_quotes = quotes.entry(symbol.clone()).or_default();
+        symbol_quotes.insert(exchange, ticker);
+        index_price(&symbol, symbol_quotes, now)
+    }
+
+    /// Index price of `symbol` at `now`, `None` without a fresh quote
+    pub async fn index(&self, symbol: &str, now: DateTime<Utc>) -> Option<IndexPrice> {
+        let quotes = self.quotes.read().await;
+        index_price(symbol, quotes.get(symbol)?, now)
+    }
+
+    /// Index prices at `now` of every symbol with a fresh quote
+    pub async fn all(&self, now: DateTime<Utc>) -> Vec<IndexPrice> {
+        let quotes = self.quotes.read().await;
+        quotes
+            .iter()
+            .filter_map(|(symbol, symbol_quotes)| index_price(symbol, symbol_quotes, now))
+            .collect()
+    }
+}
+
+/// Median of the prices quoted within `MAX_QUOTE_AGE` of `now`
+fn index_price(symbol: &str, quotes: &HashMap<Exchange, Ticker>, now: DateTime<Utc>) -> Option<IndexPrice> {
+    let max_age = chrono::Duration::from_std(MAX_QUOTE_AGE).ok()?;
+    let mut components: Vec<IndexComponent> = quotes
+        .iter()
+        .filter(|(_, ticker)| now - ticker.timestamp <= max_age)
+        .map(|(exchange, ticker)| IndexComponent {
+            exchange: exchange.as_str().to_string(),
+            price: ticker.price,
+            timestamp: ticker.timestamp,
+        })
+        .collect();
+    if components.is_empty() {
+        return None;
+    }
+    components.sort_by(|a, b| a.price.cmp(&b.price).then_with(|| a.exchange.cmp(&b.exchange)));
+
+    let middle = components.len() / 2;
+    let price = if components.len() % 2 == 1 {
+        components[middle].price
+    } else {
+        (components[middle - 1].price + components[middle].price) / Decimal::TWO
+    };
+    Some(IndexPrice {
+        symbol: symbol.to_string(),
+        price,
+        components,
+        timestamp: now,
+    })
+}
+
+/// Stream the tickers of `symbols` from each of `exchanges` into `index`,
+/// publishing every new index price to `websocket`
+pub fn spawn_feeds(exchanges: Vec<Exchange>, symbols: Vec<String>, index: IndexPrices, websocket: WebSocketManager) {
+    for exchange in exchanges {
+        tokio::spawn(run(exchange, symbols.clone(), index.clone(), websocket.clone()));
+    }
+}
+
+/// Hold a connection to the exchange, reconnecting with backoff
+async fn run(exchange: Exchange, symbols: Vec<String>, index: IndexPrices, websocket: WebSocketManager) {
+    let url = exchange.url(&symbols);
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        match connect_async(url.as_str()).await {
+            Ok((socket, _)) => {
+                info!("Connected to the {} feed", exchange.as_str());
+                backoff = INITIAL_BACKOFF;
+                match forward(exchange, socket, &symbols, &index, &websocket).await {
+                    Ok(()) => info!("The {} feed closed", exchange.as_str()),
+                    Err(e) => warn!("The {} feed failed: {}", exchange.as_str(), e),
+                }
+            }
+            Err(e) => warn!("Failed to connect to the {} feed: {}", exchange.as_str(), e),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Subscribe, then fold tickers into the index until the connection closes
+async fn forward(
+    exchange: Exchange,
+    mut socket: Socket,
+    symbols: &[String],
+    index: &IndexPrices,
+    websocket: &WebSocketManager,
+) -> Result<(), TungsteniteError> {
+    if let Some(request) = exchange.subscribe_message(symbols) {
+        socket.send(Message::Text(request)).await?;
+    }
+
+    while let Some(frame) = socket.next().await {
+        let text = match frame? {
+            Message::Text(text) => text,
+            Message::Close(frame) => {
+                debug!("The {} feed closed the connection: {:?}", exchange.as_str(), frame);
+                break;
+            }
+            _ => continue,
+        };
+        let Some(ticker) = exchange.parse_ticker(&text, symbols) else {
+            continue;
+        };
+        if let Some(index_price) = index.update(exchange, ticker).await {
+            let _ = websocket.publish_index_price(index_price).await;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols() -> Vec<String> {
+        vec!["BTC-USDT".to_string(), "ETH-USDT".to_string()]
+    }
+
+    /// 测试：Binance与Coinbase的行情消息转换为统一的Ticker
+    #[test]
+    fn test_parse_exchange_tickers() {
+        let binance = r#"{"stream":"btcusdt@ticker","data":{"e":"24hrTicker","E":1700000000000,"s":"BTCUSDT",
+            "p":"500.00","P":"1.123","w":"44800.50","c":"45000.00","h":"45500.00","l":"44000.00","v":"1234.5"}}"#;
+        let ticker = Exchange::Binance.parse_ticker(binance, &symbols()).unwrap();
+        assert_eq!(ticker.symbol, "BTC-USDT");
+        assert_eq!(ticker.price, Decimal::new(45000, 0));
+        assert_eq!(ticker.change_percent, Decimal::new(1123, 3));
+        assert_eq!(ticker.vwap, Some(Decimal::new(4480050, 2)));
+        assert_eq!(ticker.timestamp.timestamp_millis(), 1_700_000_000_000);
+
+        let coinbase = r#"{"type":"ticker","sequence":1,"product_id":"ETH-USDT","price":"3300","open_24h":"3000",
+            "volume_24h":"900.5","low_24h":"2950","high_24h":"3350","side":"buy","time":"2023-11-14T22:13:20.000Z"}"#;
+        let ticker = Exchange::Coinbase.parse_ticker(coinbase, &symbols()).unwrap();
+        assert_eq!(ticker.symbol, "ETH-USDT");
+        assert_eq!(ticker.change, Decimal::new(300, 0));
+        assert_eq!(ticker.change_percent, Decimal::new(10, 0));
+        assert_eq!(ticker.high, Decimal::new(3350, 0));
+
+        // 订阅确认与未订阅的交易对不产生行情
+        let subscriptions = r#"{"type":"subscriptions","channels":[{"name":"ticker","product_ids":["ETH-USDT"]}]}"#;
+        assert!(Exchange::Coinbase.parse_ticker(subscriptions, &symbols()).is_none());
+        assert!(Exchange::Binance.parse_ticker(&binance.replace("BTCUSDT", "SOLUSDT"), &symbols()).is_none());
+        assert_eq!(Exchange::from_name(" Coinbase"), Some(Exchange::Coinbase));
+        assert!(Exchange::from_name("kraken").is_none());
+    }
+
+    /// 测试：指数价格取各交易所最新价格的中位数，过期报价不计入
+    #[tokio::test]
+    async fn test_index_price_median() {
+        let index = IndexPrices::new();
+        let start = Utc::now();
+        let ticker = |price: i64, seconds: i64| Ticker {
+            symbol: "BTC-USDT".to_string(),
+            price: Decimal::new(price, 0),
+            change: Decimal::ZERO,
+            change_percent: Decimal::ZERO,
+            high: Decimal::new(price, 0),
+            low: Decimal::new(price, 0),
+            volume: Decimal::ZERO,
+            vwap: None,
+            timestamp: start + chrono::Duration::seconds(seconds),
+        };
+
+        let first = index.update(Exchange::Binance, ticker(45000, 0)).await.unwrap();
+        assert_eq!(first.price, Decimal::new(45000, 0));
+        let both = index.update(Exchange::Coinbase, ticker(45100, 10)).await.unwrap();
+        assert_eq!(both.price, Decimal::new(45050, 0));
+        assert_eq!(both.components.len(), 2);
+        assert_eq!(both.components[0].exchange, "binance");
+
+        // Binance的报价已超过30秒
+        let stale = index.update(Exchange::Coinbase, ticker(45200, 40)).await.unwrap();
+        assert_eq!(stale.price, Decimal::new(45200, 0));
+        assert_eq!(stale.components.len(), 1);
+
+        assert!(index.index("BTC-USDT", start + chrono::Duration::seconds(100)).await.is_none());
+        assert!(index.index("ETH-USDT", start).await.is_none());
+        assert_eq!(index.all(start + chrono::Duration::seconds(40)).await.len(), 1);
+    }
+}
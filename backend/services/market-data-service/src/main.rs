@@ -2,91 +2,223 @@
 //!
 //! Enterprise-grade market data service providing real-time price feeds,
 //! historical data, and market statistics.
+//!
+//! Closed candles are persisted under `MARKET_DATA_CANDLES_DIR` (see
+//! `candle_store`), so `GET /api/market-data/candles/:symbol` can serve any
+//! time range, not only the candles still held in memory.
+//!
+//! With `TRADING_SERVICE_URL` set, tickers and candles are computed from the
+//! trades the trading service matches (see `trade_feed`); the 24h tickers
+//! cover the trades of the last 24 hours as of each request.
+//!
+//! With `EXTERNAL_FEEDS` set, reference prices of the `INDEX_SYMBOLS` are
+//! streamed from external exchanges into an index price per symbol (see
+//! `external_feeds`).
 
 use axum::{
-    extract::{Path, State},
+    extract::{ws::WebSocketUpgrade, Path, Query, State},
     http::StatusCode,
-    response::Json,
+    response::{Json, Response},
     routing::get,
     Router,
 };
 use flowex_types::{
-    ApiResponse, HealthResponse, Ticker, Trade, OrderSide,
+    ApiResponse, Candle, CandleInterval, HealthResponse, IndexPrice, Ticker, Trade, OrderSide,
 };
+use candle_store::FileCandleStore;
+use external_feeds::{Exchange, IndexPrices};
+use trade_feed::TradeFeed;
+use flowex_matching_engine::candles::{CandleAggregator, CandleStore, DEFAULT_CANDLE_CAPACITY};
+use flowex_matching_engine::stats::MarketStats;
+use flowex_metrics::MetricsCollector;
+use flowex_websocket::recording::{self, MarketRecorder, ReplayRequest};
+use flowex_websocket::WebSocketManager;
 use rust_decimal::Decimal;
-use std::{collections::HashMap, sync::Arc, time::SystemTime};
+use serde::Deserialize;
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::{Duration, SystemTime}};
 use tokio::sync::RwLock;
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
-use tracing::{info};
+use tracing::{info, warn};
 use uuid::Uuid;
 
+mod candle_store;
+mod external_feeds;
+mod trade_feed;
+
+/// How often candles of quiet symbols are closed once their interval ends
+const CANDLE_CLOSE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Concurrent WebSocket subscribers accepted
+const MAX_WEBSOCKET_CONNECTIONS: usize = 10_000;
+
+/// Directory market data is recorded to, unless `MARKET_DATA_RECORDINGS_DIR` is set
+const DEFAULT_RECORDINGS_DIR: &str = "data/market-recordings";
+
+/// Directory closed candles are persisted to, unless `MARKET_DATA_CANDLES_DIR` is set
+const DEFAULT_CANDLES_DIR: &str = "data/candles";
+
+/// How long WebSocket clients get to drain and disconnect on shutdown
+const WEBSOCKET_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Candles returned when the request does not set a limit
+const DEFAULT_CANDLE_LIMIT: usize = 500;
+
+/// Symbols given an index price, unless `INDEX_SYMBOLS` is set
+const DEFAULT_INDEX_SYMBOLS: &str = "BTC-USDT,ETH-USDT";
+
+/// Recent trades kept per symbol
+const MAX_RECENT_TRADES: usize = 1000;
+
 /// Application state for the market data service
 #[derive(Clone)]
 pub struct AppState {
+    /// Ticker of each symbol as of its last trade
     pub tickers: Arc<RwLock<HashMap<String, Ticker>>>,
     pub trades: Arc<RwLock<HashMap<String, Vec<Trade>>>>,
+    /// Rolling 24h statistics the tickers are computed from
+    pub stats: Arc<RwLock<HashMap<String, MarketStats>>>,
+    /// Per-symbol kline aggregation
+    pub candles: Arc<RwLock<HashMap<String, CandleAggregator>>>,
+    /// Where closed candles are persisted, if anywhere
+    pub candle_store: Option<Arc<dyn CandleStore>>,
+    /// Reference prices from external exchanges
+    pub index_prices: IndexPrices,
+    /// Streams candle updates to `kline.<interval>.<symbol>` subscribers
+    pub websocket: WebSocketManager,
+    /// Directory the published market data is recorded to and replayed from
+    pub recordings: PathBuf,
     pub start_time: SystemTime,
 }
 
 impl AppState {
+    /// State seeded with demo trades, for running without a trading service
     pub fn new() -> Self {
+        Self::with_trades(vec![
+            demo_trade("BTC-USDT", 1, Decimal::new(4500000, 2), Decimal::new(12345, 5), OrderSide::Buy),
+            demo_trade("BTC-USDT", 2, Decimal::new(4499999, 2), Decimal::new(23456, 5), OrderSide::Sell),
+            demo_trade("ETH-USDT", 1, Decimal::new(300000, 2), Decimal::new(150000, 5), OrderSide::Buy),
+        ])
+    }
+
+    /// State computed from `trades`, oldest first
+    pub fn with_trades(initial_trades: Vec<Trade>) -> Self {
         let mut tickers = HashMap::new();
         let mut trades = HashMap::new();
+        let mut stats = HashMap::new();
+        let mut candles = HashMap::new();
 
-        // Initialize demo tickers
-        let btc_ticker = Ticker {
-            symbol: "BTC-USDT".to_string(),
-            price: Decimal::new(4500000, 2), // 45000.00
-            change: Decimal::new(250, 2), // 2.50
-            change_percent: Decimal::new(556, 4), // 5.56%
-            high: Decimal::new(4650000, 2), // 46500.00
-            low: Decimal::new(4350000, 2), // 43500.00
-            volume: Decimal::new(123456789, 5), // 1234.56789
-            timestamp: chrono::Utc::now(),
-        };
-
-        let eth_ticker = Ticker {
-            symbol: "ETH-USDT".to_string(),
-            price: Decimal::new(300000, 2), // 3000.00
-            change: Decimal::new(150, 2), // 1.50
-            change_percent: Decimal::new(526, 4), // 5.26%
-            high: Decimal::new(310000, 2), // 3100.00
-            low: Decimal::new(290000, 2), // 2900.00
-            volume: Decimal::new(987654321, 5), // 9876.54321
-            timestamp: chrono::Utc::now(),
-        };
-
-        // Initialize demo trades
-        let btc_trades = vec![
-            Trade {
-                id: Uuid::new_v4(),
-                symbol: "BTC-USDT".to_string(),
-                price: Decimal::new(4500000, 2),
-                quantity: Decimal::new(12345, 5),
-                side: OrderSide::Buy,
-                timestamp: chrono::Utc::now(),
-            },
-            Trade {
-                id: Uuid::new_v4(),
-                symbol: "BTC-USDT".to_string(),
-                price: Decimal::new(4499999, 2),
-                quantity: Decimal::new(23456, 5),
-                side: OrderSide::Sell,
-                timestamp: chrono::Utc::now(),
-            },
-        ];
+        for trade in initial_trades {
+            ingest_trade(&mut trades, &mut stats, &mut tickers, &mut candles, None, trade);
+        }
 
-        tickers.insert("BTC-USDT".to_string(), btc_ticker);
-        tickers.insert("ETH-USDT".to_string(), eth_ticker);
-        trades.insert("BTC-USDT".to_string(), btc_trades);
+        // Only symbols with market data can be subscribed to
+        let websocket = WebSocketManager::new(MAX_WEBSOCKET_CONNECTIONS)
+            .with_symbols(tickers.keys().cloned())
+            .with_metrics(Arc::new(MetricsCollector::new()));
 
         Self {
             tickers: Arc::new(RwLock::new(tickers)),
             trades: Arc::new(RwLock::new(trades)),
+            stats: Arc::new(RwLock::new(stats)),
+            candles: Arc::new(RwLock::new(candles)),
+            candle_store: None,
+            index_prices: IndexPrices::new(),
+            websocket,
+            recordings: PathBuf::from(DEFAULT_RECORDINGS_DIR),
             start_time: SystemTime::now(),
         }
     }
+
+    /// Persist closed candles to `store` and fill each symbol's ring
+    /// buffers from the history already in it
+    pub async fn set_candle_store(&mut self, store: Arc<dyn CandleStore>) {
+        for aggregator in self.candles.write().await.values_mut() {
+            aggregator.set_store(Some(store.clone()));
+            if let Err(e) = aggregator.load_history() {
+                warn!("Failed to load the candle history of {}: {}", aggregator.symbol(), e);
+            }
+        }
+        self.candle_store = Some(store);
+    }
+
+    /// Fold a newly matched trade into the market data and stream the
+    /// candles it updated
+    pub async fn record_trade(&self, trade: Trade) {
+        self.websocket.add_symbol(trade.symbol.clone());
+        let updates = ingest_trade(
+            &mut *self.trades.write().await,
+            &mut *self.stats.write().await,
+            &mut *self.tickers.write().await,
+            &mut *self.candles.write().await,
+            self.candle_store.as_ref(),
+            trade,
+        );
+        for (candle, closed) in updates {
+            let _ = self.websocket.publish_candle(candle, closed).await;
+        }
+    }
+}
+
+/// Append a trade and refresh its symbol's rolling statistics, ticker and
+/// candles. Returns the candle updates to stream: the candles the trade
+/// closed, marked closed, then the symbol's candles in progress.
+fn ingest_trade(
+    trades: &mut HashMap<String, Vec<Trade>>,
+    stats: &mut HashMap<String, MarketStats>,
+    tickers: &mut HashMap<String, Ticker>,
+    candles: &mut HashMap<String, CandleAggregator>,
+    candle_store: Option<&Arc<dyn CandleStore>>,
+    trade: Trade,
+) -> Vec<(Candle, bool)> {
+    let symbol_stats = stats.entry(trade.symbol.clone()).or_default();
+    symbol_stats.record(trade.price, trade.quantity, trade.timestamp);
+    if let Some(ticker) = symbol_stats.ticker(&trade.symbol, trade.timestamp) {
+        tickers.insert(trade.symbol.clone(), ticker);
+    }
+    let aggregator = candles.entry(trade.symbol.clone()).or_insert_with(|| {
+        let mut aggregator = CandleAggregator::new(trade.symbol.clone());
+        aggregator.set_store(candle_store.cloned());
+        if let Err(e) = aggregator.load_history() {
+            warn!("Failed to load the candle history of {}: {}", trade.symbol, e);
+        }
+        aggregator
+    });
+    let mut updates: Vec<(Candle, bool)> = aggregator.ingest(&trade).into_iter().map(|candle| (candle, true)).collect();
+    updates.extend(
+        aggregator
+            .intervals()
+            .filter_map(|interval| aggregator.current(interval))
+            .map(|candle| (candle.clone(), false)),
+    );
+    let symbol_trades = trades.entry(trade.symbol.clone()).or_default();
+    symbol_trades.push(trade);
+    if symbol_trades.len() > MAX_RECENT_TRADES {
+        symbol_trades.drain(..symbol_trades.len() - MAX_RECENT_TRADES);
+    }
+    updates
+}
+
+/// Demo trade between two anonymous users
+fn demo_trade(symbol: &str, sequence: u64, price: Decimal, quantity: Decimal, side: OrderSide) -> Trade {
+    Trade {
+        id: Uuid::new_v4(),
+        symbol: symbol.to_string(),
+        sequence,
+        price,
+        quantity,
+        is_buyer_maker: side == OrderSide::Sell,
+        side,
+        maker_order_id: Uuid::new_v4(),
+        taker_order_id: Uuid::new_v4(),
+        maker_user_id: Uuid::new_v4(),
+        taker_user_id: Uuid::new_v4(),
+        maker_fee: Decimal::ZERO,
+        maker_fee_currency: None,
+        taker_fee: Decimal::ZERO,
+        taker_fee_currency: None,
+        timestamp: chrono::Utc::now(),
+    }
 }
 
 /// Health check endpoint
@@ -102,27 +234,48 @@ async fn health_check(State(state): State<AppState>) -> Json<HealthResponse> {
     })
 }
 
-/// Get all market tickers
+/// Get all market tickers over the 24 hours up to now
 async fn get_tickers(State(state): State<AppState>) -> Json<ApiResponse<Vec<Ticker>>> {
-    let tickers = state.tickers.read().await;
-    let tickers_vec: Vec<Ticker> = tickers.values().cloned().collect();
+    let now = chrono::Utc::now();
+    let stats = state.stats.read().await;
+    let tickers_vec: Vec<Ticker> = stats
+        .iter()
+        .filter_map(|(symbol, symbol_stats)| symbol_stats.ticker(symbol, now))
+        .collect();
     Json(ApiResponse::success(tickers_vec))
 }
 
-/// Get ticker for a specific symbol
+/// Get the ticker of a specific symbol over the 24 hours up to now
 async fn get_ticker(
     State(state): State<AppState>,
     Path(symbol): Path<String>,
 ) -> Result<Json<ApiResponse<Ticker>>, StatusCode> {
-    let tickers = state.tickers.read().await;
-    
-    if let Some(ticker) = tickers.get(&symbol) {
-        Ok(Json(ApiResponse::success(ticker.clone())))
+    let stats = state.stats.read().await;
+    let now = chrono::Utc::now();
+
+    if let Some(ticker) = stats.get(&symbol).and_then(|symbol_stats| symbol_stats.ticker(&symbol, now)) {
+        Ok(Json(ApiResponse::success(ticker)))
     } else {
         Err(StatusCode::NOT_FOUND)
     }
 }
 
+/// Get the index price of every symbol quoted by an external exchange
+async fn get_index_prices(State(state): State<AppState>) -> Json<ApiResponse<Vec<IndexPrice>>> {
+    Json(ApiResponse::success(state.index_prices.all(chrono::Utc::now()).await))
+}
+
+/// Get the index price of a specific symbol
+async fn get_index_price(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+) -> Result<Json<ApiResponse<IndexPrice>>, StatusCode> {
+    match state.index_prices.index(&symbol, chrono::Utc::now()).await {
+        Some(index) => Ok(Json(ApiResponse::success(index))),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
 /// Get recent trades for a symbol
 async fn get_trades(
     State(state): State<AppState>,
@@ -137,6 +290,107 @@ async fn get_trades(
     }
 }
 
+/// Candle query parameters
+#[derive(Debug, Deserialize)]
+struct CandlesQuery {
+    #[serde(default = "default_candle_interval")]
+    interval: CandleInterval,
+    /// Earliest open time included
+    #[serde(default)]
+    start: Option<chrono::DateTime<chrono::Utc>>,
+    /// Open time the candles end before
+    #[serde(default)]
+    end: Option<chrono::DateTime<chrono::Utc>>,
+    limit: Option<usize>,
+}
+
+fn default_candle_interval() -> CandleInterval {
+    CandleInterval::OneMinute
+}
+
+/// Get a symbol's candles of an interval opening between `start` and
+/// `end`, oldest first, including the candle in progress. With a `start`,
+/// the first `limit` candles from it; otherwise the `limit` most recent.
+async fn get_candles(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+    Query(query): Query<CandlesQuery>,
+) -> Result<Json<ApiResponse<Vec<Candle>>>, StatusCode> {
+    let limit = query.limit.unwrap_or(DEFAULT_CANDLE_LIMIT).min(DEFAULT_CANDLE_CAPACITY);
+    if limit == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let (Some(start), Some(end)) = (query.start, query.end) {
+        if start >= end {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let candles = state.candles.read().await;
+    let aggregator = candles.get(&symbol).ok_or(StatusCode::NOT_FOUND)?;
+    match aggregator.candles_in_range(query.interval, query.start, query.end, limit) {
+        Ok(Some(candles)) => Ok(Json(ApiResponse::success(candles))),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            warn!("Failed to read {} candles: {}", symbol, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Periodically close candles whose interval has ended without a new trade
+fn spawn_candle_closer(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CANDLE_CLOSE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = chrono::Utc::now();
+            let closed: Vec<Candle> = state
+                .candles
+                .write()
+                .await
+                .values_mut()
+                .flat_map(|aggregator| aggregator.close_expired(now))
+                .collect();
+            for candle in closed {
+                let _ = state.websocket.publish_candle(candle, true).await;
+            }
+        }
+    });
+}
+
+/// Poll the trading service for new trades and record them
+fn spawn_trade_feed(state: AppState, mut feed: TradeFeed) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(trade_feed::POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match feed.poll().await {
+                Ok(trades) => {
+                    for trade in trades {
+                        state.record_trade(trade).await;
+                    }
+                }
+                Err(e) => warn!("Failed to poll the trading service for trades: {}", e),
+            }
+        }
+    });
+}
+
+/// Upgrade to a WebSocket streaming market data channels
+async fn websocket_handler(State(state): State<AppState>, ws: WebSocketUpgrade) -> Response {
+    state.websocket.handle_websocket(ws, None).await
+}
+
+/// Replay a recorded time range over a WebSocket
+async fn replay_handler(
+    State(state): State<AppState>,
+    Query(request): Query<ReplayRequest>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    recording::handle_replay(ws, state.recordings.clone(), request).await
+}
+
 /// Create the application router
 fn create_app(state: AppState) -> Router {
     Router::new()
@@ -144,6 +398,11 @@ fn create_app(state: AppState) -> Router {
         .route("/api/market-data/tickers", get(get_tickers))
         .route("/api/market-data/ticker/:symbol", get(get_ticker))
         .route("/api/market-data/trades/:symbol", get(get_trades))
+        .route("/api/market-data/index", get(get_index_prices))
+        .route("/api/market-data/index/:symbol", get(get_index_price))
+        .route("/api/market-data/candles/:symbol", get(get_candles))
+        .route("/ws", get(websocket_handler))
+        .route("/ws/replay", get(replay_handler))
         .layer(
             ServiceBuilder::new()
                 .layer(CorsLayer::permissive())
@@ -162,13 +421,66 @@ async fn main() -> anyhow::Result<()> {
 
     info!("Starting FlowEx Market Data Service");
 
-    let state = AppState::new();
-    let app = create_app(state);
+    let prometheus = flowex_metrics::install_prometheus_recorder()?;
+    let trading_service_url = std::env::var("TRADING_SERVICE_URL").ok();
+    let mut state = match trading_service_url {
+        Some(_) => AppState::with_trades(Vec::new()),
+        None => {
+            warn!("TRADING_SERVICE_URL is not set; serving demo market data");
+            AppState::new()
+        }
+    };
+    // Share WebSocket events with the other instances of the service
+    if let Ok(redis_url) = std::env::var("REDIS_URL") {
+        state.websocket = flowex_websocket::bridge::connect_redis(state.websocket, &redis_url).await?;
+    }
+    if let Ok(dir) = std::env::var("MARKET_DATA_RECORDINGS_DIR") {
+        state.recordings = PathBuf::from(dir);
+    }
+    let candles_dir = std::env::var("MARKET_DATA_CANDLES_DIR").unwrap_or_else(|_| DEFAULT_CANDLES_DIR.to_string());
+    state.set_candle_store(Arc::new(FileCandleStore::new(candles_dir))).await;
+    MarketRecorder::new(&state.recordings).spawn(&state.websocket);
+    spawn_candle_closer(state.clone());
+    if let Some(url) = trading_service_url {
+        spawn_trade_feed(state.clone(), TradeFeed::new(&url));
+    }
+    if let Ok(feeds) = std::env::var("EXTERNAL_FEEDS") {
+        let exchanges: Vec<Exchange> = feeds
+            .split(',')
+            .filter_map(|name| {
+                let exchange = Exchange::from_name(name);
+                if exchange.is_none() {
+                    warn!("Ignoring unknown external feed {:?}", name);
+                }
+                exchange
+            })
+            .collect();
+        let symbols: Vec<String> = std::env::var("INDEX_SYMBOLS")
+            .unwrap_or_else(|_| DEFAULT_INDEX_SYMBOLS.to_string())
+            .split(',')
+            .map(|symbol| symbol.trim().to_string())
+            .filter(|symbol| !symbol.is_empty())
+            .collect();
+        for symbol in &symbols {
+            state.websocket.add_symbol(symbol.clone());
+        }
+        external_feeds::spawn_feeds(exchanges, symbols, state.index_prices.clone(), state.websocket.clone());
+    }
+    let websocket = state.websocket.clone();
+    let app = create_app(state).route("/metrics", get(move || std::future::ready(prometheus.render())));
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8003").await?;
     info!("Market data service listening on http://0.0.0.0:8003");
 
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            info!("Shutting down market data service");
+            if let Err(e) = websocket.shutdown(WEBSOCKET_DRAIN_TIMEOUT).await {
+                warn!("WebSocket drain incomplete: {}", e);
+            }
+        })
+        .await?;
 
     Ok(())
 }
@@ -204,12 +516,12 @@ mod tests {
         // 验证初始数据存在
         tokio::runtime::Runtime::new().unwrap().block_on(async {
             let tickers = state.tickers.read().await;
-            assert!(tickers.len() > 0, "应该有初始ticker数据");
+            assert!(!tickers.is_empty(), "应该有初始ticker数据");
             assert!(tickers.contains_key("BTC-USDT"), "应该包含BTC-USDT ticker");
             assert!(tickers.contains_key("ETH-USDT"), "应该包含ETH-USDT ticker");
 
             let trades = state.trades.read().await;
-            assert!(trades.len() > 0, "应该有初始交易数据");
+            assert!(!trades.is_empty(), "应该有初始交易数据");
         });
     }
 
@@ -226,6 +538,7 @@ mod tests {
             high: Decimal::new(10500, 2), // 105.00
             low: Decimal::new(9500, 2), // 95.00
             volume: Decimal::new(1000000, 3), // 1000.000
+            vwap: None,
             timestamp: Utc::now(),
         };
 
@@ -246,9 +559,19 @@ mod tests {
         let trade = Trade {
             id: Uuid::new_v4(),
             symbol: "BTC-USDT".to_string(),
+            sequence: 1,
             price: Decimal::new(4500000, 2), // 45000.00
             quantity: Decimal::new(100, 3), // 0.100
             side: OrderSide::Buy,
+            maker_order_id: Uuid::new_v4(),
+            taker_order_id: Uuid::new_v4(),
+            maker_user_id: Uuid::new_v4(),
+            taker_user_id: Uuid::new_v4(),
+            is_buyer_maker: false,
+            maker_fee: Decimal::ZERO,
+            maker_fee_currency: None,
+            taker_fee: Decimal::ZERO,
+            taker_fee_currency: None,
             timestamp: Utc::now(),
         };
 
@@ -279,14 +602,14 @@ mod tests {
         init_test_env();
 
         let state = AppState::new();
-        let response = get_all_tickers(State(state)).await;
+        let response = get_tickers(State(state)).await;
 
         let api_response = response.0;
         assert!(api_response.success);
         assert!(api_response.data.is_some());
 
         let tickers = api_response.data.unwrap();
-        assert!(tickers.len() > 0);
+        assert!(!tickers.is_empty());
 
         // 验证包含预期的ticker
         let btc_ticker = tickers.iter().find(|t| t.symbol == "BTC-USDT");
@@ -346,7 +669,7 @@ mod tests {
                 assert!(api_response.data.is_some());
 
                 let trades = api_response.data.unwrap();
-                assert!(trades.len() > 0, "应该有交易历史数据");
+                assert!(!trades.is_empty(), "应该有交易历史数据");
 
                 // 验证交易数据格式
                 for trade in &trades {
@@ -369,6 +692,126 @@ mod tests {
         }
     }
 
+    /// 测试：获取K线
+    #[tokio::test]
+    async fn test_get_candles() {
+        init_test_env();
+
+        let state = AppState::new();
+
+        let query = CandlesQuery { interval: CandleInterval::OneMinute, start: None, end: None, limit: None };
+        let candles = get_candles(State(state.clone()), Path("BTC-USDT".to_string()), Query(query))
+            .await
+            .unwrap()
+            .0
+            .data
+            .unwrap();
+        let volume: Decimal = candles.iter().map(|candle| candle.volume).sum();
+        assert_eq!(volume, Decimal::new(35801, 5));
+        assert_eq!(candles.iter().map(|candle| candle.trade_count).sum::<u64>(), 2);
+
+        let query = CandlesQuery { interval: CandleInterval::OneDay, start: None, end: None, limit: Some(1) };
+        let candles = get_candles(State(state.clone()), Path("ETH-USDT".to_string()), Query(query))
+            .await
+            .unwrap()
+            .0
+            .data
+            .unwrap();
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0].close, Decimal::new(300000, 2));
+
+        let query = CandlesQuery { interval: CandleInterval::OneMinute, start: None, end: None, limit: None };
+        let response = get_candles(State(state), Path("INVALID-USDT".to_string()), Query(query)).await;
+        assert_eq!(response.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    /// 测试：按时间范围查询K线，历史K线从持久化存储读取
+    #[tokio::test]
+    async fn test_get_candles_in_range() {
+        init_test_env();
+
+        let dir = std::env::temp_dir().join(format!("flowex-candles-{}", Uuid::new_v4()));
+        let store = Arc::new(FileCandleStore::new(&dir));
+        let hour_ago = CandleInterval::OneMinute.open_time(Utc::now() - chrono::Duration::hours(1));
+        for minutes in 0..3 {
+            let candle = Candle {
+                symbol: "BTC-USDT".to_string(),
+                interval: CandleInterval::OneMinute,
+                open_time: hour_ago + chrono::Duration::minutes(minutes),
+                close_time: hour_ago + chrono::Duration::minutes(minutes + 1),
+                open: Decimal::new(44000, 0),
+                high: Decimal::new(44000, 0),
+                low: Decimal::new(44000, 0),
+                close: Decimal::new(44000, 0),
+                volume: Decimal::ONE,
+                quote_volume: Decimal::new(44000, 0),
+                trade_count: 1,
+            };
+            store.save(&candle).unwrap();
+        }
+        let mut state = AppState::new();
+        state.set_candle_store(store).await;
+
+        let candles = |start, end, limit| {
+            let query = CandlesQuery { interval: CandleInterval::OneMinute, start, end, limit };
+            get_candles(State(state.clone()), Path("BTC-USDT".to_string()), Query(query))
+        };
+
+        let history = candles(Some(hour_ago), Some(hour_ago + chrono::Duration::minutes(2)), None)
+            .await
+            .unwrap()
+            .0
+            .data
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].open_time, hour_ago);
+
+        // 从起始时间向后读取，包含当前K线
+        let forward = candles(Some(hour_ago + chrono::Duration::minutes(2)), None, None)
+            .await
+            .unwrap()
+            .0
+            .data
+            .unwrap();
+        assert_eq!(forward.len(), 2);
+        assert_eq!(forward[1].close, Decimal::new(4499999, 2));
+
+        let latest = candles(None, None, Some(1)).await.unwrap().0.data.unwrap();
+        assert_eq!(latest[0].trade_count, 2);
+
+        assert_eq!(candles(Some(hour_ago), Some(hour_ago), None).await.unwrap_err(), StatusCode::BAD_REQUEST);
+        assert_eq!(candles(None, None, Some(0)).await.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// 测试：24小时行情由实际成交计算，超出窗口的成交不计入
+    #[tokio::test]
+    async fn test_ticker_from_recorded_trades() {
+        init_test_env();
+
+        let trade_at = |sequence, price, quantity, hours_ago| Trade {
+            timestamp: Utc::now() - chrono::Duration::hours(hours_ago),
+            ..demo_trade("SOL-USDT", sequence, Decimal::new(price, 0), Decimal::new(quantity, 0), OrderSide::Buy)
+        };
+        let state = AppState::with_trades(vec![trade_at(1, 50, 10, 30), trade_at(2, 100, 1, 20)]);
+        state.record_trade(trade_at(3, 130, 2, 10)).await;
+        state.record_trade(trade_at(4, 90, 1, 0)).await;
+
+        let ticker = get_ticker(State(state.clone()), Path("SOL-USDT".to_string())).await.unwrap().0.data.unwrap();
+        assert_eq!(ticker.price, Decimal::new(90, 0));
+        assert_eq!(ticker.change, Decimal::new(-10, 0));
+        assert_eq!(ticker.change_percent, Decimal::new(-10, 0));
+        assert_eq!(ticker.high, Decimal::new(130, 0));
+        assert_eq!(ticker.low, Decimal::new(90, 0));
+        assert_eq!(ticker.volume, Decimal::new(4, 0));
+        assert_eq!(ticker.vwap, Some(Decimal::new(450, 0) / Decimal::new(4, 0)));
+
+        let tickers = get_tickers(State(state.clone())).await.0.data.unwrap();
+        assert_eq!(tickers.len(), 1);
+        assert_eq!(state.trades.read().await["SOL-USDT"].len(), 4);
+    }
+
     /// 测试：数据一致性
     #[tokio::test]
     async fn test_data_consistency() {
@@ -458,12 +901,13 @@ mod tests {
             high: Decimal::new(10000, 2),
             low: Decimal::new(10000, 2),
             volume: Decimal::ZERO,
+            vwap: None,
             timestamp: now,
         };
 
         // 验证时间戳在合理范围内
         let time_diff = (Utc::now() - ticker.timestamp).num_seconds();
-        assert!(time_diff >= 0 && time_diff < 5, "时间戳应该在当前时间附近");
+        assert!((0..5).contains(&time_diff), "时间戳应该在当前时间附近");
     }
 
     /// 测试：性能基准
@@ -521,6 +965,7 @@ mod tests {
                     high: Decimal::new(11000 + i, 2),
                     low: Decimal::new(9000 + i, 2),
                     volume: Decimal::new(1000000 + i, 3),
+                    vwap: None,
                     timestamp: Utc::now(),
                 };
                 tickers.insert(symbol.clone(), ticker);
@@ -529,9 +974,19 @@ mod tests {
                 let trade = Trade {
                     id: Uuid::new_v4(),
                     symbol: symbol.clone(),
+                    sequence: 1,
                     price: Decimal::new(10000 + i, 2),
                     quantity: Decimal::new(100, 3),
                     side: if i % 2 == 0 { OrderSide::Buy } else { OrderSide::Sell },
+                    maker_order_id: Uuid::new_v4(),
+                    taker_order_id: Uuid::new_v4(),
+                    maker_user_id: Uuid::new_v4(),
+                    taker_user_id: Uuid::new_v4(),
+                    is_buyer_maker: i % 2 != 0,
+                    maker_fee: Decimal::ZERO,
+                    maker_fee_currency: None,
+                    taker_fee: Decimal::ZERO,
+                    taker_fee_currency: None,
                     timestamp: Utc::now(),
                 };
                 trades.entry(symbol).or_insert_with(Vec::new).push(trade);
@@ -548,7 +1003,6 @@ mod tests {
         // 清理内存（通过作用域自动清理）
         drop(tickers);
         drop(trades);
-        assert!(true, "内存使用优化测试完成");
     }
 
     /// 测试：错误处理
@@ -586,6 +1040,7 @@ mod tests {
             high: Decimal::new(4650000, 2), // 46500.00
             low: Decimal::new(4350000, 2), // 43500.00
             volume: Decimal::new(123456789, 5), // 1234.56789
+            vwap: None,
             timestamp: Utc::now(),
         };
 
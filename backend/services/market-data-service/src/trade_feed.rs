@@ -0,0 +1,191 @@
+//! Trade feed from the trading service
+//!
+//! Tickers and candles are computed from the trades the matching engines
+//! actually make. `TradeFeed` polls the trading service's public
+//! `GET /api/trading/trades/:symbol` for every listed pair and hands back
+//! the trades it has not seen yet, oldest first. A symbol's first poll
+//! returns its latest `MAX_TRADES_PER_POLL` trades, which rebuilds the 24h
+//! statistics after a restart.
+
+use flowex_types::{ApiResponse, FlowExError, FlowExResult, Trade, TradingPair};
+use serde::de::DeserializeOwned;
+use std::{collections::HashMap, time::Duration};
+use tracing::warn;
+
+/// How often the trading service is asked for new trades
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Trades fetched per symbol and poll, the most the trading service serves
+const MAX_TRADES_PER_POLL: usize = 1000;
+
+/// Timeout of requests to the trading service
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Polls the trading service for the trades made since the last poll
+pub struct TradeFeed {
+    http: reqwest::Client,
+    base_url: String,
+    /// Sequence of the last trade handed back, per symbol
+    last_sequences: HashMap<String, u64>,
+}
+
+impl TradeFeed {
+    /// Feed from the trading service at `base_url`
+    pub fn new(base_url: &str) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            last_sequences: HashMap::new(),
+        }
+    }
+
+    /// Trades made on any listed pair since the last poll, oldest first
+    /// per symbol. A symbol whose trades cannot be fetched is skipped
+    /// until the next poll.
+    pub async fn poll(&mut self) -> FlowExResult<Vec<Trade>> {
+        let pairs: Vec<TradingPair> = self.get("/api/trading/pairs").await?;
+
+        let mut trades = Vec::new();
+        for pair in pairs {
+            let path = format!("/api/trading/trades/{}?limit={}", pair.symbol, MAX_TRADES_PER_POLL);
+            match self.get::<Vec<Trade>>(&path).await {
+                Ok(recent) => trades.extend(self.unseen(&pair.symbol, recent)),
+                Err(e) => warn!("Failed to fetch the trades of {}: {}", pair.symbol, e),
+            }
+        }
+        Ok(trades)
+    }
+
+    /// The trades among `recent` newer than the last one handed back for
+    /// `symbol`, oldest first
+    fn unseen(&mut self, symbol: &str, mut recent: Vec<Trade>) -> Vec<Trade> {
+        recent.sort_by_key(|trade| trade.sequence);
+        let Some(newest) = recent.last().map(|trade| trade.sequence) else {
+            return Vec::new();
+        };
+
+        let last = self.last_sequences.entry(symbol.to_string()).or_default();
+        if newest < *last {
+            // The trading service numbered its trades afresh, e.g. after a
+            // restart without a database
+            warn!("Trade sequence of {} went back from {} to {}", symbol, last, newest);
+            *last = 0;
+        }
+        let unseen: Vec<Trade> = recent.into_iter().filter(|trade| trade.sequence > *last).collect();
+        if let Some(first) = unseen.first().filter(|first| *last > 0 && first.sequence > *last + 1) {
+            warn!("Missed {} trades of {} between polls", first.sequence - *last - 1, symbol);
+        }
+        *last = newest;
+        unseen
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> FlowExResult<T> {
+        let url = format!("{}{}", self.base_url, path);
+        let response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| FlowExError::MarketData(format!("Trading service unavailable: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(FlowExError::MarketData(format!(
+                "Trading service returned HTTP {}",
+                response.status()
+            )));
+        }
+        let body: ApiResponse<T> = response
+            .json()
+            .await
+            .map_err(|e| FlowExError::MarketData(format!("Invalid trading service response: {}", e)))?;
+        body.data
+            .ok_or_else(|| FlowExError::MarketData("Trading service response has no data".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Path, routing::get, Json, Router};
+    use flowex_types::{OrderSide, TradingStatus};
+    use rust_decimal::Decimal;
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+    use uuid::Uuid;
+
+    fn trade(sequence: u64) -> Trade {
+        Trade {
+            id: Uuid::new_v4(),
+            symbol: "BTC-USDT".to_string(),
+            sequence,
+            price: Decimal::new(45000, 0),
+            quantity: Decimal::ONE,
+            side: OrderSide::Buy,
+            is_buyer_maker: false,
+            maker_order_id: Uuid::nil(),
+            taker_order_id: Uuid::nil(),
+            maker_user_id: Uuid::nil(),
+            taker_user_id: Uuid::nil(),
+            maker_fee: Decimal::ZERO,
+            maker_fee_currency: None,
+            taker_fee: Decimal::ZERO,
+            taker_fee_currency: None,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    /// 测试：轮询交易服务只返回新成交，按序号从旧到新
+    #[tokio::test]
+    async fn test_poll_returns_new_trades() {
+        // 模拟交易服务：成交从新到旧返回，每次请求多一笔成交
+        let newest = Arc::new(AtomicU64::new(3));
+        let trades_served = newest.clone();
+        let trading_app = Router::new()
+            .route(
+                "/api/trading/pairs",
+                get(|| async {
+                    Json(ApiResponse::success(vec![TradingPair {
+                        symbol: "BTC-USDT".to_string(),
+                        base_asset: "BTC".to_string(),
+                        quote_asset: "USDT".to_string(),
+                        status: TradingStatus::Trading,
+                        min_price: Decimal::ZERO,
+                        max_price: Decimal::new(1_000_000, 0),
+                        min_qty: Decimal::ZERO,
+                        max_qty: Decimal::new(1_000, 0),
+                        step_size: Decimal::ZERO,
+                        tick_size: Decimal::ZERO,
+                        min_notional: Decimal::ZERO,
+                    }]))
+                }),
+            )
+            .route(
+                "/api/trading/trades/:symbol",
+                get(move |Path(symbol): Path<String>| async move {
+                    assert_eq!(symbol, "BTC-USDT");
+                    let newest = trades_served.fetch_add(1, Ordering::SeqCst);
+                    Json(ApiResponse::success((1..=newest).rev().map(trade).collect::<Vec<_>>()))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, trading_app).await.unwrap() });
+
+        let mut feed = TradeFeed::new(&url);
+        let sequences = |trades: Vec<Trade>| trades.iter().map(|trade| trade.sequence).collect::<Vec<_>>();
+        assert_eq!(sequences(feed.poll().await.unwrap()), vec![1, 2, 3]);
+        assert_eq!(sequences(feed.poll().await.unwrap()), vec![4]);
+
+        // 交易服务重启后序号重新编号
+        newest.store(2, Ordering::SeqCst);
+        assert_eq!(sequences(feed.poll().await.unwrap()), vec![1, 2]);
+
+        assert!(TradeFeed::new("http://127.0.0.1:1").poll().await.is_err());
+    }
+}
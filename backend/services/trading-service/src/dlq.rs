@@ -0,0 +1,342 @@
+//! Dead-letter queue for failed event handlers
+//!
+//! Events whose handler keeps failing after its own retries (settlement,
+//! notifications, projections, webhooks) are parked here together with the
+//! error context so operators can inspect and replay them. An alert is
+//! raised when the queue depth crosses the configured threshold.
+//!
+//! Every change is written through to the service's `Store` (the
+//! `dead_letter_events` table with PostgreSQL), and `DeadLetterQueue::load`
+//! reads the entries back on startup, so parked events survive a restart.
+
+use crate::store::Store;
+use chrono::{DateTime, Utc};
+use flowex_types::FlowExResult;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Handler that failed to process an event
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HandlerKind {
+    Settlement,
+    Notification,
+    Projection,
+    Webhook,
+}
+
+/// Dead letter lifecycle
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterStatus {
+    Pending,
+    Replayed,
+    Discarded,
+}
+
+/// A parked event with its failure context
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub handler: HandlerKind,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub error: String,
+    pub attempts: u32,
+    pub status: DeadLetterStatus,
+    pub first_failed_at: DateTime<Utc>,
+    pub last_failed_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// Dead-letter queue statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetterStats {
+    pub pending: usize,
+    pub replayed: usize,
+    pub discarded: usize,
+    pub alert_threshold: usize,
+    pub alerting: bool,
+}
+
+/// Dead-letter queue, cached in memory and persisted to a `Store`
+#[derive(Clone)]
+pub struct DeadLetterQueue {
+    entries: Arc<RwLock<HashMap<Uuid, DeadLetter>>>,
+    store: Store,
+    alert_threshold: usize,
+    alerting: Arc<AtomicBool>,
+}
+
+impl DeadLetterQueue {
+    /// Create a new in-memory dead-letter queue alerting above `alert_threshold` pending entries
+    pub fn new(alert_threshold: usize) -> Self {
+        Self::from_entries(Store::in_memory(), HashMap::new(), alert_threshold)
+    }
+
+    /// Dead-letter queue persisted to `store`, holding the entries already in it
+    pub async fn load(store: Store, alert_threshold: usize) -> FlowExResult<Self> {
+        let entries: HashMap<Uuid, DeadLetter> =
+            store.dead_letters().await?.into_iter().map(|entry| (entry.id, entry)).collect();
+        info!("Loaded {} dead letters", entries.len());
+
+        let queue = Self::from_entries(store, entries, alert_threshold);
+        queue.check_depth().await;
+        Ok(queue)
+    }
+
+    fn from_entries(store: Store, entries: HashMap<Uuid, DeadLetter>, alert_threshold: usize) -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(entries)),
+            store,
+            alert_threshold,
+            alerting: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Park a failed event
+    pub async fn park(
+        &self,
+        handler: HandlerKind,
+        event_type: &str,
+        payload: serde_json::Value,
+        error: String,
+        attempts: u32,
+    ) -> Uuid {
+        let now = Utc::now();
+        let entry = DeadLetter {
+            id: Uuid::new_v4(),
+            handler,
+            event_type: event_type.to_string(),
+            payload,
+            error,
+            attempts,
+            status: DeadLetterStatus::Pending,
+            first_failed_at: now,
+            last_failed_at: now,
+            resolved_at: None,
+        };
+        let id = entry.id;
+
+        warn!(
+            "Parked {:?} event {} in dead-letter queue after {} attempts: {}",
+            handler, event_type, attempts, entry.error
+        );
+
+        self.persist(&entry).await;
+        self.entries.write().await.insert(id, entry);
+        self.check_depth().await;
+        id
+    }
+
+    /// List entries, optionally filtered by status, oldest first
+    pub async fn list(&self, status: Option<DeadLetterStatus>) -> Vec<DeadLetter> {
+        let entries = self.entries.read().await;
+        let mut result: Vec<DeadLetter> = entries
+            .values()
+            .filter(|e| status.is_none_or(|s| e.status == s))
+            .cloned()
+            .collect();
+        result.sort_by_key(|e| e.first_failed_at);
+        result
+    }
+
+    /// Get a single entry
+    pub async fn get(&self, id: Uuid) -> Option<DeadLetter> {
+        self.entries.read().await.get(&id).cloned()
+    }
+
+    /// Mark an entry as successfully replayed
+    pub async fn mark_replayed(&self, id: Uuid) -> bool {
+        let updated = self.resolve(id, DeadLetterStatus::Replayed).await;
+        if updated {
+            info!("Dead letter {} replayed successfully", id);
+        }
+        updated
+    }
+
+    /// Record another failed replay attempt
+    pub async fn record_failure(&self, id: Uuid, error: String) {
+        let updated = self.entries.write().await.get_mut(&id).map(|entry| {
+            entry.attempts += 1;
+            entry.error = error;
+            entry.last_failed_at = Utc::now();
+            entry.clone()
+        });
+        if let Some(entry) = updated {
+            self.persist(&entry).await;
+        }
+    }
+
+    /// Discard an entry without replaying it
+    pub async fn discard(&self, id: Uuid) -> bool {
+        self.resolve(id, DeadLetterStatus::Discarded).await
+    }
+
+    /// Number of pending entries
+    pub async fn depth(&self) -> usize {
+        self.entries
+            .read()
+            .await
+            .values()
+            .filter(|e| e.status == DeadLetterStatus::Pending)
+            .count()
+    }
+
+    /// Queue statistics
+    pub async fn stats(&self) -> DeadLetterStats {
+        let entries = self.entries.read().await;
+        let count = |status| entries.values().filter(|e| e.status == status).count();
+
+        DeadLetterStats {
+            pending: count(DeadLetterStatus::Pending),
+            replayed: count(DeadLetterStatus::Replayed),
+            discarded: count(DeadLetterStatus::Discarded),
+            alert_threshold: self.alert_threshold,
+            alerting: self.alerting.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn resolve(&self, id: Uuid, status: DeadLetterStatus) -> bool {
+        let resolved = {
+            let mut entries = self.entries.write().await;
+            match entries.get_mut(&id) {
+                Some(entry) if entry.status == DeadLetterStatus::Pending => {
+                    entry.status = status;
+                    entry.resolved_at = Some(Utc::now());
+                    Some(entry.clone())
+                }
+                _ => None,
+            }
+        };
+
+        match resolved {
+            Some(entry) => {
+                self.persist(&entry).await;
+                self.check_depth().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Write an entry through to the store. A failure is logged rather than
+    /// returned: the entry stays queued in memory and is written again on
+    /// its next change.
+    async fn persist(&self, entry: &DeadLetter) {
+        if let Err(e) = self.store.save_dead_letter(entry).await {
+            error!("Failed to persist dead letter {}: {}", entry.id, e);
+        }
+    }
+
+    /// Raise (or clear) the depth alert when crossing the threshold
+    async fn check_depth(&self) {
+        let depth = self.depth().await;
+
+        if depth > self.alert_threshold {
+            if !self.alerting.swap(true, Ordering::Relaxed) {
+                error!(
+                    "ALERT: dead-letter queue depth {} exceeds threshold {}",
+                    depth, self.alert_threshold
+                );
+            }
+        } else if self.alerting.swap(false, Ordering::Relaxed) {
+            info!("Dead-letter queue depth {} back under threshold {}", depth, self.alert_threshold);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：入队、查询与重放状态
+    #[tokio::test]
+    async fn test_park_and_replay() {
+        let dlq = DeadLetterQueue::new(10);
+
+        let id = dlq
+            .park(
+                HandlerKind::Settlement,
+                "trade_settled",
+                serde_json::json!({ "trade_id": "abc" }),
+                "wallet unavailable".to_string(),
+                3,
+            )
+            .await;
+
+        assert_eq!(dlq.depth().await, 1);
+        let entry = dlq.get(id).await.unwrap();
+        assert_eq!(entry.status, DeadLetterStatus::Pending);
+        assert_eq!(entry.attempts, 3);
+
+        dlq.record_failure(id, "still unavailable".to_string()).await;
+        assert_eq!(dlq.get(id).await.unwrap().attempts, 4);
+
+        assert!(dlq.mark_replayed(id).await);
+        assert!(!dlq.mark_replayed(id).await);
+        assert_eq!(dlq.depth().await, 0);
+        assert_eq!(dlq.list(Some(DeadLetterStatus::Replayed)).await.len(), 1);
+    }
+
+    /// 测试：深度超过阈值时告警，回落后解除
+    #[tokio::test]
+    async fn test_depth_alert_threshold() {
+        let dlq = DeadLetterQueue::new(1);
+
+        let first = dlq
+            .park(HandlerKind::Projection, "order_updated", serde_json::Value::Null, "err".to_string(), 1)
+            .await;
+        assert!(!dlq.stats().await.alerting);
+
+        dlq.park(HandlerKind::Projection, "order_updated", serde_json::Value::Null, "err".to_string(), 1)
+            .await;
+        assert!(dlq.stats().await.alerting);
+
+        assert!(dlq.discard(first).await);
+        let stats = dlq.stats().await;
+        assert!(!stats.alerting);
+        assert_eq!(stats.pending, 1);
+        assert_eq!(stats.discarded, 1);
+    }
+
+    /// 测试：队列内容写入存储，重启后重新加载并恢复告警状态
+    #[tokio::test]
+    async fn test_entries_survive_reload() {
+        let store = Store::in_memory();
+        let dlq = DeadLetterQueue::load(store.clone(), 1).await.unwrap();
+
+        let replayed = dlq
+            .park(HandlerKind::Webhook, "order_filled", serde_json::json!({ "order_id": "abc" }), "timeout".to_string(), 5)
+            .await;
+        let failing = dlq
+            .park(HandlerKind::Settlement, "trade_settled", serde_json::Value::Null, "err".to_string(), 3)
+            .await;
+        dlq.park(HandlerKind::Settlement, "trade_settled", serde_json::Value::Null, "err".to_string(), 3)
+            .await;
+        assert!(dlq.mark_replayed(replayed).await);
+        dlq.record_failure(failing, "still failing".to_string()).await;
+
+        let reloaded = DeadLetterQueue::load(store, 1).await.unwrap();
+        let stats = reloaded.stats().await;
+        assert_eq!(stats.pending, 2);
+        assert_eq!(stats.replayed, 1);
+        assert!(stats.alerting);
+
+        let entry = reloaded.get(replayed).await.unwrap();
+        assert_eq!(entry.status, DeadLetterStatus::Replayed);
+        assert_eq!(entry.payload, serde_json::json!({ "order_id": "abc" }));
+        let entry = reloaded.get(failing).await.unwrap();
+        assert_eq!(entry.attempts, 4);
+        assert_eq!(entry.error, "still failing");
+    }
+}
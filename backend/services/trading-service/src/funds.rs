@@ -0,0 +1,286 @@
+//! Funds reservation through the wallet service
+//!
+//! With a wallet service configured, an order only reaches the matching
+//! engine once the wallet has locked what it may spend: for a buy, its cost
+//! at the limit price plus a fee reserve in the quote asset; for a sell, the
+//! quantity in the base asset. A market or stop-market buy has no price to
+//! bound its cost, so it locks the whole available quote balance. Every trade is then settled against the reservations
+//! of its two orders, and whatever an order did not spend is released once
+//! it is filled, cancelled or expired. Settlements and releases that fail
+//! are parked in the dead-letter queue so they can be replayed. Each call
+//! returns the balances the wallet changed, for the users' streams.
+//!
+//! The wallet only accepts these calls signed by the trading service, with
+//! the secret in `FLOWEX_SERVICE_SECRET_TRADING` (see
+//! `flowex_middleware::service_auth`).
+
+use crate::dlq::{DeadLetterQueue, HandlerKind};
+use flowex_types::{
+    ApiResponse, BalanceChange, FlowExError, FlowExResult, Order, OrderSide, Reservation, TradeSettlement, TradingPair,
+};
+use flowex_middleware::service_auth::service_headers;
+use reqwest::{header::CONTENT_TYPE, Method, RequestBuilder, Response, StatusCode};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::debug;
+use uuid::Uuid;
+
+/// Name the trading service signs its wallet requests with
+pub const SERVICE_NAME: &str = "trading";
+
+/// Fee reserved on top of a buy order's cost, the most a fill may charge
+pub const FEE_RESERVE_RATE: Decimal = flowex_fees::MAX_FEE_RATE;
+
+/// Dead letter event type of a failed trade settlement
+const SETTLEMENT_EVENT: &str = "trade_settlement";
+
+/// Dead letter event type of a failed reservation release
+const RELEASE_EVENT: &str = "funds_release";
+
+/// Timeout of requests to the wallet service
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Dead-lettered reservation release
+#[derive(Debug, serde::Serialize, Deserialize)]
+struct ReleaseDeadLetter {
+    order_id: Uuid,
+}
+
+/// Client of the wallet service's reservation and settlement endpoints
+#[derive(Clone)]
+pub struct WalletClient {
+    http: reqwest::Client,
+    base_url: String,
+    /// Secret requests are signed with; unsigned requests are refused
+    service_secret: Option<String>,
+    dead_letters: DeadLetterQueue,
+}
+
+impl WalletClient {
+    /// Client of the wallet service at `base_url`, signing its requests
+    /// with `service_secret` and parking failed settlements and releases in
+    /// `dead_letters`
+    pub fn new(base_url: &str, service_secret: Option<String>, dead_letters: DeadLetterQueue) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            service_secret,
+            dead_letters,
+        }
+    }
+
+    /// Lock funds for an order, or adjust the amount locked for it. A
+    /// refusal, such as an insufficient balance, is a `FlowExError::Wallet`.
+    pub async fn reserve(&self, reservation: &Reservation) -> FlowExResult<Vec<BalanceChange>> {
+        let request = self.request(Method::POST, "/api/wallet/reservations", json_body(reservation)?);
+        balance_changes(self.send(request).await?).await
+    }
+
+    /// Settle a trade, parking it in the dead-letter queue if that fails
+    pub async fn settle(&self, settlement: TradeSettlement) -> Vec<BalanceChange> {
+        match self.try_settle(&settlement).await {
+            Ok(changes) => changes,
+            Err(e) => {
+                self.park(SETTLEMENT_EVENT, &settlement, e).await;
+                Vec::new()
+            }
+        }
+    }
+
+    /// Release whatever is still locked for an order, parking the release
+    /// in the dead-letter queue if that fails
+    pub async fn release(&self, order_id: Uuid) -> Vec<BalanceChange> {
+        match self.try_release(order_id).await {
+            Ok(changes) => changes,
+            Err(e) => {
+                self.park(RELEASE_EVENT, &ReleaseDeadLetter { order_id }, e).await;
+                Vec::new()
+            }
+        }
+    }
+
+    /// Replay a dead-lettered settlement or release
+    pub async fn replay(&self, event_type: &str, payload: &serde_json::Value) -> FlowExResult<()> {
+        let invalid = |e: serde_json::Error| FlowExError::Validation(format!("Invalid {} dead letter: {}", event_type, e));
+        match event_type {
+            SETTLEMENT_EVENT => {
+                let settlement: TradeSettlement = serde_json::from_value(payload.clone()).map_err(invalid)?;
+                self.try_settle(&settlement).await.map(|_| ())
+            }
+            RELEASE_EVENT => {
+                let release: ReleaseDeadLetter = serde_json::from_value(payload.clone()).map_err(invalid)?;
+                self.try_release(release.order_id).await.map(|_| ())
+            }
+            _ => Err(FlowExError::Validation(format!("Unknown settlement event {}", event_type))),
+        }
+    }
+
+    async fn try_settle(&self, settlement: &TradeSettlement) -> FlowExResult<Vec<BalanceChange>> {
+        let request = self.request(Method::POST, "/api/wallet/settlements", json_body(settlement)?);
+        balance_changes(self.send(request).await?).await
+    }
+
+    async fn try_release(&self, order_id: Uuid) -> FlowExResult<Vec<BalanceChange>> {
+        let path = format!("/api/wallet/reservations/{}", order_id);
+        let response = self.send(self.request(Method::DELETE, &path, Vec::new())).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            // Nothing was locked, or it was already released
+            return Ok(Vec::new());
+        }
+        balance_changes(response).await
+    }
+
+    /// Request to `path` at the wallet service, signed as the trading service
+    fn request(&self, method: Method, path: &str, body: Vec<u8>) -> RequestBuilder {
+        let url = format!("{}{}", self.base_url, path);
+        let signed_path = reqwest::Url::parse(&url).map_or_else(|_| path.to_string(), |url| url.path().to_string());
+        let mut request = self.http.request(method.clone(), url);
+        if let Some(secret) = &self.service_secret {
+            for (name, value) in service_headers(SERVICE_NAME, secret, method.as_str(), &signed_path, &body) {
+                request = request.header(name, value);
+            }
+        }
+        if body.is_empty() {
+            request
+        } else {
+            request.header(CONTENT_TYPE, "application/json").body(body)
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder) -> FlowExResult<Response> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Wallet service unavailable: {}", e)))?;
+        debug!("Wallet service answered HTTP {}", response.status());
+        Ok(response)
+    }
+
+    async fn park<T: serde::Serialize>(&self, event_type: &str, payload: &T, error: FlowExError) {
+        match serde_json::to_value(payload) {
+            Ok(value) => {
+                self.dead_letters
+                    .park(HandlerKind::Settlement, event_type, value, error.to_string(), 1)
+                    .await;
+            }
+            Err(e) => tracing::warn!("Failed to serialize {} dead letter: {}", event_type, e),
+        }
+    }
+}
+
+fn json_body<T: serde::Serialize>(value: &T) -> FlowExResult<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| FlowExError::Internal(format!("Failed to encode wallet request: {}", e)))
+}
+
+/// Balances a wallet service response reports changed. A refusal by the
+/// wallet service is a `FlowExError::Wallet`, any other failure an internal
+/// error.
+async fn balance_changes(response: Response) -> FlowExResult<Vec<BalanceChange>> {
+    let status = response.status();
+    if status.is_client_error() {
+        return Err(FlowExError::Wallet(format!("Wallet service refused the request: HTTP {}", status)));
+    }
+    if !status.is_success() {
+        return Err(FlowExError::Internal(format!("Wallet service returned HTTP {}", status)));
+    }
+    let body: ApiResponse<Vec<BalanceChange>> = response
+        .json()
+        .await
+        .map_err(|e| FlowExError::Internal(format!("Invalid wallet service response: {}", e)))?;
+    Ok(body.data.unwrap_or_default())
+}
+
+/// Funds `order` may spend on `trading_pair`. `None` when there is nothing
+/// to lock.
+pub fn reservation(order: &Order, trading_pair: &TradingPair) -> Option<Reservation> {
+    let (currency, amount) = match (&order.side, order.price) {
+        (OrderSide::Sell, _) => (&trading_pair.base_asset, order.remaining_quantity),
+        (OrderSide::Buy, Some(price)) => (
+            &trading_pair.quote_asset,
+            price * order.remaining_quantity * (Decimal::ONE + FEE_RESERVE_RATE),
+        ),
+        (OrderSide::Buy, None) => {
+            return (order.remaining_quantity > Decimal::ZERO).then(|| Reservation {
+                order_id: order.id,
+                user_id: order.user_id,
+                currency: trading_pair.quote_asset.clone(),
+                amount: Decimal::ZERO,
+                all_available: true,
+            });
+        }
+    };
+
+    (amount > Decimal::ZERO).then(|| Reservation {
+        order_id: order.id,
+        user_id: order.user_id,
+        currency: currency.clone(),
+        amount,
+        all_available: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::{create_limit_order, create_market_order};
+    use flowex_types::{OrderType, TradingStatus};
+
+    fn trading_pair() -> TradingPair {
+        TradingPair {
+            symbol: "BTCUSDT".to_string(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            status: TradingStatus::Trading,
+            min_price: Decimal::new(1, 2),
+            max_price: Decimal::new(10000000, 0),
+            min_qty: Decimal::new(1, 8),
+            max_qty: Decimal::new(1000000, 0),
+            step_size: Decimal::new(1, 8),
+            tick_size: Decimal::new(1, 2),
+            min_notional: Decimal::ZERO,
+        }
+    }
+
+    fn order(side: OrderSide, price: Option<i64>, quantity: Decimal) -> Order {
+        match price {
+            Some(price) => create_limit_order(side, price, quantity),
+            None => create_market_order(side, quantity),
+        }
+    }
+
+    /// 测试：限价买单冻结计价资产及手续费，卖单冻结基础资产，市价和止损市价买单冻结全部可用计价资产
+    #[test]
+    fn test_reservation_amounts() {
+        let pair = trading_pair();
+
+        let buy = reservation(&order(OrderSide::Buy, Some(45000), Decimal::new(2, 1)), &pair).unwrap();
+        assert_eq!(buy.currency, "USDT");
+        assert_eq!((buy.amount, buy.all_available), (Decimal::new(9009, 0), false));
+
+        let sell = reservation(&order(OrderSide::Sell, Some(45000), Decimal::new(2, 1)), &pair).unwrap();
+        assert_eq!(sell.currency, "BTC");
+        assert_eq!(sell.amount, Decimal::new(2, 1));
+        let market_sell = reservation(&order(OrderSide::Sell, None, Decimal::new(2, 1)), &pair).unwrap();
+        assert_eq!((market_sell.currency.as_str(), market_sell.amount), ("BTC", Decimal::new(2, 1)));
+
+        let market_buy = reservation(&order(OrderSide::Buy, None, Decimal::new(2, 1)), &pair).unwrap();
+        assert_eq!(market_buy.currency, "USDT");
+        assert!(market_buy.all_available);
+
+        // A stop-market buy executes at market once triggered, above its stop price
+        let stop_market_buy = Order {
+            order_type: OrderType::StopLoss,
+            stop_price: Some(Decimal::new(45000, 0)),
+            ..order(OrderSide::Buy, None, Decimal::new(2, 1))
+        };
+        assert!(reservation(&stop_market_buy, &pair).unwrap().all_available);
+
+        assert!(reservation(&order(OrderSide::Buy, None, Decimal::ZERO), &pair).is_none());
+    }
+}
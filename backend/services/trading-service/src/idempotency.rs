@@ -0,0 +1,219 @@
+//! Idempotent order placement
+//!
+//! A client that times out placing an order cannot tell whether it was
+//! accepted. Sending the same `Idempotency-Key` header (or, without one, the
+//! same `client_order_id`) on the retry returns the outcome of the first
+//! request instead of placing a second order. Outcomes are kept per user for
+//! `COMPLETED_TTL`, in Redis when the service runs with `REDIS_URL` so every
+//! instance sees them, otherwise in memory.
+//!
+//! A key is claimed before the order is placed, so a retry racing the
+//! original request is refused rather than placed twice. Reusing a key for a
+//! different request is refused as well.
+
+use flowex_cache::{CacheError, CacheManager};
+use flowex_matching_engine::ExecutionReport;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use tracing::debug;
+use uuid::Uuid;
+
+/// Header carrying the client's idempotency key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Longest idempotency key accepted
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// How long the outcome of a request is replayed to retries
+const COMPLETED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long a claim waits for its request to complete; a request that dies
+/// mid-flight frees its key after this
+const PENDING_TTL: Duration = Duration::from_secs(30);
+
+/// Redis key prefix of idempotency records
+const KEY_PREFIX: &str = "flowex:trading:idempotency";
+
+/// State of a request under an idempotency key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum IdempotencyRecord {
+    /// The first request is still being processed
+    Pending { fingerprint: String },
+    /// The first request completed with `status`, and `report` if it succeeded
+    Completed {
+        fingerprint: String,
+        status: u16,
+        report: Option<Box<ExecutionReport>>,
+    },
+}
+
+impl IdempotencyRecord {
+    fn fingerprint(&self) -> &str {
+        match self {
+            IdempotencyRecord::Pending { fingerprint } | IdempotencyRecord::Completed { fingerprint, .. } => fingerprint,
+        }
+    }
+}
+
+/// What to do with a request carrying an idempotency key
+#[derive(Debug)]
+pub enum Claim {
+    /// The key is new; process the request, then `complete` or `abandon` it
+    Acquired,
+    /// The first request under the key is still in flight
+    InProgress,
+    /// The key was used for a different request
+    Mismatch,
+    /// The first request completed; replay its outcome
+    Completed { status: u16, report: Option<Box<ExecutionReport>> },
+}
+
+/// Outcomes of keyed order requests
+#[derive(Clone)]
+pub enum IdempotencyCache {
+    Memory(Arc<RwLock<HashMap<String, (IdempotencyRecord, Instant)>>>),
+    Redis(Box<CacheManager>),
+}
+
+impl IdempotencyCache {
+    /// Cache local to this instance
+    pub fn in_memory() -> Self {
+        IdempotencyCache::Memory(Arc::new(RwLock::new(HashMap::new())))
+    }
+
+    /// Cache shared by every instance through Redis
+    pub async fn connect(redis_url: &str) -> Result<Self, CacheError> {
+        Ok(IdempotencyCache::Redis(Box::new(CacheManager::new(redis_url, COMPLETED_TTL).await?)))
+    }
+
+    /// Claim `key` for a request of `user_id` whose content hashes to
+    /// `fingerprint`
+    pub async fn claim(&self, user_id: Uuid, key: &str, fingerprint: &str) -> Result<Claim, CacheError> {
+        let cache_key = cache_key(user_id, key);
+        let pending = IdempotencyRecord::Pending {
+            fingerprint: fingerprint.to_string(),
+        };
+
+        let existing = match self {
+            IdempotencyCache::Memory(records) => {
+                let mut records = records.write().await;
+                let now = Instant::now();
+                records.retain(|_, (_, expires_at)| *expires_at > now);
+                match records.get(&cache_key) {
+                    Some((record, _)) => Some(record.clone()),
+                    None => {
+                        records.insert(cache_key, (pending, now + PENDING_TTL));
+                        None
+                    }
+                }
+            }
+            IdempotencyCache::Redis(cache) => {
+                if cache.set_if_absent(&cache_key, &pending, Some(PENDING_TTL)).await? {
+                    None
+                } else {
+                    // The record may expire between the two calls; a retry
+                    // then claims the key afresh
+                    cache.get::<IdempotencyRecord>(&cache_key).await?
+                }
+            }
+        };
+
+        let claim = match existing {
+            None => Claim::Acquired,
+            Some(record) if record.fingerprint() != fingerprint => Claim::Mismatch,
+            Some(IdempotencyRecord::Pending { .. }) => Claim::InProgress,
+            Some(IdempotencyRecord::Completed { status, report, .. }) => Claim::Completed { status, report },
+        };
+        debug!("Idempotency key {} of user {}: {:?}", key, user_id, claim);
+        Ok(claim)
+    }
+
+    /// Record the outcome of the request that claimed `key`
+    pub async fn complete(
+        &self,
+        user_id: Uuid,
+        key: &str,
+        fingerprint: &str,
+        status: u16,
+        report: Option<Box<ExecutionReport>>,
+    ) -> Result<(), CacheError> {
+        let cache_key = cache_key(user_id, key);
+        let record = IdempotencyRecord::Completed {
+            fingerprint: fingerprint.to_string(),
+            status,
+            report,
+        };
+        match self {
+            IdempotencyCache::Memory(records) => {
+                records
+                    .write()
+                    .await
+                    .insert(cache_key, (record, Instant::now() + COMPLETED_TTL));
+                Ok(())
+            }
+            IdempotencyCache::Redis(cache) => cache.set(&cache_key, &record, Some(COMPLETED_TTL)).await,
+        }
+    }
+
+    /// Free `key` after its request failed in a way a retry may not, so the
+    /// retry is processed afresh
+    pub async fn abandon(&self, user_id: Uuid, key: &str) -> Result<(), CacheError> {
+        let cache_key = cache_key(user_id, key);
+        match self {
+            IdempotencyCache::Memory(records) => {
+                records.write().await.remove(&cache_key);
+                Ok(())
+            }
+            IdempotencyCache::Redis(cache) => cache.delete(&cache_key).await.map(|_| ()),
+        }
+    }
+}
+
+/// Hash of a request's content, telling a retry from a reused key
+pub fn fingerprint<T: Serialize>(request: &T) -> String {
+    let body = serde_json::to_vec(request).unwrap_or_default();
+    hex::encode(Sha256::digest(body))
+}
+
+fn cache_key(user_id: Uuid, key: &str) -> String {
+    format!("{}:{}:{}", KEY_PREFIX, user_id, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：幂等键首次占用后，重试在处理中被拒绝、完成后重放结果，键被复用于不同请求时拒绝
+    #[tokio::test]
+    async fn test_claim_lifecycle() {
+        let cache = IdempotencyCache::in_memory();
+        let user_id = Uuid::new_v4();
+
+        assert!(matches!(cache.claim(user_id, "retry-1", "a").await.unwrap(), Claim::Acquired));
+        assert!(matches!(cache.claim(user_id, "retry-1", "a").await.unwrap(), Claim::InProgress));
+        assert!(matches!(cache.claim(user_id, "retry-1", "b").await.unwrap(), Claim::Mismatch));
+        // 不同用户的同名键互不影响
+        assert!(matches!(cache.claim(Uuid::new_v4(), "retry-1", "a").await.unwrap(), Claim::Acquired));
+
+        cache.complete(user_id, "retry-1", "a", 400, None).await.unwrap();
+        match cache.claim(user_id, "retry-1", "a").await.unwrap() {
+            Claim::Completed { status, report } => {
+                assert_eq!(status, 400);
+                assert!(report.is_none());
+            }
+            claim => panic!("unexpected claim {:?}", claim),
+        }
+
+        // 放弃的键可被重新占用
+        assert!(matches!(cache.claim(user_id, "retry-2", "a").await.unwrap(), Claim::Acquired));
+        cache.abandon(user_id, "retry-2").await.unwrap();
+        assert!(matches!(cache.claim(user_id, "retry-2", "a").await.unwrap(), Claim::Acquired));
+    }
+}
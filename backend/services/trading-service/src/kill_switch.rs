@@ -0,0 +1,123 @@
+//! Per-user kill switch
+//!
+//! Pulling the kill switch cancels every resting order of a user across all
+//! symbols and can also block them from placing or modifying orders until
+//! the switch is reset, so an API trader whose strategy misbehaves can stop
+//! it in one call. A block set by an admin can only be lifted by an admin.
+
+use chrono::{DateTime, Utc};
+use flowex_types::Order;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::RwLock;
+use tracing::info;
+use uuid::Uuid;
+
+/// A user's block on new orders
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradingBlock {
+    pub user_id: Uuid,
+    /// Who pulled the switch
+    pub blocked_by: Uuid,
+    /// Whether an admin pulled it, so only an admin may reset it
+    pub by_admin: bool,
+    pub blocked_at: DateTime<Utc>,
+}
+
+/// Kill switch request
+#[derive(Debug, Deserialize)]
+pub struct KillSwitchRequest {
+    /// Also block new orders until the switch is reset
+    #[serde(default)]
+    pub block: bool,
+    /// User whose orders to cancel, for admins; defaults to the caller
+    pub user_id: Option<Uuid>,
+}
+
+/// Orders cancelled by the kill switch and the block it set, if any
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KillSwitchResponse {
+    pub cancelled: Vec<Order>,
+    pub block: Option<TradingBlock>,
+}
+
+/// Why a block could not be lifted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetError {
+    NotBlocked,
+    /// The block was set by an admin and the caller is not one
+    AdminBlock,
+}
+
+/// Users blocked from placing orders
+#[derive(Clone, Default)]
+pub struct KillSwitch {
+    blocks: Arc<RwLock<HashMap<Uuid, TradingBlock>>>,
+}
+
+impl KillSwitch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block `user_id` from placing orders. An admin's block replaces the
+    /// user's own.
+    pub async fn block(&self, user_id: Uuid, blocked_by: Uuid, by_admin: bool) -> TradingBlock {
+        let mut blocks = self.blocks.write().await;
+        if let Some(existing) = blocks.get(&user_id).filter(|block| block.by_admin || !by_admin) {
+            return existing.clone();
+        }
+
+        let block = TradingBlock {
+            user_id,
+            blocked_by,
+            by_admin,
+            blocked_at: Utc::now(),
+        };
+        info!("Order placement blocked for user {} by {}", user_id, blocked_by);
+        blocks.insert(user_id, block.clone());
+        block
+    }
+
+    /// Lift the block of `user_id`
+    pub async fn reset(&self, user_id: Uuid, by_admin: bool) -> Result<TradingBlock, ResetError> {
+        let mut blocks = self.blocks.write().await;
+        match blocks.get(&user_id) {
+            None => Err(ResetError::NotBlocked),
+            Some(block) if block.by_admin && !by_admin => Err(ResetError::AdminBlock),
+            Some(_) => {
+                info!("Order placement re-enabled for user {}", user_id);
+                Ok(blocks.remove(&user_id).expect("block checked above"))
+            }
+        }
+    }
+
+    /// The block of `user_id`, if any
+    pub async fn block_of(&self, user_id: Uuid) -> Option<TradingBlock> {
+        self.blocks.read().await.get(&user_id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：用户可解除自己的禁止下单状态，管理员设置的只能由管理员解除
+    #[tokio::test]
+    async fn test_block_and_reset() {
+        let kill_switch = KillSwitch::new();
+        let (user_id, admin_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert_eq!(kill_switch.reset(user_id, false).await.unwrap_err(), ResetError::NotBlocked);
+        kill_switch.block(user_id, user_id, false).await;
+        assert!(kill_switch.block_of(user_id).await.is_some());
+        kill_switch.reset(user_id, false).await.unwrap();
+        assert!(kill_switch.block_of(user_id).await.is_none());
+
+        kill_switch.block(user_id, admin_id, true).await;
+        // 用户自己的禁止不会覆盖管理员的禁止
+        assert!(kill_switch.block(user_id, user_id, false).await.by_admin);
+        assert_eq!(kill_switch.reset(user_id, false).await.unwrap_err(), ResetError::AdminBlock);
+        assert_eq!(kill_switch.reset(user_id, true).await.unwrap().blocked_by, admin_id);
+    }
+}
@@ -0,0 +1,361 @@
+//! Per-user order limits
+//!
+//! Caps how many orders a user may have open and the notional their open
+//! orders may commit on any one symbol, checked when an order is placed or
+//! enlarged, before it reaches the wallet or the matching engine. Every
+//! account gets the default limits, read from `ORDER_LIMIT_MAX_OPEN_ORDERS`
+//! and `ORDER_LIMIT_MAX_SYMBOL_EXPOSURE`, unless an admin has set an
+//! override for it. A lower exposure cap, such as the one the user's KYC
+//! level allows, takes precedence over either.
+//!
+//! Overrides are written through to the service's `Store` and read back on
+//! startup. A user's orders are checked and placed one at a time, under the
+//! lock `lock_user` hands out, so two concurrent orders cannot both pass
+//! against the same open orders.
+
+use crate::store::Store;
+use flowex_types::{FlowExError, FlowExResult, Order, OrderType};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{OwnedMutexGuard, RwLock};
+use tracing::info;
+use uuid::Uuid;
+
+/// Limits on one account's open orders
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderLimits {
+    /// Orders the account may have open across all symbols
+    pub max_open_orders: usize,
+    /// Notional, in the quote asset, the account's open orders may commit
+    /// on one symbol
+    pub max_symbol_exposure: Decimal,
+}
+
+impl Default for OrderLimits {
+    fn default() -> Self {
+        Self {
+            max_open_orders: 200,
+            max_symbol_exposure: Decimal::new(1_000_000, 0),
+        }
+    }
+}
+
+impl OrderLimits {
+    /// Defaults, overridden by `ORDER_LIMIT_MAX_OPEN_ORDERS` and
+    /// `ORDER_LIMIT_MAX_SYMBOL_EXPOSURE`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_open_orders: std::env::var("ORDER_LIMIT_MAX_OPEN_ORDERS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(defaults.max_open_orders),
+            max_symbol_exposure: std::env::var("ORDER_LIMIT_MAX_SYMBOL_EXPOSURE")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(defaults.max_symbol_exposure),
+        }
+    }
+
+    fn validate(&self) -> FlowExResult<()> {
+        if self.max_open_orders == 0 || self.max_symbol_exposure <= Decimal::ZERO {
+            return Err(FlowExError::Validation("Order limits must be positive".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Limits in force for an account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountLimits {
+    pub user_id: Uuid,
+    pub limits: OrderLimits,
+    /// Whether an admin set these limits rather than the defaults applying
+    pub overridden: bool,
+}
+
+/// Limit an order would break
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitViolation {
+    OpenOrders { limit: usize },
+    SymbolExposure { symbol: String, exposure: Decimal, limit: Decimal },
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitViolation::OpenOrders { limit } => write!(f, "more than {} open orders", limit),
+            LimitViolation::SymbolExposure { symbol, exposure, limit } => {
+                write!(f, "exposure of {} on {} above the limit of {}", exposure, symbol, limit)
+            }
+        }
+    }
+}
+
+/// Default order limits and the overrides set for individual accounts,
+/// cached in memory and persisted to a `Store`
+#[derive(Clone)]
+pub struct OrderLimitRegistry {
+    defaults: OrderLimits,
+    overrides: Arc<RwLock<HashMap<Uuid, OrderLimits>>>,
+    store: Store,
+    /// Placement locks of the users with an order being placed
+    user_locks: Arc<Mutex<HashMap<Uuid, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl OrderLimitRegistry {
+    /// In-memory registry applying `defaults` to every account
+    pub fn new(defaults: OrderLimits) -> Self {
+        Self::from_overrides(Store::in_memory(), HashMap::new(), defaults)
+    }
+
+    /// Registry persisted to `store`, holding the overrides already in it
+    pub async fn load(store: Store, defaults: OrderLimits) -> FlowExResult<Self> {
+        let overrides: HashMap<Uuid, OrderLimits> = store.order_limit_overrides().await?.into_iter().collect();
+        info!("Loaded {} order limit overrides", overrides.len());
+        Ok(Self::from_overrides(store, overrides, defaults))
+    }
+
+    fn from_overrides(store: Store, overrides: HashMap<Uuid, OrderLimits>, defaults: OrderLimits) -> Self {
+        Self {
+            defaults,
+            overrides: Arc::new(RwLock::new(overrides)),
+            store,
+            user_locks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Wait for `user_id`'s other order placements to finish; hold the
+    /// guard from the limit check until the order is recorded
+    pub async fn lock_user(&self, user_id: Uuid) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.user_locks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            // Drop the locks nobody holds or waits for
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(user_id).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+
+    /// Limits in force for `user_id`
+    pub async fn limits(&self, user_id: Uuid) -> AccountLimits {
+        match self.overrides.read().await.get(&user_id) {
+            Some(limits) => AccountLimits {
+                user_id,
+                limits: limits.clone(),
+                overridden: true,
+            },
+            None => AccountLimits {
+                user_id,
+                limits: self.defaults.clone(),
+                overridden: false,
+            },
+        }
+    }
+
+    /// Give `user_id` its own limits in place of the defaults
+    pub async fn set_override(&self, user_id: Uuid, limits: OrderLimits) -> FlowExResult<AccountLimits> {
+        limits.validate()?;
+        let mut overrides = self.overrides.write().await;
+        self.store.save_order_limit_override(user_id, &limits).await?;
+        overrides.insert(user_id, limits.clone());
+        info!("Order limits of user {} set to {:?}", user_id, limits);
+        Ok(AccountLimits {
+            user_id,
+            limits,
+            overridden: true,
+        })
+    }
+
+    /// Return `user_id` to the default limits; false if it had no override
+    pub async fn remove_override(&self, user_id: Uuid) -> FlowExResult<bool> {
+        let mut overrides = self.overrides.write().await;
+        if !overrides.contains_key(&user_id) {
+            return Ok(false);
+        }
+        self.store.delete_order_limit_override(user_id).await?;
+        overrides.remove(&user_id);
+        Ok(true)
+    }
+
+    /// Check that `order` fits its owner's limits alongside their
+    /// `open_orders`, which may include an earlier version of it.
+    /// `reference_price` prices an order without a limit or stop price;
+    /// `exposure_cap` lowers the exposure limit further.
+    pub async fn check(
+        &self,
+        order: &Order,
+        open_orders: &[Order],
+        reference_price: Option<Decimal>,
+        exposure_cap: Option<Decimal>,
+    ) -> Result<(), LimitViolation> {
+        let limits = self.limits(order.user_id).await.limits;
+        let max_symbol_exposure = exposure_cap.map_or(limits.max_symbol_exposure, |cap| cap.min(limits.max_symbol_exposure));
+        let others = || open_orders.iter().filter(|open| open.id != order.id);
+
+        // Only an order that may rest adds to the open orders, so a user at
+        // the limit can still trade out of positions
+        let may_rest = order.order_type != OrderType::Market && order.time_in_force.is_resting();
+        let replaces_open_order = others().count() < open_orders.len();
+        if may_rest && !replaces_open_order && open_orders.len() >= limits.max_open_orders {
+            return Err(LimitViolation::OpenOrders {
+                limit: limits.max_open_orders,
+            });
+        }
+
+        let exposure = others()
+            .filter(|open| open.trading_pair == order.trading_pair)
+            .map(|open| notional(open, None))
+            .sum::<Decimal>()
+            + notional(order, reference_price);
+        if exposure > max_symbol_exposure {
+            return Err(LimitViolation::SymbolExposure {
+                symbol: order.trading_pair.clone(),
+                exposure,
+                limit: max_symbol_exposure,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Notional `order` still commits, at its limit or stop price, else at
+/// `reference_price`
+fn notional(order: &Order, reference_price: Option<Decimal>) -> Decimal {
+    order
+        .price
+        .or(order.stop_price)
+        .or(reference_price)
+        .map(|price| price * order.remaining_quantity)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::{create_limit_order, create_market_order};
+    use flowex_types::OrderSide;
+
+    fn order(user_id: Uuid, symbol: &str, price: Option<i64>, quantity: i64) -> Order {
+        let order = match price {
+            Some(price) => create_limit_order(OrderSide::Buy, price, quantity),
+            None => create_market_order(OrderSide::Buy, quantity),
+        };
+        Order { user_id, trading_pair: symbol.to_string(), ..order }
+    }
+
+    /// 测试：挂单数量和单交易对名义敞口超限被拒绝，修改订单时不重复计算原订单
+    #[tokio::test]
+    async fn test_open_order_and_exposure_limits() {
+        let registry = OrderLimitRegistry::new(OrderLimits {
+            max_open_orders: 2,
+            max_symbol_exposure: Decimal::new(10_000, 0),
+        });
+        let user_id = Uuid::new_v4();
+        let open = vec![order(user_id, "BTCUSDT", Some(4000), 2), order(user_id, "ETHUSDT", Some(3000), 1)];
+
+        let violation = registry.check(&order(user_id, "ETHUSDT", Some(1), 1), &open, None, None).await.unwrap_err();
+        assert_eq!(violation, LimitViolation::OpenOrders { limit: 2 });
+        // 不会挂单的市价单不受挂单数量限制
+        registry.check(&order(user_id, "ETHUSDT", None, 1), &open, Some(Decimal::ONE), None).await.unwrap();
+
+        // 修改已有挂单：8000 -> 10000 在限额内，-> 12000 超限
+        let mut modified = open[0].clone();
+        modified.remaining_quantity = Decimal::new(25, 1);
+        registry.check(&modified, &open, None, None).await.unwrap();
+        modified.remaining_quantity = Decimal::new(3, 0);
+        assert!(matches!(
+            registry.check(&modified, &open, None, None).await,
+            Err(LimitViolation::SymbolExposure { exposure, .. }) if exposure == Decimal::new(12_000, 0)
+        ));
+
+        // 市价单按参考价格计算敞口
+        let market = order(user_id, "BTCUSDT", None, 1);
+        let open = vec![open[0].clone()];
+        registry.check(&market, &open, Some(Decimal::new(2000, 0)), None).await.unwrap();
+        assert!(registry.check(&market, &open, Some(Decimal::new(2001, 0)), None).await.is_err());
+
+        // 更低的敞口上限（如 KYC 级别的上限）优先生效，更高的上限不放宽限额
+        let cap = Some(Decimal::new(9_000, 0));
+        assert!(registry.check(&market, &open, Some(Decimal::new(1000, 0)), cap).await.is_ok());
+        assert!(matches!(
+            registry.check(&market, &open, Some(Decimal::new(1001, 0)), cap).await,
+            Err(LimitViolation::SymbolExposure { limit, .. }) if limit == Decimal::new(9_000, 0)
+        ));
+        let higher = Some(Decimal::new(1_000_000, 0));
+        assert!(registry.check(&market, &open, Some(Decimal::new(2001, 0)), higher).await.is_err());
+    }
+
+    /// 测试：管理员为账户设置的限额覆盖默认值，移除后恢复默认
+    #[tokio::test]
+    async fn test_overrides() {
+        let registry = OrderLimitRegistry::new(OrderLimits::default());
+        let user_id = Uuid::new_v4();
+        assert!(!registry.limits(user_id).await.overridden);
+
+        let limits = OrderLimits {
+            max_open_orders: 1000,
+            max_symbol_exposure: Decimal::new(50_000_000, 0),
+        };
+        registry.set_override(user_id, limits.clone()).await.unwrap();
+        assert_eq!(registry.limits(user_id).await.limits, limits);
+        assert!(!registry.limits(Uuid::new_v4()).await.overridden);
+
+        let invalid = OrderLimits {
+            max_open_orders: 0,
+            ..limits
+        };
+        assert!(registry.set_override(user_id, invalid).await.is_err());
+
+        assert!(registry.remove_override(user_id).await.unwrap());
+        assert!(!registry.remove_override(user_id).await.unwrap());
+        assert_eq!(registry.limits(user_id).await.limits, OrderLimits::default());
+    }
+
+    /// 测试：账户限额覆盖写入存储，重新加载后仍然生效，移除后不再恢复
+    #[tokio::test]
+    async fn test_overrides_survive_reload() {
+        let store = Store::in_memory();
+        let registry = OrderLimitRegistry::load(store.clone(), OrderLimits::default()).await.unwrap();
+        let (kept, removed) = (Uuid::new_v4(), Uuid::new_v4());
+        let limits = OrderLimits {
+            max_open_orders: 5,
+            max_symbol_exposure: Decimal::new(25_000, 0),
+        };
+        registry.set_override(kept, limits.clone()).await.unwrap();
+        registry.set_override(removed, limits.clone()).await.unwrap();
+        assert!(registry.remove_override(removed).await.unwrap());
+
+        let reloaded = OrderLimitRegistry::load(store, OrderLimits::default()).await.unwrap();
+        assert_eq!(reloaded.limits(kept).await.limits, limits);
+        assert!(reloaded.limits(kept).await.overridden);
+        assert!(!reloaded.limits(removed).await.overridden);
+    }
+
+    /// 测试：同一用户的下单锁互斥，不同用户互不阻塞，释放后锁被回收
+    #[tokio::test]
+    async fn test_user_lock_serializes_placements() {
+        let registry = OrderLimitRegistry::new(OrderLimits::default());
+        let (user_id, other) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let guard = registry.lock_user(user_id).await;
+        let waiting = tokio::spawn({
+            let registry = registry.clone();
+            async move { registry.lock_user(user_id).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        let other_guard = registry.lock_user(other).await;
+
+        drop(guard);
+        drop(waiting.await.unwrap());
+        drop(other_guard);
+        let _ = registry.lock_user(Uuid::new_v4()).await;
+        assert_eq!(registry.user_locks.lock().unwrap().len(), 1);
+    }
+}
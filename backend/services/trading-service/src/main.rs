@@ -2,98 +2,303 @@
 //!
 //! Enterprise-grade trading service providing order management, order book operations,
 //! and trade execution for the FlowEx cryptocurrency exchange platform.
+//!
+//! Orders are matched by one `flowex-matching-engine` actor per trading
+//! pair. Placing or modifying an order returns the trades it executed, and
+//! the orders it traded against are updated in the order store.
+//!
+//! Orders, executed trades and dead-lettered events are kept in PostgreSQL
+//! when `FLOWEX_DATABASE_URL` is set, otherwise in memory (see `store`). On
+//! startup each pair's book is rebuilt from the open orders stored, and the
+//! dead-letter queue from the events parked before the restart.
+//!
+//! Executed trades are kept for the public recent trades of each symbol and
+//! for each user's trade history, which lists their fills with fees and
+//! maker/taker role.
+//!
+//! With `WALLET_SERVICE_URL` set, an order is only matched once the wallet
+//! service has reserved the funds it may spend; its trades are settled and
+//! its unspent funds released through the wallet (see `funds`).
+//!
+//! Order placement honours an `Idempotency-Key` header, replaying the
+//! outcome of the first request to its retries (see `idempotency`).
+//!
+//! `POST /api/trading/killswitch` cancels all of a user's resting orders
+//! and can block them from placing new ones until the switch is reset (see
+//! `kill_switch`).
+//!
+//! Each user may only have so many orders open, committing so much notional
+//! per symbol; admins can set limits for individual accounts (see
+//! `limits`). The user's KYC level, looked up at the auth service, may
+//! forbid trading or cap the exposure further under the `flowex-kyc`
+//! policy, read from `KYC_POLICY_PATH` unless the default applies.
+//!
+//! Before an order reaches its engine it runs through the `flowex-risk`
+//! pre-trade checks, priced against the symbol's last trade; the checks
+//! run are read from `RISK_CONFIG_PATH`, and admins manage the blacklist
+//! and per-user symbol restrictions and read the checks' audit records.
+//!
+//! Trades are charged maker and taker fees from `flowex-fees` tiers, by each
+//! user's 30-day volume; `GET /api/trading/fees` lists a user's rates. A fee
+//! schedule other than the default is read from `FEE_SCHEDULE_PATH`.
+//!
+//! Placing, modifying and mass cancelling orders spend weighted per-user
+//! and per-symbol budgets; a request over budget gets HTTP 429 (see
+//! `rate_limit`).
+//!
+//! Every order, user stream, webhook and admin endpoint requires a JWT bearer
+//! token, checked by the shared `jwt_auth_middleware`, or a request signed
+//! with an API key the auth service issued (at `AUTH_SERVICE_URL`), checked
+//! by `api_key_auth_middleware`: reads need the key's `trading:read` scope
+//! and anything else `trading:write`. Users only see and change their own
+//! orders.
+//!
+//! Authenticated users stream their own order, balance and fill events over
+//! the `/api/trading/ws` WebSocket. Every order placed, matched, modified,
+//! cancelled or expired reaches its owner as an `OrderUpdate`, every executed
+//! trade reaches both its maker and taker as a fill through
+//! `WebSocketManager::publish_fills`, and the balances the wallet service
+//! reports changed by a reservation, settlement or release follow as
+//! `BalanceUpdate`s. Fills, cancellations and expiries are also posted to
+//! the webhooks their owner registered (see `webhooks`).
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
-    routing::{get, post},
+    extract::{ws::WebSocketUpgrade, Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post, put},
     Router,
 };
+use flowex_fees::{FeeConfig, FeeManager, UserFeeRates};
+use flowex_middleware::api_key::{api_key_auth_middleware, ApiKeyVerifier};
+use flowex_middleware::service_auth::service_secret;
+use flowex_matching_engine::actor::{ActorConfig, MatchingEngineHandle};
+use flowex_matching_engine::{ExecutionReport, MatchingEngine};
 use flowex_types::{
-    ApiResponse, CreateOrderRequest, FlowExError, FlowExResult, HealthResponse, Order,
-    OrderBook, OrderBookLevel, OrderSide, OrderStatus, OrderType, TradingPair, TradingStatus,
+    ApiResponse, AuthContext, BalanceChange, CancelOrdersFilter, CreateOrderRequest, Fill, FlowExError, FlowExResult,
+    HealthResponse, ModifyOrderRequest, Order, OrderBook, OrderHistoryQuery, OrderSide, OrderStatus, OrderType,
+    Page, Permission, Role, Trade, TradeHistoryQuery, TradeSettlement, TradingPair, TradingStatus,
 };
+use flowex_risk::{RiskAuditRecord, RiskConfig, RiskEngine};
+use flowex_kyc::{KycGate, KycPolicy};
+use flowex_websocket::{WebSocketManager, WsMessage};
+use funds::WalletClient;
+use rate_limit::{RateLimitConfig, RateLimiter, CANCEL_ORDERS_WEIGHT, MODIFY_ORDER_WEIGHT, PLACE_ORDER_WEIGHT};
+use kill_switch::{KillSwitch, KillSwitchRequest, KillSwitchResponse, ResetError, TradingBlock};
+use limits::{AccountLimits, OrderLimitRegistry, OrderLimits};
+use idempotency::{Claim, IdempotencyCache, IDEMPOTENCY_KEY_HEADER, MAX_IDEMPOTENCY_KEY_LEN};
+use dlq::{DeadLetter, DeadLetterQueue, DeadLetterStats, DeadLetterStatus, HandlerKind};
 use rust_decimal::Decimal;
-use std::{collections::HashMap, str::FromStr, sync::Arc, time::SystemTime};
+use serde::Deserialize;
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::{Duration, SystemTime}};
 use tokio::sync::RwLock;
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
-use tracing::{info, warn};
+use store::Store;
+use tracing::{error, info, warn};
 use uuid::Uuid;
+use webhooks::{CreateWebhookRequest, CreateWebhookResponse, Webhook, WebhookConfig, WebhookEventType, WebhookRegistry};
+
+mod dlq;
+mod funds;
+mod idempotency;
+mod kill_switch;
+mod limits;
+mod rate_limit;
+mod store;
+mod webhooks;
+
+/// Pending dead letters above which an alert is raised
+const DLQ_ALERT_THRESHOLD: usize = 100;
+
+/// Longest client order id accepted
+const MAX_CLIENT_ORDER_ID_LEN: usize = 64;
+
+/// How often good-till-date orders are checked for expiry
+const ORDER_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Price levels per side returned by the order book endpoint
+const ORDER_BOOK_DEPTH: usize = 100;
+
+/// Orders or fills per page of history unless the request sets a limit
+const DEFAULT_HISTORY_PAGE_LIMIT: usize = 50;
+
+/// Largest page of order or trade history a request may ask for
+const MAX_HISTORY_PAGE_LIMIT: usize = 500;
+
+/// Recent trades returned unless the request sets a limit
+const DEFAULT_RECENT_TRADES: usize = 100;
+
+/// Most recent trades a request may ask for
+const MAX_RECENT_TRADES: usize = 1000;
+
+/// Risk audit records returned unless the request sets a limit
+const DEFAULT_RISK_AUDIT_LIMIT: usize = 100;
+
+/// Concurrent user stream connections accepted
+const MAX_USER_STREAM_CONNECTIONS: usize = 10_000;
 
 /// Application state for the trading service
 #[derive(Clone)]
 pub struct AppState {
     pub trading_pairs: Arc<RwLock<HashMap<String, TradingPair>>>,
-    pub orders: Arc<RwLock<HashMap<Uuid, Order>>>,
-    pub order_books: Arc<RwLock<HashMap<String, OrderBook>>>,
+    /// Orders and executed trades
+    pub store: Store,
+    /// Matching engine of each trading pair, by symbol
+    pub engines: Arc<HashMap<String, MatchingEngineHandle>>,
+    pub webhooks: WebhookRegistry,
+    pub dead_letters: DeadLetterQueue,
+    /// Outcomes of order requests sent with an idempotency key
+    pub idempotency: IdempotencyCache,
+    /// Order request budgets per user and per symbol
+    pub rate_limiter: RateLimiter,
+    /// Open order and exposure limits of each account
+    pub order_limits: OrderLimitRegistry,
+    /// Users blocked from placing orders by the kill switch
+    pub kill_switch: KillSwitch,
+    /// Pre-trade checks every order runs through
+    pub risk: RiskEngine,
+    /// Wallet service reserving and settling order funds, if configured
+    pub wallet: Option<WalletClient>,
+    /// Fee schedule of every pair and the trading volumes it is priced on
+    pub fees: FeeManager,
+    /// Private order, balance and fill streams of connected users
+    pub websocket: WebSocketManager,
+    /// Verifies requests signed with API keys against the auth service
+    pub api_keys: ApiKeyVerifier,
+    /// What users may trade by their KYC level
+    pub kyc: KycGate,
     pub start_time: SystemTime,
 }
 
 impl AppState {
+    /// State with an in-memory store
     pub fn new() -> Self {
-        let mut trading_pairs = HashMap::new();
-        let mut order_books = HashMap::new();
+        let trading_pairs = demo_trading_pairs();
+        let fees = FeeManager::new(FeeConfig::default());
+        let engines = spawn_engines(&trading_pairs, &fees);
+        let dead_letters = DeadLetterQueue::new(DLQ_ALERT_THRESHOLD);
+        let webhooks = WebhookRegistry::new(WebhookConfig::default(), dead_letters.clone());
+        let order_limits = OrderLimitRegistry::new(OrderLimits::from_env());
+        Self::from_parts(trading_pairs, engines, Store::in_memory(), fees, dead_letters, webhooks, order_limits)
+    }
 
-        // Initialize demo trading pairs
-        let btc_usdt = TradingPair {
-            symbol: "BTC-USDT".to_string(),
-            base_asset: "BTC".to_string(),
-            quote_asset: "USDT".to_string(),
-            status: TradingStatus::Trading,
-            min_price: Decimal::new(1, 2), // 0.01
-            max_price: Decimal::new(10000000, 0), // 10M
-            min_qty: Decimal::new(1, 8), // 0.00000001
-            max_qty: Decimal::new(1000000, 0), // 1M
-            step_size: Decimal::new(1, 8),
-            tick_size: Decimal::new(1, 2),
-        };
+    /// State backed by `store`, with each pair's book rebuilt from the open
+    /// orders in it, users' fee tiers from its trades of the last 30 days,
+    /// the dead-letter queue from the entries parked in it, and the webhooks
+    /// and order limit overrides kept in it
+    pub async fn with_store(store: Store) -> FlowExResult<Self> {
+        let trading_pairs = demo_trading_pairs();
+        let fees = FeeManager::new(FeeConfig::default());
+        let since = chrono::Utc::now() - chrono::Duration::days(flowex_fees::VOLUME_WINDOW_DAYS);
+        for (user_id, date, volume) in store.daily_volumes(since).await? {
+            fees.record_volume(user_id, date, volume);
+        }
 
-        let eth_usdt = TradingPair {
-            symbol: "ETH-USDT".to_string(),
-            base_asset: "ETH".to_string(),
-            quote_asset: "USDT".to_string(),
-            status: TradingStatus::Trading,
-            min_price: Decimal::new(1, 2),
-            max_price: Decimal::new(1000000, 0),
-            min_qty: Decimal::new(1, 8),
-            max_qty: Decimal::new(1000000, 0),
-            step_size: Decimal::new(1, 8),
-            tick_size: Decimal::new(1, 2),
-        };
-
-        // Initialize order books
-        let btc_order_book = OrderBook {
-            symbol: "BTC-USDT".to_string(),
-            bids: vec![
-                OrderBookLevel {
-                    price: Decimal::new(4499999, 2), // 44999.99
-                    quantity: Decimal::new(12345, 5), // 0.12345
-                },
-            ],
-            asks: vec![
-                OrderBookLevel {
-                    price: Decimal::new(4500001, 2), // 45000.01
-                    quantity: Decimal::new(11111, 5),
-                },
-            ],
-            timestamp: chrono::Utc::now(),
-        };
-
-        trading_pairs.insert("BTC-USDT".to_string(), btc_usdt);
-        trading_pairs.insert("ETH-USDT".to_string(), eth_usdt);
-        order_books.insert("BTC-USDT".to_string(), btc_order_book);
+        let mut engines = HashMap::new();
+        for (symbol, pair) in &trading_pairs {
+            let engine = store.restore_engine(pair.clone()).await?;
+            engines.insert(symbol.clone(), spawn_engine(engine, &fees));
+        }
+        let dead_letters = DeadLetterQueue::load(store.clone(), DLQ_ALERT_THRESHOLD).await?;
+        let webhooks = WebhookRegistry::load(store.clone(), WebhookConfig::default(), dead_letters.clone()).await?;
+        let order_limits = OrderLimitRegistry::load(store.clone(), OrderLimits::from_env()).await?;
+        Ok(Self::from_parts(trading_pairs, engines, store, fees, dead_letters, webhooks, order_limits))
+    }
+
+    fn from_parts(
+        trading_pairs: HashMap<String, TradingPair>,
+        engines: HashMap<String, MatchingEngineHandle>,
+        store: Store,
+        fees: FeeManager,
+        dead_letters: DeadLetterQueue,
+        webhooks: WebhookRegistry,
+        order_limits: OrderLimitRegistry,
+    ) -> Self {
+        let wallet = std::env::var("WALLET_SERVICE_URL")
+            .ok()
+            .map(|url| WalletClient::new(&url, service_secret(funds::SERVICE_NAME), dead_letters.clone()));
+        let auth_service_url =
+            std::env::var("AUTH_SERVICE_URL").unwrap_or_else(|_| "http://localhost:8001".to_string());
 
         Self {
+            engines: Arc::new(engines),
             trading_pairs: Arc::new(RwLock::new(trading_pairs)),
-            orders: Arc::new(RwLock::new(HashMap::new())),
-            order_books: Arc::new(RwLock::new(order_books)),
+            store,
+            webhooks,
+            dead_letters,
+            idempotency: IdempotencyCache::in_memory(),
+            rate_limiter: RateLimiter::in_memory(RateLimitConfig::from_env()),
+            order_limits,
+            kill_switch: KillSwitch::new(),
+            risk: RiskEngine::new(RiskConfig::default()),
+            wallet,
+            fees,
+            websocket: WebSocketManager::new(MAX_USER_STREAM_CONNECTIONS),
+            api_keys: ApiKeyVerifier::new(&auth_service_url, Permission::TradingRead, Permission::TradingWrite),
+            kyc: KycGate::new(&auth_service_url, KycPolicy::default()),
             start_time: SystemTime::now(),
         }
     }
 }
 
+/// Trading pairs listed by the service
+fn demo_trading_pairs() -> HashMap<String, TradingPair> {
+    let mut trading_pairs = HashMap::new();
+
+    // Initialize demo trading pairs
+    let btc_usdt = TradingPair {
+        symbol: "BTC-USDT".to_string(),
+        base_asset: "BTC".to_string(),
+        quote_asset: "USDT".to_string(),
+        status: TradingStatus::Trading,
+        min_price: Decimal::new(1, 2), // 0.01
+        max_price: Decimal::new(10000000, 0), // 10M
+        min_qty: Decimal::new(1, 8), // 0.00000001
+        max_qty: Decimal::new(1000000, 0), // 1M
+        step_size: Decimal::new(1, 8),
+        tick_size: Decimal::new(1, 2),
+        min_notional: Decimal::new(10, 0), // 10 USDT
+    };
+
+    let eth_usdt = TradingPair {
+        symbol: "ETH-USDT".to_string(),
+        base_asset: "ETH".to_string(),
+        quote_asset: "USDT".to_string(),
+        status: TradingStatus::Trading,
+        min_price: Decimal::new(1, 2),
+        max_price: Decimal::new(1000000, 0),
+        min_qty: Decimal::new(1, 8),
+        max_qty: Decimal::new(1000000, 0),
+        step_size: Decimal::new(1, 8),
+        tick_size: Decimal::new(1, 2),
+        min_notional: Decimal::new(10, 0), // 10 USDT
+    };
+
+    trading_pairs.insert("BTC-USDT".to_string(), btc_usdt);
+    trading_pairs.insert("ETH-USDT".to_string(), eth_usdt);
+    trading_pairs
+}
+
+/// Start an empty matching engine for each trading pair
+fn spawn_engines(trading_pairs: &HashMap<String, TradingPair>, fees: &FeeManager) -> HashMap<String, MatchingEngineHandle> {
+    trading_pairs
+        .iter()
+        .map(|(symbol, pair)| (symbol.clone(), spawn_engine(MatchingEngine::with_trading_pair(pair.clone()), fees)))
+        .collect()
+}
+
+/// Start `engine`, pricing its trades with its pair's fee schedule
+fn spawn_engine(mut engine: MatchingEngine, fees: &FeeManager) -> MatchingEngineHandle {
+    if let Some(trading_pair) = engine.trading_pair() {
+        let schedule = fees.schedule(trading_pair);
+        engine.set_fee_schedule(Some(schedule));
+    }
+    let (handle, _) = MatchingEngineHandle::spawn(engine, ActorConfig::default());
+    handle
+}
+
 /// Health check endpoint
 async fn health_check(State(state): State<AppState>) -> Json<HealthResponse> {
     let uptime = state.start_time.elapsed().unwrap_or_default().as_secs();
@@ -119,60 +324,1029 @@ async fn get_order_book(
     State(state): State<AppState>,
     Path(symbol): Path<String>,
 ) -> Result<Json<ApiResponse<OrderBook>>, StatusCode> {
-    let order_books = state.order_books.read().await;
-    
-    if let Some(order_book) = order_books.get(&symbol) {
-        Ok(Json(ApiResponse::success((*order_book).clone())))
-    } else {
-        Err(StatusCode::NOT_FOUND)
-    }
+    let engine = state.engines.get(&symbol).ok_or(StatusCode::NOT_FOUND)?;
+    let order_book = engine
+        .order_book(ORDER_BOOK_DEPTH)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(ApiResponse::success(order_book)))
 }
 
-/// Create a new order
+/// Create a new order and match it. A retry carrying the same
+/// `Idempotency-Key` header, or without one the same client order id, gets
+/// the outcome of the first request instead of placing another order.
 async fn create_order(
     State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    headers: HeaderMap,
     Json(request): Json<CreateOrderRequest>,
-) -> Result<Json<ApiResponse<Order>>, StatusCode> {
+) -> Result<Json<ApiResponse<ExecutionReport>>, Response> {
+    state
+        .rate_limiter
+        .check(auth.user_id, Some(&request.trading_pair), PLACE_ORDER_WEIGHT)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let report = place_order_once(&state, &auth, &headers, request)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    Ok(Json(ApiResponse::success(report)))
+}
+
+/// Place an order unless it is a retry under an idempotency key, in which
+/// case the first request's outcome is returned
+async fn place_order_once(
+    state: &AppState,
+    auth: &AuthContext,
+    headers: &HeaderMap,
+    request: CreateOrderRequest,
+) -> Result<ExecutionReport, StatusCode> {
+    let key = match headers.get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN)
+                .ok_or(StatusCode::BAD_REQUEST)?
+                .to_string(),
+        ),
+        None => request.client_order_id.clone(),
+    };
+    let Some(key) = key else {
+        return place_order(state, auth, request).await;
+    };
+
+    let user_id = auth.user_id;
+    let fingerprint = idempotency::fingerprint(&request);
+    match state.idempotency.claim(user_id, &key, &fingerprint).await {
+        Ok(Claim::Acquired) => {}
+        Ok(Claim::InProgress) => return Err(StatusCode::CONFLICT),
+        Ok(Claim::Mismatch) => {
+            warn!("Idempotency key {} of user {} reused for a different order", key, user_id);
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        Ok(Claim::Completed { status, report }) => {
+            info!("Order request is a retry under idempotency key {}", key);
+            return match report {
+                Some(report) => Ok(*report),
+                None => Err(StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)),
+            };
+        }
+        Err(e) => {
+            error!("Idempotency cache failed: {}", e);
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+
+    let result = place_order(state, auth, request).await;
+    // A rejection is final, but a server error may succeed when retried
+    let recorded = match &result {
+        Ok(report) => {
+            state
+                .idempotency
+                .complete(user_id, &key, &fingerprint, StatusCode::OK.as_u16(), Some(Box::new(report.clone())))
+                .await
+        }
+        Err(status) if status.is_client_error() => {
+            state.idempotency.complete(user_id, &key, &fingerprint, status.as_u16(), None).await
+        }
+        Err(_) => state.idempotency.abandon(user_id, &key).await,
+    };
+    if let Err(e) = recorded {
+        warn!("Failed to record the outcome under idempotency key {}: {}", key, e);
+    }
+    result
+}
+
+/// Validate an order, check it against its owner's limits, reserve its
+/// funds and match it
+async fn place_order(
+    state: &AppState,
+    auth: &AuthContext,
+    request: CreateOrderRequest,
+) -> Result<ExecutionReport, StatusCode> {
     info!("Creating order for trading pair: {}", request.trading_pair);
 
+    if request
+        .client_order_id
+        .as_ref()
+        .is_some_and(|id| id.is_empty() || id.len() > MAX_CLIENT_ORDER_ID_LEN)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user_id = auth.user_id;
+    // An unlisted pair is a malformed order, not a missing resource
+    let engine = state.engines.get(&request.trading_pair).ok_or(StatusCode::BAD_REQUEST)?;
+
+    // Reject dust orders below the pair's minimum notional
+    if let (Some(price), Some(trading_pair)) = (
+        request.price,
+        state.trading_pairs.read().await.get(&request.trading_pair),
+    ) {
+        if let Err(violation) = trading_pair.check_notional(price, request.quantity) {
+            warn!("Rejected order for {}: {}", request.trading_pair, violation);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
     // Create new order
     let order = Order {
         id: Uuid::new_v4(),
-        user_id: Uuid::new_v4(), // In real implementation, extract from JWT
+        user_id,
         trading_pair: request.trading_pair,
         side: request.side,
         order_type: request.order_type,
         price: request.price,
+        stop_price: request.stop_price,
         quantity: request.quantity,
         filled_quantity: Decimal::ZERO,
+        remaining_quantity: request.quantity,
+        time_in_force: request.time_in_force,
+        post_only: request.post_only,
+        display_quantity: request.display_quantity,
+        expires_at: request.expires_at,
+        reduce_only: request.reduce_only,
+        client_order_id: request.client_order_id,
         status: OrderStatus::New,
         created_at: chrono::Utc::now(),
         updated_at: chrono::Utc::now(),
     };
 
-    // Store order
-    let mut orders = state.orders.write().await;
-    orders.insert(order.id, order.clone());
+    ensure_trading_enabled(state, order.user_id).await?;
+    check_risk(state, &order).await?;
+    // Held until the order is recorded, so the next one is checked against it
+    let _placement = state.order_limits.lock_user(user_id).await;
+    check_order_limits(state, engine, &order).await?;
+    reserve_funds(state, &order).await?;
+
+    let order_id = order.id;
+    let report = match engine.add_order(order).await {
+        Ok(report) => report,
+        Err(e) => {
+            warn!("Matching engine rejected order {}: {}", order_id, e);
+            if let Some(wallet) = &state.wallet {
+                publish_balances(state, &wallet.release(order_id).await).await;
+            }
+            return Err(rejection_status(&e));
+        }
+    };
+
+    record_execution(state, &report.order, &report.trades).await?;
+
+    info!(
+        "Order created successfully: {} ({:?}, {} trades)",
+        report.order.id,
+        report.order.status,
+        report.trades.len()
+    );
+    Ok(report)
+}
+
+/// Get a page of the authenticated user's order history, newest first
+/// unless `sort=asc`
+async fn get_orders(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<OrderHistoryQuery>,
+) -> Result<Json<ApiResponse<Page<Order>>>, StatusCode> {
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_PAGE_LIMIT);
+    if limit == 0 || limit > MAX_HISTORY_PAGE_LIMIT {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let page = state
+        .store
+        .order_history(auth.user_id, &query, limit)
+        .await
+        .map_err(store_error)?;
+    Ok(Json(ApiResponse::success(page)))
+}
+
+/// Recent trades query parameters
+#[derive(Debug, Deserialize)]
+struct RecentTradesQuery {
+    limit: Option<usize>,
+}
 
-    info!("Order created successfully: {}", order.id);
+/// Recent public trades on a symbol, newest first
+async fn get_recent_trades(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+    Query(query): Query<RecentTradesQuery>,
+) -> Result<Json<ApiResponse<Vec<Trade>>>, StatusCode> {
+    if !state.engines.contains_key(&symbol) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_RECENT_TRADES);
+    if limit == 0 || limit > MAX_RECENT_TRADES {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let trades = state.store.recent_trades(&symbol, limit).await.map_err(store_error)?;
+    let recent: Vec<Trade> = trades.iter().map(Trade::anonymized).collect();
+    Ok(Json(ApiResponse::success(recent)))
+}
+
+/// Get a page of the authenticated user's fills, with fees and maker/taker
+/// role, newest first unless `sort=asc`
+async fn get_my_trades(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<TradeHistoryQuery>,
+) -> Result<Json<ApiResponse<Page<Fill>>>, StatusCode> {
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_PAGE_LIMIT);
+    if limit == 0 || limit > MAX_HISTORY_PAGE_LIMIT {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let page = state
+        .store
+        .fill_history(auth.user_id, &query, limit)
+        .await
+        .map_err(store_error)?;
+    Ok(Json(ApiResponse::success(page)))
+}
+
+/// Maker and taker rates the authenticated user pays on each trading pair,
+/// with their 30-day volume
+async fn get_fees(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Json<ApiResponse<UserFeeRates>> {
+    let mut symbols: Vec<String> = state.trading_pairs.read().await.keys().cloned().collect();
+    symbols.sort();
+    Json(ApiResponse::success(
+        state.fees.user_rates(auth.user_id, symbols.iter().map(String::as_str)),
+    ))
+}
+
+/// Modify an open order's price and/or total quantity; a re-priced or
+/// enlarged order may match, and its trades are applied
+async fn modify_order(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(order_id): Path<Uuid>,
+    Json(request): Json<ModifyOrderRequest>,
+) -> Result<Json<ApiResponse<Order>>, Response> {
+    let order = state
+        .store
+        .order(order_id)
+        .await
+        .map_err(store_error)
+        .and_then(|order| {
+            order
+                .filter(|order| order.user_id == auth.user_id)
+                .ok_or(StatusCode::NOT_FOUND)
+        })
+        .map_err(IntoResponse::into_response)?;
+    state
+        .rate_limiter
+        .check(auth.user_id, Some(&order.trading_pair), MODIFY_ORDER_WEIGHT)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let order = apply_modification(&state, order, request)
+        .await
+        .map_err(IntoResponse::into_response)?;
     Ok(Json(ApiResponse::success(order)))
 }
 
-/// Get user orders
-async fn get_orders(State(state): State<AppState>) -> Json<ApiResponse<Vec<Order>>> {
-    let orders = state.orders.read().await;
-    let orders_vec: Vec<Order> = orders.values().cloned().collect();
-    Json(ApiResponse::success(orders_vec))
+async fn apply_modification(state: &AppState, mut order: Order, request: ModifyOrderRequest) -> Result<Order, StatusCode> {
+    let order_id = order.id;
+    if !matches!(order.status, OrderStatus::New | OrderStatus::PartiallyFilled) {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    if request.price.is_none() && request.quantity.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    if let Some(price) = request.price {
+        if order.order_type == OrderType::Market || price <= Decimal::ZERO {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    if let Some(quantity) = request.quantity {
+        if quantity <= order.filled_quantity {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let engine = state.engines.get(&order.trading_pair).ok_or(StatusCode::NOT_FOUND)?;
+    let original = order.clone();
+    if let Some(price) = request.price {
+        order.price = Some(price);
+    }
+    if let Some(quantity) = request.quantity {
+        order.quantity = quantity;
+        order.remaining_quantity = quantity - order.filled_quantity;
+    }
+    order.updated_at = chrono::Utc::now();
+
+    ensure_trading_enabled(state, order.user_id).await?;
+    check_risk(state, &order).await?;
+    let _placement = state.order_limits.lock_user(order.user_id).await;
+    check_order_limits(state, engine, &order).await?;
+    reserve_funds(state, &order).await?;
+    let trades = match engine.modify_order(order_id, request.price, request.quantity).await {
+        Ok(trades) => trades,
+        Err(e) => {
+            warn!("Matching engine rejected modification of {}: {}", order_id, e);
+            // Put the reservation back to what the unmodified order needs
+            if let Err(status) = reserve_funds(state, &original).await {
+                error!("Failed to restore the reservation of order {}: {}", order_id, status);
+            }
+            return Err(rejection_status(&e));
+        }
+    };
+    for trade in trades.iter().filter(|trade| [trade.maker_order_id, trade.taker_order_id].contains(&order_id)) {
+        store::apply_fill(&mut order, trade);
+    }
+    record_execution(state, &order, &trades).await?;
+
+    info!("Order modified: {} ({} trades)", order_id, trades.len());
+    Ok(order)
+}
+
+/// Mass cancel query parameters
+#[derive(Debug, Deserialize)]
+struct CancelOrdersQuery {
+    symbol: String,
+    side: Option<OrderSide>,
+    min_price: Option<Decimal>,
+    max_price: Option<Decimal>,
+    user_id: Option<Uuid>,
+}
+
+/// Cancel every open order on a symbol matching the filters.
+///
+/// Admins (risk desk) may cancel any user's orders; other callers only
+/// cancel their own, e.g. as a market-maker kill switch.
+async fn cancel_orders(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<CancelOrdersQuery>,
+) -> Result<Json<ApiResponse<Vec<Order>>>, Response> {
+    state
+        .rate_limiter
+        .check(auth.user_id, Some(&query.symbol), CANCEL_ORDERS_WEIGHT)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let cancelled = cancel_matching_orders(&state, &auth, query)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    Ok(Json(ApiResponse::success(cancelled)))
+}
+
+async fn cancel_matching_orders(
+    state: &AppState,
+    auth: &AuthContext,
+    query: CancelOrdersQuery,
+) -> Result<Vec<Order>, StatusCode> {
+    let caller = auth.user_id;
+    let user_id = if is_admin(auth) {
+        query.user_id
+    } else {
+        match query.user_id {
+            Some(user_id) if user_id != caller => return Err(StatusCode::FORBIDDEN),
+            _ => Some(caller),
+        }
+    };
+
+    let engine = state.engines.get(&query.symbol).ok_or(StatusCode::NOT_FOUND)?;
+
+    let filter = CancelOrdersFilter {
+        side: query.side,
+        min_price: query.min_price,
+        max_price: query.max_price,
+        user_id,
+    };
+
+    let cancelled = engine
+        .cancel_all(filter.clone())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.store.save_orders(&cancelled).await.map_err(store_error)?;
+    let balances = release_funds(state, &cancelled).await;
+    publish_user_updates(state, &cancelled, &[], &balances).await;
+
+    info!(
+        "Mass cancel on {} by {}: {} orders cancelled ({:?})",
+        query.symbol,
+        caller,
+        cancelled.len(),
+        filter
+    );
+    Ok(cancelled)
+}
+
+/// Query selecting whose kill switch to reset
+#[derive(Debug, Deserialize)]
+struct KillSwitchQuery {
+    /// User to re-enable, for admins; defaults to the caller
+    user_id: Option<Uuid>,
+}
+
+/// Cancel all of a user's resting orders on every symbol and, if asked,
+/// block them from placing orders until the switch is reset. Admins may
+/// pull the switch for any user. Not rate limited, so it works for a
+/// trader who has exhausted their budget.
+async fn pull_kill_switch(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<KillSwitchRequest>,
+) -> Result<Json<ApiResponse<KillSwitchResponse>>, StatusCode> {
+    let user_id = target_user(&auth, request.user_id)?;
+
+    // Block first, so no order placed meanwhile survives the cancel
+    let block = if request.block {
+        Some(state.kill_switch.block(user_id, auth.user_id, is_admin(&auth)).await)
+    } else {
+        None
+    };
+
+    let mut cancelled = Vec::new();
+    for (symbol, engine) in state.engines.iter() {
+        let filter = CancelOrdersFilter {
+            user_id: Some(user_id),
+            ..Default::default()
+        };
+        match engine.cancel_all(filter).await {
+            Ok(orders) => cancelled.extend(orders),
+            Err(e) => error!("Kill switch failed to cancel orders of user {} on {}: {}", user_id, symbol, e),
+        }
+    }
+    state.store.save_orders(&cancelled).await.map_err(store_error)?;
+    let balances = release_funds(&state, &cancelled).await;
+    publish_user_updates(&state, &cancelled, &[], &balances).await;
+
+    warn!(
+        "Kill switch pulled for user {} by {}: {} orders cancelled, new orders {}",
+        user_id,
+        auth.user_id,
+        cancelled.len(),
+        if block.is_some() { "blocked" } else { "allowed" }
+    );
+    Ok(Json(ApiResponse::success(KillSwitchResponse { cancelled, block })))
+}
+
+/// Let a user blocked by the kill switch place orders again. A block set
+/// by an admin can only be reset by an admin.
+async fn reset_kill_switch(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<KillSwitchQuery>,
+) -> Result<Json<ApiResponse<TradingBlock>>, StatusCode> {
+    let user_id = target_user(&auth, query.user_id)?;
+    match state.kill_switch.reset(user_id, is_admin(&auth)).await {
+        Ok(block) => Ok(Json(ApiResponse::success(block))),
+        Err(ResetError::NotBlocked) => Err(StatusCode::NOT_FOUND),
+        Err(ResetError::AdminBlock) => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+/// Expire open orders whose good-till-date has passed; returns how many expired
+async fn expire_orders(state: &AppState, now: chrono::DateTime<chrono::Utc>) -> usize {
+    let mut expired = 0;
+
+    for (symbol, engine) in state.engines.iter() {
+        let orders = match engine.expire_orders(now).await {
+            Ok(orders) => orders,
+            Err(e) => {
+                warn!("Order expiry on {} failed: {}", symbol, e);
+                continue;
+            }
+        };
+        if let Err(e) = state.store.save_orders(&orders).await {
+            error!("Failed to store expired orders on {}: {}", symbol, e);
+            continue;
+        }
+        let balances = release_funds(state, &orders).await;
+        publish_user_updates(state, &orders, &[], &balances).await;
+        for order in &orders {
+            info!("Order expired: {}", order.id);
+        }
+        expired += orders.len();
+    }
+
+    expired
+}
+
+/// Refuse an order that would take its owner over their open order or
+/// per-symbol exposure limit, or the exposure their KYC level allows, and
+/// forbid it if their level does not allow trading. An order without a
+/// price is valued at the best price on the other side of the book.
+async fn check_order_limits(state: &AppState, engine: &MatchingEngineHandle, order: &Order) -> Result<(), StatusCode> {
+    let (level, tier) = state.kyc.limits(order.user_id).await;
+    if !tier.can_trade {
+        info!("Order {} of user {} refused: KYC level {} may not trade", order.id, order.user_id, level.as_str());
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let open_orders = state.store.user_open_orders(order.user_id).await.map_err(store_error)?;
+    let reference_price = if order.price.or(order.stop_price).is_none() {
+        let book = engine.order_book(1).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let levels = if order.side == OrderSide::Buy { book.asks } else { book.bids };
+        levels.first().map(|level| level.price)
+    } else {
+        None
+    };
+
+    if let Err(violation) = state
+        .order_limits
+        .check(order, &open_orders, reference_price, tier.max_symbol_exposure)
+        .await {
+        info!("Order {} of user {} refused: {}", order.id, order.user_id, violation);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+/// Refuse orders from a user the kill switch has blocked
+async fn ensure_trading_enabled(state: &AppState, user_id: Uuid) -> Result<(), StatusCode> {
+    if state.kill_switch.block_of(user_id).await.is_some() {
+        info!("Order of user {} refused: blocked by the kill switch", user_id);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+/// Run `order` through the pre-trade risk checks, priced against the last
+/// trade on its symbol. A blacklisted user, or one restricted from the
+/// symbol, is forbidden; other failures are bad requests.
+async fn check_risk(state: &AppState, order: &Order) -> Result<(), StatusCode> {
+    let reference_price = state
+        .store
+        .recent_trades(&order.trading_pair, 1)
+        .await
+        .map_err(store_error)?
+        .first()
+        .map(|trade| trade.price);
+
+    match state.risk.check(order, reference_price) {
+        Ok(()) => Ok(()),
+        Err(violation) if violation.is_forbidden() => Err(StatusCode::FORBIDDEN),
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+/// Lock the funds `order` may spend in the wallet service, if one is
+/// configured. An order the wallet cannot cover is a bad request.
+async fn reserve_funds(state: &AppState, order: &Order) -> Result<(), StatusCode> {
+    let Some(wallet) = &state.wallet else {
+        return Ok(());
+    };
+    let trading_pair = state
+        .trading_pairs
+        .read()
+        .await
+        .get(&order.trading_pair)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let Some(reservation) = funds::reservation(order, &trading_pair) else {
+        return Ok(());
+    };
+
+    match wallet.reserve(&reservation).await {
+        Ok(balances) => {
+            publish_balances(state, &balances).await;
+            Ok(())
+        }
+        Err(e @ FlowExError::Wallet(_)) => {
+            info!("Funds reservation for order {} refused: {}", order.id, e);
+            Err(StatusCode::BAD_REQUEST)
+        }
+        Err(e) => {
+            error!("Funds reservation for order {} failed: {}", order.id, e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+/// Release the funds still locked for each order that can no longer trade;
+/// returns the balances that changed
+async fn release_funds(state: &AppState, orders: &[Order]) -> Vec<BalanceChange> {
+    let mut balances = Vec::new();
+    if let Some(wallet) = &state.wallet {
+        for order in orders.iter().filter(|order| !store::is_open(order)) {
+            balances.extend(wallet.release(order.id).await);
+        }
+    }
+    balances
+}
+
+/// Store an order as it stands after matching together with its trades,
+/// count them towards the users' fee tiers, settle them in the wallet
+/// service and release the funds of the orders they completed, then send
+/// the order, its counterparties, their fills and changed balances to the
+/// users' streams
+async fn record_execution(state: &AppState, order: &Order, trades: &[Trade]) -> Result<(), StatusCode> {
+    let mut orders = state.store.record_execution(order, trades).await.map_err(store_error)?;
+    for trade in trades {
+        state.fees.record_trade(trade);
+    }
+    let mut balances = Vec::new();
+    if let Some(wallet) = &state.wallet {
+        let trading_pair = state.trading_pairs.read().await.get(&order.trading_pair).cloned();
+        if let Some(trading_pair) = trading_pair {
+            for trade in trades {
+                let settlement =
+                    TradeSettlement::for_trade(trade, &trading_pair.base_asset, &trading_pair.quote_asset);
+                balances.extend(wallet.settle(settlement).await);
+            }
+        }
+    }
+    orders.push(order.clone());
+    balances.extend(release_funds(state, &orders).await);
+
+    publish_user_updates(state, &orders, trades, &balances).await;
+    Ok(())
+}
+
+/// Send changed orders, fills and balances to their owners' user streams,
+/// and fills and cancellations to their owners' webhooks
+async fn publish_user_updates(state: &AppState, orders: &[Order], trades: &[Trade], balances: &[BalanceChange]) {
+    for order in orders {
+        if let Err(e) = state
+            .websocket
+            .send_user_data(order.user_id, WsMessage::OrderUpdate(order.clone()))
+            .await
+        {
+            warn!("Failed to publish update of order {}: {}", order.id, e);
+        }
+        if let Some(event) = WebhookEventType::for_order(order, trades) {
+            state.webhooks.dispatch(event, order).await;
+        }
+    }
+    if !trades.is_empty() {
+        if let Err(e) = state.websocket.publish_fills(trades).await {
+            warn!("Failed to publish fills: {}", e);
+        }
+    }
+    publish_balances(state, balances).await;
+}
+
+/// Send each changed balance to its owner's user stream
+async fn publish_balances(state: &AppState, balances: &[BalanceChange]) {
+    for change in balances {
+        let message = WsMessage::BalanceUpdate {
+            currency: change.balance.currency.clone(),
+            available: change.balance.available.to_string(),
+            locked: change.balance.locked.to_string(),
+        };
+        if let Err(e) = state.websocket.send_user_data(change.user_id, message).await {
+            warn!("Failed to publish {} balance of user {}: {}", change.balance.currency, change.user_id, e);
+        }
+    }
+}
+
+/// Log a failed store operation and answer with a server error
+fn store_error(e: FlowExError) -> StatusCode {
+    error!("Order store failed: {}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// HTTP status for a request the matching engine refused
+fn rejection_status(error: &FlowExError) -> StatusCode {
+    match error {
+        FlowExError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+/// Periodically sweep expired good-till-date orders
+fn spawn_order_expiry(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ORDER_EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            expire_orders(&state, chrono::Utc::now()).await;
+        }
+    });
+}
+
+/// Whether the authenticated user holds an admin role
+fn is_admin(auth: &AuthContext) -> bool {
+    auth.roles
+        .iter()
+        .any(|role| role == Role::Admin.as_str() || role == Role::SuperAdmin.as_str())
+}
+
+/// User an endpoint acts for: `requested` if the caller is an admin or the
+/// user themselves, else the caller
+fn target_user(auth: &AuthContext, requested: Option<Uuid>) -> Result<Uuid, StatusCode> {
+    match requested {
+        Some(user_id) if user_id != auth.user_id && !is_admin(auth) => Err(StatusCode::FORBIDDEN),
+        Some(user_id) => Ok(user_id),
+        None => Ok(auth.user_id),
+    }
+}
+
+/// Require an admin role
+fn require_admin(auth: &AuthContext) -> Result<(), StatusCode> {
+    if is_admin(auth) {
+        Ok(())
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+/// Order limits in force for an account (admin only)
+async fn get_order_limits(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<AccountLimits>>, StatusCode> {
+    require_admin(&auth)?;
+    Ok(Json(ApiResponse::success(state.order_limits.limits(user_id).await)))
+}
+
+/// Set an account's own order limits in place of the defaults (admin only)
+async fn set_order_limits(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(user_id): Path<Uuid>,
+    Json(limits): Json<OrderLimits>,
+) -> Result<Json<ApiResponse<AccountLimits>>, StatusCode> {
+    require_admin(&auth)?;
+    match state.order_limits.set_override(user_id, limits).await {
+        Ok(limits) => {
+            info!("Admin {} set the order limits of user {}", auth.user_id, user_id);
+            Ok(Json(ApiResponse::success(limits)))
+        }
+        Err(e @ FlowExError::Validation(_)) => {
+            info!("Order limits of user {} rejected: {}", user_id, e);
+            Err(StatusCode::BAD_REQUEST)
+        }
+        Err(e) => Err(store_error(e)),
+    }
+}
+
+/// Return an account to the default order limits (admin only)
+async fn delete_order_limits(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<AccountLimits>>, StatusCode> {
+    require_admin(&auth)?;
+    if !state.order_limits.remove_override(user_id).await.map_err(store_error)? {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    info!("Admin {} reset the order limits of user {}", auth.user_id, user_id);
+    Ok(Json(ApiResponse::success(state.order_limits.limits(user_id).await)))
+}
+
+/// Risk audit query parameters
+#[derive(Debug, Deserialize)]
+struct RiskAuditQuery {
+    user_id: Option<Uuid>,
+    limit: Option<usize>,
+}
+
+/// Latest pre-trade check records, newest first (admin only)
+async fn get_risk_audit(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<RiskAuditQuery>,
+) -> Result<Json<ApiResponse<Vec<RiskAuditRecord>>>, StatusCode> {
+    require_admin(&auth)?;
+    let limit = query.limit.unwrap_or(DEFAULT_RISK_AUDIT_LIMIT);
+    if limit == 0 || limit > flowex_risk::MAX_AUDIT_RECORDS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Json(ApiResponse::success(state.risk.audit_log(query.user_id, limit))))
+}
+
+/// Bar a user from placing orders (admin only)
+async fn blacklist_user(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<bool>>, StatusCode> {
+    require_admin(&auth)?;
+    state.risk.blacklist(user_id);
+    info!("Admin {} blacklisted user {}", auth.user_id, user_id);
+    Ok(Json(ApiResponse::success(true)))
+}
+
+/// Lift a user's bar from placing orders (admin only)
+async fn remove_from_blacklist(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<bool>>, StatusCode> {
+    require_admin(&auth)?;
+    if !state.risk.remove_from_blacklist(user_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    info!("Admin {} removed user {} from the blacklist", auth.user_id, user_id);
+    Ok(Json(ApiResponse::success(true)))
+}
+
+/// Symbols a user may not trade (admin only)
+async fn get_restricted_symbols(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<HashSet<String>>>, StatusCode> {
+    require_admin(&auth)?;
+    Ok(Json(ApiResponse::success(state.risk.restricted_symbols(user_id))))
+}
+
+/// Replace the symbols a user may not trade (admin only)
+async fn set_restricted_symbols(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(user_id): Path<Uuid>,
+    Json(symbols): Json<HashSet<String>>,
+) -> Result<Json<ApiResponse<HashSet<String>>>, StatusCode> {
+    require_admin(&auth)?;
+    if !symbols.iter().all(|symbol| state.engines.contains_key(symbol)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    state.risk.set_restricted_symbols(user_id, symbols);
+    info!("Admin {} set the restricted symbols of user {}", auth.user_id, user_id);
+    Ok(Json(ApiResponse::success(state.risk.restricted_symbols(user_id))))
+}
+
+/// Open the authenticated user's private event stream
+async fn user_stream_handler(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    let user_id = auth.user_id;
+    Ok(state.websocket.handle_websocket(ws, Some(user_id)).await)
+}
+
+/// Dead-letter listing query parameters
+#[derive(Debug, Deserialize)]
+struct DeadLetterQuery {
+    status: Option<DeadLetterStatus>,
+}
+
+/// List dead-lettered events
+async fn get_dead_letters(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<DeadLetterQuery>,
+) -> Result<Json<ApiResponse<Vec<DeadLetter>>>, StatusCode> {
+    require_admin(&auth)?;
+    Ok(Json(ApiResponse::success(state.dead_letters.list(query.status).await)))
+}
+
+/// Dead-letter queue statistics
+async fn get_dead_letter_stats(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<DeadLetterStats>>, StatusCode> {
+    require_admin(&auth)?;
+    Ok(Json(ApiResponse::success(state.dead_letters.stats().await)))
+}
+
+/// Get a single dead-lettered event
+async fn get_dead_letter(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<DeadLetter>>, StatusCode> {
+    require_admin(&auth)?;
+
+    match state.dead_letters.get(id).await {
+        Some(entry) => Ok(Json(ApiResponse::success(entry))),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Replay a dead-lettered event through its original handler
+async fn replay_dead_letter(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<DeadLetter>>, StatusCode> {
+    require_admin(&auth)?;
+
+    let entry = state.dead_letters.get(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    if entry.status != DeadLetterStatus::Pending {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let result = match entry.handler {
+        HandlerKind::Webhook => state.webhooks.replay(&entry.payload).await,
+        HandlerKind::Settlement => match &state.wallet {
+            Some(wallet) => wallet.replay(&entry.event_type, &entry.payload).await,
+            None => Err(FlowExError::Internal("No wallet service is configured".to_string())),
+        },
+        handler => Err(FlowExError::Internal(format!("No replay handler for {:?} events", handler))),
+    };
+
+    match result {
+        Ok(()) => {
+            state.dead_letters.mark_replayed(id).await;
+        }
+        Err(e) => {
+            warn!("Replay of dead letter {} failed: {}", id, e);
+            state.dead_letters.record_failure(id, e.to_string()).await;
+        }
+    }
+
+    let entry = state.dead_letters.get(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(ApiResponse::success(entry)))
+}
+
+/// Discard a dead-lettered event
+async fn discard_dead_letter(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<bool>>, StatusCode> {
+    require_admin(&auth)?;
+
+    if state.dead_letters.discard(id).await {
+        Ok(Json(ApiResponse::success(true)))
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Register an order event webhook
+async fn create_webhook(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<CreateWebhookRequest>,
+) -> Result<Json<ApiResponse<CreateWebhookResponse>>, StatusCode> {
+    let user_id = auth.user_id;
+
+    match state.webhooks.register(user_id, request).await {
+        Ok(response) => Ok(Json(ApiResponse::success(response))),
+        Err(e @ FlowExError::Validation(_)) => {
+            warn!("Webhook registration rejected: {}", e);
+            Err(StatusCode::BAD_REQUEST)
+        }
+        Err(e) => Err(store_error(e)),
+    }
+}
+
+/// List the user's webhooks
+async fn get_webhooks(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<Vec<Webhook>>>, StatusCode> {
+    let user_id = auth.user_id;
+    Ok(Json(ApiResponse::success(state.webhooks.list_for_user(user_id).await)))
+}
+
+/// Remove one of the user's webhooks
+async fn delete_webhook(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<bool>>, StatusCode> {
+    let user_id = auth.user_id;
+
+    match state.webhooks.remove(user_id, webhook_id).await {
+        Ok(true) => Ok(Json(ApiResponse::success(true))),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(store_error(e)),
+    }
 }
 
 /// Create the application router
 fn create_app(state: AppState) -> Router {
+    let authenticated = Router::new()
+        .route("/api/trading/orders", post(create_order))
+        .route("/api/trading/orders", get(get_orders).delete(cancel_orders))
+        .route("/api/trading/orders/:id", put(modify_order))
+        .route("/api/trading/mytrades", get(get_my_trades))
+        .route("/api/trading/fees", get(get_fees))
+        .route("/api/trading/killswitch", post(pull_kill_switch).delete(reset_kill_switch))
+        .route("/api/trading/ws", get(user_stream_handler))
+        .route("/api/trading/webhooks", post(create_webhook).get(get_webhooks))
+        .route("/api/trading/webhooks/:id", delete(delete_webhook))
+        .route("/api/admin/dlq", get(get_dead_letters))
+        .route("/api/admin/dlq/stats", get(get_dead_letter_stats))
+        .route("/api/admin/dlq/:id", get(get_dead_letter).delete(discard_dead_letter))
+        .route("/api/admin/dlq/:id/replay", post(replay_dead_letter))
+        .route(
+            "/api/admin/limits/:user_id",
+            get(get_order_limits).put(set_order_limits).delete(delete_order_limits),
+        )
+        .route("/api/admin/risk/audit", get(get_risk_audit))
+        .route(
+            "/api/admin/risk/blacklist/:user_id",
+            put(blacklist_user).delete(remove_from_blacklist),
+        )
+        .route(
+            "/api/admin/risk/restrictions/:user_id",
+            get(get_restricted_symbols).put(set_restricted_symbols),
+        )
+        .route_layer(middleware::from_fn_with_state(state.api_keys.clone(), api_key_auth_middleware));
+
     Router::new()
         .route("/health", get(health_check))
         .route("/api/trading/pairs", get(get_trading_pairs))
         .route("/api/trading/orderbook/:symbol", get(get_order_book))
-        .route("/api/trading/orders", post(create_order))
-        .route("/api/trading/orders", get(get_orders))
+        .route("/api/trading/trades/:symbol", get(get_recent_trades))
+        .merge(authenticated)
         .layer(
             ServiceBuilder::new()
                 .layer(CorsLayer::permissive())
@@ -191,8 +1365,35 @@ async fn main() -> anyhow::Result<()> {
 
     info!("Starting FlowEx Trading Service");
 
-    let state = AppState::new();
-    let app = create_app(state);
+    let prometheus = flowex_metrics::install_prometheus_recorder()?;
+    let mut state = match std::env::var("FLOWEX_DATABASE_URL") {
+        Ok(database_url) => AppState::with_store(Store::connect(&database_url).await?).await?,
+        Err(_) => {
+            warn!("FLOWEX_DATABASE_URL is not set; orders and trades are kept in memory only");
+            AppState::new()
+        }
+    };
+    if let Ok(path) = std::env::var("FEE_SCHEDULE_PATH") {
+        state.fees.set_config(FeeConfig::load(&path)?)?;
+    }
+    if let Ok(path) = std::env::var("RISK_CONFIG_PATH") {
+        state.risk.set_config(RiskConfig::load(&path)?)?;
+    }
+    if let Ok(path) = std::env::var("KYC_POLICY_PATH") {
+        state.kyc.set_policy(KycPolicy::load(&path)?)?;
+    }
+    if state.wallet.is_none() {
+        warn!("WALLET_SERVICE_URL is not set; orders are accepted without a funds check");
+    }
+    // Reach users connected to the other instances of the service, and
+    // share idempotency keys and rate limit budgets with them
+    if let Ok(redis_url) = std::env::var("REDIS_URL") {
+        state.websocket = flowex_websocket::bridge::connect_redis(state.websocket, &redis_url).await?;
+        state.idempotency = IdempotencyCache::connect(&redis_url).await?;
+        state.rate_limiter = RateLimiter::connect(RateLimitConfig::from_env(), &redis_url).await?;
+    }
+    spawn_order_expiry(state.clone());
+    let app = create_app(state).route("/metrics", get(move || std::future::ready(prometheus.render())));
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8002").await?;
     info!("Trading service listening on http://0.0.0.0:8002");
@@ -205,6 +1406,9 @@ async fn main() -> anyhow::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use flowex_types::TimeInForce;
+    use flowex_kyc::KycLevel;
+    use flowex_middleware::service_auth::{service_auth_middleware, ServiceAuth};
     use axum::{
         body::Body,
         http::{Request, StatusCode},
@@ -214,6 +1418,9 @@ mod tests {
 
     static INIT: Once = Once::new();
 
+    /// 测试订单所属用户
+    const TEST_USER_ID: Uuid = Uuid::from_u128(0x7e57);
+
     /// 初始化测试环境
     fn init_test_env() {
         INIT.call_once(|| {
@@ -227,62 +1434,120 @@ mod tests {
     /// 创建测试用的应用状态
     fn create_test_app_state() -> AppState {
         let mut trading_pairs = HashMap::new();
-        let mut orders = HashMap::new();
 
         // 添加测试交易对
         trading_pairs.insert("BTCUSDT".to_string(), TradingPair {
             symbol: "BTCUSDT".to_string(),
             base_asset: "BTC".to_string(),
             quote_asset: "USDT".to_string(),
-            status: "TRADING".to_string(),
+            status: TradingStatus::Trading,
             min_price: Decimal::new(1, 8), // 0.00000001
             max_price: Decimal::new(99999999999999999, 8), // 999999999.99999999
             min_qty: Decimal::new(1, 8), // 0.00000001
             max_qty: Decimal::new(99999999999999999, 8), // 999999999.99999999
             step_size: Decimal::new(1, 8), // 0.00000001
             tick_size: Decimal::new(1, 8), // 0.00000001
+            min_notional: Decimal::ZERO,
         });
 
         trading_pairs.insert("ETHUSDT".to_string(), TradingPair {
             symbol: "ETHUSDT".to_string(),
             base_asset: "ETH".to_string(),
             quote_asset: "USDT".to_string(),
-            status: "TRADING".to_string(),
+            status: TradingStatus::Trading,
             min_price: Decimal::new(1, 8),
             max_price: Decimal::new(99999999999999999, 8),
             min_qty: Decimal::new(1, 8),
             max_qty: Decimal::new(99999999999999999, 8),
             step_size: Decimal::new(1, 8),
             tick_size: Decimal::new(1, 8),
+            min_notional: Decimal::ZERO,
         });
 
         // 添加测试订单
         let test_order = Order {
             id: Uuid::new_v4(),
-            user_id: Uuid::new_v4(),
+            user_id: TEST_USER_ID,
             trading_pair: "BTCUSDT".to_string(),
             side: OrderSide::Buy,
             order_type: OrderType::Limit,
             price: Some(Decimal::new(4500000, 2)), // 45000.00
+            stop_price: None,
             quantity: Decimal::new(100, 3), // 0.100
             filled_quantity: Decimal::ZERO,
             remaining_quantity: Decimal::new(100, 3), // 0.100
+            time_in_force: TimeInForce::Gtc,
+            post_only: false,
+            display_quantity: None,
+            expires_at: None,
+            reduce_only: false,
+            client_order_id: None,
             status: OrderStatus::New,
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         };
-        orders.insert(test_order.id, test_order);
 
+        let fees = FeeManager::new(FeeConfig::default());
+        let dead_letters = DeadLetterQueue::new(DLQ_ALERT_THRESHOLD);
         AppState {
+            engines: Arc::new(spawn_engines(&trading_pairs, &fees)),
             trading_pairs: Arc::new(RwLock::new(trading_pairs)),
-            orders: Arc::new(RwLock::new(orders)),
+            store: Store::in_memory_with_orders(vec![test_order]),
+            webhooks: WebhookRegistry::new(WebhookConfig::default(), dead_letters.clone()),
+            dead_letters,
+            idempotency: IdempotencyCache::in_memory(),
+            rate_limiter: RateLimiter::in_memory(RateLimitConfig::default()),
+            order_limits: OrderLimitRegistry::new(OrderLimits::default()),
+            kill_switch: KillSwitch::new(),
+            risk: RiskEngine::new(RiskConfig::default()),
+            wallet: None,
+            fees,
+            websocket: WebSocketManager::new(MAX_USER_STREAM_CONNECTIONS),
+            api_keys: ApiKeyVerifier::new("http://localhost:8001", Permission::TradingRead, Permission::TradingWrite),
+            kyc: KycGate::fixed(KycLevel::L2, KycPolicy::default()),
             start_time: SystemTime::now(),
         }
     }
 
+    /// 签发测试用户的JWT访问令牌
+    fn bearer_token(user_id: Uuid) -> String {
+        bearer_token_with_role(user_id, Role::Trader)
+    }
+
+    /// 测试令牌的HS256签名密钥，首次签发时设为JWT_SECRET
+    const TEST_JWT_SECRET: &str = "flowex_test_jwt_secret";
+
+    static JWT_SECRET: Once = Once::new();
+
+    /// 签发指定角色用户的JWT访问令牌
+    fn bearer_token_with_role(user_id: Uuid, role: Role) -> String {
+        let now = chrono::Utc::now();
+        let claims = flowex_types::JwtClaims {
+            sub: user_id.to_string(),
+            email: "trader@flowex.com".to_string(),
+            iss: flowex_middleware::auth::TOKEN_ISSUER.to_string(),
+            aud: flowex_middleware::auth::TOKEN_AUDIENCE.to_string(),
+            exp: (now + chrono::Duration::hours(1)).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+            roles: vec![role.as_str().to_string()],
+            permissions: Vec::new(),
+            account: Default::default(),
+        };
+        JWT_SECRET.call_once(|| std::env::set_var("JWT_SECRET", TEST_JWT_SECRET));
+        let secret = TEST_JWT_SECRET;
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(secret.as_ref()),
+        )
+        .unwrap();
+        format!("Bearer {}", token)
+    }
+
     /// 测试：应用状态创建
-    #[test]
-    fn test_app_state_creation() {
+    #[tokio::test]
+    async fn test_app_state_creation() {
         init_test_env();
 
         let state = create_test_app_state();
@@ -291,15 +1556,13 @@ mod tests {
         assert!(state.start_time.elapsed().unwrap().as_secs() < 1);
 
         // 验证初始数据
-        tokio::runtime::Runtime::new().unwrap().block_on(async {
-            let trading_pairs = state.trading_pairs.read().await;
-            assert!(trading_pairs.len() > 0, "应该有初始交易对数据");
-            assert!(trading_pairs.contains_key("BTCUSDT"), "应该包含BTCUSDT交易对");
-            assert!(trading_pairs.contains_key("ETHUSDT"), "应该包含ETHUSDT交易对");
-
-            let orders = state.orders.read().await;
-            assert!(orders.len() > 0, "应该有初始订单数据");
-        });
+        let trading_pairs = state.trading_pairs.read().await;
+        assert!(!trading_pairs.is_empty(), "应该有初始交易对数据");
+        assert!(trading_pairs.contains_key("BTCUSDT"), "应该包含BTCUSDT交易对");
+        assert!(trading_pairs.contains_key("ETHUSDT"), "应该包含ETHUSDT交易对");
+
+        let orders = state.store.order_history(TEST_USER_ID, &OrderHistoryQuery::default(), 10).await.unwrap();
+        assert!(!orders.items.is_empty(), "应该有初始订单数据");
     }
 
     /// 测试：交易对数据结构
@@ -311,19 +1574,20 @@ mod tests {
             symbol: "BTCUSDT".to_string(),
             base_asset: "BTC".to_string(),
             quote_asset: "USDT".to_string(),
-            status: "TRADING".to_string(),
+            status: TradingStatus::Trading,
             min_price: Decimal::new(1, 8),
             max_price: Decimal::new(99999999999999999, 8),
             min_qty: Decimal::new(1, 8),
             max_qty: Decimal::new(99999999999999999, 8),
             step_size: Decimal::new(1, 8),
             tick_size: Decimal::new(1, 8),
+            min_notional: Decimal::ZERO,
         };
 
         assert_eq!(trading_pair.symbol, "BTCUSDT");
         assert_eq!(trading_pair.base_asset, "BTC");
         assert_eq!(trading_pair.quote_asset, "USDT");
-        assert_eq!(trading_pair.status, "TRADING");
+        assert_eq!(trading_pair.status, TradingStatus::Trading);
         assert!(trading_pair.min_price > Decimal::ZERO);
         assert!(trading_pair.max_price > trading_pair.min_price);
         assert!(trading_pair.min_qty > Decimal::ZERO);
@@ -342,9 +1606,16 @@ mod tests {
             side: OrderSide::Sell,
             order_type: OrderType::Market,
             price: None, // 市价单没有价格
+            stop_price: None,
             quantity: Decimal::new(250, 2), // 2.50
             filled_quantity: Decimal::ZERO,
             remaining_quantity: Decimal::new(250, 2), // 2.50
+            time_in_force: TimeInForce::Gtc,
+            post_only: false,
+            display_quantity: None,
+            expires_at: None,
+            reduce_only: false,
+            client_order_id: None,
             status: OrderStatus::New,
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
@@ -378,7 +1649,7 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
         let health_response: HealthResponse = serde_json::from_slice(&body).unwrap();
 
         assert_eq!(health_response.status, "healthy");
@@ -407,14 +1678,14 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
         let api_response: ApiResponse<Vec<TradingPair>> = serde_json::from_slice(&body).unwrap();
 
         assert!(api_response.success);
         assert!(api_response.data.is_some());
 
         let trading_pairs = api_response.data.unwrap();
-        assert!(trading_pairs.len() > 0);
+        assert!(!trading_pairs.is_empty());
 
         // 验证包含预期的交易对
         let btc_pair = trading_pairs.iter().find(|p| p.symbol == "BTCUSDT");
@@ -436,6 +1707,7 @@ mod tests {
             .oneshot(
                 Request::builder()
                     .uri("/api/trading/orders")
+                    .header("authorization", bearer_token(TEST_USER_ID))
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -444,14 +1716,14 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        let api_response: ApiResponse<Vec<Order>> = serde_json::from_slice(&body).unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let api_response: ApiResponse<Page<Order>> = serde_json::from_slice(&body).unwrap();
 
         assert!(api_response.success);
         assert!(api_response.data.is_some());
 
-        let orders = api_response.data.unwrap();
-        assert!(orders.len() > 0, "应该有订单数据");
+        let orders = api_response.data.unwrap().items;
+        assert!(!orders.is_empty(), "应该有订单数据");
 
         // 验证订单数据格式
         for order in &orders {
@@ -475,7 +1747,14 @@ mod tests {
             side: OrderSide::Buy,
             order_type: OrderType::Limit,
             price: Some(Decimal::new(4400000, 2)), // 44000.00
+            stop_price: None,
             quantity: Decimal::new(50, 3), // 0.050
+            time_in_force: TimeInForce::Gtc,
+            post_only: false,
+            display_quantity: None,
+            expires_at: None,
+            reduce_only: false,
+            client_order_id: None,
         };
 
         let response = app
@@ -483,6 +1762,7 @@ mod tests {
                 Request::builder()
                     .method("POST")
                     .uri("/api/trading/orders")
+                    .header("authorization", bearer_token(TEST_USER_ID))
                     .header("content-type", "application/json")
                     .body(Body::from(serde_json::to_string(&order_request).unwrap()))
                     .unwrap(),
@@ -490,15 +1770,15 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        let api_response: ApiResponse<Order> = serde_json::from_slice(&body).unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let api_response: ApiResponse<ExecutionReport> = serde_json::from_slice(&body).unwrap();
 
         assert!(api_response.success);
         assert!(api_response.data.is_some());
 
-        let order = api_response.data.unwrap();
+        let order = api_response.data.unwrap().order;
         assert_eq!(order.trading_pair, "BTCUSDT");
         assert!(matches!(order.side, OrderSide::Buy));
         assert!(matches!(order.order_type, OrderType::Limit));
@@ -520,7 +1800,14 @@ mod tests {
             side: OrderSide::Sell,
             order_type: OrderType::Market,
             price: None, // 市价单没有价格
+            stop_price: None,
             quantity: Decimal::new(100, 2), // 1.00
+            time_in_force: TimeInForce::Gtc,
+            post_only: false,
+            display_quantity: None,
+            expires_at: None,
+            reduce_only: false,
+            client_order_id: None,
         };
 
         let response = app
@@ -528,6 +1815,7 @@ mod tests {
                 Request::builder()
                     .method("POST")
                     .uri("/api/trading/orders")
+                    .header("authorization", bearer_token(TEST_USER_ID))
                     .header("content-type", "application/json")
                     .body(Body::from(serde_json::to_string(&order_request).unwrap()))
                     .unwrap(),
@@ -535,15 +1823,15 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        let api_response: ApiResponse<Order> = serde_json::from_slice(&body).unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let api_response: ApiResponse<ExecutionReport> = serde_json::from_slice(&body).unwrap();
 
         assert!(api_response.success);
         assert!(api_response.data.is_some());
 
-        let order = api_response.data.unwrap();
+        let order = api_response.data.unwrap().order;
         assert_eq!(order.trading_pair, "ETHUSDT");
         assert!(matches!(order.side, OrderSide::Sell));
         assert!(matches!(order.order_type, OrderType::Market));
@@ -551,6 +1839,1219 @@ mod tests {
         assert_eq!(order.quantity, Decimal::new(100, 2));
     }
 
+    /// 测试：订单经撮合引擎成交，双方订单状态同步更新
+    #[tokio::test]
+    async fn test_crossing_orders_are_matched() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let place = |user_id: Uuid, side: OrderSide| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: "BTCUSDT".to_string(),
+                side,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(4500000, 2)), // 45000.00
+                stop_price: None,
+                quantity: Decimal::new(100, 3), // 0.100
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method("POST")
+                            .uri("/api/trading/orders")
+                    .header("authorization", bearer_token(TEST_USER_ID))
+                            .header("content-type", "application/json")
+                            .header("authorization", bearer_token(user_id))
+                            .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                let api_response: ApiResponse<ExecutionReport> = serde_json::from_slice(&body).unwrap();
+                api_response.data.unwrap()
+            }
+        };
+
+        // 挂单方进入订单簿，不产生成交
+        let maker = place(Uuid::new_v4(), OrderSide::Sell).await;
+        assert_eq!(maker.order.status, OrderStatus::New);
+        assert!(maker.trades.is_empty());
+
+        // 吃单方与之完全成交
+        let taker = place(Uuid::new_v4(), OrderSide::Buy).await;
+        assert_eq!(taker.order.status, OrderStatus::Filled);
+        assert_eq!(taker.trades.len(), 1);
+        assert_eq!(taker.trades[0].maker_order_id, maker.order.id);
+        assert_eq!(taker.trades[0].price, Decimal::new(4500000, 2));
+
+        // 挂单方的订单状态已同步更新
+        let stored_maker = state.store.order(maker.order.id).await.unwrap().unwrap();
+        assert_eq!(stored_maker.status, OrderStatus::Filled);
+        assert_eq!(stored_maker.remaining_quantity, Decimal::ZERO);
+        let stored_taker = state.store.order(taker.order.id).await.unwrap().unwrap();
+        assert_eq!(stored_taker.filled_quantity, Decimal::new(100, 3));
+    }
+
+    /// 测试：下单成交与批量撤单经HTTP流程触发签名的订单Webhook回调
+    #[tokio::test]
+    async fn test_order_events_are_posted_to_webhooks() {
+        init_test_env();
+
+        // 模拟Webhook接收端，记录收到的回调
+        type Deliveries = Arc<RwLock<Vec<(HeaderMap, String)>>>;
+        let deliveries = Deliveries::default();
+        let receiver = Router::new()
+            .route(
+                "/hook",
+                post(|State(deliveries): State<Deliveries>, headers: HeaderMap, body: String| async move {
+                    deliveries.write().await.push((headers, body));
+                    StatusCode::OK
+                }),
+            )
+            .with_state(deliveries.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hook_url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let mut state = create_test_app_state();
+        let config = WebhookConfig { allow_insecure: true, ..Default::default() };
+        state.webhooks = WebhookRegistry::new(config, state.dead_letters.clone());
+        let (maker_id, taker_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let response = create_app(state.clone())
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/trading/webhooks")
+                    .header("content-type", "application/json")
+                    .header("authorization", bearer_token(maker_id))
+                    .body(Body::from(
+                        serde_json::json!({ "url": hook_url, "events": ["order_filled", "order_cancelled"] }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let registered: ApiResponse<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let secret = registered.data.unwrap()["secret"].as_str().unwrap().to_string();
+
+        let place = |user_id: Uuid, side: OrderSide| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: "BTCUSDT".to_string(),
+                side,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(45000, 0)),
+                stop_price: None,
+                quantity: Decimal::ONE,
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method("POST")
+                            .uri("/api/trading/orders")
+                            .header("content-type", "application/json")
+                            .header("authorization", bearer_token(user_id))
+                            .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<ApiResponse<ExecutionReport>>(&body).unwrap().data.unwrap().order
+            }
+        };
+        let delivered = |count: usize| {
+            let deliveries = deliveries.clone();
+            async move {
+                for _ in 0..100 {
+                    if deliveries.read().await.len() >= count {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                deliveries.read().await.clone()
+            }
+        };
+
+        // 挂单被吃单方完全成交，只有注册了Webhook的挂单方收到回调
+        let filled = place(maker_id, OrderSide::Sell).await;
+        place(taker_id, OrderSide::Buy).await;
+        let received = delivered(1).await;
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        assert_eq!(headers[webhooks::EVENT_HEADER], "order_filled");
+        let payload: webhooks::WebhookPayload = serde_json::from_str(body).unwrap();
+        assert_eq!(payload.order.id, filled.id);
+        assert_eq!(payload.order.status, OrderStatus::Filled);
+
+        // 回调携带可用注册时返回的密钥验证的签名
+        let signature = headers[webhooks::SIGNATURE_HEADER].to_str().unwrap();
+        let (timestamp, digest) = signature.split_once(',').unwrap();
+        let timestamp: i64 = timestamp.trim_start_matches("t=").parse().unwrap();
+        assert_eq!(digest.trim_start_matches("v1="), webhooks::sign_payload(&secret, timestamp, body));
+
+        // 批量撤单触发撤单回调
+        let resting = place(maker_id, OrderSide::Sell).await;
+        let response = create_app(state.clone())
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/api/trading/orders?symbol=BTCUSDT")
+                    .header("authorization", bearer_token(maker_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let received = delivered(2).await;
+        assert_eq!(received.len(), 2);
+        let (headers, body) = &received[1];
+        assert_eq!(headers[webhooks::EVENT_HEADER], "order_cancelled");
+        let payload: webhooks::WebhookPayload = serde_json::from_str(body).unwrap();
+        assert_eq!(payload.order.id, resting.id);
+        assert_eq!(payload.order.status, OrderStatus::Cancelled);
+    }
+
+    /// 测试：下单前在钱包冻结资金，成交后结算并释放剩余冻结，余额不足的订单被拒绝
+    #[tokio::test]
+    async fn test_orders_reserve_and_settle_funds() {
+        init_test_env();
+
+        // 模拟钱包服务，记录收到的冻结、结算和释放请求
+        let calls = Arc::new(RwLock::new(Vec::<String>::new()));
+        let wallet_app = Router::new()
+            .route(
+                "/api/wallet/reservations",
+                post(|State(calls): State<Arc<RwLock<Vec<String>>>>, Json(reservation): Json<flowex_types::Reservation>| async move {
+                    if reservation.amount > Decimal::new(10000, 0) {
+                        return Err(StatusCode::CONFLICT);
+                    }
+                    calls.write().await.push(format!(
+                        "reserve {} {} {}",
+                        reservation.order_id, reservation.currency, reservation.amount.normalize()
+                    ));
+                    let balance = flowex_types::Balance {
+                        currency: reservation.currency,
+                        available: Decimal::ZERO,
+                        locked: reservation.amount,
+                    };
+                    Ok(Json(ApiResponse::success(vec![BalanceChange { user_id: reservation.user_id, balance }])))
+                }),
+            )
+            .route(
+                "/api/wallet/reservations/:order_id",
+                delete(|State(calls): State<Arc<RwLock<Vec<String>>>>, Path(order_id): Path<Uuid>| async move {
+                    calls.write().await.push(format!("release {}", order_id));
+                    Json(ApiResponse::success(Vec::<BalanceChange>::new()))
+                }),
+            )
+            .route(
+                "/api/wallet/settlements",
+                post(|State(calls): State<Arc<RwLock<Vec<String>>>>, Json(settlement): Json<TradeSettlement>| async move {
+                    calls.write().await.push(format!("settle {}", settlement.trade_id));
+                    let balance = flowex_types::Balance {
+                        currency: settlement.base_asset,
+                        available: settlement.quantity,
+                        locked: Decimal::ZERO,
+                    };
+                    Json(ApiResponse::success(vec![BalanceChange { user_id: settlement.buyer.user_id, balance }]))
+                }),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                ServiceAuth::new([(funds::SERVICE_NAME, "trading-s3cret")]),
+                service_auth_middleware,
+            ))
+            .with_state(calls.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let wallet_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, wallet_app).await.unwrap() });
+
+        let mut state = create_test_app_state();
+        state.wallet = Some(WalletClient::new(
+            &wallet_url,
+            Some("trading-s3cret".to_string()),
+            state.dead_letters.clone(),
+        ));
+        let bus = Arc::new(RecordingBus::default());
+        state.websocket = state.websocket.with_bus(bus.clone());
+        let place = |side: OrderSide, quantity: Decimal| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: "BTCUSDT".to_string(),
+                side,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(45000, 0)),
+                stop_price: None,
+                quantity,
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/trading/orders")
+                        .header("content-type", "application/json")
+                        .header("authorization", bearer_token(Uuid::new_v4()))
+                        .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+            }
+        };
+        let report = |response: Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<ApiResponse<ExecutionReport>>(&body).unwrap().data.unwrap()
+        };
+
+        // 卖单冻结基础资产，买单冻结计价资产及手续费
+        let maker = report(place(OrderSide::Sell, Decimal::new(1, 1)).await).await;
+        let taker = report(place(OrderSide::Buy, Decimal::new(1, 1)).await).await;
+        assert_eq!(taker.order.status, OrderStatus::Filled);
+        let trade_id = taker.trades[0].id;
+
+        let recorded = calls.read().await.clone();
+        assert_eq!(recorded.len(), 5);
+        assert_eq!(recorded[0], format!("reserve {} BTC 0.1", maker.order.id));
+        assert_eq!(recorded[1], format!("reserve {} USDT 4504.5", taker.order.id));
+        // 先结算成交，再释放两个已完成订单的剩余冻结
+        assert_eq!(recorded[2], format!("settle {}", trade_id));
+        assert!(recorded[3..].contains(&format!("release {}", maker.order.id)));
+        assert!(recorded[3..].contains(&format!("release {}", taker.order.id)));
+
+        // 钱包返回的余额变动推送到用户数据流
+        let balances: Vec<(Uuid, String, String)> = bus
+            .user_events()
+            .into_iter()
+            .filter_map(|(user_id, event)| match event {
+                WsMessage::BalanceUpdate { currency, available, .. } => Some((user_id, currency, available)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(balances.len(), 3);
+        assert_eq!(balances[2], (taker.order.user_id, "BTC".to_string(), "0.1".to_string()));
+
+        // 钱包余额不足时拒绝下单，订单不进入订单簿
+        let refused = place(OrderSide::Buy, Decimal::ONE).await;
+        assert_eq!(refused.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(calls.read().await.len(), 5);
+        let book = state.engines["BTCUSDT"].order_book(10).await.unwrap();
+        assert!(book.bids.is_empty());
+    }
+
+    /// 测试：携带相同幂等键的重试返回首次结果而不重复下单，幂等键复用于不同订单时拒绝
+    #[tokio::test]
+    async fn test_idempotent_order_placement() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let place = |key: &'static str, quantity: Decimal| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: "ETHUSDT".to_string(),
+                side: OrderSide::Buy,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(3000, 0)),
+                stop_price: None,
+                quantity,
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/trading/orders")
+                        .header("content-type", "application/json")
+                        .header("authorization", bearer_token(TEST_USER_ID))
+                        .header("idempotency-key", key)
+                        .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+            }
+        };
+        let order_id = |response: Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<ApiResponse<ExecutionReport>>(&body).unwrap().data.unwrap().order.id
+        };
+
+        let first = order_id(place("order-1", Decimal::ONE).await).await;
+        let retry = order_id(place("order-1", Decimal::ONE).await).await;
+        assert_eq!(first, retry);
+        let book = state.engines["ETHUSDT"].order_book(10).await.unwrap();
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(book.bids[0].quantity, Decimal::ONE);
+
+        // 同一幂等键用于不同的订单
+        assert_eq!(place("order-1", Decimal::TWO).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        // 新的幂等键正常下单
+        assert_ne!(order_id(place("order-2", Decimal::ONE).await).await, first);
+    }
+
+    /// 测试：相同客户端订单号的重试返回原订单，换了幂等键时因原订单仍挂着而被拒绝
+    #[tokio::test]
+    async fn test_duplicate_client_order_id() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let place = |user_id: Uuid, idempotency_key: Option<&'static str>| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: "ETHUSDT".to_string(),
+                side: OrderSide::Buy,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(3000, 0)),
+                stop_price: None,
+                quantity: Decimal::ONE,
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: Some("client-1".to_string()),
+            };
+            async move {
+                let mut request = Request::builder()
+                    .method("POST")
+                    .uri("/api/trading/orders")
+                    .header("content-type", "application/json")
+                    .header("authorization", bearer_token(user_id));
+                if let Some(key) = idempotency_key {
+                    request = request.header("idempotency-key", key);
+                }
+                app.oneshot(request.body(Body::from(serde_json::to_string(&order_request).unwrap())).unwrap())
+                    .await
+                    .unwrap()
+            }
+        };
+        let order_id = |response: Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<ApiResponse<ExecutionReport>>(&body).unwrap().data.unwrap().order.id
+        };
+
+        let first = order_id(place(TEST_USER_ID, None).await).await;
+        // 客户端订单号作为幂等键的重试
+        assert_eq!(order_id(place(TEST_USER_ID, None).await).await, first);
+        // 换了幂等键但客户端订单号相同：原订单仍挂着，拒绝
+        assert_eq!(place(TEST_USER_ID, Some("other-key")).await.status(), StatusCode::BAD_REQUEST);
+        let book = state.engines["ETHUSDT"].order_book(10).await.unwrap();
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(book.bids[0].quantity, Decimal::ONE);
+
+        // 其他用户可使用相同的客户端订单号
+        assert_ne!(order_id(place(Uuid::new_v4(), None).await).await, first);
+        let book = state.engines["ETHUSDT"].order_book(10).await.unwrap();
+        assert_eq!(book.bids[0].quantity, Decimal::TWO);
+    }
+
+    /// 测试：下单超过用户速率限制时返回429及Retry-After
+    #[tokio::test]
+    async fn test_order_rate_limit() {
+        init_test_env();
+
+        let mut state = create_test_app_state();
+        state.rate_limiter = RateLimiter::in_memory(RateLimitConfig {
+            window: Duration::from_secs(3600),
+            user_limit: 3,
+            symbol_limit: 100,
+        });
+        let user_id = Uuid::new_v4();
+        let place = || {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: "ETHUSDT".to_string(),
+                side: OrderSide::Buy,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(3000, 0)),
+                stop_price: None,
+                quantity: Decimal::ONE,
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/trading/orders")
+                        .header("content-type", "application/json")
+                        .header("authorization", bearer_token(user_id))
+                        .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        for _ in 0..3 {
+            assert_eq!(place().await.status(), StatusCode::OK);
+        }
+        let limited = place().await;
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = limited.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+        assert!((1..=3600).contains(&retry_after));
+
+        // 被拒绝的订单没有进入订单簿
+        let book = state.engines["ETHUSDT"].order_book(10).await.unwrap();
+        assert_eq!(book.bids[0].quantity, Decimal::new(3, 0));
+    }
+
+    /// 记录WebSocket管理器转发的事件
+    #[derive(Debug, Default)]
+    struct RecordingBus(std::sync::Mutex<Vec<flowex_websocket::bridge::BridgeEvent>>);
+
+    impl flowex_websocket::bridge::EventBus for RecordingBus {
+        fn publish(&self, event: &flowex_websocket::bridge::BridgeEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    impl RecordingBus {
+        /// 按发布顺序返回各用户收到的事件
+        fn user_events(&self) -> Vec<(Uuid, WsMessage)> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|event| match &event.payload {
+                    flowex_websocket::bridge::BridgePayload::User {
+                        user_id,
+                        event: WsMessage::UserEvent { event, .. },
+                    } => Some((*user_id, (**event).clone())),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    /// 测试：撮合成交后向双方推送订单和成交更新，撤单后推送订单状态
+    #[tokio::test]
+    async fn test_order_updates_are_pushed_to_users() {
+        init_test_env();
+
+        let mut state = create_test_app_state();
+        let bus = Arc::new(RecordingBus::default());
+        state.websocket = state.websocket.with_bus(bus.clone());
+        let (maker, taker) = (Uuid::new_v4(), Uuid::new_v4());
+        let place = |user_id: Uuid, side: OrderSide, quantity: Decimal| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: "ETHUSDT".to_string(),
+                side,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(3000, 0)),
+                stop_price: None,
+                quantity,
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method("POST")
+                            .uri("/api/trading/orders")
+                            .header("content-type", "application/json")
+                            .header("authorization", bearer_token(user_id))
+                            .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        };
+
+        place(maker, OrderSide::Sell, Decimal::TWO).await;
+        place(taker, OrderSide::Buy, Decimal::ONE).await;
+
+        let events = bus.user_events();
+        let order_updates = |user_id: Uuid| -> Vec<OrderStatus> {
+            events
+                .iter()
+                .filter_map(|(owner, event)| match event {
+                    WsMessage::OrderUpdate(order) if *owner == user_id => Some(order.status.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(order_updates(maker), vec![OrderStatus::New, OrderStatus::PartiallyFilled]);
+        assert_eq!(order_updates(taker), vec![OrderStatus::Filled]);
+        let fills: Vec<Uuid> = events
+            .iter()
+            .filter(|(_, event)| matches!(event, WsMessage::FillUpdate(_)))
+            .map(|(user_id, _)| *user_id)
+            .collect();
+        assert_eq!(fills.len(), 2);
+        assert!(fills.contains(&maker) && fills.contains(&taker));
+
+        // 撤销剩余挂单后推送已撤销状态
+        let response = create_app(state.clone())
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/api/trading/orders?symbol=ETHUSDT")
+                    .header("authorization", bearer_token(maker))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        match bus.user_events().last() {
+            Some((user_id, WsMessage::OrderUpdate(order))) => {
+                assert_eq!(*user_id, maker);
+                assert_eq!(order.status, OrderStatus::Cancelled);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    /// 测试：手续费接口返回用户的30天成交量和各交易对费率，成交按等级收费
+    #[tokio::test]
+    async fn test_fee_tiers() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let (maker, taker) = (Uuid::new_v4(), Uuid::new_v4());
+        state
+            .fees
+            .record_volume(maker, chrono::Utc::now().date_naive(), Decimal::new(10_000_000, 0));
+
+        for (user_id, side) in [(maker, OrderSide::Sell), (taker, OrderSide::Buy)] {
+            let order_request = CreateOrderRequest {
+                trading_pair: "ETHUSDT".to_string(),
+                side,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(3000, 0)),
+                stop_price: None,
+                quantity: Decimal::ONE,
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            let response = create_app(state.clone())
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/trading/orders")
+                        .header("content-type", "application/json")
+                        .header("authorization", bearer_token(user_id))
+                        .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let trade = state.store.recent_trades("ETHUSDT", 1).await.unwrap().remove(0);
+        assert_eq!(trade.maker_fee, Decimal::new(18, 1));
+        assert_eq!(trade.taker_fee, Decimal::new(3, 0));
+
+        let fees = |user_id: Uuid| {
+            let app = create_app(state.clone());
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .uri("/api/trading/fees")
+                            .header("authorization", bearer_token(user_id))
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<ApiResponse<UserFeeRates>>(&body).unwrap().data.unwrap()
+            }
+        };
+
+        let rates = fees(maker).await;
+        assert_eq!(rates.volume_30d, Decimal::new(10_003_000, 0));
+        assert_eq!(rates.pairs.len(), 2);
+        assert!(rates.pairs.iter().all(|pair| pair.tier == 2));
+        let rates = fees(taker).await;
+        assert_eq!(rates.volume_30d, Decimal::new(3000, 0));
+        assert_eq!(rates.pairs[0].symbol, "BTCUSDT");
+        assert_eq!((rates.pairs[0].tier, rates.pairs[0].taker_rate), (0, Decimal::new(1, 3)));
+
+        let response = create_app(state)
+            .oneshot(Request::builder().uri("/api/trading/fees").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    /// 测试：同一用户并发下单时逐个检查限额，挂单数量上限为1时只有一笔成功
+    #[tokio::test]
+    async fn test_concurrent_orders_respect_limits() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let user_id = Uuid::new_v4();
+        state
+            .order_limits
+            .set_override(user_id, OrderLimits { max_open_orders: 1, max_symbol_exposure: Decimal::new(1_000_000, 0) })
+            .await
+            .unwrap();
+
+        let placements = (0..8).map(|i| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: "BTCUSDT".to_string(),
+                side: OrderSide::Buy,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(100 + i, 0)),
+                stop_price: None,
+                quantity: Decimal::ONE,
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            tokio::spawn(async move {
+                app.oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/trading/orders")
+                        .header("content-type", "application/json")
+                        .header("authorization", bearer_token(user_id))
+                        .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+                .status()
+            })
+        });
+
+        let mut accepted = 0;
+        for placement in placements.collect::<Vec<_>>() {
+            match placement.await.unwrap() {
+                StatusCode::OK => accepted += 1,
+                status => assert_eq!(status, StatusCode::BAD_REQUEST),
+            }
+        }
+        assert_eq!(accepted, 1);
+        assert_eq!(state.store.user_open_orders(user_id).await.unwrap().len(), 1);
+    }
+
+    /// 测试：超出挂单数量或敞口限额的订单被拒绝，管理员可为账户设置和移除限额
+    #[tokio::test]
+    async fn test_order_limits() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let user_id = Uuid::new_v4();
+        let admin = bearer_token_with_role(Uuid::new_v4(), Role::Admin);
+        let limits_request = |token: String, method: &str, body: Body| {
+            Request::builder()
+                .method(method)
+                .uri(format!("/api/admin/limits/{}", user_id))
+                .header("content-type", "application/json")
+                .header("authorization", token)
+                .body(body)
+                .unwrap()
+        };
+        let limits = OrderLimits {
+            max_open_orders: 1,
+            max_symbol_exposure: Decimal::new(5000, 0),
+        };
+        let body = || Body::from(serde_json::to_string(&limits).unwrap());
+
+        // 非管理员不能设置限额
+        let response = create_app(state.clone())
+            .oneshot(limits_request(bearer_token(user_id), "PUT", body()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = create_app(state.clone())
+            .oneshot(limits_request(admin.clone(), "PUT", body()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let place = |symbol: &str, price: i64, quantity: i64| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: symbol.to_string(),
+                side: OrderSide::Buy,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(price, 0)),
+                stop_price: None,
+                quantity: Decimal::new(quantity, 0),
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/trading/orders")
+                        .header("content-type", "application/json")
+                        .header("authorization", bearer_token(user_id))
+                        .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+                .status()
+            }
+        };
+
+        assert_eq!(place("ETHUSDT", 3000, 2).await, StatusCode::BAD_REQUEST);
+        assert_eq!(place("ETHUSDT", 2000, 2).await, StatusCode::OK);
+        assert_eq!(place("BTCUSDT", 100, 1).await, StatusCode::BAD_REQUEST);
+
+        // 移除覆盖后恢复默认限额
+        let response = create_app(state.clone())
+            .oneshot(limits_request(admin.clone(), "DELETE", Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let account: ApiResponse<AccountLimits> = serde_json::from_slice(&body).unwrap();
+        assert!(!account.data.unwrap().overridden);
+        assert_eq!(place("BTCUSDT", 100, 1).await, StatusCode::OK);
+
+        let response = create_app(state)
+            .oneshot(limits_request(admin, "DELETE", Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// 测试：紧急停止撤销用户在所有交易对的挂单，并可禁止下单直到重置
+    #[tokio::test]
+    async fn test_kill_switch() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let (user_id, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let place = |user_id: Uuid, symbol: &str| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: symbol.to_string(),
+                side: OrderSide::Buy,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(100, 0)),
+                stop_price: None,
+                quantity: Decimal::ONE,
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/trading/orders")
+                        .header("content-type", "application/json")
+                        .header("authorization", bearer_token(user_id))
+                        .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+                .status()
+            }
+        };
+        let kill_switch = |token: String, body: serde_json::Value| {
+            create_app(state.clone()).oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/trading/killswitch")
+                    .header("content-type", "application/json")
+                    .header("authorization", token)
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+        };
+        let reset = |token: String, uri: String| {
+            create_app(state.clone()).oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri(uri)
+                    .header("authorization", token)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        assert_eq!(place(user_id, "BTCUSDT").await, StatusCode::OK);
+        assert_eq!(place(user_id, "ETHUSDT").await, StatusCode::OK);
+        assert_eq!(place(other, "ETHUSDT").await, StatusCode::OK);
+
+        // 普通用户不能对他人使用紧急停止
+        let response = kill_switch(bearer_token(user_id), serde_json::json!({ "user_id": other })).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = kill_switch(bearer_token(user_id), serde_json::json!({ "block": true })).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let outcome: ApiResponse<KillSwitchResponse> = serde_json::from_slice(&body).unwrap();
+        let outcome = outcome.data.unwrap();
+        assert_eq!(outcome.cancelled.len(), 2);
+        assert!(outcome.cancelled.iter().all(|order| order.user_id == user_id && order.status == OrderStatus::Cancelled));
+        assert!(outcome.block.is_some());
+        assert_eq!(state.store.user_open_orders(other).await.unwrap().len(), 1);
+
+        assert_eq!(place(user_id, "BTCUSDT").await, StatusCode::FORBIDDEN);
+        assert_eq!(place(other, "BTCUSDT").await, StatusCode::OK);
+
+        let response = reset(bearer_token(user_id), "/api/trading/killswitch".to_string()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(place(user_id, "BTCUSDT").await, StatusCode::OK);
+
+        // 管理员设置的禁止只能由管理员解除
+        let admin = bearer_token_with_role(Uuid::new_v4(), Role::Admin);
+        let response = kill_switch(admin.clone(), serde_json::json!({ "user_id": user_id, "block": true })).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = reset(bearer_token(user_id), "/api/trading/killswitch".to_string()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = reset(admin, format!("/api/trading/killswitch?user_id={}", user_id)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = reset(bearer_token(user_id), "/api/trading/killswitch".to_string()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// 测试：风控检查拒绝黑名单用户、受限交易对和偏离最新成交价的订单，并记录审计
+    #[tokio::test]
+    async fn test_pre_trade_risk_checks() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let (user_id, buyer, seller) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let admin = bearer_token_with_role(Uuid::new_v4(), Role::Admin);
+        let place = |user_id: Uuid, symbol: &str, side: OrderSide, price: i64| {
+            let app = create_app(state.clone());
+            let order_request = CreateOrderRequest {
+                trading_pair: symbol.to_string(),
+                side,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(price, 0)),
+                stop_price: None,
+                quantity: Decimal::new(1, 1),
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/trading/orders")
+                        .header("content-type", "application/json")
+                        .header("authorization", bearer_token(user_id))
+                        .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+                .status()
+            }
+        };
+        let admin_request = |token: String, method: &str, uri: String, body: Body| {
+            create_app(state.clone()).oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header("content-type", "application/json")
+                    .header("authorization", token)
+                    .body(body)
+                    .unwrap(),
+            )
+        };
+
+        // 在45000成交，之后以45000为参考价
+        assert_eq!(place(seller, "BTCUSDT", OrderSide::Sell, 45000).await, StatusCode::OK);
+        assert_eq!(place(buyer, "BTCUSDT", OrderSide::Buy, 45000).await, StatusCode::OK);
+        assert_eq!(place(user_id, "BTCUSDT", OrderSide::Buy, 60000).await, StatusCode::BAD_REQUEST);
+        assert_eq!(place(user_id, "BTCUSDT", OrderSide::Buy, 44000).await, StatusCode::OK);
+
+        // 非管理员不能管理黑名单
+        let blacklist_uri = format!("/api/admin/risk/blacklist/{}", user_id);
+        let response = admin_request(bearer_token(user_id), "PUT", blacklist_uri.clone(), Body::empty()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = admin_request(admin.clone(), "PUT", blacklist_uri.clone(), Body::empty()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(place(user_id, "ETHUSDT", OrderSide::Buy, 3000).await, StatusCode::FORBIDDEN);
+        let response = admin_request(admin.clone(), "DELETE", blacklist_uri.clone(), Body::empty()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = admin_request(admin.clone(), "DELETE", blacklist_uri, Body::empty()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let restrictions_uri = format!("/api/admin/risk/restrictions/{}", user_id);
+        let response = admin_request(admin.clone(), "PUT", restrictions_uri.clone(), Body::from(r#"["NOPE"]"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = admin_request(admin.clone(), "PUT", restrictions_uri, Body::from(r#"["ETHUSDT"]"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(place(user_id, "ETHUSDT", OrderSide::Buy, 3000).await, StatusCode::FORBIDDEN);
+        assert_eq!(place(user_id, "BTCUSDT", OrderSide::Buy, 44000).await, StatusCode::OK);
+
+        let response = admin_request(
+            admin,
+            "GET",
+            format!("/api/admin/risk/audit?user_id={}", user_id),
+            Body::empty(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let audit: ApiResponse<Vec<RiskAuditRecord>> = serde_json::from_slice(&body).unwrap();
+        let audit = audit.data.unwrap();
+        assert_eq!(audit.len(), 5);
+        assert!(audit[0].accepted);
+        assert!(!audit[1].accepted);
+        assert_eq!(audit[1].checks.last().unwrap().check, "restricted_symbols");
+        assert_eq!(audit[4].checks.last().unwrap().check, "price_band");
+        assert_eq!(audit[4].reference_price, Some(Decimal::new(45000, 0)));
+    }
+
+    /// 测试：订单接口需要认证，且用户只能查看和修改自己的订单
+    #[tokio::test]
+    async fn test_orders_are_scoped_to_user() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let get_orders = |authorization: Option<String>| {
+            let app = create_app(state.clone());
+            let mut request = Request::builder().uri("/api/trading/orders");
+            if let Some(authorization) = authorization {
+                request = request.header("authorization", authorization);
+            }
+            async move { app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap() }
+        };
+
+        // 未认证的请求被拒绝
+        assert_eq!(get_orders(None).await.status(), StatusCode::UNAUTHORIZED);
+
+        // 其他用户看不到测试用户的订单
+        let response = get_orders(Some(bearer_token(Uuid::new_v4()))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let api_response: ApiResponse<Page<Order>> = serde_json::from_slice(&body).unwrap();
+        assert!(api_response.data.unwrap().items.is_empty());
+
+        // 测试用户只看到自己的订单
+        let response = get_orders(Some(bearer_token(TEST_USER_ID))).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let api_response: ApiResponse<Page<Order>> = serde_json::from_slice(&body).unwrap();
+        let orders = api_response.data.unwrap().items;
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].user_id, TEST_USER_ID);
+
+        // 其他用户不能修改该订单
+        let response = create_app(state.clone())
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!("/api/trading/orders/{}", orders[0].id))
+                    .header("content-type", "application/json")
+                    .header("authorization", bearer_token(Uuid::new_v4()))
+                    .body(Body::from(r#"{"quantity": 1}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// 测试：订单历史按条件过滤并以游标分页
+    #[tokio::test]
+    async fn test_order_history_pagination() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let start = chrono::Utc::now() + chrono::Duration::minutes(1);
+        let template = state.store.order_history(TEST_USER_ID, &OrderHistoryQuery::default(), 1).await.unwrap().items[0].clone();
+        let orders: Vec<Order> = (0..5)
+            .map(|i| Order {
+                id: Uuid::new_v4(),
+                trading_pair: "ETHUSDT".to_string(),
+                side: if i % 2 == 0 { OrderSide::Buy } else { OrderSide::Sell },
+                created_at: start + chrono::Duration::seconds(i),
+                ..template.clone()
+            })
+            .collect();
+        state.store.save_orders(&orders).await.unwrap();
+        let get_page = |query: String| {
+            let app = create_app(state.clone());
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .uri(format!("/api/trading/orders?{}", query))
+                            .header("authorization", bearer_token(TEST_USER_ID))
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                let api_response: ApiResponse<Page<Order>> = serde_json::from_slice(&body).unwrap();
+                api_response.data.unwrap()
+            }
+        };
+
+        // 按交易对过滤，最新的订单在前
+        let first = get_page("symbol=ETHUSDT&limit=2".to_string()).await;
+        assert_eq!(first.items.len(), 2);
+        assert_eq!(first.items[0].created_at, start + chrono::Duration::seconds(4));
+        let cursor = String::from(first.next_cursor.unwrap());
+
+        // 沿游标翻页直到最后一页
+        let second = get_page(format!("symbol=ETHUSDT&limit=2&cursor={}", cursor)).await;
+        assert_eq!(second.items[0].created_at, start + chrono::Duration::seconds(2));
+        let last = get_page(format!("symbol=ETHUSDT&limit=2&cursor={}", String::from(second.next_cursor.unwrap()))).await;
+        assert_eq!(last.items.len(), 1);
+        assert!(last.next_cursor.is_none());
+
+        // 按方向过滤并升序排列
+        let sells = get_page("symbol=ETHUSDT&side=sell&sort=asc".to_string()).await;
+        assert_eq!(sells.items.len(), 2);
+        assert!(sells.items[0].created_at < sells.items[1].created_at);
+    }
+
+    /// 测试：公开成交记录隐去双方信息，个人成交记录只含本人的成交明细
+    #[tokio::test]
+    async fn test_trade_history() {
+        init_test_env();
+
+        let state = create_test_app_state();
+        let (maker, taker) = (Uuid::new_v4(), Uuid::new_v4());
+        for (user_id, side) in [(maker, OrderSide::Sell), (taker, OrderSide::Buy)] {
+            let order_request = CreateOrderRequest {
+                trading_pair: "ETHUSDT".to_string(),
+                side,
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(300000, 2)), // 3000.00
+                stop_price: None,
+                quantity: Decimal::new(2, 0),
+                time_in_force: TimeInForce::Gtc,
+                post_only: false,
+                display_quantity: None,
+                expires_at: None,
+                reduce_only: false,
+                client_order_id: None,
+            };
+            let response = create_app(state.clone())
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/trading/orders")
+                        .header("content-type", "application/json")
+                        .header("authorization", bearer_token(user_id))
+                        .body(Body::from(serde_json::to_string(&order_request).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let get = |uri: &str, user_id: Uuid| {
+            let app = create_app(state.clone());
+            let request = Request::builder()
+                .uri(uri)
+                .header("authorization", bearer_token(user_id))
+                .body(Body::empty())
+                .unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()
+            }
+        };
+
+        // 公开成交记录不暴露用户
+        let body = get("/api/trading/trades/ETHUSDT", taker).await;
+        let api_response: ApiResponse<Vec<Trade>> = serde_json::from_slice(&body).unwrap();
+        let trades = api_response.data.unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, Decimal::new(2, 0));
+        assert!(trades[0].maker_user_id.is_nil() && trades[0].taker_user_id.is_nil());
+
+        // 挂单方看到自己作为maker的成交
+        let body = get("/api/trading/mytrades?symbol=ETHUSDT", maker).await;
+        let api_response: ApiResponse<Page<Fill>> = serde_json::from_slice(&body).unwrap();
+        let fills = api_response.data.unwrap().items;
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].liquidity, flowex_types::Liquidity::Maker);
+        assert_eq!(fills[0].side, OrderSide::Sell);
+
+        // 其他用户没有成交记录
+        let body = get("/api/trading/mytrades", Uuid::new_v4()).await;
+        let api_response: ApiResponse<Page<Fill>> = serde_json::from_slice(&body).unwrap();
+        assert!(api_response.data.unwrap().items.is_empty());
+    }
+
     /// 测试：创建无效交易对订单
     #[tokio::test]
     async fn test_create_invalid_trading_pair_order() {
@@ -564,7 +3065,14 @@ mod tests {
             side: OrderSide::Buy,
             order_type: OrderType::Limit,
             price: Some(Decimal::new(100, 0)),
+            stop_price: None,
             quantity: Decimal::new(1, 0),
+            time_in_force: TimeInForce::Gtc,
+            post_only: false,
+            display_quantity: None,
+            expires_at: None,
+            reduce_only: false,
+            client_order_id: None,
         };
 
         let response = app
@@ -572,6 +3080,7 @@ mod tests {
                 Request::builder()
                     .method("POST")
                     .uri("/api/trading/orders")
+                    .header("authorization", bearer_token(TEST_USER_ID))
                     .header("content-type", "application/json")
                     .body(Body::from(serde_json::to_string(&order_request).unwrap()))
                     .unwrap(),
@@ -596,7 +3105,14 @@ mod tests {
             side: OrderSide::Buy,
             order_type: OrderType::Limit,
             price: Some(Decimal::new(45000, 0)),
+            stop_price: None,
             quantity: Decimal::ZERO, // 零数量
+            time_in_force: TimeInForce::Gtc,
+            post_only: false,
+            display_quantity: None,
+            expires_at: None,
+            reduce_only: false,
+            client_order_id: None,
         };
 
         let response = app
@@ -604,6 +3120,7 @@ mod tests {
                 Request::builder()
                     .method("POST")
                     .uri("/api/trading/orders")
+                    .header("authorization", bearer_token(TEST_USER_ID))
                     .header("content-type", "application/json")
                     .body(Body::from(serde_json::to_string(&zero_quantity_request).unwrap()))
                     .unwrap(),
@@ -625,25 +3142,10 @@ mod tests {
         let take_profit = OrderType::TakeProfit;
 
         // 验证订单类型可以正确创建和比较
-        match market {
-            OrderType::Market => assert!(true),
-            _ => assert!(false, "应该是市价单类型"),
-        }
-
-        match limit {
-            OrderType::Limit => assert!(true),
-            _ => assert!(false, "应该是限价单类型"),
-        }
-
-        match stop_loss {
-            OrderType::StopLoss => assert!(true),
-            _ => assert!(false, "应该是止损单类型"),
-        }
-
-        match take_profit {
-            OrderType::TakeProfit => assert!(true),
-            _ => assert!(false, "应该是止盈单类型"),
-        }
+        assert!(matches!(market, OrderType::Market), "应该是市价单类型");
+        assert!(matches!(limit, OrderType::Limit), "应该是限价单类型");
+        assert!(matches!(stop_loss, OrderType::StopLoss), "应该是止损单类型");
+        assert!(matches!(take_profit, OrderType::TakeProfit), "应该是止盈单类型");
     }
 
     /// 测试：订单状态枚举
@@ -659,35 +3161,12 @@ mod tests {
         let expired = OrderStatus::Expired;
 
         // 验证订单状态可以正确创建和比较
-        match new {
-            OrderStatus::New => assert!(true),
-            _ => assert!(false, "应该是新订单状态"),
-        }
-
-        match partially_filled {
-            OrderStatus::PartiallyFilled => assert!(true),
-            _ => assert!(false, "应该是部分成交状态"),
-        }
-
-        match filled {
-            OrderStatus::Filled => assert!(true),
-            _ => assert!(false, "应该是完全成交状态"),
-        }
-
-        match cancelled {
-            OrderStatus::Cancelled => assert!(true),
-            _ => assert!(false, "应该是已取消状态"),
-        }
-
-        match rejected {
-            OrderStatus::Rejected => assert!(true),
-            _ => assert!(false, "应该是已拒绝状态"),
-        }
-
-        match expired {
-            OrderStatus::Expired => assert!(true),
-            _ => assert!(false, "应该是已过期状态"),
-        }
+        assert!(matches!(new, OrderStatus::New), "应该是新订单状态");
+        assert!(matches!(partially_filled, OrderStatus::PartiallyFilled), "应该是部分成交状态");
+        assert!(matches!(filled, OrderStatus::Filled), "应该是完全成交状态");
+        assert!(matches!(cancelled, OrderStatus::Cancelled), "应该是已取消状态");
+        assert!(matches!(rejected, OrderStatus::Rejected), "应该是已拒绝状态");
+        assert!(matches!(expired, OrderStatus::Expired), "应该是已过期状态");
     }
 
     /// 测试：并发访问安全性
@@ -708,9 +3187,8 @@ mod tests {
                 drop(trading_pairs);
 
                 // 并发读取订单数据
-                let orders = state_clone.orders.read().await;
-                let order_count = orders.len();
-                drop(orders);
+                let orders = state_clone.store.order_history(TEST_USER_ID, &OrderHistoryQuery::default(), 10).await.unwrap();
+                let order_count = orders.items.len();
 
                 (i, pair_count, order_count)
             });
@@ -739,7 +3217,7 @@ mod tests {
             let state_clone = state.clone();
             let handle = tokio::spawn(async move {
                 let _trading_pairs = state_clone.trading_pairs.read().await;
-                let _orders = state_clone.orders.read().await;
+                let _orders = state_clone.store.order_history(TEST_USER_ID, &OrderHistoryQuery::default(), 10).await;
             });
             handles.push(handle);
         }
@@ -766,13 +3244,14 @@ mod tests {
             symbol: "BTCUSDT".to_string(),
             base_asset: "BTC".to_string(),
             quote_asset: "USDT".to_string(),
-            status: "TRADING".to_string(),
+            status: TradingStatus::Trading,
             min_price: Decimal::new(1, 8),
             max_price: Decimal::new(99999999999999999, 8),
             min_qty: Decimal::new(1, 8),
             max_qty: Decimal::new(99999999999999999, 8),
             step_size: Decimal::new(1, 8),
             tick_size: Decimal::new(1, 8),
+            min_notional: Decimal::ZERO,
         };
 
         // 验证交易对关系
@@ -791,9 +3270,16 @@ mod tests {
             side: OrderSide::Buy,
             order_type: OrderType::Limit,
             price: Some(Decimal::new(300000, 2)), // 3000.00
+            stop_price: None,
             quantity: Decimal::new(100, 2), // 1.00
             filled_quantity: Decimal::ZERO,
             remaining_quantity: Decimal::new(100, 2), // 1.00
+            time_in_force: TimeInForce::Gtc,
+            post_only: false,
+            display_quantity: None,
+            expires_at: None,
+            reduce_only: false,
+            client_order_id: None,
             status: OrderStatus::New,
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
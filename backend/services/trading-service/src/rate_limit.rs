@@ -0,0 +1,264 @@
+//! Order rate limits
+//!
+//! Every order request spends its weight from two fixed-window budgets: the
+//! caller's, across all symbols, and the symbol's, shared by every user so
+//! no burst can swamp one matching engine. Mass cancels weigh more than
+//! placing or modifying a single order. Counters live in Redis when the
+//! service runs with `REDIS_URL`, so the budgets hold across instances, and
+//! in memory otherwise. A request over budget is answered with HTTP 429 and
+//! a `Retry-After` of the time left in the window, and counted in the
+//! `flowex_rate_limit_violations_total` metric.
+//!
+//! The limiter fails open: if Redis is unreachable, orders are not refused.
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use flowex_cache::{CacheError, CacheManager};
+use flowex_metrics::MetricsCollector;
+use flowex_types::ApiResponse;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Weight of placing an order
+pub const PLACE_ORDER_WEIGHT: u64 = 1;
+/// Weight of modifying an order
+pub const MODIFY_ORDER_WEIGHT: u64 = 1;
+/// Weight of a mass cancel
+pub const CANCEL_ORDERS_WEIGHT: u64 = 5;
+
+/// Redis key prefix of rate limit counters
+const KEY_PREFIX: &str = "flowex:trading:rate";
+
+/// Order rate limit configuration
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Length of each budget window
+    pub window: Duration,
+    /// Weight one user may spend per window
+    pub user_limit: u64,
+    /// Weight all users together may spend on one symbol per window
+    pub symbol_limit: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(1),
+            user_limit: 10,
+            symbol_limit: 1000,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Defaults, with limits overridden by `ORDER_RATE_LIMIT_PER_USER` and
+    /// `ORDER_RATE_LIMIT_PER_SYMBOL` (weight per second)
+    pub fn from_env() -> Self {
+        let limit = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        let defaults = Self::default();
+        Self {
+            user_limit: limit("ORDER_RATE_LIMIT_PER_USER", defaults.user_limit),
+            symbol_limit: limit("ORDER_RATE_LIMIT_PER_SYMBOL", defaults.symbol_limit),
+            ..defaults
+        }
+    }
+}
+
+/// Budget a request exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitScope {
+    User,
+    Symbol,
+}
+
+impl LimitScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitScope::User => "user",
+            LimitScope::Symbol => "symbol",
+        }
+    }
+}
+
+/// A request refused for exceeding a budget
+#[derive(Debug)]
+pub struct RateLimited {
+    pub scope: LimitScope,
+    /// Time left until the budget is refilled
+    pub retry_after: Duration,
+}
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        // Whole seconds, rounded up
+        let retry_after = self.retry_after.as_millis().div_ceil(1000).max(1);
+        let body = ApiResponse::<()>::error(format!("Order rate limit exceeded for {}", self.scope.as_str()));
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            Json(body),
+        )
+            .into_response()
+    }
+}
+
+/// Window counters, by key
+#[derive(Clone)]
+enum Counters {
+    /// Count of each key in the window it was last used in
+    Memory(Arc<RwLock<HashMap<String, (u64, u64)>>>),
+    Redis(Box<CacheManager>),
+}
+
+/// Order rate limiter
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    counters: Counters,
+    metrics: MetricsCollector,
+}
+
+impl RateLimiter {
+    /// Limiter counting in this instance's memory
+    pub fn in_memory(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            counters: Counters::Memory(Arc::new(RwLock::new(HashMap::new()))),
+            metrics: MetricsCollector::new(),
+        }
+    }
+
+    /// Limiter counting in Redis, shared by every instance
+    pub async fn connect(config: RateLimitConfig, redis_url: &str) -> Result<Self, CacheError> {
+        let cache = CacheManager::new(redis_url, config.window).await?;
+        Ok(Self {
+            counters: Counters::Redis(Box::new(cache)),
+            ..Self::in_memory(config)
+        })
+    }
+
+    /// Spend `weight` from `user_id`'s budget and, if the request names one,
+    /// from `symbol`'s
+    pub async fn check(&self, user_id: Uuid, symbol: Option<&str>, weight: u64) -> Result<(), RateLimited> {
+        self.spend(LimitScope::User, &user_id.to_string(), self.config.user_limit, weight)
+            .await?;
+        if let Some(symbol) = symbol {
+            self.spend(LimitScope::Symbol, symbol, self.config.symbol_limit, weight)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn spend(&self, scope: LimitScope, subject: &str, limit: u64, weight: u64) -> Result<(), RateLimited> {
+        let window_ms = self.config.window.as_millis().max(1) as u64;
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let window = now_ms / window_ms;
+        let key = format!("{}:{}:{}:{}", KEY_PREFIX, scope.as_str(), subject, window);
+
+        let spent = match self.add(&key, window, weight).await {
+            Ok(spent) => spent,
+            Err(e) => {
+                warn!("Order rate limiter unavailable, letting the request through: {}", e);
+                return Ok(());
+            }
+        };
+        if spent <= limit {
+            return Ok(());
+        }
+
+        debug!("Order rate limit for {} {} exceeded: {}/{}", scope.as_str(), subject, spent, limit);
+        self.metrics.record_rate_limit_violation("trading", scope.as_str());
+        Err(RateLimited {
+            scope,
+            retry_after: Duration::from_millis((window + 1) * window_ms - now_ms),
+        })
+    }
+
+    /// Add `weight` to the counter of `key` in `window`; returns its total
+    async fn add(&self, key: &str, window: u64, weight: u64) -> Result<u64, CacheError> {
+        match &self.counters {
+            Counters::Memory(counters) => {
+                let mut counters = counters.write().await;
+                counters.retain(|_, (_, counted_in)| *counted_in >= window);
+                let (count, _) = counters.entry(key.to_string()).or_insert((0, window));
+                *count += weight;
+                Ok(*count)
+            }
+            Counters::Redis(cache) => {
+                let spent = cache.increment(key, weight as i64).await?;
+                if spent == weight as i64 {
+                    // First use of the window's key; let it outlive the
+                    // window only briefly
+                    cache.expire(key, self.config.window * 2).await?;
+                }
+                Ok(spent.max(0) as u64)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(user_limit: u64, symbol_limit: u64) -> RateLimiter {
+        RateLimiter::in_memory(RateLimitConfig {
+            // A window long enough that the test never crosses into the next
+            window: Duration::from_secs(3600),
+            user_limit,
+            symbol_limit,
+        })
+    }
+
+    /// 测试：按用户和交易对的权重预算限流，超限后返回重试等待时间
+    #[tokio::test]
+    async fn test_user_and_symbol_budgets() {
+        let limiter = limiter(10, 12);
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+        for _ in 0..5 {
+            limiter.check(alice, Some("BTCUSDT"), PLACE_ORDER_WEIGHT).await.unwrap();
+        }
+        limiter.check(alice, None, CANCEL_ORDERS_WEIGHT).await.unwrap();
+        let limited = limiter.check(alice, Some("ETHUSDT"), PLACE_ORDER_WEIGHT).await.unwrap_err();
+        assert_eq!(limited.scope, LimitScope::User);
+        assert!(limited.retry_after > Duration::ZERO);
+        assert!(limited.retry_after <= Duration::from_secs(3600));
+
+        // 交易对预算由所有用户共享
+        for _ in 0..7 {
+            limiter.check(bob, Some("BTCUSDT"), PLACE_ORDER_WEIGHT).await.unwrap();
+        }
+        let limited = limiter.check(bob, Some("BTCUSDT"), PLACE_ORDER_WEIGHT).await.unwrap_err();
+        assert_eq!(limited.scope, LimitScope::Symbol);
+        limiter.check(bob, Some("ETHUSDT"), PLACE_ORDER_WEIGHT).await.unwrap();
+    }
+
+    /// 测试：限流响应为429并携带Retry-After头
+    #[test]
+    fn test_rate_limited_response() {
+        let response = RateLimited {
+            scope: LimitScope::User,
+            retry_after: Duration::from_millis(250),
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    }
+}
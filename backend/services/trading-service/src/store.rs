@@ -0,0 +1,963 @@
+//! Order and trade storage
+//!
+//! `Store` holds the service's orders and executed trades: in PostgreSQL
+//! through `flowex-database`, so they survive restarts, or in memory when no
+//! database is configured. An execution — the submitted order's state after
+//! matching, its trades and the fills they apply to the resting orders on
+//! the other side — is written in one transaction, so an order's status
+//! never disagrees with the trades recorded for it.
+//!
+//! On startup `restore_engine` rebuilds a symbol's matching engine from the
+//! open orders in the store, in placement order, continuing the trade
+//! sequence from the last recorded trade.
+//!
+//! It also keeps the dead-letter queue's entries (see `dlq`), so events
+//! parked before a restart can still be replayed after it, the order
+//! webhooks users registered (see `webhooks`) and the order limits admins
+//! set for individual accounts (see `limits`).
+
+use crate::dlq::{DeadLetter, DeadLetterStatus, HandlerKind};
+use crate::limits::OrderLimits;
+use crate::webhooks::{Webhook, WebhookEventType};
+use chrono::{DateTime, NaiveDate, Utc};
+use flowex_database::DatabasePool;
+use flowex_matching_engine::trigger::TriggerDirection;
+use flowex_matching_engine::MatchingEngine;
+use flowex_types::{
+    Cursor, Fill, FlowExError, FlowExResult, Order, OrderHistoryQuery, OrderSide, OrderStatus, OrderType, Page,
+    SortOrder, TimeInForce, Trade, TradeHistoryQuery, TradingPair,
+};
+use rust_decimal::Decimal;
+use sqlx::postgres::PgRow;
+use sqlx::{PgConnection, Postgres, QueryBuilder, Row};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+const ORDER_COLUMNS: &str = "id, user_id, trading_pair, side, order_type, price, stop_price, quantity, \
+    filled_quantity, remaining_quantity, time_in_force, post_only, display_quantity, expires_at, reduce_only, \
+    client_order_id, status, created_at, updated_at";
+
+const TRADE_COLUMNS: &str = "id, symbol, sequence, price, quantity, buyer_order_id, seller_order_id, \
+    buyer_user_id, seller_user_id, is_buyer_maker, buyer_fee, buyer_fee_currency, seller_fee, \
+    seller_fee_currency, created_at";
+
+const DEAD_LETTER_COLUMNS: &str =
+    "id, handler, event_type, payload, error, attempts, status, first_failed_at, last_failed_at, resolved_at";
+
+const WEBHOOK_COLUMNS: &str = "id, user_id, url, events, secret, active, created_at";
+
+/// Orders and trades of the trading service
+#[derive(Clone)]
+pub enum Store {
+    /// Kept in process and lost on restart
+    Memory(Arc<RwLock<MemoryStore>>),
+    /// Kept in PostgreSQL
+    Postgres(DatabasePool),
+}
+
+/// Contents of an in-memory store
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    orders: HashMap<Uuid, Order>,
+    /// Executed trades of every symbol, in execution order
+    trades: Vec<Trade>,
+    dead_letters: HashMap<Uuid, DeadLetter>,
+    webhooks: HashMap<Uuid, Webhook>,
+    order_limit_overrides: HashMap<Uuid, OrderLimits>,
+}
+
+impl Store {
+    /// Store that keeps everything in memory
+    pub fn in_memory() -> Self {
+        Store::Memory(Arc::new(RwLock::new(MemoryStore::default())))
+    }
+
+    /// In-memory store holding `orders`
+    #[cfg(test)]
+    pub fn in_memory_with_orders(orders: Vec<Order>) -> Self {
+        let orders = orders.into_iter().map(|order| (order.id, order)).collect();
+        Store::Memory(Arc::new(RwLock::new(MemoryStore { orders, ..MemoryStore::default() })))
+    }
+
+    /// Store backed by the PostgreSQL database at `database_url`
+    pub async fn connect(database_url: &str) -> FlowExResult<Self> {
+        let pool = DatabasePool::new(database_url).await.map_err(database_error)?;
+        Ok(Store::Postgres(pool))
+    }
+
+    /// Order by id
+    pub async fn order(&self, order_id: Uuid) -> FlowExResult<Option<Order>> {
+        match self {
+            Store::Memory(memory) => Ok(memory.read().await.orders.get(&order_id).cloned()),
+            Store::Postgres(pool) => {
+                let sql = format!("SELECT {} FROM orders WHERE id = $1", ORDER_COLUMNS);
+                let row = sqlx::query(&sql)
+                    .bind(order_id)
+                    .fetch_optional(pool.pool())
+                    .await
+                    .map_err(database_error)?;
+                row.as_ref().map(order_from_row).transpose().map_err(database_error)
+            }
+        }
+    }
+
+    /// A page of `limit` of a user's orders selected by `query`
+    pub async fn order_history(&self, user_id: Uuid, query: &OrderHistoryQuery, limit: usize) -> FlowExResult<Page<Order>> {
+        let position = |order: &Order| Cursor { timestamp: order.created_at, id: order.id };
+        match self {
+            Store::Memory(memory) => {
+                let orders: Vec<Order> = memory
+                    .read()
+                    .await
+                    .orders
+                    .values()
+                    .filter(|order| order.user_id == user_id && query.matches(order))
+                    .cloned()
+                    .collect();
+                Ok(Page::paginate(orders, position, query.sort, query.cursor, limit))
+            }
+            Store::Postgres(pool) => {
+                let mut sql = QueryBuilder::<Postgres>::new(format!("SELECT {} FROM orders WHERE user_id = ", ORDER_COLUMNS));
+                sql.push_bind(user_id);
+                if let Some(status) = &query.status {
+                    sql.push(" AND status = ").push_bind(status_to_db(status));
+                }
+                if let Some(symbol) = &query.symbol {
+                    sql.push(" AND trading_pair = ").push_bind(symbol.clone());
+                }
+                if let Some(side) = &query.side {
+                    sql.push(" AND side = ").push_bind(side_to_db(side));
+                }
+                push_page_bounds(&mut sql, query.start_time, query.end_time, query.cursor, query.sort, limit);
+
+                let rows = sql.build().fetch_all(pool.pool()).await.map_err(database_error)?;
+                let orders = rows.iter().map(order_from_row).collect::<Result<Vec<_>, _>>().map_err(database_error)?;
+                Ok(Page::paginate(orders, position, query.sort, None, limit))
+            }
+        }
+    }
+
+    /// Open orders on `symbol`, oldest first
+    pub async fn open_orders(&self, symbol: &str) -> FlowExResult<Vec<Order>> {
+        match self {
+            Store::Memory(memory) => {
+                let mut orders: Vec<Order> = memory
+                    .read()
+                    .await
+                    .orders
+                    .values()
+                    .filter(|order| order.trading_pair == symbol && is_open(order))
+                    .cloned()
+                    .collect();
+                orders.sort_by_key(|order| order.created_at);
+                Ok(orders)
+            }
+            Store::Postgres(pool) => {
+                let sql = format!(
+                    "SELECT {} FROM orders WHERE trading_pair = $1 AND status IN ('NEW', 'PARTIALLY_FILLED') \
+                     ORDER BY created_at, id",
+                    ORDER_COLUMNS
+                );
+                let rows = sqlx::query(&sql)
+                    .bind(symbol)
+                    .fetch_all(pool.pool())
+                    .await
+                    .map_err(database_error)?;
+                rows.iter().map(order_from_row).collect::<Result<_, _>>().map_err(database_error)
+            }
+        }
+    }
+
+    /// A user's open orders on every symbol
+    pub async fn user_open_orders(&self, user_id: Uuid) -> FlowExResult<Vec<Order>> {
+        match self {
+            Store::Memory(memory) => Ok(memory
+                .read()
+                .await
+                .orders
+                .values()
+                .filter(|order| order.user_id == user_id && is_open(order))
+                .cloned()
+                .collect()),
+            Store::Postgres(pool) => {
+                let sql = format!(
+                    "SELECT {} FROM orders WHERE user_id = $1 AND status IN ('NEW', 'PARTIALLY_FILLED')",
+                    ORDER_COLUMNS
+                );
+                let rows = sqlx::query(&sql)
+                    .bind(user_id)
+                    .fetch_all(pool.pool())
+                    .await
+                    .map_err(database_error)?;
+                rows.iter().map(order_from_row).collect::<Result<_, _>>().map_err(database_error)
+            }
+        }
+    }
+
+    /// Store orders whose state changed without trading, e.g. on cancel or expiry
+    pub async fn save_orders(&self, orders: &[Order]) -> FlowExResult<()> {
+        if orders.is_empty() {
+            return Ok(());
+        }
+        match self {
+            Store::Memory(memory) => {
+                let mut memory = memory.write().await;
+                for order in orders {
+                    memory.orders.insert(order.id, order.clone());
+                }
+                Ok(())
+            }
+            Store::Postgres(pool) => {
+                let mut tx = pool.begin_transaction().await.map_err(database_error)?;
+                for order in orders {
+                    upsert_order(&mut tx, order).await.map_err(database_error)?;
+                }
+                tx.commit().await.map_err(database_error)
+            }
+        }
+    }
+
+    /// Store `order` as it stands after matching, together with its trades,
+    /// applying their fills to the resting orders it traded against; returns
+    /// those resting orders as updated
+    pub async fn record_execution(&self, order: &Order, trades: &[Trade]) -> FlowExResult<Vec<Order>> {
+        let mut resting: HashMap<Uuid, Order> = HashMap::new();
+        match self {
+            Store::Memory(memory) => {
+                let mut memory = memory.write().await;
+                for trade in trades {
+                    for order_id in [trade.maker_order_id, trade.taker_order_id] {
+                        if order_id == order.id {
+                            continue;
+                        }
+                        if let Some(counterparty) = memory.orders.get_mut(&order_id) {
+                            apply_fill(counterparty, trade);
+                            resting.insert(order_id, counterparty.clone());
+                        }
+                    }
+                }
+                memory.orders.insert(order.id, order.clone());
+                memory.trades.extend_from_slice(trades);
+            }
+            Store::Postgres(pool) => {
+                let mut tx = pool.begin_transaction().await.map_err(database_error)?;
+                upsert_order(&mut tx, order).await.map_err(database_error)?;
+                for trade in trades {
+                    for order_id in [trade.maker_order_id, trade.taker_order_id] {
+                        if order_id == order.id {
+                            continue;
+                        }
+                        if let Some(counterparty) = update_fill(&mut tx, order_id, trade).await.map_err(database_error)? {
+                            resting.insert(order_id, counterparty);
+                        }
+                    }
+                    insert_trade(&mut tx, trade).await.map_err(database_error)?;
+                }
+                tx.commit().await.map_err(database_error)?;
+            }
+        }
+        Ok(resting.into_values().collect())
+    }
+
+    /// The `limit` most recent trades on `symbol`, newest first
+    pub async fn recent_trades(&self, symbol: &str, limit: usize) -> FlowExResult<Vec<Trade>> {
+        match self {
+            Store::Memory(memory) => Ok(memory
+                .read()
+                .await
+                .trades
+                .iter()
+                .rev()
+                .filter(|trade| trade.symbol == symbol)
+                .take(limit)
+                .cloned()
+                .collect()),
+            Store::Postgres(pool) => {
+                let sql = format!(
+                    "SELECT {} FROM trades WHERE symbol = $1 ORDER BY sequence DESC, created_at DESC LIMIT $2",
+                    TRADE_COLUMNS
+                );
+                let rows = sqlx::query(&sql)
+                    .bind(symbol)
+                    .bind(limit as i64)
+                    .fetch_all(pool.pool())
+                    .await
+                    .map_err(database_error)?;
+                rows.iter().map(trade_from_row).collect::<Result<_, _>>().map_err(database_error)
+            }
+        }
+    }
+
+    /// A page of `limit` of a user's fills selected by `query`
+    pub async fn fill_history(&self, user_id: Uuid, query: &TradeHistoryQuery, limit: usize) -> FlowExResult<Page<Fill>> {
+        let position = |fill: &Fill| Cursor { timestamp: fill.timestamp, id: fill.trade_id };
+        let user_fills = |trade: &Trade| {
+            trade
+                .fills()
+                .into_iter()
+                .filter(|fill| fill.user_id == user_id && query.matches(fill))
+                .collect::<Vec<_>>()
+        };
+        match self {
+            Store::Memory(memory) => {
+                let fills: Vec<Fill> = memory.read().await.trades.iter().flat_map(user_fills).collect();
+                Ok(Page::paginate(fills, position, query.sort, query.cursor, limit))
+            }
+            Store::Postgres(pool) => {
+                let mut sql = QueryBuilder::<Postgres>::new(format!("SELECT {} FROM trades WHERE (buyer_user_id = ", TRADE_COLUMNS));
+                sql.push_bind(user_id).push(" OR seller_user_id = ").push_bind(user_id).push(")");
+                if let Some(symbol) = &query.symbol {
+                    sql.push(" AND symbol = ").push_bind(symbol.clone());
+                }
+                push_page_bounds(&mut sql, query.start_time, query.end_time, query.cursor, query.sort, limit);
+
+                let rows = sql.build().fetch_all(pool.pool()).await.map_err(database_error)?;
+                let trades = rows.iter().map(trade_from_row).collect::<Result<Vec<_>, _>>().map_err(database_error)?;
+                let fills = trades.iter().flat_map(user_fills).collect();
+                Ok(Page::paginate(fills, position, query.sort, None, limit))
+            }
+        }
+    }
+
+    /// The last trade executed on `symbol`
+    pub async fn last_trade(&self, symbol: &str) -> FlowExResult<Option<Trade>> {
+        Ok(self.recent_trades(symbol, 1).await?.pop())
+    }
+
+    /// Notional each user traded per day since `since`, counting a trade
+    /// once for each of its two users
+    pub async fn daily_volumes(&self, since: DateTime<Utc>) -> FlowExResult<Vec<(Uuid, NaiveDate, Decimal)>> {
+        match self {
+            Store::Memory(memory) => {
+                let mut volumes: HashMap<(Uuid, NaiveDate), Decimal> = HashMap::new();
+                for trade in memory.read().await.trades.iter().filter(|trade| trade.timestamp >= since) {
+                    let mut users = vec![trade.maker_user_id];
+                    if trade.taker_user_id != trade.maker_user_id {
+                        users.push(trade.taker_user_id);
+                    }
+                    for user_id in users {
+                        *volumes.entry((user_id, trade.timestamp.date_naive())).or_default() += trade.price * trade.quantity;
+                    }
+                }
+                Ok(volumes.into_iter().map(|((user_id, date), volume)| (user_id, date, volume)).collect())
+            }
+            Store::Postgres(pool) => {
+                let rows = sqlx::query(
+                    "SELECT user_id, day, SUM(notional) AS volume FROM ( \
+                         SELECT buyer_user_id AS user_id, (created_at AT TIME ZONE 'UTC')::date AS day, \
+                                price * quantity AS notional \
+                         FROM trades WHERE created_at >= $1 \
+                         UNION ALL \
+                         SELECT seller_user_id, (created_at AT TIME ZONE 'UTC')::date, price * quantity \
+                         FROM trades WHERE created_at >= $1 AND seller_user_id IS DISTINCT FROM buyer_user_id \
+                     ) volumes WHERE user_id IS NOT NULL GROUP BY user_id, day",
+                )
+                .bind(since)
+                .fetch_all(pool.pool())
+                .await
+                .map_err(database_error)?;
+                rows.iter()
+                    .map(|row| Ok((row.try_get("user_id")?, row.try_get("day")?, row.try_get("volume")?)))
+                    .collect::<Result<_, sqlx::Error>>()
+                    .map_err(database_error)
+            }
+        }
+    }
+
+    /// A matching engine for `trading_pair` holding its open orders
+    pub async fn restore_engine(&self, trading_pair: TradingPair) -> FlowExResult<MatchingEngine> {
+        let open_orders = self.open_orders(&trading_pair.symbol).await?;
+        let last_trade = self.last_trade(&trading_pair.symbol).await?;
+
+        let mut snapshot = MatchingEngine::with_trading_pair(trading_pair).snapshot();
+        for order in open_orders {
+            if TriggerDirection::for_order(&order).is_some() {
+                snapshot.triggers.push(order);
+            } else if order.side == OrderSide::Buy {
+                snapshot.bids.push(order);
+            } else {
+                snapshot.asks.push(order);
+            }
+        }
+        if let Some(trade) = last_trade {
+            snapshot.trade_sequence = trade.sequence;
+            snapshot.last_trade_price = Some(trade.price);
+        }
+        MatchingEngine::restore(snapshot)
+    }
+
+    /// Every dead letter kept, whatever its status
+    pub async fn dead_letters(&self) -> FlowExResult<Vec<DeadLetter>> {
+        match self {
+            Store::Memory(memory) => Ok(memory.read().await.dead_letters.values().cloned().collect()),
+            Store::Postgres(pool) => {
+                let sql = format!("SELECT {} FROM dead_letter_events", DEAD_LETTER_COLUMNS);
+                let rows = sqlx::query(&sql).fetch_all(pool.pool()).await.map_err(database_error)?;
+                rows.iter().map(dead_letter_from_row).collect::<Result<_, _>>().map_err(database_error)
+            }
+        }
+    }
+
+    /// Insert a dead letter, or update its attempts and status
+    pub async fn save_dead_letter(&self, entry: &DeadLetter) -> FlowExResult<()> {
+        match self {
+            Store::Memory(memory) => {
+                memory.write().await.dead_letters.insert(entry.id, entry.clone());
+            }
+            Store::Postgres(pool) => {
+                let sql = format!(
+                    "INSERT INTO dead_letter_events ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+                     ON CONFLICT (id) DO UPDATE SET error = EXCLUDED.error, attempts = EXCLUDED.attempts, \
+                     status = EXCLUDED.status, last_failed_at = EXCLUDED.last_failed_at, \
+                     resolved_at = EXCLUDED.resolved_at",
+                    DEAD_LETTER_COLUMNS
+                );
+                sqlx::query(&sql)
+                    .bind(entry.id)
+                    .bind(handler_to_db(entry.handler))
+                    .bind(&entry.event_type)
+                    .bind(&entry.payload)
+                    .bind(&entry.error)
+                    .bind(entry.attempts as i32)
+                    .bind(dead_letter_status_to_db(entry.status))
+                    .bind(entry.first_failed_at)
+                    .bind(entry.last_failed_at)
+                    .bind(entry.resolved_at)
+                    .execute(pool.pool())
+                    .await
+                    .map_err(database_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Every registered webhook
+    pub async fn webhooks(&self) -> FlowExResult<Vec<Webhook>> {
+        match self {
+            Store::Memory(memory) => Ok(memory.read().await.webhooks.values().cloned().collect()),
+            Store::Postgres(pool) => {
+                let sql = format!("SELECT {} FROM webhooks", WEBHOOK_COLUMNS);
+                let rows = sqlx::query(&sql).fetch_all(pool.pool()).await.map_err(database_error)?;
+                rows.iter().map(webhook_from_row).collect::<Result<_, _>>().map_err(database_error)
+            }
+        }
+    }
+
+    /// Insert a newly registered webhook
+    pub async fn insert_webhook(&self, webhook: &Webhook) -> FlowExResult<()> {
+        match self {
+            Store::Memory(memory) => {
+                memory.write().await.webhooks.insert(webhook.id, webhook.clone());
+            }
+            Store::Postgres(pool) => {
+                let sql = format!("INSERT INTO webhooks ({}) VALUES ($1, $2, $3, $4, $5, $6, $7)", WEBHOOK_COLUMNS);
+                let events: Vec<&str> = webhook.events.iter().map(|event| event.as_str()).collect();
+                sqlx::query(&sql)
+                    .bind(webhook.id)
+                    .bind(webhook.user_id)
+                    .bind(&webhook.url)
+                    .bind(events)
+                    .bind(&webhook.secret)
+                    .bind(webhook.active)
+                    .bind(webhook.created_at)
+                    .execute(pool.pool())
+                    .await
+                    .map_err(database_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete a webhook
+    pub async fn delete_webhook(&self, webhook_id: Uuid) -> FlowExResult<()> {
+        match self {
+            Store::Memory(memory) => {
+                memory.write().await.webhooks.remove(&webhook_id);
+            }
+            Store::Postgres(pool) => {
+                sqlx::query("DELETE FROM webhooks WHERE id = $1")
+                    .bind(webhook_id)
+                    .execute(pool.pool())
+                    .await
+                    .map_err(database_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Order limits set for individual accounts
+    pub async fn order_limit_overrides(&self) -> FlowExResult<Vec<(Uuid, OrderLimits)>> {
+        match self {
+            Store::Memory(memory) => Ok(memory
+                .read()
+                .await
+                .order_limit_overrides
+                .iter()
+                .map(|(user_id, limits)| (*user_id, limits.clone()))
+                .collect()),
+            Store::Postgres(pool) => {
+                let rows = sqlx::query("SELECT user_id, max_open_orders, max_symbol_exposure FROM order_limit_overrides")
+                    .fetch_all(pool.pool())
+                    .await
+                    .map_err(database_error)?;
+                rows.iter()
+                    .map(|row| {
+                        let max_open_orders: i32 = row.try_get("max_open_orders")?;
+                        let limits = OrderLimits {
+                            max_open_orders: max_open_orders.max(0) as usize,
+                            max_symbol_exposure: row.try_get("max_symbol_exposure")?,
+                        };
+                        Ok((row.try_get("user_id")?, limits))
+                    })
+                    .collect::<Result<_, sqlx::Error>>()
+                    .map_err(database_error)
+            }
+        }
+    }
+
+    /// Set, or replace, an account's order limits
+    pub async fn save_order_limit_override(&self, user_id: Uuid, limits: &OrderLimits) -> FlowExResult<()> {
+        match self {
+            Store::Memory(memory) => {
+                memory.write().await.order_limit_overrides.insert(user_id, limits.clone());
+            }
+            Store::Postgres(pool) => {
+                let max_open_orders = i32::try_from(limits.max_open_orders)
+                    .map_err(|_| FlowExError::Validation("Open order limit is too large".to_string()))?;
+                sqlx::query(
+                    "INSERT INTO order_limit_overrides (user_id, max_open_orders, max_symbol_exposure) \
+                     VALUES ($1, $2, $3) ON CONFLICT (user_id) DO UPDATE SET \
+                     max_open_orders = EXCLUDED.max_open_orders, \
+                     max_symbol_exposure = EXCLUDED.max_symbol_exposure, updated_at = NOW()",
+                )
+                .bind(user_id)
+                .bind(max_open_orders)
+                .bind(limits.max_symbol_exposure)
+                .execute(pool.pool())
+                .await
+                .map_err(database_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove an account's order limits; false if it had none
+    pub async fn delete_order_limit_override(&self, user_id: Uuid) -> FlowExResult<bool> {
+        match self {
+            Store::Memory(memory) => Ok(memory.write().await.order_limit_overrides.remove(&user_id).is_some()),
+            Store::Postgres(pool) => {
+                let result = sqlx::query("DELETE FROM order_limit_overrides WHERE user_id = $1")
+                    .bind(user_id)
+                    .execute(pool.pool())
+                    .await
+                    .map_err(database_error)?;
+                Ok(result.rows_affected() > 0)
+            }
+        }
+    }
+}
+
+/// Apply one trade's fill to an order on either side of it
+pub fn apply_fill(order: &mut Order, trade: &Trade) {
+    order.filled_quantity += trade.quantity;
+    order.remaining_quantity = (order.quantity - order.filled_quantity).max(Decimal::ZERO);
+    order.status = if order.remaining_quantity.is_zero() {
+        OrderStatus::Filled
+    } else {
+        OrderStatus::PartiallyFilled
+    };
+    order.updated_at = trade.timestamp;
+}
+
+/// Whether an order can still trade
+pub fn is_open(order: &Order) -> bool {
+    matches!(order.status, OrderStatus::New | OrderStatus::PartiallyFilled)
+}
+
+fn database_error(error: sqlx::Error) -> FlowExError {
+    FlowExError::Database(error.to_string())
+}
+
+/// Restrict a history query to `[start, end)` and to after `cursor`, and
+/// fetch one row beyond the page so `Page::paginate` can tell if more follow
+fn push_page_bounds(
+    sql: &mut QueryBuilder<'_, Postgres>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    cursor: Option<Cursor>,
+    sort: SortOrder,
+    limit: usize,
+) {
+    if let Some(start) = start {
+        sql.push(" AND created_at >= ").push_bind(start);
+    }
+    if let Some(end) = end {
+        sql.push(" AND created_at < ").push_bind(end);
+    }
+    if let Some(cursor) = cursor {
+        sql.push(match sort {
+            SortOrder::Asc => " AND (created_at, id) > (",
+            SortOrder::Desc => " AND (created_at, id) < (",
+        });
+        sql.push_bind(cursor.timestamp).push(", ").push_bind(cursor.id).push(")");
+    }
+    sql.push(match sort {
+        SortOrder::Asc => " ORDER BY created_at, id",
+        SortOrder::Desc => " ORDER BY created_at DESC, id DESC",
+    });
+    sql.push(" LIMIT ").push_bind(limit as i64 + 1);
+}
+
+async fn upsert_order(conn: &mut PgConnection, order: &Order) -> Result<(), sqlx::Error> {
+    let sql = format!(
+        "INSERT INTO orders ({}) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19) \
+         ON CONFLICT (id) DO UPDATE SET order_type = EXCLUDED.order_type, price = EXCLUDED.price, \
+         stop_price = EXCLUDED.stop_price, quantity = EXCLUDED.quantity, \
+         filled_quantity = EXCLUDED.filled_quantity, remaining_quantity = EXCLUDED.remaining_quantity, \
+         status = EXCLUDED.status, updated_at = EXCLUDED.updated_at",
+        ORDER_COLUMNS
+    );
+    sqlx::query(&sql)
+        .bind(order.id)
+        .bind(order.user_id)
+        .bind(&order.trading_pair)
+        .bind(side_to_db(&order.side))
+        .bind(order_type_to_db(&order.order_type))
+        .bind(order.price)
+        .bind(order.stop_price)
+        .bind(order.quantity)
+        .bind(order.filled_quantity)
+        .bind(order.remaining_quantity)
+        .bind(time_in_force_to_db(order.time_in_force))
+        .bind(order.post_only)
+        .bind(order.display_quantity)
+        .bind(order.expires_at)
+        .bind(order.reduce_only)
+        .bind(&order.client_order_id)
+        .bind(status_to_db(&order.status))
+        .bind(order.created_at)
+        .bind(order.updated_at)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The database counterpart of `apply_fill`
+async fn update_fill(conn: &mut PgConnection, order_id: Uuid, trade: &Trade) -> Result<Option<Order>, sqlx::Error> {
+    let sql = format!(
+        "UPDATE orders SET filled_quantity = filled_quantity + $2, \
+         remaining_quantity = GREATEST(quantity - filled_quantity - $2, 0), \
+         status = CASE WHEN quantity - filled_quantity - $2 <= 0 THEN 'FILLED' ELSE 'PARTIALLY_FILLED' END, \
+         updated_at = $3 \
+         WHERE id = $1 RETURNING {}",
+        ORDER_COLUMNS
+    );
+    let row = sqlx::query(&sql)
+        .bind(order_id)
+        .bind(trade.quantity)
+        .bind(trade.timestamp)
+        .fetch_optional(conn)
+        .await?;
+    row.as_ref().map(order_from_row).transpose()
+}
+
+async fn insert_trade(conn: &mut PgConnection, trade: &Trade) -> Result<(), sqlx::Error> {
+    let maker = (trade.maker_order_id, trade.maker_user_id, trade.maker_fee, &trade.maker_fee_currency);
+    let taker = (trade.taker_order_id, trade.taker_user_id, trade.taker_fee, &trade.taker_fee_currency);
+    let (buyer, seller) = if trade.is_buyer_maker { (maker, taker) } else { (taker, maker) };
+
+    let sql = format!(
+        "INSERT INTO trades ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
+        TRADE_COLUMNS
+    );
+    sqlx::query(&sql)
+        .bind(trade.id)
+        .bind(&trade.symbol)
+        .bind(trade.sequence as i64)
+        .bind(trade.price)
+        .bind(trade.quantity)
+        .bind(buyer.0)
+        .bind(seller.0)
+        .bind(buyer.1)
+        .bind(seller.1)
+        .bind(trade.is_buyer_maker)
+        .bind(buyer.2)
+        .bind(buyer.3)
+        .bind(seller.2)
+        .bind(seller.3)
+        .bind(trade.timestamp)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+fn order_from_row(row: &PgRow) -> Result<Order, sqlx::Error> {
+    Ok(Order {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        trading_pair: row.try_get("trading_pair")?,
+        side: side_from_db(row.try_get("side")?)?,
+        order_type: order_type_from_db(row.try_get("order_type")?)?,
+        price: row.try_get("price")?,
+        stop_price: row.try_get("stop_price")?,
+        quantity: row.try_get("quantity")?,
+        filled_quantity: row.try_get("filled_quantity")?,
+        remaining_quantity: row.try_get("remaining_quantity")?,
+        time_in_force: time_in_force_from_db(row.try_get("time_in_force")?)?,
+        post_only: row.try_get("post_only")?,
+        display_quantity: row.try_get("display_quantity")?,
+        expires_at: row.try_get("expires_at")?,
+        reduce_only: row.try_get("reduce_only")?,
+        client_order_id: row.try_get("client_order_id")?,
+        status: status_from_db(row.try_get("status")?)?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+fn trade_from_row(row: &PgRow) -> Result<Trade, sqlx::Error> {
+    let is_buyer_maker: bool = row.try_get("is_buyer_maker")?;
+    let buyer = (
+        row.try_get("buyer_order_id")?,
+        row.try_get::<Option<Uuid>, _>("buyer_user_id")?.unwrap_or_default(),
+        row.try_get("buyer_fee")?,
+        row.try_get("buyer_fee_currency")?,
+    );
+    let seller = (
+        row.try_get("seller_order_id")?,
+        row.try_get::<Option<Uuid>, _>("seller_user_id")?.unwrap_or_default(),
+        row.try_get("seller_fee")?,
+        row.try_get("seller_fee_currency")?,
+    );
+    let (maker, taker, side) = if is_buyer_maker {
+        (buyer, seller, OrderSide::Sell)
+    } else {
+        (seller, buyer, OrderSide::Buy)
+    };
+
+    Ok(Trade {
+        id: row.try_get("id")?,
+        symbol: row.try_get("symbol")?,
+        sequence: row.try_get::<i64, _>("sequence")? as u64,
+        price: row.try_get("price")?,
+        quantity: row.try_get("quantity")?,
+        side,
+        maker_order_id: maker.0,
+        taker_order_id: taker.0,
+        maker_user_id: maker.1,
+        taker_user_id: taker.1,
+        is_buyer_maker,
+        maker_fee: maker.2,
+        maker_fee_currency: maker.3,
+        taker_fee: taker.2,
+        taker_fee_currency: taker.3,
+        timestamp: row.try_get("created_at")?,
+    })
+}
+
+fn dead_letter_from_row(row: &PgRow) -> Result<DeadLetter, sqlx::Error> {
+    let attempts: i32 = row.try_get("attempts")?;
+    Ok(DeadLetter {
+        id: row.try_get("id")?,
+        handler: handler_from_db(row.try_get("handler")?)?,
+        event_type: row.try_get("event_type")?,
+        payload: row.try_get("payload")?,
+        error: row.try_get("error")?,
+        attempts: attempts.max(0) as u32,
+        status: dead_letter_status_from_db(row.try_get("status")?)?,
+        first_failed_at: row.try_get("first_failed_at")?,
+        last_failed_at: row.try_get("last_failed_at")?,
+        resolved_at: row.try_get("resolved_at")?,
+    })
+}
+
+fn webhook_from_row(row: &PgRow) -> Result<Webhook, sqlx::Error> {
+    let events: Vec<String> = row.try_get("events")?;
+    Ok(Webhook {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        url: row.try_get("url")?,
+        events: events.iter().map(|event| webhook_event_from_db(event)).collect::<Result<_, _>>()?,
+        secret: row.try_get("secret")?,
+        active: row.try_get("active")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn unknown_value(column: &str, value: &str) -> sqlx::Error {
+    sqlx::Error::Decode(format!("unknown {} '{}'", column, value).into())
+}
+
+fn side_to_db(side: &OrderSide) -> &'static str {
+    match side {
+        OrderSide::Buy => "buy",
+        OrderSide::Sell => "sell",
+    }
+}
+
+fn side_from_db(value: &str) -> Result<OrderSide, sqlx::Error> {
+    match value {
+        "buy" => Ok(OrderSide::Buy),
+        "sell" => Ok(OrderSide::Sell),
+        _ => Err(unknown_value("side", value)),
+    }
+}
+
+fn order_type_to_db(order_type: &OrderType) -> &'static str {
+    match order_type {
+        OrderType::Market => "market",
+        OrderType::Limit => "limit",
+        OrderType::StopLoss => "stop_loss",
+        OrderType::TakeProfit => "take_profit",
+    }
+}
+
+fn order_type_from_db(value: &str) -> Result<OrderType, sqlx::Error> {
+    match value {
+        "market" => Ok(OrderType::Market),
+        "limit" => Ok(OrderType::Limit),
+        "stop_loss" => Ok(OrderType::StopLoss),
+        "take_profit" => Ok(OrderType::TakeProfit),
+        _ => Err(unknown_value("order type", value)),
+    }
+}
+
+fn status_to_db(status: &OrderStatus) -> &'static str {
+    match status {
+        OrderStatus::New => "NEW",
+        OrderStatus::PartiallyFilled => "PARTIALLY_FILLED",
+        OrderStatus::Filled => "FILLED",
+        OrderStatus::Cancelled => "CANCELLED",
+        OrderStatus::Rejected => "REJECTED",
+        OrderStatus::Expired => "EXPIRED",
+    }
+}
+
+fn status_from_db(value: &str) -> Result<OrderStatus, sqlx::Error> {
+    match value {
+        "NEW" => Ok(OrderStatus::New),
+        "PARTIALLY_FILLED" => Ok(OrderStatus::PartiallyFilled),
+        "FILLED" => Ok(OrderStatus::Filled),
+        "CANCELLED" => Ok(OrderStatus::Cancelled),
+        "REJECTED" => Ok(OrderStatus::Rejected),
+        "EXPIRED" => Ok(OrderStatus::Expired),
+        _ => Err(unknown_value("order status", value)),
+    }
+}
+
+fn time_in_force_to_db(time_in_force: TimeInForce) -> &'static str {
+    match time_in_force {
+        TimeInForce::Gtc => "GTC",
+        TimeInForce::Ioc => "IOC",
+        TimeInForce::Fok => "FOK",
+        TimeInForce::Gtd => "GTD",
+    }
+}
+
+fn time_in_force_from_db(value: &str) -> Result<TimeInForce, sqlx::Error> {
+    match value {
+        "GTC" => Ok(TimeInForce::Gtc),
+        "IOC" => Ok(TimeInForce::Ioc),
+        "FOK" => Ok(TimeInForce::Fok),
+        "GTD" => Ok(TimeInForce::Gtd),
+        _ => Err(unknown_value("time in force", value)),
+    }
+}
+
+fn handler_to_db(handler: HandlerKind) -> &'static str {
+    match handler {
+        HandlerKind::Settlement => "settlement",
+        HandlerKind::Notification => "notification",
+        HandlerKind::Projection => "projection",
+        HandlerKind::Webhook => "webhook",
+    }
+}
+
+fn handler_from_db(value: &str) -> Result<HandlerKind, sqlx::Error> {
+    match value {
+        "settlement" => Ok(HandlerKind::Settlement),
+        "notification" => Ok(HandlerKind::Notification),
+        "projection" => Ok(HandlerKind::Projection),
+        "webhook" => Ok(HandlerKind::Webhook),
+        _ => Err(unknown_value("dead letter handler", value)),
+    }
+}
+
+fn dead_letter_status_to_db(status: DeadLetterStatus) -> &'static str {
+    match status {
+        DeadLetterStatus::Pending => "pending",
+        DeadLetterStatus::Replayed => "replayed",
+        DeadLetterStatus::Discarded => "discarded",
+    }
+}
+
+fn dead_letter_status_from_db(value: &str) -> Result<DeadLetterStatus, sqlx::Error> {
+    match value {
+        "pending" => Ok(DeadLetterStatus::Pending),
+        "replayed" => Ok(DeadLetterStatus::Replayed),
+        "discarded" => Ok(DeadLetterStatus::Discarded),
+        _ => Err(unknown_value("dead letter status", value)),
+    }
+}
+
+fn webhook_event_from_db(value: &str) -> Result<WebhookEventType, sqlx::Error> {
+    match value {
+        "order_filled" => Ok(WebhookEventType::OrderFilled),
+        "order_partially_filled" => Ok(WebhookEventType::OrderPartiallyFilled),
+        "order_cancelled" => Ok(WebhookEventType::OrderCancelled),
+        _ => Err(unknown_value("webhook event", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+    use flowex_types::TradingStatus;
+
+    fn trading_pair() -> TradingPair {
+        TradingPair {
+            symbol: "BTCUSDT".to_string(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            status: TradingStatus::Trading,
+            min_price: Decimal::new(1, 2),
+            max_price: Decimal::new(10000000, 0),
+            min_qty: Decimal::new(1, 8),
+            max_qty: Decimal::new(1000000, 0),
+            step_size: Decimal::new(1, 8),
+            tick_size: Decimal::new(1, 2),
+            min_notional: Decimal::ZERO,
+        }
+    }
+
+    /// 测试：成交同时更新对手方订单状态，重启后订单簿与成交序号得以恢复
+    #[tokio::test]
+    async fn test_execution_survives_engine_restart() {
+        let store = Store::in_memory();
+        let mut engine = store.restore_engine(trading_pair()).await.unwrap();
+
+        for order in [create_limit_order(OrderSide::Sell, 50000, 2), create_limit_order(OrderSide::Sell, 50100, 1)] {
+            let report = engine.add_order(order).unwrap();
+            store.record_execution(&report.order, &report.trades).await.unwrap();
+        }
+        let report = engine.add_order(create_limit_order(OrderSide::Buy, 50000, 1)).unwrap();
+        assert_eq!(report.trades.len(), 1);
+        store.record_execution(&report.order, &report.trades).await.unwrap();
+
+        let maker = store.order(report.trades[0].maker_order_id).await.unwrap().unwrap();
+        assert_eq!(maker.status, OrderStatus::PartiallyFilled);
+        assert_eq!(maker.remaining_quantity, Decimal::ONE);
+        assert_eq!(store.order(report.order.id).await.unwrap().unwrap().status, OrderStatus::Filled);
+
+        let restored = store.restore_engine(trading_pair()).await.unwrap();
+        let book = restored.get_order_book(10);
+        assert!(book.bids.is_empty());
+        assert_eq!(book.asks.len(), 2);
+        assert_eq!(book.asks[0].price, Decimal::new(50000, 0));
+        assert_eq!(book.asks[0].quantity, Decimal::ONE);
+        assert_eq!(restored.trade_sequence(), 1);
+    }
+}
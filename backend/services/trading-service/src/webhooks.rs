@@ -0,0 +1,732 @@
+//! Order event webhooks
+//!
+//! Lets users register HTTPS endpoints that receive signed callbacks when
+//! their orders are filled or cancelled, as a lightweight alternative to a
+//! persistent WebSocket connection. Deliveries are retried with exponential
+//! backoff; each endpoint has its own signing secret. Registrations are
+//! written through to the service's `Store` and read back on startup.
+//!
+//! Endpoints must be public HTTPS servers: URLs naming a loopback, private,
+//! link-local or otherwise internal address are refused when registered,
+//! and every delivery resolves the host again, refuses internal addresses
+//! and connects only to the addresses it checked, without following
+//! redirects.
+
+use crate::dlq::{DeadLetterQueue, HandlerKind};
+use crate::store::Store;
+use chrono::{DateTime, Utc};
+use flowex_types::{FlowExError, FlowExResult, Order, OrderStatus, Trade};
+use hmac::{Hmac, Mac};
+use reqwest::{redirect, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::RwLock;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Header carrying the `t=<timestamp>,v1=<signature>` payload signature
+pub const SIGNATURE_HEADER: &str = "x-flowex-signature";
+/// Header carrying the event type
+pub const EVENT_HEADER: &str = "x-flowex-event";
+/// Header carrying the unique delivery id
+pub const DELIVERY_HEADER: &str = "x-flowex-delivery";
+
+/// Maximum number of webhooks a single user may register
+const MAX_WEBHOOKS_PER_USER: usize = 10;
+
+/// Order events a webhook can subscribe to
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    OrderFilled,
+    OrderPartiallyFilled,
+    OrderCancelled,
+}
+
+impl WebhookEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::OrderFilled => "order_filled",
+            WebhookEventType::OrderPartiallyFilled => "order_partially_filled",
+            WebhookEventType::OrderCancelled => "order_cancelled",
+        }
+    }
+
+    /// Event `order` raises in its current status, given the trades that
+    /// just executed. Fill events need a trade of the order among `trades`;
+    /// an expired order is reported as cancelled.
+    pub fn for_order(order: &Order, trades: &[Trade]) -> Option<Self> {
+        let traded = trades
+            .iter()
+            .any(|trade| trade.maker_order_id == order.id || trade.taker_order_id == order.id);
+        match order.status {
+            OrderStatus::Filled if traded => Some(WebhookEventType::OrderFilled),
+            OrderStatus::PartiallyFilled if traded => Some(WebhookEventType::OrderPartiallyFilled),
+            OrderStatus::Cancelled | OrderStatus::Expired => Some(WebhookEventType::OrderCancelled),
+            _ => None,
+        }
+    }
+}
+
+/// Registered webhook endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub url: String,
+    pub events: Vec<WebhookEventType>,
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Webhook registration request
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub events: Vec<WebhookEventType>,
+}
+
+/// Webhook registration response; the secret is only returned once
+#[derive(Debug, Serialize)]
+pub struct CreateWebhookResponse {
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+/// Body posted to webhook endpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub delivery_id: Uuid,
+    pub event: WebhookEventType,
+    pub order: Order,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Dead-lettered delivery, kept so it can be replayed later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDeadLetter {
+    pub webhook_id: Uuid,
+    pub payload: WebhookPayload,
+}
+
+/// Delivery retry configuration
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub request_timeout: Duration,
+    /// Allow plain `http://` URLs and internal addresses, for tests' local receivers
+    #[cfg(test)]
+    pub allow_insecure: bool,
+}
+
+impl WebhookConfig {
+    fn allows_insecure(&self) -> bool {
+        #[cfg(test)]
+        {
+            self.allow_insecure
+        }
+        #[cfg(not(test))]
+        {
+            false
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            request_timeout: Duration::from_secs(10),
+            #[cfg(test)]
+            allow_insecure: false,
+        }
+    }
+}
+
+/// Registry of webhook endpoints, cached in memory and persisted to a
+/// `Store`, and their delivery worker
+#[derive(Clone)]
+pub struct WebhookRegistry {
+    webhooks: Arc<RwLock<HashMap<Uuid, Webhook>>>,
+    store: Store,
+    config: WebhookConfig,
+    dead_letters: DeadLetterQueue,
+}
+
+impl WebhookRegistry {
+    /// Create a new in-memory webhook registry parking exhausted deliveries in `dead_letters`
+    pub fn new(config: WebhookConfig, dead_letters: DeadLetterQueue) -> Self {
+        Self::from_webhooks(Store::in_memory(), HashMap::new(), config, dead_letters)
+    }
+
+    /// Webhook registry persisted to `store`, holding the webhooks already in it
+    pub async fn load(store: Store, config: WebhookConfig, dead_letters: DeadLetterQueue) -> FlowExResult<Self> {
+        let webhooks: HashMap<Uuid, Webhook> =
+            store.webhooks().await?.into_iter().map(|webhook| (webhook.id, webhook)).collect();
+        info!("Loaded {} webhooks", webhooks.len());
+        Ok(Self::from_webhooks(store, webhooks, config, dead_letters))
+    }
+
+    fn from_webhooks(
+        store: Store,
+        webhooks: HashMap<Uuid, Webhook>,
+        config: WebhookConfig,
+        dead_letters: DeadLetterQueue,
+    ) -> Self {
+        Self {
+            webhooks: Arc::new(RwLock::new(webhooks)),
+            store,
+            config,
+            dead_letters,
+        }
+    }
+
+    /// Register a new webhook for a user
+    pub async fn register(
+        &self,
+        user_id: Uuid,
+        request: CreateWebhookRequest,
+    ) -> FlowExResult<CreateWebhookResponse> {
+        self.validate_url(&request.url)?;
+
+        if request.events.is_empty() {
+            return Err(FlowExError::Validation("At least one event type is required".to_string()));
+        }
+
+        let mut webhooks = self.webhooks.write().await;
+        let existing = webhooks.values().filter(|w| w.user_id == user_id).count();
+        if existing >= MAX_WEBHOOKS_PER_USER {
+            return Err(FlowExError::Validation(format!(
+                "A maximum of {} webhooks may be registered",
+                MAX_WEBHOOKS_PER_USER
+            )));
+        }
+
+        let mut events = request.events;
+        events.sort_by_key(|e| e.as_str());
+        events.dedup();
+
+        let secret = format!("whsec_{}", Uuid::new_v4().simple());
+        let webhook = Webhook {
+            id: Uuid::new_v4(),
+            user_id,
+            url: request.url,
+            events,
+            secret: secret.clone(),
+            active: true,
+            created_at: Utc::now(),
+        };
+
+        self.store.insert_webhook(&webhook).await?;
+        webhooks.insert(webhook.id, webhook.clone());
+        info!("Registered webhook {} for user {}", webhook.id, user_id);
+
+        Ok(CreateWebhookResponse { webhook, secret })
+    }
+
+    /// List a user's webhooks
+    pub async fn list_for_user(&self, user_id: Uuid) -> Vec<Webhook> {
+        let webhooks = self.webhooks.read().await;
+        let mut result: Vec<Webhook> = webhooks
+            .values()
+            .filter(|w| w.user_id == user_id)
+            .cloned()
+            .collect();
+        result.sort_by_key(|w| w.created_at);
+        result
+    }
+
+    /// Remove a user's webhook; false if the user has no such webhook
+    pub async fn remove(&self, user_id: Uuid, webhook_id: Uuid) -> FlowExResult<bool> {
+        let mut webhooks = self.webhooks.write().await;
+        match webhooks.get(&webhook_id) {
+            Some(webhook) if webhook.user_id == user_id => {
+                self.store.delete_webhook(webhook_id).await?;
+                webhooks.remove(&webhook_id);
+                info!("Removed webhook {} for user {}", webhook_id, user_id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Dispatch an order event to every matching webhook of the order owner.
+    ///
+    /// Deliveries run in the background; the returned handles resolve to
+    /// the delivery outcome of each endpoint. Deliveries that exhaust their
+    /// retries are parked in the dead-letter queue.
+    pub async fn dispatch(
+        &self,
+        event: WebhookEventType,
+        order: &Order,
+    ) -> Vec<tokio::task::JoinHandle<FlowExResult<()>>> {
+        let targets: Vec<Webhook> = {
+            let webhooks = self.webhooks.read().await;
+            webhooks
+                .values()
+                .filter(|w| w.active && w.user_id == order.user_id && w.events.contains(&event))
+                .cloned()
+                .collect()
+        };
+
+        targets
+            .into_iter()
+            .map(|webhook| {
+                let payload = WebhookPayload {
+                    delivery_id: Uuid::new_v4(),
+                    event,
+                    order: order.clone(),
+                    timestamp: Utc::now(),
+                };
+                let registry = self.clone();
+                tokio::spawn(async move {
+                    let result = registry.deliver(&webhook, &payload).await;
+                    if let Err(e) = &result {
+                        registry.park_failed(&webhook, payload, e.to_string()).await;
+                    }
+                    result
+                })
+            })
+            .collect()
+    }
+
+    /// Deliver a payload to a single endpoint, retrying with exponential backoff
+    pub async fn deliver(&self, webhook: &Webhook, payload: &WebhookPayload) -> FlowExResult<()> {
+        let body = serde_json::to_string(payload)
+            .map_err(|e| FlowExError::Internal(format!("Failed to serialize webhook payload: {}", e)))?;
+
+        let mut backoff = self.config.initial_backoff;
+        let mut last_error = String::new();
+
+        for attempt in 1..=self.config.max_attempts.max(1) {
+            let timestamp = Utc::now().timestamp();
+            let signature = sign_payload(&webhook.secret, timestamp, &body);
+
+            // An internal address is refused for good; a failed lookup is retried
+            let result = match self.client_for(&webhook.url).await {
+                Ok(client) => client
+                    .post(&webhook.url)
+                    .header("content-type", "application/json")
+                    .header(SIGNATURE_HEADER, format!("t={},v1={}", timestamp, signature))
+                    .header(EVENT_HEADER, payload.event.as_str())
+                    .header(DELIVERY_HEADER, payload.delivery_id.to_string())
+                    .body(body.clone())
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e @ FlowExError::Validation(_)) => {
+                    warn!("Webhook {} delivery {} refused: {}", webhook.id, payload.delivery_id, e);
+                    return Err(e);
+                }
+                Err(e) => Err(e.to_string()),
+            };
+
+            match result {
+                Ok(response) if response.status().is_success() => {
+                    debug!(
+                        "Webhook {} delivered {} (attempt {})",
+                        webhook.id, payload.delivery_id, attempt
+                    );
+                    return Ok(());
+                }
+                Ok(response) => last_error = format!("HTTP {}", response.status()),
+                Err(e) => last_error = e,
+            }
+
+            warn!(
+                "Webhook {} delivery {} failed (attempt {}/{}): {}",
+                webhook.id, payload.delivery_id, attempt, self.config.max_attempts, last_error
+            );
+
+            if attempt < self.config.max_attempts {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(self.config.max_backoff);
+            }
+        }
+
+        Err(FlowExError::Internal(format!(
+            "Webhook {} delivery failed after {} attempts: {}",
+            webhook.id, self.config.max_attempts, last_error
+        )))
+    }
+
+    /// Replay a dead-lettered delivery
+    pub async fn replay(&self, dead_letter: &serde_json::Value) -> FlowExResult<()> {
+        let dead_letter: WebhookDeadLetter = serde_json::from_value(dead_letter.clone())
+            .map_err(|e| FlowExError::Validation(format!("Invalid webhook dead letter: {}", e)))?;
+
+        let webhook = self
+            .webhooks
+            .read()
+            .await
+            .get(&dead_letter.webhook_id)
+            .cloned()
+            .ok_or_else(|| FlowExError::Validation("Webhook no longer exists".to_string()))?;
+
+        self.deliver(&webhook, &dead_letter.payload).await
+    }
+
+    async fn park_failed(&self, webhook: &Webhook, payload: WebhookPayload, error: String) {
+        let event_type = payload.event.as_str();
+        let dead_letter = WebhookDeadLetter {
+            webhook_id: webhook.id,
+            payload,
+        };
+
+        match serde_json::to_value(&dead_letter) {
+            Ok(value) => {
+                self.dead_letters
+                    .park(HandlerKind::Webhook, event_type, value, error, self.config.max_attempts)
+                    .await;
+            }
+            Err(e) => warn!("Failed to serialize webhook dead letter: {}", e),
+        }
+    }
+
+    fn validate_url(&self, url: &str) -> FlowExResult<Url> {
+        let parsed = Url::parse(url).map_err(|_| FlowExError::Validation("Invalid webhook URL".to_string()))?;
+
+        match parsed.scheme() {
+            "https" => {}
+            "http" if self.config.allows_insecure() => {}
+            _ => return Err(FlowExError::Validation("Webhook URL must use https".to_string())),
+        }
+        if self.config.allows_insecure() {
+            return Ok(parsed);
+        }
+
+        // The URL parser has already normalised IP literals, so `0x7f.1` reads as 127.0.0.1
+        let internal = match parsed.host_str() {
+            Some(host) => match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+                Ok(ip) => is_internal(ip),
+                Err(_) => {
+                    let domain = host.trim_end_matches('.');
+                    domain.eq_ignore_ascii_case("localhost") || domain.to_ascii_lowercase().ends_with(".localhost")
+                }
+            },
+            None => true,
+        };
+        if internal {
+            return Err(FlowExError::Validation(
+                "Webhook URL must not point to an internal address".to_string(),
+            ));
+        }
+        Ok(parsed)
+    }
+
+    /// Client for one delivery to `url`, connecting only to the public
+    /// addresses its host resolves to now
+    async fn client_for(&self, url: &str) -> FlowExResult<reqwest::Client> {
+        let parsed = self.validate_url(url)?;
+        let builder = reqwest::Client::builder()
+            .timeout(self.config.request_timeout)
+            .redirect(redirect::Policy::none());
+
+        let builder = match (parsed.host_str(), parsed.port_or_known_default()) {
+            (Some(host), Some(port)) if !self.config.allows_insecure() => {
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+                    .await
+                    .map_err(|e| FlowExError::Internal(format!("Failed to resolve webhook host {}: {}", host, e)))?
+                    .collect();
+                if addrs.is_empty() {
+                    return Err(FlowExError::Internal(format!("Webhook host {} has no addresses", host)));
+                }
+                if addrs.iter().any(|addr| is_internal(addr.ip())) {
+                    return Err(FlowExError::Validation(format!(
+                        "Webhook host {} resolves to an internal address",
+                        host
+                    )));
+                }
+                builder.resolve_to_addrs(host, &addrs)
+            }
+            _ => builder,
+        };
+        builder
+            .build()
+            .map_err(|e| FlowExError::Internal(format!("Failed to build webhook client: {}", e)))
+    }
+}
+
+/// Whether `ip` is not a public unicast address: loopback, private,
+/// link-local, shared, unspecified, broadcast, multicast or documentation
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_internal_v4(mapped),
+            None => is_internal_v6(ip),
+        },
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network" 0.0.0.0/8 and shared address space 100.64.0.0/10
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local fc00::/7 and link-local fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+}
+
+/// Compute the hex HMAC-SHA256 signature over `<timestamp>.<body>`
+pub fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_user_limit_order;
+    use flowex_types::{OrderSide, OrderStatus};
+    use rust_decimal::Decimal;
+
+    fn create_test_order(user_id: Uuid) -> Order {
+        Order {
+            trading_pair: "BTC-USDT".to_string(),
+            filled_quantity: Decimal::ONE,
+            remaining_quantity: Decimal::ZERO,
+            status: OrderStatus::Filled,
+            ..create_user_limit_order(user_id, OrderSide::Buy, 45000, 1)
+        }
+    }
+
+    /// 测试：签名确定且依赖密钥
+    #[test]
+    fn test_sign_payload() {
+        let a = sign_payload("secret", 1700000000, "{}");
+        let b = sign_payload("secret", 1700000000, "{}");
+        let c = sign_payload("other", 1700000000, "{}");
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.len(), 64);
+    }
+
+    /// 测试：注册校验 - 必须为https且至少一个事件
+    #[tokio::test]
+    async fn test_register_validation() {
+        let registry = WebhookRegistry::new(WebhookConfig::default(), DeadLetterQueue::new(100));
+        let user_id = Uuid::new_v4();
+
+        let insecure = registry
+            .register(user_id, CreateWebhookRequest {
+                url: "http://example.com/hook".to_string(),
+                events: vec![WebhookEventType::OrderFilled],
+            })
+            .await;
+        assert!(insecure.is_err());
+
+        let no_events = registry
+            .register(user_id, CreateWebhookRequest {
+                url: "https://example.com/hook".to_string(),
+                events: vec![],
+            })
+            .await;
+        assert!(no_events.is_err());
+
+        let created = registry
+            .register(user_id, CreateWebhookRequest {
+                url: "https://example.com/hook".to_string(),
+                events: vec![WebhookEventType::OrderFilled, WebhookEventType::OrderFilled],
+            })
+            .await
+            .unwrap();
+        assert!(created.secret.starts_with("whsec_"));
+        assert_eq!(created.webhook.events, vec![WebhookEventType::OrderFilled]);
+    }
+
+    /// 测试：用户只能查看和删除自己的webhook
+    #[tokio::test]
+    async fn test_webhooks_are_scoped_to_user() {
+        let registry = WebhookRegistry::new(WebhookConfig::default(), DeadLetterQueue::new(100));
+        let owner = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        let created = registry
+            .register(owner, CreateWebhookRequest {
+                url: "https://example.com/hook".to_string(),
+                events: vec![WebhookEventType::OrderCancelled],
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(registry.list_for_user(owner).await.len(), 1);
+        assert!(registry.list_for_user(other).await.is_empty());
+        assert!(!registry.remove(other, created.webhook.id).await.unwrap());
+        assert!(registry.remove(owner, created.webhook.id).await.unwrap());
+        assert!(registry.list_for_user(owner).await.is_empty());
+    }
+
+    /// 测试：拒绝指向回环、私有、链路本地等内部地址的URL，公网地址可以注册
+    #[tokio::test]
+    async fn test_internal_addresses_are_refused() {
+        let registry = WebhookRegistry::new(WebhookConfig::default(), DeadLetterQueue::new(100));
+        let user_id = Uuid::new_v4();
+        let register = |url: &str| {
+            registry.register(user_id, CreateWebhookRequest {
+                url: url.to_string(),
+                events: vec![WebhookEventType::OrderFilled],
+            })
+        };
+
+        for url in [
+            "https://127.0.0.1/hook",
+            "https://0x7f.1/hook",
+            "https://localhost:8443/hook",
+            "https://api.localhost./hook",
+            "https://10.1.2.3/hook",
+            "https://172.16.0.1/hook",
+            "https://192.168.1.10/hook",
+            "https://169.254.169.254/latest/meta-data",
+            "https://100.64.0.1/hook",
+            "https://0.0.0.0/hook",
+            "https://[::1]/hook",
+            "https://[fe80::1]/hook",
+            "https://[fd00::1]/hook",
+            "https://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert!(
+                matches!(register(url).await, Err(FlowExError::Validation(_))),
+                "{} should be refused",
+                url
+            );
+        }
+
+        assert!(register("https://93.184.216.34/hook").await.is_ok());
+        assert!(register("https://[2606:2800:220:1::1]/hook").await.is_ok());
+    }
+
+    /// 测试：投递前重新校验地址，指向内部地址的webhook不重试直接拒绝
+    #[tokio::test]
+    async fn test_delivery_to_internal_address_is_refused() {
+        let registry = WebhookRegistry::new(
+            WebhookConfig { max_attempts: 3, initial_backoff: Duration::from_secs(60), ..Default::default() },
+            DeadLetterQueue::new(100),
+        );
+        let user_id = Uuid::new_v4();
+        // Loaded from a store written before internal addresses were refused
+        let webhook = Webhook {
+            id: Uuid::new_v4(),
+            user_id,
+            url: "https://localhost:9/hook".to_string(),
+            events: vec![WebhookEventType::OrderFilled],
+            secret: "whsec_test".to_string(),
+            active: true,
+            created_at: Utc::now(),
+        };
+        let payload = WebhookPayload {
+            delivery_id: Uuid::new_v4(),
+            event: WebhookEventType::OrderFilled,
+            order: create_test_order(user_id),
+            timestamp: Utc::now(),
+        };
+
+        let result = tokio::time::timeout(Duration::from_secs(5), registry.deliver(&webhook, &payload)).await;
+        assert!(matches!(result, Ok(Err(FlowExError::Validation(_)))));
+    }
+
+    /// 测试：注册与删除写入存储，重新加载后webhook及其签名密钥仍在
+    #[tokio::test]
+    async fn test_webhooks_survive_reload() {
+        let store = Store::in_memory();
+        let registry = WebhookRegistry::load(store.clone(), WebhookConfig::default(), DeadLetterQueue::new(100))
+            .await
+            .unwrap();
+        let user_id = Uuid::new_v4();
+        let register = |url: &str| {
+            registry.register(user_id, CreateWebhookRequest {
+                url: url.to_string(),
+                events: vec![WebhookEventType::OrderFilled],
+            })
+        };
+        let kept = register("https://example.com/kept").await.unwrap();
+        let removed = register("https://example.com/removed").await.unwrap();
+        assert!(registry.remove(user_id, removed.webhook.id).await.unwrap());
+
+        let reloaded = WebhookRegistry::load(store, WebhookConfig::default(), DeadLetterQueue::new(100))
+            .await
+            .unwrap();
+        let webhooks = reloaded.list_for_user(user_id).await;
+        assert_eq!(webhooks.len(), 1);
+        assert_eq!(webhooks[0].id, kept.webhook.id);
+        assert_eq!(webhooks[0].secret, kept.secret);
+    }
+
+    /// 测试：投递失败后按次数重试、返回错误并进入死信队列
+    #[tokio::test]
+    async fn test_delivery_retries_then_fails() {
+        let dead_letters = DeadLetterQueue::new(100);
+        let registry = WebhookRegistry::new(
+            WebhookConfig {
+                max_attempts: 2,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(2),
+                request_timeout: Duration::from_millis(200),
+                allow_insecure: true,
+            },
+            dead_letters.clone(),
+        );
+        let user_id = Uuid::new_v4();
+
+        // Nothing listens on this port, so every attempt fails
+        registry
+            .register(user_id, CreateWebhookRequest {
+                url: "http://127.0.0.1:9/hook".to_string(),
+                events: vec![WebhookEventType::OrderFilled],
+            })
+            .await
+            .unwrap();
+
+        let order = create_test_order(user_id);
+        let handles = registry.dispatch(WebhookEventType::OrderFilled, &order).await;
+        assert_eq!(handles.len(), 1);
+
+        for handle in handles {
+            assert!(handle.await.unwrap().is_err());
+        }
+
+        let parked = dead_letters.list(None).await;
+        assert_eq!(parked.len(), 1);
+        assert_eq!(parked[0].handler, HandlerKind::Webhook);
+        assert_eq!(parked[0].event_type, "order_filled");
+        assert!(registry.replay(&parked[0].payload).await.is_err());
+
+        // Events the webhook is not subscribed to are not delivered
+        let handles = registry.dispatch(WebhookEventType::OrderCancelled, &order).await;
+        assert!(handles.is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+//! Withdrawal approval
+//!
+//! Every withdrawal is reviewed before custody may send it. One at or below
+//! the auto-approval limit of its currency is approved as it is requested;
+//! a larger one waits in the admin queue for an admin's approval, and one
+//! above the dual-approval limit for the approvals of two different admins.
+//! An admin may reject it instead, which returns its funds. Withdrawals of
+//! a currency without an auto-approval limit are always reviewed.
+//!
+//! A user may withdraw at most the daily limit of a currency in any 24
+//! hours; withdrawals rejected or failed do not count towards it.
+
+use chrono::Duration;
+use flowex_types::{FlowExError, FlowExResult};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Window the daily withdrawal limits apply to
+pub fn daily_limit_window() -> Duration {
+    Duration::hours(24)
+}
+
+/// Approval limits and daily limits of withdrawals, by currency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WithdrawalPolicy {
+    /// Largest withdrawal approved without review
+    #[serde(default)]
+    pub auto_approve_limits: HashMap<String, Decimal>,
+    /// Withdrawals above this need two admins' approval
+    #[serde(default)]
+    pub dual_approval_limits: HashMap<String, Decimal>,
+    /// Most a user may withdraw in any 24 hours; currencies without a limit
+    /// are not limited
+    #[serde(default)]
+    pub daily_limits: HashMap<String, Decimal>,
+}
+
+impl Default for WithdrawalPolicy {
+    fn default() -> Self {
+        let limits = |btc: i64, eth: i64, usdt: i64| {
+            HashMap::from([
+                ("BTC".to_string(), Decimal::new(btc, 1)),
+                ("ETH".to_string(), Decimal::new(eth, 1)),
+                ("USDT".to_string(), Decimal::new(usdt, 1)),
+            ])
+        };
+        Self {
+            auto_approve_limits: limits(1, 20, 100_000),
+            dual_approval_limits: limits(50, 1_000, 5_000_000),
+            daily_limits: limits(100, 2_000, 10_000_000),
+        }
+    }
+}
+
+impl WithdrawalPolicy {
+    /// Read a withdrawal policy from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> FlowExResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            FlowExError::Internal(format!("Failed to read withdrawal policy {}: {}", path.display(), e))
+        })?;
+        let policy: Self = serde_json::from_str(&contents)
+            .map_err(|e| FlowExError::Validation(format!("Invalid withdrawal policy {}: {}", path.display(), e)))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Check that every limit is positive and no currency needs dual
+    /// approval for less than it is approved automatically
+    pub fn validate(&self) -> FlowExResult<()> {
+        let limits = self
+            .auto_approve_limits
+            .iter()
+            .chain(&self.dual_approval_limits)
+            .chain(&self.daily_limits);
+        for (currency, limit) in limits {
+            if *limit <= Decimal::ZERO {
+                return Err(FlowExError::Validation(format!(
+                    "Withdrawal limits of {} must be positive",
+                    currency
+                )));
+            }
+        }
+        for (currency, dual) in &self.dual_approval_limits {
+            if self.auto_approve_limits.get(currency).is_some_and(|auto| auto > dual) {
+                return Err(FlowExError::Validation(format!(
+                    "Dual approval limit of {} is below its auto-approval limit",
+                    currency
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Review a withdrawal of `amount` starts in
+    pub fn review(&self, currency: &str, amount: Decimal) -> WithdrawalReview {
+        let required_approvals = if self.auto_approve_limits.get(currency).is_some_and(|limit| amount <= *limit) {
+            0
+        } else if self.dual_approval_limits.get(currency).is_some_and(|limit| amount > *limit) {
+            2
+        } else {
+            1
+        };
+        WithdrawalReview {
+            status: if required_approvals == 0 {
+                ReviewStatus::Approved
+            } else {
+                ReviewStatus::Pending
+            },
+            required_approvals,
+            approved_by: Vec::new(),
+            rejected_by: None,
+        }
+    }
+
+    pub fn daily_limit(&self, currency: &str) -> Option<Decimal> {
+        self.daily_limits.get(currency).copied()
+    }
+}
+
+/// Where a withdrawal stands in review
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    /// Waiting in the admin queue
+    Pending,
+    /// Custody may send it
+    Approved,
+    Rejected,
+}
+
+/// Review of one withdrawal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WithdrawalReview {
+    pub status: ReviewStatus,
+    /// Admin approvals needed; none if approved automatically
+    pub required_approvals: u32,
+    /// Admins who approved it, in order
+    pub approved_by: Vec<Uuid>,
+    pub rejected_by: Option<Uuid>,
+}
+
+impl WithdrawalReview {
+    /// Record `admin_id`'s approval, approving the withdrawal once it has
+    /// every approval it needs
+    pub fn approve(&mut self, admin_id: Uuid) -> FlowExResult<()> {
+        self.ensure_pending()?;
+        if self.approved_by.contains(&admin_id) {
+            return Err(FlowExError::Validation(format!(
+                "Withdrawal is already approved by {}",
+                admin_id
+            )));
+        }
+        self.approved_by.push(admin_id);
+        if self.approved_by.len() >= self.required_approvals as usize {
+            self.status = ReviewStatus::Approved;
+        }
+        Ok(())
+    }
+
+    pub fn reject(&mut self, admin_id: Uuid) -> FlowExResult<()> {
+        self.ensure_pending()?;
+        self.status = ReviewStatus::Rejected;
+        self.rejected_by = Some(admin_id);
+        Ok(())
+    }
+
+    fn ensure_pending(&self) -> FlowExResult<()> {
+        if self.status != ReviewStatus::Pending {
+            return Err(FlowExError::Wallet(format!("Withdrawal is already {:?}", self.status)));
+        }
+        Ok(())
+    }
+}
+
+/// How much of its daily limit a user has withdrawn of a currency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WithdrawalUsage {
+    pub currency: String,
+    /// Withdrawn in the last 24 hours
+    pub withdrawn: Decimal,
+    pub daily_limit: Option<Decimal>,
+    /// Still available to withdraw
+    pub remaining: Option<Decimal>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：小额提现自动批准，大额需要一名管理员审批，超大额需要两名不同管理员审批
+    #[test]
+    fn test_review_by_amount() {
+        let policy = WithdrawalPolicy::default();
+        assert!(policy.validate().is_ok());
+
+        let small = policy.review("BTC", Decimal::new(1, 1));
+        assert_eq!((small.status, small.required_approvals), (ReviewStatus::Approved, 0));
+        // 没有自动批准限额的币种总是需要审批
+        assert_eq!(policy.review("DOGE", Decimal::ONE).required_approvals, 1);
+
+        let mut large = policy.review("BTC", Decimal::new(6, 0));
+        assert_eq!((large.status, large.required_approvals), (ReviewStatus::Pending, 2));
+        let admin = Uuid::new_v4();
+        large.approve(admin).unwrap();
+        assert_eq!(large.status, ReviewStatus::Pending);
+        assert!(large.approve(admin).is_err());
+        large.approve(Uuid::new_v4()).unwrap();
+        assert_eq!(large.status, ReviewStatus::Approved);
+        assert!(matches!(large.reject(admin), Err(FlowExError::Wallet(_))));
+
+        let mut medium = policy.review("BTC", Decimal::ONE);
+        assert_eq!(medium.required_approvals, 1);
+        medium.reject(admin).unwrap();
+        assert_eq!((medium.status, medium.rejected_by), (ReviewStatus::Rejected, Some(admin)));
+    }
+
+    /// 测试：提现策略拒绝非正数限额和低于自动批准限额的双人审批限额
+    #[test]
+    fn test_policy_validation() {
+        let mut policy = WithdrawalPolicy::default();
+        policy.daily_limits.insert("BTC".to_string(), Decimal::ZERO);
+        assert!(policy.validate().is_err());
+
+        let json = r#"{"auto_approve_limits":{"USDT":1000},"dual_approval_limits":{"USDT":500}}"#;
+        let policy: WithdrawalPolicy = serde_json::from_str(json).unwrap();
+        assert!(policy.daily_limits.is_empty());
+        assert!(policy.validate().is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! Asset registry
+//!
+//! Every currency the wallet accepts is registered here with how it moves
+//! on chain: the decimal places its amounts may have, the smallest
+//! withdrawal and the fee taken from each, whether deposits and withdrawals
+//! are open, and the network and confirmations deposits travel with.
+//! Deposits, withdrawals and transfers of a currency not registered are
+//! refused. Admins add, change and remove assets at runtime; the registry
+//! is kept in the wallet's store.
+
+use chrono::{DateTime, Utc};
+use flowex_types::{FlowExError, FlowExResult};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::store::WalletStore;
+
+/// Decimal places an amount may have in the ledger
+const MAX_AMOUNT_SCALE: u32 = 8;
+
+/// Longest currency code accepted
+const MAX_CURRENCY_LEN: usize = 10;
+
+/// Longest asset or network name accepted
+const MAX_NAME_LEN: usize = 64;
+
+/// How an asset is funded, as an admin configures it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetConfig {
+    pub name: String,
+    /// Decimal places amounts may have
+    pub precision: u32,
+    /// Smallest amount a withdrawal may have, its fee included
+    pub min_withdrawal: Decimal,
+    /// Fee taken from each withdrawal's amount
+    pub withdrawal_fee: Decimal,
+    pub deposit_enabled: bool,
+    pub withdrawal_enabled: bool,
+    /// Chain deposits and withdrawals travel on, such as `ERC20`
+    pub network: String,
+    /// Confirmations a deposit needs on its network
+    pub confirmations: u32,
+}
+
+impl AssetConfig {
+    pub fn validate(&self) -> FlowExResult<()> {
+        for (field, value) in [("Name", &self.name), ("Network", &self.network)] {
+            if value.trim().is_empty() || value.chars().count() > MAX_NAME_LEN {
+                return Err(FlowExError::Validation(format!(
+                    "{} must have 1 to {} characters",
+                    field, MAX_NAME_LEN
+                )));
+            }
+        }
+        if self.precision > MAX_AMOUNT_SCALE {
+            return Err(FlowExError::Validation(format!(
+                "Precision may be at most {}",
+                MAX_AMOUNT_SCALE
+            )));
+        }
+        if self.withdrawal_fee < Decimal::ZERO || self.min_withdrawal <= self.withdrawal_fee {
+            return Err(FlowExError::Validation(
+                "Withdrawal fee must be non-negative and below the minimum withdrawal".to_string(),
+            ));
+        }
+        for amount in [self.min_withdrawal, self.withdrawal_fee] {
+            if amount.normalize().scale() > self.precision {
+                return Err(FlowExError::Validation(format!(
+                    "Withdrawal limits may have at most {} decimal places",
+                    self.precision
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A registered currency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Asset {
+    pub currency: String,
+    #[serde(flatten)]
+    pub config: AssetConfig,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Asset {
+    /// Check that `amount` is positive and within the asset's precision
+    pub fn check_amount(&self, amount: Decimal) -> FlowExResult<()> {
+        if amount <= Decimal::ZERO {
+            return Err(FlowExError::Validation("Amount must be positive".to_string()));
+        }
+        if amount.normalize().scale() > self.config.precision {
+            return Err(FlowExError::Validation(format!(
+                "{} amounts may have at most {} decimal places",
+                self.currency, self.config.precision
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that deposits are open and `amount` may be deposited
+    pub fn check_deposit(&self, amount: Decimal) -> FlowExResult<()> {
+        if !self.config.deposit_enabled {
+            return Err(FlowExError::Wallet(format!("Deposits of {} are disabled", self.currency)));
+        }
+        self.check_amount(amount)
+    }
+
+    /// Check that withdrawals are open and `amount` reaches the minimum
+    pub fn check_withdrawal(&self, amount: Decimal) -> FlowExResult<()> {
+        if !self.config.withdrawal_enabled {
+            return Err(FlowExError::Wallet(format!("Withdrawals of {} are disabled", self.currency)));
+        }
+        self.check_amount(amount)?;
+        if amount < self.config.min_withdrawal {
+            return Err(FlowExError::Validation(format!(
+                "Withdrawals of {} must be at least {}",
+                self.currency, self.config.min_withdrawal
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Assets the wallet starts with before any is configured
+pub fn default_assets() -> Vec<Asset> {
+    let asset = |currency: &str, name: &str, precision, min_withdrawal, withdrawal_fee, network: &str, confirmations| Asset {
+        currency: currency.to_string(),
+        config: AssetConfig {
+            name: name.to_string(),
+            precision,
+            min_withdrawal,
+            withdrawal_fee,
+            deposit_enabled: true,
+            withdrawal_enabled: true,
+            network: network.to_string(),
+            confirmations,
+        },
+        updated_at: Utc::now(),
+    };
+    vec![
+        asset("BTC", "Bitcoin", 8, Decimal::new(1, 3), Decimal::new(5, 4), "BTC", 2),
+        asset("ETH", "Ethereum", 8, Decimal::new(1, 2), Decimal::new(5, 3), "ERC20", 12),
+        asset("USDT", "Tether USD", 6, Decimal::new(10, 0), Decimal::ONE, "ERC20", 12),
+    ]
+}
+
+/// Currency code `currency` names, upper-cased
+pub fn validate_currency(currency: &str) -> FlowExResult<String> {
+    let currency = currency.trim().to_uppercase();
+    if currency.is_empty() || currency.len() > MAX_CURRENCY_LEN || !currency.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(FlowExError::Validation(format!("Invalid currency {:?}", currency)));
+    }
+    Ok(currency)
+}
+
+/// Registered assets by currency
+#[derive(Clone)]
+pub struct AssetRegistry {
+    store: WalletStore,
+    assets: Arc<RwLock<BTreeMap<String, Asset>>>,
+}
+
+impl AssetRegistry {
+    /// The default assets, kept in memory only
+    pub fn new() -> Self {
+        Self::with_assets(WalletStore::Memory, default_assets())
+    }
+
+    /// Registry holding `assets`, as restored from `store`
+    pub fn with_assets(store: WalletStore, assets: Vec<Asset>) -> Self {
+        info!("Registered {} assets", assets.len());
+        Self {
+            store,
+            assets: Arc::new(RwLock::new(
+                assets.into_iter().map(|asset| (asset.currency.clone(), asset)).collect(),
+            )),
+        }
+    }
+
+    /// Every asset, by currency
+    pub async fn list(&self) -> Vec<Asset> {
+        self.assets.read().await.values().cloned().collect()
+    }
+
+    pub async fn get(&self, currency: &str) -> Option<Asset> {
+        self.assets.read().await.get(&currency.trim().to_uppercase()).cloned()
+    }
+
+    /// The asset `currency` names, which must be registered
+    pub async fn asset(&self, currency: &str) -> FlowExResult<Asset> {
+        let currency = validate_currency(currency)?;
+        self.assets
+            .read()
+            .await
+            .get(&currency)
+            .cloned()
+            .ok_or_else(|| FlowExError::Validation(format!("Unsupported currency {}", currency)))
+    }
+
+    /// Register `currency` with `config`, replacing its configuration if it
+    /// is registered already
+    pub async fn set(&self, currency: &str, config: AssetConfig) -> FlowExResult<Asset> {
+        let currency = validate_currency(currency)?;
+        config.validate()?;
+        let asset = Asset {
+            currency,
+            config,
+            updated_at: Utc::now(),
+        };
+
+        let mut assets = self.assets.write().await;
+        self.store.write_asset(&asset).await?;
+        assets.insert(asset.currency.clone(), asset.clone());
+        info!("Asset {} set: {:?}", asset.currency, asset.config);
+        Ok(asset)
+    }
+
+    /// Unregister `currency`, returning its asset if it was registered
+    pub async fn remove(&self, currency: &str) -> FlowExResult<Option<Asset>> {
+        let currency = validate_currency(currency)?;
+        let mut assets = self.assets.write().await;
+        if !assets.contains_key(&currency) {
+            return Ok(None);
+        }
+        self.store.delete_asset(&currency).await?;
+        info!("Asset {} removed", currency);
+        Ok(assets.remove(&currency))
+    }
+}
+
+impl Default for AssetRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：资产注册表校验精度、最小提现额和手续费，管理员可以新增、修改和删除资产
+    #[tokio::test]
+    async fn test_register_and_check_assets() {
+        let registry = AssetRegistry::new();
+        let usdt = registry.asset(" usdt ").await.unwrap();
+        assert!(usdt.check_deposit(Decimal::new(123_456_789, 6)).is_ok());
+        assert!(usdt.check_deposit(Decimal::new(1, 7)).is_err());
+        assert!(usdt.check_withdrawal(Decimal::new(9, 0)).is_err());
+        assert!(matches!(registry.asset("DOGE").await, Err(FlowExError::Validation(_))));
+
+        let mut config = usdt.config.clone();
+        config.withdrawal_enabled = false;
+        config.name = "Tether".to_string();
+        let updated = registry.set("usdt", config.clone()).await.unwrap();
+        assert_eq!(updated.currency, "USDT");
+        assert!(matches!(updated.check_withdrawal(Decimal::new(100, 0)), Err(FlowExError::Wallet(_))));
+        assert_eq!(registry.get("USDT").await.unwrap().config.name, "Tether");
+
+        // 手续费不能达到最小提现额，精度不能超过账本的8位小数
+        config.withdrawal_fee = config.min_withdrawal;
+        assert!(registry.set("USDC", config.clone()).await.is_err());
+        config.withdrawal_fee = Decimal::ONE;
+        config.precision = 9;
+        assert!(registry.set("USDC", config).await.is_err());
+
+        assert_eq!(registry.remove("btc").await.unwrap().unwrap().currency, "BTC");
+        assert!(registry.remove("BTC").await.unwrap().is_none());
+        assert_eq!(registry.list().await.len(), 2);
+    }
+}
@@ -0,0 +1,334 @@
+//! Instant conversion
+//!
+//! Converts a small amount of one asset into another at once, at an
+//! internal RFQ price: the exchange's conversion desk takes the other side
+//! of every conversion at the rate the market-data tickers give, directly
+//! or through USDT (see `portfolio::conversion_rate`). The desk's position
+//! is held in a system account of its own and may run short of a currency;
+//! operations hedge it on the order book.
+//!
+//! A conversion is quoted first. The quote holds for `QUOTE_TTL`, after
+//! which it is repriced when executed, until it is dropped a further
+//! `QUOTE_TTL` later. If the amount received would fall
+//! below the quote by more than the slippage the user accepts, the
+//! conversion is refused and nothing moves. `CONVERSION_FEE_RATE` of what
+//! is received is paid into the conversion fee revenue account, and
+//! conversions are capped at `MAX_CONVERSION_VALUE` USDT.
+
+use chrono::{DateTime, Utc};
+use flowex_types::{BalanceChange, FlowExError, FlowExResult};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::assets::{Asset, AssetRegistry};
+use crate::ledger::Ledger;
+use crate::portfolio::{conversion_rate, MarketDataClient};
+
+/// Account of the conversion desk's position
+pub const CONVERSION_DESK_ACCOUNT: Uuid = Uuid::from_u128(6);
+
+/// How long a quoted price holds
+const QUOTE_TTL: chrono::Duration = chrono::Duration::seconds(10);
+
+/// Part of what is received taken as the fee
+const CONVERSION_FEE_RATE: Decimal = Decimal::from_parts(2, 0, 0, false, 3);
+
+/// Slippage accepted unless the request says otherwise: 0.5%
+const DEFAULT_MAX_SLIPPAGE: Decimal = Decimal::from_parts(5, 0, 0, false, 3);
+
+/// Most slippage a request may accept: 5%
+const MAX_SLIPPAGE: Decimal = Decimal::from_parts(5, 0, 0, false, 2);
+
+/// Largest conversion, by value in USDT
+const MAX_CONVERSION_VALUE: Decimal = Decimal::from_parts(10_000, 0, 0, false, 0);
+
+/// Currency conversions are valued in for `MAX_CONVERSION_VALUE`
+const VALUE_CURRENCY: &str = "USDT";
+
+/// Quote request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuoteRequest {
+    pub from: String,
+    pub to: String,
+    /// Amount of `from` to convert
+    pub amount: Decimal,
+}
+
+/// Conversion request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConvertRequest {
+    pub quote_id: Uuid,
+    /// Largest fall in the amount received accepted should the quote be
+    /// repriced, as a fraction; 0.005 if not given
+    pub max_slippage: Option<Decimal>,
+}
+
+/// Price of converting an amount, held until `expires_at`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversionQuote {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub from: String,
+    pub to: String,
+    pub amount: Decimal,
+    /// Units of `to` per unit of `from`
+    pub rate: Decimal,
+    /// Part of what is converted taken as the fee, in `to`
+    pub fee: Decimal,
+    /// Amount of `to` received, after the fee
+    pub receive: Decimal,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A conversion executed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conversion {
+    pub id: Uuid,
+    pub quote_id: Uuid,
+    pub user_id: Uuid,
+    pub from: String,
+    pub to: String,
+    pub amount: Decimal,
+    pub rate: Decimal,
+    pub fee: Decimal,
+    pub receive: Decimal,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A conversion with the user's balances it changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversionReceipt {
+    pub conversion: Conversion,
+    pub balances: Vec<BalanceChange>,
+}
+
+/// What converting `amount` of `from` into `to` at `prices` yields: the
+/// rate, the fee and the amount received, rounded down to `to`'s precision.
+/// The converted amount is first rounded to 16 places, so an inverse rate
+/// such as 1/45000 does not cost a unit of the last place.
+fn price(
+    prices: &HashMap<String, Decimal>,
+    from: &Asset,
+    to: &Asset,
+    amount: Decimal,
+) -> FlowExResult<(Decimal, Decimal, Decimal)> {
+    let unpriced = || FlowExError::MarketData(format!("No price to convert {} into {}", from.currency, to.currency));
+    let value = amount * conversion_rate(prices, &from.currency, VALUE_CURRENCY).ok_or_else(unpriced)?;
+    if value > MAX_CONVERSION_VALUE {
+        return Err(FlowExError::Validation(format!(
+            "Conversions are limited to {} {}",
+            MAX_CONVERSION_VALUE, VALUE_CURRENCY
+        )));
+    }
+    let rate = conversion_rate(prices, &from.currency, &to.currency).ok_or_else(unpriced)?;
+    let precision = to.config.precision;
+    let gross = (amount * rate)
+        .round_dp(16)
+        .round_dp_with_strategy(precision, RoundingStrategy::ToZero);
+    let fee = (gross * CONVERSION_FEE_RATE).round_dp_with_strategy(precision, RoundingStrategy::AwayFromZero);
+    let receive = gross - fee;
+    if receive <= Decimal::ZERO {
+        return Err(FlowExError::Validation(format!(
+            "{} {} is too small to convert into {}",
+            amount, from.currency, to.currency
+        )));
+    }
+    Ok((rate, fee, receive))
+}
+
+/// Quotes and executes conversions against the conversion desk
+#[derive(Clone)]
+pub struct Converter {
+    ledger: Ledger,
+    assets: AssetRegistry,
+    market_data: MarketDataClient,
+    /// Open quotes by id
+    quotes: Arc<RwLock<HashMap<Uuid, ConversionQuote>>>,
+}
+
+impl Converter {
+    pub fn new(ledger: Ledger, assets: AssetRegistry, market_data: MarketDataClient) -> Self {
+        Self {
+            ledger,
+            assets,
+            market_data,
+            quotes: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Quote converting `request.amount` for `user_id` at the latest prices
+    pub async fn quote(&self, user_id: Uuid, request: QuoteRequest) -> FlowExResult<ConversionQuote> {
+        let prices = self.market_data.fetch_prices().await?;
+        self.quote_at(&prices, user_id, request).await
+    }
+
+    /// Execute one of `user_id`'s quotes, repriced at the latest prices if
+    /// it has expired
+    pub async fn convert(&self, user_id: Uuid, request: ConvertRequest) -> FlowExResult<ConversionReceipt> {
+        let prices = self.market_data.fetch_prices().await?;
+        self.convert_at(&prices, user_id, request).await
+    }
+
+    async fn quote_at(
+        &self,
+        prices: &HashMap<String, Decimal>,
+        user_id: Uuid,
+        request: QuoteRequest,
+    ) -> FlowExResult<ConversionQuote> {
+        let from = self.assets.asset(&request.from).await?;
+        let to = self.assets.asset(&request.to).await?;
+        if from.currency == to.currency {
+            return Err(FlowExError::Validation("Cannot convert an asset into itself".to_string()));
+        }
+        from.check_amount(request.amount)?;
+        let (rate, fee, receive) = price(prices, &from, &to, request.amount)?;
+
+        let now = Utc::now();
+        let quote = ConversionQuote {
+            id: Uuid::new_v4(),
+            user_id,
+            from: from.currency,
+            to: to.currency,
+            amount: request.amount,
+            rate,
+            fee,
+            receive,
+            expires_at: now + QUOTE_TTL,
+        };
+        let mut quotes = self.quotes.write().await;
+        quotes.retain(|_, quote| quote.expires_at + QUOTE_TTL > now);
+        quotes.insert(quote.id, quote.clone());
+        Ok(quote)
+    }
+
+    async fn convert_at(
+        &self,
+        prices: &HashMap<String, Decimal>,
+        user_id: Uuid,
+        request: ConvertRequest,
+    ) -> FlowExResult<ConversionReceipt> {
+        let max_slippage = request.max_slippage.unwrap_or(DEFAULT_MAX_SLIPPAGE);
+        if max_slippage < Decimal::ZERO || max_slippage > MAX_SLIPPAGE {
+            return Err(FlowExError::Validation(format!(
+                "Slippage must be between 0 and {}",
+                MAX_SLIPPAGE
+            )));
+        }
+        let now = Utc::now();
+        let quote = {
+            let mut quotes = self.quotes.write().await;
+            match quotes.get(&request.quote_id) {
+                Some(quote) if quote.user_id == user_id => quotes.remove(&request.quote_id),
+                _ => None,
+            }
+        }
+        .filter(|quote| quote.expires_at + QUOTE_TTL > now)
+        .ok_or_else(|| FlowExError::Validation(format!("No quote {}", request.quote_id)))?;
+
+        let (rate, fee, receive) = if now < quote.expires_at {
+            (quote.rate, quote.fee, quote.receive)
+        } else {
+            let from = self.assets.asset(&quote.from).await?;
+            let to = self.assets.asset(&quote.to).await?;
+            price(prices, &from, &to, quote.amount)?
+        };
+        let least = quote.receive * (Decimal::ONE - max_slippage);
+        if receive < least {
+            return Err(FlowExError::Wallet(format!(
+                "Price moved: {} {} would be received, below the {} accepted",
+                receive, quote.to, least
+            )));
+        }
+
+        let conversion = Conversion {
+            id: Uuid::new_v4(),
+            quote_id: quote.id,
+            user_id,
+            from: quote.from,
+            to: quote.to,
+            amount: quote.amount,
+            rate,
+            fee,
+            receive,
+            created_at: now,
+        };
+        let balances = self.ledger.convert(&conversion).await?;
+        info!(
+            "Converted {} {} into {} {} for {}",
+            conversion.amount, conversion.from, conversion.receive, conversion.to, user_id
+        );
+        Ok(ConversionReceipt { conversion, balances })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::revenue::CONVERSION_FEE_ACCOUNT;
+    use std::time::Duration;
+
+    fn prices(btc: i64) -> HashMap<String, Decimal> {
+        HashMap::from([
+            ("BTC-USDT".to_string(), Decimal::new(btc, 0)),
+            ("ETH-USDT".to_string(), Decimal::new(3000, 0)),
+        ])
+    }
+
+    /// 测试：报价后按报价兑换并收取手续费，超额、同币种和余额不足被拒绝，过期报价重新定价时超出滑点被拒绝
+    #[tokio::test]
+    async fn test_quote_and_convert() {
+        let ledger = Ledger::new();
+        let market_data = MarketDataClient::new("http://localhost:0".to_string(), Duration::from_millis(1));
+        let converter = Converter::new(ledger.clone(), AssetRegistry::new(), market_data);
+        let user_id = Uuid::new_v4();
+        ledger.credit(user_id, "USDT", Decimal::new(10_000, 0)).await.unwrap();
+        let request = |from: &str, to: &str, amount: i64| QuoteRequest {
+            from: from.to_string(),
+            to: to.to_string(),
+            amount: Decimal::new(amount, 0),
+        };
+
+        let quote = converter.quote_at(&prices(45_000), user_id, request("usdt", "BTC", 4500)).await.unwrap();
+        assert_eq!((quote.rate * Decimal::new(45_000, 0)).round_dp(8), Decimal::ONE);
+        assert_eq!(quote.fee, Decimal::new(20_000, 8));
+        assert_eq!(quote.receive, Decimal::new(9_980_000, 8));
+        assert!(converter.quote_at(&prices(45_000), user_id, request("USDT", "USDT", 1)).await.is_err());
+        assert!(converter.quote_at(&prices(45_000), user_id, request("USDT", "BTC", 20_000)).await.is_err());
+        assert!(converter.quote_at(&prices(45_000), user_id, request("USDT", "DOGE", 1)).await.is_err());
+
+        let convert = |quote_id| ConvertRequest { quote_id, max_slippage: None };
+        assert!(converter.convert_at(&prices(45_000), Uuid::new_v4(), convert(quote.id)).await.is_err());
+        let quote = converter.quote_at(&prices(45_000), user_id, request("USDT", "BTC", 4500)).await.unwrap();
+        let receipt = converter.convert_at(&prices(45_000), user_id, convert(quote.id)).await.unwrap();
+        assert_eq!(receipt.conversion.receive, quote.receive);
+        assert_eq!(receipt.balances.len(), 2);
+        assert!(converter.convert_at(&prices(45_000), user_id, convert(quote.id)).await.is_err());
+
+        let balances = ledger.balances(user_id).await;
+        let held = |currency: &str| balances.iter().find(|balance| balance.currency == currency).unwrap().available;
+        assert_eq!((held("USDT"), held("BTC")), (Decimal::new(5500, 0), Decimal::new(9_980_000, 8)));
+        assert_eq!(ledger.balances(CONVERSION_FEE_ACCOUNT).await[0].available, Decimal::new(20_000, 8));
+        // 兑换台收入用户支付的币种，付出兑换所得和手续费
+        let desk = ledger.balances(CONVERSION_DESK_ACCOUNT).await;
+        let desk_held = |currency: &str| desk.iter().find(|balance| balance.currency == currency).unwrap().available;
+        assert_eq!((desk_held("USDT"), desk_held("BTC")), (Decimal::new(4500, 0), Decimal::new(-1, 1)));
+
+        // 过期报价按新价格执行，价格不利变动超过滑点时拒绝且不动余额
+        let mut stale = converter.quote_at(&prices(45_000), user_id, request("USDT", "BTC", 4500)).await.unwrap();
+        stale.expires_at = Utc::now();
+        converter.quotes.write().await.insert(stale.id, stale.clone());
+        let moved = converter.convert_at(&prices(46_000), user_id, convert(stale.id)).await;
+        assert!(matches!(moved, Err(FlowExError::Wallet(_))));
+        converter.quotes.write().await.insert(stale.id, stale.clone());
+        let slippage = ConvertRequest { quote_id: stale.id, max_slippage: Some(Decimal::new(3, 2)) };
+        let repriced = converter.convert_at(&prices(46_000), user_id, slippage).await.unwrap();
+        assert!(repriced.conversion.receive < stale.receive);
+        let insufficient = converter.quote_at(&prices(45_000), user_id, request("USDT", "ETH", 5000)).await.unwrap();
+        assert!(matches!(converter.convert_at(&prices(45_000), user_id, convert(insufficient.id)).await, Err(FlowExError::Wallet(_))));
+    }
+}
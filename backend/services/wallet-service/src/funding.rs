@@ -0,0 +1,715 @@
+//! Deposits and withdrawals
+//!
+//! A deposit is credited to the user's available balance and recorded
+//! `Completed` at once, there being no chain watcher yet to confirm it; a
+//! deposit repeating the transaction hash of an earlier one returns that
+//! deposit instead of crediting it twice.
+//!
+//! A withdrawal within the user's daily limit, the lower of the policy's
+//! and the one their KYC level allows, locks its amount while
+//! `Pending` and goes to review (see `approval`); one an admin rejects is
+//! `Cancelled` and its amount returned. Once approved, the custody system
+//! that sends it reports back: a `Completed` withdrawal takes the locked
+//! amount out of the wallet, a `Failed` one returns it to the available
+//! balance.
+//!
+//! Only registered assets are funded, in amounts within their precision; a
+//! withdrawal must reach its asset's minimum, and the asset's withdrawal fee
+//! is recorded as part of its amount (see `assets`). The fee is paid into
+//! the withdrawal fee revenue account once the withdrawal completes.
+//!
+//! Each state a deposit or withdrawal reaches is stored with the balance it
+//! changed, through `Ledger::apply_funding`.
+
+use chrono::{DateTime, Utc};
+use flowex_types::{BalanceChange, FlowExError, FlowExResult, Transaction, TransactionStatus, TransactionType};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::approval::{daily_limit_window, ReviewStatus, WithdrawalPolicy, WithdrawalReview, WithdrawalUsage};
+use crate::assets::AssetRegistry;
+use crate::ledger::{Ledger, LedgerView};
+
+/// Longest withdrawal address or transaction hash accepted
+const MAX_ADDRESS_LEN: usize = 128;
+
+/// Deposit request
+#[derive(Debug, Clone, Deserialize)]
+pub struct DepositRequest {
+    pub currency: String,
+    pub amount: Decimal,
+    /// Hash of the on-chain transaction, which makes the deposit idempotent
+    pub tx_hash: Option<String>,
+}
+
+/// Withdrawal request
+#[derive(Debug, Clone, Deserialize)]
+pub struct WithdrawalRequest {
+    pub currency: String,
+    pub amount: Decimal,
+    pub address: String,
+}
+
+/// Outcome of a withdrawal reported by custody
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum WithdrawalOutcome {
+    Completed { tx_hash: String },
+    Failed { reason: String },
+}
+
+/// A deposit or withdrawal and where it stands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FundingTransaction {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// `Deposit` or `Withdrawal`
+    pub transaction_type: TransactionType,
+    pub currency: String,
+    pub amount: Decimal,
+    /// Part of a withdrawal's amount taken as its fee; custody sends the
+    /// rest
+    #[serde(default)]
+    pub fee: Decimal,
+    /// Destination of a withdrawal
+    pub address: Option<String>,
+    pub tx_hash: Option<String>,
+    pub status: TransactionStatus,
+    /// Why a withdrawal failed or was rejected
+    pub failure_reason: Option<String>,
+    /// Review of a withdrawal
+    pub review: Option<WithdrawalReview>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl FundingTransaction {
+    /// Entry of the transaction history
+    pub fn transaction(&self) -> Transaction {
+        Transaction {
+            id: self.id,
+            user_id: self.user_id,
+            transaction_type: self.transaction_type.clone(),
+            currency: self.currency.clone(),
+            amount: self.amount,
+            status: self.status.clone(),
+            created_at: self.created_at,
+        }
+    }
+
+    /// Change the transaction in its current state made to the available
+    /// and locked balances
+    pub fn balance_effect(&self) -> (Decimal, Decimal) {
+        let amount = self.amount;
+        match (&self.transaction_type, &self.status) {
+            (TransactionType::Deposit, TransactionStatus::Completed) => (amount, Decimal::ZERO),
+            (TransactionType::Withdrawal, TransactionStatus::Pending) => (-amount, amount),
+            (TransactionType::Withdrawal, TransactionStatus::Completed) => (-amount, Decimal::ZERO),
+            _ => (Decimal::ZERO, Decimal::ZERO),
+        }
+    }
+
+    /// Change the transaction in its current state made to the hot wallet's
+    /// holdings: a completed deposit arrives in it, a completed withdrawal
+    /// leaves it less the fee
+    pub fn custody_effect(&self) -> Decimal {
+        match (&self.transaction_type, &self.status) {
+            (TransactionType::Deposit, TransactionStatus::Completed) => self.amount,
+            (TransactionType::Withdrawal, TransactionStatus::Completed) => self.fee - self.amount,
+            _ => Decimal::ZERO,
+        }
+    }
+
+    /// Fee the transaction in its current state paid into revenue: a
+    /// withdrawal's fee once it completes
+    pub fn fee_revenue(&self) -> Decimal {
+        match (&self.transaction_type, &self.status) {
+            (TransactionType::Withdrawal, TransactionStatus::Completed) => self.fee,
+            _ => Decimal::ZERO,
+        }
+    }
+}
+
+/// A funding transaction with the balance it changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FundingReceipt {
+    pub transaction: FundingTransaction,
+    pub balance: Option<BalanceChange>,
+}
+
+/// Deposits and withdrawals, applied to the ledger
+#[derive(Clone)]
+pub struct Funding {
+    ledger: Ledger,
+    assets: AssetRegistry,
+    transactions: Arc<RwLock<HashMap<Uuid, FundingTransaction>>>,
+    policy: Arc<std::sync::RwLock<WithdrawalPolicy>>,
+}
+
+impl Funding {
+    pub fn new(ledger: Ledger, assets: AssetRegistry) -> Self {
+        Self::with_transactions(ledger, assets, Vec::new())
+    }
+
+    /// Funding holding `transactions` already applied to `ledger`, as
+    /// restored from its store
+    pub fn with_transactions(ledger: Ledger, assets: AssetRegistry, transactions: Vec<FundingTransaction>) -> Self {
+        info!("Restored {} deposits and withdrawals", transactions.len());
+        Self {
+            ledger,
+            assets,
+            transactions: Arc::new(RwLock::new(
+                transactions
+                    .into_iter()
+                    .map(|transaction| (transaction.id, transaction))
+                    .collect(),
+            )),
+            policy: Arc::new(std::sync::RwLock::new(WithdrawalPolicy::default())),
+        }
+    }
+
+    pub fn policy(&self) -> WithdrawalPolicy {
+        self.policy.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the approval and daily limits of withdrawals
+    pub fn set_policy(&self, policy: WithdrawalPolicy) -> FlowExResult<()> {
+        policy.validate()?;
+        info!(
+            "Withdrawal policy set: auto-approval {:?}, dual approval {:?}, daily {:?}",
+            policy.auto_approve_limits, policy.dual_approval_limits, policy.daily_limits
+        );
+        *self.policy.write().unwrap_or_else(|e| e.into_inner()) = policy;
+        Ok(())
+    }
+
+    pub async fn get(&self, id: Uuid) -> Option<FundingTransaction> {
+        self.transactions.read().await.get(&id).cloned()
+    }
+
+    /// A user's deposits and withdrawals, newest first
+    pub async fn user_transactions(&self, user_id: Uuid) -> Vec<FundingTransaction> {
+        let mut transactions: Vec<FundingTransaction> = self
+            .transactions
+            .read()
+            .await
+            .values()
+            .filter(|transaction| transaction.user_id == user_id)
+            .cloned()
+            .collect();
+        transactions.sort_by_key(|transaction| std::cmp::Reverse(transaction.created_at));
+        transactions
+    }
+
+    /// Record a deposit of `user_id` and credit it
+    pub async fn deposit(&self, user_id: Uuid, request: DepositRequest) -> FlowExResult<FundingReceipt> {
+        let asset = self.assets.asset(&request.currency).await?;
+        asset.check_deposit(request.amount)?;
+        let currency = asset.currency;
+        if let Some(tx_hash) = &request.tx_hash {
+            validate_reference("Transaction hash", tx_hash)?;
+        }
+
+        let mut transactions = self.transactions.write().await;
+        if let Some(existing) = request.tx_hash.as_ref().and_then(|tx_hash| {
+            transactions.values().find(|transaction| {
+                transaction.transaction_type == TransactionType::Deposit
+                    && transaction.tx_hash.as_ref() == Some(tx_hash)
+            })
+        }) {
+            if existing.user_id != user_id || existing.currency != currency || existing.amount != request.amount {
+                return Err(FlowExError::Validation(format!(
+                    "Transaction {} was deposited with different details",
+                    request.tx_hash.unwrap_or_default()
+                )));
+            }
+            info!("Deposit {} repeats transaction {:?}", existing.id, existing.tx_hash);
+            return Ok(FundingReceipt {
+                transaction: existing.clone(),
+                balance: None,
+            });
+        }
+
+        let now = Utc::now();
+        let deposit = FundingTransaction {
+            id: Uuid::new_v4(),
+            user_id,
+            transaction_type: TransactionType::Deposit,
+            currency,
+            amount: request.amount,
+            fee: Decimal::ZERO,
+            address: None,
+            tx_hash: request.tx_hash,
+            status: TransactionStatus::Completed,
+            failure_reason: None,
+            review: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let balance = self.ledger.apply_funding(&deposit, None).await?;
+        transactions.insert(deposit.id, deposit.clone());
+
+        info!(
+            "Deposited {} {} for user {} ({})",
+            deposit.amount, deposit.currency, deposit.user_id, deposit.id
+        );
+        Ok(FundingReceipt {
+            transaction: deposit,
+            balance: Some(balance),
+        })
+    }
+
+    /// Record a withdrawal of `user_id` and lock its amount until custody
+    /// reports its outcome, approving it if it is small enough. `caps` are
+    /// daily limits by currency the user is held to besides the policy's.
+    pub async fn withdraw(
+        &self,
+        user_id: Uuid,
+        request: WithdrawalRequest,
+        caps: &HashMap<String, Decimal>,
+    ) -> FlowExResult<FundingReceipt> {
+        let asset = self.assets.asset(&request.currency).await?;
+        asset.check_withdrawal(request.amount)?;
+        validate_reference("Withdrawal address", &request.address)?;
+        let currency = asset.currency;
+
+        let policy = self.policy();
+        let mut transactions = self.transactions.write().await;
+        let now = Utc::now();
+        if let Some(limit) = daily_limit(&policy, caps, &currency) {
+            let withdrawn = withdrawn_since(transactions.values(), user_id, &currency, now - daily_limit_window());
+            if withdrawn + request.amount > limit {
+                return Err(FlowExError::Wallet(format!(
+                    "Daily {} withdrawal limit of {} exceeded: {} withdrawn in the last 24 hours",
+                    currency, limit, withdrawn
+                )));
+            }
+        }
+
+        let review = policy.review(&currency, request.amount);
+        let withdrawal = FundingTransaction {
+            id: Uuid::new_v4(),
+            user_id,
+            transaction_type: TransactionType::Withdrawal,
+            currency,
+            amount: request.amount,
+            fee: asset.config.withdrawal_fee,
+            address: Some(request.address),
+            tx_hash: None,
+            status: TransactionStatus::Pending,
+            failure_reason: None,
+            review: Some(review),
+            created_at: now,
+            updated_at: now,
+        };
+        let balance = self.ledger.apply_funding(&withdrawal, None).await?;
+        transactions.insert(withdrawal.id, withdrawal.clone());
+
+        info!(
+            "Withdrawal {} of {} {} for user {} pending, {} approvals required",
+            withdrawal.id,
+            withdrawal.amount,
+            withdrawal.currency,
+            withdrawal.user_id,
+            withdrawal.review.as_ref().map_or(0, |review| review.required_approvals)
+        );
+        Ok(FundingReceipt {
+            transaction: withdrawal,
+            balance: Some(balance),
+        })
+    }
+
+    /// Withdrawals whose review is `status`, oldest first
+    pub async fn review_queue(&self, status: ReviewStatus) -> Vec<FundingTransaction> {
+        let mut queue: Vec<FundingTransaction> = self
+            .transactions
+            .read()
+            .await
+            .values()
+            .filter(|transaction| {
+                transaction.status == TransactionStatus::Pending
+                    && transaction.review.as_ref().is_some_and(|review| review.status == status)
+            })
+            .cloned()
+            .collect();
+        queue.sort_by_key(|transaction| transaction.created_at);
+        queue
+    }
+
+    /// Record `admin_id`'s approval of a withdrawal under review. Admins
+    /// may not approve their own withdrawals.
+    pub async fn approve(&self, id: Uuid, admin_id: Uuid) -> FlowExResult<FundingReceipt> {
+        let mut transactions = self.transactions.write().await;
+        let previous = pending_withdrawal(&transactions, id)?;
+        if previous.user_id == admin_id {
+            return Err(FlowExError::Validation("Admins may not approve their own withdrawals".to_string()));
+        }
+
+        let mut withdrawal = previous.clone();
+        withdrawal.review.get_or_insert_with(approved).approve(admin_id)?;
+        withdrawal.updated_at = Utc::now();
+        let balance = self.ledger.apply_funding(&withdrawal, Some(previous)).await?;
+        info!(
+            "Withdrawal {} approved by {}, now {:?}",
+            id,
+            admin_id,
+            withdrawal.review.as_ref().map(|review| review.status)
+        );
+        transactions.insert(id, withdrawal.clone());
+        Ok(FundingReceipt {
+            transaction: withdrawal,
+            balance: Some(balance),
+        })
+    }
+
+    /// Reject a withdrawal under review, returning its funds
+    pub async fn reject(&self, id: Uuid, admin_id: Uuid, reason: String) -> FlowExResult<FundingReceipt> {
+        let mut transactions = self.transactions.write().await;
+        let previous = pending_withdrawal(&transactions, id)?;
+
+        let mut withdrawal = previous.clone();
+        withdrawal.review.get_or_insert_with(approved).reject(admin_id)?;
+        withdrawal.status = TransactionStatus::Cancelled;
+        withdrawal.failure_reason = Some(reason);
+        withdrawal.updated_at = Utc::now();
+        let balance = self.ledger.apply_funding(&withdrawal, Some(previous)).await?;
+        warn!(
+            "Withdrawal {} rejected by {}: {}",
+            id,
+            admin_id,
+            withdrawal.failure_reason.as_deref().unwrap_or_default()
+        );
+        transactions.insert(id, withdrawal.clone());
+        Ok(FundingReceipt {
+            transaction: withdrawal,
+            balance: Some(balance),
+        })
+    }
+
+    /// View of the ledger taken while no withdrawal can change, with the
+    /// amount each user's pending withdrawals lock by currency
+    pub async fn ledger_view(&self) -> FlowExResult<(LedgerView, HashMap<(Uuid, String), Decimal>)> {
+        let transactions = self.transactions.read().await;
+        let view = self.ledger.view().await?;
+        let mut locked: HashMap<(Uuid, String), Decimal> = HashMap::new();
+        for transaction in transactions.values() {
+            let (_, locks) = transaction.balance_effect();
+            if !locks.is_zero() {
+                *locked.entry((transaction.user_id, transaction.currency.clone())).or_default() += locks;
+            }
+        }
+        Ok((view, locked))
+    }
+
+    /// How much of each currency's daily limit `user_id` has withdrawn,
+    /// held to `caps` besides the policy's limits
+    pub async fn withdrawal_usage(&self, user_id: Uuid, caps: &HashMap<String, Decimal>) -> Vec<WithdrawalUsage> {
+        let policy = self.policy();
+        let transactions = self.transactions.read().await;
+        let since = Utc::now() - daily_limit_window();
+
+        let mut currencies: BTreeMap<&str, Option<Decimal>> = policy
+            .daily_limits
+            .keys()
+            .chain(caps.keys())
+            .map(|currency| (currency.as_str(), daily_limit(&policy, caps, currency)))
+            .collect();
+        for transaction in transactions.values() {
+            if transaction.user_id == user_id && transaction.transaction_type == TransactionType::Withdrawal {
+                currencies.entry(transaction.currency.as_str()).or_insert(None);
+            }
+        }
+        currencies
+            .into_iter()
+            .map(|(currency, daily_limit)| {
+                let withdrawn = withdrawn_since(transactions.values(), user_id, currency, since);
+                WithdrawalUsage {
+                    currency: currency.to_string(),
+                    withdrawn,
+                    daily_limit,
+                    remaining: daily_limit.map(|limit| (limit - withdrawn).max(Decimal::ZERO)),
+                }
+            })
+            .collect()
+    }
+
+    /// Apply custody's outcome to an approved withdrawal
+    pub async fn finish_withdrawal(&self, id: Uuid, outcome: WithdrawalOutcome) -> FlowExResult<FundingReceipt> {
+        let mut transactions = self.transactions.write().await;
+        let withdrawal = pending_withdrawal(&transactions, id)?;
+        if let Some(review) = withdrawal.review.as_ref().filter(|review| review.status != ReviewStatus::Approved) {
+            return Err(FlowExError::Wallet(format!(
+                "Withdrawal {} is not approved: review {:?}",
+                id, review.status
+            )));
+        }
+
+        let previous = withdrawal.clone();
+        let mut withdrawal = withdrawal.clone();
+        match outcome {
+            WithdrawalOutcome::Completed { tx_hash } => {
+                validate_reference("Transaction hash", &tx_hash)?;
+                withdrawal.status = TransactionStatus::Completed;
+                withdrawal.tx_hash = Some(tx_hash);
+            }
+            WithdrawalOutcome::Failed { reason } => {
+                withdrawal.status = TransactionStatus::Failed;
+                withdrawal.failure_reason = Some(reason);
+            }
+        }
+        withdrawal.updated_at = Utc::now();
+
+        let balance = self.ledger.apply_funding(&withdrawal, Some(&previous)).await?;
+        if let Some(reason) = &withdrawal.failure_reason {
+            warn!("Withdrawal {} failed: {}", id, reason);
+        }
+        transactions.insert(id, withdrawal.clone());
+        info!("Withdrawal {} {:?}", id, withdrawal.status);
+        Ok(FundingReceipt {
+            transaction: withdrawal,
+            balance: Some(balance),
+        })
+    }
+}
+
+/// A withdrawal still `Pending`
+fn pending_withdrawal(
+    transactions: &HashMap<Uuid, FundingTransaction>,
+    id: Uuid,
+) -> FlowExResult<&FundingTransaction> {
+    let withdrawal = transactions
+        .get(&id)
+        .filter(|transaction| transaction.transaction_type == TransactionType::Withdrawal)
+        .ok_or_else(|| FlowExError::Validation(format!("No withdrawal {}", id)))?;
+    if withdrawal.status != TransactionStatus::Pending {
+        return Err(FlowExError::Wallet(format!(
+            "Withdrawal {} is already {:?}",
+            id, withdrawal.status
+        )));
+    }
+    Ok(withdrawal)
+}
+
+/// Review of a withdrawal made before withdrawals were reviewed
+fn approved() -> WithdrawalReview {
+    WithdrawalReview {
+        status: ReviewStatus::Approved,
+        required_approvals: 0,
+        approved_by: Vec::new(),
+        rejected_by: None,
+    }
+}
+
+/// The lower of the policy's daily limit of `currency` and its cap
+fn daily_limit(policy: &WithdrawalPolicy, caps: &HashMap<String, Decimal>, currency: &str) -> Option<Decimal> {
+    match (policy.daily_limit(currency), caps.get(currency)) {
+        (Some(limit), Some(cap)) => Some(limit.min(*cap)),
+        (limit, cap) => limit.or(cap.copied()),
+    }
+}
+
+/// Amount of `currency` `user_id` has withdrawn since `since`, counting
+/// withdrawals pending or completed
+fn withdrawn_since<'a>(
+    transactions: impl Iterator<Item = &'a FundingTransaction>,
+    user_id: Uuid,
+    currency: &str,
+    since: DateTime<Utc>,
+) -> Decimal {
+    transactions
+        .filter(|transaction| {
+            transaction.user_id == user_id
+                && transaction.transaction_type == TransactionType::Withdrawal
+                && transaction.currency == currency
+                && transaction.created_at > since
+                && matches!(transaction.status, TransactionStatus::Pending | TransactionStatus::Completed)
+        })
+        .map(|transaction| transaction.amount)
+        .sum()
+}
+
+/// Check that a transaction hash or address is set, not too long and
+/// without whitespace
+pub fn validate_reference(name: &str, value: &str) -> FlowExResult<()> {
+    if value.is_empty() || value.len() > MAX_ADDRESS_LEN || value.chars().any(char::is_whitespace) {
+        return Err(FlowExError::Validation(format!("Invalid {}", name.to_lowercase())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balance(change: &Option<BalanceChange>) -> (Decimal, Decimal) {
+        let balance = &change.as_ref().unwrap().balance;
+        (balance.available, balance.locked)
+    }
+
+    /// 测试：充值入账且按交易哈希去重，提现冻结金额，完成后扣除并收取手续费，失败后解冻
+    #[tokio::test]
+    async fn test_deposit_and_withdrawal_lifecycle() {
+        let ledger = Ledger::new();
+        let funding = Funding::new(ledger.clone(), AssetRegistry::new());
+        let user_id = Uuid::new_v4();
+        let deposit = |amount: Decimal, tx_hash: &str| DepositRequest {
+            currency: "usdt".to_string(),
+            amount,
+            tx_hash: Some(tx_hash.to_string()),
+        };
+        let withdrawal = |amount: i64| WithdrawalRequest {
+            currency: "USDT".to_string(),
+            amount: Decimal::new(amount, 0),
+            address: "0xabc".to_string(),
+        };
+
+        let receipt = funding.deposit(user_id, deposit(Decimal::new(1000, 0), "0x1")).await.unwrap();
+        assert_eq!(receipt.transaction.status, TransactionStatus::Completed);
+        assert_eq!(receipt.transaction.currency, "USDT");
+        assert_eq!(balance(&receipt.balance), (Decimal::new(1000, 0), Decimal::ZERO));
+        let repeat = funding.deposit(user_id, deposit(Decimal::new(1000, 0), "0x1")).await.unwrap();
+        assert_eq!(repeat.transaction.id, receipt.transaction.id);
+        assert!(repeat.balance.is_none());
+        assert!(funding.deposit(user_id, deposit(Decimal::new(5, 0), "0x1")).await.is_err());
+        assert!(funding.deposit(user_id, deposit(Decimal::ZERO, "0x2")).await.is_err());
+        assert!(funding.deposit(user_id, deposit(Decimal::new(1, 9), "0x2")).await.is_err());
+
+        assert!(matches!(funding.withdraw(user_id, withdrawal(1001), &HashMap::new()).await, Err(FlowExError::Wallet(_))));
+        // 低于最小提现额的提现被拒绝
+        assert!(matches!(funding.withdraw(user_id, withdrawal(5), &HashMap::new()).await, Err(FlowExError::Validation(_))));
+        let first = funding.withdraw(user_id, withdrawal(300), &HashMap::new()).await.unwrap();
+        assert_eq!(first.transaction.status, TransactionStatus::Pending);
+        assert_eq!(first.transaction.fee, Decimal::ONE);
+        assert_eq!(balance(&first.balance), (Decimal::new(700, 0), Decimal::new(300, 0)));
+        let second = funding.withdraw(user_id, withdrawal(200), &HashMap::new()).await.unwrap();
+
+        let completed = funding
+            .finish_withdrawal(first.transaction.id, WithdrawalOutcome::Completed { tx_hash: "0x3".to_string() })
+            .await
+            .unwrap();
+        assert_eq!(completed.transaction.status, TransactionStatus::Completed);
+        assert_eq!(balance(&completed.balance), (Decimal::new(500, 0), Decimal::new(200, 0)));
+        let revenue = ledger.balances(crate::revenue::WITHDRAWAL_FEE_ACCOUNT).await;
+        assert_eq!((revenue[0].currency.as_str(), revenue[0].available), ("USDT", Decimal::ONE));
+        let failed = funding
+            .finish_withdrawal(second.transaction.id, WithdrawalOutcome::Failed { reason: "rejected".to_string() })
+            .await
+            .unwrap();
+        assert_eq!(failed.transaction.status, TransactionStatus::Failed);
+        assert_eq!(balance(&failed.balance), (Decimal::new(700, 0), Decimal::ZERO));
+
+        // 已完成的提现不能再次变更
+        let again = funding
+            .finish_withdrawal(first.transaction.id, WithdrawalOutcome::Failed { reason: "late".to_string() })
+            .await;
+        assert!(matches!(again, Err(FlowExError::Wallet(_))));
+        assert_eq!(funding.user_transactions(user_id).await.len(), 3);
+    }
+
+    /// 测试：充值提现记入认证用户自己的账户，首次使用时开户
+    #[tokio::test]
+    async fn test_funding_is_kept_per_user() {
+        let ledger = Ledger::new();
+        let funding = Funding::new(ledger.clone(), AssetRegistry::new());
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let deposit = DepositRequest {
+            currency: "BTC".to_string(),
+            amount: Decimal::new(2, 0),
+            tx_hash: Some("0xa".to_string()),
+        };
+
+        funding.deposit(alice, deposit.clone()).await.unwrap();
+        assert!(!ledger.open_account(alice, "alice@flowex.com").await.unwrap());
+        // 同一笔链上交易不能再记入其他用户
+        assert!(funding.deposit(bob, deposit).await.is_err());
+        assert!(ledger.balances(bob).await.is_empty());
+        assert!(ledger.open_account(bob, "bob@flowex.com").await.unwrap());
+
+        let withdrawal = WithdrawalRequest {
+            currency: "BTC".to_string(),
+            amount: Decimal::new(5, 1),
+            address: "bc1q".to_string(),
+        };
+        assert!(matches!(funding.withdraw(bob, withdrawal.clone(), &HashMap::new()).await, Err(FlowExError::Wallet(_))));
+        let pending = funding.withdraw(alice, withdrawal, &HashMap::new()).await.unwrap();
+        assert_eq!(pending.transaction.user_id, alice);
+        assert_eq!(funding.user_transactions(alice).await.len(), 2);
+        assert!(funding.user_transactions(bob).await.is_empty());
+
+        // 从存储恢复时记录和余额一并恢复
+        let restored = Funding::with_transactions(ledger, AssetRegistry::new(), funding.user_transactions(alice).await);
+        assert_eq!(restored.get(pending.transaction.id).await, Some(pending.transaction));
+    }
+
+    /// 测试：大额提现进入审批队列，批准后才能由托管完成，驳回后解冻；超过每日限额的提现被拒绝
+    #[tokio::test]
+    async fn test_withdrawal_review_and_daily_limit() {
+        let funding = Funding::new(Ledger::new(), AssetRegistry::new());
+        let user_id = Uuid::new_v4();
+        let admin = Uuid::new_v4();
+        funding
+            .set_policy(WithdrawalPolicy {
+                auto_approve_limits: HashMap::from([("BTC".to_string(), Decimal::ONE)]),
+                dual_approval_limits: HashMap::new(),
+                daily_limits: HashMap::from([("BTC".to_string(), Decimal::new(5, 0))]),
+            })
+            .unwrap();
+        funding
+            .deposit(
+                user_id,
+                DepositRequest {
+                    currency: "BTC".to_string(),
+                    amount: Decimal::new(10, 0),
+                    tx_hash: None,
+                },
+            )
+            .await
+            .unwrap();
+        let withdraw = |amount: i64| WithdrawalRequest {
+            currency: "BTC".to_string(),
+            amount: Decimal::new(amount, 0),
+            address: "bc1q".to_string(),
+        };
+        let completed = || WithdrawalOutcome::Completed { tx_hash: "0x1".to_string() };
+
+        let small = funding.withdraw(user_id, withdraw(1), &HashMap::new()).await.unwrap().transaction;
+        assert_eq!(small.review.as_ref().unwrap().status, ReviewStatus::Approved);
+        let large = funding.withdraw(user_id, withdraw(2), &HashMap::new()).await.unwrap().transaction;
+        assert_eq!(funding.review_queue(ReviewStatus::Pending).await, vec![large.clone()]);
+        assert!(matches!(funding.finish_withdrawal(large.id, completed()).await, Err(FlowExError::Wallet(_))));
+
+        // 用户不能审批自己的提现
+        assert!(funding.approve(large.id, user_id).await.is_err());
+        let approved = funding.approve(large.id, admin).await.unwrap();
+        assert_eq!(approved.transaction.review.unwrap().approved_by, vec![admin]);
+        assert!(funding.review_queue(ReviewStatus::Pending).await.is_empty());
+        funding.finish_withdrawal(large.id, completed()).await.unwrap();
+
+        // 24小时内已提现3 BTC，限额5 BTC
+        assert!(matches!(funding.withdraw(user_id, withdraw(3), &HashMap::new()).await, Err(FlowExError::Wallet(_))));
+        let rejected = funding.withdraw(user_id, withdraw(2), &HashMap::new()).await.unwrap().transaction;
+        let receipt = funding.reject(rejected.id, admin, "address flagged".to_string()).await.unwrap();
+        assert_eq!(receipt.transaction.status, TransactionStatus::Cancelled);
+        assert_eq!(balance(&receipt.balance), (Decimal::new(7, 0), Decimal::ONE));
+        assert!(funding.approve(rejected.id, admin).await.is_err());
+
+        // 驳回的提现不计入每日限额
+        let usage = funding.withdrawal_usage(user_id, &HashMap::new()).await;
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].withdrawn, Decimal::new(3, 0));
+        assert_eq!(usage[0].remaining, Some(Decimal::new(2, 0)));
+
+        // KYC 级别的更低限额优先：上限 4 BTC 时只剩 1 BTC
+        let caps = HashMap::from([("BTC".to_string(), Decimal::new(4, 0)), ("ETH".to_string(), Decimal::ONE)]);
+        assert!(matches!(funding.withdraw(user_id, withdraw(2), &caps).await, Err(FlowExError::Wallet(_))));
+        let usage = funding.withdrawal_usage(user_id, &caps).await;
+        assert_eq!(usage.iter().map(|usage| usage.currency.as_str()).collect::<Vec<_>>(), vec!["BTC", "ETH"]);
+        assert_eq!(usage[0].remaining, Some(Decimal::ONE));
+        assert_eq!(usage[1].daily_limit, Some(Decimal::ONE));
+    }
+}
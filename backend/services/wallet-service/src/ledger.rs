@@ -0,0 +1,880 @@
+//! Trading balances and order reservations
+//!
+//! Every user the wallet sees has an account of their own, opened the
+//! first time they use it or a balance of theirs changes. An account keeps
+//! the email its user last authenticated with, for transfers addressed to
+//! it.
+//!
+//! The trading service locks funds here before an order reaches the
+//! matching engine: a reservation moves the amount an order may spend from
+//! a user's available balance to locked, or all of it for an order whose
+//! cost is unknown until it executes. Each trade is settled against the
+//! reservations of its two orders, never drawing on available balances, and
+//! whatever an order did not spend is released when it is filled, cancelled
+//! or expired. Settlements are
+//! idempotent per trade, so a retried or replayed settlement is applied once.
+//! Every operation returns the balances it changed, for the trading service
+//! to push to the users' streams.
+//!
+//! Fees are not lost: the trading fee each side of a trade pays and the fee
+//! of each completed withdrawal are paid into the exchange's revenue
+//! accounts and journaled as `FeeEntry`s. What the exchange holds in
+//! custody is tracked alongside, in the hot and cold wallet accounts: a
+//! completed deposit arrives in the hot wallet and a completed withdrawal
+//! leaves it, less its fee (see `treasury`). The balances of these system
+//! accounts are not reported as changes, having no user to push them to.
+//!
+//! An operation stages what it changes in a `LedgerWrite` and only applies
+//! it once the store has written it, so a failed write changes nothing.
+
+use chrono::Utc;
+use flowex_types::{Balance, BalanceChange, FlowExError, FlowExResult, Reservation, SettlementParty, TradeSettlement};
+use rust_decimal::Decimal;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use tokio::sync::RwLock;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::convert::{self, Conversion};
+use crate::funding::FundingTransaction;
+use crate::revenue::{self, FeeEntry, FeeSource, RevenueBalance, RevenueQuery, RevenueReport};
+use crate::store::{LedgerWrite, WalletAccount, WalletSnapshot, WalletStore};
+use crate::transfer::Transfer;
+use crate::treasury::{self, TreasuryMovement};
+
+/// Whether `user_id` is one of the exchange's own accounts, revenue,
+/// custody or the conversion desk, rather than a user's
+pub fn is_system_account(user_id: Uuid) -> bool {
+    revenue::is_revenue_account(user_id)
+        || treasury::is_custody_account(user_id)
+        || user_id == convert::CONVERSION_DESK_ACCOUNT
+}
+
+/// Every balance and reservation at one moment, with the balance totals
+/// the store held at that moment
+#[derive(Debug, Default)]
+pub struct LedgerView {
+    pub balances: Vec<(Uuid, Balance)>,
+    pub reservations: Vec<Reservation>,
+    /// Balances stored by currency, available and locked; `None` without a
+    /// database
+    pub stored_totals: Option<HashMap<String, Decimal>>,
+}
+
+/// Accounts, balances and reservations of every user
+#[derive(Clone, Default)]
+pub struct Ledger {
+    state: Arc<RwLock<LedgerState>>,
+    store: WalletStore,
+}
+
+#[derive(Default)]
+struct LedgerState {
+    /// Accounts by user
+    accounts: HashMap<Uuid, WalletAccount>,
+    /// Balances by user, then currency
+    balances: HashMap<Uuid, HashMap<String, Balance>>,
+    /// Reservations by order
+    reservations: HashMap<Uuid, Reservation>,
+    /// Trades already settled
+    settled: HashSet<Uuid>,
+    /// Fees paid into revenue accounts, oldest first
+    fees: Vec<FeeEntry>,
+}
+
+/// Amount taken from a user, first from an order's reservation
+struct Debit<'a> {
+    party: &'a SettlementParty,
+    currency: &'a str,
+    amount: Decimal,
+}
+
+/// Amount paid to a user's available balance
+struct Credit<'a> {
+    party: &'a SettlementParty,
+    currency: &'a str,
+    amount: Decimal,
+}
+
+impl Ledger {
+    /// Ledger kept in memory only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ledger written to `store`, starting from what `snapshot` holds
+    pub fn with_store(store: WalletStore, snapshot: &WalletSnapshot) -> Self {
+        let mut state = LedgerState {
+            accounts: snapshot
+                .accounts
+                .iter()
+                .map(|account| (account.user_id, account.clone()))
+                .collect(),
+            reservations: snapshot
+                .reservations
+                .iter()
+                .map(|reservation| (reservation.order_id, reservation.clone()))
+                .collect(),
+            settled: snapshot.settled.iter().copied().collect(),
+            fees: snapshot.fees.clone(),
+            ..LedgerState::default()
+        };
+        for (user_id, balance) in &snapshot.balances {
+            state
+                .balances
+                .entry(*user_id)
+                .or_default()
+                .insert(balance.currency.clone(), balance.clone());
+        }
+        info!(
+            "Restored {} wallet accounts with {} open reservations",
+            state.accounts.len(),
+            state.reservations.len()
+        );
+        Self {
+            state: Arc::new(RwLock::new(state)),
+            store,
+        }
+    }
+
+    /// Open an account for `user_id` unless they have one, recording the
+    /// email they authenticated with; true if opened
+    pub async fn open_account(&self, user_id: Uuid, email: &str) -> FlowExResult<bool> {
+        let mut state = self.state.write().await;
+        let opened = match state.accounts.get(&user_id) {
+            Some(account) if account.email.as_deref() == Some(email) => return Ok(false),
+            Some(account) => WalletAccount {
+                email: Some(email.to_string()),
+                ..account.clone()
+            },
+            None => WalletAccount {
+                user_id,
+                email: Some(email.to_string()),
+                created_at: Utc::now(),
+            },
+        };
+        let is_new = !state.accounts.contains_key(&user_id);
+        let write = LedgerWrite {
+            accounts: vec![opened],
+            ..LedgerWrite::default()
+        };
+        self.commit(&mut state, &write).await?;
+        if is_new {
+            info!("Opened wallet account for user {}", user_id);
+        }
+        Ok(is_new)
+    }
+
+    pub async fn has_account(&self, user_id: Uuid) -> bool {
+        self.state.read().await.accounts.contains_key(&user_id)
+    }
+
+    /// User whose account holds `email`, ignoring case
+    pub async fn find_account_by_email(&self, email: &str) -> Option<Uuid> {
+        self.state
+            .read()
+            .await
+            .accounts
+            .values()
+            .find(|account| account.email.as_deref().is_some_and(|known| known.eq_ignore_ascii_case(email)))
+            .map(|account| account.user_id)
+    }
+
+    /// A user's balances, by currency
+    pub async fn balances(&self, user_id: Uuid) -> Vec<Balance> {
+        let state = self.state.read().await;
+        let mut balances: Vec<Balance> = state
+            .balances
+            .get(&user_id)
+            .map(|balances| balances.values().cloned().collect())
+            .unwrap_or_default();
+        balances.sort_by(|a, b| a.currency.cmp(&b.currency));
+        balances
+    }
+
+    /// Funds of `currency` all accounts hold, available and locked
+    pub async fn total(&self, currency: &str) -> Decimal {
+        self.state
+            .read()
+            .await
+            .balances
+            .values()
+            .filter_map(|balances| balances.get(currency))
+            .map(|balance| balance.available + balance.locked)
+            .sum()
+    }
+
+    /// Fee revenue over the range `query` selects by period, with the
+    /// balances the revenue accounts hold now
+    pub async fn revenue(&self, query: &RevenueQuery) -> RevenueReport {
+        let (start_time, end_time) = query.range(Utc::now());
+        let state = self.state.read().await;
+        let fees: Vec<FeeEntry> = state
+            .fees
+            .iter()
+            .filter(|fee| fee.created_at >= start_time && fee.created_at < end_time)
+            .cloned()
+            .collect();
+        let mut balances = Vec::new();
+        for source in FeeSource::ALL {
+            let Some(held) = state.balances.get(&source.account()) else {
+                continue;
+            };
+            let mut held: Vec<&Balance> = held.values().collect();
+            held.sort_by(|a, b| a.currency.cmp(&b.currency));
+            balances.extend(held.into_iter().map(|balance| RevenueBalance {
+                source,
+                balance: balance.clone(),
+            }));
+        }
+        RevenueReport {
+            period: query.period,
+            start_time,
+            end_time,
+            buckets: revenue::buckets(&fees, query.period),
+            balances,
+        }
+    }
+
+    /// Every balance and reservation, read together with the store's
+    /// balance totals while no operation can change them
+    pub async fn view(&self) -> FlowExResult<LedgerView> {
+        let state = self.state.read().await;
+        let stored_totals = self.store.balance_totals().await?;
+        let mut balances: Vec<(Uuid, Balance)> = state
+            .balances
+            .iter()
+            .flat_map(|(user_id, balances)| balances.values().map(|balance| (*user_id, balance.clone())))
+            .collect();
+        balances.sort_by(|(a, x), (b, y)| (a, &x.currency).cmp(&(b, &y.currency)));
+        Ok(LedgerView {
+            balances,
+            reservations: state.reservations.values().cloned().collect(),
+            stored_totals,
+        })
+    }
+
+    /// Add `amount` to a user's available balance
+    #[cfg(test)]
+    pub async fn credit(&self, user_id: Uuid, currency: &str, amount: Decimal) -> FlowExResult<BalanceChange> {
+        let mut state = self.state.write().await;
+        let mut write = LedgerWrite::default();
+        state.staged_balance(&mut write, user_id, currency).available += amount;
+        self.commit(&mut state, &write).await?;
+        let (user_id, balance) = write.balances.remove(0);
+        Ok(BalanceChange { user_id, balance })
+    }
+
+    /// Apply the change a deposit or withdrawal made to its owner's balance
+    /// since its `previous` state, and record it with that balance. Fails
+    /// without changing anything if the available balance cannot cover it.
+    pub async fn apply_funding(
+        &self,
+        transaction: &FundingTransaction,
+        previous: Option<&FundingTransaction>,
+    ) -> FlowExResult<BalanceChange> {
+        let (available, locked) = transaction.balance_effect();
+        let (previous_available, previous_locked) =
+            previous.map(FundingTransaction::balance_effect).unwrap_or_default();
+
+        let mut state = self.state.write().await;
+        let mut write = LedgerWrite::default();
+        let balance = state.staged_balance(&mut write, transaction.user_id, &transaction.currency);
+        let required = previous_available - available;
+        if required > balance.available {
+            return Err(FlowExError::Wallet(format!(
+                "Insufficient {} balance: {} available, {} required",
+                transaction.currency, balance.available, required
+            )));
+        }
+        balance.available += available - previous_available;
+        balance.locked += locked - previous_locked;
+        let fee = transaction.fee_revenue() - previous.map_or(Decimal::ZERO, FundingTransaction::fee_revenue);
+        if !fee.is_zero() {
+            let entry = FeeEntry::new(FeeSource::Withdrawal, &transaction.currency, fee, transaction.user_id, transaction.id);
+            state.stage_fee(&mut write, entry);
+        }
+        let custody = transaction.custody_effect() - previous.map_or(Decimal::ZERO, FundingTransaction::custody_effect);
+        if !custody.is_zero() {
+            state
+                .staged_balance(&mut write, treasury::HOT_WALLET_ACCOUNT, &transaction.currency)
+                .available += custody;
+        }
+        write.funding = Some(transaction.clone());
+
+        self.commit(&mut state, &write).await?;
+        Ok(changes(&write).remove(0))
+    }
+
+    /// Post a transfer's entries, each in one currency, after checking the
+    /// paying accounts' available balances cover them; returns the balances
+    /// it changed, in the order of the postings
+    pub async fn transfer(&self, transfer: &Transfer) -> FlowExResult<Vec<BalanceChange>> {
+        if transfer.postings.iter().map(|posting| posting.amount).sum::<Decimal>() != Decimal::ZERO {
+            return Err(FlowExError::Internal(format!("Postings of transfer {} do not balance", transfer.id)));
+        }
+
+        let mut state = self.state.write().await;
+        let mut write = LedgerWrite::default();
+        for posting in &transfer.postings {
+            let balance = state.staged_balance(&mut write, posting.user_id, &posting.currency);
+            if balance.available + posting.amount < Decimal::ZERO {
+                return Err(FlowExError::Wallet(format!(
+                    "Insufficient {} balance: {} available, {} required",
+                    posting.currency, balance.available, -posting.amount
+                )));
+            }
+            balance.available += posting.amount;
+        }
+        write.transfer = Some(transfer.clone());
+
+        self.commit(&mut state, &write).await?;
+        Ok(changes(&write))
+    }
+
+    /// Exchange a user's `from` for `to` with the conversion desk, after
+    /// checking their available balance covers it, and pay the fee out of
+    /// what the desk gives; returns the user's balances, `from` then `to`
+    pub async fn convert(&self, conversion: &Conversion) -> FlowExResult<Vec<BalanceChange>> {
+        let mut state = self.state.write().await;
+        let mut write = LedgerWrite::default();
+        let from = state.staged_balance(&mut write, conversion.user_id, &conversion.from);
+        if conversion.amount > from.available {
+            return Err(FlowExError::Wallet(format!(
+                "Insufficient {} balance: {} available, {} required",
+                conversion.from, from.available, conversion.amount
+            )));
+        }
+        from.available -= conversion.amount;
+        state.staged_balance(&mut write, conversion.user_id, &conversion.to).available += conversion.receive;
+        let desk = convert::CONVERSION_DESK_ACCOUNT;
+        state.staged_balance(&mut write, desk, &conversion.from).available += conversion.amount;
+        state.staged_balance(&mut write, desk, &conversion.to).available -= conversion.receive + conversion.fee;
+        let fee = FeeEntry::new(FeeSource::Conversion, &conversion.to, conversion.fee, conversion.user_id, conversion.id);
+        state.stage_fee(&mut write, fee);
+        write.conversion = Some(conversion.clone());
+
+        self.commit(&mut state, &write).await?;
+        Ok(changes(&write))
+    }
+
+    /// Move funds between the custody wallets, after checking the paying
+    /// wallet holds them
+    pub async fn move_treasury(&self, movement: &TreasuryMovement) -> FlowExResult<()> {
+        let mut state = self.state.write().await;
+        let mut write = LedgerWrite::default();
+        let from = state.staged_balance(&mut write, movement.from.account(), &movement.currency);
+        if movement.amount > from.available {
+            return Err(FlowExError::Wallet(format!(
+                "The {:?} wallet holds {} {}, less than {}",
+                movement.from, from.available, movement.currency, movement.amount
+            )));
+        }
+        from.available -= movement.amount;
+        state.staged_balance(&mut write, movement.to.account(), &movement.currency).available += movement.amount;
+        write.treasury = Some(movement.clone());
+        self.commit(&mut state, &write).await
+    }
+
+    /// Lock `reservation.amount` for an order, or with `all_available` the
+    /// whole available balance, adjusting the amount already locked for it;
+    /// an amount of zero releases the reservation
+    pub async fn reserve(&self, reservation: Reservation) -> FlowExResult<BalanceChange> {
+        if reservation.amount < Decimal::ZERO {
+            return Err(FlowExError::Validation("Reservation amount must not be negative".to_string()));
+        }
+
+        let mut state = self.state.write().await;
+        let current = match state.reservations.get(&reservation.order_id) {
+            Some(existing) if existing.user_id != reservation.user_id || existing.currency != reservation.currency => {
+                return Err(FlowExError::Validation(format!(
+                    "Order {} already holds a reservation in {}",
+                    reservation.order_id, existing.currency
+                )));
+            }
+            Some(existing) => existing.amount,
+            None => Decimal::ZERO,
+        };
+
+        let mut write = LedgerWrite::default();
+        let balance = state.staged_balance(&mut write, reservation.user_id, &reservation.currency);
+        let amount = if reservation.all_available {
+            if balance.available.is_zero() {
+                return Err(FlowExError::Wallet(format!("No {} balance available", reservation.currency)));
+            }
+            current + balance.available
+        } else {
+            reservation.amount
+        };
+        let delta = amount - current;
+        if delta > balance.available {
+            return Err(FlowExError::Wallet(format!(
+                "Insufficient {} balance: {} available, {} required",
+                reservation.currency, balance.available, delta
+            )));
+        }
+        balance.available -= delta;
+        balance.locked += delta;
+        let reserved = (!amount.is_zero()).then(|| Reservation {
+            amount,
+            all_available: false,
+            ..reservation.clone()
+        });
+        *state.staged_reservation(&mut write, reservation.order_id) = reserved;
+
+        self.commit(&mut state, &write).await?;
+        debug!(
+            "Reserved {} {} for order {}",
+            amount, reservation.currency, reservation.order_id
+        );
+        Ok(changes(&write).remove(0))
+    }
+
+    /// Return what is still locked for an order to its owner's available
+    /// balance; `None` if nothing is
+    pub async fn release(&self, order_id: Uuid) -> FlowExResult<Option<(Reservation, BalanceChange)>> {
+        let mut state = self.state.write().await;
+        let Some(reservation) = state.reservations.get(&order_id).cloned() else {
+            return Ok(None);
+        };
+
+        let mut write = LedgerWrite::default();
+        let balance = state.staged_balance(&mut write, reservation.user_id, &reservation.currency);
+        balance.locked -= reservation.amount;
+        balance.available += reservation.amount;
+        *state.staged_reservation(&mut write, order_id) = None;
+
+        self.commit(&mut state, &write).await?;
+        debug!(
+            "Released {} {} for order {}",
+            reservation.amount, reservation.currency, order_id
+        );
+        Ok(Some((reservation, changes(&write).remove(0))))
+    }
+
+    /// Exchange a trade's assets between buyer and seller, each paying from
+    /// their order's reservation. A fee in the currency a side pays is paid
+    /// with it; otherwise it is deducted from what the side receives. A
+    /// trade a reservation does not cover is refused rather than overdrawing
+    /// the available balance, and a trade already settled changes nothing.
+    pub async fn settle(&self, settlement: &TradeSettlement) -> FlowExResult<Vec<BalanceChange>> {
+        let mut state = self.state.write().await;
+        if state.settled.contains(&settlement.trade_id) {
+            debug!("Trade {} is already settled", settlement.trade_id);
+            return Ok(Vec::new());
+        }
+
+        let base = settlement.base_asset.as_str();
+        let quote = settlement.quote_asset.as_str();
+        let notional = settlement.price * settlement.quantity;
+        let (buyer_pays, buyer_receives) = split_fee(&settlement.buyer, quote, notional, base, settlement.quantity);
+        let (seller_pays, seller_receives) = split_fee(&settlement.seller, base, settlement.quantity, quote, notional);
+
+        let debits = [
+            Debit { party: &settlement.buyer, currency: quote, amount: buyer_pays },
+            Debit { party: &settlement.seller, currency: base, amount: seller_pays },
+        ];
+        let credits = [
+            Credit { party: &settlement.buyer, currency: base, amount: buyer_receives },
+            Credit { party: &settlement.seller, currency: quote, amount: seller_receives },
+        ];
+
+        // Check both reservations cover the trade before moving anything
+        for debit in &debits {
+            let reserved = state.reserved(debit.party, debit.currency);
+            if debit.amount > reserved {
+                return Err(FlowExError::Wallet(format!(
+                    "Order {} has {} {} reserved, {} needed to settle trade {}",
+                    debit.party.order_id, reserved, debit.currency, debit.amount, settlement.trade_id
+                )));
+            }
+        }
+
+        let mut write = LedgerWrite::default();
+        for debit in &debits {
+            if !debit.amount.is_zero() {
+                if let Some(reservation) = state.staged_reservation(&mut write, debit.party.order_id) {
+                    reservation.amount -= debit.amount;
+                }
+            }
+            state.staged_balance(&mut write, debit.party.user_id, debit.currency).locked -= debit.amount;
+        }
+        for credit in &credits {
+            state.staged_balance(&mut write, credit.party.user_id, credit.currency).available += credit.amount;
+        }
+        for (party, pays, receives) in [(&settlement.buyer, quote, base), (&settlement.seller, base, quote)] {
+            if let Some(currency) = charged_fee(party, pays, receives) {
+                let entry = FeeEntry::new(FeeSource::Trading, currency, party.fee, party.user_id, settlement.trade_id);
+                state.stage_fee(&mut write, entry);
+            }
+        }
+        write.settled = Some(settlement.trade_id);
+
+        self.commit(&mut state, &write).await?;
+        info!(
+            "Settled trade {}: {} {} at {} {}",
+            settlement.trade_id, settlement.quantity, base, settlement.price, quote
+        );
+        Ok(changes(&write))
+    }
+
+    /// Write `write` to the store, then apply it to `state`
+    async fn commit(&self, state: &mut LedgerState, write: &LedgerWrite) -> FlowExResult<()> {
+        self.store.write(write).await?;
+        state.apply(write);
+        Ok(())
+    }
+}
+
+impl LedgerState {
+    /// Stage opening `user_id`'s account unless they have one
+    fn stage_account(&self, write: &mut LedgerWrite, user_id: Uuid) {
+        if !self.accounts.contains_key(&user_id) && !write.accounts.iter().any(|account| account.user_id == user_id) {
+            write.accounts.push(WalletAccount {
+                user_id,
+                email: None,
+                created_at: Utc::now(),
+            });
+        }
+    }
+
+    /// `user_id`'s balance in `currency` as `write` leaves it, staged in
+    /// `write`, with their account if it is new
+    fn staged_balance<'w>(&self, write: &'w mut LedgerWrite, user_id: Uuid, currency: &str) -> &'w mut Balance {
+        let index = match write
+            .balances
+            .iter()
+            .position(|(id, balance)| *id == user_id && balance.currency == currency)
+        {
+            Some(index) => index,
+            None => {
+                self.stage_account(write, user_id);
+                let balance = self
+                    .balances
+                    .get(&user_id)
+                    .and_then(|balances| balances.get(currency))
+                    .cloned()
+                    .unwrap_or_else(|| Balance {
+                        currency: currency.to_string(),
+                        available: Decimal::ZERO,
+                        locked: Decimal::ZERO,
+                    });
+                write.balances.push((user_id, balance));
+                write.balances.len() - 1
+            }
+        };
+        &mut write.balances[index].1
+    }
+
+    /// Pay `fee` into its revenue account, staged in `write`
+    fn stage_fee(&self, write: &mut LedgerWrite, fee: FeeEntry) {
+        self.staged_balance(write, fee.source.account(), &fee.currency).available += fee.amount;
+        write.fees.push(fee);
+    }
+
+    /// An order's reservation as `write` leaves it, staged in `write`
+    fn staged_reservation<'w>(&self, write: &'w mut LedgerWrite, order_id: Uuid) -> &'w mut Option<Reservation> {
+        let index = match write.reservations.iter().position(|(id, _)| *id == order_id) {
+            Some(index) => index,
+            None => {
+                write.reservations.push((order_id, self.reservations.get(&order_id).cloned()));
+                write.reservations.len() - 1
+            }
+        };
+        &mut write.reservations[index].1
+    }
+
+    fn apply(&mut self, write: &LedgerWrite) {
+        for account in &write.accounts {
+            let email = account
+                .email
+                .clone()
+                .or_else(|| self.accounts.get(&account.user_id).and_then(|known| known.email.clone()));
+            self.accounts.insert(account.user_id, WalletAccount { email, ..account.clone() });
+        }
+        for (user_id, balance) in &write.balances {
+            self.balances
+                .entry(*user_id)
+                .or_default()
+                .insert(balance.currency.clone(), balance.clone());
+        }
+        for (order_id, reservation) in &write.reservations {
+            match reservation {
+                Some(reservation) => self.reservations.insert(*order_id, reservation.clone()),
+                None => self.reservations.remove(order_id),
+            };
+        }
+        if let Some(trade_id) = write.settled {
+            self.settled.insert(trade_id);
+        }
+        self.fees.extend(write.fees.iter().cloned());
+    }
+
+    /// Amount `party`'s order still has locked in `currency`
+    fn reserved(&self, party: &SettlementParty, currency: &str) -> Decimal {
+        self.reservations
+            .get(&party.order_id)
+            .filter(|reservation| reservation.user_id == party.user_id && reservation.currency == currency)
+            .map_or(Decimal::ZERO, |reservation| reservation.amount)
+    }
+}
+
+/// Users' balances `write` changes, in the order it staged them
+fn changes(write: &LedgerWrite) -> Vec<BalanceChange> {
+    write
+        .balances
+        .iter()
+        .filter(|(user_id, _)| !is_system_account(*user_id))
+        .map(|(user_id, balance)| BalanceChange {
+            user_id: *user_id,
+            balance: balance.clone(),
+        })
+        .collect()
+}
+
+/// What a side pays and receives once its fee is charged to one or the other
+fn split_fee(
+    party: &SettlementParty,
+    pays_currency: &str,
+    pays: Decimal,
+    receives_currency: &str,
+    receives: Decimal,
+) -> (Decimal, Decimal) {
+    match charged_fee(party, pays_currency, receives_currency) {
+        Some(currency) if currency == pays_currency => (pays + party.fee, receives),
+        Some(_) => (pays, receives - party.fee),
+        None => (pays, receives),
+    }
+}
+
+/// Currency a side's fee is charged in, if it is charged at all: only a
+/// nonzero fee in the currency the side pays or receives is
+fn charged_fee<'a>(party: &'a SettlementParty, pays_currency: &str, receives_currency: &str) -> Option<&'a str> {
+    party
+        .fee_currency
+        .as_deref()
+        .filter(|currency| !party.fee.is_zero() && (*currency == pays_currency || *currency == receives_currency))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn party(user_id: Uuid, order_id: Uuid, fee: Decimal) -> SettlementParty {
+        SettlementParty {
+            user_id,
+            order_id,
+            fee,
+            fee_currency: Some("USDT".to_string()),
+        }
+    }
+
+    fn balance(balances: &[Balance], currency: &str) -> (Decimal, Decimal) {
+        balances
+            .iter()
+            .find(|balance| balance.currency == currency)
+            .map_or((Decimal::ZERO, Decimal::ZERO), |balance| (balance.available, balance.locked))
+    }
+
+    /// 测试：余额不足时拒绝冻结，调整冻结金额只冻结差额
+    #[tokio::test]
+    async fn test_reserve_checks_available_balance() {
+        let ledger = Ledger::new();
+        let user_id = Uuid::new_v4();
+        let order_id = Uuid::new_v4();
+        ledger.credit(user_id, "USDT", Decimal::new(1000, 0)).await.unwrap();
+        let reservation = |amount| Reservation {
+            order_id,
+            user_id,
+            currency: "USDT".to_string(),
+            amount: Decimal::new(amount, 0),
+            all_available: false,
+        };
+
+        assert!(matches!(ledger.reserve(reservation(1001)).await, Err(FlowExError::Wallet(_))));
+        ledger.reserve(reservation(600)).await.unwrap();
+        ledger.reserve(reservation(900)).await.unwrap();
+        assert_eq!(balance(&ledger.balances(user_id).await, "USDT"), (Decimal::new(100, 0), Decimal::new(900, 0)));
+
+        let (released, change) = ledger.release(order_id).await.unwrap().unwrap();
+        assert_eq!(released.amount, Decimal::new(900, 0));
+        assert_eq!(change.balance.available, Decimal::new(1000, 0));
+        assert!(ledger.release(order_id).await.unwrap().is_none());
+        assert_eq!(balance(&ledger.balances(user_id).await, "USDT"), (Decimal::new(1000, 0), Decimal::ZERO));
+    }
+
+    /// 测试：成交结算从双方冻结中扣款并入账，手续费以计价货币收取且只结算一次
+    #[tokio::test]
+    async fn test_settle_trade_against_reservations() {
+        let ledger = Ledger::new();
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let (buy_order, sell_order) = (Uuid::new_v4(), Uuid::new_v4());
+        ledger.credit(buyer, "USDT", Decimal::new(1000, 0)).await.unwrap();
+        ledger.credit(seller, "BTC", Decimal::new(2, 0)).await.unwrap();
+
+        // Buy 0.02 BTC at 45000 (900 USDT) with up to 0.1% fees
+        ledger
+            .reserve(Reservation { order_id: buy_order, user_id: buyer, currency: "USDT".to_string(), amount: Decimal::new(9009, 1), all_available: false })
+            .await
+            .unwrap();
+        ledger
+            .reserve(Reservation { order_id: sell_order, user_id: seller, currency: "BTC".to_string(), amount: Decimal::new(2, 2), all_available: false })
+            .await
+            .unwrap();
+
+        let settlement = TradeSettlement {
+            trade_id: Uuid::new_v4(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            price: Decimal::new(45000, 0),
+            quantity: Decimal::new(2, 2),
+            buyer: party(buyer, buy_order, Decimal::new(45, 2)),
+            seller: party(seller, sell_order, Decimal::new(9, 1)),
+        };
+        // 结算返回双方变动的四个余额，重复结算不再变动
+        assert_eq!(ledger.settle(&settlement).await.unwrap().len(), 4);
+        assert!(ledger.settle(&settlement).await.unwrap().is_empty());
+
+        let buyer_balances = ledger.balances(buyer).await;
+        assert_eq!(balance(&buyer_balances, "BTC"), (Decimal::new(2, 2), Decimal::ZERO));
+        assert_eq!(balance(&buyer_balances, "USDT"), (Decimal::new(991, 1), Decimal::new(45, 2)));
+        let seller_balances = ledger.balances(seller).await;
+        assert_eq!(balance(&seller_balances, "BTC"), (Decimal::new(198, 2), Decimal::ZERO));
+        assert_eq!(balance(&seller_balances, "USDT"), (Decimal::new(8991, 1), Decimal::ZERO));
+
+        // 双方手续费计入交易手续费收入账户并记账
+        let revenue = ledger.revenue(&RevenueQuery::default()).await;
+        assert_eq!(balance(&ledger.balances(revenue::TRADING_FEE_ACCOUNT).await, "USDT"), (Decimal::new(135, 2), Decimal::ZERO));
+        assert_eq!(revenue.buckets.len(), 1);
+        assert_eq!((revenue.buckets[0].amount, revenue.buckets[0].entries), (Decimal::new(135, 2), 2));
+        assert_eq!(revenue.balances.len(), 1);
+
+        // The buy order's unused fee reserve goes back when it is released
+        assert_eq!(ledger.release(buy_order).await.unwrap().unwrap().0.amount, Decimal::new(45, 2));
+        assert_eq!(balance(&ledger.balances(buyer).await, "USDT"), (Decimal::new(9955, 2), Decimal::ZERO));
+    }
+
+    /// 测试：成本未知的订单冻结全部可用余额，没有可用余额时拒绝
+    #[tokio::test]
+    async fn test_reserve_all_available() {
+        let ledger = Ledger::new();
+        let user_id = Uuid::new_v4();
+        let order_id = Uuid::new_v4();
+        ledger.credit(user_id, "USDT", Decimal::new(1000, 0)).await.unwrap();
+        ledger
+            .reserve(Reservation { order_id: Uuid::new_v4(), user_id, currency: "USDT".to_string(), amount: Decimal::new(300, 0), all_available: false })
+            .await
+            .unwrap();
+
+        let market_buy = Reservation {
+            order_id,
+            user_id,
+            currency: "USDT".to_string(),
+            amount: Decimal::ZERO,
+            all_available: true,
+        };
+        let change = ledger.reserve(market_buy.clone()).await.unwrap();
+        assert_eq!((change.balance.available, change.balance.locked), (Decimal::ZERO, Decimal::new(1000, 0)));
+        let (released, _) = ledger.release(order_id).await.unwrap().unwrap();
+        assert_eq!((released.amount, released.all_available), (Decimal::new(700, 0), false));
+
+        ledger.reserve(market_buy.clone()).await.unwrap();
+        let other = Reservation { order_id: Uuid::new_v4(), ..market_buy };
+        assert!(matches!(ledger.reserve(other).await, Err(FlowExError::Wallet(_))));
+    }
+
+    /// 测试：冻结不足以支付的成交被拒绝结算，不动用可用余额
+    #[tokio::test]
+    async fn test_settle_refuses_to_overdraw() {
+        let ledger = Ledger::new();
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let (buy_order, sell_order) = (Uuid::new_v4(), Uuid::new_v4());
+        ledger.credit(buyer, "USDT", Decimal::new(10000, 0)).await.unwrap();
+        ledger.credit(seller, "BTC", Decimal::new(2, 0)).await.unwrap();
+        // Reserved for 0.02 BTC at 45000, but the trade is at 50000
+        ledger
+            .reserve(Reservation { order_id: buy_order, user_id: buyer, currency: "USDT".to_string(), amount: Decimal::new(9009, 1), all_available: false })
+            .await
+            .unwrap();
+        ledger
+            .reserve(Reservation { order_id: sell_order, user_id: seller, currency: "BTC".to_string(), amount: Decimal::new(2, 2), all_available: false })
+            .await
+            .unwrap();
+
+        let settlement = TradeSettlement {
+            trade_id: Uuid::new_v4(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            price: Decimal::new(50000, 0),
+            quantity: Decimal::new(2, 2),
+            buyer: party(buyer, buy_order, Decimal::ONE),
+            seller: party(seller, sell_order, Decimal::ONE),
+        };
+        assert!(matches!(ledger.settle(&settlement).await, Err(FlowExError::Wallet(_))));
+        assert_eq!(balance(&ledger.balances(buyer).await, "USDT"), (Decimal::new(90991, 1), Decimal::new(9009, 1)));
+        assert_eq!(balance(&ledger.balances(seller).await, "BTC"), (Decimal::new(198, 2), Decimal::new(2, 2)));
+        assert_eq!(balance(&ledger.balances(buyer).await, "BTC"), (Decimal::ZERO, Decimal::ZERO));
+
+        // 未冻结的订单同样不能结算
+        let unreserved = TradeSettlement {
+            trade_id: Uuid::new_v4(),
+            price: Decimal::new(45000, 0),
+            buyer: party(buyer, Uuid::new_v4(), Decimal::ZERO),
+            ..settlement
+        };
+        assert!(matches!(ledger.settle(&unreserved).await, Err(FlowExError::Wallet(_))));
+    }
+
+    /// 测试：从存储快照恢复账户、余额、冻结和已结算成交
+    #[tokio::test]
+    async fn test_restore_from_snapshot() {
+        let user_id = Uuid::new_v4();
+        let order_id = Uuid::new_v4();
+        let trade_id = Uuid::new_v4();
+        let reservation = Reservation {
+            order_id,
+            user_id,
+            currency: "USDT".to_string(),
+            amount: Decimal::new(300, 0),
+            all_available: false,
+        };
+        let snapshot = WalletSnapshot {
+            accounts: vec![WalletAccount {
+                user_id,
+                email: Some("alice@flowex.com".to_string()),
+                created_at: Utc::now(),
+            }],
+            balances: vec![(
+                user_id,
+                Balance {
+                    currency: "USDT".to_string(),
+                    available: Decimal::new(700, 0),
+                    locked: Decimal::new(300, 0),
+                },
+            )],
+            reservations: vec![reservation.clone()],
+            settled: vec![trade_id],
+            ..WalletSnapshot::default()
+        };
+
+        let ledger = Ledger::with_store(WalletStore::Memory, &snapshot);
+        assert!(!ledger.open_account(user_id, "alice@flowex.com").await.unwrap());
+        assert_eq!(ledger.find_account_by_email("Alice@FlowEx.com").await, Some(user_id));
+        let settlement = TradeSettlement {
+            trade_id,
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            price: Decimal::new(45000, 0),
+            quantity: Decimal::new(1, 2),
+            buyer: party(user_id, order_id, Decimal::ZERO),
+            seller: party(Uuid::new_v4(), Uuid::new_v4(), Decimal::ZERO),
+        };
+        assert!(ledger.settle(&settlement).await.unwrap().is_empty());
+        let (released, change) = ledger.release(order_id).await.unwrap().unwrap();
+        assert_eq!(released, reservation);
+        assert_eq!((change.balance.available, change.balance.locked), (Decimal::new(1000, 0), Decimal::ZERO));
+    }
+}
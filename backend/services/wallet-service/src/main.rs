@@ -2,90 +2,251 @@
 //!
 //! Enterprise-grade wallet service providing balance management,
 //! transaction history, and deposit/withdrawal operations.
+//!
+//! The trading service reserves the funds of each order here before
+//! matching it and settles every trade against those reservations through
+//! the internal `/api/wallet/reservations` and `/api/wallet/settlements`
+//! endpoints (see `ledger`), each of which answers with the balances it
+//! changed. Those only accept requests the trading service signed with its
+//! `FLOWEX_SERVICE_SECRET_TRADING` (see `flowex_middleware::service_auth`).
+//!
+//! `POST /api/wallet/deposits` credits a deposit and
+//! `POST /api/wallet/withdrawals` locks a withdrawal's amount until custody
+//! reports it completed or failed through
+//! `PUT /api/wallet/withdrawals/:id/status`, signed with
+//! `FLOWEX_SERVICE_SECRET_CUSTODY` (see `funding`). Withdrawals above the
+//! auto-approval limit of their currency wait for admins to approve or
+//! reject them under `/api/admin/withdrawals`, and each user's withdrawals
+//! are capped per currency over any 24 hours (see `approval`); limits other
+//! than the defaults are read from `WITHDRAWAL_POLICY_PATH`. Users may only
+//! withdraw once their KYC level, looked up at the auth service
+//! (`AUTH_SERVICE_URL`), allows it, and within the lower daily limits the
+//! `flowex-kyc` policy sets for their level, read from `KYC_POLICY_PATH`
+//! unless the default applies.
+//!
+//! Withdrawals are only sent to addresses the user whitelisted under
+//! `/api/wallet/whitelist` at least 24 hours earlier, and users may set an
+//! anti-phishing code under `/api/wallet/security` that their notifications
+//! carry (see `security`).
+//!
+//! `POST /api/wallet/transfer` moves funds to another FlowEx account at
+//! once, addressed by user id or email (see `transfer`).
+//!
+//! A master account moves funds between itself and the sub-accounts it
+//! opened at the auth service with `POST /api/wallet/subaccounts/transfer`,
+//! and `GET /api/wallet/subaccounts/balances` reports their balances
+//! together with its own (see `subaccounts`).
+//!
+//! `GET /api/wallet/transactions` pages through the user's deposits,
+//! withdrawals and transfers, filtered by type, currency, status and time;
+//! with `format=csv` it streams them all as CSV instead (see `history`).
+//!
+//! Only assets registered under `/api/admin/assets` are deposited,
+//! withdrawn or transferred, within their precision and withdrawal minimum
+//! and fee; `GET /api/wallet/assets` lists them (see `assets`).
+//!
+//! At the end of every business day each account's balances are
+//! snapshotted and the ledger reconciled against them; admins read the
+//! reports and run a reconciliation on demand under
+//! `/api/admin/reconciliation` (see `reconciliation`).
+//!
+//! Trading and withdrawal fees are paid into the exchange's revenue
+//! accounts in the ledger; `GET /api/admin/revenue` reports them by day,
+//! week or month (see `revenue`).
+//!
+//! Every user balance is committed to a Merkle sum tree per currency at
+//! the end of each business day, or when an admin posts to
+//! `/api/admin/liabilities`. `GET /api/wallet/liabilities` publishes the
+//! roots and totals, and `GET /api/wallet/liabilities/proof/:currency`
+//! proves a user's balance is counted in them (see `liabilities`).
+//!
+//! What the exchange holds in its hot and cold wallets is tracked in the
+//! ledger too; admins record the funds they move between them and set the
+//! hot wallet float thresholds that raise sweep and refill alerts under
+//! `/api/admin/treasury` (see `treasury`).
+//!
+//! `POST /api/wallet/convert/quote` quotes converting an amount of one
+//! asset into another at the exchange's conversion desk, and
+//! `POST /api/wallet/convert` executes the quote within the slippage the
+//! user accepts (see `convert`).
+//!
+//! Balances, deposits, withdrawals, transfers, conversions and the portfolio are those of the user
+//! authenticated by the JWT bearer token the shared `jwt_auth_middleware`
+//! checks; a user's account is opened the first time they use it. Accounts,
+//! balances, reservations and funding transactions are kept in PostgreSQL
+//! when `FLOWEX_DATABASE_URL` is set, otherwise in memory (see `store`).
+
+mod approval;
+mod assets;
+mod convert;
+mod funding;
+mod history;
+mod ledger;
+mod liabilities;
+mod portfolio;
+mod reconciliation;
+mod revenue;
+mod security;
+mod store;
+mod subaccounts;
+mod transfer;
+mod treasury;
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
-    routing::get,
+    body::Body,
+    extract::{Extension, Path, Query, State},
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post, put},
     Router,
 };
+use flowex_kyc::{KycGate, KycPolicy};
+use flowex_middleware::auth::jwt_auth_middleware;
+use flowex_middleware::service_auth::{service_auth_middleware, ServiceAuth};
+use approval::{ReviewStatus, WithdrawalPolicy, WithdrawalUsage};
+use assets::{Asset, AssetConfig, AssetRegistry};
+use convert::{ConversionQuote, ConversionReceipt, ConvertRequest, Converter, QuoteRequest};
 use flowex_types::{
-    ApiResponse, Balance, HealthResponse, Transaction, TransactionStatus, TransactionType,
+    ApiResponse, AuthContext, Balance, BalanceChange, FlowExError, FlowExResult, HealthResponse, Reservation, Role,
+    TradeSettlement, TransactionHistoryQuery, TransactionType,
 };
+use funding::{DepositRequest, Funding, FundingReceipt, FundingTransaction, WithdrawalOutcome, WithdrawalRequest};
+use history::History;
+use ledger::Ledger;
+use liabilities::{InclusionProof, LiabilityReport, ProofOfLiabilities};
+use portfolio::{MarketDataClient, PortfolioService, PortfolioValuation};
+use reconciliation::{Reconciler, ReconciliationReport};
+use revenue::{RevenueQuery, RevenueReport};
 use rust_decimal::Decimal;
-use std::{collections::HashMap, sync::Arc, time::SystemTime};
-use tokio::sync::RwLock;
+use security::{AccountSecurity, AntiPhishingCodeRequest, SecuritySettings, WhitelistRequest, WhitelistedAddress};
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+use store::WalletStore;
+use subaccounts::{ConsolidatedBalances, SubAccountTransferRequest, SubAccounts};
+use transfer::{TransferReceipt, TransferRequest, Transfers};
+use treasury::{FloatThreshold, Treasury, TreasuryMovement, TreasuryMovementRequest, TreasuryOverview};
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
-use tracing::{info};
+use tracing::{info, warn};
 use uuid::Uuid;
 
 /// Application state for the wallet service
 #[derive(Clone)]
 pub struct AppState {
-    pub balances: Arc<RwLock<HashMap<String, Vec<Balance>>>>,
-    pub transactions: Arc<RwLock<HashMap<String, Vec<Transaction>>>>,
+    /// Accounts, trading balances and order reservations by user
+    pub ledger: Ledger,
+    /// Currencies the wallet accepts
+    pub assets: AssetRegistry,
+    /// Deposits and withdrawals, applied to the ledger
+    pub funding: Funding,
+    /// Transfers between accounts, posted to the ledger
+    pub transfers: Transfers,
+    /// Transaction history of funding and transfers
+    pub history: History,
+    /// End-of-day snapshots and reconciliation of the ledger
+    pub reconciler: Reconciler,
+    /// Published liability reports and inclusion proofs
+    pub liabilities: ProofOfLiabilities,
+    /// Hot and cold wallet holdings and movements
+    pub treasury: Treasury,
+    /// Quotes and conversions against the conversion desk
+    pub converter: Converter,
+    pub portfolio: PortfolioService,
+    /// What users may withdraw by their KYC level
+    pub kyc: KycGate,
+    /// Anti-phishing codes and withdrawal address whitelists
+    pub security: AccountSecurity,
+    /// Funds and balances of masters' sub-accounts
+    pub sub_accounts: SubAccounts,
+    /// Services allowed to reserve and settle funds
+    pub trading_service: ServiceAuth,
+    /// Services allowed to report withdrawal outcomes
+    pub custody_service: ServiceAuth,
     pub start_time: SystemTime,
 }
 
+/// How long a portfolio valuation is served from cache
+const PORTFOLIO_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Transaction history entries per page unless the request asks for fewer
+const DEFAULT_HISTORY_PAGE_LIMIT: usize = 50;
+
+/// Most transaction history entries a page may hold
+const MAX_HISTORY_PAGE_LIMIT: usize = 500;
+
 impl AppState {
+    /// State kept in memory only
     pub fn new() -> Self {
-        let mut balances = HashMap::new();
-        let mut transactions = HashMap::new();
-
-        // Initialize demo balances for demo user
-        let demo_balances = vec![
-            Balance {
-                currency: "BTC".to_string(),
-                available: Decimal::new(12345678, 8), // 0.12345678
-                locked: Decimal::new(0, 8),
-            },
-            Balance {
-                currency: "ETH".to_string(),
-                available: Decimal::new(245678901, 8), // 2.45678901
-                locked: Decimal::new(10000000, 8), // 0.10000000
-            },
-            Balance {
-                currency: "USDT".to_string(),
-                available: Decimal::new(100000000000, 8), // 1000.00000000
-                locked: Decimal::new(5000000000, 8), // 50.00000000
-            },
-            Balance {
-                currency: "BNB".to_string(),
-                available: Decimal::new(1050000000, 8), // 10.50000000
-                locked: Decimal::new(0, 8),
-            },
-        ];
+        let ledger = Ledger::new();
+        let assets = AssetRegistry::new();
+        let funding = Funding::new(ledger.clone(), assets.clone());
+        let transfers = Transfers::new(ledger.clone(), assets.clone());
+        let reconciler = Reconciler::new(funding.clone(), WalletStore::Memory, Vec::new());
+        let liabilities = ProofOfLiabilities::new(ledger.clone(), WalletStore::Memory, None);
+        let treasury = Treasury::new(ledger.clone(), assets.clone(), WalletStore::Memory, Vec::new(), Vec::new());
+        Self::with_ledger(ledger, assets, funding, transfers, reconciler, liabilities, treasury)
+    }
 
-        // Initialize demo transactions
-        let demo_transactions = vec![
-            Transaction {
-                id: Uuid::new_v4(),
-                user_id: Uuid::new_v4(),
-                transaction_type: TransactionType::Deposit,
-                currency: "BTC".to_string(),
-                amount: Decimal::new(10000000, 8), // 0.10000000
-                status: TransactionStatus::Completed,
-                created_at: chrono::Utc::now(),
-            },
-            Transaction {
-                id: Uuid::new_v4(),
-                user_id: Uuid::new_v4(),
-                transaction_type: TransactionType::Trade,
-                currency: "USDT".to_string(),
-                amount: Decimal::new(50000000000, 8), // 500.00000000
-                status: TransactionStatus::Completed,
-                created_at: chrono::Utc::now(),
-            },
-        ];
+    /// State kept in `store`, restored from what it holds
+    pub async fn with_store(store: WalletStore) -> FlowExResult<Self> {
+        let snapshot = store.load().await?;
+        let ledger = Ledger::with_store(store.clone(), &snapshot);
+        let assets = AssetRegistry::with_assets(store.clone(), snapshot.assets);
+        let funding = Funding::with_transactions(ledger.clone(), assets.clone(), snapshot.funding);
+        let transfers = Transfers::with_transfers(ledger.clone(), assets.clone(), snapshot.transfers);
+        let reconciler = Reconciler::new(funding.clone(), store.clone(), snapshot.reports);
+        let liabilities = ProofOfLiabilities::new(ledger.clone(), store.clone(), snapshot.liabilities);
+        let treasury = Treasury::new(
+            ledger.clone(),
+            assets.clone(),
+            store.clone(),
+            snapshot.float_thresholds,
+            snapshot.treasury_movements,
+        );
+        let security = AccountSecurity::new(
+            assets.clone(),
+            store,
+            snapshot.anti_phishing_codes,
+            snapshot.address_whitelist,
+        );
+        Ok(Self {
+            security,
+            ..Self::with_ledger(ledger, assets, funding, transfers, reconciler, liabilities, treasury)
+        })
+    }
 
-        balances.insert("demo@flowex.com".to_string(), demo_balances);
-        transactions.insert("demo@flowex.com".to_string(), demo_transactions);
+    fn with_ledger(
+        ledger: Ledger,
+        assets: AssetRegistry,
+        funding: Funding,
+        transfers: Transfers,
+        reconciler: Reconciler,
+        liabilities: ProofOfLiabilities,
+        treasury: Treasury,
+    ) -> Self {
+        let market_data_url = std::env::var("MARKET_DATA_SERVICE_URL")
+            .unwrap_or_else(|_| "http://localhost:8003".to_string());
+        let market_data = MarketDataClient::new(market_data_url, Duration::from_secs(2));
+        let auth_service_url =
+            std::env::var("AUTH_SERVICE_URL").unwrap_or_else(|_| "http://localhost:8001".to_string());
 
         Self {
-            balances: Arc::new(RwLock::new(balances)),
-            transactions: Arc::new(RwLock::new(transactions)),
+            converter: Converter::new(ledger.clone(), assets.clone(), market_data.clone()),
+            security: AccountSecurity::new(assets.clone(), WalletStore::Memory, Vec::new(), Vec::new()),
+            sub_accounts: SubAccounts::new(ledger.clone(), transfers.clone(), &auth_service_url),
+            ledger,
+            assets,
+            history: History::new(funding.clone(), transfers.clone()),
+            reconciler,
+            liabilities,
+            treasury,
+            funding,
+            transfers,
+            portfolio: PortfolioService::new(market_data, PORTFOLIO_CACHE_TTL),
+            kyc: KycGate::new(&auth_service_url, KycPolicy::default()),
+            trading_service: ServiceAuth::from_env(&["trading"]),
+            custody_service: ServiceAuth::from_env(&["custody"]),
             start_time: SystemTime::now(),
         }
     }
@@ -104,60 +265,816 @@ async fn health_check(State(state): State<AppState>) -> Json<HealthResponse> {
     })
 }
 
+/// Open the authenticated user's account on first use
+async fn open_account(state: &AppState, auth: &AuthContext) -> Result<(), StatusCode> {
+    state.ledger.open_account(auth.user_id, &auth.email).await.map(|_| ()).map_err(|e| {
+        warn!("Failed to open the wallet account of {}: {}", auth.user_id, e);
+        StatusCode::SERVICE_UNAVAILABLE
+    })
+}
+
 /// Get all balances for the user
-async fn get_balances(State(state): State<AppState>) -> Json<ApiResponse<Vec<Balance>>> {
-    let balances = state.balances.read().await;
-    
-    // In real implementation, extract user from JWT token
-    if let Some(user_balances) = balances.get("demo@flowex.com") {
-        Json(ApiResponse::success(user_balances.clone()))
-    } else {
-        Json(ApiResponse::success(vec![]))
-    }
+async fn get_balances(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<Vec<Balance>>>, StatusCode> {
+    open_account(&state, &auth).await?;
+    Ok(Json(ApiResponse::success(state.ledger.balances(auth.user_id).await)))
 }
 
 /// Get balance for a specific currency
 async fn get_balance(
     State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
     Path(currency): Path<String>,
 ) -> Result<Json<ApiResponse<Balance>>, StatusCode> {
-    let balances = state.balances.read().await;
-    
-    if let Some(user_balances) = balances.get("demo@flowex.com") {
-        if let Some(balance) = user_balances.iter().find(|b| b.currency == currency.to_uppercase()) {
-            Ok(Json(ApiResponse::success(balance.clone())))
-        } else {
-            Err(StatusCode::NOT_FOUND)
+    open_account(&state, &auth).await?;
+    let currency = currency.to_uppercase();
+    state
+        .ledger
+        .balances(auth.user_id)
+        .await
+        .into_iter()
+        .find(|balance| balance.currency == currency)
+        .map(|balance| Json(ApiResponse::success(balance)))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Format transaction history is returned in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum HistoryFormat {
+    /// One page wrapped in an `ApiResponse`
+    #[default]
+    Json,
+    /// Every selected entry as a CSV file
+    Csv,
+}
+
+/// Transaction history format query parameter
+#[derive(Debug, Deserialize)]
+struct HistoryFormatQuery {
+    #[serde(default)]
+    format: HistoryFormat,
+}
+
+/// Get a page of the authenticated user's transaction history, newest first
+/// unless `sort=asc`, or with `format=csv` every entry the filters select
+async fn get_transactions(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<TransactionHistoryQuery>,
+    Query(format): Query<HistoryFormatQuery>,
+) -> Result<Response, StatusCode> {
+    open_account(&state, &auth).await?;
+    if format.format == HistoryFormat::Csv {
+        let body = Body::from_stream(state.history.export_csv(auth.user_id, query));
+        let headers = [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"transactions.csv\""),
+        ];
+        return Ok((headers, body).into_response());
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_PAGE_LIMIT);
+    if limit == 0 || limit > MAX_HISTORY_PAGE_LIMIT {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let page = state.history.page(auth.user_id, &query, limit).await;
+    Ok(Json(ApiResponse::success(page)).into_response())
+}
+
+/// Portfolio valuation query parameters
+#[derive(Debug, Deserialize)]
+struct PortfolioQuery {
+    quote: Option<String>,
+}
+
+/// Get the portfolio valued in a quote currency
+async fn get_portfolio(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<PortfolioQuery>,
+) -> Result<Json<ApiResponse<PortfolioValuation>>, StatusCode> {
+    let quote = query
+        .quote
+        .map(|q| q.trim().to_uppercase())
+        .unwrap_or_else(|| "USDT".to_string());
+
+    if quote.is_empty() || !quote.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    open_account(&state, &auth).await?;
+    let account = auth.user_id.to_string();
+    let balances = state.ledger.balances(auth.user_id).await;
+
+    match state.portfolio.valuation(&account, &balances, &quote).await {
+        Ok(valuation) => Ok(Json(ApiResponse::success(valuation))),
+        Err(e) => {
+            warn!("Failed to value portfolio: {}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+/// Lock funds for an order, or adjust the amount locked for it
+async fn reserve_funds(
+    State(state): State<AppState>,
+    Json(reservation): Json<Reservation>,
+) -> Result<Json<ApiResponse<Vec<BalanceChange>>>, StatusCode> {
+    match state.ledger.reserve(reservation).await {
+        Ok(change) => Ok(Json(ApiResponse::success(vec![change]))),
+        Err(e) => {
+            info!("Reservation refused: {}", e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Release the funds still locked for an order
+async fn release_funds(
+    State(state): State<AppState>,
+    Path(order_id): Path<Uuid>,
+) -> Result<Json<ApiResponse<Vec<BalanceChange>>>, StatusCode> {
+    match state.ledger.release(order_id).await {
+        Ok(Some((_, change))) => Ok(Json(ApiResponse::success(vec![change]))),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            warn!("Release of order {} failed: {}", order_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Settle a trade between the buyer's and seller's wallets
+async fn settle_trade(
+    State(state): State<AppState>,
+    Json(settlement): Json<TradeSettlement>,
+) -> Result<Json<ApiResponse<Vec<BalanceChange>>>, StatusCode> {
+    match state.ledger.settle(&settlement).await {
+        Ok(changes) => Ok(Json(ApiResponse::success(changes))),
+        Err(e) => {
+            warn!("Settlement of trade {} failed: {}", settlement.trade_id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Credit a deposit to the user
+async fn create_deposit(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<DepositRequest>,
+) -> Result<Json<ApiResponse<FundingReceipt>>, StatusCode> {
+    match state.funding.deposit(auth.user_id, request).await {
+        Ok(receipt) => Ok(Json(ApiResponse::success(receipt))),
+        Err(e) => {
+            info!("Deposit refused: {}", e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Request a withdrawal for the user, locking its amount until it is sent
+async fn create_withdrawal(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<WithdrawalRequest>,
+) -> Result<Json<ApiResponse<FundingReceipt>>, StatusCode> {
+    let (level, tier) = state.kyc.limits(auth.user_id).await;
+    if !tier.can_withdraw {
+        info!("Withdrawal refused: KYC level {} of user {} may not withdraw", level.as_str(), auth.user_id);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if let Err(e) = state.security.check_withdrawal(auth.user_id, &request.currency, &request.address).await {
+        info!("Withdrawal of user {} refused: {}", auth.user_id, e);
+        return Err(rejection_status(&e));
+    }
+    match state.funding.withdraw(auth.user_id, request, &tier.daily_withdrawal_limits).await {
+        Ok(receipt) => Ok(Json(ApiResponse::success(receipt))),
+        Err(e) => {
+            info!("Withdrawal refused: {}", e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Transfer funds from the user to another account
+async fn create_transfer(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<TransferRequest>,
+) -> Result<Json<ApiResponse<TransferReceipt>>, StatusCode> {
+    open_account(&state, &auth).await?;
+    match state.transfers.transfer(auth.user_id, request).await {
+        Ok(receipt) => Ok(Json(ApiResponse::success(receipt))),
+        Err(e) => {
+            info!("Transfer refused: {}", e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Move funds between the user's account and its sub-accounts
+async fn create_sub_account_transfer(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<SubAccountTransferRequest>,
+) -> Result<Json<ApiResponse<TransferReceipt>>, StatusCode> {
+    open_account(&state, &auth).await?;
+    match state.sub_accounts.transfer(auth.user_id, request).await {
+        Ok(receipt) => Ok(Json(ApiResponse::success(receipt))),
+        Err(e) => {
+            info!("Sub-account transfer refused: {}", e);
+            Err(sub_account_status(&e))
+        }
+    }
+}
+
+/// Balances of the user and its sub-accounts, with their totals
+async fn get_sub_account_balances(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<ConsolidatedBalances>>, StatusCode> {
+    open_account(&state, &auth).await?;
+    match state.sub_accounts.balances(auth.user_id).await {
+        Ok(balances) => Ok(Json(ApiResponse::success(balances))),
+        Err(e) => {
+            warn!("Consolidated balances of {} unavailable: {}", auth.user_id, e);
+            Err(sub_account_status(&e))
+        }
+    }
+}
+
+/// HTTP status for a sub-account request that failed, the auth service
+/// being unreachable if it is an internal error
+fn sub_account_status(error: &FlowExError) -> StatusCode {
+    match error {
+        FlowExError::Internal(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => rejection_status(error),
+    }
+}
+
+/// Quote converting an amount of one asset into another for the user
+async fn quote_conversion(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<QuoteRequest>,
+) -> Result<Json<ApiResponse<ConversionQuote>>, StatusCode> {
+    match state.converter.quote(auth.user_id, request).await {
+        Ok(quote) => Ok(Json(ApiResponse::success(quote))),
+        Err(e) => {
+            info!("Conversion quote refused: {}", e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Execute one of the user's conversion quotes
+async fn create_conversion(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<ConvertRequest>,
+) -> Result<Json<ApiResponse<ConversionReceipt>>, StatusCode> {
+    open_account(&state, &auth).await?;
+    match state.converter.convert(auth.user_id, request).await {
+        Ok(receipt) => Ok(Json(ApiResponse::success(receipt))),
+        Err(e) => {
+            info!("Conversion refused: {}", e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Get one of the user's deposits
+async fn get_deposit(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<FundingTransaction>>, StatusCode> {
+    let deposit = funding_transaction(&state, id, TransactionType::Deposit).await?;
+    owned_by(deposit, &auth)
+}
+
+/// Get one of the user's withdrawals
+async fn get_withdrawal(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<FundingTransaction>>, StatusCode> {
+    let withdrawal = funding_transaction(&state, id, TransactionType::Withdrawal).await?;
+    owned_by(withdrawal, &auth)
+}
+
+async fn funding_transaction(
+    state: &AppState,
+    id: Uuid,
+    transaction_type: TransactionType,
+) -> Result<FundingTransaction, StatusCode> {
+    state
+        .funding
+        .get(id)
+        .await
+        .filter(|transaction| transaction.transaction_type == transaction_type)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// The transaction if the user owns it; other users' transactions are not
+/// found
+fn owned_by(
+    transaction: FundingTransaction,
+    auth: &AuthContext,
+) -> Result<Json<ApiResponse<FundingTransaction>>, StatusCode> {
+    if transaction.user_id != auth.user_id {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(ApiResponse::success(transaction)))
+}
+
+/// Record custody's outcome of a pending withdrawal; one already completed
+/// or failed stays so
+async fn finish_withdrawal(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(outcome): Json<WithdrawalOutcome>,
+) -> Result<Json<ApiResponse<FundingReceipt>>, StatusCode> {
+    funding_transaction(&state, id, TransactionType::Withdrawal).await?;
+    match state.funding.finish_withdrawal(id, outcome).await {
+        Ok(receipt) => Ok(Json(ApiResponse::success(receipt))),
+        Err(e) => {
+            warn!("Outcome of withdrawal {} refused: {}", id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// The user's withdrawals against their daily limits, those of their KYC
+/// level included
+async fn get_withdrawal_limits(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Json<ApiResponse<Vec<WithdrawalUsage>>> {
+    let (_, tier) = state.kyc.limits(auth.user_id).await;
+    Json(ApiResponse::success(
+        state.funding.withdrawal_usage(auth.user_id, &tier.daily_withdrawal_limits).await,
+    ))
+}
+
+/// Withdrawal review queue query parameters
+#[derive(Debug, Deserialize)]
+struct ReviewQueueQuery {
+    /// Review status listed; pending if not given
+    review: Option<ReviewStatus>,
+}
+
+/// Withdrawals by review status, oldest first (admin only)
+async fn get_review_queue(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<ReviewQueueQuery>,
+) -> Result<Json<ApiResponse<Vec<FundingTransaction>>>, StatusCode> {
+    require_admin(&auth)?;
+    let queue = state
+        .funding
+        .review_queue(query.review.unwrap_or(ReviewStatus::Pending))
+        .await;
+    Ok(Json(ApiResponse::success(queue)))
+}
+
+/// Approve a withdrawal under review (admin only)
+async fn approve_withdrawal(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<FundingReceipt>>, StatusCode> {
+    require_admin(&auth)?;
+    funding_transaction(&state, id, TransactionType::Withdrawal).await?;
+    match state.funding.approve(id, auth.user_id).await {
+        Ok(receipt) => Ok(Json(ApiResponse::success(receipt))),
+        Err(e) => {
+            info!("Approval of withdrawal {} refused: {}", id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Withdrawal rejection request
+#[derive(Debug, Deserialize)]
+struct RejectWithdrawalRequest {
+    reason: String,
+}
+
+/// Reject a withdrawal under review, returning its funds (admin only)
+async fn reject_withdrawal(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<RejectWithdrawalRequest>,
+) -> Result<Json<ApiResponse<FundingReceipt>>, StatusCode> {
+    require_admin(&auth)?;
+    funding_transaction(&state, id, TransactionType::Withdrawal).await?;
+    match state.funding.reject(id, auth.user_id, request.reason).await {
+        Ok(receipt) => Ok(Json(ApiResponse::success(receipt))),
+        Err(e) => {
+            info!("Rejection of withdrawal {} refused: {}", id, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Approval and daily limits of withdrawals (admin only)
+async fn get_withdrawal_policy(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<WithdrawalPolicy>>, StatusCode> {
+    require_admin(&auth)?;
+    Ok(Json(ApiResponse::success(state.funding.policy())))
+}
+
+/// Every registered asset
+async fn get_assets(State(state): State<AppState>) -> Json<ApiResponse<Vec<Asset>>> {
+    Json(ApiResponse::success(state.assets.list().await))
+}
+
+/// One registered asset
+async fn get_asset(
+    State(state): State<AppState>,
+    Path(currency): Path<String>,
+) -> Result<Json<ApiResponse<Asset>>, StatusCode> {
+    state
+        .assets
+        .get(&currency)
+        .await
+        .map(|asset| Json(ApiResponse::success(asset)))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Register an asset or change its configuration (admin only)
+async fn set_asset(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(currency): Path<String>,
+    Json(config): Json<AssetConfig>,
+) -> Result<Json<ApiResponse<Asset>>, StatusCode> {
+    require_admin(&auth)?;
+    match state.assets.set(&currency, config).await {
+        Ok(asset) => {
+            info!("Admin {} set asset {}", auth.user_id, asset.currency);
+            Ok(Json(ApiResponse::success(asset)))
+        }
+        Err(e) => {
+            info!("Asset {} refused: {}", currency, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Unregister an asset no account holds (admin only)
+async fn delete_asset(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(currency): Path<String>,
+) -> Result<Json<ApiResponse<Asset>>, StatusCode> {
+    require_admin(&auth)?;
+    let currency = currency.trim().to_uppercase();
+    if state.ledger.total(&currency).await > Decimal::ZERO {
+        info!("Asset {} not removed: accounts still hold it", currency);
+        return Err(StatusCode::CONFLICT);
+    }
+    match state.assets.remove(&currency).await {
+        Ok(Some(asset)) => {
+            info!("Admin {} removed asset {}", auth.user_id, currency);
+            Ok(Json(ApiResponse::success(asset)))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// Reconciliation reports query parameters
+#[derive(Debug, Deserialize)]
+struct ReconciliationReportsQuery {
+    limit: Option<usize>,
+}
+
+/// Latest reconciliation reports, newest first (admin only)
+async fn get_reconciliation_reports(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<ReconciliationReportsQuery>,
+) -> Result<Json<ApiResponse<Vec<ReconciliationReport>>>, StatusCode> {
+    require_admin(&auth)?;
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_PAGE_LIMIT);
+    if limit == 0 || limit > MAX_HISTORY_PAGE_LIMIT {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Json(ApiResponse::success(state.reconciler.reports(limit).await)))
+}
+
+/// Snapshot every balance and reconcile the ledger now, as of today
+/// (admin only)
+async fn run_reconciliation(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<ReconciliationReport>>, StatusCode> {
+    require_admin(&auth)?;
+    info!("Admin {} ran a reconciliation", auth.user_id);
+    match state.reconciler.run(chrono::Utc::now().date_naive()).await {
+        Ok(report) => Ok(Json(ApiResponse::success(report))),
+        Err(e) => {
+            warn!("Reconciliation failed: {}", e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Fee revenue by period, with what the revenue accounts hold (admin only)
+async fn get_revenue(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<RevenueQuery>,
+) -> Result<Json<ApiResponse<RevenueReport>>, StatusCode> {
+    require_admin(&auth)?;
+    if matches!((query.start_time, query.end_time), (Some(start), Some(end)) if start >= end) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Json(ApiResponse::success(state.ledger.revenue(&query).await)))
+}
+
+/// Latest liability report: each currency's Merkle root and total
+async fn get_liabilities(State(state): State<AppState>) -> Result<Json<ApiResponse<LiabilityReport>>, StatusCode> {
+    state
+        .liabilities
+        .latest()
+        .await
+        .map(|report| Json(ApiResponse::success(report)))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Proof the user's balance in a currency is counted in the latest
+/// liability report
+async fn get_liability_proof(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(currency): Path<String>,
+) -> Result<Json<ApiResponse<InclusionProof>>, StatusCode> {
+    state
+        .liabilities
+        .proof(auth.user_id, &currency.to_uppercase())
+        .await
+        .map(|proof| Json(ApiResponse::success(proof)))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Commit every balance to a new liability report and publish it (admin
+/// only)
+async fn publish_liabilities(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<LiabilityReport>>, StatusCode> {
+    require_admin(&auth)?;
+    info!("Admin {} published a liability report", auth.user_id);
+    match state.liabilities.publish().await {
+        Ok(report) => Ok(Json(ApiResponse::success(report))),
+        Err(e) => {
+            warn!("Publishing liability report failed: {}", e);
+            Err(rejection_status(&e))
         }
-    } else {
-        Err(StatusCode::NOT_FOUND)
     }
 }
 
-/// Get transaction history
-async fn get_transactions(State(state): State<AppState>) -> Json<ApiResponse<Vec<Transaction>>> {
-    let transactions = state.transactions.read().await;
-    
-    // In real implementation, extract user from JWT token
-    if let Some(user_transactions) = transactions.get("demo@flowex.com") {
-        Json(ApiResponse::success(user_transactions.clone()))
+/// What the hot and cold wallets hold, with float alerts (admin only)
+async fn get_treasury(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<TreasuryOverview>>, StatusCode> {
+    require_admin(&auth)?;
+    Ok(Json(ApiResponse::success(state.treasury.overview().await)))
+}
+
+/// Treasury movements query parameters
+#[derive(Debug, Deserialize)]
+struct TreasuryMovementsQuery {
+    limit: Option<usize>,
+}
+
+/// Latest movements between the custody wallets, newest first (admin only)
+async fn get_treasury_movements(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<TreasuryMovementsQuery>,
+) -> Result<Json<ApiResponse<Vec<TreasuryMovement>>>, StatusCode> {
+    require_admin(&auth)?;
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_PAGE_LIMIT);
+    if limit == 0 || limit > MAX_HISTORY_PAGE_LIMIT {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Json(ApiResponse::success(state.treasury.movements(limit).await)))
+}
+
+/// Record funds moved between the custody wallets (admin only)
+async fn record_treasury_movement(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<TreasuryMovementRequest>,
+) -> Result<Json<ApiResponse<TreasuryMovement>>, StatusCode> {
+    require_admin(&auth)?;
+    match state.treasury.record(auth.user_id, request).await {
+        Ok(movement) => Ok(Json(ApiResponse::success(movement))),
+        Err(e) => {
+            info!("Treasury movement refused: {}", e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Set the hot wallet float threshold of a currency (admin only)
+async fn set_float_threshold(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(currency): Path<String>,
+    Json(threshold): Json<FloatThreshold>,
+) -> Result<Json<ApiResponse<FloatThreshold>>, StatusCode> {
+    require_admin(&auth)?;
+    match state.treasury.set_threshold(&currency, threshold).await {
+        Ok(threshold) => {
+            info!("Admin {} set the {} float threshold", auth.user_id, currency);
+            Ok(Json(ApiResponse::success(threshold)))
+        }
+        Err(e) => {
+            info!("Float threshold of {} refused: {}", currency, e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// The user's anti-phishing code and withdrawal address whitelist
+async fn get_security_settings(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Json<ApiResponse<SecuritySettings>> {
+    Json(ApiResponse::success(state.security.settings(auth.user_id).await))
+}
+
+/// Set the user's anti-phishing code
+async fn set_anti_phishing_code(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<AntiPhishingCodeRequest>,
+) -> Result<Json<ApiResponse<SecuritySettings>>, StatusCode> {
+    if let Err(e) = state.security.set_anti_phishing_code(auth.user_id, &request.code).await {
+        info!("Anti-phishing code refused: {}", e);
+        return Err(rejection_status(&e));
+    }
+    Ok(Json(ApiResponse::success(state.security.settings(auth.user_id).await)))
+}
+
+/// Remove the user's anti-phishing code
+async fn clear_anti_phishing_code(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<ApiResponse<SecuritySettings>>, StatusCode> {
+    match state.security.clear_anti_phishing_code(auth.user_id).await {
+        Ok(true) => Ok(Json(ApiResponse::success(state.security.settings(auth.user_id).await))),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// The user's whitelisted withdrawal addresses, oldest first
+async fn get_whitelist(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Json<ApiResponse<Vec<WhitelistedAddress>>> {
+    Json(ApiResponse::success(state.security.whitelist(auth.user_id).await))
+}
+
+/// Whitelist a withdrawal address for the user, usable after the
+/// activation delay
+async fn add_whitelisted_address(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<WhitelistRequest>,
+) -> Result<Json<ApiResponse<WhitelistedAddress>>, StatusCode> {
+    match state.security.add_address(auth.user_id, request).await {
+        Ok(entry) => Ok(Json(ApiResponse::success(entry))),
+        Err(e) => {
+            info!("Whitelisted address refused: {}", e);
+            Err(rejection_status(&e))
+        }
+    }
+}
+
+/// Remove an address from the user's whitelist
+async fn remove_whitelisted_address(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApiResponse<WhitelistedAddress>>, StatusCode> {
+    match state.security.remove_address(auth.user_id, id).await {
+        Ok(Some(entry)) => Ok(Json(ApiResponse::success(entry))),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(rejection_status(&e)),
+    }
+}
+
+/// Require an admin role
+fn require_admin(auth: &AuthContext) -> Result<(), StatusCode> {
+    let is_admin = auth
+        .roles
+        .iter()
+        .any(|role| role == Role::Admin.as_str() || role == Role::SuperAdmin.as_str());
+    if is_admin {
+        Ok(())
     } else {
-        Json(ApiResponse::success(vec![]))
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+/// HTTP status for a ledger operation that was refused
+fn rejection_status(error: &FlowExError) -> StatusCode {
+    match error {
+        FlowExError::Wallet(_) => StatusCode::CONFLICT,
+        FlowExError::Authorization(_) => StatusCode::FORBIDDEN,
+        FlowExError::Database(_) | FlowExError::MarketData(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_REQUEST,
     }
 }
 
 /// Create the application router
 fn create_app(state: AppState) -> Router {
-    Router::new()
-        .route("/health", get(health_check))
+    let authenticated = Router::new()
         .route("/api/wallet/balances", get(get_balances))
         .route("/api/wallet/balance/:currency", get(get_balance))
         .route("/api/wallet/transactions", get(get_transactions))
+        .route("/api/wallet/portfolio", get(get_portfolio))
+        .route("/api/wallet/deposits", post(create_deposit))
+        .route("/api/wallet/deposits/:id", get(get_deposit))
+        .route("/api/wallet/withdrawals", post(create_withdrawal))
+        .route("/api/wallet/withdrawals/:id", get(get_withdrawal))
+        .route("/api/wallet/withdrawal-limits", get(get_withdrawal_limits))
+        .route("/api/wallet/transfer", post(create_transfer))
+        .route("/api/wallet/subaccounts/transfer", post(create_sub_account_transfer))
+        .route("/api/wallet/subaccounts/balances", get(get_sub_account_balances))
+        .route("/api/wallet/convert/quote", post(quote_conversion))
+        .route("/api/wallet/convert", post(create_conversion))
+        .route("/api/wallet/liabilities/proof/:currency", get(get_liability_proof))
+        .route("/api/wallet/security", get(get_security_settings))
+        .route(
+            "/api/wallet/security/anti-phishing-code",
+            put(set_anti_phishing_code).delete(clear_anti_phishing_code),
+        )
+        .route("/api/wallet/whitelist", get(get_whitelist).post(add_whitelisted_address))
+        .route("/api/wallet/whitelist/:id", delete(remove_whitelisted_address))
+        .route("/api/admin/withdrawals", get(get_review_queue))
+        .route("/api/admin/withdrawals/policy", get(get_withdrawal_policy))
+        .route("/api/admin/withdrawals/:id/approve", post(approve_withdrawal))
+        .route("/api/admin/withdrawals/:id/reject", post(reject_withdrawal))
+        .route("/api/admin/assets/:currency", put(set_asset).delete(delete_asset))
+        .route(
+            "/api/admin/reconciliation",
+            get(get_reconciliation_reports).post(run_reconciliation),
+        )
+        .route("/api/admin/revenue", get(get_revenue))
+        .route("/api/admin/liabilities", post(publish_liabilities))
+        .route("/api/admin/treasury", get(get_treasury))
+        .route(
+            "/api/admin/treasury/movements",
+            get(get_treasury_movements).post(record_treasury_movement),
+        )
+        .route("/api/admin/treasury/thresholds/:currency", put(set_float_threshold))
+        .route_layer(middleware::from_fn(jwt_auth_middleware));
+
+    let internal = Router::new()
+        .route("/api/wallet/reservations", post(reserve_funds))
+        .route("/api/wallet/reservations/:order_id", delete(release_funds))
+        .route("/api/wallet/settlements", post(settle_trade))
+        .route_layer(middleware::from_fn_with_state(
+            state.trading_service.clone(),
+            service_auth_middleware,
+        ));
+
+    let custody = Router::new()
+        .route("/api/wallet/withdrawals/:id/status", put(finish_withdrawal))
+        .route_layer(middleware::from_fn_with_state(
+            state.custody_service.clone(),
+            service_auth_middleware,
+        ));
+
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/api/wallet/assets", get(get_assets))
+        .route("/api/wallet/assets/:currency", get(get_asset))
+        .route("/api/wallet/liabilities", get(get_liabilities))
+        .merge(authenticated)
         .layer(
             ServiceBuilder::new()
                 .layer(CorsLayer::permissive())
                 .into_inner(),
         )
+        .merge(internal)
+        .merge(custody)
         .with_state(state)
 }
 
@@ -171,8 +1088,30 @@ async fn main() -> anyhow::Result<()> {
 
     info!("Starting FlowEx Wallet Service");
 
-    let state = AppState::new();
-    let app = create_app(state);
+    let prometheus = flowex_metrics::install_prometheus_recorder()?;
+    let mut state = match std::env::var("FLOWEX_DATABASE_URL") {
+        Ok(database_url) => AppState::with_store(WalletStore::connect(&database_url).await?).await?,
+        Err(_) => {
+            warn!("FLOWEX_DATABASE_URL is not set; wallet accounts are kept in memory only");
+            AppState::new()
+        }
+    };
+    if let Ok(path) = std::env::var("WITHDRAWAL_POLICY_PATH") {
+        state.funding.set_policy(WithdrawalPolicy::load(&path)?)?;
+    }
+    if let Ok(path) = std::env::var("KYC_POLICY_PATH") {
+        state.kyc.set_policy(KycPolicy::load(&path)?)?;
+    }
+    match std::env::var("TRANSFER_WEBHOOK_URL") {
+        Ok(url) => {
+            transfer::spawn_webhook(state.transfers.subscribe(), url, state.security.clone());
+        }
+        Err(_) => warn!("TRANSFER_WEBHOOK_URL is not set; transfer notifications are not delivered"),
+    }
+    reconciliation::spawn_end_of_day(state.reconciler.clone());
+    liabilities::spawn_end_of_day(state.liabilities.clone());
+    treasury::spawn_float_monitor(state.treasury.clone());
+    let app = create_app(state).route("/metrics", get(move || std::future::ready(prometheus.render())));
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8004").await?;
     info!("Wallet service listening on http://0.0.0.0:8004");
@@ -185,15 +1124,16 @@ async fn main() -> anyhow::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use axum::{
-        body::Body,
-        http::{Request, StatusCode},
-    };
+    use axum::http::Request;
+    use flowex_types::{Page, Transaction, TransactionStatus};
     use tower::ServiceExt;
     use std::sync::Once;
 
     static INIT: Once = Once::new();
 
+    /// 测试账户所属用户
+    const TEST_USER_ID: Uuid = Uuid::from_u128(0x7e57);
+
     /// 初始化测试环境
     fn init_test_env() {
         INIT.call_once(|| {
@@ -204,82 +1144,116 @@ mod tests {
         });
     }
 
-    /// 创建测试用的应用状态
-    fn create_test_app_state() -> AppState {
-        let mut balances = HashMap::new();
-
-        // 添加测试余额数据
-        balances.insert("BTC".to_string(), Balance {
-            currency: "BTC".to_string(),
-            available: Decimal::new(123456, 6), // 0.123456
-            locked: Decimal::new(10000, 6), // 0.010000
-        });
+    /// 测试令牌的HS256签名密钥，首次签发时设为JWT_SECRET
+    const TEST_JWT_SECRET: &str = "flowex_test_jwt_secret";
+
+    static JWT_SECRET: Once = Once::new();
+
+    /// 签发测试用户的JWT访问令牌
+    fn bearer_token(user_id: Uuid) -> String {
+        let now = chrono::Utc::now();
+        let claims = flowex_types::JwtClaims {
+            sub: user_id.to_string(),
+            email: "wallet@flowex.com".to_string(),
+            iss: flowex_middleware::auth::TOKEN_ISSUER.to_string(),
+            aud: flowex_middleware::auth::TOKEN_AUDIENCE.to_string(),
+            exp: (now + chrono::Duration::hours(1)).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+            roles: vec![Role::Trader.as_str().to_string()],
+            permissions: Vec::new(),
+            account: Default::default(),
+        };
+        JWT_SECRET.call_once(|| std::env::set_var("JWT_SECRET", TEST_JWT_SECRET));
+        let secret = TEST_JWT_SECRET;
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(secret.as_ref()),
+        )
+        .unwrap();
+        format!("Bearer {}", token)
+    }
 
-        balances.insert("ETH".to_string(), Balance {
-            currency: "ETH".to_string(),
-            available: Decimal::new(2500000, 6), // 2.500000
-            locked: Decimal::new(100000, 6), // 0.100000
-        });
+    /// 创建测试用的应用状态，测试用户持有BTC、ETH和USDT存款
+    async fn create_test_app_state() -> AppState {
+        let state = AppState::new();
+        state.ledger.open_account(TEST_USER_ID, "wallet@flowex.com").await.unwrap();
+
+        // 添加测试存款数据
+        for (currency, amount) in [
+            ("BTC", Decimal::new(123456, 6)),     // 0.123456
+            ("ETH", Decimal::new(2500000, 6)),    // 2.500000
+            ("USDT", Decimal::new(1000000000, 6)), // 1000.000000
+        ] {
+            let deposit = DepositRequest {
+                currency: currency.to_string(),
+                amount,
+                tx_hash: None,
+            };
+            state.funding.deposit(TEST_USER_ID, deposit).await.unwrap();
+        }
 
-        balances.insert("USDT".to_string(), Balance {
-            currency: "USDT".to_string(),
-            available: Decimal::new(1000000000, 6), // 1000.000000
-            locked: Decimal::new(50000000, 6), // 50.000000
-        });
+        state
+    }
 
-        let mut transactions = Vec::new();
+    /// 以测试用户身份发送GET请求
+    async fn get(state: &AppState, uri: &str) -> Response {
+        create_app(state.clone())
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .header("authorization", bearer_token(TEST_USER_ID))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
 
-        // 添加测试交易数据
-        transactions.push(Transaction {
-            id: Uuid::new_v4(),
-            user_id: Uuid::new_v4(),
-            transaction_type: TransactionType::Deposit,
-            currency: "BTC".to_string(),
-            amount: Decimal::new(100000, 6), // 0.100000
-            status: TransactionStatus::Completed,
-            created_at: chrono::Utc::now(),
-            updated_at: chrono::Utc::now(),
-        });
+    /// 测试用交易服务密钥
+    const TEST_SERVICE_SECRET: &str = "trading-s3cret";
 
-        transactions.push(Transaction {
-            id: Uuid::new_v4(),
-            user_id: Uuid::new_v4(),
-            transaction_type: TransactionType::Withdrawal,
-            currency: "ETH".to_string(),
-            amount: Decimal::new(500000, 6), // 0.500000
-            status: TransactionStatus::Pending,
-            created_at: chrono::Utc::now(),
-            updated_at: chrono::Utc::now(),
-        });
+    /// 测试用托管服务密钥
+    const TEST_CUSTODY_SECRET: &str = "custody-s3cret";
 
-        AppState {
-            balances: Arc::new(RwLock::new(balances)),
-            transactions: Arc::new(RwLock::new(transactions)),
-            start_time: SystemTime::now(),
+    /// 以`service`服务身份用`secret`签名的内部请求
+    fn service_request(service: &str, secret: &str, method: &str, uri: &str, body: String) -> Request<Body> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        for (name, value) in flowex_middleware::service_auth::service_headers(service, secret, method, uri, body.as_bytes()) {
+            request = request.header(name, value);
         }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    /// 测试用户锁定的USDT
+    async fn locked_usdt(state: &AppState) -> Decimal {
+        let balances = state.ledger.balances(TEST_USER_ID).await;
+        balances.iter().find(|b| b.currency == "USDT").map_or(Decimal::ZERO, |b| b.locked)
     }
 
     /// 测试：应用状态创建
-    #[test]
-    fn test_app_state_creation() {
+    #[tokio::test]
+    async fn test_app_state_creation() {
         init_test_env();
 
-        let state = create_test_app_state();
+        let state = create_test_app_state().await;
 
         // 验证状态创建成功
         assert!(state.start_time.elapsed().unwrap().as_secs() < 1);
 
         // 验证初始数据
-        tokio::runtime::Runtime::new().unwrap().block_on(async {
-            let balances = state.balances.read().await;
-            assert!(balances.len() > 0, "应该有初始余额数据");
-            assert!(balances.contains_key("BTC"), "应该包含BTC余额");
-            assert!(balances.contains_key("ETH"), "应该包含ETH余额");
-            assert!(balances.contains_key("USDT"), "应该包含USDT余额");
-
-            let transactions = state.transactions.read().await;
-            assert!(transactions.len() > 0, "应该有初始交易数据");
-        });
+        let balances = state.ledger.balances(TEST_USER_ID).await;
+        assert!(!balances.is_empty(), "应该有初始余额数据");
+        assert!(balances.iter().any(|b| b.currency == "BTC"), "应该包含BTC余额");
+        assert!(balances.iter().any(|b| b.currency == "ETH"), "应该包含ETH余额");
+        assert!(balances.iter().any(|b| b.currency == "USDT"), "应该包含USDT余额");
+
+        let transactions = state.funding.user_transactions(TEST_USER_ID).await;
+        assert!(!transactions.is_empty(), "应该有初始交易数据");
     }
 
     /// 测试：余额数据结构
@@ -315,7 +1289,6 @@ mod tests {
             amount: Decimal::new(2500000, 6), // 2.500000
             status: TransactionStatus::Completed,
             created_at: chrono::Utc::now(),
-            updated_at: chrono::Utc::now(),
         };
 
         assert_eq!(transaction.currency, "ETH");
@@ -329,7 +1302,7 @@ mod tests {
     async fn test_health_check_response() {
         init_test_env();
 
-        let state = create_test_app_state();
+        let state = create_test_app_state().await;
         let app = create_app(state);
 
         let response = app
@@ -344,7 +1317,7 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
         let health_response: HealthResponse = serde_json::from_slice(&body).unwrap();
 
         assert_eq!(health_response.status, "healthy");
@@ -358,29 +1331,19 @@ mod tests {
     async fn test_get_all_balances() {
         init_test_env();
 
-        let state = create_test_app_state();
-        let app = create_app(state);
-
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .uri("/api/wallet/balances")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+        let state = create_test_app_state().await;
+        let response = get(&state, "/api/wallet/balances").await;
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
         let api_response: ApiResponse<Vec<Balance>> = serde_json::from_slice(&body).unwrap();
 
         assert!(api_response.success);
         assert!(api_response.data.is_some());
 
         let balances = api_response.data.unwrap();
-        assert!(balances.len() > 0);
+        assert!(!balances.is_empty());
 
         // 验证包含预期的余额
         let btc_balance = balances.iter().find(|b| b.currency == "BTC");
@@ -398,23 +1361,14 @@ mod tests {
     async fn test_get_specific_balance() {
         init_test_env();
 
-        let state = create_test_app_state();
-        let app = create_app(state);
+        let state = create_test_app_state().await;
 
         // 测试存在的货币
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .uri("/api/wallet/balance/BTC")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+        let response = get(&state, "/api/wallet/balance/BTC").await;
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
         let api_response: ApiResponse<Balance> = serde_json::from_slice(&body).unwrap();
 
         assert!(api_response.success);
@@ -422,7 +1376,7 @@ mod tests {
 
         let balance = api_response.data.unwrap();
         assert_eq!(balance.currency, "BTC");
-        assert!(balance.available > Decimal::ZERO);
+        assert_eq!(balance.available, Decimal::new(123456, 6));
     }
 
     /// 测试：获取不存在的货币余额
@@ -430,18 +1384,8 @@ mod tests {
     async fn test_get_nonexistent_balance() {
         init_test_env();
 
-        let state = create_test_app_state();
-        let app = create_app(state);
-
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .uri("/api/wallet/balance/INVALID")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+        let state = create_test_app_state().await;
+        let response = get(&state, "/api/wallet/balance/INVALID").await;
 
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
@@ -451,36 +1395,26 @@ mod tests {
     async fn test_get_transactions() {
         init_test_env();
 
-        let state = create_test_app_state();
-        let app = create_app(state);
-
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .uri("/api/wallet/transactions")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+        let state = create_test_app_state().await;
+        let response = get(&state, "/api/wallet/transactions").await;
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        let api_response: ApiResponse<Vec<Transaction>> = serde_json::from_slice(&body).unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let api_response: ApiResponse<Page<Transaction>> = serde_json::from_slice(&body).unwrap();
 
         assert!(api_response.success);
         assert!(api_response.data.is_some());
 
-        let transactions = api_response.data.unwrap();
-        assert!(transactions.len() > 0, "应该有交易历史数据");
+        let transactions = api_response.data.unwrap().items;
+        assert_eq!(transactions.len(), 3, "应该有交易历史数据");
 
         // 验证交易数据格式
         for transaction in &transactions {
             assert!(!transaction.currency.is_empty());
             assert!(transaction.amount > Decimal::ZERO);
             assert!(!transaction.id.is_nil());
-            assert!(!transaction.user_id.is_nil());
+            assert_eq!(transaction.user_id, TEST_USER_ID);
         }
     }
 
@@ -495,25 +1429,10 @@ mod tests {
         let fee = TransactionType::Fee;
 
         // 验证交易类型可以正确创建和比较
-        match deposit {
-            TransactionType::Deposit => assert!(true),
-            _ => assert!(false, "应该是存款类型"),
-        }
-
-        match withdrawal {
-            TransactionType::Withdrawal => assert!(true),
-            _ => assert!(false, "应该是提款类型"),
-        }
-
-        match trade {
-            TransactionType::Trade => assert!(true),
-            _ => assert!(false, "应该是交易类型"),
-        }
-
-        match fee {
-            TransactionType::Fee => assert!(true),
-            _ => assert!(false, "应该是手续费类型"),
-        }
+        assert!(matches!(deposit, TransactionType::Deposit), "应该是存款类型");
+        assert!(matches!(withdrawal, TransactionType::Withdrawal), "应该是提款类型");
+        assert!(matches!(trade, TransactionType::Trade), "应该是交易类型");
+        assert!(matches!(fee, TransactionType::Fee), "应该是手续费类型");
     }
 
     /// 测试：交易状态枚举
@@ -527,25 +1446,10 @@ mod tests {
         let cancelled = TransactionStatus::Cancelled;
 
         // 验证交易状态可以正确创建和比较
-        match pending {
-            TransactionStatus::Pending => assert!(true),
-            _ => assert!(false, "应该是待处理状态"),
-        }
-
-        match completed {
-            TransactionStatus::Completed => assert!(true),
-            _ => assert!(false, "应该是已完成状态"),
-        }
-
-        match failed {
-            TransactionStatus::Failed => assert!(true),
-            _ => assert!(false, "应该是失败状态"),
-        }
-
-        match cancelled {
-            TransactionStatus::Cancelled => assert!(true),
-            _ => assert!(false, "应该是已取消状态"),
-        }
+        assert!(matches!(pending, TransactionStatus::Pending), "应该是待处理状态");
+        assert!(matches!(completed, TransactionStatus::Completed), "应该是已完成状态");
+        assert!(matches!(failed, TransactionStatus::Failed), "应该是失败状态");
+        assert!(matches!(cancelled, TransactionStatus::Cancelled), "应该是已取消状态");
     }
 
     /// 测试：并发访问安全性
@@ -553,7 +1457,7 @@ mod tests {
     async fn test_concurrent_access_safety() {
         init_test_env();
 
-        let state = create_test_app_state();
+        let state = create_test_app_state().await;
         let mut handles = vec![];
 
         // 启动多个并发任务
@@ -561,14 +1465,10 @@ mod tests {
             let state_clone = state.clone();
             let handle = tokio::spawn(async move {
                 // 并发读取余额数据
-                let balances = state_clone.balances.read().await;
-                let balance_count = balances.len();
-                drop(balances);
+                let balance_count = state_clone.ledger.balances(TEST_USER_ID).await.len();
 
                 // 并发读取交易数据
-                let transactions = state_clone.transactions.read().await;
-                let transaction_count = transactions.len();
-                drop(transactions);
+                let transaction_count = state_clone.funding.user_transactions(TEST_USER_ID).await.len();
 
                 (i, balance_count, transaction_count)
             });
@@ -608,7 +1508,7 @@ mod tests {
     async fn test_performance_benchmark() {
         init_test_env();
 
-        let state = create_test_app_state();
+        let state = create_test_app_state().await;
         let start = std::time::Instant::now();
 
         // 模拟大量并发请求
@@ -616,8 +1516,8 @@ mod tests {
         for _ in 0..100 {
             let state_clone = state.clone();
             let handle = tokio::spawn(async move {
-                let _balances = state_clone.balances.read().await;
-                let _transactions = state_clone.transactions.read().await;
+                let _balances = state_clone.ledger.balances(TEST_USER_ID).await;
+                let _transactions = state_clone.funding.user_transactions(TEST_USER_ID).await;
             });
             handles.push(handle);
         }
@@ -639,50 +1539,31 @@ mod tests {
     async fn test_memory_usage_optimization() {
         init_test_env();
 
-        let state = create_test_app_state();
+        let state = create_test_app_state().await;
+        let user_id = Uuid::new_v4();
+        state.ledger.open_account(user_id, "bulk@flowex.com").await.unwrap();
 
         // 模拟添加大量数据
-        {
-            let mut balances = state.balances.write().await;
-            let mut transactions = state.transactions.write().await;
-
-            for i in 0..1000 {
-                let currency = format!("TEST{}", i);
-
-                // 添加余额
-                let balance = Balance {
-                    currency: currency.clone(),
-                    available: Decimal::new(10000 + i, 4),
-                    locked: Decimal::new(1000 + i, 4),
-                };
-                balances.insert(currency.clone(), balance);
-
-                // 添加交易
-                let transaction = Transaction {
-                    id: Uuid::new_v4(),
-                    user_id: Uuid::new_v4(),
-                    transaction_type: if i % 2 == 0 { TransactionType::Deposit } else { TransactionType::Withdrawal },
-                    currency: currency.clone(),
-                    amount: Decimal::new(1000 + i, 4),
-                    status: TransactionStatus::Completed,
-                    created_at: chrono::Utc::now(),
-                    updated_at: chrono::Utc::now(),
-                };
-                transactions.push(transaction);
-            }
+        for i in 0..1000 {
+            // 添加余额
+            state
+                .ledger
+                .credit(user_id, &format!("TEST{}", i), Decimal::new(10000 + i, 4))
+                .await
+                .unwrap();
+
+            // 添加交易
+            let deposit = DepositRequest {
+                currency: "USDT".to_string(),
+                amount: Decimal::new(1000 + i, 4),
+                tx_hash: None,
+            };
+            state.funding.deposit(user_id, deposit).await.unwrap();
         }
 
         // 验证数据添加成功
-        let balances = state.balances.read().await;
-        let transactions = state.transactions.read().await;
-
-        assert!(balances.len() >= 1000, "应该有至少1000个余额");
-        assert!(transactions.len() >= 1000, "应该有至少1000个交易");
-
-        // 清理内存（通过作用域自动清理）
-        drop(balances);
-        drop(transactions);
-        assert!(true, "内存使用优化测试完成");
+        assert!(state.ledger.balances(user_id).await.len() >= 1000, "应该有至少1000个余额");
+        assert!(state.funding.user_transactions(user_id).await.len() >= 1000, "应该有至少1000个交易");
     }
 
     /// 测试：错误处理
@@ -690,23 +1571,113 @@ mod tests {
     async fn test_error_handling() {
         init_test_env();
 
-        let state = create_test_app_state();
-        let app = create_app(state);
+        let state = create_test_app_state().await;
 
         // 测试无效路径
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .uri("/api/wallet/invalid")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+        let response = get(&state, "/api/wallet/invalid").await;
 
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
+    /// 测试：资金预留与结算接口只接受交易服务签名的请求，未签名、签名错误或仅带用户令牌的请求被拒绝
+    #[tokio::test]
+    async fn test_internal_endpoints_require_service_signature() {
+        init_test_env();
+
+        let state = AppState {
+            trading_service: ServiceAuth::new([("trading", TEST_SERVICE_SECRET)]),
+            ..create_test_app_state().await
+        };
+        let order_id = Uuid::new_v4();
+        let reservation = serde_json::to_string(&Reservation {
+            order_id,
+            user_id: TEST_USER_ID,
+            currency: "USDT".to_string(),
+            amount: Decimal::new(100, 0),
+            all_available: false,
+        })
+        .unwrap();
+        let release_uri = format!("/api/wallet/reservations/{}", order_id);
+        let status = |request: Request<Body>| {
+            let app = create_app(state.clone());
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        let unsigned = |method: &str, uri: &str, body: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .header("authorization", bearer_token(TEST_USER_ID))
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        assert_eq!(status(unsigned("POST", "/api/wallet/reservations", &reservation)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(unsigned("DELETE", &release_uri, "")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(unsigned("POST", "/api/wallet/settlements", "{}")).await, StatusCode::UNAUTHORIZED);
+        let forged = service_request("trading", "guessed", "POST", "/api/wallet/reservations", reservation.clone());
+        assert_eq!(status(forged).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(locked_usdt(&state).await, Decimal::ZERO, "被拒绝的请求不应锁定资金");
+
+        let signed = service_request("trading", TEST_SERVICE_SECRET, "POST", "/api/wallet/reservations", reservation);
+        assert_eq!(status(signed).await, StatusCode::OK);
+        assert_eq!(locked_usdt(&state).await, Decimal::new(100, 0));
+
+        let signed = service_request("trading", TEST_SERVICE_SECRET, "DELETE", &release_uri, String::new());
+        assert_eq!(status(signed).await, StatusCode::OK);
+        assert_eq!(locked_usdt(&state).await, Decimal::ZERO);
+    }
+
+    /// 测试：提现结果回调只接受托管服务签名的请求，已完成的提现不能再被改为失败
+    #[tokio::test]
+    async fn test_withdrawal_callback_requires_custody_signature() {
+        init_test_env();
+
+        let state = AppState {
+            trading_service: ServiceAuth::new([("trading", TEST_SERVICE_SECRET)]),
+            custody_service: ServiceAuth::new([("custody", TEST_CUSTODY_SECRET)]),
+            ..create_test_app_state().await
+        };
+        let request = WithdrawalRequest {
+            currency: "USDT".to_string(),
+            amount: Decimal::new(100, 0),
+            address: "0xabc".to_string(),
+        };
+        let withdrawal = state
+            .funding
+            .withdraw(TEST_USER_ID, request, &std::collections::HashMap::new())
+            .await
+            .unwrap()
+            .transaction;
+        let uri = format!("/api/wallet/withdrawals/{}/status", withdrawal.id);
+        let completed = r#"{"status":"completed","tx_hash":"0x1"}"#.to_string();
+        let failed = r#"{"status":"failed","reason":"late"}"#.to_string();
+        let status = |request: Request<Body>| {
+            let app = create_app(state.clone());
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        let unsigned = Request::builder()
+            .method("PUT")
+            .uri(&uri)
+            .header("content-type", "application/json")
+            .body(Body::from(completed.clone()))
+            .unwrap();
+        assert_eq!(status(unsigned).await, StatusCode::UNAUTHORIZED);
+        let by_trading = service_request("trading", TEST_SERVICE_SECRET, "PUT", &uri, completed.clone());
+        assert_eq!(status(by_trading).await, StatusCode::UNAUTHORIZED);
+        let forged = service_request("custody", "guessed", "PUT", &uri, completed.clone());
+        assert_eq!(status(forged).await, StatusCode::UNAUTHORIZED);
+
+        let signed = service_request("custody", TEST_CUSTODY_SECRET, "PUT", &uri, completed);
+        assert_eq!(status(signed).await, StatusCode::OK);
+        let signed = service_request("custody", TEST_CUSTODY_SECRET, "PUT", &uri, failed);
+        assert_eq!(status(signed).await, StatusCode::CONFLICT);
+        let transactions = state.funding.user_transactions(TEST_USER_ID).await;
+        let stored = transactions.iter().find(|transaction| transaction.id == withdrawal.id).unwrap();
+        assert_eq!(stored.status, TransactionStatus::Completed);
+    }
+
     /// 测试：数据验证
     #[test]
     fn test_data_validation() {
@@ -733,13 +1704,11 @@ mod tests {
             amount: Decimal::new(2500000, 6), // 2.500000
             status: TransactionStatus::Completed,
             created_at: chrono::Utc::now(),
-            updated_at: chrono::Utc::now(),
         };
 
         assert!(transaction.amount > Decimal::ZERO, "交易金额应该大于零");
         assert!(!transaction.currency.is_empty(), "货币代码不应该为空");
         assert!(!transaction.id.is_nil(), "交易ID不应该为空");
         assert!(!transaction.user_id.is_nil(), "用户ID不应该为空");
-        assert!(transaction.updated_at >= transaction.created_at, "更新时间应该大于等于创建时间");
     }
 }
@@ -0,0 +1,284 @@
+//! Portfolio valuation
+//!
+//! Values each balance in a requested quote currency using conversion rates
+//! derived from the market-data service tickers. Valuations are cached for a
+//! short period so frequent portfolio polling does not hammer the pricing path.
+
+use chrono::{DateTime, Utc};
+use flowex_types::{Balance, FlowExError, FlowExResult, Ticker};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+/// Currency used to bridge pairs without a direct market
+const BRIDGE_CURRENCY: &str = "USDT";
+
+/// Valuation of a single balance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetValuation {
+    pub currency: String,
+    pub available: Decimal,
+    pub locked: Decimal,
+    pub total: Decimal,
+    /// Price of one unit in the quote currency, if a conversion rate exists
+    pub price: Option<Decimal>,
+    pub value: Option<Decimal>,
+}
+
+/// Valuation of all balances of an account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortfolioValuation {
+    pub quote: String,
+    pub total_value: Decimal,
+    pub assets: Vec<AssetValuation>,
+    /// Currencies that could not be priced and are excluded from the total
+    pub unpriced: Vec<String>,
+    pub priced_at: DateTime<Utc>,
+}
+
+/// Market-data service tickers response
+#[derive(Debug, Deserialize)]
+struct TickersResponse {
+    data: Option<Vec<Ticker>>,
+}
+
+/// Client for the market-data service price feed
+#[derive(Clone)]
+pub struct MarketDataClient {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl MarketDataClient {
+    /// Create a new market-data client
+    pub fn new(base_url: String, timeout: Duration) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default();
+
+        Self { base_url, http }
+    }
+
+    /// Fetch the latest price of every symbol, keyed by symbol
+    pub async fn fetch_prices(&self) -> FlowExResult<HashMap<String, Decimal>> {
+        let url = format!("{}/api/market-data/tickers", self.base_url.trim_end_matches('/'));
+
+        let response = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| FlowExError::MarketData(format!("Failed to fetch tickers: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(FlowExError::MarketData(format!(
+                "Market data service returned {}",
+                response.status()
+            )));
+        }
+
+        let body: TickersResponse = response
+            .json()
+            .await
+            .map_err(|e| FlowExError::MarketData(format!("Invalid tickers response: {}", e)))?;
+
+        Ok(body
+            .data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|ticker| (ticker.symbol, ticker.price))
+            .collect())
+    }
+}
+
+/// Conversion rate from one currency to another.
+///
+/// Uses a direct `FROM-TO` market, the inverse `TO-FROM` market, or a cross
+/// rate through the bridge currency, in that order.
+pub fn conversion_rate(prices: &HashMap<String, Decimal>, from: &str, to: &str) -> Option<Decimal> {
+    direct_rate(prices, from, to).or_else(|| {
+        if from == BRIDGE_CURRENCY || to == BRIDGE_CURRENCY {
+            return None;
+        }
+        let to_bridge = direct_rate(prices, from, BRIDGE_CURRENCY)?;
+        let from_bridge = direct_rate(prices, BRIDGE_CURRENCY, to)?;
+        Some(to_bridge * from_bridge)
+    })
+}
+
+fn direct_rate(prices: &HashMap<String, Decimal>, from: &str, to: &str) -> Option<Decimal> {
+    if from == to {
+        return Some(Decimal::ONE);
+    }
+
+    if let Some(price) = prices.get(&format!("{}-{}", from, to)) {
+        if *price > Decimal::ZERO {
+            return Some(*price);
+        }
+    }
+
+    match prices.get(&format!("{}-{}", to, from)) {
+        Some(price) if *price > Decimal::ZERO => Some(Decimal::ONE / *price),
+        _ => None,
+    }
+}
+
+/// Value a set of balances in the quote currency
+pub fn value_portfolio(
+    balances: &[Balance],
+    prices: &HashMap<String, Decimal>,
+    quote: &str,
+) -> PortfolioValuation {
+    let mut total_value = Decimal::ZERO;
+    let mut unpriced = Vec::new();
+
+    let assets = balances
+        .iter()
+        .map(|balance| {
+            let total = balance.available + balance.locked;
+            let price = conversion_rate(prices, &balance.currency, quote);
+            let value = price.map(|p| (total * p).round_dp(8));
+
+            match value {
+                Some(v) => total_value += v,
+                None => unpriced.push(balance.currency.clone()),
+            }
+
+            AssetValuation {
+                currency: balance.currency.clone(),
+                available: balance.available,
+                locked: balance.locked,
+                total,
+                price,
+                value,
+            }
+        })
+        .collect();
+
+    PortfolioValuation {
+        quote: quote.to_string(),
+        total_value,
+        assets,
+        unpriced,
+        priced_at: Utc::now(),
+    }
+}
+
+/// Cached valuations keyed by (account, quote currency)
+type ValuationCache = HashMap<(String, String), (Instant, PortfolioValuation)>;
+
+/// Portfolio valuation service with a short-lived cache
+#[derive(Clone)]
+pub struct PortfolioService {
+    market_data: MarketDataClient,
+    cache: Arc<RwLock<ValuationCache>>,
+    cache_ttl: Duration,
+}
+
+impl PortfolioService {
+    /// Create a new portfolio service
+    pub fn new(market_data: MarketDataClient, cache_ttl: Duration) -> Self {
+        Self {
+            market_data,
+            cache: Arc::new(RwLock::new(HashMap::new())),
+            cache_ttl,
+        }
+    }
+
+    /// Value an account's balances, serving a cached valuation when fresh
+    pub async fn valuation(
+        &self,
+        account: &str,
+        balances: &[Balance],
+        quote: &str,
+    ) -> FlowExResult<PortfolioValuation> {
+        let key = (account.to_string(), quote.to_string());
+
+        if let Some((cached_at, valuation)) = self.cache.read().await.get(&key) {
+            if cached_at.elapsed() < self.cache_ttl {
+                debug!("Portfolio valuation cache hit for {} in {}", account, quote);
+                return Ok(valuation.clone());
+            }
+        }
+
+        let prices = self.market_data.fetch_prices().await.map_err(|e| {
+            warn!("Portfolio pricing failed: {}", e);
+            e
+        })?;
+
+        let valuation = value_portfolio(balances, &prices, quote);
+
+        let mut cache = self.cache.write().await;
+        cache.retain(|_, (cached_at, _)| cached_at.elapsed() < self.cache_ttl);
+        cache.insert(key, (Instant::now(), valuation.clone()));
+
+        Ok(valuation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_prices() -> HashMap<String, Decimal> {
+        HashMap::from([
+            ("BTC-USDT".to_string(), Decimal::new(45000, 0)),
+            ("ETH-USDT".to_string(), Decimal::new(3000, 0)),
+            ("USDT-EUR".to_string(), Decimal::new(9, 1)), // 0.9
+        ])
+    }
+
+    /// 测试：直接、反向与桥接汇率
+    #[test]
+    fn test_conversion_rate() {
+        let prices = test_prices();
+
+        assert_eq!(conversion_rate(&prices, "BTC", "BTC"), Some(Decimal::ONE));
+        assert_eq!(conversion_rate(&prices, "BTC", "USDT"), Some(Decimal::new(45000, 0)));
+        // Inverse market
+        let usdt_eth = conversion_rate(&prices, "USDT", "ETH").unwrap();
+        assert_eq!((usdt_eth * Decimal::new(3000, 0)).round_dp(8), Decimal::ONE);
+        // Cross rate through USDT
+        assert_eq!(conversion_rate(&prices, "BTC", "EUR"), Some(Decimal::new(40500, 0)));
+        let eth_btc = conversion_rate(&prices, "ETH", "BTC").unwrap();
+        assert_eq!((eth_btc * Decimal::new(15, 0)).round_dp(8), Decimal::ONE);
+        assert_eq!(conversion_rate(&prices, "DOGE", "USDT"), None);
+    }
+
+    /// 测试：组合估值汇总及未定价资产
+    #[test]
+    fn test_value_portfolio() {
+        let balances = vec![
+            Balance {
+                currency: "BTC".to_string(),
+                available: Decimal::new(1, 1), // 0.1
+                locked: Decimal::ZERO,
+            },
+            Balance {
+                currency: "USDT".to_string(),
+                available: Decimal::new(1000, 0),
+                locked: Decimal::new(500, 0),
+            },
+            Balance {
+                currency: "DOGE".to_string(),
+                available: Decimal::new(100, 0),
+                locked: Decimal::ZERO,
+            },
+        ];
+
+        let valuation = value_portfolio(&balances, &test_prices(), "USDT");
+
+        assert_eq!(valuation.quote, "USDT");
+        assert_eq!(valuation.total_value, Decimal::new(6000, 0)); // 4500 + 1500
+        assert_eq!(valuation.assets.len(), 3);
+        assert_eq!(valuation.unpriced, vec!["DOGE".to_string()]);
+        assert_eq!(valuation.assets[1].total, Decimal::new(1500, 0));
+    }
+}
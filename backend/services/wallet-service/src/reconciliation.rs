@@ -0,0 +1,445 @@
+//! End-of-day balance snapshots and reconciliation
+//!
+//! At midnight UTC every account's balances are recorded in a snapshot of
+//! the business day that ended, and the ledger is reconciled against it:
+//!
+//! - the snapshot, as stored, sums per currency to the ledger's totals:
+//!   what the exchange owes its users, its liabilities, the fee revenue it
+//!   holds itself, the conversion desk's position and what its hot and
+//!   cold wallets hold in custody;
+//! - custody covers liabilities, revenue and the desk's position exactly;
+//! - with a database, the balances stored sum to the same totals as the
+//!   ledger holds in memory;
+//! - each account's locked balance is what its open order reservations and
+//!   pending withdrawals lock, and no user's balance is negative. A
+//!   revenue account may be, having paid out more rebates than it took, and
+//!   so may the conversion desk, short of a currency until it is hedged.
+//!
+//! Each run produces a report, stored with its snapshot. Mismatches are
+//! logged as errors and published in the `flowex_wallet_reconciliation_mismatches`
+//! gauge to alert on, with the liabilities of each currency in
+//! `flowex_wallet_liabilities`. Admins may also run a reconciliation at any
+//! time.
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use flowex_metrics::MetricsCollector;
+use flowex_types::{Balance, FlowExResult};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::convert::CONVERSION_DESK_ACCOUNT;
+use crate::funding::Funding;
+use crate::revenue;
+use crate::treasury;
+use crate::store::WalletStore;
+
+/// Balances of every account at the end of a business day
+#[derive(Debug, Clone)]
+pub struct BalanceSnapshot {
+    pub id: Uuid,
+    pub business_date: NaiveDate,
+    pub taken_at: DateTime<Utc>,
+    pub balances: Vec<(Uuid, Balance)>,
+}
+
+/// What a reconciliation compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchKind {
+    /// Stored snapshot against the ledger's total of a currency
+    Snapshot,
+    /// Stored balances against the ledger's total of a currency
+    Store,
+    /// Custody against liabilities, revenue and the desk's position of a currency
+    Custody,
+    /// An account's locked balance against what its reservations and
+    /// pending withdrawals lock
+    Locked,
+    /// A negative available or locked balance
+    Negative,
+}
+
+/// Amount a reconciliation expected and the amount it found instead
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mismatch {
+    pub kind: MismatchKind,
+    pub currency: String,
+    /// Account, for mismatches of a single account
+    pub user_id: Option<Uuid>,
+    pub expected: Decimal,
+    pub actual: Decimal,
+}
+
+/// What users, the revenue accounts, the conversion desk and custody hold
+/// of a currency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CurrencyTotal {
+    pub currency: String,
+    /// Available to users
+    pub available: Decimal,
+    /// Locked for users
+    pub locked: Decimal,
+    /// Available and locked, owed to users
+    pub liabilities: Decimal,
+    /// Held by the revenue accounts
+    #[serde(default)]
+    pub revenue: Decimal,
+    /// Held by the conversion desk; negative while it is short
+    #[serde(default)]
+    pub desk: Decimal,
+    /// Held by the hot and cold wallets
+    #[serde(default)]
+    pub custody: Decimal,
+    /// Users' accounts holding a balance of it
+    pub accounts: usize,
+}
+
+impl CurrencyTotal {
+    /// What the ledger holds of the currency in all accounts
+    pub fn ledger_total(&self) -> Decimal {
+        self.liabilities + self.revenue + self.desk + self.custody
+    }
+}
+
+/// Outcome of reconciling the ledger against a snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReconciliationReport {
+    pub id: Uuid,
+    pub snapshot_id: Uuid,
+    pub business_date: NaiveDate,
+    pub taken_at: DateTime<Utc>,
+    /// Totals by currency
+    pub totals: Vec<CurrencyTotal>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ReconciliationReport {
+    /// Whether nothing was found out of balance
+    pub fn balanced(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Takes snapshots and reconciles the ledger against them
+#[derive(Clone)]
+pub struct Reconciler {
+    funding: Funding,
+    store: WalletStore,
+    metrics: MetricsCollector,
+    /// Reports, oldest first
+    reports: Arc<RwLock<Vec<ReconciliationReport>>>,
+}
+
+impl Reconciler {
+    /// Reconciler holding `reports` of earlier runs, as restored from
+    /// `store`
+    pub fn new(funding: Funding, store: WalletStore, reports: Vec<ReconciliationReport>) -> Self {
+        Self {
+            funding,
+            store,
+            metrics: MetricsCollector::new(),
+            reports: Arc::new(RwLock::new(reports)),
+        }
+    }
+
+    /// Up to `limit` reports, newest first
+    pub async fn reports(&self, limit: usize) -> Vec<ReconciliationReport> {
+        self.reports.read().await.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Snapshot every account's balances as of the end of `business_date`
+    /// and reconcile the ledger against it
+    pub async fn run(&self, business_date: NaiveDate) -> FlowExResult<ReconciliationReport> {
+        let (view, withdrawals) = self.funding.ledger_view().await?;
+        let snapshot = BalanceSnapshot {
+            id: Uuid::new_v4(),
+            business_date,
+            taken_at: Utc::now(),
+            balances: view.balances,
+        };
+        let totals = currency_totals(&snapshot.balances);
+        let mut mismatches = Vec::new();
+
+        // What open orders and pending withdrawals lock, by account
+        let mut locks = withdrawals;
+        for reservation in &view.reservations {
+            *locks
+                .entry((reservation.user_id, reservation.currency.clone()))
+                .or_default() += reservation.amount;
+        }
+        for (user_id, balance) in &snapshot.balances {
+            let expected = locks.remove(&(*user_id, balance.currency.clone())).unwrap_or_default();
+            if balance.locked != expected {
+                mismatches.push(Mismatch {
+                    kind: MismatchKind::Locked,
+                    currency: balance.currency.clone(),
+                    user_id: Some(*user_id),
+                    expected,
+                    actual: balance.locked,
+                });
+            }
+            let negative = balance.available < Decimal::ZERO || balance.locked < Decimal::ZERO;
+            if negative && !revenue::is_revenue_account(*user_id) && *user_id != CONVERSION_DESK_ACCOUNT {
+                mismatches.push(Mismatch {
+                    kind: MismatchKind::Negative,
+                    currency: balance.currency.clone(),
+                    user_id: Some(*user_id),
+                    expected: Decimal::ZERO,
+                    actual: balance.available.min(balance.locked),
+                });
+            }
+        }
+        // Locks of accounts without a balance of the currency at all
+        for ((user_id, currency), expected) in locks {
+            mismatches.push(Mismatch {
+                kind: MismatchKind::Locked,
+                currency,
+                user_id: Some(user_id),
+                expected,
+                actual: Decimal::ZERO,
+            });
+        }
+
+        for total in &totals {
+            let expected = total.liabilities + total.revenue + total.desk;
+            if total.custody != expected {
+                mismatches.push(Mismatch {
+                    kind: MismatchKind::Custody,
+                    currency: total.currency.clone(),
+                    user_id: None,
+                    expected,
+                    actual: total.custody,
+                });
+            }
+        }
+        if let Some(stored) = &view.stored_totals {
+            compare_totals(&mut mismatches, MismatchKind::Store, &totals, stored);
+        }
+        self.store.write_snapshot(&snapshot).await?;
+        if let Some(stored) = self.store.snapshot_totals(snapshot.id).await? {
+            compare_totals(&mut mismatches, MismatchKind::Snapshot, &totals, &stored);
+        }
+
+        let report = ReconciliationReport {
+            id: Uuid::new_v4(),
+            snapshot_id: snapshot.id,
+            business_date,
+            taken_at: snapshot.taken_at,
+            totals,
+            mismatches,
+        };
+        self.store.write_report(&report).await?;
+        self.reports.write().await.push(report.clone());
+
+        for total in &report.totals {
+            self.metrics
+                .record_wallet_liabilities(&total.currency, total.liabilities.to_f64().unwrap_or_default());
+        }
+        self.metrics.record_reconciliation(report.mismatches.len());
+        if report.balanced() {
+            info!(
+                "Reconciled {} balances of {} in {} currencies",
+                snapshot.balances.len(),
+                business_date,
+                report.totals.len()
+            );
+        } else {
+            for mismatch in &report.mismatches {
+                error!("Reconciliation of {} mismatch: {:?}", business_date, mismatch);
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Totals of `balances` by currency
+fn currency_totals(balances: &[(Uuid, Balance)]) -> Vec<CurrencyTotal> {
+    let mut totals: BTreeMap<&str, CurrencyTotal> = BTreeMap::new();
+    for (user_id, balance) in balances {
+        let total = totals.entry(&balance.currency).or_insert_with(|| CurrencyTotal {
+            currency: balance.currency.clone(),
+            available: Decimal::ZERO,
+            locked: Decimal::ZERO,
+            liabilities: Decimal::ZERO,
+            revenue: Decimal::ZERO,
+            desk: Decimal::ZERO,
+            custody: Decimal::ZERO,
+            accounts: 0,
+        });
+        if revenue::is_revenue_account(*user_id) {
+            total.revenue += balance.available + balance.locked;
+            continue;
+        }
+        if *user_id == CONVERSION_DESK_ACCOUNT {
+            total.desk += balance.available + balance.locked;
+            continue;
+        }
+        if treasury::is_custody_account(*user_id) {
+            total.custody += balance.available + balance.locked;
+            continue;
+        }
+        total.available += balance.available;
+        total.locked += balance.locked;
+        total.liabilities += balance.available + balance.locked;
+        if !(balance.available + balance.locked).is_zero() {
+            total.accounts += 1;
+        }
+    }
+    totals.into_values().collect()
+}
+
+/// Record a `kind` mismatch for every currency whose ledger total differs
+/// from `found`
+fn compare_totals(
+    mismatches: &mut Vec<Mismatch>,
+    kind: MismatchKind,
+    totals: &[CurrencyTotal],
+    found: &HashMap<String, Decimal>,
+) {
+    let mut found = found.clone();
+    for total in totals {
+        let actual = found.remove(&total.currency).unwrap_or_default();
+        if actual != total.ledger_total() {
+            mismatches.push(Mismatch {
+                kind,
+                currency: total.currency.clone(),
+                user_id: None,
+                expected: total.ledger_total(),
+                actual,
+            });
+        }
+    }
+    for (currency, actual) in found.into_iter().filter(|(_, actual)| !actual.is_zero()) {
+        mismatches.push(Mismatch {
+            kind,
+            currency,
+            user_id: None,
+            expected: Decimal::ZERO,
+            actual,
+        });
+    }
+}
+
+/// Next end of a business day after `now`: the coming midnight UTC
+pub fn next_end_of_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    let tomorrow = now.date_naive() + Days::new(1);
+    tomorrow.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+}
+
+/// Reconcile the ledger at the end of every business day
+pub fn spawn_end_of_day(reconciler: Reconciler) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let end_of_day = next_end_of_day(Utc::now());
+            let wait = (end_of_day - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+            let business_date = end_of_day.date_naive() - Days::new(1);
+            if let Err(e) = reconciler.run(business_date).await {
+                error!("End-of-day reconciliation of {} failed: {}", business_date, e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::AssetRegistry;
+    use crate::funding::{DepositRequest, WithdrawalOutcome, WithdrawalRequest};
+    use crate::ledger::Ledger;
+    use chrono::TimeZone;
+    use flowex_types::Reservation;
+
+    /// 测试：日终快照按币种汇总负债和手续费收入，冻结余额与挂单预留和待处理提现一致时对账平衡，不一致时报告差异
+    #[tokio::test]
+    async fn test_snapshot_and_reconcile() {
+        let ledger = Ledger::new();
+        let funding = Funding::new(ledger.clone(), AssetRegistry::new());
+        let reconciler = Reconciler::new(funding.clone(), WalletStore::Memory, Vec::new());
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        for user_id in [alice, bob] {
+            let deposit = DepositRequest {
+                currency: "USDT".to_string(),
+                amount: Decimal::new(1000, 0),
+                tx_hash: None,
+            };
+            funding.deposit(user_id, deposit).await.unwrap();
+        }
+        let withdrawal = WithdrawalRequest {
+            currency: "USDT".to_string(),
+            amount: Decimal::new(100, 0),
+            address: "0xabc".to_string(),
+        };
+        let withdrawal = funding.withdraw(alice, withdrawal, &HashMap::new()).await.unwrap().transaction;
+        ledger
+            .reserve(Reservation {
+                order_id: Uuid::new_v4(),
+                user_id: bob,
+                currency: "USDT".to_string(),
+                amount: Decimal::new(250, 0),
+                all_available: false,
+            })
+            .await
+            .unwrap();
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        let report = reconciler.run(date).await.unwrap();
+        assert!(report.balanced(), "{:?}", report.mismatches);
+        assert_eq!(report.business_date, date);
+        assert_eq!(report.totals.len(), 1);
+        assert_eq!(report.totals[0].liabilities, Decimal::new(2000, 0));
+        assert_eq!(report.totals[0].locked, Decimal::new(350, 0));
+        assert_eq!(report.totals[0].accounts, 2);
+
+        // 存储的余额合计与账本不一致时报告差异
+        let stored = HashMap::from([("USDT".to_string(), Decimal::new(3990, 0))]);
+        let mut mismatches = Vec::new();
+        compare_totals(&mut mismatches, MismatchKind::Store, &report.totals, &stored);
+        assert_eq!(
+            mismatches,
+            vec![Mismatch {
+                kind: MismatchKind::Store,
+                currency: "USDT".to_string(),
+                user_id: None,
+                expected: Decimal::new(4000, 0),
+                actual: Decimal::new(3990, 0),
+            }]
+        );
+
+        // 提现完成后手续费计入收入而非负债
+        funding
+            .finish_withdrawal(withdrawal.id, WithdrawalOutcome::Completed { tx_hash: "0x1".to_string() })
+            .await
+            .unwrap();
+        let deposit = DepositRequest {
+            currency: "BTC".to_string(),
+            amount: Decimal::ONE,
+            tx_hash: None,
+        };
+        funding.deposit(bob, deposit).await.unwrap();
+        let next = reconciler.run(date.succ_opt().unwrap()).await.unwrap();
+        assert!(next.balanced(), "{:?}", next.mismatches);
+        assert_eq!(next.totals.len(), 2);
+        assert_eq!((next.totals[1].liabilities, next.totals[1].revenue), (Decimal::new(1900, 0), Decimal::ONE));
+        assert_eq!((next.totals[1].accounts, next.totals[1].custody), (2, Decimal::new(1901, 0)));
+
+        // 托管余额与负债和收入不符时报告差异
+        ledger.credit(bob, "BTC", Decimal::ONE).await.unwrap();
+        let short = reconciler.run(date.succ_opt().unwrap()).await.unwrap();
+        assert_eq!(short.mismatches.len(), 1);
+        assert_eq!((short.mismatches[0].kind, short.mismatches[0].actual), (MismatchKind::Custody, Decimal::ONE));
+        let reports = reconciler.reports(10).await;
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[2].id, report.id);
+
+        let now = Utc.with_ymd_and_hms(2024, 1, 31, 18, 30, 0).unwrap();
+        assert_eq!(next_end_of_day(now), Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+    }
+}
@@ -0,0 +1,210 @@
+//! Fee revenue
+//!
+//! Fees stay in the ledger as the exchange's income: trading fees are paid
+//! into `TRADING_FEE_ACCOUNT` as each trade settles, withdrawal fees into
+//! `WITHDRAWAL_FEE_ACCOUNT` once custody completes the withdrawal, and
+//! conversion fees into `CONVERSION_FEE_ACCOUNT` with each conversion. A
+//! maker rebate is paid out of the trading fee account, which may therefore
+//! fall below zero. Revenue accounts are system accounts: they belong to no
+//! user, cannot receive transfers and are not liabilities (see
+//! `ledger::is_system_account`).
+//!
+//! Every fee is journaled as a `FeeEntry`, from which admins report revenue
+//! by day, week or month.
+
+use chrono::{DateTime, Datelike, Days, Duration, TimeZone, Utc};
+use flowex_types::Balance;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Account trading fees are paid into
+pub const TRADING_FEE_ACCOUNT: Uuid = Uuid::from_u128(1);
+
+/// Account withdrawal fees are paid into
+pub const WITHDRAWAL_FEE_ACCOUNT: Uuid = Uuid::from_u128(2);
+
+/// Account conversion fees are paid into
+pub const CONVERSION_FEE_ACCOUNT: Uuid = Uuid::from_u128(5);
+
+/// Whether `user_id` is one of the revenue accounts
+pub fn is_revenue_account(user_id: Uuid) -> bool {
+    FeeSource::ALL.iter().any(|source| source.account() == user_id)
+}
+
+/// What a fee was charged for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeSource {
+    Trading,
+    Withdrawal,
+    Conversion,
+}
+
+impl FeeSource {
+    pub const ALL: [FeeSource; 3] = [FeeSource::Trading, FeeSource::Withdrawal, FeeSource::Conversion];
+
+    /// Revenue account the fees are paid into
+    pub fn account(self) -> Uuid {
+        match self {
+            FeeSource::Trading => TRADING_FEE_ACCOUNT,
+            FeeSource::Withdrawal => WITHDRAWAL_FEE_ACCOUNT,
+            FeeSource::Conversion => CONVERSION_FEE_ACCOUNT,
+        }
+    }
+}
+
+/// A fee paid into a revenue account; negative for a rebate paid out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeEntry {
+    pub id: Uuid,
+    pub source: FeeSource,
+    pub currency: String,
+    pub amount: Decimal,
+    /// User who paid the fee
+    pub user_id: Uuid,
+    /// Trade, withdrawal or conversion the fee was charged on
+    pub reference_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+impl FeeEntry {
+    pub fn new(source: FeeSource, currency: &str, amount: Decimal, user_id: Uuid, reference_id: Uuid) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            source,
+            currency: currency.to_string(),
+            amount,
+            user_id,
+            reference_id,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// Length of the periods revenue is reported by, in UTC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RevenuePeriod {
+    #[default]
+    Day,
+    /// Weeks starting on Monday
+    Week,
+    Month,
+}
+
+impl RevenuePeriod {
+    /// Start of the period `at` falls in
+    pub fn start(self, at: DateTime<Utc>) -> DateTime<Utc> {
+        let date = at.date_naive();
+        let first = match self {
+            RevenuePeriod::Day => date,
+            RevenuePeriod::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+            RevenuePeriod::Month => date.with_day(1).unwrap_or(date),
+        };
+        Utc.from_utc_datetime(&first.and_hms_opt(0, 0, 0).unwrap_or_default())
+    }
+}
+
+/// Revenue report query parameters
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RevenueQuery {
+    #[serde(default)]
+    pub period: RevenuePeriod,
+    /// Earliest fee included; 30 days ago if not given
+    pub start_time: Option<DateTime<Utc>>,
+    /// Time from which fees are excluded; now if not given
+    pub end_time: Option<DateTime<Utc>>,
+}
+
+impl RevenueQuery {
+    /// Range of fees reported
+    pub fn range(&self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let end = self.end_time.unwrap_or(now);
+        (self.start_time.unwrap_or(end - Duration::days(30)), end)
+    }
+}
+
+/// Revenue of one source in one currency over one period
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RevenueBucket {
+    pub period_start: DateTime<Utc>,
+    pub source: FeeSource,
+    pub currency: String,
+    /// Fees less rebates
+    pub amount: Decimal,
+    pub entries: usize,
+}
+
+/// Balance of a revenue account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevenueBalance {
+    pub source: FeeSource,
+    pub balance: Balance,
+}
+
+/// Revenue by period, with what each revenue account holds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevenueReport {
+    pub period: RevenuePeriod,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    /// By period, source and currency
+    pub buckets: Vec<RevenueBucket>,
+    pub balances: Vec<RevenueBalance>,
+}
+
+/// Sum `entries` by `period`, source and currency
+pub fn buckets(entries: &[FeeEntry], period: RevenuePeriod) -> Vec<RevenueBucket> {
+    let mut buckets: BTreeMap<(DateTime<Utc>, FeeSource, &str), RevenueBucket> = BTreeMap::new();
+    for entry in entries {
+        let period_start = period.start(entry.created_at);
+        let bucket = buckets
+            .entry((period_start, entry.source, &entry.currency))
+            .or_insert_with(|| RevenueBucket {
+                period_start,
+                source: entry.source,
+                currency: entry.currency.clone(),
+                amount: Decimal::ZERO,
+                entries: 0,
+            });
+        bucket.amount += entry.amount;
+        bucket.entries += 1;
+    }
+    buckets.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：手续费收入按日、周、月和来源、币种汇总，返佣抵减收入
+    #[test]
+    fn test_revenue_buckets() {
+        let at = |day: u32, hour: u32| Utc.with_ymd_and_hms(2024, 5, day, hour, 0, 0).unwrap();
+        let entry = |source, currency: &str, amount: i64, created_at| FeeEntry {
+            created_at,
+            ..FeeEntry::new(source, currency, Decimal::new(amount, 1), Uuid::new_v4(), Uuid::new_v4())
+        };
+        let entries = vec![
+            entry(FeeSource::Trading, "USDT", 10, at(6, 9)),
+            entry(FeeSource::Trading, "USDT", -2, at(6, 23)),
+            entry(FeeSource::Trading, "USDT", 5, at(8, 1)),
+            entry(FeeSource::Withdrawal, "BTC", 5, at(6, 12)),
+        ];
+
+        let daily = buckets(&entries, RevenuePeriod::Day);
+        assert_eq!(daily.len(), 3);
+        assert_eq!((daily[0].source, daily[0].amount, daily[0].entries), (FeeSource::Trading, Decimal::new(8, 1), 2));
+        assert_eq!(daily[1].currency, "BTC");
+
+        // 2024-05-06 是周一
+        let weekly = buckets(&entries, RevenuePeriod::Week);
+        assert_eq!(weekly.len(), 2);
+        assert_eq!(weekly[0].period_start, at(6, 0));
+        assert_eq!(weekly[0].amount, Decimal::new(13, 1));
+        assert_eq!(RevenuePeriod::Month.start(at(8, 1)), Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap());
+        assert!(is_revenue_account(FeeSource::Withdrawal.account()));
+    }
+}
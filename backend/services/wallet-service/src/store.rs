@@ -0,0 +1,999 @@
+//! Wallet storage
+//!
+//! The ledger keeps every account's balances in memory; `WalletStore`
+//! makes them durable. In PostgreSQL, through `flowex-database`, each
+//! ledger operation is written as one `LedgerWrite` in one transaction: the
+//! accounts it provisioned, the balances and reservations it changed, the
+//! trade it settled, the deposit or withdrawal it recorded, the transfer
+//! it posted, the fees it paid into revenue accounts, the funds it moved
+//! between the custody wallets and the conversion it executed. On startup the ledger is rebuilt from `load`, and the asset
+//! registry with it; assets are written as admins change them. End-of-day
+//! balance snapshots and their reconciliation reports are kept alongside,
+//! as are published liability reports with the leaves committed to them.
+//! Without a database nothing outlives the process.
+
+use chrono::{DateTime, Utc};
+use flowex_database::DatabasePool;
+use flowex_types::{Balance, FlowExError, FlowExResult, Reservation, TransactionStatus, TransactionType};
+use rust_decimal::Decimal;
+use sqlx::postgres::PgRow;
+use std::collections::HashMap;
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::approval::{ReviewStatus, WithdrawalReview};
+use crate::assets::{Asset, AssetConfig};
+use crate::convert::Conversion;
+use crate::funding::FundingTransaction;
+use crate::liabilities::{LiabilityLeaf, LiabilityReport};
+use crate::reconciliation::{BalanceSnapshot, ReconciliationReport};
+use crate::revenue::{FeeEntry, FeeSource};
+use crate::security::WhitelistedAddress;
+use crate::transfer::{Posting, Transfer};
+use crate::treasury::{FloatThreshold, TreasuryMovement, TreasuryWallet};
+
+/// A user's wallet account
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletAccount {
+    pub user_id: Uuid,
+    /// Email the user last authenticated with; transfers may be addressed
+    /// to it
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Changes of one ledger operation, written together
+#[derive(Debug, Default)]
+pub struct LedgerWrite {
+    /// Accounts provisioned or whose email changed
+    pub accounts: Vec<WalletAccount>,
+    pub balances: Vec<(Uuid, Balance)>,
+    /// Reservations by order, `None` once released
+    pub reservations: Vec<(Uuid, Option<Reservation>)>,
+    /// Trade settled
+    pub settled: Option<Uuid>,
+    /// Deposit or withdrawal recorded
+    pub funding: Option<FundingTransaction>,
+    /// Transfer posted
+    pub transfer: Option<Transfer>,
+    /// Fees paid into revenue accounts
+    pub fees: Vec<FeeEntry>,
+    /// Funds moved between the custody wallets
+    pub treasury: Option<TreasuryMovement>,
+    /// Conversion executed with the conversion desk
+    pub conversion: Option<Conversion>,
+}
+
+/// Everything stored, to rebuild the ledger from
+#[derive(Debug, Default)]
+pub struct WalletSnapshot {
+    pub accounts: Vec<WalletAccount>,
+    pub balances: Vec<(Uuid, Balance)>,
+    pub reservations: Vec<Reservation>,
+    pub settled: Vec<Uuid>,
+    /// Deposits and withdrawals, oldest first
+    pub funding: Vec<FundingTransaction>,
+    /// Transfers, oldest first
+    pub transfers: Vec<Transfer>,
+    /// Registered assets
+    pub assets: Vec<Asset>,
+    /// Reconciliation reports, oldest first
+    pub reports: Vec<ReconciliationReport>,
+    /// Fees paid into revenue accounts, oldest first
+    pub fees: Vec<FeeEntry>,
+    /// Latest liability report with its leaves
+    pub liabilities: Option<(LiabilityReport, Vec<LiabilityLeaf>)>,
+    /// Movements between the custody wallets, oldest first
+    pub treasury_movements: Vec<TreasuryMovement>,
+    /// Hot wallet float thresholds by currency
+    pub float_thresholds: Vec<(String, FloatThreshold)>,
+    /// Anti-phishing codes by user
+    pub anti_phishing_codes: Vec<(Uuid, String)>,
+    /// Whitelisted withdrawal addresses, oldest first
+    pub address_whitelist: Vec<WhitelistedAddress>,
+}
+
+/// Where the ledger is kept
+#[derive(Clone, Default)]
+pub enum WalletStore {
+    /// Only in the ledger's memory, lost on restart
+    #[default]
+    Memory,
+    /// In PostgreSQL
+    Postgres(DatabasePool),
+}
+
+impl WalletStore {
+    /// Store backed by the PostgreSQL database at `database_url`
+    pub async fn connect(database_url: &str) -> FlowExResult<Self> {
+        let pool = DatabasePool::new(database_url).await.map_err(database_error)?;
+        Ok(WalletStore::Postgres(pool))
+    }
+
+    /// Every account, balance, reservation, settled trade, funding
+    /// transaction, transfer, asset, reconciliation report, fee, treasury
+    /// movement, float threshold, anti-phishing code and whitelisted address
+    /// stored, with the latest liability report
+    pub async fn load(&self) -> FlowExResult<WalletSnapshot> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(WalletSnapshot::default());
+        };
+        let pool = pool.pool();
+
+        let accounts = sqlx::query("SELECT user_id, email, created_at FROM wallet_accounts")
+            .fetch_all(pool)
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| {
+                Ok(WalletAccount {
+                    user_id: row.try_get("user_id")?,
+                    email: row.try_get("email")?,
+                    created_at: row.try_get("created_at")?,
+                })
+            })
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(database_error)?;
+        let balances = sqlx::query("SELECT user_id, currency, available, locked FROM balances")
+            .fetch_all(pool)
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| {
+                let balance = Balance {
+                    currency: row.try_get("currency")?,
+                    available: row.try_get("available")?,
+                    locked: row.try_get("locked")?,
+                };
+                Ok((row.try_get("user_id")?, balance))
+            })
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(database_error)?;
+        let reservations = sqlx::query("SELECT order_id, user_id, currency, amount FROM wallet_reservations")
+            .fetch_all(pool)
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| {
+                Ok(Reservation {
+                    order_id: row.try_get("order_id")?,
+                    user_id: row.try_get("user_id")?,
+                    currency: row.try_get("currency")?,
+                    amount: row.try_get("amount")?,
+                    all_available: false,
+                })
+            })
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(database_error)?;
+        let settled = sqlx::query("SELECT trade_id FROM wallet_settled_trades")
+            .fetch_all(pool)
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| row.try_get("trade_id"))
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(database_error)?;
+        let funding = sqlx::query(
+            "SELECT id, user_id, transaction_type, currency, amount, fee, address, external_id, status, \
+             failure_reason, review_status, required_approvals, approved_by, rejected_by, created_at, updated_at \
+             FROM transactions \
+             WHERE transaction_type IN ('deposit', 'withdrawal') ORDER BY created_at, id",
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(funding_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)?;
+        let transfers = load_transfers(pool).await.map_err(database_error)?;
+        let assets = sqlx::query(
+            "SELECT currency, name, precision, min_withdrawal, withdrawal_fee, deposit_enabled, \
+             withdrawal_enabled, network, confirmations, updated_at FROM wallet_assets",
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(asset_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)?;
+        let reports = sqlx::query(
+            "SELECT id, snapshot_id, business_date, taken_at, totals, mismatches \
+             FROM wallet_reconciliation_reports ORDER BY taken_at, id",
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(report_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)?;
+        let liabilities = load_latest_liabilities(pool).await.map_err(database_error)?;
+        let treasury_movements = sqlx::query(
+            "SELECT id, from_wallet, to_wallet, currency, amount, tx_hash, note, recorded_by, created_at \
+             FROM wallet_treasury_movements ORDER BY created_at, id",
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(treasury_movement_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)?;
+        let float_thresholds = sqlx::query("SELECT currency, sweep_above, refill_below FROM wallet_float_thresholds")
+            .fetch_all(pool)
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| {
+                let threshold = FloatThreshold {
+                    sweep_above: row.try_get("sweep_above")?,
+                    refill_below: row.try_get("refill_below")?,
+                };
+                Ok((row.try_get("currency")?, threshold))
+            })
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(database_error)?;
+        let fees = sqlx::query(
+            "SELECT id, source, currency, amount, user_id, reference_id, created_at \
+             FROM wallet_fee_revenue ORDER BY created_at, id",
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(fee_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)?;
+
+        let anti_phishing_codes = sqlx::query("SELECT user_id, code FROM wallet_anti_phishing_codes")
+            .fetch_all(pool)
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| Ok((row.try_get("user_id")?, row.try_get("code")?)))
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(database_error)?;
+        let address_whitelist = sqlx::query(
+            "SELECT id, user_id, currency, address, label, created_at, active_at \
+             FROM wallet_address_whitelist ORDER BY created_at, id",
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(database_error)?
+        .iter()
+        .map(whitelisted_address_from_row)
+        .collect::<Result<_, sqlx::Error>>()
+        .map_err(database_error)?;
+
+        Ok(WalletSnapshot {
+            accounts,
+            balances,
+            reservations,
+            settled,
+            funding,
+            transfers,
+            assets,
+            reports,
+            fees,
+            liabilities,
+            treasury_movements,
+            float_thresholds,
+            anti_phishing_codes,
+            address_whitelist,
+        })
+    }
+
+    /// Write the changes of one ledger operation
+    pub async fn write(&self, write: &LedgerWrite) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        let mut tx = pool.begin_transaction().await.map_err(database_error)?;
+
+        for account in &write.accounts {
+            sqlx::query(
+                "INSERT INTO wallet_accounts (user_id, email, created_at) VALUES ($1, $2, $3) \
+                 ON CONFLICT (user_id) DO UPDATE SET email = COALESCE(EXCLUDED.email, wallet_accounts.email)",
+            )
+            .bind(account.user_id)
+            .bind(&account.email)
+            .bind(account.created_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        }
+        for (user_id, balance) in &write.balances {
+            sqlx::query(
+                "INSERT INTO balances (user_id, currency, available, locked, updated_at) \
+                 VALUES ($1, $2, $3, $4, NOW()) \
+                 ON CONFLICT (user_id, currency) DO UPDATE SET available = EXCLUDED.available, \
+                 locked = EXCLUDED.locked, updated_at = EXCLUDED.updated_at",
+            )
+            .bind(user_id)
+            .bind(&balance.currency)
+            .bind(balance.available)
+            .bind(balance.locked)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        }
+        for (order_id, reservation) in &write.reservations {
+            match reservation {
+                Some(reservation) => {
+                    sqlx::query(
+                        "INSERT INTO wallet_reservations (order_id, user_id, currency, amount) \
+                         VALUES ($1, $2, $3, $4) ON CONFLICT (order_id) DO UPDATE SET amount = EXCLUDED.amount",
+                    )
+                    .bind(order_id)
+                    .bind(reservation.user_id)
+                    .bind(&reservation.currency)
+                    .bind(reservation.amount)
+                    .execute(&mut *tx)
+                    .await
+                }
+                None => {
+                    sqlx::query("DELETE FROM wallet_reservations WHERE order_id = $1")
+                        .bind(order_id)
+                        .execute(&mut *tx)
+                        .await
+                }
+            }
+            .map_err(database_error)?;
+        }
+        if let Some(trade_id) = write.settled {
+            sqlx::query("INSERT INTO wallet_settled_trades (trade_id) VALUES ($1)")
+                .bind(trade_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(database_error)?;
+        }
+        if let Some(transaction) = &write.funding {
+            let review = transaction.review.as_ref();
+            sqlx::query(
+                "INSERT INTO transactions (id, user_id, transaction_type, currency, amount, fee, address, \
+                 external_id, status, failure_reason, review_status, required_approvals, approved_by, rejected_by, \
+                 created_at, updated_at) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16) \
+                 ON CONFLICT (id) DO UPDATE SET external_id = EXCLUDED.external_id, status = EXCLUDED.status, \
+                 failure_reason = EXCLUDED.failure_reason, review_status = EXCLUDED.review_status, \
+                 approved_by = EXCLUDED.approved_by, rejected_by = EXCLUDED.rejected_by, \
+                 updated_at = EXCLUDED.updated_at",
+            )
+            .bind(transaction.id)
+            .bind(transaction.user_id)
+            .bind(transaction_type_to_db(&transaction.transaction_type))
+            .bind(&transaction.currency)
+            .bind(transaction.amount)
+            .bind(transaction.fee)
+            .bind(&transaction.address)
+            .bind(&transaction.tx_hash)
+            .bind(status_to_db(&transaction.status))
+            .bind(&transaction.failure_reason)
+            .bind(review.map(|review| review_status_to_db(review.status)))
+            .bind(review.map(|review| review.required_approvals as i32))
+            .bind(review.map(|review| review.approved_by.clone()))
+            .bind(review.and_then(|review| review.rejected_by))
+            .bind(transaction.created_at)
+            .bind(transaction.updated_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        }
+
+        if let Some(transfer) = &write.transfer {
+            sqlx::query(
+                "INSERT INTO wallet_transfers (id, from_user_id, to_user_id, currency, amount, memo, created_at) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(transfer.id)
+            .bind(transfer.from_user_id)
+            .bind(transfer.to_user_id)
+            .bind(&transfer.currency)
+            .bind(transfer.amount)
+            .bind(&transfer.memo)
+            .bind(transfer.created_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+            for (index, posting) in transfer.postings.iter().enumerate() {
+                sqlx::query(
+                    "INSERT INTO wallet_postings (transfer_id, entry, user_id, currency, amount) \
+                     VALUES ($1, $2, $3, $4, $5)",
+                )
+                .bind(transfer.id)
+                .bind(index as i32)
+                .bind(posting.user_id)
+                .bind(&posting.currency)
+                .bind(posting.amount)
+                .execute(&mut *tx)
+                .await
+                .map_err(database_error)?;
+            }
+        }
+        for fee in &write.fees {
+            sqlx::query(
+                "INSERT INTO wallet_fee_revenue (id, source, currency, amount, user_id, reference_id, created_at) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(fee.id)
+            .bind(fee_source_to_db(fee.source))
+            .bind(&fee.currency)
+            .bind(fee.amount)
+            .bind(fee.user_id)
+            .bind(fee.reference_id)
+            .bind(fee.created_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        }
+        if let Some(movement) = &write.treasury {
+            sqlx::query(
+                "INSERT INTO wallet_treasury_movements (id, from_wallet, to_wallet, currency, amount, tx_hash, note, \
+                 recorded_by, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            )
+            .bind(movement.id)
+            .bind(treasury_wallet_to_db(movement.from))
+            .bind(treasury_wallet_to_db(movement.to))
+            .bind(&movement.currency)
+            .bind(movement.amount)
+            .bind(&movement.tx_hash)
+            .bind(&movement.note)
+            .bind(movement.recorded_by)
+            .bind(movement.created_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        }
+        if let Some(conversion) = &write.conversion {
+            sqlx::query(
+                "INSERT INTO wallet_conversions (id, quote_id, user_id, from_currency, to_currency, amount, rate, fee, \
+                 receive, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            )
+            .bind(conversion.id)
+            .bind(conversion.quote_id)
+            .bind(conversion.user_id)
+            .bind(&conversion.from)
+            .bind(&conversion.to)
+            .bind(conversion.amount)
+            .bind(conversion.rate)
+            .bind(conversion.fee)
+            .bind(conversion.receive)
+            .bind(conversion.created_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        }
+
+        tx.commit().await.map_err(database_error)
+    }
+
+    /// Write the hot wallet float threshold of a currency
+    pub async fn write_float_threshold(&self, currency: &str, threshold: &FloatThreshold) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO wallet_float_thresholds (currency, sweep_above, refill_below, updated_at) \
+             VALUES ($1, $2, $3, NOW()) ON CONFLICT (currency) DO UPDATE SET sweep_above = EXCLUDED.sweep_above, \
+             refill_below = EXCLUDED.refill_below, updated_at = EXCLUDED.updated_at",
+        )
+        .bind(currency)
+        .bind(threshold.sweep_above)
+        .bind(threshold.refill_below)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Write the anti-phishing code of a user, or delete it with `None`
+    pub async fn write_anti_phishing_code(&self, user_id: Uuid, code: Option<&str>) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        let query = match code {
+            Some(code) => sqlx::query(
+                "INSERT INTO wallet_anti_phishing_codes (user_id, code, updated_at) VALUES ($1, $2, NOW()) \
+                 ON CONFLICT (user_id) DO UPDATE SET code = EXCLUDED.code, updated_at = EXCLUDED.updated_at",
+            )
+            .bind(user_id)
+            .bind(code),
+            None => sqlx::query("DELETE FROM wallet_anti_phishing_codes WHERE user_id = $1").bind(user_id),
+        };
+        query.execute(pool.pool()).await.map_err(database_error)?;
+        Ok(())
+    }
+
+    pub async fn insert_whitelisted_address(&self, entry: &WhitelistedAddress) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO wallet_address_whitelist (id, user_id, currency, address, label, created_at, active_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(entry.id)
+        .bind(entry.user_id)
+        .bind(&entry.currency)
+        .bind(&entry.address)
+        .bind(&entry.label)
+        .bind(entry.created_at)
+        .bind(entry.active_at)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    pub async fn delete_whitelisted_address(&self, id: Uuid) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query("DELETE FROM wallet_address_whitelist WHERE id = $1")
+            .bind(id)
+            .execute(pool.pool())
+            .await
+            .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Write a published liability report with the leaves committed to it
+    pub async fn write_liability_report(&self, report: &LiabilityReport, leaves: &[LiabilityLeaf]) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        let mut tx = pool.begin_transaction().await.map_err(database_error)?;
+        sqlx::query("INSERT INTO wallet_liability_reports (id, taken_at, roots) VALUES ($1, $2, $3)")
+            .bind(report.id)
+            .bind(report.taken_at)
+            .bind(sqlx::types::Json(&report.roots))
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        for leaf in leaves {
+            sqlx::query(
+                "INSERT INTO wallet_liability_leaves (report_id, user_id, currency, balance, nonce) \
+                 VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(report.id)
+            .bind(leaf.user_id)
+            .bind(&leaf.currency)
+            .bind(leaf.balance)
+            .bind(&leaf.nonce)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        }
+        tx.commit().await.map_err(database_error)
+    }
+
+    /// Write an asset an admin registered or changed
+    pub async fn write_asset(&self, asset: &Asset) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        let config = &asset.config;
+        sqlx::query(
+            "INSERT INTO wallet_assets (currency, name, precision, min_withdrawal, withdrawal_fee, deposit_enabled, \
+             withdrawal_enabled, network, confirmations, updated_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+             ON CONFLICT (currency) DO UPDATE SET name = EXCLUDED.name, precision = EXCLUDED.precision, \
+             min_withdrawal = EXCLUDED.min_withdrawal, withdrawal_fee = EXCLUDED.withdrawal_fee, \
+             deposit_enabled = EXCLUDED.deposit_enabled, withdrawal_enabled = EXCLUDED.withdrawal_enabled, \
+             network = EXCLUDED.network, confirmations = EXCLUDED.confirmations, updated_at = EXCLUDED.updated_at",
+        )
+        .bind(&asset.currency)
+        .bind(&config.name)
+        .bind(config.precision as i32)
+        .bind(config.min_withdrawal)
+        .bind(config.withdrawal_fee)
+        .bind(config.deposit_enabled)
+        .bind(config.withdrawal_enabled)
+        .bind(&config.network)
+        .bind(config.confirmations as i32)
+        .bind(asset.updated_at)
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Balances stored by currency, available and locked; `None` without a
+    /// database
+    pub async fn balance_totals(&self) -> FlowExResult<Option<HashMap<String, Decimal>>> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(None);
+        };
+        let rows = sqlx::query("SELECT currency, SUM(available + locked) AS total FROM balances GROUP BY currency")
+            .fetch_all(pool.pool())
+            .await
+            .map_err(database_error)?;
+        totals_from_rows(&rows).map(Some).map_err(database_error)
+    }
+
+    /// Write the balances of an end-of-day snapshot
+    pub async fn write_snapshot(&self, snapshot: &BalanceSnapshot) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        let mut tx = pool.begin_transaction().await.map_err(database_error)?;
+        sqlx::query("INSERT INTO wallet_balance_snapshots (id, business_date, taken_at) VALUES ($1, $2, $3)")
+            .bind(snapshot.id)
+            .bind(snapshot.business_date)
+            .bind(snapshot.taken_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        for (user_id, balance) in &snapshot.balances {
+            sqlx::query(
+                "INSERT INTO wallet_snapshot_balances (snapshot_id, user_id, currency, available, locked) \
+                 VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(snapshot.id)
+            .bind(user_id)
+            .bind(&balance.currency)
+            .bind(balance.available)
+            .bind(balance.locked)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+        }
+        tx.commit().await.map_err(database_error)
+    }
+
+    /// Balances of a stored snapshot by currency, available and locked;
+    /// `None` without a database
+    pub async fn snapshot_totals(&self, snapshot_id: Uuid) -> FlowExResult<Option<HashMap<String, Decimal>>> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(None);
+        };
+        let rows = sqlx::query(
+            "SELECT currency, SUM(available + locked) AS total FROM wallet_snapshot_balances \
+             WHERE snapshot_id = $1 GROUP BY currency",
+        )
+        .bind(snapshot_id)
+        .fetch_all(pool.pool())
+        .await
+        .map_err(database_error)?;
+        totals_from_rows(&rows).map(Some).map_err(database_error)
+    }
+
+    pub async fn write_report(&self, report: &ReconciliationReport) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query(
+            "INSERT INTO wallet_reconciliation_reports (id, snapshot_id, business_date, taken_at, balanced, totals, \
+             mismatches) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(report.id)
+        .bind(report.snapshot_id)
+        .bind(report.business_date)
+        .bind(report.taken_at)
+        .bind(report.balanced())
+        .bind(sqlx::types::Json(&report.totals))
+        .bind(sqlx::types::Json(&report.mismatches))
+        .execute(pool.pool())
+        .await
+        .map_err(database_error)?;
+        Ok(())
+    }
+
+    pub async fn delete_asset(&self, currency: &str) -> FlowExResult<()> {
+        let WalletStore::Postgres(pool) = self else {
+            return Ok(());
+        };
+        sqlx::query("DELETE FROM wallet_assets WHERE currency = $1")
+            .bind(currency)
+            .execute(pool.pool())
+            .await
+            .map_err(database_error)?;
+        Ok(())
+    }
+}
+
+/// Every transfer with its postings, oldest first
+async fn load_transfers(pool: &sqlx::PgPool) -> Result<Vec<Transfer>, sqlx::Error> {
+    let mut postings: HashMap<Uuid, Vec<Posting>> = HashMap::new();
+    for row in sqlx::query("SELECT transfer_id, user_id, currency, amount FROM wallet_postings ORDER BY transfer_id, entry")
+        .fetch_all(pool)
+        .await?
+    {
+        postings.entry(row.try_get("transfer_id")?).or_default().push(Posting {
+            user_id: row.try_get("user_id")?,
+            currency: row.try_get("currency")?,
+            amount: row.try_get("amount")?,
+        });
+    }
+
+    sqlx::query(
+        "SELECT id, from_user_id, to_user_id, currency, amount, memo, created_at FROM wallet_transfers \
+         ORDER BY created_at, id",
+    )
+    .fetch_all(pool)
+    .await?
+    .iter()
+    .map(|row| {
+        let id = row.try_get("id")?;
+        Ok(Transfer {
+            id,
+            from_user_id: row.try_get("from_user_id")?,
+            to_user_id: row.try_get("to_user_id")?,
+            currency: row.try_get("currency")?,
+            amount: row.try_get("amount")?,
+            memo: row.try_get("memo")?,
+            postings: postings.remove(&id).unwrap_or_default(),
+            created_at: row.try_get("created_at")?,
+        })
+    })
+    .collect()
+}
+
+/// Latest liability report with its leaves, if one was published
+async fn load_latest_liabilities(
+    pool: &sqlx::PgPool,
+) -> Result<Option<(LiabilityReport, Vec<LiabilityLeaf>)>, sqlx::Error> {
+    let Some(row) = sqlx::query("SELECT id, taken_at, roots FROM wallet_liability_reports ORDER BY taken_at DESC LIMIT 1")
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let report = LiabilityReport {
+        id: row.try_get("id")?,
+        taken_at: row.try_get("taken_at")?,
+        roots: row.try_get::<sqlx::types::Json<_>, _>("roots")?.0,
+    };
+    let leaves = sqlx::query("SELECT user_id, currency, balance, nonce FROM wallet_liability_leaves WHERE report_id = $1")
+        .bind(report.id)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(LiabilityLeaf {
+                user_id: row.try_get("user_id")?,
+                currency: row.try_get("currency")?,
+                balance: row.try_get("balance")?,
+                nonce: row.try_get("nonce")?,
+            })
+        })
+        .collect::<Result<_, sqlx::Error>>()?;
+    Ok(Some((report, leaves)))
+}
+
+fn database_error(error: sqlx::Error) -> FlowExError {
+    FlowExError::Database(error.to_string())
+}
+
+fn funding_from_row(row: &PgRow) -> Result<FundingTransaction, sqlx::Error> {
+    let review = match row.try_get::<Option<&str>, _>("review_status")? {
+        Some(status) => Some(WithdrawalReview {
+            status: review_status_from_db(status)?,
+            required_approvals: row.try_get::<i32, _>("required_approvals")?.max(0) as u32,
+            approved_by: row.try_get("approved_by")?,
+            rejected_by: row.try_get("rejected_by")?,
+        }),
+        None => None,
+    };
+    Ok(FundingTransaction {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        transaction_type: transaction_type_from_db(row.try_get("transaction_type")?)?,
+        currency: row.try_get("currency")?,
+        amount: row.try_get("amount")?,
+        fee: row.try_get("fee")?,
+        address: row.try_get("address")?,
+        tx_hash: row.try_get("external_id")?,
+        status: status_from_db(row.try_get("status")?)?,
+        failure_reason: row.try_get("failure_reason")?,
+        review,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+fn totals_from_rows(rows: &[PgRow]) -> Result<HashMap<String, Decimal>, sqlx::Error> {
+    rows.iter()
+        .map(|row| Ok((row.try_get("currency")?, row.try_get("total")?)))
+        .collect()
+}
+
+fn report_from_row(row: &PgRow) -> Result<ReconciliationReport, sqlx::Error> {
+    Ok(ReconciliationReport {
+        id: row.try_get("id")?,
+        snapshot_id: row.try_get("snapshot_id")?,
+        business_date: row.try_get("business_date")?,
+        taken_at: row.try_get("taken_at")?,
+        totals: row.try_get::<sqlx::types::Json<_>, _>("totals")?.0,
+        mismatches: row.try_get::<sqlx::types::Json<_>, _>("mismatches")?.0,
+    })
+}
+
+fn fee_from_row(row: &PgRow) -> Result<FeeEntry, sqlx::Error> {
+    Ok(FeeEntry {
+        id: row.try_get("id")?,
+        source: fee_source_from_db(row.try_get("source")?)?,
+        currency: row.try_get("currency")?,
+        amount: row.try_get("amount")?,
+        user_id: row.try_get("user_id")?,
+        reference_id: row.try_get("reference_id")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn treasury_movement_from_row(row: &PgRow) -> Result<TreasuryMovement, sqlx::Error> {
+    Ok(TreasuryMovement {
+        id: row.try_get("id")?,
+        from: treasury_wallet_from_db(row.try_get("from_wallet")?)?,
+        to: treasury_wallet_from_db(row.try_get("to_wallet")?)?,
+        currency: row.try_get("currency")?,
+        amount: row.try_get("amount")?,
+        tx_hash: row.try_get("tx_hash")?,
+        note: row.try_get("note")?,
+        recorded_by: row.try_get("recorded_by")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn whitelisted_address_from_row(row: &PgRow) -> Result<WhitelistedAddress, sqlx::Error> {
+    Ok(WhitelistedAddress {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        currency: row.try_get("currency")?,
+        address: row.try_get("address")?,
+        label: row.try_get("label")?,
+        created_at: row.try_get("created_at")?,
+        active_at: row.try_get("active_at")?,
+    })
+}
+
+fn asset_from_row(row: &PgRow) -> Result<Asset, sqlx::Error> {
+    Ok(Asset {
+        currency: row.try_get("currency")?,
+        config: AssetConfig {
+            name: row.try_get("name")?,
+            precision: row.try_get::<i32, _>("precision")?.max(0) as u32,
+            min_withdrawal: row.try_get("min_withdrawal")?,
+            withdrawal_fee: row.try_get("withdrawal_fee")?,
+            deposit_enabled: row.try_get("deposit_enabled")?,
+            withdrawal_enabled: row.try_get("withdrawal_enabled")?,
+            network: row.try_get("network")?,
+            confirmations: row.try_get::<i32, _>("confirmations")?.max(0) as u32,
+        },
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+pub fn transaction_type_to_db(transaction_type: &TransactionType) -> &'static str {
+    match transaction_type {
+        TransactionType::Deposit => "deposit",
+        TransactionType::Withdrawal => "withdrawal",
+        TransactionType::Trade => "trade",
+        TransactionType::Fee => "fee",
+        TransactionType::Transfer => "transfer",
+    }
+}
+
+fn transaction_type_from_db(value: &str) -> Result<TransactionType, sqlx::Error> {
+    match value {
+        "deposit" => Ok(TransactionType::Deposit),
+        "withdrawal" => Ok(TransactionType::Withdrawal),
+        "trade" => Ok(TransactionType::Trade),
+        "fee" => Ok(TransactionType::Fee),
+        "transfer" => Ok(TransactionType::Transfer),
+        other => Err(decode_error("transaction type", other)),
+    }
+}
+
+pub fn status_to_db(status: &TransactionStatus) -> &'static str {
+    match status {
+        TransactionStatus::Pending => "pending",
+        TransactionStatus::Completed => "completed",
+        TransactionStatus::Failed => "failed",
+        TransactionStatus::Cancelled => "cancelled",
+    }
+}
+
+fn status_from_db(value: &str) -> Result<TransactionStatus, sqlx::Error> {
+    match value {
+        "pending" => Ok(TransactionStatus::Pending),
+        "completed" => Ok(TransactionStatus::Completed),
+        "failed" => Ok(TransactionStatus::Failed),
+        "cancelled" => Ok(TransactionStatus::Cancelled),
+        other => Err(decode_error("transaction status", other)),
+    }
+}
+
+fn review_status_to_db(status: ReviewStatus) -> &'static str {
+    match status {
+        ReviewStatus::Pending => "pending",
+        ReviewStatus::Approved => "approved",
+        ReviewStatus::Rejected => "rejected",
+    }
+}
+
+fn review_status_from_db(value: &str) -> Result<ReviewStatus, sqlx::Error> {
+    match value {
+        "pending" => Ok(ReviewStatus::Pending),
+        "approved" => Ok(ReviewStatus::Approved),
+        "rejected" => Ok(ReviewStatus::Rejected),
+        other => Err(decode_error("review status", other)),
+    }
+}
+
+fn fee_source_to_db(source: FeeSource) -> &'static str {
+    match source {
+        FeeSource::Trading => "trading",
+        FeeSource::Withdrawal => "withdrawal",
+        FeeSource::Conversion => "conversion",
+    }
+}
+
+fn fee_source_from_db(value: &str) -> Result<FeeSource, sqlx::Error> {
+    match value {
+        "trading" => Ok(FeeSource::Trading),
+        "withdrawal" => Ok(FeeSource::Withdrawal),
+        "conversion" => Ok(FeeSource::Conversion),
+        other => Err(decode_error("fee source", other)),
+    }
+}
+
+fn treasury_wallet_to_db(wallet: TreasuryWallet) -> &'static str {
+    match wallet {
+        TreasuryWallet::Hot => "hot",
+        TreasuryWallet::Cold => "cold",
+    }
+}
+
+fn treasury_wallet_from_db(value: &str) -> Result<TreasuryWallet, sqlx::Error> {
+    match value {
+        "hot" => Ok(TreasuryWallet::Hot),
+        "cold" => Ok(TreasuryWallet::Cold),
+        other => Err(decode_error("treasury wallet", other)),
+    }
+}
+
+fn decode_error(what: &str, value: &str) -> sqlx::Error {
+    sqlx::Error::Decode(format!("unknown {} {:?}", what, value).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：交易类型和状态与数据库取值互相转换
+    #[test]
+    fn test_transaction_values_round_trip() {
+        for transaction_type in [
+            TransactionType::Deposit,
+            TransactionType::Withdrawal,
+            TransactionType::Trade,
+            TransactionType::Fee,
+            TransactionType::Transfer,
+        ] {
+            let value = transaction_type_to_db(&transaction_type);
+            assert_eq!(transaction_type_from_db(value).unwrap(), transaction_type);
+        }
+        for status in [
+            TransactionStatus::Pending,
+            TransactionStatus::Completed,
+            TransactionStatus::Failed,
+            TransactionStatus::Cancelled,
+        ] {
+            assert_eq!(status_from_db(status_to_db(&status)).unwrap(), status);
+        }
+        for status in [ReviewStatus::Pending, ReviewStatus::Approved, ReviewStatus::Rejected] {
+            assert_eq!(review_status_from_db(review_status_to_db(status)).unwrap(), status);
+        }
+        for source in FeeSource::ALL {
+            assert_eq!(fee_source_from_db(fee_source_to_db(source)).unwrap(), source);
+        }
+        for wallet in [TreasuryWallet::Hot, TreasuryWallet::Cold] {
+            assert_eq!(treasury_wallet_from_db(treasury_wallet_to_db(wallet)).unwrap(), wallet);
+        }
+        assert!(transaction_type_from_db("refund").is_err());
+        assert!(status_from_db("COMPLETED").is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Sub-accounts
+//!
+//! A sub-account is a user of its own to the wallet, its balances apart
+//! from its master's; the auth service opens them and keeps which master
+//! each belongs to. A master moves funds between its own account and its
+//! sub-accounts, and reads their balances together with its own, by
+//! account and totalled by currency. The auth service is asked for a
+//! master's sub-accounts on every request, so one just opened may be funded
+//! at once.
+
+use flowex_types::{ApiResponse, Balance, FlowExError, FlowExResult, SubAccount};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::ledger::Ledger;
+use crate::transfer::{TransferReceipt, TransferRequest, Transfers};
+
+/// Timeout of requests to the auth service
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where masters' sub-accounts are looked up
+#[derive(Debug, Clone)]
+enum Directory {
+    /// The auth service at `base_url`
+    Remote { http: reqwest::Client, base_url: String },
+    /// A fixed set of sub-accounts
+    Fixed(Vec<SubAccount>),
+}
+
+/// Transfer between a master's accounts request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubAccountTransferRequest {
+    /// Sub-account debited; the master's own account if unset
+    pub from: Option<Uuid>,
+    /// Sub-account credited; the master's own account if unset
+    pub to: Option<Uuid>,
+    pub currency: String,
+    pub amount: Decimal,
+    pub memo: Option<String>,
+}
+
+/// Balances of one of a master's accounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountBalances {
+    pub user_id: Uuid,
+    /// The sub-account's label; `None` for the master's own account
+    pub label: Option<String>,
+    pub balances: Vec<Balance>,
+}
+
+/// Balances of a master and its sub-accounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsolidatedBalances {
+    /// The master's own account first, then its sub-accounts, oldest first
+    pub accounts: Vec<AccountBalances>,
+    /// Sum of every account's balances by currency
+    pub totals: Vec<Balance>,
+}
+
+/// Funds and balances of masters' sub-accounts
+#[derive(Clone)]
+pub struct SubAccounts {
+    ledger: Ledger,
+    transfers: Transfers,
+    directory: Directory,
+}
+
+impl SubAccounts {
+    /// Sub-accounts looked up at the auth service at `base_url`
+    pub fn new(ledger: Ledger, transfers: Transfers, base_url: &str) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            ledger,
+            transfers,
+            directory: Directory::Remote {
+                http,
+                base_url: base_url.trim_end_matches('/').to_string(),
+            },
+        }
+    }
+
+    /// Sub-accounts of a fixed set
+    pub fn fixed(ledger: Ledger, transfers: Transfers, accounts: Vec<SubAccount>) -> Self {
+        Self {
+            ledger,
+            transfers,
+            directory: Directory::Fixed(accounts),
+        }
+    }
+
+    /// Sub-accounts of `master_id`, oldest first
+    pub async fn accounts(&self, master_id: Uuid) -> FlowExResult<Vec<SubAccount>> {
+        let (http, base_url) = match &self.directory {
+            Directory::Remote { http, base_url } => (http, base_url),
+            Directory::Fixed(accounts) => {
+                return Ok(accounts.iter().filter(|account| account.master_id == master_id).cloned().collect())
+            }
+        };
+
+        let url = format!("{}/internal/subaccounts/{}", base_url, master_id);
+        let response = http
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Sub-account lookup for {} failed: {}", master_id, e)))?;
+        if !response.status().is_success() {
+            return Err(FlowExError::Internal(format!(
+                "Sub-account lookup for {} answered {}",
+                master_id,
+                response.status()
+            )));
+        }
+        let body: ApiResponse<Vec<SubAccount>> = response
+            .json()
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Invalid sub-account response for {}: {}", master_id, e)))?;
+        Ok(body.data.unwrap_or_default())
+    }
+
+    /// Balances of `master_id` and its sub-accounts, with their totals
+    pub async fn balances(&self, master_id: Uuid) -> FlowExResult<ConsolidatedBalances> {
+        let sub_accounts = self.accounts(master_id).await?;
+        let mut accounts = vec![AccountBalances {
+            user_id: master_id,
+            label: None,
+            balances: self.ledger.balances(master_id).await,
+        }];
+        for account in sub_accounts {
+            accounts.push(AccountBalances {
+                user_id: account.user_id,
+                label: Some(account.label),
+                balances: self.ledger.balances(account.user_id).await,
+            });
+        }
+
+        let mut totals: BTreeMap<String, Balance> = BTreeMap::new();
+        for balance in accounts.iter().flat_map(|account| &account.balances) {
+            let total = totals.entry(balance.currency.clone()).or_insert_with(|| Balance {
+                currency: balance.currency.clone(),
+                available: Decimal::ZERO,
+                locked: Decimal::ZERO,
+            });
+            total.available += balance.available;
+            total.locked += balance.locked;
+        }
+        Ok(ConsolidatedBalances {
+            accounts,
+            totals: totals.into_values().collect(),
+        })
+    }
+
+    /// Move funds between `master_id`'s own account and its sub-accounts,
+    /// opening the sub-accounts' wallet accounts if they have none yet
+    pub async fn transfer(&self, master_id: Uuid, request: SubAccountTransferRequest) -> FlowExResult<TransferReceipt> {
+        let from = request.from.unwrap_or(master_id);
+        let to = request.to.unwrap_or(master_id);
+        if from == to {
+            return Err(FlowExError::Validation("Cannot transfer to the same account".to_string()));
+        }
+        let sub_accounts = self.accounts(master_id).await?;
+        for user_id in [from, to] {
+            if user_id == master_id {
+                continue;
+            }
+            let account = sub_accounts
+                .iter()
+                .find(|account| account.user_id == user_id)
+                .ok_or_else(|| FlowExError::Validation(format!("No sub-account {}", user_id)))?;
+            self.ledger.open_account(account.user_id, &account.email).await?;
+        }
+
+        let transfer = TransferRequest {
+            to: to.to_string(),
+            currency: request.currency,
+            amount: request.amount,
+            memo: request.memo,
+        };
+        self.transfers.transfer(from, transfer).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::AssetRegistry;
+    use crate::funding::{DepositRequest, Funding};
+    use chrono::Utc;
+
+    /// 测试：主账户与子账户之间划转资金，余额按账户分别列出并按币种汇总，不能划转到其他主账户的子账户
+    #[tokio::test]
+    async fn test_sub_account_transfers_and_consolidated_balances() {
+        let ledger = Ledger::new();
+        let assets = AssetRegistry::new();
+        let transfers = Transfers::new(ledger.clone(), assets.clone());
+        let (master, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let sub_account = |master_id: Uuid, label: &str| SubAccount {
+            user_id: Uuid::new_v4(),
+            master_id,
+            label: label.to_string(),
+            email: format!("{}@subaccounts.flowex.com", label),
+            permissions: vec!["trading:read".to_string()],
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let (desk, stranger) = (sub_account(master, "desk"), sub_account(other, "stranger"));
+        let sub_accounts = SubAccounts::fixed(ledger.clone(), transfers, vec![desk.clone(), stranger.clone()]);
+        ledger.open_account(master, "master@flowex.com").await.unwrap();
+        Funding::new(ledger.clone(), assets)
+            .deposit(
+                master,
+                DepositRequest {
+                    currency: "USDT".to_string(),
+                    amount: Decimal::new(100, 0),
+                    tx_hash: None,
+                },
+            )
+            .await
+            .unwrap();
+        let request = |from: Option<Uuid>, to: Option<Uuid>, amount: i64| SubAccountTransferRequest {
+            from,
+            to,
+            currency: "USDT".to_string(),
+            amount: Decimal::new(amount, 0),
+            memo: None,
+        };
+
+        let receipt = sub_accounts.transfer(master, request(None, Some(desk.user_id), 70)).await.unwrap();
+        assert_eq!(receipt.transfer.to_user_id, desk.user_id);
+        sub_accounts.transfer(master, request(Some(desk.user_id), None, 20)).await.unwrap();
+        assert!(sub_accounts.transfer(master, request(None, Some(stranger.user_id), 1)).await.is_err());
+        assert!(sub_accounts.transfer(master, request(None, None, 1)).await.is_err());
+        assert!(sub_accounts.transfer(desk.user_id, request(None, Some(master), 1)).await.is_err());
+
+        let consolidated = sub_accounts.balances(master).await.unwrap();
+        assert_eq!(consolidated.accounts.len(), 2);
+        assert_eq!(consolidated.accounts[0].balances[0].available, Decimal::new(50, 0));
+        assert_eq!(consolidated.accounts[1].label.as_deref(), Some("desk"));
+        assert_eq!(consolidated.accounts[1].balances[0].available, Decimal::new(50, 0));
+        assert_eq!(consolidated.totals[0].available, Decimal::new(100, 0));
+    }
+}
@@ -0,0 +1,338 @@
+//! Internal transfers
+//!
+//! Moves funds between two FlowEx accounts at once, without touching the
+//! chain. The recipient is addressed by user id or by the email their
+//! account was last used with, and must already have a user's account;
+//! the exchange's own accounts cannot be paid into this way. Each
+//! transfer is booked as two postings that sum to zero, a debit of the
+//! sender and a credit of the recipient, applied to the ledger together.
+//! Only registered assets may be transferred (see `assets`).
+//!
+//! Both parties are notified of every transfer on a broadcast channel; with
+//! `TRANSFER_WEBHOOK_URL` set, each notification is posted there as JSON,
+//! carrying the anti-phishing code of the party it is for, if they set one
+//! (see `security`).
+
+use chrono::{DateTime, Utc};
+use flowex_types::{BalanceChange, FlowExError, FlowExResult, Transaction, TransactionStatus, TransactionType};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::assets::AssetRegistry;
+use crate::ledger::{is_system_account, Ledger};
+use crate::security::AccountSecurity;
+
+/// Longest memo accepted
+const MAX_MEMO_LEN: usize = 256;
+
+/// Notifications buffered for a subscriber that falls behind
+const NOTIFICATION_CAPACITY: usize = 1024;
+
+/// Transfer request
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransferRequest {
+    /// Recipient's user id or email
+    pub to: String,
+    pub currency: String,
+    pub amount: Decimal,
+    pub memo: Option<String>,
+}
+
+/// One side of a transfer: negative for the account debited
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Posting {
+    pub user_id: Uuid,
+    pub currency: String,
+    pub amount: Decimal,
+}
+
+/// A transfer between two accounts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transfer {
+    pub id: Uuid,
+    pub from_user_id: Uuid,
+    pub to_user_id: Uuid,
+    pub currency: String,
+    pub amount: Decimal,
+    pub memo: Option<String>,
+    /// Debit of the sender and credit of the recipient
+    pub postings: Vec<Posting>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Transfer {
+    /// Entry of `user_id`'s transaction history, negative if they sent it
+    pub fn transaction(&self, user_id: Uuid) -> Transaction {
+        Transaction {
+            id: self.id,
+            user_id,
+            transaction_type: TransactionType::Transfer,
+            currency: self.currency.clone(),
+            amount: if user_id == self.from_user_id { -self.amount } else { self.amount },
+            status: TransactionStatus::Completed,
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// A transfer with the sender's balance after it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferReceipt {
+    pub transfer: Transfer,
+    pub balance: BalanceChange,
+}
+
+/// Which side of a transfer a notification is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Sent,
+    Received,
+}
+
+/// A transfer, as notified to one of its parties
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferNotification {
+    pub user_id: Uuid,
+    pub direction: TransferDirection,
+    pub transfer: Transfer,
+    /// The party's balance after the transfer
+    pub balance: BalanceChange,
+    /// The party's anti-phishing code, filled in when the notification is
+    /// delivered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anti_phishing_code: Option<String>,
+}
+
+/// Transfers between accounts, posted to the ledger
+#[derive(Clone)]
+pub struct Transfers {
+    ledger: Ledger,
+    assets: AssetRegistry,
+    /// Transfers, oldest first
+    transfers: Arc<RwLock<Vec<Transfer>>>,
+    notifications: broadcast::Sender<TransferNotification>,
+}
+
+impl Transfers {
+    pub fn new(ledger: Ledger, assets: AssetRegistry) -> Self {
+        Self::with_transfers(ledger, assets, Vec::new())
+    }
+
+    /// Transfers holding `transfers` already posted to `ledger`, as
+    /// restored from its store
+    pub fn with_transfers(ledger: Ledger, assets: AssetRegistry, transfers: Vec<Transfer>) -> Self {
+        let (notifications, _) = broadcast::channel(NOTIFICATION_CAPACITY);
+        Self {
+            ledger,
+            assets,
+            transfers: Arc::new(RwLock::new(transfers)),
+            notifications,
+        }
+    }
+
+    /// Notifications of every transfer from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<TransferNotification> {
+        self.notifications.subscribe()
+    }
+
+    /// Transfers a user sent or received, newest first
+    pub async fn user_transfers(&self, user_id: Uuid) -> Vec<Transfer> {
+        self.transfers
+            .read()
+            .await
+            .iter()
+            .rev()
+            .filter(|transfer| transfer.from_user_id == user_id || transfer.to_user_id == user_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Move funds from `from_user_id` to the account `request` addresses
+    pub async fn transfer(&self, from_user_id: Uuid, request: TransferRequest) -> FlowExResult<TransferReceipt> {
+        let asset = self.assets.asset(&request.currency).await?;
+        asset.check_amount(request.amount)?;
+        let currency = asset.currency;
+        let memo = request.memo.map(|memo| memo.trim().to_string()).filter(|memo| !memo.is_empty());
+        if memo.as_ref().is_some_and(|memo| memo.chars().count() > MAX_MEMO_LEN) {
+            return Err(FlowExError::Validation(format!(
+                "Memo may have at most {} characters",
+                MAX_MEMO_LEN
+            )));
+        }
+        let to_user_id = self.recipient(request.to.trim()).await?;
+        if to_user_id == from_user_id {
+            return Err(FlowExError::Validation("Cannot transfer to the same account".to_string()));
+        }
+
+        let transfer = Transfer {
+            id: Uuid::new_v4(),
+            from_user_id,
+            to_user_id,
+            currency: currency.clone(),
+            amount: request.amount,
+            memo,
+            postings: vec![
+                Posting {
+                    user_id: from_user_id,
+                    currency: currency.clone(),
+                    amount: -request.amount,
+                },
+                Posting {
+                    user_id: to_user_id,
+                    currency,
+                    amount: request.amount,
+                },
+            ],
+            created_at: Utc::now(),
+        };
+
+        let mut transfers = self.transfers.write().await;
+        let mut changes = self.ledger.transfer(&transfer).await?.into_iter();
+        transfers.push(transfer.clone());
+        drop(transfers);
+        info!(
+            "Transferred {} {} from {} to {} ({})",
+            transfer.amount, transfer.currency, from_user_id, to_user_id, transfer.id
+        );
+
+        let (Some(sent), Some(received)) = (changes.next(), changes.next()) else {
+            return Err(FlowExError::Internal(format!("Transfer {} changed no balances", transfer.id)));
+        };
+        self.notify(TransferDirection::Sent, &transfer, sent.clone());
+        self.notify(TransferDirection::Received, &transfer, received);
+        Ok(TransferReceipt {
+            transfer,
+            balance: sent,
+        })
+    }
+
+    /// Account `to` addresses: a user id, or else an email
+    async fn recipient(&self, to: &str) -> FlowExResult<Uuid> {
+        let user_id = match Uuid::parse_str(to) {
+            Ok(user_id) => Some(user_id),
+            Err(_) if to.contains('@') => self.ledger.find_account_by_email(to).await,
+            Err(_) => return Err(FlowExError::Validation(format!("Invalid recipient {:?}", to))),
+        };
+        match user_id {
+            Some(user_id) if !is_system_account(user_id) && self.ledger.has_account(user_id).await => {
+                Ok(user_id)
+            }
+            _ => Err(FlowExError::Validation(format!("No account {}", to))),
+        }
+    }
+
+    fn notify(&self, direction: TransferDirection, transfer: &Transfer, balance: BalanceChange) {
+        let notification = TransferNotification {
+            user_id: balance.user_id,
+            direction,
+            transfer: transfer.clone(),
+            balance,
+            anti_phishing_code: None,
+        };
+        // No subscribers is fine: nobody is listening yet
+        let _ = self.notifications.send(notification);
+    }
+}
+
+/// Post every notification received on `notifications` to `url` as JSON,
+/// with the anti-phishing code `security` holds for its party, until the
+/// channel closes
+pub fn spawn_webhook(
+    mut notifications: broadcast::Receiver<TransferNotification>,
+    url: String,
+    security: AccountSecurity,
+) -> JoinHandle<()> {
+    let http = reqwest::Client::new();
+    tokio::spawn(async move {
+        loop {
+            let mut notification = match notifications.recv().await {
+                Ok(notification) => notification,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Transfer webhook fell behind; {} notifications skipped", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            notification.anti_phishing_code = security.anti_phishing_code(notification.user_id).await;
+            match http.post(&url).json(&notification).send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Notified {} of transfer {}", notification.user_id, notification.transfer.id)
+                }
+                Ok(response) => warn!(
+                    "Transfer webhook answered {} for transfer {}",
+                    response.status(),
+                    notification.transfer.id
+                ),
+                Err(e) => warn!("Transfer webhook failed for transfer {}: {}", notification.transfer.id, e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::funding::{DepositRequest, Funding};
+
+    /// 测试：按邮箱或用户ID转账，双方余额同时变动并各自收到通知，余额不足和转给自己被拒绝
+    #[tokio::test]
+    async fn test_transfer_between_accounts() {
+        let ledger = Ledger::new();
+        let transfers = Transfers::new(ledger.clone(), AssetRegistry::new());
+        let mut notifications = transfers.subscribe();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        ledger.open_account(alice, "alice@flowex.com").await.unwrap();
+        ledger.open_account(bob, "bob@flowex.com").await.unwrap();
+        Funding::new(ledger.clone(), AssetRegistry::new())
+            .deposit(
+                alice,
+                DepositRequest {
+                    currency: "USDT".to_string(),
+                    amount: Decimal::new(100, 0),
+                    tx_hash: None,
+                },
+            )
+            .await
+            .unwrap();
+        let request = |to: &str, amount: i64| TransferRequest {
+            to: to.to_string(),
+            currency: "usdt".to_string(),
+            amount: Decimal::new(amount, 0),
+            memo: Some(" rent ".to_string()),
+        };
+
+        let receipt = transfers.transfer(alice, request("Bob@FlowEx.com", 60)).await.unwrap();
+        assert_eq!(receipt.transfer.to_user_id, bob);
+        assert_eq!(receipt.transfer.memo.as_deref(), Some("rent"));
+        assert_eq!(receipt.balance.balance.available, Decimal::new(40, 0));
+        assert_eq!(
+            receipt.transfer.postings.iter().map(|posting| posting.amount).sum::<Decimal>(),
+            Decimal::ZERO
+        );
+        assert_eq!(ledger.balances(bob).await[0].available, Decimal::new(60, 0));
+
+        let sent = notifications.recv().await.unwrap();
+        assert_eq!((sent.user_id, sent.direction), (alice, TransferDirection::Sent));
+        let received = notifications.recv().await.unwrap();
+        assert_eq!((received.user_id, received.direction), (bob, TransferDirection::Received));
+        assert_eq!(received.balance.balance.available, Decimal::new(60, 0));
+
+        transfers.transfer(bob, request(&alice.to_string(), 10)).await.unwrap();
+        assert!(matches!(transfers.transfer(alice, request("bob@flowex.com", 51)).await, Err(FlowExError::Wallet(_))));
+        assert!(transfers.transfer(alice, request("alice@flowex.com", 1)).await.is_err());
+        assert!(transfers.transfer(alice, request("carol@flowex.com", 1)).await.is_err());
+        assert!(transfers.transfer(alice, request(&Uuid::new_v4().to_string(), 1)).await.is_err());
+        assert!(transfers.transfer(alice, request(&crate::revenue::TRADING_FEE_ACCOUNT.to_string(), 1)).await.is_err());
+
+        let history = transfers.user_transfers(alice).await;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].transaction(alice).amount, Decimal::new(10, 0));
+        assert_eq!(history[1].transaction(alice).amount, Decimal::new(-60, 0));
+    }
+}
@@ -0,0 +1,415 @@
+//! Hot and cold wallet treasury
+//!
+//! The ledger tracks what the exchange holds in custody in two system
+//! accounts of its own: the hot wallet, online to receive deposits and send
+//! withdrawals, and the cold wallet, offline where the bulk of the funds is
+//! kept. A completed deposit arrives in the hot wallet and a completed
+//! withdrawal leaves it, less the fee kept as revenue (see `ledger`).
+//! Moving funds between the two is done by hand on chain and recorded here
+//! by an admin as a `TreasuryMovement`.
+//!
+//! Each currency may have a float threshold: once the hot wallet holds more
+//! than `sweep_above`, deposits should be swept to cold storage; once it
+//! holds less than `refill_below`, it should be refilled before
+//! withdrawals stall. A monitor checks the float every minute, logs a
+//! warning for each currency outside its thresholds and counts it in the
+//! `flowex_wallet_float_alerts_total` metric to alert on.
+
+use chrono::{DateTime, Utc};
+use flowex_metrics::MetricsCollector;
+use flowex_types::{FlowExError, FlowExResult};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::assets::AssetRegistry;
+use crate::ledger::Ledger;
+use crate::store::WalletStore;
+
+/// Account of the hot wallet's holdings
+pub const HOT_WALLET_ACCOUNT: Uuid = Uuid::from_u128(3);
+
+/// Account of the cold wallet's holdings
+pub const COLD_WALLET_ACCOUNT: Uuid = Uuid::from_u128(4);
+
+/// How often the hot wallet float is checked against its thresholds
+const FLOAT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Longest note accepted on a movement
+const MAX_NOTE_LEN: usize = 256;
+
+/// Whether `user_id` is one of the custody accounts
+pub fn is_custody_account(user_id: Uuid) -> bool {
+    user_id == HOT_WALLET_ACCOUNT || user_id == COLD_WALLET_ACCOUNT
+}
+
+/// One of the exchange's custody wallets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TreasuryWallet {
+    Hot,
+    Cold,
+}
+
+impl TreasuryWallet {
+    /// Ledger account of the wallet's holdings
+    pub fn account(self) -> Uuid {
+        match self {
+            TreasuryWallet::Hot => HOT_WALLET_ACCOUNT,
+            TreasuryWallet::Cold => COLD_WALLET_ACCOUNT,
+        }
+    }
+}
+
+/// Movement request body
+#[derive(Debug, Clone, Deserialize)]
+pub struct TreasuryMovementRequest {
+    pub from: TreasuryWallet,
+    pub to: TreasuryWallet,
+    pub currency: String,
+    pub amount: Decimal,
+    /// On-chain transaction that moved the funds
+    pub tx_hash: Option<String>,
+    pub note: Option<String>,
+}
+
+/// Funds moved between the hot and cold wallets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreasuryMovement {
+    pub id: Uuid,
+    pub from: TreasuryWallet,
+    pub to: TreasuryWallet,
+    pub currency: String,
+    pub amount: Decimal,
+    pub tx_hash: Option<String>,
+    pub note: Option<String>,
+    /// Admin who recorded it
+    pub recorded_by: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Bounds the hot wallet's holdings of a currency should stay within
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FloatThreshold {
+    /// Above this, deposits should be swept to the cold wallet
+    pub sweep_above: Decimal,
+    /// Below this, the hot wallet should be refilled from the cold wallet
+    #[serde(default)]
+    pub refill_below: Decimal,
+}
+
+impl FloatThreshold {
+    pub fn validate(&self) -> FlowExResult<()> {
+        if self.refill_below < Decimal::ZERO {
+            return Err(FlowExError::Validation("Refill threshold must not be negative".to_string()));
+        }
+        if self.sweep_above <= self.refill_below {
+            return Err(FlowExError::Validation(
+                "Sweep threshold must be above the refill threshold".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// What the hot wallet holding `hot` should do, if anything, and how
+    /// much to move to get back within the thresholds
+    fn check(&self, hot: Decimal) -> Option<(FloatAction, Decimal, Decimal)> {
+        if hot > self.sweep_above {
+            Some((FloatAction::Sweep, self.sweep_above, hot - self.sweep_above))
+        } else if hot < self.refill_below {
+            Some((FloatAction::Refill, self.refill_below, self.refill_below - hot))
+        } else {
+            None
+        }
+    }
+}
+
+/// What a float alert calls for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FloatAction {
+    /// Move funds from the hot wallet to the cold wallet
+    Sweep,
+    /// Move funds from the cold wallet to the hot wallet
+    Refill,
+}
+
+impl FloatAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            FloatAction::Sweep => "sweep",
+            FloatAction::Refill => "refill",
+        }
+    }
+}
+
+/// A currency whose hot wallet float is outside its thresholds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FloatAlert {
+    pub currency: String,
+    pub action: FloatAction,
+    pub hot: Decimal,
+    /// Threshold crossed
+    pub threshold: Decimal,
+    /// Least amount to move to get back within the thresholds
+    pub amount: Decimal,
+}
+
+/// What the custody wallets hold of a currency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustodyBalance {
+    pub currency: String,
+    pub hot: Decimal,
+    pub cold: Decimal,
+    pub threshold: Option<FloatThreshold>,
+}
+
+/// Custody balances with the alerts they raise
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TreasuryOverview {
+    pub balances: Vec<CustodyBalance>,
+    pub alerts: Vec<FloatAlert>,
+}
+
+/// Hot and cold wallet holdings, movements and float thresholds
+#[derive(Clone)]
+pub struct Treasury {
+    ledger: Ledger,
+    assets: AssetRegistry,
+    store: WalletStore,
+    metrics: MetricsCollector,
+    /// Float thresholds by currency
+    thresholds: Arc<RwLock<BTreeMap<String, FloatThreshold>>>,
+    /// Movements, oldest first
+    movements: Arc<RwLock<Vec<TreasuryMovement>>>,
+}
+
+impl Treasury {
+    /// Treasury with the thresholds and movements restored from `store`
+    pub fn new(
+        ledger: Ledger,
+        assets: AssetRegistry,
+        store: WalletStore,
+        thresholds: Vec<(String, FloatThreshold)>,
+        movements: Vec<TreasuryMovement>,
+    ) -> Self {
+        Self {
+            ledger,
+            assets,
+            store,
+            metrics: MetricsCollector::new(),
+            thresholds: Arc::new(RwLock::new(thresholds.into_iter().collect())),
+            movements: Arc::new(RwLock::new(movements)),
+        }
+    }
+
+    /// What the hot and cold wallets hold of each currency, with the
+    /// currencies outside their float thresholds
+    pub async fn overview(&self) -> TreasuryOverview {
+        let thresholds = self.thresholds.read().await.clone();
+        let mut balances: BTreeMap<String, CustodyBalance> = BTreeMap::new();
+        for currency in thresholds.keys() {
+            balances.insert(currency.clone(), custody_balance(currency));
+        }
+        for wallet in [TreasuryWallet::Hot, TreasuryWallet::Cold] {
+            for balance in self.ledger.balances(wallet.account()).await {
+                let held = balance.available + balance.locked;
+                let custody = balances
+                    .entry(balance.currency.clone())
+                    .or_insert_with(|| custody_balance(&balance.currency));
+                match wallet {
+                    TreasuryWallet::Hot => custody.hot = held,
+                    TreasuryWallet::Cold => custody.cold = held,
+                }
+            }
+        }
+
+        let mut alerts = Vec::new();
+        for custody in balances.values_mut() {
+            custody.threshold = thresholds.get(&custody.currency).cloned();
+            if let Some((action, threshold, amount)) = custody.threshold.as_ref().and_then(|t| t.check(custody.hot)) {
+                alerts.push(FloatAlert {
+                    currency: custody.currency.clone(),
+                    action,
+                    hot: custody.hot,
+                    threshold,
+                    amount,
+                });
+            }
+        }
+        TreasuryOverview {
+            balances: balances.into_values().collect(),
+            alerts,
+        }
+    }
+
+    /// Up to `limit` movements, newest first
+    pub async fn movements(&self, limit: usize) -> Vec<TreasuryMovement> {
+        self.movements.read().await.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Record funds `admin_id` moved between the hot and cold wallets
+    pub async fn record(&self, admin_id: Uuid, request: TreasuryMovementRequest) -> FlowExResult<TreasuryMovement> {
+        if request.from == request.to {
+            return Err(FlowExError::Validation("Cannot move funds to the same wallet".to_string()));
+        }
+        if request.note.as_ref().is_some_and(|note| note.len() > MAX_NOTE_LEN) {
+            return Err(FlowExError::Validation(format!(
+                "Note must be at most {} bytes",
+                MAX_NOTE_LEN
+            )));
+        }
+        let asset = self.assets.asset(&request.currency).await?;
+        asset.check_amount(request.amount)?;
+
+        let movement = TreasuryMovement {
+            id: Uuid::new_v4(),
+            from: request.from,
+            to: request.to,
+            currency: asset.currency,
+            amount: request.amount,
+            tx_hash: request.tx_hash,
+            note: request.note,
+            recorded_by: admin_id,
+            created_at: Utc::now(),
+        };
+        let mut movements = self.movements.write().await;
+        self.ledger.move_treasury(&movement).await?;
+        movements.push(movement.clone());
+        info!(
+            "Admin {} moved {} {} from the {:?} to the {:?} wallet",
+            admin_id, movement.amount, movement.currency, movement.from, movement.to
+        );
+        Ok(movement)
+    }
+
+    /// Set the float threshold of a registered currency
+    pub async fn set_threshold(&self, currency: &str, threshold: FloatThreshold) -> FlowExResult<FloatThreshold> {
+        threshold.validate()?;
+        let asset = self.assets.asset(currency).await?;
+        let mut thresholds = self.thresholds.write().await;
+        self.store.write_float_threshold(&asset.currency, &threshold).await?;
+        thresholds.insert(asset.currency, threshold.clone());
+        Ok(threshold)
+    }
+
+    /// Publish the hot wallet float and warn of every currency outside its
+    /// thresholds
+    pub async fn check_float(&self) -> Vec<FloatAlert> {
+        let overview = self.overview().await;
+        for custody in &overview.balances {
+            self.metrics
+                .record_hot_wallet_float(&custody.currency, custody.hot.to_f64().unwrap_or_default());
+        }
+        for alert in &overview.alerts {
+            warn!(
+                "Hot wallet holds {} {}, past its {} threshold of {}: {} {} to get back within it",
+                alert.hot,
+                alert.currency,
+                alert.action.as_str(),
+                alert.threshold,
+                alert.action.as_str(),
+                alert.amount
+            );
+            self.metrics.record_float_alert(&alert.currency, alert.action.as_str());
+        }
+        overview.alerts
+    }
+}
+
+fn custody_balance(currency: &str) -> CustodyBalance {
+    CustodyBalance {
+        currency: currency.to_string(),
+        hot: Decimal::ZERO,
+        cold: Decimal::ZERO,
+        threshold: None,
+    }
+}
+
+/// Check the hot wallet float against its thresholds every minute
+pub fn spawn_float_monitor(treasury: Treasury) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLOAT_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            treasury.check_float().await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::funding::{DepositRequest, Funding, WithdrawalOutcome, WithdrawalRequest};
+    use std::collections::HashMap;
+
+    /// 测试：充值进入热钱包，提现扣除热钱包，归集到冷钱包后记录流水，热钱包余额超出阈值时告警
+    #[tokio::test]
+    async fn test_custody_movements_and_float_alerts() {
+        let ledger = Ledger::new();
+        let assets = AssetRegistry::new();
+        let funding = Funding::new(ledger.clone(), assets.clone());
+        let treasury = Treasury::new(ledger.clone(), assets, WalletStore::Memory, Vec::new(), Vec::new());
+        let user_id = Uuid::new_v4();
+        let deposit = DepositRequest {
+            currency: "USDT".to_string(),
+            amount: Decimal::new(1000, 0),
+            tx_hash: None,
+        };
+        funding.deposit(user_id, deposit).await.unwrap();
+        let withdrawal = WithdrawalRequest {
+            currency: "USDT".to_string(),
+            amount: Decimal::new(100, 0),
+            address: "0xabc".to_string(),
+        };
+        let withdrawal = funding.withdraw(user_id, withdrawal, &HashMap::new()).await.unwrap().transaction;
+        funding
+            .finish_withdrawal(withdrawal.id, WithdrawalOutcome::Completed { tx_hash: "0x1".to_string() })
+            .await
+            .unwrap();
+
+        // 热钱包收到1000，发出提现金额扣除1手续费后的99
+        let threshold = FloatThreshold {
+            sweep_above: Decimal::new(500, 0),
+            refill_below: Decimal::new(100, 0),
+        };
+        treasury.set_threshold("usdt", threshold).await.unwrap();
+        let overview = treasury.overview().await;
+        assert_eq!((overview.balances[0].hot, overview.balances[0].cold), (Decimal::new(901, 0), Decimal::ZERO));
+        assert_eq!(overview.alerts.len(), 1);
+        assert_eq!((overview.alerts[0].action, overview.alerts[0].amount), (FloatAction::Sweep, Decimal::new(401, 0)));
+
+        let admin = Uuid::new_v4();
+        let sweep = |from, to, amount| TreasuryMovementRequest {
+            from,
+            to,
+            currency: "USDT".to_string(),
+            amount: Decimal::new(amount, 0),
+            tx_hash: Some("0x2".to_string()),
+            note: None,
+        };
+        assert!(treasury.record(admin, sweep(TreasuryWallet::Hot, TreasuryWallet::Hot, 1)).await.is_err());
+        assert!(matches!(
+            treasury.record(admin, sweep(TreasuryWallet::Cold, TreasuryWallet::Hot, 1)).await,
+            Err(FlowExError::Wallet(_))
+        ));
+        treasury.record(admin, sweep(TreasuryWallet::Hot, TreasuryWallet::Cold, 851)).await.unwrap();
+
+        let overview = treasury.overview().await;
+        assert_eq!((overview.balances[0].hot, overview.balances[0].cold), (Decimal::new(50, 0), Decimal::new(851, 0)));
+        assert_eq!((overview.alerts[0].action, overview.alerts[0].amount), (FloatAction::Refill, Decimal::new(50, 0)));
+        assert_eq!(treasury.movements(10).await[0].recorded_by, admin);
+        assert_eq!(ledger.balances(user_id).await[0].available, Decimal::new(900, 0));
+        assert!(treasury
+            .set_threshold("USDT", FloatThreshold { sweep_above: Decimal::ONE, refill_below: Decimal::TEN })
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! RS256 signing keys
+//!
+//! A `KeyRing` holds the RSA keys tokens are signed with. The newest key
+//! signs; the keys it replaced keep verifying the tokens they signed, and
+//! stay published in the ring's JWKS, until `retention` after they were
+//! replaced, which should outlast every token they could have signed.
+//! Each key is identified by the `kid` of the tokens it signs, a
+//! thumbprint of its public key, so the same key loaded on every instance
+//! gets the same id.
+//!
+//! Keys are either loaded from PEM files, shared by every instance and
+//! rotated by deploying a new file, or generated in process and rotated
+//! once the signing key is `rotation` old.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use flowex_types::{FlowExError, FlowExResult};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, Jwk, JwkSet, KeyAlgorithm, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{DecodingKey, EncodingKey};
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey};
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, RwLock};
+use tracing::info;
+
+/// Size of generated keys, in bits
+const KEY_BITS: usize = 2048;
+
+/// An RSA key of the ring
+#[derive(Clone)]
+struct SigningKey {
+    kid: String,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Jwk,
+    created_at: DateTime<Utc>,
+    /// When a newer key took over signing
+    retired_at: Option<DateTime<Utc>>,
+}
+
+impl SigningKey {
+    fn new(private_key: &RsaPrivateKey, created_at: DateTime<Utc>) -> FlowExResult<Self> {
+        let der = private_key
+            .to_pkcs1_der()
+            .map_err(|e| FlowExError::Internal(format!("Failed to encode signing key: {}", e)))?;
+        let n = private_key.n().to_bytes_be();
+        let e = private_key.e().to_bytes_be();
+        let kid = hex_prefix(&Sha256::digest(&n), 8);
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::RS256),
+                key_id: Some(kid.clone()),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(&n),
+                e: URL_SAFE_NO_PAD.encode(&e),
+            }),
+        };
+        Ok(Self {
+            kid,
+            encoding: EncodingKey::from_rsa_der(der.as_bytes()),
+            decoding: DecodingKey::from_rsa_raw_components(&n, &e),
+            jwk,
+            created_at,
+            retired_at: None,
+        })
+    }
+
+    /// Whether the key still verifies tokens at `now`
+    fn is_live(&self, now: DateTime<Utc>, retention: Duration) -> bool {
+        self.retired_at.is_none_or(|retired_at| now - retired_at < retention)
+    }
+}
+
+/// RSA keys tokens are signed and verified with, the newest signing
+#[derive(Clone)]
+pub struct KeyRing {
+    /// Oldest first
+    keys: Arc<RwLock<Vec<SigningKey>>>,
+    /// Age at which the signing key is replaced; never for keys from files
+    rotation: Option<Duration>,
+    retention: Duration,
+}
+
+impl KeyRing {
+    /// A ring starting with a newly generated key
+    pub fn generate(rotation: Duration, retention: Duration) -> FlowExResult<Self> {
+        let ring = Self {
+            keys: Arc::new(RwLock::new(Vec::new())),
+            rotation: Some(rotation),
+            retention,
+        };
+        ring.rotate()?;
+        Ok(ring)
+    }
+
+    /// A ring of PKCS#1 or PKCS#8 PEM private keys, the last signing
+    pub fn from_pems(pems: &[String], retention: Duration) -> FlowExResult<Self> {
+        if pems.is_empty() {
+            return Err(FlowExError::Validation("A key ring needs at least one key".to_string()));
+        }
+        let now = Utc::now();
+        let mut keys = pems
+            .iter()
+            .map(|pem| {
+                let private_key = RsaPrivateKey::from_pkcs1_pem(pem)
+                    .or_else(|_| RsaPrivateKey::from_pkcs8_pem(pem))
+                    .map_err(|e| FlowExError::Validation(format!("Invalid RSA private key: {}", e)))?;
+                SigningKey::new(&private_key, now)
+            })
+            .collect::<FlowExResult<Vec<_>>>()?;
+        let signing = keys.len() - 1;
+        for key in &mut keys[..signing] {
+            key.retired_at = Some(now);
+        }
+        Ok(Self {
+            keys: Arc::new(RwLock::new(keys)),
+            rotation: None,
+            retention,
+        })
+    }
+
+    /// Generate a new signing key, retiring the current one; its id
+    pub fn rotate(&self) -> FlowExResult<String> {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)
+            .map_err(|e| FlowExError::Internal(format!("Failed to generate signing key: {}", e)))?;
+        let now = Utc::now();
+        let key = SigningKey::new(&private_key, now)?;
+        let kid = key.kid.clone();
+
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(current) = keys.last_mut() {
+            current.retired_at = Some(now);
+        }
+        keys.retain(|key| key.is_live(now, self.retention));
+        keys.push(key);
+        info!("Rotated token signing key to {}", kid);
+        Ok(kid)
+    }
+
+    /// Rotate if the signing key is due; the new key's id if it was
+    pub fn rotate_if_due(&self) -> FlowExResult<Option<String>> {
+        let Some(rotation) = self.rotation else {
+            return Ok(None);
+        };
+        let due = self
+            .keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .last()
+            .is_none_or(|key| Utc::now() - key.created_at >= rotation);
+        if !due {
+            return Ok(None);
+        }
+        self.rotate().map(Some)
+    }
+
+    /// Id and key of the signing key
+    pub(crate) fn signing_key(&self) -> FlowExResult<(String, EncodingKey)> {
+        self.keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .last()
+            .map(|key| (key.kid.clone(), key.encoding.clone()))
+            .ok_or_else(|| FlowExError::Internal("No signing key".to_string()))
+    }
+
+    /// Key verifying the tokens `kid` signed, unless it is unknown or has
+    /// been retired for longer than the ring keeps keys
+    pub(crate) fn decoding_key(&self, kid: &str) -> Option<DecodingKey> {
+        let now = Utc::now();
+        self.keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|key| key.is_live(now, self.retention))
+            .find(|key| key.kid == kid)
+            .map(|key| key.decoding.clone())
+    }
+
+    /// Public keys still verifying tokens, as a JWK set
+    pub fn jwks(&self) -> JwkSet {
+        let now = Utc::now();
+        JwkSet {
+            keys: self
+                .keys
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .rev()
+                .filter(|key| key.is_live(now, self.retention))
+                .map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+fn hex_prefix(bytes: &[u8], len: usize) -> String {
+    bytes.iter().take(len).map(|byte| format!("{:02x}", byte)).collect()
+}
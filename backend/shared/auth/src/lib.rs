@@ -2,21 +2,45 @@
 //!
 //! Enterprise-grade authentication utilities including JWT token management,
 //! password hashing, session management, and security features.
+//!
+//! Tokens are signed either with a shared HMAC secret (HS256) or with the
+//! RSA keys of a `keys::KeyRing` (RS256), which services verify against
+//! the ring's published JWKS without holding any secret. The permissions
+//! signed into a token are those of its roles, built-in or defined in a
+//! `roles::RoleDirectory`; the account's status, KYC tier and token
+//! version are signed in beside them, and tokens of frozen accounts do not
+//! validate.
+
+pub mod keys;
+pub mod roles;
 
 use bcrypt::{hash, verify, DEFAULT_COST};
 use chrono::{Duration, Utc};
-use flowex_types::{JwtClaims, User, Role, Permission, FlowExError, FlowExResult};
-use jsonwebtoken::{encode, decode, EncodingKey, DecodingKey, Header, Validation, Algorithm};
+use flowex_types::{AccountClaims, AccountStatus, JwtClaims, User, FlowExError, FlowExResult};
+use jsonwebtoken::{encode, decode, decode_header, EncodingKey, DecodingKey, Header, Validation, Algorithm};
+use keys::KeyRing;
+use roles::RoleDirectory;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use tracing::{info, warn, error, debug};
+use tracing::{warn, debug};
 use uuid::Uuid;
 
+/// What tokens are signed with
+#[derive(Clone)]
+enum Signer {
+    /// HS256 with a shared secret
+    Secret {
+        encoding_key: EncodingKey,
+        decoding_key: DecodingKey,
+    },
+    /// RS256 with the keys of a ring
+    Keys(KeyRing),
+}
+
 /// JWT token manager for FlowEx authentication
 #[derive(Clone)]
 pub struct JwtManager {
-    encoding_key: EncodingKey,
-    decoding_key: DecodingKey,
+    signer: Signer,
+    roles: RoleDirectory,
     issuer: String,
     audience: String,
     expiration_hours: i64,
@@ -36,8 +60,29 @@ impl JwtManager {
         let decoding_key = DecodingKey::from_secret(secret.as_ref());
 
         Self {
-            encoding_key,
-            decoding_key,
+            signer: Signer::Secret {
+                encoding_key,
+                decoding_key,
+            },
+            roles: RoleDirectory::new(),
+            issuer,
+            audience,
+            expiration_hours,
+            refresh_expiration_days,
+        }
+    }
+
+    /// Create a JWT manager signing with the keys of `keys`
+    pub fn with_keys(
+        keys: KeyRing,
+        issuer: String,
+        audience: String,
+        expiration_hours: i64,
+        refresh_expiration_days: i64,
+    ) -> Self {
+        Self {
+            signer: Signer::Keys(keys),
+            roles: RoleDirectory::new(),
             issuer,
             audience,
             expiration_hours,
@@ -45,8 +90,19 @@ impl JwtManager {
         }
     }
 
+    /// Resolve the permissions of custom roles from `roles`
+    pub fn with_roles(mut self, roles: RoleDirectory) -> Self {
+        self.roles = roles;
+        self
+    }
+
     /// Generate JWT token for user
     pub fn generate_token(&self, user: &User, roles: Vec<String>) -> FlowExResult<String> {
+        self.generate_account_token(user, roles, AccountClaims::default())
+    }
+
+    /// Generate JWT token for user, carrying the state of their account
+    pub fn generate_account_token(&self, user: &User, roles: Vec<String>, account: AccountClaims) -> FlowExResult<String> {
         let now = Utc::now();
         let exp = now + Duration::hours(self.expiration_hours);
         
@@ -56,65 +112,70 @@ impl JwtManager {
         let claims = JwtClaims {
             sub: user.id.to_string(),
             email: user.email.clone(),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
             exp: exp.timestamp() as usize,
             iat: now.timestamp() as usize,
             jti: Uuid::new_v4().to_string(),
             roles,
             permissions,
+            account,
         };
 
-        let header = Header::new(Algorithm::HS256);
-        
-        encode(&header, &claims, &self.encoding_key)
+        self.sign(&claims)
             .map_err(|e| FlowExError::Authentication(format!("Failed to generate token: {}", e)))
     }
 
-    /// Generate refresh token
-    pub fn generate_refresh_token(&self, user: &User) -> FlowExResult<String> {
+    /// Generate refresh token for the session `session_id`
+    pub fn generate_refresh_token(&self, user: &User, session_id: &str) -> FlowExResult<String> {
         let now = Utc::now();
         let exp = now + Duration::days(self.refresh_expiration_days);
 
         let claims = RefreshTokenClaims {
             sub: user.id.to_string(),
             email: user.email.clone(),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
             exp: exp.timestamp() as usize,
             iat: now.timestamp() as usize,
             jti: Uuid::new_v4().to_string(),
             token_type: "refresh".to_string(),
+            sid: session_id.to_string(),
         };
 
-        let header = Header::new(Algorithm::HS256);
-        
-        encode(&header, &claims, &self.encoding_key)
+        self.sign(&claims)
             .map_err(|e| FlowExError::Authentication(format!("Failed to generate refresh token: {}", e)))
     }
 
     /// Validate and decode JWT token
     pub fn validate_token(&self, token: &str) -> FlowExResult<JwtClaims> {
-        let mut validation = Validation::new(Algorithm::HS256);
+        let (mut validation, decoding_key) = self.verifier(token)?;
         validation.set_issuer(&[&self.issuer]);
         validation.set_audience(&[&self.audience]);
         validation.validate_exp = true;
         validation.validate_nbf = true;
         validation.leeway = 60; // 60 seconds leeway for clock skew
 
-        let token_data = decode::<JwtClaims>(token, &self.decoding_key, &validation)
+        let token_data = decode::<JwtClaims>(token, &decoding_key, &validation)
             .map_err(|e| {
                 warn!("JWT validation failed: {}", e);
                 FlowExError::Authentication("Invalid or expired token".to_string())
             })?;
+        if token_data.claims.account.account_status == AccountStatus::Frozen {
+            return Err(FlowExError::Authorization("Account is frozen".to_string()));
+        }
 
         Ok(token_data.claims)
     }
 
     /// Validate refresh token
     pub fn validate_refresh_token(&self, token: &str) -> FlowExResult<RefreshTokenClaims> {
-        let mut validation = Validation::new(Algorithm::HS256);
+        let (mut validation, decoding_key) = self.verifier(token)?;
         validation.set_issuer(&[&self.issuer]);
         validation.set_audience(&[&self.audience]);
         validation.validate_exp = true;
 
-        let token_data = decode::<RefreshTokenClaims>(token, &self.decoding_key, &validation)
+        let token_data = decode::<RefreshTokenClaims>(token, &decoding_key, &validation)
             .map_err(|e| {
                 warn!("Refresh token validation failed: {}", e);
                 FlowExError::Authentication("Invalid or expired refresh token".to_string())
@@ -123,19 +184,42 @@ impl JwtManager {
         Ok(token_data.claims)
     }
 
-    /// Get permissions for roles
-    fn get_permissions_for_roles(&self, roles: &[String]) -> Vec<String> {
-        let mut permissions = HashSet::new();
+    /// Sign `claims` with the current key
+    fn sign<T: Serialize>(&self, claims: &T) -> jsonwebtoken::errors::Result<String> {
+        match &self.signer {
+            Signer::Secret { encoding_key, .. } => encode(&Header::new(Algorithm::HS256), claims, encoding_key),
+            Signer::Keys(keys) => {
+                let (kid, encoding_key) = keys
+                    .signing_key()
+                    .map_err(|_| jsonwebtoken::errors::ErrorKind::InvalidRsaKey("no signing key".to_string()))?;
+                let mut header = Header::new(Algorithm::RS256);
+                header.kid = Some(kid);
+                encode(&header, claims, &encoding_key)
+            }
+        }
+    }
 
-        for role_str in roles {
-            if let Ok(role) = role_str.parse::<Role>() {
-                for permission in role.permissions() {
-                    permissions.insert(permission.as_str().to_string());
-                }
+    /// Validation and key for `token`, by the key id in its header
+    fn verifier(&self, token: &str) -> FlowExResult<(Validation, DecodingKey)> {
+        match &self.signer {
+            Signer::Secret { decoding_key, .. } => Ok((Validation::new(Algorithm::HS256), decoding_key.clone())),
+            Signer::Keys(keys) => {
+                let kid = decode_header(token)
+                    .ok()
+                    .and_then(|header| header.kid)
+                    .ok_or_else(|| FlowExError::Authentication("Invalid or expired token".to_string()))?;
+                let decoding_key = keys.decoding_key(&kid).ok_or_else(|| {
+                    warn!("Token signed with unknown key {}", kid);
+                    FlowExError::Authentication("Invalid or expired token".to_string())
+                })?;
+                Ok((Validation::new(Algorithm::RS256), decoding_key))
             }
         }
+    }
 
-        permissions.into_iter().collect()
+    /// Get permissions for roles, built-in or custom
+    pub fn get_permissions_for_roles(&self, roles: &[String]) -> Vec<String> {
+        self.roles.permissions_for_roles(roles)
     }
 }
 
@@ -144,10 +228,13 @@ impl JwtManager {
 pub struct RefreshTokenClaims {
     pub sub: String,        // Subject (user ID)
     pub email: String,      // User email
+    pub iss: String,        // Issuer
+    pub aud: String,        // Audience
     pub exp: usize,         // Expiration time
     pub iat: usize,         // Issued at
     pub jti: String,        // JWT ID
     pub token_type: String, // Token type
+    pub sid: String,        // Session the token renews
 }
 
 /// Password manager for secure password operations
@@ -284,26 +371,10 @@ pub struct SessionData {
     pub last_accessed: chrono::DateTime<Utc>,
 }
 
-/// Role parsing implementation
-impl std::str::FromStr for Role {
-    type Err = FlowExError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "user" => Ok(Role::User),
-            "trader" => Ok(Role::Trader),
-            "vip_trader" => Ok(Role::VipTrader),
-            "admin" => Ok(Role::Admin),
-            "super_admin" => Ok(Role::SuperAdmin),
-            "system" => Ok(Role::System),
-            _ => Err(FlowExError::Validation(format!("Invalid role: {}", s))),
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use flowex_types::Permission;
 
     #[test]
     fn test_password_hashing() {
@@ -355,5 +426,102 @@ mod tests {
         let claims = jwt_manager.validate_token(&token).unwrap();
         assert_eq!(claims.email, user.email);
         assert_eq!(claims.sub, user.id.to_string());
+        assert_eq!(claims.account, AccountClaims::default());
+    }
+
+    /// 测试：令牌携带账户状态、KYC 级别和令牌版本，冻结账户的令牌验证失败
+    #[test]
+    fn test_account_claims() {
+        let jwt_manager = JwtManager::new("test_secret", "flowex".to_string(), "flowex-users".to_string(), 1, 30);
+        let user = User {
+            id: Uuid::new_v4(),
+            email: "claims@example.com".to_string(),
+            first_name: "Claims".to_string(),
+            last_name: "User".to_string(),
+            is_verified: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let account = AccountClaims {
+            account_status: AccountStatus::Active,
+            kyc_tier: Some("L1".to_string()),
+            token_version: 3,
+        };
+
+        let token = jwt_manager.generate_account_token(&user, vec!["trader".to_string()], account.clone()).unwrap();
+        assert_eq!(jwt_manager.validate_token(&token).unwrap().account, account);
+
+        let frozen = AccountClaims {
+            account_status: AccountStatus::Frozen,
+            ..account
+        };
+        let token = jwt_manager.generate_account_token(&user, vec!["trader".to_string()], frozen).unwrap();
+        assert!(matches!(jwt_manager.validate_token(&token), Err(FlowExError::Authorization(_))));
+    }
+
+    /// 测试：RS256 签名的令牌在密钥轮换后仍可验证，JWKS 发布所有有效公钥，其他密钥签名的令牌被拒绝
+    #[test]
+    fn test_rs256_key_rotation() {
+        let keys = KeyRing::generate(Duration::days(7), Duration::days(30)).unwrap();
+        let jwt_manager = JwtManager::with_keys(keys.clone(), "flowex".to_string(), "flowex-users".to_string(), 1, 30);
+        let user = User {
+            id: Uuid::new_v4(),
+            email: "rotate@example.com".to_string(),
+            first_name: "Rotate".to_string(),
+            last_name: "User".to_string(),
+            is_verified: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        let token = jwt_manager.generate_token(&user, vec!["trader".to_string()]).unwrap();
+        let first_kid = decode_header(&token).unwrap().kid.unwrap();
+        assert_eq!(keys.jwks().keys.len(), 1);
+        assert!(keys.jwks().find(&first_kid).is_some());
+        assert!(keys.rotate_if_due().unwrap().is_none());
+
+        let second_kid = keys.rotate().unwrap();
+        assert_ne!(second_kid, first_kid);
+        assert_eq!(jwt_manager.validate_token(&token).unwrap().sub, user.id.to_string());
+        let rotated = jwt_manager.generate_refresh_token(&user, "session").unwrap();
+        assert_eq!(decode_header(&rotated).unwrap().kid, Some(second_kid.clone()));
+        assert_eq!(jwt_manager.validate_refresh_token(&rotated).unwrap().sid, "session");
+        assert_eq!(keys.jwks().keys[0].common.key_id, Some(second_kid));
+        assert_eq!(keys.jwks().keys.len(), 2);
+
+        let secret_manager = JwtManager::new("test_secret", "flowex".to_string(), "flowex-users".to_string(), 1, 30);
+        let hs256 = secret_manager.generate_token(&user, vec!["trader".to_string()]).unwrap();
+        assert!(jwt_manager.validate_token(&hs256).is_err());
+        assert!(secret_manager.validate_token(&token).is_err());
+    }
+
+    /// 测试：令牌权限由内置角色和自定义角色共同决定，删除自定义角色后不再授予其权限
+    #[test]
+    fn test_custom_role_permissions() {
+        let roles = RoleDirectory::new();
+        let jwt_manager = JwtManager::new("test_secret", "flowex".to_string(), "flowex-users".to_string(), 1, 30)
+            .with_roles(roles.clone());
+        let assigned = vec!["user".to_string(), "market_maker".to_string()];
+        assert_eq!(jwt_manager.get_permissions_for_roles(&assigned), vec!["user:read", "wallet:read"]);
+
+        roles.define("market_maker", vec![Permission::TradingWrite, Permission::WalletRead]);
+        assert!(roles.exists("market_maker") && roles.exists("admin") && !roles.exists("auditor"));
+        let user = User {
+            id: Uuid::new_v4(),
+            email: "maker@example.com".to_string(),
+            first_name: "Market".to_string(),
+            last_name: "Maker".to_string(),
+            is_verified: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let token = jwt_manager.generate_token(&user, assigned.clone()).unwrap();
+        assert_eq!(
+            jwt_manager.validate_token(&token).unwrap().permissions,
+            vec!["trading:write", "user:read", "wallet:read"]
+        );
+
+        roles.remove("market_maker");
+        assert_eq!(jwt_manager.get_permissions_for_roles(&assigned), vec!["user:read", "wallet:read"]);
     }
 }
@@ -0,0 +1,57 @@
+//! Custom roles
+//!
+//! Besides the built-in `Role`s, administrators define roles of their own,
+//! each granting a set of permissions. A `RoleDirectory` holds those
+//! definitions for `JwtManager` to resolve the permissions of the roles it
+//! signs into tokens; whoever manages the roles keeps it current. Built-in
+//! role names cannot be redefined.
+
+use flowex_types::{Permission, Role};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+
+/// Permissions of every custom role, by role name
+#[derive(Clone, Default)]
+pub struct RoleDirectory {
+    roles: Arc<RwLock<HashMap<String, Vec<Permission>>>>,
+}
+
+impl RoleDirectory {
+    /// A directory without custom roles
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define or redefine the custom role `name`
+    pub fn define(&self, name: &str, permissions: Vec<Permission>) {
+        self.roles
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.to_string(), permissions);
+    }
+
+    /// Forget the custom role `name`
+    pub fn remove(&self, name: &str) {
+        self.roles.write().unwrap_or_else(|e| e.into_inner()).remove(name);
+    }
+
+    /// Whether `name` is a built-in or custom role
+    pub fn exists(&self, name: &str) -> bool {
+        name.parse::<Role>().is_ok() || self.roles.read().unwrap_or_else(|e| e.into_inner()).contains_key(name)
+    }
+
+    /// Permissions granted by any of `roles`, sorted; unknown roles grant
+    /// none
+    pub fn permissions_for_roles(&self, roles: &[String]) -> Vec<String> {
+        let custom = self.roles.read().unwrap_or_else(|e| e.into_inner());
+        let mut permissions = BTreeSet::new();
+        for role in roles {
+            let granted = match role.parse::<Role>() {
+                Ok(role) => role.permissions(),
+                Err(_) => custom.get(role).cloned().unwrap_or_default(),
+            };
+            permissions.extend(granted.iter().map(|permission| permission.as_str().to_string()));
+        }
+        permissions.into_iter().collect()
+    }
+}
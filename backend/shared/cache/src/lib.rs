@@ -1,12 +1,15 @@
 //! FlowEx Cache Library
 //!
 //! Enterprise-grade Redis caching and session management for FlowEx services.
-//! Provides distributed caching, session storage, and rate limiting capabilities.
+//! Provides distributed caching, session storage, and rate limiting capabilities,
+//! plus pub/sub channels for fanning events out across service instances.
 
 use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
 use redis::{AsyncCommands, Client, RedisResult};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
+use tokio::sync::mpsc;
 use tracing::{info, error, debug, warn};
 use uuid::Uuid;
 
@@ -69,13 +72,40 @@ impl CacheManager {
         let mut conn = self.connection_pool.clone();
         let ttl_seconds = ttl.unwrap_or(self.default_ttl).as_secs();
         
-        conn.set_ex(key, serialized, ttl_seconds).await
+        let _: () = conn.set_ex(key, serialized, ttl_seconds).await
             .map_err(|e| CacheError::Redis(e))?;
         
         debug!("📝 Cached value for key: {} (TTL: {}s)", key, ttl_seconds);
         Ok(())
     }
     
+    /// Set a value with TTL only if the key does not exist yet. Returns
+    /// whether it was set.
+    pub async fn set_if_absent<T>(&self, key: &str, value: &T, ttl: Option<Duration>) -> Result<bool, CacheError>
+    where
+        T: Serialize,
+    {
+        let serialized = serde_json::to_string(value)
+            .map_err(|e| CacheError::Serialization(e.to_string()))?;
+
+        let mut conn = self.connection_pool.clone();
+        let ttl_seconds = ttl.unwrap_or(self.default_ttl).as_secs();
+
+        let reply: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(serialized)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl_seconds)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| CacheError::Redis(e))?;
+
+        let set = reply.is_some();
+        debug!("📝 Set-if-absent for key: {} = {} (TTL: {}s)", key, set, ttl_seconds);
+        Ok(set)
+    }
+
     /// Get a value from cache
     pub async fn get<T>(&self, key: &str) -> Result<Option<T>, CacheError>
     where
@@ -112,6 +142,46 @@ impl CacheManager {
         Ok(deleted)
     }
 
+    /// Add a member to the set at `key`, keeping the set for at least `ttl`
+    pub async fn add_to_set(&self, key: &str, member: &str, ttl: Option<Duration>) -> Result<(), CacheError> {
+        let mut conn = self.connection_pool.clone();
+        let ttl_seconds = ttl.unwrap_or(self.default_ttl).as_secs();
+
+        let _: () = redis::pipe()
+            .sadd(key, member)
+            .ignore()
+            .expire(key, ttl_seconds as i64)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| CacheError::Redis(e))?;
+
+        debug!("📝 Added {} to set: {} (TTL: {}s)", member, key, ttl_seconds);
+        Ok(())
+    }
+
+    /// Remove a member from the set at `key`
+    pub async fn remove_from_set(&self, key: &str, member: &str) -> Result<bool, CacheError> {
+        let mut conn = self.connection_pool.clone();
+
+        let result: i32 = conn.srem(key, member).await
+            .map_err(|e| CacheError::Redis(e))?;
+
+        debug!("🗑️ Removed {} from set: {} (existed: {})", member, key, result > 0);
+        Ok(result > 0)
+    }
+
+    /// Members of the set at `key`
+    pub async fn set_members(&self, key: &str) -> Result<Vec<String>, CacheError> {
+        let mut conn = self.connection_pool.clone();
+
+        let members: Vec<String> = conn.smembers(key).await
+            .map_err(|e| CacheError::Redis(e))?;
+
+        debug!("🔍 Set {} has {} members", key, members.len());
+        Ok(members)
+    }
+
     /// Check if a key exists in cache
     pub async fn exists(&self, key: &str) -> Result<bool, CacheError> {
         let mut conn = self.connection_pool.clone();
@@ -127,7 +197,7 @@ impl CacheManager {
     pub async fn expire(&self, key: &str, ttl: Duration) -> Result<bool, CacheError> {
         let mut conn = self.connection_pool.clone();
 
-        let result: bool = conn.expire(key, ttl.as_secs() as usize).await
+        let result: bool = conn.expire(key, ttl.as_secs() as i64).await
             .map_err(|e| CacheError::Redis(e))?;
 
         debug!("⏰ Set expiration for key: {} ({}s)", key, ttl.as_secs());
@@ -186,85 +256,57 @@ impl CacheManager {
         debug!("📖 Retrieved multiple keys");
         Ok(results)
     }
-    
-    /// Delete a key from cache
-    pub async fn delete(&self, key: &str) -> Result<bool, CacheError> {
-        let mut conn = self.connection_pool.clone();
-        
-        let deleted: i32 = conn.del(key).await
-            .map_err(|e| CacheError::Redis(e))?;
-        
-        let was_deleted = deleted > 0;
-        debug!("🗑️  Deleted key: {} (existed: {})", key, was_deleted);
-        Ok(was_deleted)
-    }
-    
-    /// Check if key exists
-    pub async fn exists(&self, key: &str) -> Result<bool, CacheError> {
-        let mut conn = self.connection_pool.clone();
-        
-        let exists: bool = conn.exists(key).await
-            .map_err(|e| CacheError::Redis(e))?;
-        
-        Ok(exists)
-    }
-    
-    /// Set TTL for existing key
-    pub async fn expire(&self, key: &str, ttl: Duration) -> Result<bool, CacheError> {
-        let mut conn = self.connection_pool.clone();
-        
-        let result: bool = conn.expire(key, ttl.as_secs() as i64).await
-            .map_err(|e| CacheError::Redis(e))?;
-        
-        Ok(result)
-    }
-    
-    /// Increment a counter
-    pub async fn increment(&self, key: &str, delta: i64) -> Result<i64, CacheError> {
+
+    /// Publish a message on a pub/sub channel. Returns the number of
+    /// subscribers that received it.
+    pub async fn publish(&self, channel: &str, message: &str) -> Result<u32, CacheError> {
         let mut conn = self.connection_pool.clone();
-        
-        let result: i64 = conn.incr(key, delta).await
+
+        let receivers: u32 = conn.publish(channel, message).await
             .map_err(|e| CacheError::Redis(e))?;
-        
-        debug!("📈 Incremented counter {}: {} (delta: {})", key, result, delta);
-        Ok(result)
+
+        debug!("📣 Published to channel: {} ({} receivers)", channel, receivers);
+        Ok(receivers)
     }
-    
-    /// Get multiple keys at once
-    pub async fn get_multiple<T>(&self, keys: &[String]) -> Result<Vec<Option<T>>, CacheError>
-    where
-        T: for<'de> Deserialize<'de>,
-    {
-        if keys.is_empty() {
-            return Ok(vec![]);
-        }
-        
-        let mut conn = self.connection_pool.clone();
-        
-        let results: Vec<Option<String>> = conn.get(keys).await
+
+    /// Subscribe to a pub/sub channel on a dedicated connection. Messages
+    /// arrive on the returned receiver, which closes when the connection
+    /// drops; `capacity` messages are buffered for a slow reader.
+    pub async fn subscribe(&self, channel: &str, capacity: usize) -> Result<mpsc::Receiver<String>, CacheError> {
+        let mut pubsub = self.client.get_async_connection().await
+            .map_err(|e| CacheError::Redis(e))?
+            .into_pubsub();
+        pubsub.subscribe(channel).await
             .map_err(|e| CacheError::Redis(e))?;
-        
-        let mut values = Vec::new();
-        for (i, result) in results.into_iter().enumerate() {
-            match result {
-                Some(serialized) => {
-                    let value = serde_json::from_str(&serialized)
-                        .map_err(|e| CacheError::Deserialization(e.to_string()))?;
-                    values.push(Some(value));
-                    debug!("📖 Cache hit for key: {}", keys[i]);
-                }
-                None => {
-                    values.push(None);
-                    debug!("📭 Cache miss for key: {}", keys[i]);
+        info!("📡 Subscribed to channel: {}", channel);
+
+        let (tx, rx) = mpsc::channel(capacity);
+        let channel = channel.to_string();
+        tokio::spawn(async move {
+            let mut messages = pubsub.into_on_message();
+            while let Some(message) = messages.next().await {
+                match message.get_payload::<String>() {
+                    Ok(payload) => {
+                        if tx.send(payload).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("Dropping undecodable message on {}: {}", channel, e),
                 }
             }
-        }
-        
-        Ok(values)
+            warn!("📡 Subscription to channel {} ended", channel);
+        });
+
+        Ok(rx)
     }
 }
 
 /// Session manager for user sessions
+///
+/// Each session is kept under `session:{id}` and indexed under
+/// `session:user:{user_id}`, so a user's sessions can be listed and revoked
+/// together.
+#[derive(Clone)]
 pub struct SessionManager {
     cache: CacheManager,
     session_ttl: Duration,
@@ -293,6 +335,7 @@ impl SessionManager {
         };
         
         self.cache.set(&session_key, &session, Some(self.session_ttl)).await?;
+        self.cache.add_to_set(&user_sessions_key(user_id), &session_id, Some(self.session_ttl)).await?;
         
         info!("🔐 Created session for user: {} (session: {})", user_id, session_id);
         Ok(session_id)
@@ -315,9 +358,28 @@ impl SessionManager {
         }
     }
     
+    /// Sessions of a user still alive, without touching them
+    pub async fn list_user_sessions(&self, user_id: Uuid) -> Result<Vec<UserSession>, CacheError> {
+        let index_key = user_sessions_key(user_id);
+        let mut sessions = Vec::new();
+        for session_id in self.cache.set_members(&index_key).await? {
+            match self.cache.get::<UserSession>(&format!("session:{}", session_id)).await? {
+                Some(session) => sessions.push(session),
+                // Expired since it was indexed
+                None => {
+                    self.cache.remove_from_set(&index_key, &session_id).await?;
+                }
+            }
+        }
+        Ok(sessions)
+    }
+    
     /// Delete session
     pub async fn delete_session(&self, session_id: &str) -> Result<bool, CacheError> {
         let session_key = format!("session:{}", session_id);
+        if let Some(session) = self.cache.get::<UserSession>(&session_key).await? {
+            self.cache.remove_from_set(&user_sessions_key(session.user_id), session_id).await?;
+        }
         let deleted = self.cache.delete(&session_key).await?;
         
         if deleted {
@@ -329,13 +391,25 @@ impl SessionManager {
     
     /// Delete all sessions for a user
     pub async fn delete_user_sessions(&self, user_id: Uuid) -> Result<u32, CacheError> {
-        // In a production system, you would maintain a user->sessions mapping
-        // For now, this is a placeholder
-        warn!("🚧 delete_user_sessions not fully implemented for user: {}", user_id);
-        Ok(0)
+        let index_key = user_sessions_key(user_id);
+        let mut deleted = 0;
+        for session_id in self.cache.set_members(&index_key).await? {
+            if self.cache.delete(&format!("session:{}", session_id)).await? {
+                deleted += 1;
+            }
+        }
+        self.cache.delete(&index_key).await?;
+        
+        info!("🗑️  Deleted {} sessions of user: {}", deleted, user_id);
+        Ok(deleted)
     }
 }
 
+/// Key of the set indexing a user's sessions
+fn user_sessions_key(user_id: Uuid) -> String {
+    format!("session:user:{}", user_id)
+}
+
 /// Cache health information
 #[derive(Debug, Clone)]
 pub struct CacheHealth {
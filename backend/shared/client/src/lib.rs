@@ -0,0 +1,301 @@
+//! FlowEx API client
+//!
+//! `FlowExClient` wraps the REST API in typed async methods and opens
+//! WebSocket streams that reconnect on their own (see `stream`). Requests
+//! go to one base URL, normally the API gateway's; the market data stream
+//! defaults to `/ws` and the user stream to `/api/trading/ws` on the same
+//! host, and either can be pointed elsewhere. Requests and stream
+//! handshakes carry the client's JWT as a bearer token.
+//!
+//! ```no_run
+//! # async fn example() -> flowex_client::ClientResult<()> {
+//! use flowex_client::FlowExClient;
+//! use futures_util::StreamExt;
+//!
+//! let client = FlowExClient::new("http://localhost:8000").with_token("...");
+//! let mut trades = client.subscribe_trades("BTCUSDT");
+//! while let Some(trade) = trades.next().await {
+//!     println!("{} {} @ {}", trade.symbol, trade.quantity, trade.price);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod stream;
+
+pub use stream::WsStream;
+
+use flowex_matching_engine::ExecutionReport;
+use flowex_types::{
+    ApiResponse, CancelOrdersFilter, CreateOrderRequest, Fill, ModifyOrderRequest, Order, OrderBook,
+    OrderHistoryQuery, Page, Ticker, Trade, TradeHistoryQuery, TradingPair,
+};
+use flowex_websocket::subscription::PRIVATE_CHANNELS;
+use flowex_websocket::WsMessage;
+use futures_util::{future, Stream, StreamExt};
+use reqwest::{Method, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Errors returned by the client
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("API error ({status}): {message}")]
+    Api { status: u16, message: String },
+}
+
+/// Result type alias for client operations
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// Client for the FlowEx REST and WebSocket APIs
+#[derive(Debug, Clone)]
+pub struct FlowExClient {
+    http: reqwest::Client,
+    base_url: String,
+    market_stream_url: String,
+    user_stream_url: String,
+    token: Option<String>,
+}
+
+impl FlowExClient {
+    /// Client for the API at `base_url`, e.g. `https://api.flowex.com`
+    pub fn new(base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let ws_base = if let Some(host) = base_url.strip_prefix("https://") {
+            format!("wss://{}", host)
+        } else if let Some(host) = base_url.strip_prefix("http://") {
+            format!("ws://{}", host)
+        } else {
+            base_url.clone()
+        };
+        Self {
+            http: reqwest::Client::new(),
+            market_stream_url: format!("{}/ws", ws_base),
+            user_stream_url: format!("{}/api/trading/ws", ws_base),
+            base_url,
+            token: None,
+        }
+    }
+
+    /// Authenticate requests with a bearer token
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Connect market data streams to `url` instead of `/ws`
+    pub fn with_market_stream_url(mut self, url: impl Into<String>) -> Self {
+        self.market_stream_url = url.into();
+        self
+    }
+
+    /// Connect user streams to `url` instead of `/api/trading/ws`
+    pub fn with_user_stream_url(mut self, url: impl Into<String>) -> Self {
+        self.user_stream_url = url.into();
+        self
+    }
+
+    /// Trading pairs listed on the exchange
+    pub async fn trading_pairs(&self) -> ClientResult<Vec<TradingPair>> {
+        self.send(self.request(Method::GET, "/api/trading/pairs")).await
+    }
+
+    /// Current order book of `symbol`
+    pub async fn order_book(&self, symbol: &str) -> ClientResult<OrderBook> {
+        self.send(self.request(Method::GET, &format!("/api/trading/orderbook/{}", symbol))).await
+    }
+
+    /// Tickers of every symbol
+    pub async fn tickers(&self) -> ClientResult<Vec<Ticker>> {
+        self.send(self.request(Method::GET, "/api/market-data/tickers")).await
+    }
+
+    /// Ticker of `symbol`
+    pub async fn ticker(&self, symbol: &str) -> ClientResult<Ticker> {
+        self.send(self.request(Method::GET, &format!("/api/market-data/ticker/{}", symbol))).await
+    }
+
+    /// Recent trades on `symbol`
+    pub async fn trades(&self, symbol: &str) -> ClientResult<Vec<Trade>> {
+        self.send(self.request(Method::GET, &format!("/api/market-data/trades/{}", symbol))).await
+    }
+
+    /// Place an order, returning its state after matching and its trades
+    pub async fn place_order(&self, order: &CreateOrderRequest) -> ClientResult<ExecutionReport> {
+        self.send(self.request(Method::POST, "/api/trading/orders").json(order)).await
+    }
+
+    /// A page of the caller's order history; pass the page's `next_cursor`
+    /// back in `query.cursor` for the next one
+    pub async fn orders(&self, query: &OrderHistoryQuery) -> ClientResult<Page<Order>> {
+        self.send(self.request(Method::GET, "/api/trading/orders").query(query)).await
+    }
+
+    /// Most recent public trades executed on `symbol`, newest first
+    pub async fn recent_trades(&self, symbol: &str) -> ClientResult<Vec<Trade>> {
+        self.send(self.request(Method::GET, &format!("/api/trading/trades/{}", symbol))).await
+    }
+
+    /// A page of the caller's fills, with fees and maker/taker role
+    pub async fn my_trades(&self, query: &TradeHistoryQuery) -> ClientResult<Page<Fill>> {
+        self.send(self.request(Method::GET, "/api/trading/mytrades").query(query)).await
+    }
+
+    /// Change the price or quantity of an open order
+    pub async fn modify_order(&self, order_id: Uuid, changes: &ModifyOrderRequest) -> ClientResult<Order> {
+        self.send(self.request(Method::PUT, &format!("/api/trading/orders/{}", order_id)).json(changes)).await
+    }
+
+    /// Cancel the open orders on `symbol` matching `filter`, returning the
+    /// cancelled orders
+    pub async fn cancel(&self, symbol: &str, filter: &CancelOrdersFilter) -> ClientResult<Vec<Order>> {
+        self.send(self.cancel_request(symbol, filter)).await
+    }
+
+    /// Stream messages on the market data `channels`, e.g. `ticker.BTCUSDT`
+    /// or `orderbook.ETHUSDT?depth=20`
+    pub fn subscribe(&self, channels: Vec<String>) -> WsStream {
+        WsStream::connect(self.market_stream_url.clone(), self.stream_headers(), channels)
+    }
+
+    /// Stream the trades on `symbol`
+    pub fn subscribe_trades(&self, symbol: &str) -> impl Stream<Item = Trade> + Unpin {
+        self.subscribe(vec![format!("trades.{}", symbol)]).filter_map(|message| {
+            future::ready(match message {
+                WsMessage::TradeUpdate(trade) => Some(trade),
+                _ => None,
+            })
+        })
+    }
+
+    /// Stream the caller's order, balance and fill updates, each wrapped in
+    /// a numbered `UserEvent`
+    pub fn user_stream(&self) -> WsStream {
+        let channels = PRIVATE_CHANNELS.iter().map(|channel| channel.to_string()).collect();
+        WsStream::connect(self.user_stream_url.clone(), self.stream_headers(), channels)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let mut request = self.http.request(method, format!("{}{}", self.base_url, path));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        request
+    }
+
+    fn cancel_request(&self, symbol: &str, filter: &CancelOrdersFilter) -> RequestBuilder {
+        #[derive(Serialize)]
+        struct Query<'a> {
+            symbol: &'a str,
+            #[serde(flatten)]
+            filter: &'a CancelOrdersFilter,
+        }
+        self.request(Method::DELETE, "/api/trading/orders").query(&Query { symbol, filter })
+    }
+
+    fn stream_headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if let Some(token) = &self.token {
+            headers.push(("authorization", format!("Bearer {}", token)));
+        }
+        headers
+    }
+
+    /// Send a request and unwrap the `ApiResponse` it returns
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> ClientResult<T> {
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let message = match serde_json::from_str::<ApiResponse<serde_json::Value>>(&body) {
+                Ok(ApiResponse { error: Some(error), .. }) => error,
+                _ if body.is_empty() => status.canonical_reason().unwrap_or("request failed").to_string(),
+                _ => body,
+            };
+            return Err(ClientError::Api { status: status.as_u16(), message });
+        }
+
+        let response: ApiResponse<T> = response.json().await?;
+        match response.data {
+            Some(data) if response.success => Ok(data),
+            _ => Err(ClientError::Api {
+                status: status.as_u16(),
+                message: response.error.unwrap_or_else(|| "empty response".to_string()),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_types::OrderSide;
+    use futures_util::SinkExt;
+    use rust_decimal::Decimal;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[test]
+    fn test_request_urls() {
+        let client = FlowExClient::new("https://api.flowex.com/").with_token("secret");
+        assert_eq!(client.market_stream_url, "wss://api.flowex.com/ws");
+        assert_eq!(client.user_stream_url, "wss://api.flowex.com/api/trading/ws");
+
+        let filter = CancelOrdersFilter {
+            side: Some(OrderSide::Buy),
+            max_price: Some(Decimal::new(50000, 0)),
+            ..Default::default()
+        };
+        let request = client.cancel_request("BTCUSDT", &filter).build().unwrap();
+        assert_eq!(request.method(), Method::DELETE);
+        assert_eq!(
+            request.url().as_str(),
+            "https://api.flowex.com/api/trading/orders?symbol=BTCUSDT&side=buy&max_price=50000.0"
+        );
+        assert_eq!(request.headers()["authorization"], "Bearer secret");
+    }
+
+    #[tokio::test]
+    async fn test_stream_resubscribes_and_resumes_after_restart() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let client = FlowExClient::new("http://localhost").with_user_stream_url(url);
+        let mut stream = client.user_stream();
+        let event = |seq: u64| WsMessage::UserEvent {
+            seq,
+            event: Box::new(WsMessage::BalanceUpdate {
+                currency: "USDT".to_string(),
+                available: "100".to_string(),
+                locked: "0".to_string(),
+            }),
+        };
+
+        let mut requests = Vec::new();
+        for seq in 1..=2 {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(tcp).await.unwrap();
+            for _ in 0..seq {
+                let Some(Ok(Message::Text(text))) = socket.next().await else {
+                    panic!("expected a request");
+                };
+                requests.push(serde_json::from_str::<WsMessage>(&text).unwrap());
+            }
+            let text = serde_json::to_string(&event(seq)).unwrap();
+            socket.send(Message::Text(text)).await.unwrap();
+
+            assert!(matches!(stream.recv().await, Some(WsMessage::UserEvent { seq: received, .. }) if received == seq));
+            let restart = CloseFrame { code: CloseCode::Restart, reason: "Server restarting".into() };
+            socket.close(Some(restart)).await.unwrap();
+        }
+
+        assert!(matches!(&requests[0], WsMessage::Subscribe { channels } if channels.len() == 3));
+        assert!(matches!(&requests[1], WsMessage::Subscribe { channels } if channels.len() == 3));
+        assert!(matches!(requests[2], WsMessage::Resume { from_seq: 2 }));
+    }
+}
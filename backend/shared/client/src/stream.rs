@@ -0,0 +1,150 @@
+//! Auto-reconnecting WebSocket streams
+//!
+//! A `WsStream` owns a background task holding the connection. Whenever the
+//! connection drops, including the restart close the server sends when it
+//! shuts down, the task reconnects with exponential backoff and subscribes
+//! to the same channels again. Once user events have arrived it also sends
+//! `Resume` from the next sequence number, so none are lost across the
+//! reconnect; if the server no longer buffers them its `resume_gap` error
+//! is passed on and the caller must reload its state. Incremental books
+//! restart from the fresh snapshot the server sends on subscribe.
+
+use flowex_websocket::WsMessage;
+use futures_util::{SinkExt, Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{self, HeaderValue};
+use tokio_tungstenite::tungstenite::{Error as TungsteniteError, Message};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, warn};
+
+/// Messages buffered between the connection and the caller
+const STREAM_CAPACITY: usize = 1000;
+
+/// Delay before the first reconnect attempt, doubled after each failure
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest delay between reconnect attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Messages from a WebSocket endpoint, across reconnects
+#[derive(Debug)]
+pub struct WsStream {
+    rx: mpsc::Receiver<WsMessage>,
+    task: JoinHandle<()>,
+}
+
+impl WsStream {
+    /// Connect to `url` with the handshake `headers` and subscribe to `channels`
+    pub(crate) fn connect(url: String, headers: Vec<(&'static str, String)>, channels: Vec<String>) -> Self {
+        let (tx, rx) = mpsc::channel(STREAM_CAPACITY);
+        let task = tokio::spawn(run(url, headers, channels, tx));
+        Self { rx, task }
+    }
+
+    /// The next message, waiting through reconnects
+    pub async fn recv(&mut self) -> Option<WsMessage> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for WsStream {
+    type Item = WsMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WsMessage>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for WsStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Hold a connection to `url` until the stream is dropped
+async fn run(url: String, headers: Vec<(&'static str, String)>, channels: Vec<String>, tx: mpsc::Sender<WsMessage>) {
+    let mut resume_from = None;
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        match connect(&url, &headers).await {
+            Ok(socket) => {
+                info!("Connected to {}", url);
+                backoff = INITIAL_BACKOFF;
+                match forward(socket, &channels, &mut resume_from, &tx).await {
+                    Ok(()) => info!("Connection to {} closed", url),
+                    Err(e) => warn!("Connection to {} failed: {}", url, e),
+                }
+            }
+            Err(e) => warn!("Failed to connect to {}: {}", url, e),
+        }
+        if tx.is_closed() {
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn connect(url: &str, headers: &[(&'static str, String)]) -> Result<Socket, TungsteniteError> {
+    let mut request = url.into_client_request()?;
+    for (name, value) in headers {
+        let value = HeaderValue::from_str(value).map_err(http::Error::from)?;
+        request.headers_mut().insert(*name, value);
+    }
+    let (socket, _) = connect_async(request).await?;
+    Ok(socket)
+}
+
+/// Subscribe, resume any user events, then pass messages on until the
+/// connection closes or the stream is dropped
+async fn forward(
+    mut socket: Socket,
+    channels: &[String],
+    resume_from: &mut Option<u64>,
+    tx: &mpsc::Sender<WsMessage>,
+) -> Result<(), TungsteniteError> {
+    if !channels.is_empty() {
+        send(&mut socket, &WsMessage::Subscribe { channels: channels.to_vec() }).await?;
+    }
+    if let Some(from_seq) = *resume_from {
+        send(&mut socket, &WsMessage::Resume { from_seq }).await?;
+    }
+
+    while let Some(frame) = socket.next().await {
+        let text = match frame? {
+            Message::Text(text) => text,
+            Message::Close(frame) => {
+                debug!("Server closed the connection: {:?}", frame);
+                break;
+            }
+            _ => continue,
+        };
+        let message = match serde_json::from_str::<WsMessage>(&text) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Ignoring unrecognised message: {}", e);
+                continue;
+            }
+        };
+        if let WsMessage::UserEvent { seq, .. } = &message {
+            *resume_from = Some(seq + 1);
+        }
+        if tx.send(message).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn send(socket: &mut Socket, message: &WsMessage) -> Result<(), TungsteniteError> {
+    let text = serde_json::to_string(message).map_err(|e| TungsteniteError::Io(std::io::Error::other(e)))?;
+    socket.send(Message::Text(text)).await
+}
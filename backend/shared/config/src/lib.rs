@@ -1,12 +1,19 @@
 //! FlowEx Configuration Library
 //!
 //! Configuration management for FlowEx services.
+//!
+//! Besides the settings every service shares, the auth service reads the
+//! OAuth2 / OpenID Connect providers users sign in with from the `oauth`
+//! table of `config/default`, or from `FLOWEX_OAUTH__<PROVIDER>__<FIELD>`
+//! environment variables. Google and GitHub only need a client id, secret
+//! and redirect URL; any other provider names its endpoints too.
 
 use config::{Config, ConfigError, Environment, File};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Base configuration for all FlowEx services
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ServiceConfig {
     pub host: String,
     pub port: u16,
@@ -28,6 +35,106 @@ impl ServiceConfig {
     }
 }
 
+/// An OAuth2 / OpenID Connect provider users sign in with
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OAuthProviderConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Where the provider sends users back to, the auth service's callback
+    pub redirect_url: String,
+    #[serde(default)]
+    pub authorize_url: String,
+    #[serde(default)]
+    pub token_url: String,
+    /// Profile of the user an access token was issued to
+    #[serde(default)]
+    pub userinfo_url: String,
+    /// Email addresses of the user, for providers whose profile may omit
+    /// a verified one
+    #[serde(default)]
+    pub emails_url: Option<String>,
+    /// Space separated
+    #[serde(default)]
+    pub scopes: String,
+}
+
+impl OAuthProviderConfig {
+    /// Fill in the endpoints and scopes the well-known provider `name`
+    /// has, where not configured
+    fn with_defaults(mut self, name: &str) -> Self {
+        let (authorize_url, token_url, userinfo_url, emails_url, scopes) = match name {
+            "google" => (
+                "https://accounts.google.com/o/oauth2/v2/auth",
+                "https://oauth2.googleapis.com/token",
+                "https://openidconnect.googleapis.com/v1/userinfo",
+                None,
+                "openid email profile",
+            ),
+            "github" => (
+                "https://github.com/login/oauth/authorize",
+                "https://github.com/login/oauth/access_token",
+                "https://api.github.com/user",
+                Some("https://api.github.com/user/emails"),
+                "read:user user:email",
+            ),
+            _ => return self,
+        };
+        for (field, default) in [
+            (&mut self.authorize_url, authorize_url),
+            (&mut self.token_url, token_url),
+            (&mut self.userinfo_url, userinfo_url),
+            (&mut self.scopes, scopes),
+        ] {
+            if field.is_empty() {
+                *field = default.to_string();
+            }
+        }
+        if self.emails_url.is_none() {
+            self.emails_url = emails_url.map(str::to_string);
+        }
+        self
+    }
+}
+
+/// OAuth2 / OpenID Connect providers, by name
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct OAuthConfig {
+    pub providers: HashMap<String, OAuthProviderConfig>,
+}
+
+impl OAuthConfig {
+    /// Load the providers from environment and config files; none if none
+    /// are configured
+    pub fn load() -> Result<Self, ConfigError> {
+        let config = Config::builder()
+            .add_source(File::with_name("config/default").required(false))
+            .add_source(Environment::with_prefix("FLOWEX").prefix_separator("_").separator("__"))
+            .build()?;
+
+        let providers = match config.get::<HashMap<String, OAuthProviderConfig>>("oauth") {
+            Ok(providers) => providers,
+            Err(ConfigError::NotFound(_)) => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        let providers: HashMap<String, OAuthProviderConfig> = providers
+            .into_iter()
+            .map(|(name, provider)| {
+                let provider = provider.with_defaults(&name);
+                (name, provider)
+            })
+            .collect();
+        if let Some((name, _)) = providers.iter().find(|(_, provider)| {
+            provider.authorize_url.is_empty() || provider.token_url.is_empty() || provider.userinfo_url.is_empty()
+        }) {
+            return Err(ConfigError::Message(format!(
+                "OAuth provider {} needs authorize_url, token_url and userinfo_url",
+                name
+            )));
+        }
+        Ok(Self { providers })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -289,6 +396,39 @@ mod tests {
         assert!(duration.as_secs() < 1, "配置加载性能不达标");
     }
 
+    /// 测试：从环境变量加载 OAuth 提供方，Google 使用默认端点，自定义提供方缺少端点时报错
+    #[test]
+    fn test_oauth_provider_config() {
+        init_test_env();
+
+        env::set_var("FLOWEX_OAUTH__GOOGLE__CLIENT_ID", "google-client");
+        env::set_var("FLOWEX_OAUTH__GOOGLE__CLIENT_SECRET", "google-secret");
+        env::set_var("FLOWEX_OAUTH__GOOGLE__REDIRECT_URL", "https://flowex.com/oauth/google/callback");
+        let config = OAuthConfig::load().unwrap();
+        let google = &config.providers["google"];
+        assert_eq!(google.client_id, "google-client");
+        assert_eq!(google.token_url, "https://oauth2.googleapis.com/token");
+        assert_eq!(google.scopes, "openid email profile");
+        assert!(google.emails_url.is_none());
+
+        env::set_var("FLOWEX_OAUTH__CORPORATE__CLIENT_ID", "corporate-client");
+        env::set_var("FLOWEX_OAUTH__CORPORATE__CLIENT_SECRET", "corporate-secret");
+        env::set_var("FLOWEX_OAUTH__CORPORATE__REDIRECT_URL", "https://flowex.com/oauth/corporate/callback");
+        let incomplete = OAuthConfig::load();
+
+        for var in [
+            "FLOWEX_OAUTH__GOOGLE__CLIENT_ID",
+            "FLOWEX_OAUTH__GOOGLE__CLIENT_SECRET",
+            "FLOWEX_OAUTH__GOOGLE__REDIRECT_URL",
+            "FLOWEX_OAUTH__CORPORATE__CLIENT_ID",
+            "FLOWEX_OAUTH__CORPORATE__CLIENT_SECRET",
+            "FLOWEX_OAUTH__CORPORATE__REDIRECT_URL",
+        ] {
+            env::remove_var(var);
+        }
+        assert!(incomplete.is_err());
+    }
+
     /// 测试：配置内存使用
     #[test]
     fn test_config_memory_usage() {
@@ -0,0 +1,434 @@
+//! FlowEx Fees
+//!
+//! Tiered maker/taker fee schedules. A user's rates are those of the highest
+//! tier their trading volume over the last 30 days reaches; a trading pair
+//! may replace the tier table with its own, and a user may hold a discount
+//! on the fees they pay (rebates are never discounted).
+//!
+//! `FeeManager` tracks each user's daily volume as trades execute and hands
+//! every pair's matching engine a `FeeSchedule` reading from it, so fees are
+//! priced once, at matching time, and settled as they appear on the trade.
+//! Volumes are trade notionals in the quote asset of each pair.
+
+use chrono::{Duration, NaiveDate, Utc};
+use flowex_matching_engine::fees::{Fee, FeeSchedule, Liquidity};
+use flowex_types::{FlowExError, FlowExResult, Order, Trade, TradingPair};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::{Arc, RwLock},
+};
+use tracing::info;
+use uuid::Uuid;
+
+/// Days of trading counted towards a user's tier
+pub const VOLUME_WINDOW_DAYS: i64 = 30;
+
+/// Highest rate a schedule may charge (0.1%). Order funds are reserved with
+/// this much on top, so no fill can cost more than was locked for it.
+pub const MAX_FEE_RATE: Decimal = Decimal::from_parts(1, 0, 0, false, 3);
+
+/// Rates of users whose 30-day volume reaches `min_volume`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub level: u32,
+    /// 30-day volume from which the tier applies
+    pub min_volume: Decimal,
+    /// Fraction of notional charged to makers (0.001 = 0.1%); negative for a rebate
+    pub maker_rate: Decimal,
+    /// Fraction of notional charged to takers
+    pub taker_rate: Decimal,
+}
+
+/// Fee schedule of the exchange
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeConfig {
+    /// Tiers of every pair without an override, lowest volume first
+    pub tiers: Vec<FeeTier>,
+    /// Tier tables replacing `tiers` on individual pairs, by symbol
+    #[serde(default)]
+    pub pair_overrides: HashMap<String, Vec<FeeTier>>,
+    /// Fraction taken off the fees a user pays (0.25 = 25% off), by user
+    #[serde(default)]
+    pub discounts: HashMap<Uuid, Decimal>,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        let tier = |level, min_volume, maker_bps, taker_bps| FeeTier {
+            level,
+            min_volume: Decimal::new(min_volume, 0),
+            maker_rate: Decimal::new(maker_bps, 4),
+            taker_rate: Decimal::new(taker_bps, 4),
+        };
+
+        Self {
+            tiers: vec![
+                tier(0, 0, 10, 10),
+                tier(1, 1_000_000, 8, 9),
+                tier(2, 10_000_000, 6, 8),
+                tier(3, 50_000_000, 2, 6),
+                tier(4, 200_000_000, 0, 5),
+            ],
+            pair_overrides: HashMap::new(),
+            discounts: HashMap::new(),
+        }
+    }
+}
+
+impl FeeConfig {
+    /// Schedule read from the JSON file at `path`
+    pub fn load(path: impl AsRef<Path>) -> FlowExResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| FlowExError::Internal(format!("Failed to read fee schedule {}: {}", path.display(), e)))?;
+        let config: Self = serde_json::from_str(&contents)
+            .map_err(|e| FlowExError::Validation(format!("Invalid fee schedule {}: {}", path.display(), e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that every tier table starts at zero volume, rises in volume
+    /// and stays within `MAX_FEE_RATE`, and that discounts are fractions
+    pub fn validate(&self) -> FlowExResult<()> {
+        validate_tiers("default", &self.tiers)?;
+        for (symbol, tiers) in &self.pair_overrides {
+            validate_tiers(symbol, tiers)?;
+        }
+        for (user_id, discount) in &self.discounts {
+            if *discount < Decimal::ZERO || *discount > Decimal::ONE {
+                return Err(FlowExError::Validation(format!(
+                    "Fee discount of user {} must be between 0 and 1",
+                    user_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tier table of `symbol`
+    fn tiers(&self, symbol: &str) -> &[FeeTier] {
+        self.pair_overrides.get(symbol).unwrap_or(&self.tiers)
+    }
+}
+
+fn validate_tiers(name: &str, tiers: &[FeeTier]) -> FlowExResult<()> {
+    let invalid = |reason: &str| Err(FlowExError::Validation(format!("Fee tiers of {} {}", name, reason)));
+    match tiers.first() {
+        None => return invalid("are empty"),
+        Some(tier) if !tier.min_volume.is_zero() => return invalid("must start at zero volume"),
+        Some(_) => {}
+    }
+    if tiers.windows(2).any(|pair| pair[1].min_volume <= pair[0].min_volume) {
+        return invalid("must be ordered by rising volume");
+    }
+    if tiers
+        .iter()
+        .any(|tier| tier.maker_rate.abs() > MAX_FEE_RATE || tier.taker_rate.abs() > MAX_FEE_RATE)
+    {
+        return invalid(&format!("may not charge more than {}", MAX_FEE_RATE));
+    }
+    Ok(())
+}
+
+/// Rates a user pays on one trading pair
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PairFeeRates {
+    pub symbol: String,
+    pub tier: u32,
+    /// Maker rate after the user's discount
+    pub maker_rate: Decimal,
+    /// Taker rate after the user's discount
+    pub taker_rate: Decimal,
+}
+
+/// A user's fee rates across trading pairs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserFeeRates {
+    /// Volume traded over the last `VOLUME_WINDOW_DAYS` days
+    pub volume_30d: Decimal,
+    pub discount: Decimal,
+    pub pairs: Vec<PairFeeRates>,
+}
+
+/// Daily traded volume of each user
+#[derive(Debug, Default)]
+struct VolumeTracker {
+    volumes: HashMap<Uuid, BTreeMap<NaiveDate, Decimal>>,
+}
+
+impl VolumeTracker {
+    fn record(&mut self, user_id: Uuid, date: NaiveDate, volume: Decimal) {
+        let days = self.volumes.entry(user_id).or_default();
+        *days.entry(date).or_default() += volume;
+        // Days that can no longer count towards a tier
+        let oldest = date - Duration::days(VOLUME_WINDOW_DAYS);
+        days.retain(|day, _| *day > oldest);
+    }
+
+    /// Volume of `user_id` over the window ending on `today`
+    fn volume(&self, user_id: Uuid, today: NaiveDate) -> Decimal {
+        let oldest = today - Duration::days(VOLUME_WINDOW_DAYS);
+        self.volumes
+            .get(&user_id)
+            .map(|days| days.range(oldest.succ_opt().unwrap_or(oldest)..).map(|(_, volume)| *volume).sum())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct FeeState {
+    config: RwLock<FeeConfig>,
+    volumes: RwLock<VolumeTracker>,
+}
+
+/// Fee schedule and the volumes it is priced on, shared by every pair's
+/// matching engine
+#[derive(Debug, Clone)]
+pub struct FeeManager {
+    state: Arc<FeeState>,
+}
+
+impl FeeManager {
+    pub fn new(config: FeeConfig) -> Self {
+        Self {
+            state: Arc::new(FeeState {
+                config: RwLock::new(config),
+                volumes: RwLock::new(VolumeTracker::default()),
+            }),
+        }
+    }
+
+    /// Replace the schedule; engines price their next trades with it
+    pub fn set_config(&self, config: FeeConfig) -> FlowExResult<()> {
+        config.validate()?;
+        info!(
+            "Fee schedule set: {} tiers, {} pair overrides, {} discounts",
+            config.tiers.len(),
+            config.pair_overrides.len(),
+            config.discounts.len()
+        );
+        *self.state.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+        Ok(())
+    }
+
+    /// Fee schedule of `trading_pair`'s matching engine
+    pub fn schedule(&self, trading_pair: &TradingPair) -> Arc<dyn FeeSchedule> {
+        Arc::new(PairFeeSchedule {
+            fees: self.clone(),
+            symbol: trading_pair.symbol.clone(),
+            quote_asset: trading_pair.quote_asset.clone(),
+        })
+    }
+
+    /// Count a user's volume traded on `date`, such as volume recorded
+    /// before the service started
+    pub fn record_volume(&self, user_id: Uuid, date: NaiveDate, volume: Decimal) {
+        self.state
+            .volumes
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .record(user_id, date, volume);
+    }
+
+    /// Count an executed trade towards both its users' volumes
+    pub fn record_trade(&self, trade: &Trade) {
+        let notional = trade.price * trade.quantity;
+        let date = trade.timestamp.date_naive();
+        let mut volumes = self.state.volumes.write().unwrap_or_else(|e| e.into_inner());
+        volumes.record(trade.maker_user_id, date, notional);
+        if trade.taker_user_id != trade.maker_user_id {
+            volumes.record(trade.taker_user_id, date, notional);
+        }
+    }
+
+    /// Volume `user_id` traded over the last `VOLUME_WINDOW_DAYS` days
+    pub fn volume(&self, user_id: Uuid) -> Decimal {
+        self.state
+            .volumes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .volume(user_id, Utc::now().date_naive())
+    }
+
+    /// Rates `user_id` pays on each of `symbols`
+    pub fn user_rates<'a>(&self, user_id: Uuid, symbols: impl IntoIterator<Item = &'a str>) -> UserFeeRates {
+        let volume_30d = self.volume(user_id);
+        let config = self.state.config.read().unwrap_or_else(|e| e.into_inner());
+        let discount = config.discounts.get(&user_id).copied().unwrap_or_default();
+        let pairs = symbols
+            .into_iter()
+            .map(|symbol| {
+                let tier = tier(config.tiers(symbol), volume_30d);
+                PairFeeRates {
+                    symbol: symbol.to_string(),
+                    tier: tier.level,
+                    maker_rate: discounted(tier.maker_rate, discount),
+                    taker_rate: discounted(tier.taker_rate, discount),
+                }
+            })
+            .collect();
+
+        UserFeeRates {
+            volume_30d,
+            discount,
+            pairs,
+        }
+    }
+
+    /// Rate `user_id` pays on `symbol` as maker or taker
+    fn rate(&self, user_id: Uuid, symbol: &str, liquidity: Liquidity) -> Decimal {
+        let volume = self.volume(user_id);
+        let config = self.state.config.read().unwrap_or_else(|e| e.into_inner());
+        let tier = tier(config.tiers(symbol), volume);
+        let rate = match liquidity {
+            Liquidity::Maker => tier.maker_rate,
+            Liquidity::Taker => tier.taker_rate,
+        };
+        discounted(rate, config.discounts.get(&user_id).copied().unwrap_or_default())
+    }
+}
+
+/// Highest tier of `tiers` that `volume` reaches
+fn tier(tiers: &[FeeTier], volume: Decimal) -> &FeeTier {
+    tiers
+        .iter()
+        .rev()
+        .find(|tier| tier.min_volume <= volume)
+        .unwrap_or(&tiers[0])
+}
+
+/// `rate` with `discount` taken off, unless it is a rebate
+fn discounted(rate: Decimal, discount: Decimal) -> Decimal {
+    if rate > Decimal::ZERO {
+        rate * (Decimal::ONE - discount)
+    } else {
+        rate
+    }
+}
+
+/// Tiered schedule of one trading pair, charged in its quote asset
+#[derive(Debug)]
+struct PairFeeSchedule {
+    fees: FeeManager,
+    symbol: String,
+    quote_asset: String,
+}
+
+impl FeeSchedule for PairFeeSchedule {
+    fn fee(&self, order: &Order, liquidity: Liquidity, price: Decimal, quantity: Decimal) -> Fee {
+        Fee {
+            amount: price * quantity * self.fees.rate(order.user_id, &self.symbol, liquidity),
+            currency: self.quote_asset.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_matching_engine::MatchingEngine;
+    use flowex_test_support::create_user_limit_order;
+    use flowex_types::{OrderSide, TradingStatus};
+
+    fn trading_pair(symbol: &str) -> TradingPair {
+        TradingPair {
+            symbol: symbol.to_string(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            status: TradingStatus::Trading,
+            min_price: Decimal::new(1, 2),
+            max_price: Decimal::new(10000000, 0),
+            min_qty: Decimal::new(1, 8),
+            max_qty: Decimal::new(1000000, 0),
+            step_size: Decimal::new(1, 8),
+            tick_size: Decimal::new(1, 2),
+            min_notional: Decimal::ZERO,
+        }
+    }
+
+    /// 测试：按30天成交量确定费率等级，交易对可覆盖等级表，折扣不作用于返佣
+    #[test]
+    fn test_tier_overrides_and_discounts() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut config = FeeConfig::default();
+        config.pair_overrides.insert(
+            "ETHUSDT".to_string(),
+            vec![FeeTier {
+                level: 0,
+                min_volume: Decimal::ZERO,
+                maker_rate: Decimal::new(-1, 4),
+                taker_rate: Decimal::new(2, 4),
+            }],
+        );
+        config.discounts.insert(bob, Decimal::new(25, 2));
+        let fees = FeeManager::new(config);
+
+        let today = Utc::now().date_naive();
+        fees.record_volume(alice, today, Decimal::new(6_000_000, 0));
+        fees.record_volume(alice, today - Duration::days(3), Decimal::new(5_000_000, 0));
+        // 超出30天窗口的成交量不计入
+        fees.record_volume(alice, today - Duration::days(VOLUME_WINDOW_DAYS), Decimal::new(100_000_000, 0));
+        assert_eq!(fees.volume(alice), Decimal::new(11_000_000, 0));
+
+        let rates = fees.user_rates(alice, ["BTCUSDT", "ETHUSDT"]);
+        assert_eq!(rates.pairs[0].tier, 2);
+        assert_eq!(rates.pairs[0].maker_rate, Decimal::new(6, 4));
+        assert_eq!(rates.pairs[0].taker_rate, Decimal::new(8, 4));
+        assert_eq!(rates.pairs[1].maker_rate, Decimal::new(-1, 4));
+
+        let rates = fees.user_rates(bob, ["BTCUSDT", "ETHUSDT"]);
+        assert_eq!(rates.pairs[0].tier, 0);
+        assert_eq!(rates.pairs[0].taker_rate, Decimal::new(75, 5));
+        assert_eq!(rates.pairs[1].maker_rate, Decimal::new(-1, 4));
+        assert_eq!(rates.pairs[1].taker_rate, Decimal::new(15, 5));
+    }
+
+    /// 测试：撮合引擎按用户等级计费，成交计入双方成交量
+    #[test]
+    fn test_engine_charges_tiered_fees() {
+        let fees = FeeManager::new(FeeConfig::default());
+        let pair = trading_pair("BTCUSDT");
+        let mut engine = MatchingEngine::with_trading_pair(pair.clone());
+        engine.set_fee_schedule(Some(fees.schedule(&pair)));
+
+        let (maker, taker) = (Uuid::new_v4(), Uuid::new_v4());
+        fees.record_volume(maker, Utc::now().date_naive(), Decimal::new(1_000_000, 0));
+
+        engine.add_order(create_user_limit_order(maker, OrderSide::Sell, 50000, 2)).unwrap();
+        let trades = engine.add_order(create_user_limit_order(taker, OrderSide::Buy, 50000, 1)).unwrap().trades;
+        assert_eq!(trades[0].maker_fee, Decimal::new(40, 0));
+        assert_eq!(trades[0].taker_fee, Decimal::new(50, 0));
+        assert_eq!(trades[0].taker_fee_currency.as_deref(), Some("USDT"));
+
+        fees.record_trade(&trades[0]);
+        assert_eq!(fees.volume(maker), Decimal::new(1_050_000, 0));
+        assert_eq!(fees.volume(taker), Decimal::new(50_000, 0));
+    }
+
+    /// 测试：费率表须从零成交量开始、按成交量递增且不超过最高费率
+    #[test]
+    fn test_config_validation() {
+        assert!(FeeConfig::default().validate().is_ok());
+
+        let mut config = FeeConfig::default();
+        config.tiers.swap(1, 2);
+        assert!(config.validate().is_err());
+
+        let mut config = FeeConfig::default();
+        config.tiers[0].taker_rate = Decimal::new(2, 3);
+        assert!(config.validate().is_err());
+
+        let mut config = FeeConfig::default();
+        config.discounts.insert(Uuid::new_v4(), Decimal::new(15, 1));
+        assert!(config.validate().is_err());
+
+        let config = FeeConfig {
+            tiers: Vec::new(),
+            ..FeeConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Enforcement of the KYC policy in services
+//!
+//! A `KycGate` tells a service what a user may do under its `KycPolicy`.
+//! It asks the auth service for users' levels and caches each answer for
+//! `LEVEL_CACHE_TTL`, so a user's new level lifts their limits within that
+//! time. When the auth service cannot answer, the user is treated as
+//! unverified, failing closed.
+
+use flowex_types::{ApiResponse, FlowExResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{KycLevel, KycPolicy, KycStatus, TierLimits};
+
+/// How long a user's level is cached
+pub const LEVEL_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Timeout of requests to the auth service
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where a gate learns users' levels
+#[derive(Debug, Clone)]
+enum LevelSource {
+    /// The auth service at `base_url`
+    Remote {
+        http: reqwest::Client,
+        base_url: String,
+        cache: Arc<RwLock<HashMap<Uuid, (KycLevel, Instant)>>>,
+    },
+    /// Every user is verified to the same level
+    Fixed(KycLevel),
+}
+
+/// Limits of users by their verification level
+#[derive(Debug, Clone)]
+pub struct KycGate {
+    source: LevelSource,
+    policy: Arc<KycPolicy>,
+}
+
+impl KycGate {
+    /// Gate asking the auth service at `base_url` for users' levels
+    pub fn new(base_url: &str, policy: KycPolicy) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            source: LevelSource::Remote {
+                http,
+                base_url: base_url.trim_end_matches('/').to_string(),
+                cache: Arc::new(RwLock::new(HashMap::new())),
+            },
+            policy: Arc::new(policy),
+        }
+    }
+
+    /// Gate treating every user as verified to `level`
+    pub fn fixed(level: KycLevel, policy: KycPolicy) -> Self {
+        Self {
+            source: LevelSource::Fixed(level),
+            policy: Arc::new(policy),
+        }
+    }
+
+    /// Enforce `policy` from now on
+    pub fn set_policy(&mut self, policy: KycPolicy) -> FlowExResult<()> {
+        policy.validate()?;
+        self.policy = Arc::new(policy);
+        Ok(())
+    }
+
+    pub fn policy(&self) -> &KycPolicy {
+        &self.policy
+    }
+
+    /// Level `user_id` is verified to
+    pub async fn level(&self, user_id: Uuid) -> KycLevel {
+        let (http, base_url, cache) = match &self.source {
+            LevelSource::Remote { http, base_url, cache } => (http, base_url, cache),
+            LevelSource::Fixed(level) => return *level,
+        };
+
+        if let Some((level, fetched_at)) = cache.read().await.get(&user_id) {
+            if fetched_at.elapsed() < LEVEL_CACHE_TTL {
+                return *level;
+            }
+        }
+
+        let url = format!("{}/internal/kyc/{}", base_url, user_id);
+        let level = match http.get(&url).send().await {
+            Ok(response) if response.status().is_success() => {
+                match response.json::<ApiResponse<KycStatus>>().await {
+                    Ok(body) => body.data.map(|status| status.level),
+                    Err(e) => {
+                        warn!("Invalid KYC level response for {}: {}", user_id, e);
+                        None
+                    }
+                }
+            }
+            Ok(response) => {
+                warn!("KYC level lookup for {} answered {}", user_id, response.status());
+                None
+            }
+            Err(e) => {
+                warn!("KYC level lookup for {} failed: {}", user_id, e);
+                None
+            }
+        };
+        // Unanswered lookups are not cached, so the next request asks again
+        let Some(level) = level else {
+            return KycLevel::L0;
+        };
+
+        let mut cache = cache.write().await;
+        cache.retain(|_, (_, fetched_at)| fetched_at.elapsed() < LEVEL_CACHE_TTL);
+        cache.insert(user_id, (level, Instant::now()));
+        level
+    }
+
+    /// Level `user_id` is verified to and what it lets them do
+    pub async fn limits(&self, user_id: Uuid) -> (KycLevel, TierLimits) {
+        let level = self.level(user_id).await;
+        (level, self.policy.limits(level))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Path, routing::get, Json, Router};
+
+    /// 测试：从认证服务查询用户级别并缓存，认证服务不可用时按未认证处理
+    #[tokio::test]
+    async fn test_remote_levels() {
+        let verified = Uuid::new_v4();
+        let app = Router::new().route(
+            "/internal/kyc/:user_id",
+            get(move |Path(user_id): Path<Uuid>| async move {
+                let level = if user_id == verified { KycLevel::L2 } else { KycLevel::L0 };
+                Json(ApiResponse::success(KycStatus { user_id, level }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let gate = KycGate::new(&format!("http://{}/", address), KycPolicy::default());
+        let (level, limits) = gate.limits(verified).await;
+        assert_eq!(level, KycLevel::L2);
+        assert!(limits.can_withdraw && limits.max_symbol_exposure.is_none());
+        assert_eq!(gate.level(Uuid::new_v4()).await, KycLevel::L0);
+
+        let unreachable = KycGate::new("http://127.0.0.1:1", KycPolicy::default());
+        let (level, limits) = unreachable.limits(verified).await;
+        assert_eq!(level, KycLevel::L0);
+        assert!(!limits.can_withdraw);
+
+        assert_eq!(KycGate::fixed(KycLevel::L1, KycPolicy::default()).level(verified).await, KycLevel::L1);
+    }
+}
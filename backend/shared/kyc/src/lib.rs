@@ -0,0 +1,316 @@
+//! FlowEx KYC
+//!
+//! Identity verification of users. Each user is verified to a `KycLevel`:
+//! `L0` unverified, `L1` with their personal details checked, and `L2`
+//! with an identity document and a selfie or proof of address checked too.
+//! A user applies for a level with a `KycApplication` naming the documents
+//! they uploaded; a `KycProvider` decides on it or leaves it to an admin's
+//! review (see `provider`). A user's level is the highest one an
+//! application of theirs was approved for.
+//!
+//! What each level may do — trade, up to which exposure, withdraw, and how
+//! much a day — is a `KycPolicy` (see `policy`). Services enforce it
+//! through a `KycGate`, which learns users' levels from the auth service
+//! (see `gate`).
+
+pub mod gate;
+pub mod policy;
+pub mod provider;
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use flowex_types::{FlowExError, FlowExResult};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use uuid::Uuid;
+
+pub use gate::KycGate;
+pub use policy::{KycPolicy, TierLimits};
+pub use provider::{HttpKycProvider, KycProvider, ManualReview, ProviderDecision, ProviderVerdict};
+
+/// Youngest age users are verified at
+pub const MIN_AGE_YEARS: i32 = 18;
+
+/// Longest name accepted in personal details
+const MAX_NAME_LEN: usize = 100;
+
+/// How far a user's identity has been verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+pub enum KycLevel {
+    /// Unverified
+    #[default]
+    L0,
+    /// Personal details verified
+    L1,
+    /// Identity document and selfie or proof of address verified
+    L2,
+}
+
+impl KycLevel {
+    pub const ALL: [KycLevel; 3] = [KycLevel::L0, KycLevel::L1, KycLevel::L2];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KycLevel::L0 => "L0",
+            KycLevel::L1 => "L1",
+            KycLevel::L2 => "L2",
+        }
+    }
+}
+
+impl FromStr for KycLevel {
+    type Err = FlowExError;
+
+    fn from_str(s: &str) -> FlowExResult<Self> {
+        match s {
+            "L0" => Ok(KycLevel::L0),
+            "L1" => Ok(KycLevel::L1),
+            "L2" => Ok(KycLevel::L2),
+            _ => Err(FlowExError::Validation(format!("Invalid KYC level: {}", s))),
+        }
+    }
+}
+
+/// Kind of a document a user uploads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentType {
+    Passport,
+    NationalId,
+    DriversLicense,
+    ProofOfAddress,
+    Selfie,
+}
+
+impl DocumentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentType::Passport => "passport",
+            DocumentType::NationalId => "national_id",
+            DocumentType::DriversLicense => "drivers_license",
+            DocumentType::ProofOfAddress => "proof_of_address",
+            DocumentType::Selfie => "selfie",
+        }
+    }
+
+    /// Whether the document is a government-issued identity document
+    pub fn proves_identity(&self) -> bool {
+        matches!(self, DocumentType::Passport | DocumentType::NationalId | DocumentType::DriversLicense)
+    }
+}
+
+impl FromStr for DocumentType {
+    type Err = FlowExError;
+
+    fn from_str(s: &str) -> FlowExResult<Self> {
+        match s {
+            "passport" => Ok(DocumentType::Passport),
+            "national_id" => Ok(DocumentType::NationalId),
+            "drivers_license" => Ok(DocumentType::DriversLicense),
+            "proof_of_address" => Ok(DocumentType::ProofOfAddress),
+            "selfie" => Ok(DocumentType::Selfie),
+            _ => Err(FlowExError::Validation(format!("Invalid document type: {}", s))),
+        }
+    }
+}
+
+/// Who a user says they are
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PersonalDetails {
+    pub first_name: String,
+    pub last_name: String,
+    pub date_of_birth: NaiveDate,
+    /// ISO 3166-1 alpha-2 code of the country of residence
+    pub country: String,
+    #[serde(default)]
+    pub address: Option<String>,
+}
+
+impl PersonalDetails {
+    /// Check the details are complete and the user is of age on `today`
+    pub fn validate(&self, today: NaiveDate) -> FlowExResult<()> {
+        for name in [&self.first_name, &self.last_name] {
+            if name.trim().is_empty() || name.len() > MAX_NAME_LEN {
+                return Err(FlowExError::Validation(format!(
+                    "Names must be 1 to {} characters long",
+                    MAX_NAME_LEN
+                )));
+            }
+        }
+        if self.country.len() != 2 || !self.country.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(FlowExError::Validation(format!(
+                "Invalid country code: {}",
+                self.country
+            )));
+        }
+        let mut age = today.year() - self.date_of_birth.year();
+        if (today.month(), today.day()) < (self.date_of_birth.month(), self.date_of_birth.day()) {
+            age -= 1;
+        }
+        if age < MIN_AGE_YEARS {
+            return Err(FlowExError::Validation(format!(
+                "Users must be at least {} years old",
+                MIN_AGE_YEARS
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A document a user uploaded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KycDocument {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub document_type: DocumentType,
+    /// Hex SHA-256 of the content
+    pub sha256: String,
+    /// Size of the content, in bytes
+    pub size: usize,
+    pub uploaded_at: DateTime<Utc>,
+    /// The file itself, never sent back to users
+    #[serde(skip)]
+    pub content: Vec<u8>,
+}
+
+/// Where an application stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApplicationStatus {
+    /// Waiting for the provider or an admin
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl ApplicationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApplicationStatus::Pending => "pending",
+            ApplicationStatus::Approved => "approved",
+            ApplicationStatus::Rejected => "rejected",
+        }
+    }
+}
+
+impl FromStr for ApplicationStatus {
+    type Err = FlowExError;
+
+    fn from_str(s: &str) -> FlowExResult<Self> {
+        match s {
+            "pending" => Ok(ApplicationStatus::Pending),
+            "approved" => Ok(ApplicationStatus::Approved),
+            "rejected" => Ok(ApplicationStatus::Rejected),
+            _ => Err(FlowExError::Validation(format!("Invalid application status: {}", s))),
+        }
+    }
+}
+
+/// A user's application to be verified to a level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KycApplication {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub level: KycLevel,
+    pub details: PersonalDetails,
+    /// Documents submitted with the application
+    pub document_ids: Vec<Uuid>,
+    pub status: ApplicationStatus,
+    /// Provider the application was submitted to
+    pub provider: String,
+    /// The provider's id of its check
+    pub provider_reference: Option<String>,
+    pub rejection_reason: Option<String>,
+    /// Admin who approved or rejected the application, if not the provider
+    pub reviewed_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Level a user is verified to, from their applications
+pub fn verified_level<'a>(applications: impl IntoIterator<Item = &'a KycApplication>) -> KycLevel {
+    applications
+        .into_iter()
+        .filter(|application| application.status == ApplicationStatus::Approved)
+        .map(|application| application.level)
+        .max()
+        .unwrap_or_default()
+}
+
+/// Check an application for `level` by a user verified to `current` has
+/// the `documents` the level requires
+pub fn check_requirements(level: KycLevel, current: KycLevel, documents: &[KycDocument]) -> FlowExResult<()> {
+    if level == KycLevel::L0 {
+        return Err(FlowExError::Validation("Nobody applies for L0".to_string()));
+    }
+    if level <= current {
+        return Err(FlowExError::Validation(format!("Already verified to {}", current.as_str())));
+    }
+    if level == KycLevel::L2 {
+        let has = |check: fn(&DocumentType) -> bool| documents.iter().any(|document| check(&document.document_type));
+        if !has(DocumentType::proves_identity) {
+            return Err(FlowExError::Validation(
+                "L2 needs a passport, national id or driver's license".to_string(),
+            ));
+        }
+        if !has(|document_type| matches!(document_type, DocumentType::Selfie | DocumentType::ProofOfAddress)) {
+            return Err(FlowExError::Validation("L2 needs a selfie or proof of address".to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Level of a user, as the auth service reports it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KycStatus {
+    pub user_id: Uuid,
+    pub level: KycLevel,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(document_type: DocumentType) -> KycDocument {
+        KycDocument {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            document_type,
+            sha256: String::new(),
+            size: 0,
+            uploaded_at: Utc::now(),
+            content: Vec::new(),
+        }
+    }
+
+    /// 测试：各级别的申请要求，L2 需要身份证件和自拍或地址证明，不能重复申请已有级别
+    #[test]
+    fn test_application_requirements() {
+        assert!(check_requirements(KycLevel::L1, KycLevel::L0, &[]).is_ok());
+        assert!(check_requirements(KycLevel::L1, KycLevel::L1, &[]).is_err());
+        assert!(check_requirements(KycLevel::L0, KycLevel::L0, &[]).is_err());
+
+        let passport = document(DocumentType::Passport);
+        let selfie = document(DocumentType::Selfie);
+        assert!(check_requirements(KycLevel::L2, KycLevel::L1, std::slice::from_ref(&passport)).is_err());
+        assert!(check_requirements(KycLevel::L2, KycLevel::L1, std::slice::from_ref(&selfie)).is_err());
+        assert!(check_requirements(KycLevel::L2, KycLevel::L0, &[passport, selfie]).is_ok());
+    }
+
+    /// 测试：个人信息校验，未成年和无效国家代码被拒绝
+    #[test]
+    fn test_personal_details_validation() {
+        let today = NaiveDate::from_ymd_opt(2024, 6, 15).unwrap();
+        let details = |born: NaiveDate, country: &str| PersonalDetails {
+            first_name: "Jane".to_string(),
+            last_name: "Doe".to_string(),
+            date_of_birth: born,
+            country: country.to_string(),
+            address: None,
+        };
+        assert!(details(NaiveDate::from_ymd_opt(2006, 6, 15).unwrap(), "DE").validate(today).is_ok());
+        assert!(details(NaiveDate::from_ymd_opt(2006, 6, 16).unwrap(), "DE").validate(today).is_err());
+        assert!(details(NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(), "de").validate(today).is_err());
+        assert_eq!("L2".parse::<KycLevel>().unwrap(), KycLevel::L2);
+        assert!(KycLevel::L2 > KycLevel::L1);
+    }
+}
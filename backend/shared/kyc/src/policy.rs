@@ -0,0 +1,157 @@
+//! What each verification level may do
+//!
+//! A `KycPolicy` gives every level its `TierLimits`: whether its users may
+//! trade and up to which exposure per symbol, and whether they may withdraw
+//! and how much of each currency a day. A level the policy does not list
+//! may neither trade nor withdraw. The default policy lets unverified users
+//! trade small amounts only, caps the daily withdrawals of `L1` and leaves
+//! `L2` to the services' own limits.
+
+use flowex_types::{FlowExError, FlowExResult};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::KycLevel;
+
+/// What users of one level may do
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TierLimits {
+    pub can_trade: bool,
+    /// Largest position and open orders a user may hold in one symbol, in
+    /// the quote asset; `None` for no cap beyond the trading service's own
+    #[serde(default)]
+    pub max_symbol_exposure: Option<Decimal>,
+    pub can_withdraw: bool,
+    /// Most of each currency a user may withdraw in a day; currencies not
+    /// listed are capped by the wallet service's own limits only
+    #[serde(default)]
+    pub daily_withdrawal_limits: HashMap<String, Decimal>,
+}
+
+/// Limits by verification level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KycPolicy {
+    pub tiers: BTreeMap<KycLevel, TierLimits>,
+}
+
+impl Default for KycPolicy {
+    fn default() -> Self {
+        let tiers = BTreeMap::from([
+            (
+                KycLevel::L0,
+                TierLimits {
+                    can_trade: true,
+                    max_symbol_exposure: Some(Decimal::from(1_000)),
+                    can_withdraw: false,
+                    daily_withdrawal_limits: HashMap::new(),
+                },
+            ),
+            (
+                KycLevel::L1,
+                TierLimits {
+                    can_trade: true,
+                    max_symbol_exposure: Some(Decimal::from(100_000)),
+                    can_withdraw: true,
+                    daily_withdrawal_limits: HashMap::from([
+                        ("BTC".to_string(), Decimal::ONE),
+                        ("ETH".to_string(), Decimal::from(20)),
+                        ("USDT".to_string(), Decimal::from(50_000)),
+                    ]),
+                },
+            ),
+            (
+                KycLevel::L2,
+                TierLimits {
+                    can_trade: true,
+                    max_symbol_exposure: None,
+                    can_withdraw: true,
+                    daily_withdrawal_limits: HashMap::new(),
+                },
+            ),
+        ]);
+        Self { tiers }
+    }
+}
+
+impl KycPolicy {
+    /// Read a KYC policy from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> FlowExResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| FlowExError::Internal(format!("Failed to read KYC policy {}: {}", path.display(), e)))?;
+        let policy: Self = serde_json::from_str(&contents)
+            .map_err(|e| FlowExError::Validation(format!("Invalid KYC policy {}: {}", path.display(), e)))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Check that every cap is positive and no level may do less than the
+    /// one below it
+    pub fn validate(&self) -> FlowExResult<()> {
+        for (level, limits) in &self.tiers {
+            let mut caps = limits.max_symbol_exposure.iter().chain(limits.daily_withdrawal_limits.values());
+            if caps.any(|cap| *cap <= Decimal::ZERO) {
+                return Err(FlowExError::Validation(format!(
+                    "KYC limits of {} must be positive",
+                    level.as_str()
+                )));
+            }
+        }
+        for pair in KycLevel::ALL.windows(2) {
+            let (lower, higher) = (self.limits(pair[0]), self.limits(pair[1]));
+            let narrower = |low: Option<Decimal>, high: Option<Decimal>| match (low, high) {
+                (_, None) => false,
+                (None, Some(_)) => true,
+                (Some(low), Some(high)) => high < low,
+            };
+            let withdrawals_narrower = lower.can_withdraw
+                && lower.daily_withdrawal_limits.iter().any(|(currency, limit)| {
+                    narrower(Some(*limit), higher.daily_withdrawal_limits.get(currency).copied())
+                });
+            if (lower.can_trade && !higher.can_trade)
+                || (lower.can_withdraw && !higher.can_withdraw)
+                || (lower.can_trade && narrower(lower.max_symbol_exposure, higher.max_symbol_exposure))
+                || withdrawals_narrower
+            {
+                return Err(FlowExError::Validation(format!(
+                    "KYC level {} may not do less than {}",
+                    pair[1].as_str(),
+                    pair[0].as_str()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// What users verified to `level` may do
+    pub fn limits(&self, level: KycLevel) -> TierLimits {
+        self.tiers.get(&level).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：默认策略有效，较高级别的限额不得低于较低级别
+    #[test]
+    fn test_policy_validation() {
+        let policy = KycPolicy::default();
+        assert!(policy.validate().is_ok());
+        assert!(!policy.limits(KycLevel::L0).can_withdraw);
+        assert_eq!(policy.limits(KycLevel::L1).daily_withdrawal_limits.get("BTC"), Some(&Decimal::ONE));
+
+        let mut narrower = policy.clone();
+        narrower.tiers.get_mut(&KycLevel::L2).unwrap().daily_withdrawal_limits.insert("BTC".to_string(), Decimal::new(5, 1));
+        assert!(narrower.validate().is_err());
+
+        let mut missing = policy.clone();
+        missing.tiers.remove(&KycLevel::L2);
+        assert!(missing.validate().is_err());
+
+        let json = serde_json::to_string(&policy).unwrap();
+        assert_eq!(serde_json::from_str::<KycPolicy>(&json).unwrap(), policy);
+    }
+}
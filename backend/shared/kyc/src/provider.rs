@@ -0,0 +1,173 @@
+//! External verification services
+//!
+//! A `KycProvider` checks an application and the documents submitted with
+//! it. It may approve or reject it outright, or leave the decision to an
+//! admin's review. `ManualReview` leaves every application to an admin;
+//! `HttpKycProvider` submits applications to a verification service over
+//! HTTP.
+
+use async_trait::async_trait;
+use base64::Engine;
+use flowex_types::{FlowExError, FlowExResult};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::{KycApplication, KycDocument};
+
+/// Timeout of requests to a verification service
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a provider decided on an application
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum ProviderDecision {
+    Approved,
+    Rejected { reason: String },
+    /// Left to an admin's review
+    Review,
+}
+
+/// A provider's answer to an application
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProviderVerdict {
+    #[serde(flatten)]
+    pub decision: ProviderDecision,
+    /// The provider's id of its check
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+/// Service verifying applications
+#[async_trait]
+pub trait KycProvider: std::fmt::Debug + Send + Sync {
+    /// Name recorded on the applications submitted to the provider
+    fn name(&self) -> &str;
+
+    /// Check `application`, submitted with `documents`
+    async fn verify(&self, application: &KycApplication, documents: &[KycDocument]) -> FlowExResult<ProviderVerdict>;
+}
+
+/// Provider leaving every application to an admin's review
+#[derive(Debug, Clone, Default)]
+pub struct ManualReview;
+
+#[async_trait]
+impl KycProvider for ManualReview {
+    fn name(&self) -> &str {
+        "manual"
+    }
+
+    async fn verify(&self, _application: &KycApplication, _documents: &[KycDocument]) -> FlowExResult<ProviderVerdict> {
+        Ok(ProviderVerdict {
+            decision: ProviderDecision::Review,
+            reference: None,
+        })
+    }
+}
+
+/// Document as submitted to a verification service
+#[derive(Debug, Serialize)]
+struct SubmittedDocument<'a> {
+    id: Uuid,
+    document_type: &'a str,
+    sha256: &'a str,
+    /// Base64 of the content
+    content: String,
+}
+
+/// Request to a verification service
+#[derive(Debug, Serialize)]
+struct VerificationRequest<'a> {
+    application: &'a KycApplication,
+    documents: Vec<SubmittedDocument<'a>>,
+}
+
+/// Provider POSTing applications, with their documents, to a verification
+/// service and taking its JSON `ProviderVerdict` as the answer
+#[derive(Debug, Clone)]
+pub struct HttpKycProvider {
+    name: String,
+    http: reqwest::Client,
+    url: String,
+}
+
+impl HttpKycProvider {
+    /// Provider `name`, submitting applications to `url`
+    pub fn new(name: &str, url: &str) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            name: name.to_string(),
+            http,
+            url: url.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl KycProvider for HttpKycProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn verify(&self, application: &KycApplication, documents: &[KycDocument]) -> FlowExResult<ProviderVerdict> {
+        let request = VerificationRequest {
+            application,
+            documents: documents
+                .iter()
+                .map(|document| SubmittedDocument {
+                    id: document.id,
+                    document_type: document.document_type.as_str(),
+                    sha256: &document.sha256,
+                    content: base64::engine::general_purpose::STANDARD.encode(&document.content),
+                })
+                .collect(),
+        };
+
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Verification service {} unavailable: {}", self.name, e)))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(FlowExError::Internal(format!(
+                "Verification service {} returned HTTP {}",
+                self.name, status
+            )));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Invalid verification service response: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：供应商结论的 JSON 格式
+    #[test]
+    fn test_verdict_format() {
+        let verdict: ProviderVerdict =
+            serde_json::from_str(r#"{"decision":"rejected","reason":"document expired","reference":"chk_1"}"#).unwrap();
+        assert_eq!(
+            verdict.decision,
+            ProviderDecision::Rejected {
+                reason: "document expired".to_string()
+            }
+        );
+        assert_eq!(verdict.reference.as_deref(), Some("chk_1"));
+
+        let verdict: ProviderVerdict = serde_json::from_str(r#"{"decision":"approved"}"#).unwrap();
+        assert_eq!(verdict.decision, ProviderDecision::Approved);
+        assert!(verdict.reference.is_none());
+    }
+}
@@ -0,0 +1,817 @@
+//! FlowEx Margin
+//!
+//! Cross-margin accounts. A user transfers collateral into their margin
+//! account and may borrow against it up to the configured leverage; debts
+//! accrue interest every hour. The health of an account is its margin
+//! level, the value of everything it holds over the value of everything it
+//! owes, both priced in the valuation asset:
+//!
+//! - borrowing, withdrawing and margin orders must leave the level at or
+//!   above the initial level, `max_leverage / (max_leverage - 1)`;
+//! - at or below `margin_call_level` the user is warned;
+//! - at or below `maintenance_level` the `LiquidationEngine` force-closes
+//!   the account through the matching engines and repays its debts from
+//!   the proceeds (see `liquidation`).
+
+use chrono::{DateTime, Duration, Utc};
+use flowex_types::{Fill, FlowExError, FlowExResult, OrderSide, TradingPair};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub mod liquidation;
+
+pub use liquidation::{Liquidation, LiquidationEngine};
+
+/// Margin parameters of the exchange
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarginConfig {
+    /// Asset accounts are valued in
+    pub valuation_asset: String,
+    /// Total assets an account may hold per unit of its own equity
+    pub max_leverage: Decimal,
+    /// Margin level at or below which the user is warned
+    pub margin_call_level: Decimal,
+    /// Margin level at or below which the account is liquidated
+    pub maintenance_level: Decimal,
+    /// Interest charged per hour on borrowed amounts (0.00001 = 0.001%)
+    pub default_hourly_rate: Decimal,
+    /// Hourly rates replacing `default_hourly_rate`, by currency
+    #[serde(default)]
+    pub hourly_rates: HashMap<String, Decimal>,
+}
+
+impl Default for MarginConfig {
+    fn default() -> Self {
+        Self {
+            valuation_asset: "USDT".to_string(),
+            max_leverage: Decimal::new(3, 0),
+            margin_call_level: Decimal::new(13, 1),
+            maintenance_level: Decimal::new(11, 1),
+            default_hourly_rate: Decimal::new(5, 6),
+            hourly_rates: HashMap::new(),
+        }
+    }
+}
+
+impl MarginConfig {
+    /// Read a margin configuration from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> FlowExResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| FlowExError::Internal(format!("Failed to read margin config {}: {}", path.display(), e)))?;
+        let config: Self = serde_json::from_str(&contents)
+            .map_err(|e| FlowExError::Validation(format!("Invalid margin config {}: {}", path.display(), e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that leverage exceeds 1, that the margin call comes before
+    /// liquidation and both before the initial level, and that rates are
+    /// not negative
+    pub fn validate(&self) -> FlowExResult<()> {
+        let invalid = |reason: &str| Err(FlowExError::Validation(format!("Margin config {}", reason)));
+        if self.max_leverage <= Decimal::ONE {
+            return invalid("must allow a leverage above 1");
+        }
+        if self.maintenance_level <= Decimal::ONE
+            || self.margin_call_level <= self.maintenance_level
+            || self.initial_level() <= self.margin_call_level
+        {
+            return invalid("must order 1 < maintenance level < margin call level < initial level");
+        }
+        if self.default_hourly_rate < Decimal::ZERO || self.hourly_rates.values().any(|rate| *rate < Decimal::ZERO) {
+            return invalid("may not have negative interest rates");
+        }
+        Ok(())
+    }
+
+    /// Lowest margin level borrowing or withdrawing may leave an account at
+    pub fn initial_level(&self) -> Decimal {
+        self.max_leverage / (self.max_leverage - Decimal::ONE)
+    }
+
+    /// Hourly interest rate of `currency`
+    pub fn hourly_rate(&self, currency: &str) -> Decimal {
+        self.hourly_rates.get(currency).copied().unwrap_or(self.default_hourly_rate)
+    }
+}
+
+/// Prices of assets in the valuation asset
+#[derive(Debug, Clone, Default)]
+pub struct AssetPrices {
+    valuation_asset: String,
+    prices: HashMap<String, Decimal>,
+}
+
+impl AssetPrices {
+    pub fn new(valuation_asset: &str) -> Self {
+        Self {
+            valuation_asset: valuation_asset.to_string(),
+            prices: HashMap::new(),
+        }
+    }
+
+    /// Set the price of one unit of `currency`
+    pub fn set(&mut self, currency: &str, price: Decimal) {
+        self.prices.insert(currency.to_string(), price);
+    }
+
+    /// Price of one unit of `currency`
+    pub fn price(&self, currency: &str) -> FlowExResult<Decimal> {
+        if currency == self.valuation_asset {
+            return Ok(Decimal::ONE);
+        }
+        self.prices
+            .get(currency)
+            .copied()
+            .ok_or_else(|| FlowExError::MarketData(format!("No price for {}", currency)))
+    }
+
+    /// Value of `amount` of `currency`
+    pub fn value(&self, currency: &str, amount: Decimal) -> FlowExResult<Decimal> {
+        if amount.is_zero() {
+            return Ok(Decimal::ZERO);
+        }
+        Ok(self.price(currency)? * amount)
+    }
+}
+
+/// A user's margin account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarginAccount {
+    pub user_id: Uuid,
+    /// Assets held, by currency. Negative only when a liquidation could not
+    /// cover what the account spent.
+    pub balances: HashMap<String, Decimal>,
+    /// Principal borrowed, by currency
+    pub borrowed: HashMap<String, Decimal>,
+    /// Interest owed on top of the principal, by currency
+    pub interest: HashMap<String, Decimal>,
+    /// Whether a liquidation is force-closing the account
+    pub liquidating: bool,
+    /// When interest was last charged
+    pub last_accrual: DateTime<Utc>,
+}
+
+impl MarginAccount {
+    fn new(user_id: Uuid, now: DateTime<Utc>) -> Self {
+        Self {
+            user_id,
+            balances: HashMap::new(),
+            borrowed: HashMap::new(),
+            interest: HashMap::new(),
+            liquidating: false,
+            last_accrual: now,
+        }
+    }
+
+    pub fn balance(&self, currency: &str) -> Decimal {
+        self.balances.get(currency).copied().unwrap_or_default()
+    }
+
+    /// Principal and interest owed in `currency`
+    pub fn liability(&self, currency: &str) -> Decimal {
+        self.borrowed.get(currency).copied().unwrap_or_default() + self.interest.get(currency).copied().unwrap_or_default()
+    }
+
+    /// What the account holds of `currency` beyond what it owes; negative
+    /// when it owes more
+    pub fn net(&self, currency: &str) -> Decimal {
+        self.balance(currency) - self.liability(currency)
+    }
+
+    /// Currencies the account holds or owes
+    pub fn currencies(&self) -> Vec<String> {
+        let mut currencies: Vec<String> = self
+            .balances
+            .keys()
+            .chain(self.borrowed.keys())
+            .chain(self.interest.keys())
+            .cloned()
+            .collect();
+        currencies.sort();
+        currencies.dedup();
+        currencies
+    }
+
+    /// Value of everything the account holds
+    pub fn assets_value(&self, prices: &AssetPrices) -> FlowExResult<Decimal> {
+        self.balances
+            .iter()
+            .map(|(currency, amount)| prices.value(currency, *amount))
+            .sum()
+    }
+
+    /// Value of everything the account owes
+    pub fn liabilities_value(&self, prices: &AssetPrices) -> FlowExResult<Decimal> {
+        self.currencies()
+            .iter()
+            .map(|currency| prices.value(currency, self.liability(currency)))
+            .sum()
+    }
+
+    /// Whether the account owes nothing
+    pub fn is_debt_free(&self) -> bool {
+        self.borrowed.is_empty() && self.interest.is_empty()
+    }
+
+    /// Assets over liabilities, `None` without debts
+    pub fn margin_level(&self, prices: &AssetPrices) -> FlowExResult<Option<Decimal>> {
+        let liabilities = self.liabilities_value(prices)?;
+        if liabilities <= Decimal::ZERO {
+            return Ok(None);
+        }
+        Ok(Some(self.assets_value(prices)? / liabilities))
+    }
+
+    fn add_balance(&mut self, currency: &str, amount: Decimal) {
+        *self.balances.entry(currency.to_string()).or_default() += amount;
+    }
+
+    /// Book buying or selling `quantity` of `trading_pair`'s base asset at `price`
+    fn trade(&mut self, trading_pair: &TradingPair, side: &OrderSide, quantity: Decimal, price: Decimal) {
+        let notional = price * quantity;
+        let (base, quote) = match side {
+            OrderSide::Buy => (quantity, -notional),
+            OrderSide::Sell => (-quantity, notional),
+        };
+        self.add_balance(&trading_pair.base_asset, base);
+        self.add_balance(&trading_pair.quote_asset, quote);
+    }
+
+    /// Pay down interest, then principal, of `currency` with up to `amount`
+    /// of the balance; returns the amount repaid
+    fn repay(&mut self, currency: &str, amount: Decimal) -> Decimal {
+        let amount = amount.min(self.liability(currency)).max(Decimal::ZERO);
+        let interest = self.interest.get(currency).copied().unwrap_or_default().min(amount);
+        let principal = amount - interest;
+        for (owed, paid) in [(&mut self.interest, interest), (&mut self.borrowed, principal)] {
+            if let Some(entry) = owed.get_mut(currency) {
+                *entry -= paid;
+                if entry.is_zero() {
+                    owed.remove(currency);
+                }
+            }
+        }
+        self.add_balance(currency, -amount);
+        amount
+    }
+}
+
+/// How close an account is to liquidation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarginStatus {
+    Normal,
+    /// At or below the margin call level
+    MarginCall,
+    /// At or below the maintenance level, or being liquidated
+    Liquidation,
+}
+
+/// Valuation of a margin account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountSummary {
+    pub user_id: Uuid,
+    pub assets_value: Decimal,
+    pub liabilities_value: Decimal,
+    /// `None` without debts
+    pub margin_level: Option<Decimal>,
+    pub status: MarginStatus,
+}
+
+/// Interest charged to an account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InterestCharge {
+    pub user_id: Uuid,
+    pub currency: String,
+    pub amount: Decimal,
+    /// Hours the charge covers
+    pub hours: i64,
+}
+
+/// Margin accounts of all users
+#[derive(Clone)]
+pub struct MarginManager {
+    config: Arc<MarginConfig>,
+    accounts: Arc<RwLock<HashMap<Uuid, MarginAccount>>>,
+}
+
+impl MarginManager {
+    pub fn new(config: MarginConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            accounts: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn config(&self) -> &MarginConfig {
+        &self.config
+    }
+
+    /// Margin account of `user_id`, if they have one
+    pub fn account(&self, user_id: Uuid) -> Option<MarginAccount> {
+        self.read().get(&user_id).cloned()
+    }
+
+    /// Valuation and status of `user_id`'s account
+    pub fn summary(&self, user_id: Uuid, prices: &AssetPrices) -> FlowExResult<AccountSummary> {
+        let accounts = self.read();
+        let account = accounts.get(&user_id).ok_or_else(|| no_account(user_id))?;
+        self.summarize(account, prices)
+    }
+
+    /// Move collateral into `user_id`'s margin account
+    pub fn deposit(&self, user_id: Uuid, currency: &str, amount: Decimal) -> FlowExResult<MarginAccount> {
+        positive(amount)?;
+        let mut accounts = self.write();
+        let account = accounts
+            .entry(user_id)
+            .or_insert_with(|| MarginAccount::new(user_id, Utc::now()));
+        account.add_balance(currency, amount);
+        Ok(account.clone())
+    }
+
+    /// Move collateral out of `user_id`'s margin account, as long as the
+    /// account stays at or above the initial margin level
+    pub fn withdraw(&self, user_id: Uuid, currency: &str, amount: Decimal, prices: &AssetPrices) -> FlowExResult<MarginAccount> {
+        positive(amount)?;
+        let mut accounts = self.write();
+        let account = self.active_account(&mut accounts, user_id)?;
+        if account.balance(currency) < amount {
+            return Err(FlowExError::Wallet(format!("Insufficient {} margin balance", currency)));
+        }
+
+        let mut after = account.clone();
+        after.add_balance(currency, -amount);
+        self.check_initial_level(&after, prices)?;
+        *account = after;
+        Ok(account.clone())
+    }
+
+    /// Most `user_id` can borrow of `currency` at `prices`
+    pub fn max_borrowable(&self, user_id: Uuid, currency: &str, prices: &AssetPrices) -> FlowExResult<Decimal> {
+        let accounts = self.read();
+        let account = accounts.get(&user_id).ok_or_else(|| no_account(user_id))?;
+        if account.liquidating {
+            return Ok(Decimal::ZERO);
+        }
+
+        // Borrowing x adds its value to both sides: (A + x) / (L + x) >= I
+        // holds while x <= (A - I * L) / (I - 1)
+        let initial = self.config.initial_level();
+        let headroom = account.assets_value(prices)? - initial * account.liabilities_value(prices)?;
+        let price = prices.price(currency)?;
+        if headroom <= Decimal::ZERO || price <= Decimal::ZERO {
+            return Ok(Decimal::ZERO);
+        }
+        Ok(headroom / (initial - Decimal::ONE) / price)
+    }
+
+    /// Lend `amount` of `currency` to `user_id`, crediting their account,
+    /// as long as it stays at or above the initial margin level
+    pub fn borrow(&self, user_id: Uuid, currency: &str, amount: Decimal, prices: &AssetPrices) -> FlowExResult<MarginAccount> {
+        positive(amount)?;
+        let mut accounts = self.write();
+        let account = self.active_account(&mut accounts, user_id)?;
+
+        let mut after = account.clone();
+        after.add_balance(currency, amount);
+        *after.borrowed.entry(currency.to_string()).or_default() += amount;
+        self.check_initial_level(&after, prices)?;
+        info!("User {} borrowed {} {}", user_id, amount, currency);
+        *account = after;
+        Ok(account.clone())
+    }
+
+    /// Repay up to `amount` of `user_id`'s debt in `currency` from their
+    /// balance, interest first
+    pub fn repay(&self, user_id: Uuid, currency: &str, amount: Decimal) -> FlowExResult<MarginAccount> {
+        positive(amount)?;
+        let mut accounts = self.write();
+        let account = accounts.get_mut(&user_id).ok_or_else(|| no_account(user_id))?;
+        if account.liability(currency).is_zero() {
+            return Err(FlowExError::Validation(format!("No {} debt to repay", currency)));
+        }
+        if account.balance(currency) < amount.min(account.liability(currency)) {
+            return Err(FlowExError::Wallet(format!("Insufficient {} margin balance", currency)));
+        }
+        let repaid = account.repay(currency, amount);
+        info!("User {} repaid {} {}", user_id, repaid, currency);
+        Ok(account.clone())
+    }
+
+    /// Refuse a margin order on `trading_pair` that would take `user_id`'s
+    /// account beyond the allowed leverage if filled at `price`: what it
+    /// spends beyond the account's balance is borrowed, and the account
+    /// must stay at or above the initial margin level afterwards
+    pub fn check_order(
+        &self,
+        user_id: Uuid,
+        trading_pair: &TradingPair,
+        side: OrderSide,
+        quantity: Decimal,
+        price: Decimal,
+        prices: &AssetPrices,
+    ) -> FlowExResult<()> {
+        positive(quantity)?;
+        positive(price)?;
+        let accounts = self.read();
+        let account = accounts.get(&user_id).ok_or_else(|| no_account(user_id))?;
+        if account.liquidating {
+            return Err(FlowExError::Trading("Margin account is being liquidated".to_string()));
+        }
+
+        let (spent, amount) = match side {
+            OrderSide::Buy => (&trading_pair.quote_asset, price * quantity),
+            OrderSide::Sell => (&trading_pair.base_asset, quantity),
+        };
+        let mut after = account.clone();
+        let borrowed = amount - after.balance(spent).max(Decimal::ZERO);
+        if borrowed > Decimal::ZERO {
+            after.add_balance(spent, borrowed);
+            *after.borrowed.entry(spent.clone()).or_default() += borrowed;
+        }
+        after.trade(trading_pair, &side, quantity, price);
+        self.check_initial_level(&after, prices)
+    }
+
+    /// Price of `currency` at which `user_id`'s account would fall to the
+    /// maintenance level, other prices unchanged; `None` if no price of it
+    /// would, and for the valuation asset, whose price is fixed
+    pub fn liquidation_price(&self, user_id: Uuid, currency: &str, prices: &AssetPrices) -> FlowExResult<Option<Decimal>> {
+        let accounts = self.read();
+        let account = accounts.get(&user_id).ok_or_else(|| no_account(user_id))?;
+        if currency == self.config.valuation_asset {
+            return Ok(None);
+        }
+        let maintenance = self.config.maintenance_level;
+
+        // With the rest of the account worth A and owing L, holding b and
+        // owing l of the currency, (A + b * p) / (L + l * p) = M at
+        // p = (M * L - A) / (b - M * l)
+        let held = account.balance(currency);
+        let owed = account.liability(currency);
+        let other_assets = account.assets_value(prices)? - prices.value(currency, held)?;
+        let other_liabilities = account.liabilities_value(prices)? - prices.value(currency, owed)?;
+        let exposure = held - maintenance * owed;
+        if exposure.is_zero() || (other_liabilities.is_zero() && owed.is_zero()) {
+            return Ok(None);
+        }
+        let price = (maintenance * other_liabilities - other_assets) / exposure;
+        Ok((price > Decimal::ZERO).then_some(price))
+    }
+
+    /// Book a fill of one of the account owner's margin orders on
+    /// `trading_pair`
+    pub fn apply_fill(&self, fill: &Fill, trading_pair: &TradingPair) -> FlowExResult<()> {
+        let mut accounts = self.write();
+        let account = accounts.get_mut(&fill.user_id).ok_or_else(|| no_account(fill.user_id))?;
+        account.trade(trading_pair, &fill.side, fill.quantity, fill.price);
+        if let Some(fee_currency) = &fill.fee_currency {
+            account.add_balance(fee_currency, -fill.fee);
+        }
+        Ok(())
+    }
+
+    /// Charge every account with debts the interest of each full hour
+    /// since it was last charged
+    pub fn accrue_interest(&self, now: DateTime<Utc>) -> Vec<InterestCharge> {
+        let mut charges = Vec::new();
+        for account in self.write().values_mut() {
+            let hours = (now - account.last_accrual).num_hours();
+            if hours <= 0 {
+                continue;
+            }
+            account.last_accrual += Duration::hours(hours);
+            for (currency, principal) in &account.borrowed {
+                let amount = *principal * self.config.hourly_rate(currency) * Decimal::from(hours);
+                if amount.is_zero() {
+                    continue;
+                }
+                *account.interest.entry(currency.clone()).or_default() += amount;
+                charges.push(InterestCharge {
+                    user_id: account.user_id,
+                    currency: currency.clone(),
+                    amount,
+                    hours,
+                });
+            }
+        }
+        charges
+    }
+
+    /// Accounts at or below the margin call level, including those due for
+    /// liquidation. Accounts that cannot be priced are skipped.
+    pub fn at_risk(&self, prices: &AssetPrices) -> Vec<AccountSummary> {
+        self.read()
+            .values()
+            .filter(|account| !account.is_debt_free())
+            .filter_map(|account| match self.summarize(account, prices) {
+                Ok(summary) => Some(summary),
+                Err(e) => {
+                    warn!("Cannot value the margin account of {}: {}", account.user_id, e);
+                    None
+                }
+            })
+            .filter(|summary| summary.status != MarginStatus::Normal)
+            .collect()
+    }
+
+    /// Mark an account as being liquidated, blocking borrowing and
+    /// withdrawals; false if it already was
+    pub(crate) fn begin_liquidation(&self, user_id: Uuid) -> bool {
+        match self.write().get_mut(&user_id) {
+            Some(account) if !account.liquidating => {
+                account.liquidating = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Repay every debt the account's balances cover and end its liquidation
+    pub(crate) fn finish_liquidation(&self, user_id: Uuid) -> Option<MarginAccount> {
+        let mut accounts = self.write();
+        let account = accounts.get_mut(&user_id)?;
+        for currency in account.currencies() {
+            let available = account.balance(currency.as_str());
+            account.repay(&currency, available);
+        }
+        account.liquidating = false;
+        Some(account.clone())
+    }
+
+    fn summarize(&self, account: &MarginAccount, prices: &AssetPrices) -> FlowExResult<AccountSummary> {
+        let margin_level = account.margin_level(prices)?;
+        let status = match margin_level {
+            _ if account.liquidating => MarginStatus::Liquidation,
+            Some(level) if level <= self.config.maintenance_level => MarginStatus::Liquidation,
+            Some(level) if level <= self.config.margin_call_level => MarginStatus::MarginCall,
+            _ => MarginStatus::Normal,
+        };
+        Ok(AccountSummary {
+            user_id: account.user_id,
+            assets_value: account.assets_value(prices)?,
+            liabilities_value: account.liabilities_value(prices)?,
+            margin_level,
+            status,
+        })
+    }
+
+    /// Account of `user_id`, refusing changes while it is liquidated
+    fn active_account<'a>(
+        &self,
+        accounts: &'a mut HashMap<Uuid, MarginAccount>,
+        user_id: Uuid,
+    ) -> FlowExResult<&'a mut MarginAccount> {
+        let account = accounts.get_mut(&user_id).ok_or_else(|| no_account(user_id))?;
+        if account.liquidating {
+            return Err(FlowExError::Trading("Margin account is being liquidated".to_string()));
+        }
+        Ok(account)
+    }
+
+    fn check_initial_level(&self, account: &MarginAccount, prices: &AssetPrices) -> FlowExResult<()> {
+        match account.margin_level(prices)? {
+            Some(level) if level < self.config.initial_level() => Err(FlowExError::Wallet(format!(
+                "Margin level {} would fall below the initial level {}",
+                level.round_dp(4),
+                self.config.initial_level().round_dp(4)
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<Uuid, MarginAccount>> {
+        self.accounts.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<Uuid, MarginAccount>> {
+        self.accounts.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn positive(amount: Decimal) -> FlowExResult<()> {
+    if amount <= Decimal::ZERO {
+        return Err(FlowExError::Validation("Amount must be positive".to_string()));
+    }
+    Ok(())
+}
+
+fn no_account(user_id: Uuid) -> FlowExError {
+    FlowExError::Validation(format!("User {} has no margin account", user_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prices(btc: i64) -> AssetPrices {
+        let mut prices = AssetPrices::new("USDT");
+        prices.set("BTC", Decimal::new(btc, 0));
+        prices
+    }
+
+    fn btc_usdt() -> TradingPair {
+        TradingPair {
+            symbol: "BTC-USDT".to_string(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            status: flowex_types::TradingStatus::Trading,
+            min_price: Decimal::new(1, 2),
+            max_price: Decimal::new(1_000_000, 0),
+            min_qty: Decimal::new(1, 5),
+            max_qty: Decimal::new(1000, 0),
+            step_size: Decimal::new(1, 5),
+            tick_size: Decimal::new(1, 2),
+            min_notional: Decimal::ZERO,
+        }
+    }
+
+    /// Account that borrowed 0.04 BTC at 50000 and holds `usdt` USDT, as
+    /// after selling the BTC
+    fn short_btc(margin: &MarginManager, usdt: i64) -> Uuid {
+        let user_id = Uuid::new_v4();
+        margin.deposit(user_id, "USDT", Decimal::new(1000, 0)).unwrap();
+        margin.borrow(user_id, "BTC", Decimal::new(4, 2), &prices(50_000)).unwrap();
+        let mut account = margin.account(user_id).unwrap();
+        account.balances = HashMap::from([("USDT".to_string(), Decimal::new(usdt, 0))]);
+        margin.write().insert(user_id, account);
+        user_id
+    }
+
+    /// 测试：借款额度受初始保证金水平限制，每小时计息，还款先还利息
+    #[test]
+    fn test_borrow_interest_and_repay() {
+        let margin = MarginManager::new(MarginConfig::default());
+        let user_id = Uuid::new_v4();
+        let prices = prices(50_000);
+
+        assert!(margin.borrow(user_id, "BTC", Decimal::ONE, &prices).is_err());
+        margin.deposit(user_id, "USDT", Decimal::new(1000, 0)).unwrap();
+
+        // 3倍杠杆：1000 USDT 的权益最多借入价值 2000 USDT 的资产
+        assert_eq!(margin.max_borrowable(user_id, "BTC", &prices).unwrap(), Decimal::new(4, 2));
+        assert!(margin.borrow(user_id, "BTC", Decimal::new(5, 2), &prices).is_err());
+        let account = margin.borrow(user_id, "BTC", Decimal::new(4, 2), &prices).unwrap();
+        assert_eq!(account.balance("BTC"), Decimal::new(4, 2));
+        assert_eq!(account.margin_level(&prices).unwrap(), Some(Decimal::new(15, 1)));
+        assert!(margin.withdraw(user_id, "USDT", Decimal::ONE, &prices).is_err());
+
+        // 不足一小时不计息
+        let start = account.last_accrual;
+        assert!(margin.accrue_interest(start + Duration::minutes(59)).is_empty());
+        let charges = margin.accrue_interest(start + Duration::minutes(150));
+        assert_eq!(charges.len(), 1);
+        assert_eq!(charges[0].hours, 2);
+        assert_eq!(charges[0].amount, Decimal::new(4, 2) * Decimal::new(5, 6) * Decimal::TWO);
+
+        let account = margin.repay(user_id, "BTC", Decimal::new(1, 2)).unwrap();
+        assert!(account.interest.is_empty());
+        assert_eq!(account.borrowed["BTC"], Decimal::new(3, 2) + charges[0].amount);
+        assert!(margin.repay(user_id, "BTC", Decimal::ONE).is_err());
+        margin.deposit(user_id, "BTC", Decimal::new(1, 3)).unwrap();
+        let account = margin.repay(user_id, "BTC", Decimal::ONE).unwrap();
+        assert!(account.borrowed.is_empty());
+        assert_eq!(margin.summary(user_id, &prices).unwrap().margin_level, None);
+    }
+
+    /// 测试：价格变动后保证金水平跌至追加保证金线与强平线
+    #[test]
+    fn test_margin_status() {
+        let margin = MarginManager::new(MarginConfig::default());
+        let user_id = Uuid::new_v4();
+        margin.deposit(user_id, "USDT", Decimal::new(1000, 0)).unwrap();
+        margin.borrow(user_id, "BTC", Decimal::new(4, 2), &prices(50_000)).unwrap();
+        // 借入的BTC卖出为USDT，账户做空BTC
+        let mut account = margin.account(user_id).unwrap();
+        account.balances.insert("BTC".to_string(), Decimal::ZERO);
+        account.balances.insert("USDT".to_string(), Decimal::new(3000, 0));
+        margin.write().insert(user_id, account);
+
+        assert!(margin.at_risk(&prices(50_000)).is_empty());
+        let at_risk = margin.at_risk(&prices(60_000));
+        assert_eq!(at_risk[0].status, MarginStatus::MarginCall);
+        assert_eq!(at_risk[0].margin_level, Some(Decimal::new(125, 2)));
+        assert_eq!(margin.at_risk(&prices(70_000))[0].status, MarginStatus::Liquidation);
+        assert!(margin.at_risk(&AssetPrices::new("USDT")).is_empty());
+
+        let invalid = MarginConfig {
+            maintenance_level: Decimal::new(14, 1),
+            ..MarginConfig::default()
+        };
+        assert!(invalid.validate().is_err());
+        assert!(MarginConfig::default().validate().is_ok());
+    }
+
+    /// 测试：保证金水平恰好等于维持保证金水平时强平，恰好等于追加保证金线时追加保证金，高出一点则降一级
+    #[test]
+    fn test_status_boundaries() {
+        let margin = MarginManager::new(MarginConfig::default());
+        let prices = prices(50_000);
+        let status = |usdt: i64| margin.summary(short_btc(&margin, usdt), &prices).unwrap();
+
+        // 负债 0.04 BTC × 50000 = 2000 USDT
+        let at_maintenance = status(2200);
+        assert_eq!(at_maintenance.margin_level, Some(Decimal::new(11, 1)));
+        assert_eq!(at_maintenance.status, MarginStatus::Liquidation);
+        assert_eq!(status(2201).status, MarginStatus::MarginCall);
+        let at_margin_call = status(2600);
+        assert_eq!(at_margin_call.margin_level, Some(Decimal::new(13, 1)));
+        assert_eq!(at_margin_call.status, MarginStatus::MarginCall);
+        assert_eq!(status(2601).status, MarginStatus::Normal);
+
+        assert_eq!(margin.at_risk(&prices).len(), 3);
+    }
+
+    /// 测试：多头与空头仓位的强平价格，价格到达强平价时恰好触及维持保证金水平
+    #[test]
+    fn test_liquidation_price() {
+        let margin = MarginManager::new(MarginConfig::default());
+        let btc = btc_usdt();
+
+        // 多头：1000 USDT 本金借入 1000 USDT，以 50000 买入 0.04 BTC；1.1 × 1000 / 0.04 = 27500
+        let long = Uuid::new_v4();
+        margin.deposit(long, "USDT", Decimal::new(1000, 0)).unwrap();
+        margin.borrow(long, "USDT", Decimal::new(1000, 0), &prices(50_000)).unwrap();
+        margin.write().get_mut(&long).unwrap().trade(&btc, &OrderSide::Buy, Decimal::new(4, 2), Decimal::new(50_000, 0));
+        assert_eq!(margin.liquidation_price(long, "BTC", &prices(50_000)).unwrap(), Some(Decimal::new(27_500, 0)));
+        assert_eq!(margin.summary(long, &prices(27_500)).unwrap().status, MarginStatus::Liquidation);
+        assert_eq!(margin.summary(long, &prices(27_501)).unwrap().status, MarginStatus::MarginCall);
+
+        // 空头：借入 0.04 BTC 卖出后持有 2750 USDT；2750 / (1.1 × 0.04) = 62500
+        let short = short_btc(&margin, 2750);
+        assert_eq!(margin.liquidation_price(short, "BTC", &prices(50_000)).unwrap(), Some(Decimal::new(62_500, 0)));
+        assert_eq!(margin.summary(short, &prices(62_500)).unwrap().status, MarginStatus::Liquidation);
+        assert_eq!(margin.summary(short, &prices(62_499)).unwrap().status, MarginStatus::MarginCall);
+
+        // 没有负债或价格变动无法触及强平的账户没有强平价
+        let unlevered = Uuid::new_v4();
+        margin.deposit(unlevered, "BTC", Decimal::ONE).unwrap();
+        assert_eq!(margin.liquidation_price(unlevered, "BTC", &prices(50_000)).unwrap(), None);
+        assert_eq!(margin.liquidation_price(long, "USDT", &prices(50_000)).unwrap(), None);
+        assert!(margin.liquidation_price(Uuid::new_v4(), "BTC", &prices(50_000)).is_err());
+    }
+
+    /// 测试：权益为零或为负的账户处于强平状态，不能再借款或提取，没有权益的账户无法借款
+    #[test]
+    fn test_zero_and_negative_equity() {
+        let margin = MarginManager::new(MarginConfig::default());
+        let prices = prices(50_000);
+
+        for usdt in [2000, 1800] {
+            let user_id = short_btc(&margin, usdt);
+            let summary = margin.summary(user_id, &prices).unwrap();
+            assert!(summary.assets_value <= summary.liabilities_value);
+            assert!(summary.margin_level.unwrap() <= Decimal::ONE);
+            assert_eq!(summary.status, MarginStatus::Liquidation);
+            assert_eq!(margin.max_borrowable(user_id, "BTC", &prices).unwrap(), Decimal::ZERO);
+            assert!(margin.borrow(user_id, "USDT", Decimal::ONE, &prices).is_err());
+            assert!(margin.withdraw(user_id, "USDT", Decimal::ONE, &prices).is_err());
+        }
+
+        // 资产全部提走后没有可借额度，借入任何数量都会使保证金水平降至 1
+        let empty = Uuid::new_v4();
+        margin.deposit(empty, "USDT", Decimal::new(100, 0)).unwrap();
+        margin.withdraw(empty, "USDT", Decimal::new(100, 0), &prices).unwrap();
+        assert_eq!(margin.max_borrowable(empty, "BTC", &prices).unwrap(), Decimal::ZERO);
+        assert!(margin.borrow(empty, "BTC", Decimal::new(1, 5), &prices).is_err());
+        assert!(margin.deposit(empty, "USDT", Decimal::ZERO).is_err());
+    }
+
+    /// 测试：保证金订单成交后超过最大杠杆（低于初始保证金水平）时被拒绝，恰好达到最大杠杆时允许
+    #[test]
+    fn test_orders_beyond_max_leverage_are_rejected() {
+        let margin = MarginManager::new(MarginConfig::default());
+        let btc = btc_usdt();
+        let prices = prices(50_000);
+        let user_id = Uuid::new_v4();
+        let price = Decimal::new(50_000, 0);
+        assert!(margin.check_order(user_id, &btc, OrderSide::Buy, Decimal::ONE, price, &prices).is_err());
+        margin.deposit(user_id, "USDT", Decimal::new(1000, 0)).unwrap();
+
+        // 3倍杠杆：1000 USDT 的权益最多持有价值 3000 USDT 的资产
+        margin.check_order(user_id, &btc, OrderSide::Buy, Decimal::new(6, 2), price, &prices).unwrap();
+        assert!(margin.check_order(user_id, &btc, OrderSide::Buy, Decimal::new(6001, 5), price, &prices).is_err());
+        // 做空：卖出借入的 BTC，最多 2000 USDT
+        margin.check_order(user_id, &btc, OrderSide::Sell, Decimal::new(4, 2), price, &prices).unwrap();
+        assert!(margin.check_order(user_id, &btc, OrderSide::Sell, Decimal::new(4001, 5), price, &prices).is_err());
+        // 以高于市价的价格买入会损失价值，同样受限
+        assert!(margin
+            .check_order(user_id, &btc, OrderSide::Buy, Decimal::new(6, 2), Decimal::new(50_100, 0), &prices)
+            .is_err());
+        // 检查不改变账户
+        assert!(margin.account(user_id).unwrap().is_debt_free());
+
+        assert!(margin.begin_liquidation(user_id));
+        assert!(margin.check_order(user_id, &btc, OrderSide::Buy, Decimal::new(1, 2), price, &prices).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Liquidation engine
+//!
+//! Force-closes margin accounts that fell to the maintenance level. Every
+//! asset an account holds beyond its debts is sold for the valuation asset,
+//! and every asset it owes more of than it holds is bought back, with market
+//! orders on the pair quoting that asset in the valuation asset; sells go
+//! first so their proceeds fund the buys. The fills are booked to the
+//! account, its debts are repaid from the resulting balances, and whatever
+//! they could not cover is reported as a shortfall.
+
+use crate::{AssetPrices, MarginAccount, MarginManager, MarginStatus};
+use chrono::Utc;
+use flowex_matching_engine::actor::MatchingEngineHandle;
+use flowex_types::{FlowExResult, Order, OrderSide, OrderStatus, OrderType, TimeInForce, Trade, TradingPair};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Outcome of liquidating one account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Liquidation {
+    pub user_id: Uuid,
+    /// Margin level that triggered the liquidation
+    pub margin_level: Option<Decimal>,
+    /// Closing orders, as they ended
+    pub orders: Vec<Order>,
+    pub trades: Vec<Trade>,
+    /// Value of the debts left unpaid
+    pub shortfall: Decimal,
+}
+
+/// Pair an asset is closed on, and its matching engine
+struct Market {
+    trading_pair: TradingPair,
+    engine: MatchingEngineHandle,
+}
+
+/// Liquidates margin accounts through the matching engines
+pub struct LiquidationEngine {
+    margin: MarginManager,
+    /// Markets by base asset
+    markets: HashMap<String, Market>,
+}
+
+impl LiquidationEngine {
+    pub fn new(margin: MarginManager) -> Self {
+        Self {
+            margin,
+            markets: HashMap::new(),
+        }
+    }
+
+    /// Close positions in `trading_pair`'s base asset on `engine`. Pairs not
+    /// quoted in the valuation asset are ignored.
+    pub fn add_market(&mut self, trading_pair: TradingPair, engine: MatchingEngineHandle) {
+        if trading_pair.quote_asset != self.margin.config().valuation_asset {
+            return;
+        }
+        self.markets.insert(
+            trading_pair.base_asset.clone(),
+            Market { trading_pair, engine },
+        );
+    }
+
+    /// Liquidate every account at or below the maintenance level at `prices`
+    pub async fn run(&self, prices: &AssetPrices) -> Vec<Liquidation> {
+        let mut liquidations = Vec::new();
+        for summary in self.margin.at_risk(prices) {
+            if summary.status != MarginStatus::Liquidation || !self.margin.begin_liquidation(summary.user_id) {
+                continue;
+            }
+            warn!(
+                "Liquidating the margin account of {} at margin level {:?}",
+                summary.user_id, summary.margin_level
+            );
+            liquidations.push(self.liquidate(summary.user_id, summary.margin_level, prices).await);
+        }
+        liquidations
+    }
+
+    /// Close out an account marked as liquidating
+    async fn liquidate(&self, user_id: Uuid, margin_level: Option<Decimal>, prices: &AssetPrices) -> Liquidation {
+        let mut liquidation = Liquidation {
+            user_id,
+            margin_level,
+            orders: Vec::new(),
+            trades: Vec::new(),
+            shortfall: Decimal::ZERO,
+        };
+
+        if let Some(account) = self.margin.account(user_id) {
+            for (order, market) in self.closing_orders(&account) {
+                match self.execute(order, market).await {
+                    Ok((order, trades)) => {
+                        liquidation.orders.push(order);
+                        liquidation.trades.extend(trades);
+                    }
+                    Err(e) => warn!("Liquidation order of {} failed: {}", user_id, e),
+                }
+            }
+        }
+
+        if let Some(account) = self.margin.finish_liquidation(user_id) {
+            liquidation.shortfall = shortfall(&account, prices);
+        }
+        info!(
+            "Liquidated the margin account of {}: {} trades, shortfall {}",
+            user_id,
+            liquidation.trades.len(),
+            liquidation.shortfall
+        );
+        liquidation
+    }
+
+    /// Market orders closing every position of the account, sells first
+    fn closing_orders(&self, account: &MarginAccount) -> Vec<(Order, &Market)> {
+        let mut orders: Vec<(Order, &Market)> = account
+            .currencies()
+            .iter()
+            .filter(|currency| **currency != self.margin.config().valuation_asset)
+            .filter_map(|currency| {
+                let Some(market) = self.markets.get(currency) else {
+                    warn!("No market to close {} {} of {}", account.net(currency), currency, account.user_id);
+                    return None;
+                };
+                let net = account.net(currency);
+                let step = market.trading_pair.step_size;
+                let (side, quantity) = if net > Decimal::ZERO {
+                    (OrderSide::Sell, round_to_step(net, step, false))
+                } else {
+                    (OrderSide::Buy, round_to_step(-net, step, true))
+                };
+                (quantity > Decimal::ZERO).then(|| (closing_order(account.user_id, &market.trading_pair, side, quantity), market))
+            })
+            .collect();
+        orders.sort_by_key(|(order, _)| order.side == OrderSide::Buy);
+        orders
+    }
+
+    /// Submit a closing order and book its fills to the account
+    async fn execute(&self, order: Order, market: &Market) -> FlowExResult<(Order, Vec<Trade>)> {
+        let report = market.engine.add_order(order).await?;
+        for fill in report.trades.iter().flat_map(Trade::fills) {
+            if fill.order_id == report.order.id {
+                self.margin.apply_fill(&fill, &market.trading_pair)?;
+            }
+        }
+        Ok((report.order, report.trades))
+    }
+}
+
+fn closing_order(user_id: Uuid, trading_pair: &TradingPair, side: OrderSide, quantity: Decimal) -> Order {
+    let now = Utc::now();
+    Order {
+        id: Uuid::new_v4(),
+        user_id,
+        trading_pair: trading_pair.symbol.clone(),
+        side,
+        order_type: OrderType::Market,
+        price: None,
+        stop_price: None,
+        quantity,
+        filled_quantity: Decimal::ZERO,
+        remaining_quantity: quantity,
+        time_in_force: TimeInForce::Ioc,
+        post_only: false,
+        display_quantity: None,
+        expires_at: None,
+        reduce_only: false,
+        client_order_id: None,
+        status: OrderStatus::New,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+/// `quantity` as a multiple of `step`, rounded up or down; unchanged
+/// without a step
+fn round_to_step(quantity: Decimal, step: Decimal, up: bool) -> Decimal {
+    if step <= Decimal::ZERO {
+        return quantity;
+    }
+    let steps = quantity / step;
+    (if up { steps.ceil() } else { steps.floor() }) * step
+}
+
+/// Value of the debts the account's assets no longer cover
+fn shortfall(account: &MarginAccount, prices: &AssetPrices) -> Decimal {
+    let value = account
+        .liabilities_value(prices)
+        .and_then(|liabilities| Ok(liabilities - account.assets_value(prices)?));
+    match value {
+        Ok(value) => value.max(Decimal::ZERO),
+        Err(e) => {
+            warn!("Cannot value the shortfall of {}: {}", account.user_id, e);
+            Decimal::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MarginConfig;
+    use flowex_matching_engine::actor::ActorConfig;
+    use flowex_matching_engine::MatchingEngine;
+    use flowex_types::{Fill, Liquidity, TradingStatus};
+
+    fn btc_usdt() -> TradingPair {
+        TradingPair {
+            symbol: "BTC-USDT".to_string(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            status: TradingStatus::Trading,
+            min_price: Decimal::new(1, 2),
+            max_price: Decimal::new(1_000_000, 0),
+            min_qty: Decimal::new(1, 5),
+            max_qty: Decimal::new(1000, 0),
+            step_size: Decimal::new(1, 5),
+            tick_size: Decimal::new(1, 2),
+            min_notional: Decimal::ZERO,
+        }
+    }
+
+    /// 测试：做空账户跌破维持保证金水平后通过撮合引擎买回借入资产并还款
+    #[tokio::test]
+    async fn test_liquidate_short_position() {
+        let margin = MarginManager::new(MarginConfig::default());
+        let user_id = Uuid::new_v4();
+        let mut prices = AssetPrices::new("USDT");
+        prices.set("BTC", Decimal::new(50_000, 0));
+
+        // 存入1000 USDT，借入0.04 BTC并以50000卖出
+        margin.deposit(user_id, "USDT", Decimal::new(1000, 0)).unwrap();
+        margin.borrow(user_id, "BTC", Decimal::new(4, 2), &prices).unwrap();
+        let sell = Fill {
+            trade_id: Uuid::new_v4(),
+            order_id: Uuid::new_v4(),
+            user_id,
+            symbol: "BTC-USDT".to_string(),
+            side: OrderSide::Sell,
+            price: Decimal::new(50_000, 0),
+            quantity: Decimal::new(4, 2),
+            fee: Decimal::ZERO,
+            fee_currency: None,
+            liquidity: Liquidity::Taker,
+            timestamp: Utc::now(),
+        };
+        margin.apply_fill(&sell, &btc_usdt()).unwrap();
+
+        let (engine, _task) =
+            MatchingEngineHandle::spawn(MatchingEngine::with_trading_pair(btc_usdt()), ActorConfig::default());
+        let mut liquidations = LiquidationEngine::new(margin.clone());
+        liquidations.add_market(btc_usdt(), engine.clone());
+        assert!(liquidations.run(&prices).await.is_empty());
+
+        // 价格涨至70000：保证金水平 3000 / 2800 < 1.1
+        let ask = closing_order(Uuid::new_v4(), &btc_usdt(), OrderSide::Sell, Decimal::ONE);
+        engine
+            .add_order(Order {
+                order_type: OrderType::Limit,
+                price: Some(Decimal::new(70_000, 0)),
+                time_in_force: TimeInForce::Gtc,
+                ..ask
+            })
+            .await
+            .unwrap();
+        prices.set("BTC", Decimal::new(70_000, 0));
+
+        let liquidated = liquidations.run(&prices).await;
+        assert_eq!(liquidated.len(), 1);
+        assert_eq!(liquidated[0].orders[0].side, OrderSide::Buy);
+        assert_eq!(liquidated[0].orders[0].filled_quantity, Decimal::new(4, 2));
+        assert_eq!(liquidated[0].shortfall, Decimal::ZERO);
+
+        let account = margin.account(user_id).unwrap();
+        assert!(account.is_debt_free());
+        assert!(!account.liquidating);
+        assert_eq!(account.balance("USDT"), Decimal::new(200, 0));
+        assert_eq!(account.balance("BTC"), Decimal::ZERO);
+    }
+}
@@ -0,0 +1,184 @@
+//! Matching engine benchmarks
+//!
+//! Measures order adds, cancels and matches per second across book shapes:
+//! deep books spread over many price levels, crossing storms where every
+//! order trades, and pathological queues with every order at one price.
+//! Run with `cargo bench -p flowex-matching-engine`; criterion keeps the
+//! previous run as a baseline and reports regressions against it.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use flowex_matching_engine::MatchingEngine;
+use flowex_test_support::{create_limit_order, create_market_order};
+use flowex_types::{Order, OrderSide, TimeInForce};
+use rust_decimal::Decimal;
+use std::hint::black_box;
+use uuid::Uuid;
+
+const SYMBOL: &str = "BTCUSDT";
+const MID_PRICE: i64 = 50_000;
+const BOOK_SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+/// Deterministic xorshift so every run sees the same book
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> i64 {
+        (self.next() % bound) as i64
+    }
+}
+
+fn market_order(side: OrderSide, quantity: i64) -> Order {
+    Order {
+        time_in_force: TimeInForce::Ioc,
+        ..create_market_order(side, quantity)
+    }
+}
+
+/// Non-crossing resting orders spread over up to 500 levels per side
+fn deep_book_orders(count: usize) -> Vec<Order> {
+    let mut rng = Rng(0x5eed);
+    (0..count)
+        .map(|i| {
+            let offset = 1 + rng.below(500);
+            let quantity = 1 + rng.below(10);
+            if i % 2 == 0 {
+                create_limit_order(OrderSide::Buy, MID_PRICE - offset, quantity)
+            } else {
+                create_limit_order(OrderSide::Sell, MID_PRICE + offset, quantity)
+            }
+        })
+        .collect()
+}
+
+/// Resting asks at a single price
+fn single_price_orders(count: usize) -> Vec<Order> {
+    (0..count).map(|_| create_limit_order(OrderSide::Sell, MID_PRICE, 1)).collect()
+}
+
+fn engine_with(orders: &[Order]) -> MatchingEngine {
+    let mut engine = MatchingEngine::new(SYMBOL.to_string());
+    for order in orders {
+        engine.add_order(order.clone()).expect("benchmark order rejected");
+    }
+    engine.drain_events();
+    engine.drain_book_update();
+    engine
+}
+
+/// Resting adds into an empty engine
+fn bench_add(c: &mut Criterion) {
+    let mut group = c.benchmark_group("add");
+    for size in BOOK_SIZES {
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("deep_book", size), &size, |b, &size| {
+            b.iter_batched(
+                || deep_book_orders(size),
+                |orders| black_box(engine_with(&orders)),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("single_price", size), &size, |b, &size| {
+            b.iter_batched(
+                || single_price_orders(size),
+                |orders| black_box(engine_with(&orders)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Cancelling every resting order in a random order
+fn bench_cancel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cancel");
+    for size in BOOK_SIZES {
+        group.throughput(Throughput::Elements(size as u64));
+        for (shape, orders) in [("deep_book", deep_book_orders(size)), ("single_price", single_price_orders(size))] {
+            let mut ids: Vec<Uuid> = orders.iter().map(|order| order.id).collect();
+            let mut rng = Rng(0xcafe);
+            for i in (1..ids.len()).rev() {
+                ids.swap(i, rng.below(i as u64 + 1) as usize);
+            }
+
+            group.bench_with_input(BenchmarkId::new(shape, size), &ids, |b, ids| {
+                b.iter_batched(
+                    || engine_with(&orders),
+                    |mut engine| {
+                        for id in ids {
+                            black_box(engine.cancel_order(*id).unwrap());
+                        }
+                        engine
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Orders that trade on arrival
+fn bench_match(c: &mut Criterion) {
+    let mut group = c.benchmark_group("match");
+    for size in BOOK_SIZES {
+        group.throughput(Throughput::Elements(size as u64));
+
+        // Crossing storm: every incoming limit order takes one resting order
+        let resting = single_price_orders(size);
+        let storm: Vec<Order> = (0..size).map(|_| create_limit_order(OrderSide::Buy, MID_PRICE + 100, 1)).collect();
+        group.bench_with_input(BenchmarkId::new("crossing_storm", size), &storm, |b, storm| {
+            b.iter_batched(
+                || (engine_with(&resting), storm.clone()),
+                |(mut engine, storm)| {
+                    for order in storm {
+                        black_box(engine.add_order(order).unwrap());
+                    }
+                    engine
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        // One market order sweeping a whole single-price queue
+        group.bench_with_input(BenchmarkId::new("queue_sweep", size), &size, |b, &size| {
+            b.iter_batched(
+                || engine_with(&resting),
+                |mut engine| {
+                    black_box(engine.add_order(market_order(OrderSide::Buy, size as i64)).unwrap());
+                    engine
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        // One market order sweeping every ask level of a deep book
+        let deep = deep_book_orders(size);
+        let ask_quantity: Decimal = deep
+            .iter()
+            .filter(|order| order.side == OrderSide::Sell)
+            .map(|order| order.quantity)
+            .sum();
+        let ask_quantity = i64::try_from(ask_quantity).unwrap_or(i64::MAX);
+        group.bench_with_input(BenchmarkId::new("deep_book_sweep", size), &ask_quantity, |b, &quantity| {
+            b.iter_batched(
+                || engine_with(&deep),
+                |mut engine| {
+                    black_box(engine.add_order(market_order(OrderSide::Buy, quantity)).unwrap());
+                    engine
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_add, bench_cancel, bench_match);
+criterion_main!(benches);
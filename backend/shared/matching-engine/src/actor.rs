@@ -0,0 +1,478 @@
+//! Single-writer matching engine actor
+//!
+//! Runs a `MatchingEngine` on a dedicated task that owns it exclusively and
+//! consumes commands from the two-lane command queue, so callers never need
+//! to wrap the engine in `Arc<Mutex<_>>`. Trades, order events and order
+//! book deltas produced by each command are published on broadcast
+//! channels. When a journal is attached, state-changing commands are
+//! journaled before they are applied.
+
+use crate::command_queue::{command_queue, CommandQueueConfig, CommandReceiver, CommandSender, Lane};
+use crate::journal::{Journal, JournalCommand};
+use crate::auction::AuctionOutcome;
+use crate::{ExecutionReport, MatchingEngine};
+use chrono::{DateTime, Utc};
+use flowex_types::{BookUpdate, CancelOrdersFilter, FlowExError, FlowExResult, Order, OrderBook, OrderEvent, Trade};
+use rust_decimal::Decimal;
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+/// Command processed by the engine task
+#[derive(Debug)]
+pub enum EngineCommand {
+    AddOrder {
+        order: Order,
+        reply: oneshot::Sender<FlowExResult<ExecutionReport>>,
+    },
+    CancelOrder {
+        order_id: Uuid,
+        reply: oneshot::Sender<FlowExResult<bool>>,
+    },
+    CancelAllForUser {
+        user_id: Uuid,
+        reply: oneshot::Sender<FlowExResult<Vec<Order>>>,
+    },
+    CancelAll {
+        filter: CancelOrdersFilter,
+        reply: oneshot::Sender<FlowExResult<Vec<Order>>>,
+    },
+    GetOpenOrders {
+        user_id: Uuid,
+        reply: oneshot::Sender<Vec<Order>>,
+    },
+    ModifyOrder {
+        order_id: Uuid,
+        price: Option<Decimal>,
+        quantity: Option<Decimal>,
+        reply: oneshot::Sender<FlowExResult<Vec<Trade>>>,
+    },
+    GetOrderBook {
+        depth: usize,
+        reply: oneshot::Sender<OrderBook>,
+    },
+    HaltTrading {
+        reply: oneshot::Sender<FlowExResult<()>>,
+    },
+    ResumeTrading {
+        reply: oneshot::Sender<FlowExResult<()>>,
+    },
+    StartAuction {
+        reply: oneshot::Sender<FlowExResult<()>>,
+    },
+    RunAuction {
+        reply: oneshot::Sender<FlowExResult<AuctionOutcome>>,
+    },
+    ExpireOrders {
+        now: DateTime<Utc>,
+        reply: oneshot::Sender<FlowExResult<Vec<Order>>>,
+    },
+    UpdateSession {
+        now: DateTime<Utc>,
+        reply: oneshot::Sender<FlowExResult<Vec<Trade>>>,
+    },
+}
+
+impl EngineCommand {
+    /// Lane the command travels on; cancels, amendments, expiry sweeps,
+    /// session updates and halts jump the queue
+    fn lane(&self) -> Lane {
+        match self {
+            EngineCommand::CancelOrder { .. }
+            | EngineCommand::CancelAllForUser { .. }
+            | EngineCommand::CancelAll { .. }
+            | EngineCommand::ModifyOrder { .. }
+            | EngineCommand::ExpireOrders { .. }
+            | EngineCommand::UpdateSession { .. }
+            | EngineCommand::HaltTrading { .. }
+            | EngineCommand::ResumeTrading { .. } => Lane::Priority,
+            EngineCommand::AddOrder { .. }
+            | EngineCommand::GetOrderBook { .. }
+            | EngineCommand::GetOpenOrders { .. }
+            | EngineCommand::StartAuction { .. }
+            | EngineCommand::RunAuction { .. } => Lane::Normal,
+        }
+    }
+}
+
+/// Engine actor configuration
+#[derive(Debug, Clone)]
+pub struct ActorConfig {
+    pub queue: CommandQueueConfig,
+    /// Buffered trades/events per broadcast channel before slow subscribers lag
+    pub broadcast_capacity: usize,
+}
+
+impl Default for ActorConfig {
+    fn default() -> Self {
+        Self {
+            queue: CommandQueueConfig::default(),
+            broadcast_capacity: 10_000,
+        }
+    }
+}
+
+/// Cloneable handle to a matching engine running on its own task
+#[derive(Debug, Clone)]
+pub struct MatchingEngineHandle {
+    commands: CommandSender<EngineCommand>,
+    trades: broadcast::Sender<Trade>,
+    events: broadcast::Sender<OrderEvent>,
+    book_updates: broadcast::Sender<BookUpdate>,
+}
+
+impl MatchingEngineHandle {
+    /// Spawn the engine task.
+    ///
+    /// The task stops once every handle is dropped and returns the engine.
+    pub fn spawn(engine: MatchingEngine, config: ActorConfig) -> (Self, JoinHandle<MatchingEngine>) {
+        Self::start(engine, None, config)
+    }
+
+    /// Spawn the engine task with a write-ahead journal.
+    ///
+    /// The engine should already be recovered from the journal (see
+    /// `Journal::recover`) so that sequence numbers line up.
+    pub fn spawn_with_journal(
+        engine: MatchingEngine,
+        journal: Journal,
+        config: ActorConfig,
+    ) -> (Self, JoinHandle<MatchingEngine>) {
+        Self::start(engine, Some(journal), config)
+    }
+
+    fn start(
+        engine: MatchingEngine,
+        journal: Option<Journal>,
+        config: ActorConfig,
+    ) -> (Self, JoinHandle<MatchingEngine>) {
+        let (commands, receiver) = command_queue(config.queue);
+        let (trades, _) = broadcast::channel(config.broadcast_capacity.max(1));
+        let (events, _) = broadcast::channel(config.broadcast_capacity.max(1));
+        let (book_updates, _) = broadcast::channel(config.broadcast_capacity.max(1));
+
+        let task = tokio::spawn(run_engine(
+            engine,
+            journal,
+            receiver,
+            trades.clone(),
+            events.clone(),
+            book_updates.clone(),
+        ));
+
+        (
+            Self {
+                commands,
+                trades,
+                events,
+                book_updates,
+            },
+            task,
+        )
+    }
+
+    /// Submit a new order and get its final state, fills and trades
+    pub async fn add_order(&self, order: Order) -> FlowExResult<ExecutionReport> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::AddOrder { order, reply }, response).await?
+    }
+
+    /// Cancel an order
+    pub async fn cancel_order(&self, order_id: Uuid) -> FlowExResult<bool> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::CancelOrder { order_id, reply }, response).await?
+    }
+
+    /// Cancel every open order of a user
+    pub async fn cancel_all_for_user(&self, user_id: Uuid) -> FlowExResult<Vec<Order>> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::CancelAllForUser { user_id, reply }, response).await?
+    }
+
+    /// Cancel every open order selected by a filter
+    pub async fn cancel_all(&self, filter: CancelOrdersFilter) -> FlowExResult<Vec<Order>> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::CancelAll { filter, reply }, response).await?
+    }
+
+    /// Get a user's open orders
+    pub async fn open_orders_for_user(&self, user_id: Uuid) -> FlowExResult<Vec<Order>> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::GetOpenOrders { user_id, reply }, response).await
+    }
+
+    /// Modify a resting order's price and/or total quantity
+    pub async fn modify_order(
+        &self,
+        order_id: Uuid,
+        price: Option<Decimal>,
+        quantity: Option<Decimal>,
+    ) -> FlowExResult<Vec<Trade>> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::ModifyOrder { order_id, price, quantity, reply }, response)
+            .await?
+    }
+
+    /// Get an order book snapshot
+    pub async fn order_book(&self, depth: usize) -> FlowExResult<OrderBook> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::GetOrderBook { depth, reply }, response).await
+    }
+
+    /// Halt matching for the symbol
+    pub async fn halt_trading(&self) -> FlowExResult<()> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::HaltTrading { reply }, response).await?
+    }
+
+    /// Resume matching after a halt
+    pub async fn resume_trading(&self) -> FlowExResult<()> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::ResumeTrading { reply }, response).await?
+    }
+
+    /// Open a call auction
+    pub async fn start_auction(&self) -> FlowExResult<()> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::StartAuction { reply }, response).await?
+    }
+
+    /// Uncross the auction and return to continuous trading
+    pub async fn run_auction(&self) -> FlowExResult<AuctionOutcome> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::RunAuction { reply }, response).await?
+    }
+
+    /// Expire good-till-date orders whose expiry is at or before `now`
+    pub async fn expire_orders(&self, now: DateTime<Utc>) -> FlowExResult<Vec<Order>> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::ExpireOrders { now, reply }, response).await?
+    }
+
+    /// Apply the session schedule at `now`, halting or resuming the symbol
+    /// and submitting queued orders
+    pub async fn update_session(&self, now: DateTime<Utc>) -> FlowExResult<Vec<Trade>> {
+        let (reply, response) = oneshot::channel();
+        self.request(EngineCommand::UpdateSession { now, reply }, response).await?
+    }
+
+    /// Subscribe to executed trades
+    pub fn subscribe_trades(&self) -> broadcast::Receiver<Trade> {
+        self.trades.subscribe()
+    }
+
+    /// Subscribe to order state changes
+    pub fn subscribe_events(&self) -> broadcast::Receiver<OrderEvent> {
+        self.events.subscribe()
+    }
+
+    /// Subscribe to incremental order book changes, one update per command
+    /// that changed the book
+    pub fn subscribe_book_updates(&self) -> broadcast::Receiver<BookUpdate> {
+        self.book_updates.subscribe()
+    }
+
+    async fn request<T>(&self, command: EngineCommand, response: oneshot::Receiver<T>) -> FlowExResult<T> {
+        self.commands.send(command.lane(), command).await?;
+        response
+            .await
+            .map_err(|_| FlowExError::Internal("Matching engine task stopped".to_string()))
+    }
+}
+
+/// Engine task loop: the only place the engine is mutated
+async fn run_engine(
+    mut engine: MatchingEngine,
+    mut journal: Option<Journal>,
+    mut commands: CommandReceiver<EngineCommand>,
+    trades: broadcast::Sender<Trade>,
+    events: broadcast::Sender<OrderEvent>,
+    book_updates: broadcast::Sender<BookUpdate>,
+) -> MatchingEngine {
+    info!("Matching engine task started for {}", engine.symbol);
+
+    while let Some(command) = commands.recv().await {
+        match command {
+            EngineCommand::AddOrder { order, reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::NewOrder { order: order.clone() })
+                    .and_then(|_| engine.add_order(order));
+                publish_trades(&trades, result.as_ref().map(|execution| &execution.trades));
+                let _ = reply.send(result);
+            }
+            EngineCommand::CancelOrder { order_id, reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::CancelOrder { order_id })
+                    .and_then(|_| engine.cancel_order(order_id));
+                let _ = reply.send(result);
+            }
+            EngineCommand::CancelAllForUser { user_id, reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::CancelAllForUser { user_id })
+                    .map(|_| engine.cancel_all_for_user(user_id));
+                let _ = reply.send(result);
+            }
+            EngineCommand::CancelAll { filter, reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::CancelAll { filter: filter.clone() })
+                    .map(|_| engine.cancel_all(&filter));
+                let _ = reply.send(result);
+            }
+            EngineCommand::GetOpenOrders { user_id, reply } => {
+                let _ = reply.send(engine.open_orders_for_user(user_id));
+            }
+            EngineCommand::ModifyOrder { order_id, price, quantity, reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::ModifyOrder { order_id, price, quantity })
+                    .and_then(|_| engine.modify_order(order_id, price, quantity));
+                publish_trades(&trades, result.as_ref());
+                let _ = reply.send(result);
+            }
+            EngineCommand::GetOrderBook { depth, reply } => {
+                let _ = reply.send(engine.get_order_book(depth));
+            }
+            EngineCommand::HaltTrading { reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::HaltTrading).map(|_| engine.halt_trading());
+                let _ = reply.send(result);
+            }
+            EngineCommand::ResumeTrading { reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::ResumeTrading).map(|_| engine.resume_trading());
+                let _ = reply.send(result);
+            }
+            EngineCommand::ExpireOrders { now, reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::ExpireOrders { now }).map(|_| engine.expire_orders(now));
+                let _ = reply.send(result);
+            }
+            EngineCommand::UpdateSession { now, reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::UpdateSession { now })
+                    .and_then(|_| engine.update_session(now));
+                publish_trades(&trades, result.as_ref());
+                let _ = reply.send(result);
+            }
+            EngineCommand::StartAuction { reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::StartAuction).map(|_| engine.start_auction());
+                let _ = reply.send(result);
+            }
+            EngineCommand::RunAuction { reply } => {
+                let result = write_ahead(&mut journal, &mut engine, JournalCommand::RunAuction).and_then(|_| engine.run_auction());
+                publish_trades(&trades, result.as_ref().map(|outcome| &outcome.trades));
+                let _ = reply.send(result);
+            }
+        }
+
+        engine.set_command_time(None);
+
+        // Sending only fails when nobody is subscribed
+        for event in engine.drain_events() {
+            let _ = events.send(event);
+        }
+        if let Some(update) = engine.drain_book_update() {
+            let _ = book_updates.send(update);
+        }
+    }
+
+    debug!("Matching engine task stopped for {}", engine.symbol);
+    engine
+}
+
+/// Journal a command before it is applied, which it then is as of the time
+/// journaled so that replaying the journal decides alike; a failed write
+/// rejects the command
+fn write_ahead(journal: &mut Option<Journal>, engine: &mut MatchingEngine, command: JournalCommand) -> FlowExResult<()> {
+    match journal {
+        Some(journal) => {
+            let entry = journal.append(command)?;
+            engine.set_command_time(Some(entry.timestamp));
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+fn publish_trades(trades: &broadcast::Sender<Trade>, result: Result<&Vec<Trade>, &FlowExError>) {
+    if let Ok(executed) = result {
+        for trade in executed {
+            let _ = trades.send(trade.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+    use flowex_types::{OrderEventKind, OrderSide};
+
+    /// 测试：通过句柄下单、成交广播与事件广播
+    #[tokio::test]
+    async fn test_handle_round_trip() {
+        let (handle, task) = MatchingEngineHandle::spawn(
+            MatchingEngine::new("BTCUSDT".to_string()),
+            ActorConfig::default(),
+        );
+        let mut trades = handle.subscribe_trades();
+        let mut events = handle.subscribe_events();
+        let mut book_updates = handle.subscribe_book_updates();
+
+        assert!(handle.add_order(create_limit_order(OrderSide::Sell, 50000, 1)).await.unwrap().trades.is_empty());
+        let executed = handle.add_order(create_limit_order(OrderSide::Buy, 50000, 1)).await.unwrap().trades;
+        assert_eq!(executed.len(), 1);
+
+        assert_eq!(trades.recv().await.unwrap().id, executed[0].id);
+        assert_eq!(events.recv().await.unwrap().kind, OrderEventKind::Accepted);
+        assert_eq!(book_updates.recv().await.unwrap().deltas[0].quantity, Decimal::ONE);
+        assert_eq!(book_updates.recv().await.unwrap().deltas[0].quantity, Decimal::ZERO);
+
+        let order_book = handle.order_book(10).await.unwrap();
+        assert!(order_book.asks.is_empty());
+
+        drop(handle);
+        let engine = task.await.unwrap();
+        assert_eq!(engine.get_order_book(10).bids.len(), 0);
+    }
+
+    /// 测试：撤单走优先通道
+    #[tokio::test]
+    async fn test_cancel_via_handle() {
+        let (handle, _task) = MatchingEngineHandle::spawn(
+            MatchingEngine::new("BTCUSDT".to_string()),
+            ActorConfig::default(),
+        );
+
+        let order = create_limit_order(OrderSide::Buy, 49000, 1);
+        let order_id = order.id;
+        handle.add_order(order).await.unwrap();
+
+        assert!(handle.cancel_order(order_id).await.unwrap());
+        assert!(!handle.cancel_order(order_id).await.unwrap());
+        assert!(handle.order_book(10).await.unwrap().bids.is_empty());
+    }
+
+    /// 测试：挂载日志后命令先写日志，可据此恢复引擎；被拒绝的订单同样写入日志
+    #[tokio::test]
+    async fn test_journaled_handle_recovers() {
+        let path = std::env::temp_dir().join(format!("flowex-actor-journal-{}.jsonl", Uuid::new_v4()));
+        let (handle, task) = MatchingEngineHandle::spawn_with_journal(
+            MatchingEngine::new("BTCUSDT".to_string()),
+            Journal::open(&path).unwrap(),
+            ActorConfig::default(),
+        );
+
+        let order = create_limit_order(OrderSide::Buy, 49000, 1);
+        let order_id = order.id;
+        handle.add_order(order).await.unwrap();
+        let mut keyed = create_limit_order(OrderSide::Buy, 48000, 1);
+        keyed.client_order_id = Some("client-1".to_string());
+        handle.add_order(keyed.clone()).await.unwrap();
+        // 重复的客户端订单号被拒绝，拒绝也占用序列号
+        keyed.id = Uuid::new_v4();
+        assert!(handle.add_order(keyed).await.is_err());
+        handle.cancel_order(order_id).await.unwrap();
+        drop(handle);
+        let engine = task.await.unwrap();
+
+        let mut recovered = MatchingEngine::new("BTCUSDT".to_string());
+        let journal = Journal::recover(&path, &mut recovered).unwrap();
+        assert_eq!(journal.last_sequence(), 4);
+        assert_eq!(recovered.sequence(), engine.sequence());
+        assert_eq!(recovered.get_best_bid(), Some(Decimal::new(48000, 0)));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
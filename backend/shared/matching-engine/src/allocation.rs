@@ -0,0 +1,184 @@
+//! Price level allocation policies
+//!
+//! When an incoming order trades at a price level, a `MatchAllocator`
+//! decides how much each resting order there receives. Without one the
+//! engine fills in strict time priority (FIFO); some products, futures in
+//! particular, instead share each level pro rata to resting size or favour
+//! the largest orders. The allocator is chosen per engine, and so per
+//! symbol. Like fee schedules it is not part of a snapshot.
+
+use crate::MatchingEngine;
+use flowex_types::Order;
+use rust_decimal::Decimal;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// A resting order's claim on incoming quantity at one price level
+#[derive(Debug, Clone, Copy)]
+pub struct Claim<'a> {
+    pub order: &'a Order,
+    /// Quantity the order can fill now: its visible slice, capped for
+    /// reduce-only orders
+    pub available: Decimal,
+}
+
+/// Splits incoming quantity among the resting orders of a price level
+pub trait MatchAllocator: Debug + Send + Sync {
+    /// Share up to `quantity` among `claims`, given in time priority, and
+    /// return one allocation per claim. The engine clamps each allocation
+    /// to its claim's available quantity and, in claim order, the total to
+    /// `quantity`.
+    fn allocate(&self, quantity: Decimal, claims: &[Claim<'_>]) -> Vec<Decimal>;
+}
+
+/// Strict time priority: the oldest order fills first
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fifo;
+
+impl MatchAllocator for Fifo {
+    fn allocate(&self, quantity: Decimal, claims: &[Claim<'_>]) -> Vec<Decimal> {
+        let mut remaining = quantity;
+        claims
+            .iter()
+            .map(|claim| {
+                let allocation = remaining.min(claim.available);
+                remaining -= allocation;
+                allocation
+            })
+            .collect()
+    }
+}
+
+/// Shares in proportion to each order's available quantity, rounded down
+/// to whole lots; the rounding remainder goes out in time priority
+#[derive(Debug, Clone, Copy)]
+pub struct ProRata {
+    /// Smallest allocation increment, usually the pair's step size; zero
+    /// disables rounding
+    pub lot_size: Decimal,
+}
+
+impl MatchAllocator for ProRata {
+    fn allocate(&self, quantity: Decimal, claims: &[Claim<'_>]) -> Vec<Decimal> {
+        let total: Decimal = claims.iter().map(|claim| claim.available).sum();
+        if total <= quantity {
+            return claims.iter().map(|claim| claim.available).collect();
+        }
+
+        let mut allocations: Vec<Decimal> = claims
+            .iter()
+            .map(|claim| {
+                let share = quantity * claim.available / total;
+                if self.lot_size > Decimal::ZERO {
+                    (share / self.lot_size).floor() * self.lot_size
+                } else {
+                    share
+                }
+            })
+            .collect();
+
+        let mut remainder = quantity - allocations.iter().copied().sum::<Decimal>();
+        for (allocation, claim) in allocations.iter_mut().zip(claims) {
+            let top_up = remainder.min(claim.available - *allocation);
+            *allocation += top_up;
+            remainder -= top_up;
+        }
+        allocations
+    }
+}
+
+/// Largest available quantity first; equal sizes keep time priority
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizePriority;
+
+impl MatchAllocator for SizePriority {
+    fn allocate(&self, quantity: Decimal, claims: &[Claim<'_>]) -> Vec<Decimal> {
+        let mut by_size: Vec<usize> = (0..claims.len()).collect();
+        by_size.sort_by(|a, b| claims[*b].available.cmp(&claims[*a].available));
+
+        let mut allocations = vec![Decimal::ZERO; claims.len()];
+        let mut remaining = quantity;
+        for index in by_size {
+            allocations[index] = remaining.min(claims[index].available);
+            remaining -= allocations[index];
+        }
+        allocations
+    }
+}
+
+impl MatchingEngine {
+    /// Install an allocation policy for price levels, or `None` for the
+    /// built-in time priority
+    pub fn set_match_allocator(&mut self, match_allocator: Option<Arc<dyn MatchAllocator>>) {
+        self.match_allocator = match_allocator;
+    }
+
+    /// Allocation policy for price levels, if not time priority
+    pub fn match_allocator(&self) -> Option<&dyn MatchAllocator> {
+        self.match_allocator.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use flowex_test_support::create_limit_order;
+    use flowex_types::{OrderSide, Trade};
+    use uuid::Uuid;
+
+    /// Three resting asks at 100 sized 1, 3 and 6, oldest first
+    fn engine_with_level(allocator: Option<Arc<dyn MatchAllocator>>) -> (MatchingEngine, Vec<Uuid>) {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine.set_match_allocator(allocator);
+        let mut ids = Vec::new();
+        for (i, quantity) in [1, 3, 6].into_iter().enumerate() {
+            let mut order = create_limit_order(OrderSide::Sell, 100, quantity);
+            order.created_at += Duration::milliseconds(i as i64);
+            ids.push(order.id);
+            engine.add_order(order).unwrap();
+        }
+        (engine, ids)
+    }
+
+    fn filled(trades: &[Trade], maker: Uuid) -> Decimal {
+        trades
+            .iter()
+            .filter(|trade| trade.maker_order_id == maker)
+            .map(|trade| trade.quantity)
+            .sum()
+    }
+
+    /// 测试：按比例分配按挂单数量分摊，零头按时间优先
+    #[test]
+    fn test_pro_rata_allocation() {
+        let (mut engine, ids) = engine_with_level(Some(Arc::new(ProRata { lot_size: Decimal::ONE })));
+        assert!(engine.match_allocator().is_some());
+
+        // 5 按 1:3:6 分摊为 0.5/1.5/3，取整后为 0/1/3，剩余 1 按时间优先补给最早的订单
+        let trades = engine.add_order(create_limit_order(OrderSide::Buy, 100, 5)).unwrap().trades;
+        assert_eq!(filled(&trades, ids[0]), Decimal::ONE);
+        assert_eq!(filled(&trades, ids[1]), Decimal::ONE);
+        assert_eq!(filled(&trades, ids[2]), Decimal::new(3, 0));
+        assert_eq!(trades.iter().map(|trade| trade.quantity).sum::<Decimal>(), Decimal::new(5, 0));
+        assert_eq!(engine.get_order_book(10).asks[0].quantity, Decimal::new(5, 0));
+    }
+
+    /// 测试：数量优先与默认时间优先的分配顺序
+    #[test]
+    fn test_size_priority_and_fifo_allocation() {
+        let (mut engine, ids) = engine_with_level(Some(Arc::new(SizePriority)));
+        let trades = engine.add_order(create_limit_order(OrderSide::Buy, 100, 7)).unwrap().trades;
+        assert_eq!(filled(&trades, ids[2]), Decimal::new(6, 0));
+        assert_eq!(filled(&trades, ids[1]), Decimal::ONE);
+        assert_eq!(filled(&trades, ids[0]), Decimal::ZERO);
+
+        for allocator in [None, Some(Arc::new(Fifo) as Arc<dyn MatchAllocator>)] {
+            let (mut engine, ids) = engine_with_level(allocator);
+            let trades = engine.add_order(create_limit_order(OrderSide::Buy, 100, 7)).unwrap().trades;
+            assert_eq!(filled(&trades, ids[0]), Decimal::ONE);
+            assert_eq!(filled(&trades, ids[1]), Decimal::new(3, 0));
+            assert_eq!(filled(&trades, ids[2]), Decimal::new(3, 0));
+        }
+    }
+}
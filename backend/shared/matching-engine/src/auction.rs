@@ -0,0 +1,275 @@
+//! Call auction (uncrossing) mode
+//!
+//! While an auction is open, resting (GTC/GTD) limit orders accumulate on
+//! the book without matching, so the book may be crossed. `run_auction`
+//! then executes every crossing order at a single uncrossing price chosen to
+//! maximise matched volume, and returns the engine to continuous trading.
+//! Used for the market open and close, and to resume after a halt.
+
+use crate::MatchingEngine;
+use flowex_types::{FlowExError, FlowExResult, Order, OrderSide, OrderStatus, OrderType, Trade, TradingStatus};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Instant;
+use tracing::info;
+
+/// Uncrossing price and the volume it would execute
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuctionPrice {
+    pub price: Decimal,
+    pub volume: Decimal,
+    /// Buy minus sell quantity willing to trade at the price
+    pub imbalance: Decimal,
+}
+
+/// Result of running an auction
+#[derive(Debug, Clone)]
+pub struct AuctionOutcome {
+    /// `None` when the book was not crossed and nothing executed
+    pub price: Option<AuctionPrice>,
+    pub trades: Vec<Trade>,
+}
+
+/// Choose the uncrossing price from the remaining quantity at each bid and
+/// ask price level.
+///
+/// Maximises executable volume, then minimises the absolute imbalance, then
+/// prefers the price closest to `reference` (typically the last trade), and
+/// finally the lower price.
+pub fn uncrossing_price(
+    bids: &BTreeMap<Decimal, Decimal>,
+    asks: &BTreeMap<Decimal, Decimal>,
+    reference: Option<Decimal>,
+) -> Option<AuctionPrice> {
+    let mut candidates: Vec<Decimal> = bids.keys().chain(asks.keys()).copied().collect();
+    candidates.sort();
+    candidates.dedup();
+
+    candidates
+        .into_iter()
+        .map(|price| {
+            let demand: Decimal = bids.range(price..).map(|(_, quantity)| quantity).sum();
+            let supply: Decimal = asks.range(..=price).map(|(_, quantity)| quantity).sum();
+            AuctionPrice {
+                price,
+                volume: demand.min(supply),
+                imbalance: demand - supply,
+            }
+        })
+        .filter(|candidate| candidate.volume > Decimal::ZERO)
+        .min_by(|a, b| {
+            b.volume
+                .cmp(&a.volume)
+                .then(a.imbalance.abs().cmp(&b.imbalance.abs()))
+                .then_with(|| match reference {
+                    Some(reference) => (a.price - reference).abs().cmp(&(b.price - reference).abs()),
+                    None => std::cmp::Ordering::Equal,
+                })
+                .then(a.price.cmp(&b.price))
+        })
+}
+
+impl MatchingEngine {
+    /// Open a call auction; orders rest without matching until `run_auction`.
+    ///
+    /// Opening an auction lifts a halt so orders can be collected for the
+    /// resumption.
+    pub fn start_auction(&mut self) {
+        self.sequence += 1;
+        self.in_auction = true;
+        self.trading_status = TradingStatus::Trading;
+        info!("Auction opened for {}", self.symbol);
+    }
+
+    /// Whether a call auction is open
+    pub fn is_auction(&self) -> bool {
+        self.in_auction
+    }
+
+    /// Price the auction would uncross at if run now
+    pub fn indicative_auction_price(&self) -> Option<AuctionPrice> {
+        let level_quantities = |side: &OrderSide| -> BTreeMap<Decimal, Decimal> {
+            self.book
+                .levels_best_first(side)
+                .map(|(price, orders)| (price, orders.map(|order| order.remaining_quantity).sum()))
+                .collect()
+        };
+        uncrossing_price(
+            &level_quantities(&OrderSide::Buy),
+            &level_quantities(&OrderSide::Sell),
+            self.last_trade_price,
+        )
+    }
+
+    /// Uncross the book at a single price and return to continuous trading
+    pub fn run_auction(&mut self) -> FlowExResult<AuctionOutcome> {
+        if !self.in_auction {
+            return Err(FlowExError::Trading(format!("No auction open for {}", self.symbol)));
+        }
+        self.sequence += 1;
+        let started = self.metrics.is_some().then(Instant::now);
+
+        let auction_price = self.indicative_auction_price();
+        let mut trades = Vec::new();
+
+        if let Some(auction_price) = &auction_price {
+            if let Some(breaker) = &mut self.circuit_breaker {
+                breaker.reset();
+            }
+
+            let price = auction_price.price;
+            let mut remaining = auction_price.volume;
+
+            while remaining > Decimal::ZERO {
+                let best_bid = self.book.best_price(&OrderSide::Buy).filter(|p| *p >= price);
+                let best_ask = self.book.best_price(&OrderSide::Sell).filter(|p| *p <= price);
+                let (Some(bid_price), Some(ask_price)) = (best_bid, best_ask) else {
+                    break;
+                };
+                let (Some(mut bid), Some(mut ask)) = (
+                    self.book.pop_front(&OrderSide::Buy, self.book.key(bid_price)),
+                    self.book.pop_front(&OrderSide::Sell, self.book.key(ask_price)),
+                ) else {
+                    break;
+                };
+
+                self.touched_levels.push((OrderSide::Buy, bid_price));
+                self.touched_levels.push((OrderSide::Sell, ask_price));
+
+                let quantity = bid.remaining_quantity.min(ask.remaining_quantity).min(remaining);
+                remaining -= quantity;
+                for order in [&mut bid, &mut ask] {
+                    order.filled_quantity += quantity;
+                    order.remaining_quantity -= quantity;
+                    order.status = if order.remaining_quantity <= Decimal::ZERO {
+                        OrderStatus::Filled
+                    } else {
+                        OrderStatus::PartiallyFilled
+                    };
+                }
+
+                // The later arrival takes liquidity from the earlier one
+                let (taker, maker) = if bid.created_at > ask.created_at { (&bid, &ask) } else { (&ask, &bid) };
+                let trade = self.create_trade(taker, maker, price, quantity)?;
+                self.emit_fill(&bid, trade.id);
+                self.emit_fill(&ask, trade.id);
+                trades.push(trade);
+
+                for order in [bid, ask] {
+                    if order.remaining_quantity > Decimal::ZERO {
+                        self.book.push_front(order);
+                    } else {
+                        self.untrack_order(&order);
+                    }
+                }
+            }
+
+            if !trades.is_empty() {
+                let command_time = self.command_now();
+                if let Some(breaker) = &mut self.circuit_breaker {
+                    breaker.record(price, command_time);
+                }
+            }
+
+            info!(
+                "Auction for {} uncrossed at {}: {} executed, imbalance {}",
+                self.symbol, price, auction_price.volume, auction_price.imbalance
+            );
+        } else {
+            info!("Auction for {} closed without crossing orders", self.symbol);
+        }
+
+        if let (Some(metrics), Some(started)) = (&self.metrics, started) {
+            metrics.matched(&self.symbol, started.elapsed(), trades.len());
+        }
+        self.in_auction = false;
+        trades.extend(self.activate_triggers());
+        self.book_changed();
+
+        Ok(AuctionOutcome {
+            price: auction_price,
+            trades,
+        })
+    }
+
+    /// Only resting (GTC/GTD) limit orders can wait for the auction
+    pub(crate) fn check_auction_order(order: &Order) -> FlowExResult<()> {
+        if order.order_type != OrderType::Limit || !order.time_in_force.is_resting() {
+            return Err(FlowExError::Trading(
+                "Only good-till-cancelled or good-till-date limit orders are accepted during an auction".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use flowex_test_support::create_limit_order;
+
+    /// 测试：集合竞价按最大成交量确定单一成交价
+    #[test]
+    fn test_auction_uncrosses_at_single_price() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine.start_auction();
+
+        let mut early_bid = create_limit_order(OrderSide::Buy, 102, 3);
+        early_bid.created_at = Utc::now() - Duration::seconds(10);
+        let early_bid_id = early_bid.id;
+        for order in [
+            early_bid,
+            create_limit_order(OrderSide::Buy, 100, 2),
+            create_limit_order(OrderSide::Sell, 99, 2),
+            create_limit_order(OrderSide::Sell, 101, 2),
+            create_limit_order(OrderSide::Sell, 103, 5),
+        ] {
+            assert!(engine.add_order(order).unwrap().trades.is_empty());
+        }
+
+        // 竞价期间不接受市价单
+        let market = Order {
+            order_type: OrderType::Market,
+            price: None,
+            ..create_limit_order(OrderSide::Buy, 0, 1)
+        };
+        assert!(engine.add_order(market).is_err());
+
+        // At 101: demand 3 (>=101), supply 4 (<=101) -> 3; at 100: demand 5, supply 2 -> 2
+        let indicative = engine.indicative_auction_price().unwrap();
+        assert_eq!(indicative.price, Decimal::new(101, 0));
+        assert_eq!(indicative.volume, Decimal::new(3, 0));
+
+        let outcome = engine.run_auction().unwrap();
+        assert!(!engine.is_auction());
+        assert_eq!(outcome.trades.len(), 2);
+        assert!(outcome.trades.iter().all(|t| t.price == Decimal::new(101, 0)));
+        assert!(outcome.trades.iter().all(|t| t.maker_order_id == early_bid_id));
+
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(100, 0)));
+        assert_eq!(engine.get_best_ask(), Some(Decimal::new(101, 0)));
+        assert!(engine.run_auction().is_err());
+    }
+
+    /// 测试：熔断后通过集合竞价恢复交易
+    #[test]
+    fn test_auction_resumes_after_halt() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine.halt_trading();
+        assert!(engine.add_order(create_limit_order(OrderSide::Buy, 100, 1)).is_err());
+
+        engine.start_auction();
+        assert_eq!(engine.trading_status(), &TradingStatus::Trading);
+        engine.add_order(create_limit_order(OrderSide::Buy, 100, 1)).unwrap();
+
+        let outcome = engine.run_auction().unwrap();
+        assert!(outcome.price.is_none());
+        assert!(outcome.trades.is_empty());
+
+        // 恢复连续竞价
+        let trades = engine.add_order(create_limit_order(OrderSide::Sell, 100, 1)).unwrap().trades;
+        assert_eq!(trades.len(), 1);
+    }
+}
@@ -0,0 +1,420 @@
+//! Arena-backed resting order storage
+//!
+//! Resting orders live in a slab of nodes addressed by compact indices, and
+//! each price level is an intrusive doubly-linked list threaded through
+//! those nodes. Matching and cancelling move indices rather than cloning
+//! orders between queues, freed slots are reused so a busy book stops
+//! allocating once warm, and an id index makes cancel and modify O(1)
+//! lookups instead of a scan of every level. With a fixed-point scale the
+//! levels are keyed by whole ticks rather than decimal prices.
+
+use crate::fixed::FixedPointScale;
+use flowex_types::{Order, OrderSide};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/// Index of an order's node in the arena
+type OrderKey = usize;
+
+/// Sort key of a price level: whole ticks when the store has a fixed-point
+/// scale, the exact price otherwise. All levels of a store use one variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum PriceKey {
+    Ticks(i64),
+    Exact(Decimal),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    order: Order,
+    level: PriceKey,
+    prev: Option<OrderKey>,
+    next: Option<OrderKey>,
+}
+
+/// Queue of orders at one price, oldest first
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    price: Decimal,
+    head: Option<OrderKey>,
+    tail: Option<OrderKey>,
+    len: usize,
+}
+
+/// Both sides of a book of resting limit orders
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OrderStore {
+    nodes: Vec<Option<Node>>,
+    free: Vec<OrderKey>,
+    index: HashMap<Uuid, OrderKey>,
+    bids: BTreeMap<PriceKey, Level>,
+    asks: BTreeMap<PriceKey, Level>,
+    scale: Option<FixedPointScale>,
+}
+
+/// Orders of one price level in queue order
+pub(crate) struct LevelOrders<'a> {
+    store: &'a OrderStore,
+    next: Option<OrderKey>,
+}
+
+impl<'a> Iterator for LevelOrders<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.store.node(self.next?);
+        self.next = node.next;
+        Some(&node.order)
+    }
+}
+
+/// A price level and its orders
+pub(crate) type PriceLevel<'a> = (Decimal, LevelOrders<'a>);
+
+impl OrderStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Empty store keyed by ticks of `scale`, or by exact prices if `None`
+    pub fn with_scale(scale: Option<FixedPointScale>) -> Self {
+        Self {
+            scale,
+            ..Self::default()
+        }
+    }
+
+    pub fn scale(&self) -> Option<&FixedPointScale> {
+        self.scale.as_ref()
+    }
+
+    /// Level key of a resting price. With a scale the price must be on its
+    /// tick grid; the engine rejects orders that are not.
+    pub fn key(&self, price: Decimal) -> PriceKey {
+        match self.scale.as_ref().and_then(|scale| scale.price_to_ticks(price)) {
+            Some(ticks) => PriceKey::Ticks(ticks),
+            None => PriceKey::Exact(price),
+        }
+    }
+
+    /// Key to compare resting levels against for an order on `side` limited
+    /// to `limit`, which need not be on the tick grid
+    pub fn limit_key(&self, side: &OrderSide, limit: Decimal) -> PriceKey {
+        match &self.scale {
+            Some(scale) => PriceKey::Ticks(scale.limit_to_ticks(side, limit)),
+            None => PriceKey::Exact(limit),
+        }
+    }
+
+    /// Number of resting orders
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Number of price levels on a side
+    pub fn level_count(&self, side: &OrderSide) -> usize {
+        self.levels(side).len()
+    }
+
+    pub fn contains(&self, order_id: Uuid) -> bool {
+        self.index.contains_key(&order_id)
+    }
+
+    pub fn get(&self, order_id: Uuid) -> Option<&Order> {
+        self.index.get(&order_id).map(|key| &self.node(*key).order)
+    }
+
+    /// Mutable access to a resting order; its side and price must not change
+    pub fn get_mut(&mut self, order_id: Uuid) -> Option<&mut Order> {
+        let key = *self.index.get(&order_id)?;
+        Some(&mut self.node_mut(key).order)
+    }
+
+    /// Queue an order at the back of its price level.
+    ///
+    /// The order must have a price and an id not already on the book.
+    pub fn push_back(&mut self, order: Order) {
+        let Some((side, price)) = Self::position(&order) else {
+            return;
+        };
+        let level_key = self.key(price);
+        let key = self.allocate(order, level_key);
+        let level = Self::level_entry(self.levels_mut(&side), level_key, price);
+        let tail = level.tail.replace(key);
+        if level.head.is_none() {
+            level.head = Some(key);
+        }
+        level.len += 1;
+
+        self.node_mut(key).prev = tail;
+        if let Some(tail) = tail {
+            self.node_mut(tail).next = Some(key);
+        }
+    }
+
+    /// Queue an order at the front of its price level, e.g. to return a
+    /// partially filled order to where it was
+    pub fn push_front(&mut self, order: Order) {
+        let Some((side, price)) = Self::position(&order) else {
+            return;
+        };
+        let level_key = self.key(price);
+        let key = self.allocate(order, level_key);
+        let level = Self::level_entry(self.levels_mut(&side), level_key, price);
+        let head = level.head.replace(key);
+        if level.tail.is_none() {
+            level.tail = Some(key);
+        }
+        level.len += 1;
+
+        self.node_mut(key).next = head;
+        if let Some(head) = head {
+            self.node_mut(head).prev = Some(key);
+        }
+    }
+
+    /// Remove a resting order, dropping its level if emptied
+    pub fn remove(&mut self, order_id: Uuid) -> Option<Order> {
+        let key = self.index.remove(&order_id)?;
+        Some(self.unlink(key))
+    }
+
+    /// First order in the queue of a level
+    pub fn front(&self, side: &OrderSide, level: PriceKey) -> Option<&Order> {
+        let key = self.levels(side).get(&level)?.head?;
+        Some(&self.node(key).order)
+    }
+
+    /// Mutable access to the first order of a level; its side and price must
+    /// not change
+    pub fn front_mut(&mut self, side: &OrderSide, level: PriceKey) -> Option<&mut Order> {
+        let key = self.levels(side).get(&level)?.head?;
+        Some(&mut self.node_mut(key).order)
+    }
+
+    /// Remove the first order of a level
+    pub fn pop_front(&mut self, side: &OrderSide, level: PriceKey) -> Option<Order> {
+        let order_id = self.front(side, level)?.id;
+        self.remove(order_id)
+    }
+
+    /// Move the first order of a level to the back of its queue
+    pub fn rotate_front(&mut self, side: &OrderSide, level: PriceKey) {
+        if let Some(order) = self.pop_front(side, level) {
+            self.push_back(order);
+        }
+    }
+
+    /// Move a resting order to the back of its queue
+    pub fn move_to_back(&mut self, order_id: Uuid) {
+        if let Some(order) = self.remove(order_id) {
+            self.push_back(order);
+        }
+    }
+
+    /// Orders at one price level in queue order
+    pub fn level(&self, side: &OrderSide, price: Decimal) -> LevelOrders<'_> {
+        self.queue(side, self.key(price))
+    }
+
+    /// Orders of a level in queue order
+    pub fn queue(&self, side: &OrderSide, level: PriceKey) -> LevelOrders<'_> {
+        self.orders_from(self.levels(side).get(&level).and_then(|level| level.head))
+    }
+
+    /// Best level on a side, highest bid or lowest ask, with its price
+    pub fn best_level(&self, side: &OrderSide) -> Option<(PriceKey, Decimal)> {
+        let (key, level) = match side {
+            OrderSide::Buy => self.bids.last_key_value(),
+            OrderSide::Sell => self.asks.first_key_value(),
+        }?;
+        Some((*key, level.price))
+    }
+
+    /// Best price on a side: highest bid or lowest ask
+    pub fn best_price(&self, side: &OrderSide) -> Option<Decimal> {
+        self.best_level(side).map(|(_, price)| price)
+    }
+
+    /// Each level of a side, best price first, with the order count it
+    /// records; for integrity checks against its linked orders
+    pub fn recorded_levels(&self, side: &OrderSide) -> impl Iterator<Item = (Decimal, usize, LevelOrders<'_>)> + '_ {
+        self.levels_in_priority(side)
+            .map(|level| (level.price, level.len, self.orders_from(level.head)))
+    }
+
+    /// Price levels of a side, best price first
+    pub fn levels_best_first(&self, side: &OrderSide) -> Box<dyn Iterator<Item = PriceLevel<'_>> + '_> {
+        Box::new(
+            self.levels_in_priority(side)
+                .map(|level| (level.price, self.orders_from(level.head))),
+        )
+    }
+
+    fn levels_in_priority(&self, side: &OrderSide) -> Box<dyn Iterator<Item = &Level> + '_> {
+        match side {
+            OrderSide::Buy => Box::new(self.bids.values().rev()),
+            OrderSide::Sell => Box::new(self.asks.values()),
+        }
+    }
+
+    fn orders_from(&self, head: Option<OrderKey>) -> LevelOrders<'_> {
+        LevelOrders { store: self, next: head }
+    }
+
+    /// Every order of a side, best price first and in queue order within a
+    /// level
+    pub fn orders_best_first(&self, side: &OrderSide) -> impl Iterator<Item = &Order> + '_ {
+        self.levels_best_first(side).flat_map(|(_, orders)| orders)
+    }
+
+    /// Remove every order matching `predicate` and return them
+    pub fn remove_where(&mut self, mut predicate: impl FnMut(&Order) -> bool) -> Vec<Order> {
+        let selected: Vec<Uuid> = self
+            .orders_best_first(&OrderSide::Buy)
+            .chain(self.orders_best_first(&OrderSide::Sell))
+            .filter(|order| predicate(order))
+            .map(|order| order.id)
+            .collect();
+
+        selected.into_iter().filter_map(|order_id| self.remove(order_id)).collect()
+    }
+
+    fn position(order: &Order) -> Option<(OrderSide, Decimal)> {
+        Some((order.side.clone(), order.price?))
+    }
+
+    fn levels(&self, side: &OrderSide) -> &BTreeMap<PriceKey, Level> {
+        match side {
+            OrderSide::Buy => &self.bids,
+            OrderSide::Sell => &self.asks,
+        }
+    }
+
+    fn levels_mut(&mut self, side: &OrderSide) -> &mut BTreeMap<PriceKey, Level> {
+        match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        }
+    }
+
+    fn level_entry(levels: &mut BTreeMap<PriceKey, Level>, key: PriceKey, price: Decimal) -> &mut Level {
+        levels.entry(key).or_insert(Level {
+            price,
+            head: None,
+            tail: None,
+            len: 0,
+        })
+    }
+
+    fn node(&self, key: OrderKey) -> &Node {
+        self.nodes[key].as_ref().expect("order key points at a free slot")
+    }
+
+    fn node_mut(&mut self, key: OrderKey) -> &mut Node {
+        self.nodes[key].as_mut().expect("order key points at a free slot")
+    }
+
+    /// Place an order in a free slot, unlinked
+    fn allocate(&mut self, order: Order, level: PriceKey) -> OrderKey {
+        let order_id = order.id;
+        let node = Node {
+            order,
+            level,
+            prev: None,
+            next: None,
+        };
+        let key = match self.free.pop() {
+            Some(key) => {
+                self.nodes[key] = Some(node);
+                key
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        self.index.insert(order_id, key);
+        key
+    }
+
+    /// Detach a node from its level and free its slot
+    fn unlink(&mut self, key: OrderKey) -> Order {
+        let node = self.nodes[key].take().expect("order key points at a free slot");
+        self.free.push(key);
+
+        if let Some(prev) = node.prev {
+            self.node_mut(prev).next = node.next;
+        }
+        if let Some(next) = node.next {
+            self.node_mut(next).prev = node.prev;
+        }
+
+        let levels = self.levels_mut(&node.order.side);
+        if let Some(level) = levels.get_mut(&node.level) {
+            if level.head == Some(key) {
+                level.head = node.next;
+            }
+            if level.tail == Some(key) {
+                level.tail = node.prev;
+            }
+            level.len -= 1;
+            if level.len == 0 {
+                levels.remove(&node.level);
+            }
+        }
+
+        node.order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+
+    fn level_ids(store: &OrderStore, side: &OrderSide, price: i64) -> Vec<Uuid> {
+        store.level(side, Decimal::new(price, 0)).map(|order| order.id).collect()
+    }
+
+    /// 测试：价格档位队列保持时间优先，删除后复用空闲槽位
+    #[test]
+    fn test_store_links_and_reuses_slots() {
+        let mut store = OrderStore::new();
+        let orders: Vec<Order> = (0..3).map(|_| create_limit_order(OrderSide::Sell, 100, 1)).collect();
+        let ids: Vec<Uuid> = orders.iter().map(|order| order.id).collect();
+        for order in orders {
+            store.push_back(order);
+        }
+        store.push_back(create_limit_order(OrderSide::Sell, 101, 1));
+        store.push_back(create_limit_order(OrderSide::Buy, 99, 1));
+
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.best_price(&OrderSide::Sell), Some(Decimal::new(100, 0)));
+        assert_eq!(store.best_price(&OrderSide::Buy), Some(Decimal::new(99, 0)));
+        assert_eq!(level_ids(&store, &OrderSide::Sell, 100), ids);
+
+        // 从队列中间删除，前后节点重新链接
+        assert_eq!(store.remove(ids[1]).map(|order| order.id), Some(ids[1]));
+        assert_eq!(level_ids(&store, &OrderSide::Sell, 100), vec![ids[0], ids[2]]);
+
+        // 轮转队首，新订单复用空闲槽位
+        store.rotate_front(&OrderSide::Sell, store.key(Decimal::new(100, 0)));
+        assert_eq!(level_ids(&store, &OrderSide::Sell, 100), vec![ids[2], ids[0]]);
+        let slots = store.nodes.len();
+        let reused = create_limit_order(OrderSide::Sell, 100, 1);
+        let reused_id = reused.id;
+        store.push_front(reused);
+        assert_eq!(store.nodes.len(), slots);
+        assert_eq!(level_ids(&store, &OrderSide::Sell, 100), vec![reused_id, ids[2], ids[0]]);
+
+        // 清空档位后价格档位被移除
+        for _ in 0..3 {
+            store.pop_front(&OrderSide::Sell, store.key(Decimal::new(100, 0)));
+        }
+        assert_eq!(store.best_price(&OrderSide::Sell), Some(Decimal::new(101, 0)));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.orders_best_first(&OrderSide::Sell).count(), 1);
+    }
+}
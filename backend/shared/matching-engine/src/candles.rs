@@ -0,0 +1,472 @@
+//! Candlestick (OHLCV) aggregation
+//!
+//! `CandleAggregator` folds a symbol's trades into klines for each
+//! configured interval. Closed candles are kept in a fixed-size ring buffer
+//! per interval and handed to an optional `CandleStore` as they close, so a
+//! database can keep the full history while the service serves recent
+//! candles from memory. `candles_in_range` reads candles older than the
+//! ring buffer back from the store. Intervals without trades produce no
+//! candle.
+
+use chrono::{DateTime, Duration, Utc};
+use flowex_types::{Candle, CandleInterval, FlowExResult, Trade};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Closed candles kept in memory per interval
+pub const DEFAULT_CANDLE_CAPACITY: usize = 1000;
+
+/// Persistence hook for closed candles
+pub trait CandleStore: Debug + Send + Sync {
+    /// Persist a candle that has closed
+    fn save(&self, candle: &Candle) -> FlowExResult<()>;
+
+    /// Most recent `limit` closed candles, oldest first
+    fn load(&self, symbol: &str, interval: CandleInterval, limit: usize) -> FlowExResult<Vec<Candle>>;
+
+    /// Closed candles opening within `[start, end)`, oldest first: the
+    /// first `limit` from `start` if set, otherwise the `limit` most recent
+    fn range(
+        &self,
+        symbol: &str,
+        interval: CandleInterval,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> FlowExResult<Vec<Candle>>;
+}
+
+/// `limit` of `candles` (oldest first): the first ones when reading forward
+/// from a start time, otherwise the most recent
+pub fn take_candles(candles: Vec<Candle>, from_start: bool, limit: usize) -> Vec<Candle> {
+    if from_start {
+        candles.into_iter().take(limit).collect()
+    } else {
+        let skip = candles.len().saturating_sub(limit);
+        candles.into_iter().skip(skip).collect()
+    }
+}
+
+/// Candles of one interval: the closed ring buffer and the candle in progress
+#[derive(Debug, Default)]
+struct Series {
+    closed: VecDeque<Candle>,
+    current: Option<Candle>,
+}
+
+/// Builds candles for one symbol from its trades
+#[derive(Debug)]
+pub struct CandleAggregator {
+    symbol: String,
+    capacity: usize,
+    series: BTreeMap<CandleInterval, Series>,
+    store: Option<Arc<dyn CandleStore>>,
+}
+
+impl CandleAggregator {
+    /// Aggregate every supported interval with the default capacity
+    pub fn new(symbol: String) -> Self {
+        Self::with_intervals(symbol, &CandleInterval::ALL, DEFAULT_CANDLE_CAPACITY)
+    }
+
+    /// Aggregate the given intervals, keeping `capacity` closed candles each
+    pub fn with_intervals(symbol: String, intervals: &[CandleInterval], capacity: usize) -> Self {
+        Self {
+            symbol,
+            capacity: capacity.max(1),
+            series: intervals.iter().map(|interval| (*interval, Series::default())).collect(),
+            store: None,
+        }
+    }
+
+    /// Symbol the candles are built for
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    /// Intervals being aggregated
+    pub fn intervals(&self) -> impl Iterator<Item = CandleInterval> + '_ {
+        self.series.keys().copied()
+    }
+
+    /// Install or remove the store closed candles are persisted to
+    pub fn set_store(&mut self, store: Option<Arc<dyn CandleStore>>) {
+        self.store = store;
+    }
+
+    /// Fill the ring buffers from the store, e.g. after a restart.
+    ///
+    /// Returns the number of candles loaded.
+    pub fn load_history(&mut self) -> FlowExResult<usize> {
+        let Some(store) = self.store.clone() else {
+            return Ok(0);
+        };
+
+        let mut loaded = 0;
+        for (interval, series) in &mut self.series {
+            let candles = store.load(&self.symbol, *interval, self.capacity)?;
+            loaded += candles.len();
+            series.closed = candles.into();
+            while series.closed.len() > self.capacity {
+                series.closed.pop_front();
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Fold a trade into every interval and return the candles it closed.
+    ///
+    /// Trades must arrive in time order; a trade older than the candle in
+    /// progress, or falling in a candle already closed, is ignored for that
+    /// interval, so replaying trades after `load_history` adds nothing twice.
+    pub fn ingest(&mut self, trade: &Trade) -> Vec<Candle> {
+        let mut closed = Vec::new();
+
+        for (interval, series) in &mut self.series {
+            let open_time = interval.open_time(trade.timestamp);
+            let latest = series.current.as_ref().or(series.closed.back()).map(|candle| candle.open_time);
+
+            match &mut series.current {
+                Some(candle) if candle.open_time == open_time => {
+                    candle.high = candle.high.max(trade.price);
+                    candle.low = candle.low.min(trade.price);
+                    candle.close = trade.price;
+                    candle.volume += trade.quantity;
+                    candle.quote_volume += trade.price * trade.quantity;
+                    candle.trade_count += 1;
+                }
+                _ if latest.is_some_and(|latest| latest >= open_time) => {
+                    debug!(
+                        "Ignoring late {} trade {} for the {} candle at {}",
+                        self.symbol,
+                        trade.id,
+                        interval.as_str(),
+                        open_time
+                    );
+                }
+                current => {
+                    if let Some(candle) = current.replace(new_candle(&self.symbol, *interval, open_time, trade)) {
+                        closed.push(candle);
+                    }
+                }
+            }
+        }
+
+        self.close(closed)
+    }
+
+    /// Close candles whose interval ended at or before `now` and return them.
+    ///
+    /// Call periodically so quiet markets still persist their last candle.
+    pub fn close_expired(&mut self, now: DateTime<Utc>) -> Vec<Candle> {
+        let closed = self
+            .series
+            .values_mut()
+            .filter(|series| series.current.as_ref().is_some_and(|candle| candle.close_time <= now))
+            .filter_map(|series| series.current.take())
+            .collect();
+
+        self.close(closed)
+    }
+
+    /// Up to `limit` most recent candles of an interval, oldest first,
+    /// including the candle in progress. `None` if the interval is not
+    /// aggregated.
+    pub fn candles(&self, interval: CandleInterval, limit: usize) -> Option<Vec<Candle>> {
+        let series = self.series.get(&interval)?;
+        let all: Vec<&Candle> = series.closed.iter().chain(series.current.as_ref()).collect();
+        let skip = all.len().saturating_sub(limit);
+        Some(all.into_iter().skip(skip).cloned().collect())
+    }
+
+    /// Candles of an interval opening within `[start, end)`, oldest first,
+    /// including the candle in progress: the first `limit` from `start` if
+    /// set, otherwise the `limit` most recent. Candles older than the ring
+    /// buffer come from the store. `None` if the interval is not aggregated.
+    pub fn candles_in_range(
+        &self,
+        interval: CandleInterval,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> FlowExResult<Option<Vec<Candle>>> {
+        let Some(series) = self.series.get(&interval) else {
+            return Ok(None);
+        };
+        let in_range = |candle: &&Candle| {
+            start.is_none_or(|start| candle.open_time >= start) && end.is_none_or(|end| candle.open_time < end)
+        };
+        let recent: Vec<Candle> = series
+            .closed
+            .iter()
+            .chain(series.current.as_ref())
+            .filter(in_range)
+            .cloned()
+            .collect();
+
+        // Only candles before the oldest one in memory are read back
+        let oldest = series.closed.front().or(series.current.as_ref()).map(|candle| candle.open_time);
+        let reaches_past_memory = match (start, oldest) {
+            (_, None) => true,
+            (Some(start), Some(oldest)) => start < oldest,
+            (None, Some(_)) => recent.len() < limit,
+        };
+        let mut candles = match &self.store {
+            Some(store) if reaches_past_memory => {
+                let older_end = match (end, oldest) {
+                    (Some(end), Some(oldest)) => Some(end.min(oldest)),
+                    (end, oldest) => end.or(oldest),
+                };
+                store.range(&self.symbol, interval, start, older_end, limit)?
+            }
+            _ => Vec::new(),
+        };
+        candles.extend(recent);
+        Ok(Some(take_candles(candles, start.is_some(), limit)))
+    }
+
+    /// Candle in progress for an interval
+    pub fn current(&self, interval: CandleInterval) -> Option<&Candle> {
+        self.series.get(&interval)?.current.as_ref()
+    }
+
+    /// Move closed candles into their ring buffers and persist them
+    fn close(&mut self, candles: Vec<Candle>) -> Vec<Candle> {
+        for candle in &candles {
+            if let Some(store) = &self.store {
+                if let Err(e) = store.save(candle) {
+                    warn!(
+                        "Failed to persist {} {} candle at {}: {}",
+                        candle.symbol,
+                        candle.interval.as_str(),
+                        candle.open_time,
+                        e
+                    );
+                }
+            }
+
+            if let Some(series) = self.series.get_mut(&candle.interval) {
+                if series.closed.len() == self.capacity {
+                    series.closed.pop_front();
+                }
+                series.closed.push_back(candle.clone());
+            }
+        }
+        candles
+    }
+}
+
+fn new_candle(symbol: &str, interval: CandleInterval, open_time: DateTime<Utc>, trade: &Trade) -> Candle {
+    Candle {
+        symbol: symbol.to_string(),
+        interval,
+        open_time,
+        close_time: open_time + Duration::seconds(interval.seconds()),
+        open: trade.price,
+        high: trade.price,
+        low: trade.price,
+        close: trade.price,
+        volume: trade.quantity,
+        quote_volume: trade.price * trade.quantity,
+        trade_count: 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_types::OrderSide;
+    use rust_decimal::Decimal;
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    fn trade_at(price: i64, quantity: i64, at: DateTime<Utc>) -> Trade {
+        Trade {
+            id: Uuid::new_v4(),
+            symbol: "BTCUSDT".to_string(),
+            sequence: 0,
+            price: Decimal::new(price, 0),
+            quantity: Decimal::new(quantity, 0),
+            side: OrderSide::Buy,
+            maker_order_id: Uuid::new_v4(),
+            taker_order_id: Uuid::new_v4(),
+            maker_user_id: Uuid::new_v4(),
+            taker_user_id: Uuid::new_v4(),
+            is_buyer_maker: false,
+            maker_fee: Decimal::ZERO,
+            maker_fee_currency: None,
+            taker_fee: Decimal::ZERO,
+            taker_fee_currency: None,
+            timestamp: at,
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct MemoryStore {
+        candles: Mutex<Vec<Candle>>,
+    }
+
+    impl CandleStore for MemoryStore {
+        fn save(&self, candle: &Candle) -> FlowExResult<()> {
+            self.candles.lock().unwrap().push(candle.clone());
+            Ok(())
+        }
+
+        fn load(&self, symbol: &str, interval: CandleInterval, limit: usize) -> FlowExResult<Vec<Candle>> {
+            let candles: Vec<Candle> = self
+                .candles
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|candle| candle.symbol == symbol && candle.interval == interval)
+                .cloned()
+                .collect();
+            let skip = candles.len().saturating_sub(limit);
+            Ok(candles.into_iter().skip(skip).collect())
+        }
+
+        fn range(
+            &self,
+            symbol: &str,
+            interval: CandleInterval,
+            start: Option<DateTime<Utc>>,
+            end: Option<DateTime<Utc>>,
+            limit: usize,
+        ) -> FlowExResult<Vec<Candle>> {
+            let candles: Vec<Candle> = self
+                .candles
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|candle| candle.symbol == symbol && candle.interval == interval)
+                .filter(|candle| start.is_none_or(|start| candle.open_time >= start))
+                .filter(|candle| end.is_none_or(|end| candle.open_time < end))
+                .cloned()
+                .collect();
+            Ok(take_candles(candles, start.is_some(), limit))
+        }
+    }
+
+    /// 测试：成交按周期聚合为K线
+    #[test]
+    fn test_trades_aggregate_into_candles() {
+        let minute = CandleInterval::OneMinute.open_time(DateTime::from_timestamp(1_700_000_040, 0).unwrap());
+        let mut aggregator = CandleAggregator::with_intervals(
+            "BTCUSDT".to_string(),
+            &[CandleInterval::OneMinute, CandleInterval::FiveMinutes],
+            2,
+        );
+
+        assert!(aggregator.ingest(&trade_at(100, 1, minute)).is_empty());
+        aggregator.ingest(&trade_at(105, 2, minute + Duration::seconds(10)));
+        aggregator.ingest(&trade_at(95, 1, minute + Duration::seconds(50)));
+
+        let candle = aggregator.current(CandleInterval::OneMinute).unwrap();
+        assert_eq!(candle.open_time, minute);
+        assert_eq!(candle.close_time, minute + Duration::minutes(1));
+        assert_eq!(candle.open, Decimal::new(100, 0));
+        assert_eq!(candle.high, Decimal::new(105, 0));
+        assert_eq!(candle.low, Decimal::new(95, 0));
+        assert_eq!(candle.close, Decimal::new(95, 0));
+        assert_eq!(candle.volume, Decimal::new(4, 0));
+        assert_eq!(candle.quote_volume, Decimal::new(405, 0));
+        assert_eq!(candle.trade_count, 3);
+
+        // 下一分钟的成交关闭上一根1分钟K线
+        let closed = aggregator.ingest(&trade_at(110, 1, minute + Duration::seconds(61)));
+        let closed_intervals: Vec<CandleInterval> = closed.iter().map(|candle| candle.interval).collect();
+        assert!(closed_intervals.contains(&CandleInterval::OneMinute));
+
+        // 迟到的成交被忽略
+        aggregator.ingest(&trade_at(1, 1, minute));
+        let candles = aggregator.candles(CandleInterval::OneMinute, 10).unwrap();
+        assert_eq!(candles.len(), 2);
+        assert_eq!(candles[0].low, Decimal::new(95, 0));
+        assert_eq!(candles[1].open, Decimal::new(110, 0));
+
+        // 环形缓冲只保留最近的已关闭K线
+        aggregator.ingest(&trade_at(120, 1, minute + Duration::minutes(2)));
+        aggregator.ingest(&trade_at(130, 1, minute + Duration::minutes(3)));
+        let candles = aggregator.candles(CandleInterval::OneMinute, 10).unwrap();
+        assert_eq!(candles.len(), 3);
+        assert_eq!(candles[0].open, Decimal::new(110, 0));
+        assert_eq!(aggregator.candles(CandleInterval::OneMinute, 1).unwrap()[0].open, Decimal::new(130, 0));
+        assert!(aggregator.candles(CandleInterval::OneDay, 10).is_none());
+    }
+
+    /// 测试：关闭的K线写入存储并可在重启后加载
+    #[test]
+    fn test_candles_persist_and_reload() {
+        let store = Arc::new(MemoryStore::default());
+        let minute = CandleInterval::OneMinute.open_time(Utc::now());
+        let mut aggregator =
+            CandleAggregator::with_intervals("BTCUSDT".to_string(), &[CandleInterval::OneMinute], 10);
+        aggregator.set_store(Some(store.clone()));
+
+        aggregator.ingest(&trade_at(100, 1, minute));
+        aggregator.ingest(&trade_at(101, 1, minute + Duration::minutes(1)));
+        assert_eq!(store.candles.lock().unwrap().len(), 1);
+
+        // 无成交时由定时关闭持久化最后一根K线
+        assert!(aggregator.close_expired(minute + Duration::seconds(90)).is_empty());
+        assert_eq!(aggregator.close_expired(minute + Duration::minutes(2)).len(), 1);
+        assert!(aggregator.current(CandleInterval::OneMinute).is_none());
+        assert_eq!(store.candles.lock().unwrap().len(), 2);
+
+        let mut restarted =
+            CandleAggregator::with_intervals("BTCUSDT".to_string(), &[CandleInterval::OneMinute], 10);
+        restarted.set_store(Some(store));
+        assert_eq!(restarted.load_history().unwrap(), 2);
+        let candles = restarted.candles(CandleInterval::OneMinute, 10).unwrap();
+        assert_eq!(candles, aggregator.candles(CandleInterval::OneMinute, 10).unwrap());
+
+        // 重启后重放的成交不会生成重复的K线
+        restarted.ingest(&trade_at(101, 1, minute + Duration::seconds(70)));
+        assert!(restarted.current(CandleInterval::OneMinute).is_none());
+        restarted.ingest(&trade_at(102, 1, minute + Duration::minutes(2)));
+        assert_eq!(restarted.current(CandleInterval::OneMinute).unwrap().open, Decimal::new(102, 0));
+    }
+
+    /// 测试：按时间范围查询K线，超出内存缓冲的部分从存储读取
+    #[test]
+    fn test_candles_in_range() {
+        let store = Arc::new(MemoryStore::default());
+        let minute = CandleInterval::OneMinute.open_time(DateTime::from_timestamp(1_700_000_040, 0).unwrap());
+        let mut aggregator =
+            CandleAggregator::with_intervals("BTCUSDT".to_string(), &[CandleInterval::OneMinute], 2);
+        aggregator.set_store(Some(store));
+        for i in 0..5 {
+            aggregator.ingest(&trade_at(100 + i, 1, minute + Duration::minutes(i)));
+        }
+        let opens = |candles: Vec<Candle>| candles.iter().map(|candle| candle.open).collect::<Vec<_>>();
+        let price = |price: i64| Decimal::new(price, 0);
+
+        // 内存中仅保留最近两根已收盘K线和当前K线
+        let recent = aggregator.candles_in_range(CandleInterval::OneMinute, None, None, 3).unwrap().unwrap();
+        assert_eq!(opens(recent), vec![price(102), price(103), price(104)]);
+
+        let all = aggregator.candles_in_range(CandleInterval::OneMinute, None, None, 10).unwrap().unwrap();
+        assert_eq!(opens(all), (100..105).map(price).collect::<Vec<_>>());
+
+        let from_start = aggregator
+            .candles_in_range(CandleInterval::OneMinute, Some(minute + Duration::minutes(1)), None, 2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(opens(from_start), vec![price(101), price(102)]);
+
+        let window = aggregator
+            .candles_in_range(
+                CandleInterval::OneMinute,
+                Some(minute),
+                Some(minute + Duration::minutes(4)),
+                10,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(opens(window), (100..104).map(price).collect::<Vec<_>>());
+
+        assert!(aggregator.candles_in_range(CandleInterval::OneDay, None, None, 10).unwrap().is_none());
+    }
+}
@@ -0,0 +1,116 @@
+//! Per-symbol circuit breaker
+//!
+//! Tracks recent trade prices over a sliding window. A trade that would move
+//! the price more than the configured fraction away from any price traded
+//! within the window trips the breaker: the engine stops matching before
+//! that trade and halts the symbol until trading is explicitly resumed.
+//! Each level an order sweeps is recorded before the next is checked, so a
+//! single order cannot walk the book through the band.
+//!
+//! Windows are measured in the time of the commands, as journaled, rather
+//! than the wall clock, so a replayed journal halts at the same point.
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Circuit breaker settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Maximum fractional price move within the window (0.1 = 10%)
+    pub max_move: Decimal,
+    /// Sliding window the move is measured over
+    pub window: Duration,
+}
+
+/// Sliding window of recent trade prices
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    recent: VecDeque<(DateTime<Utc>, Decimal)>,
+}
+
+impl CircuitBreaker {
+    /// Create a circuit breaker with an empty price window
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Circuit breaker settings
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    /// Whether a trade at `price` stays within the band of prices traded in
+    /// the window ending at `now`
+    pub fn allows(&self, price: Decimal, now: DateTime<Utc>) -> bool {
+        let window_start = self.window_start(now);
+        let mut in_window = self
+            .recent
+            .iter()
+            .filter(|(at, _)| *at >= window_start)
+            .map(|(_, p)| *p);
+
+        let Some(first) = in_window.next() else {
+            return true;
+        };
+        let (low, high) = in_window.fold((first, first), |(low, high), p| (low.min(p), high.max(p)));
+
+        price <= low * (Decimal::ONE + self.config.max_move) && price >= high * (Decimal::ONE - self.config.max_move)
+    }
+
+    /// Record an executed trade price
+    pub fn record(&mut self, price: Decimal, now: DateTime<Utc>) {
+        let window_start = self.window_start(now);
+        while self.recent.front().is_some_and(|(at, _)| *at < window_start) {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((now, price));
+    }
+
+    /// Forget the price history, e.g. when trading resumes after a halt
+    pub fn reset(&mut self) {
+        self.recent.clear();
+    }
+
+    fn window_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        chrono::Duration::from_std(self.config.window)
+            .ok()
+            .and_then(|window| now.checked_sub_signed(window))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：窗口内价格波动超过阈值时熔断，窗口外的价格不计入
+    #[test]
+    fn test_price_band_within_window() {
+        let mut breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            max_move: Decimal::new(10, 2), // 10%
+            window: Duration::from_secs(60),
+        });
+        let start = Utc::now();
+
+        assert!(breaker.allows(Decimal::new(1000, 0), start));
+        breaker.record(Decimal::new(100, 0), start);
+        breaker.record(Decimal::new(105, 0), start + chrono::Duration::seconds(10));
+
+        assert!(breaker.allows(Decimal::new(110, 0), start + chrono::Duration::seconds(20)));
+        assert!(!breaker.allows(Decimal::new(111, 0), start + chrono::Duration::seconds(20)));
+        assert!(!breaker.allows(Decimal::new(94, 0), start + chrono::Duration::seconds(20)));
+
+        // The 100 print has left the window; the band is now around 105
+        assert!(breaker.allows(Decimal::new(115, 0), start + chrono::Duration::seconds(65)));
+
+        breaker.reset();
+        assert!(breaker.allows(Decimal::new(1000, 0), start + chrono::Duration::seconds(65)));
+    }
+}
@@ -0,0 +1,210 @@
+//! Two-lane command queue for the matching engine
+//!
+//! Cancels and amendments travel on a dedicated priority lane so they are
+//! never stuck behind a burst of new orders. A fairness budget bounds how
+//! many priority commands may be served in a row while new orders are
+//! waiting, so the normal lane cannot be starved either.
+
+use flowex_types::{FlowExError, FlowExResult};
+use tokio::sync::mpsc;
+
+/// Lane a command is submitted on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    /// Cancels and amendments
+    Priority,
+    /// New orders and everything else
+    Normal,
+}
+
+/// Command queue configuration
+#[derive(Debug, Clone)]
+pub struct CommandQueueConfig {
+    pub normal_capacity: usize,
+    pub priority_capacity: usize,
+    /// Maximum consecutive priority commands served while normal commands are pending
+    pub max_priority_burst: usize,
+}
+
+impl Default for CommandQueueConfig {
+    fn default() -> Self {
+        Self {
+            normal_capacity: 10_000,
+            priority_capacity: 10_000,
+            max_priority_burst: 32,
+        }
+    }
+}
+
+/// Create a new two-lane command queue
+pub fn command_queue<T>(config: CommandQueueConfig) -> (CommandSender<T>, CommandReceiver<T>) {
+    let (priority_tx, priority_rx) = mpsc::channel(config.priority_capacity.max(1));
+    let (normal_tx, normal_rx) = mpsc::channel(config.normal_capacity.max(1));
+
+    (
+        CommandSender {
+            priority: priority_tx,
+            normal: normal_tx,
+        },
+        CommandReceiver {
+            priority: priority_rx,
+            normal: normal_rx,
+            max_priority_burst: config.max_priority_burst.max(1),
+            priority_streak: 0,
+        },
+    )
+}
+
+/// Sending half of the command queue
+#[derive(Debug)]
+pub struct CommandSender<T> {
+    priority: mpsc::Sender<T>,
+    normal: mpsc::Sender<T>,
+}
+
+impl<T> Clone for CommandSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            priority: self.priority.clone(),
+            normal: self.normal.clone(),
+        }
+    }
+}
+
+impl<T> CommandSender<T> {
+    /// Submit a command, waiting for capacity on its lane
+    pub async fn send(&self, lane: Lane, command: T) -> FlowExResult<()> {
+        self.lane(lane)
+            .send(command)
+            .await
+            .map_err(|_| FlowExError::Internal("Matching engine command queue closed".to_string()))
+    }
+
+    /// Submit a command without waiting; fails if the lane is full
+    pub fn try_send(&self, lane: Lane, command: T) -> FlowExResult<()> {
+        self.lane(lane).try_send(command).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => {
+                FlowExError::Trading(format!("Matching engine {:?} lane is full", lane))
+            }
+            mpsc::error::TrySendError::Closed(_) => {
+                FlowExError::Internal("Matching engine command queue closed".to_string())
+            }
+        })
+    }
+
+    fn lane(&self, lane: Lane) -> &mpsc::Sender<T> {
+        match lane {
+            Lane::Priority => &self.priority,
+            Lane::Normal => &self.normal,
+        }
+    }
+}
+
+/// Receiving half of the command queue
+#[derive(Debug)]
+pub struct CommandReceiver<T> {
+    priority: mpsc::Receiver<T>,
+    normal: mpsc::Receiver<T>,
+    max_priority_burst: usize,
+    priority_streak: usize,
+}
+
+impl<T> CommandReceiver<T> {
+    /// Receive the next command, preferring the priority lane.
+    ///
+    /// Returns `None` once both lanes are closed and drained.
+    pub async fn recv(&mut self) -> Option<T> {
+        // Fairness: after a full priority burst, let one pending normal command through
+        if self.priority_streak >= self.max_priority_burst {
+            if let Ok(command) = self.normal.try_recv() {
+                self.priority_streak = 0;
+                return Some(command);
+            }
+        }
+
+        tokio::select! {
+            biased;
+
+            Some(command) = self.priority.recv() => {
+                self.priority_streak += 1;
+                Some(command)
+            }
+            Some(command) = self.normal.recv() => {
+                self.priority_streak = 0;
+                Some(command)
+            }
+            else => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：优先通道先于普通通道处理
+    #[tokio::test]
+    async fn test_priority_lane_served_first() {
+        let (tx, mut rx) = command_queue(CommandQueueConfig::default());
+
+        tx.send(Lane::Normal, "new-1").await.unwrap();
+        tx.send(Lane::Normal, "new-2").await.unwrap();
+        tx.send(Lane::Priority, "cancel-1").await.unwrap();
+
+        assert_eq!(rx.recv().await, Some("cancel-1"));
+        assert_eq!(rx.recv().await, Some("new-1"));
+        assert_eq!(rx.recv().await, Some("new-2"));
+    }
+
+    /// 测试：公平性 - 优先命令连续处理上限后放行一个普通命令
+    #[tokio::test]
+    async fn test_fairness_budget_prevents_starvation() {
+        let config = CommandQueueConfig {
+            max_priority_burst: 2,
+            ..Default::default()
+        };
+        let (tx, mut rx) = command_queue(config);
+
+        tx.send(Lane::Normal, "new-1").await.unwrap();
+        for cmd in ["cancel-1", "cancel-2", "cancel-3", "cancel-4"] {
+            tx.send(Lane::Priority, cmd).await.unwrap();
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..5 {
+            received.push(rx.recv().await.unwrap());
+        }
+
+        assert_eq!(received, vec!["cancel-1", "cancel-2", "new-1", "cancel-3", "cancel-4"]);
+    }
+
+    /// 测试：普通通道满时优先通道仍可提交
+    #[tokio::test]
+    async fn test_priority_lane_not_blocked_by_full_normal_lane() {
+        let config = CommandQueueConfig {
+            normal_capacity: 1,
+            priority_capacity: 1,
+            max_priority_burst: 4,
+        };
+        let (tx, mut rx) = command_queue(config);
+
+        tx.try_send(Lane::Normal, 1).unwrap();
+        assert!(tx.try_send(Lane::Normal, 2).is_err());
+        tx.try_send(Lane::Priority, 99).unwrap();
+
+        assert_eq!(rx.recv().await, Some(99));
+        assert_eq!(rx.recv().await, Some(1));
+    }
+
+    /// 测试：发送端全部关闭后接收端排空并结束
+    #[tokio::test]
+    async fn test_recv_returns_none_after_close() {
+        let (tx, mut rx) = command_queue(CommandQueueConfig::default());
+
+        tx.send(Lane::Normal, 1).await.unwrap();
+        drop(tx);
+
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(rx.recv().await, None);
+    }
+}
@@ -0,0 +1,82 @@
+//! Trade fee calculation
+//!
+//! A `FeeSchedule` injected into the engine prices both sides of every trade
+//! as it is created, so the maker and taker fees travel on the `Trade`
+//! itself and downstream services (settlement, wallets, reporting) never
+//! recompute them. Fee schedules hold no engine state and are not part of a
+//! snapshot; re-inject the schedule after restoring an engine.
+
+pub use flowex_types::Liquidity;
+use flowex_types::Order;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+/// Fee charged for one side of a trade; a negative amount is a rebate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fee {
+    pub amount: Decimal,
+    pub currency: String,
+}
+
+/// Computes the fee owed by an order's owner for a fill
+pub trait FeeSchedule: Debug + Send + Sync {
+    /// Fee for `order` filling `quantity` at `price` as maker or taker
+    fn fee(&self, order: &Order, liquidity: Liquidity, price: Decimal, quantity: Decimal) -> Fee;
+}
+
+/// Flat maker/taker rates on the trade notional, charged in the quote asset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateFeeSchedule {
+    /// Fraction of notional charged to makers (0.001 = 0.1%); may be negative
+    pub maker_rate: Decimal,
+    /// Fraction of notional charged to takers
+    pub taker_rate: Decimal,
+    pub quote_asset: String,
+}
+
+impl FeeSchedule for RateFeeSchedule {
+    fn fee(&self, _order: &Order, liquidity: Liquidity, price: Decimal, quantity: Decimal) -> Fee {
+        let rate = match liquidity {
+            Liquidity::Maker => self.maker_rate,
+            Liquidity::Taker => self.taker_rate,
+        };
+
+        Fee {
+            amount: price * quantity * rate,
+            currency: self.quote_asset.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MatchingEngine;
+    use flowex_test_support::create_limit_order;
+    use flowex_types::OrderSide;
+    use std::sync::Arc;
+
+    /// 测试：成交按费率计算挂单方与吃单方手续费
+    #[test]
+    fn test_trades_carry_fees() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine.add_order(create_limit_order(OrderSide::Sell, 50000, 2)).unwrap();
+
+        // 未配置费率时不收取手续费
+        let trades = engine.add_order(create_limit_order(OrderSide::Buy, 50000, 1)).unwrap().trades;
+        assert_eq!(trades[0].taker_fee, Decimal::ZERO);
+        assert!(trades[0].taker_fee_currency.is_none());
+
+        engine.set_fee_schedule(Some(Arc::new(RateFeeSchedule {
+            maker_rate: Decimal::new(-1, 4), // -0.01% rebate
+            taker_rate: Decimal::new(5, 4),  // 0.05%
+            quote_asset: "USDT".to_string(),
+        })));
+        let trades = engine.add_order(create_limit_order(OrderSide::Buy, 50000, 1)).unwrap().trades;
+        assert_eq!(trades[0].maker_fee, Decimal::new(-5, 0));
+        assert_eq!(trades[0].taker_fee, Decimal::new(25, 0));
+        assert_eq!(trades[0].maker_fee_currency.as_deref(), Some("USDT"));
+        assert_eq!(trades[0].taker_fee_currency.as_deref(), Some("USDT"));
+    }
+}
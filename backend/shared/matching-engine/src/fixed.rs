@@ -0,0 +1,246 @@
+//! Fixed-point prices and quantities
+//!
+//! `Decimal` arithmetic and comparisons are exact but far slower than
+//! machine integers. When a trading pair has a positive tick and step size,
+//! every valid price is a whole number of ticks and every valid quantity a
+//! whole number of lots, so the book can key its price levels by `i64`
+//! ticks instead. Conversion happens at the boundary, when an order enters
+//! or leaves the book; orders, trades and events keep their `Decimal`
+//! values. Fixed point is opt-in per engine and recorded in snapshots.
+
+use crate::book::OrderStore;
+use crate::MatchingEngine;
+use flowex_types::{FlowExError, FlowExResult, Order, OrderSide, TradingPair};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Conversion between decimal values and whole ticks and lots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedPointScale {
+    tick_size: Decimal,
+    step_size: Decimal,
+}
+
+impl FixedPointScale {
+    /// Scale with the given price tick and quantity step; both must be positive
+    pub fn new(tick_size: Decimal, step_size: Decimal) -> FlowExResult<Self> {
+        if tick_size <= Decimal::ZERO || step_size <= Decimal::ZERO {
+            return Err(FlowExError::Validation(
+                "Fixed-point prices need a positive tick size and step size".to_string(),
+            ));
+        }
+        Ok(Self { tick_size, step_size })
+    }
+
+    /// Scale of a trading pair's tick and step sizes
+    pub fn for_trading_pair(trading_pair: &TradingPair) -> FlowExResult<Self> {
+        Self::new(trading_pair.tick_size, trading_pair.step_size)
+    }
+
+    pub fn tick_size(&self) -> Decimal {
+        self.tick_size
+    }
+
+    pub fn step_size(&self) -> Decimal {
+        self.step_size
+    }
+
+    /// Whole ticks in `price`, or `None` if it is off the tick grid or out
+    /// of range
+    pub fn price_to_ticks(&self, price: Decimal) -> Option<i64> {
+        Self::whole_units(price, self.tick_size)
+    }
+
+    pub fn ticks_to_price(&self, ticks: i64) -> Decimal {
+        Decimal::from(ticks) * self.tick_size
+    }
+
+    /// Whole lots in `quantity`, or `None` if it is off the step grid or out
+    /// of range
+    pub fn quantity_to_lots(&self, quantity: Decimal) -> Option<i64> {
+        Self::whole_units(quantity, self.step_size)
+    }
+
+    pub fn lots_to_quantity(&self, lots: i64) -> Decimal {
+        Decimal::from(lots) * self.step_size
+    }
+
+    /// Ticks of the least aggressive resting price an order on `side` with
+    /// limit `limit` accepts. Limits off the tick grid round inward: down for
+    /// a buy, up for a sell. Out-of-range limits saturate.
+    pub fn limit_to_ticks(&self, side: &OrderSide, limit: Decimal) -> i64 {
+        let ticks = limit.checked_div(self.tick_size).unwrap_or(limit);
+        let ticks = match side {
+            OrderSide::Buy => ticks.floor(),
+            OrderSide::Sell => ticks.ceil(),
+        };
+        ticks.to_i64().unwrap_or(if ticks.is_sign_negative() { i64::MIN } else { i64::MAX })
+    }
+
+    fn whole_units(value: Decimal, unit: Decimal) -> Option<i64> {
+        let units = value.checked_div(unit)?;
+        if !units.fract().is_zero() {
+            return None;
+        }
+        units.to_i64()
+    }
+}
+
+impl MatchingEngine {
+    /// Key price levels by whole ticks of the trading pair's tick size, or
+    /// switch back to exact decimal levels.
+    ///
+    /// Enabling requires a trading pair with positive tick and step sizes
+    /// and a book whose resting orders all sit on its grid. The book is
+    /// rebuilt in place; time priority is preserved.
+    pub fn set_fixed_point(&mut self, enabled: bool) -> FlowExResult<()> {
+        let scale = if enabled {
+            let trading_pair = self.trading_pair.as_ref().ok_or_else(|| {
+                FlowExError::Validation("Fixed-point prices need a trading pair".to_string())
+            })?;
+            Some(FixedPointScale::for_trading_pair(trading_pair)?)
+        } else {
+            None
+        };
+        if scale == self.book.scale().copied() {
+            return Ok(());
+        }
+
+        let orders: Vec<Order> = self
+            .book
+            .orders_best_first(&OrderSide::Buy)
+            .chain(self.book.orders_best_first(&OrderSide::Sell))
+            .cloned()
+            .collect();
+        if let Some(scale) = &scale {
+            for order in &orders {
+                check_representable(scale, order)?;
+            }
+        }
+
+        let mut book = OrderStore::with_scale(scale);
+        for order in orders {
+            book.push_back(order);
+        }
+        self.book = book;
+
+        info!(
+            "{} {} book uses {} price levels",
+            if enabled { "Switched" } else { "Reverted" },
+            self.symbol,
+            if enabled { "fixed-point" } else { "decimal" }
+        );
+        Ok(())
+    }
+
+    /// Scale price levels are keyed by, if fixed point is enabled
+    pub fn fixed_point(&self) -> Option<&FixedPointScale> {
+        self.book.scale()
+    }
+}
+
+/// Reject an order whose price or quantity falls off the scale's grid
+pub(crate) fn check_representable(scale: &FixedPointScale, order: &Order) -> FlowExResult<()> {
+    if let Some(price) = order.price {
+        if scale.price_to_ticks(price).is_none() {
+            return Err(FlowExError::Validation(format!(
+                "Price {} of order {} is not a whole number of {} ticks",
+                price, order.id, scale.tick_size
+            )));
+        }
+    }
+    if scale.quantity_to_lots(order.quantity).is_none() {
+        return Err(FlowExError::Validation(format!(
+            "Quantity {} of order {} is not a whole number of {} lots",
+            order.quantity, order.id, scale.step_size
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+    use flowex_types::TradingStatus;
+
+    fn trading_pair() -> TradingPair {
+        TradingPair {
+            symbol: "BTCUSDT".to_string(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            status: TradingStatus::Trading,
+            min_price: Decimal::new(1, 2),
+            max_price: Decimal::new(1_000_000, 0),
+            min_qty: Decimal::new(1, 3),
+            max_qty: Decimal::new(1000, 0),
+            step_size: Decimal::new(1, 3),
+            tick_size: Decimal::new(5, 1), // 0.5
+            min_notional: Decimal::ZERO,
+        }
+    }
+
+    /// 测试：价格与数量在定点表示和十进制之间转换
+    #[test]
+    fn test_scale_conversions() {
+        let scale = FixedPointScale::for_trading_pair(&trading_pair()).unwrap();
+        assert_eq!(scale.price_to_ticks(Decimal::new(500005, 1)), Some(100001));
+        assert_eq!(scale.ticks_to_price(100001), Decimal::new(500005, 1));
+        assert_eq!(scale.price_to_ticks(Decimal::new(500002, 1)), None);
+        assert_eq!(scale.quantity_to_lots(Decimal::new(1250, 3)), Some(1250));
+        assert_eq!(scale.lots_to_quantity(1250), Decimal::new(125, 2));
+        assert_eq!(scale.quantity_to_lots(Decimal::new(12505, 4)), None);
+
+        // 不在最小变动单位上的限价向内取整
+        assert_eq!(scale.limit_to_ticks(&OrderSide::Buy, Decimal::new(10007, 2)), 200);
+        assert_eq!(scale.limit_to_ticks(&OrderSide::Sell, Decimal::new(10007, 2)), 201);
+        assert!(FixedPointScale::new(Decimal::ZERO, Decimal::ONE).is_err());
+    }
+
+    /// 测试：启用定点价格后撮合结果与十进制一致
+    #[test]
+    fn test_fixed_point_book_matches_like_decimal() {
+        let mut engine = MatchingEngine::with_trading_pair(trading_pair());
+        assert!(MatchingEngine::new("BTCUSDT".to_string()).set_fixed_point(true).is_err());
+
+        let resting = [
+            create_limit_order(OrderSide::Sell, Decimal::new(505, 1), Decimal::ONE),
+            create_limit_order(OrderSide::Sell, Decimal::new(500, 1), Decimal::ONE),
+            create_limit_order(OrderSide::Buy, Decimal::new(490, 1), Decimal::ONE),
+        ];
+        for order in resting.iter().cloned() {
+            engine.add_order(order).unwrap();
+        }
+
+        // 启用后挂单按原有优先级重建
+        engine.set_fixed_point(true).unwrap();
+        assert_eq!(engine.fixed_point().map(|scale| scale.tick_size()), Some(Decimal::new(5, 1)));
+        assert_eq!(engine.get_best_ask(), Some(Decimal::new(500, 1)));
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(490, 1)));
+
+        // 不在最小变动单位上的价格被拒绝
+        let off_tick = create_limit_order(OrderSide::Buy, Decimal::new(4902, 2), Decimal::ONE);
+        assert!(engine.add_order(off_tick).is_err());
+
+        // 买单按价格优先逐档成交，限价以内为止
+        let taker = create_limit_order(OrderSide::Buy, Decimal::new(505, 1), Decimal::new(3, 0));
+        let trades = engine.add_order(taker).unwrap().trades;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, Decimal::new(500, 1));
+        assert_eq!(trades[1].price, Decimal::new(505, 1));
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(505, 1)));
+        assert_eq!(engine.get_best_ask(), None);
+
+        // 快照保留定点设置
+        let restored = MatchingEngine::restore(engine.snapshot()).unwrap();
+        assert!(restored.fixed_point().is_some());
+        assert_eq!(restored.get_best_bid(), engine.get_best_bid());
+        assert_eq!(restored.snapshot().bids, engine.snapshot().bids);
+
+        engine.set_fixed_point(false).unwrap();
+        assert!(engine.fixed_point().is_none());
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(505, 1)));
+    }
+}
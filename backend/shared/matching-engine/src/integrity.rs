@@ -0,0 +1,188 @@
+//! Book integrity checks
+//!
+//! The matching loop relies on invariants the book maintains on its own:
+//! while trading continuously the best bid sits below the best ask, every
+//! resting order has quantity left and sits at its own side and price, and
+//! no price level is empty or miscounted. `verify_integrity` checks them
+//! all, logs any violation and can halt the symbol; restores run it before
+//! handing the engine back. Debug builds also assert them after every
+//! command that changes the book.
+
+use crate::MatchingEngine;
+use flowex_types::{FlowExError, FlowExResult, OrderSide, TradingStatus};
+use rust_decimal::Decimal;
+use thiserror::Error;
+use tracing::error;
+use uuid::Uuid;
+
+/// A broken book invariant
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum IntegrityViolation {
+    #[error("book is crossed: best bid {best_bid} is not below best ask {best_ask}")]
+    CrossedBook { best_bid: Decimal, best_ask: Decimal },
+
+    #[error("resting order {order_id} has remaining quantity {remaining}")]
+    InvalidRemaining { order_id: Uuid, remaining: Decimal },
+
+    #[error("resting order {order_id} is queued at {side:?} {price} but belongs elsewhere")]
+    MisplacedOrder { order_id: Uuid, side: OrderSide, price: Decimal },
+
+    #[error("{side:?} level {price} is empty")]
+    EmptyLevel { side: OrderSide, price: Decimal },
+
+    #[error("{side:?} level {price} records {recorded} orders but links {linked}")]
+    LevelCountMismatch {
+        side: OrderSide,
+        price: Decimal,
+        recorded: usize,
+        linked: usize,
+    },
+
+    #[error("{indexed} orders are indexed but {linked} are queued")]
+    IndexMismatch { indexed: usize, linked: usize },
+}
+
+impl MatchingEngine {
+    /// Every broken book invariant, best price first
+    pub fn integrity_violations(&self) -> Vec<IntegrityViolation> {
+        let mut violations = Vec::new();
+
+        // An auction collects crossing orders on purpose, and the order that
+        // trips a circuit breaker may rest across the spread until trading
+        // resumes
+        if !self.in_auction && self.trading_status == TradingStatus::Trading {
+            if let (Some(best_bid), Some(best_ask)) = (self.get_best_bid(), self.get_best_ask()) {
+                if best_bid >= best_ask {
+                    violations.push(IntegrityViolation::CrossedBook { best_bid, best_ask });
+                }
+            }
+        }
+
+        let indexed = self.book.len();
+        let mut linked = 0;
+        for side in [OrderSide::Buy, OrderSide::Sell] {
+            for (price, recorded, orders) in self.book.recorded_levels(&side) {
+                // Bound the walk so a corrupted, cyclic queue still terminates
+                let orders: Vec<_> = orders.take(indexed + 1).collect();
+                if orders.is_empty() {
+                    violations.push(IntegrityViolation::EmptyLevel {
+                        side: side.clone(),
+                        price,
+                    });
+                }
+                if orders.len() != recorded {
+                    violations.push(IntegrityViolation::LevelCountMismatch {
+                        side: side.clone(),
+                        price,
+                        recorded,
+                        linked: orders.len(),
+                    });
+                }
+
+                for order in &orders {
+                    if order.remaining_quantity <= Decimal::ZERO {
+                        violations.push(IntegrityViolation::InvalidRemaining {
+                            order_id: order.id,
+                            remaining: order.remaining_quantity,
+                        });
+                    }
+                    if order.side != side || order.price != Some(price) {
+                        violations.push(IntegrityViolation::MisplacedOrder {
+                            order_id: order.id,
+                            side: side.clone(),
+                            price,
+                        });
+                    }
+                }
+                linked += orders.len();
+            }
+        }
+        if linked != indexed {
+            violations.push(IntegrityViolation::IndexMismatch { indexed, linked });
+        }
+
+        violations
+    }
+
+    /// Check the book's invariants, logging each violation. With `halt`,
+    /// a violation also halts trading so no order matches against a
+    /// corrupt book.
+    pub fn verify_integrity(&mut self, halt: bool) -> FlowExResult<()> {
+        let violations = self.integrity_violations();
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        for violation in &violations {
+            error!("Integrity violation on {}: {}", self.symbol, violation);
+        }
+        if halt {
+            error!("Halting {} after {} integrity violations", self.symbol, violations.len());
+            self.trading_status = TradingStatus::Halted;
+        }
+        Err(FlowExError::Internal(format!(
+            "{} book failed {} integrity checks, first: {}",
+            self.symbol,
+            violations.len(),
+            violations[0]
+        )))
+    }
+
+    /// Panic in debug builds if any book invariant is broken
+    pub(crate) fn debug_assert_book_valid(&self) {
+        if cfg!(debug_assertions) {
+            let violations = self.integrity_violations();
+            debug_assert!(violations.is_empty(), "{} book invariants broken: {:?}", self.symbol, violations);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::EngineSnapshot;
+    use flowex_test_support::create_limit_order;
+
+    /// 测试：交叉的订单簿在恢复时被检测并拒绝
+    #[test]
+    fn test_crossed_snapshot_detected() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine.add_order(create_limit_order(OrderSide::Buy, 99, 1)).unwrap();
+        engine.add_order(create_limit_order(OrderSide::Sell, 101, 1)).unwrap();
+        assert!(engine.integrity_violations().is_empty());
+        assert!(engine.verify_integrity(true).is_ok());
+
+        // 篡改快照使买价高于卖价
+        let mut snapshot: EngineSnapshot = engine.snapshot();
+        snapshot.bids[0].price = Some(Decimal::new(102, 0));
+        assert!(MatchingEngine::restore(snapshot.clone()).is_err());
+
+        // 集合竞价期间允许交叉
+        snapshot.in_auction = true;
+        assert!(MatchingEngine::restore(snapshot).is_ok());
+    }
+
+    /// 测试：检测到违规时记录并可暂停交易
+    #[test]
+    fn test_violations_reported_and_halt() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let order = create_limit_order(OrderSide::Sell, 101, 1);
+        let order_id = order.id;
+        engine.add_order(order).unwrap();
+
+        // 直接破坏挂单的剩余数量
+        engine.book.get_mut(order_id).unwrap().remaining_quantity = Decimal::new(-1, 0);
+        assert_eq!(
+            engine.integrity_violations(),
+            vec![IntegrityViolation::InvalidRemaining {
+                order_id,
+                remaining: Decimal::new(-1, 0),
+            }]
+        );
+
+        assert!(engine.verify_integrity(false).is_err());
+        assert_eq!(engine.trading_status(), &TradingStatus::Trading);
+        assert!(engine.verify_integrity(true).is_err());
+        assert_eq!(engine.trading_status(), &TradingStatus::Halted);
+    }
+}
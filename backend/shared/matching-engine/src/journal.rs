@@ -0,0 +1,415 @@
+//! Write-ahead command journal
+//!
+//! Every inbound command (orders, cancels, modifications, expiry sweeps,
+//! session updates, halts, auctions) is appended to a JSON lines file with a monotonic
+//! sequence number before it reaches the engine. Because the engine is
+//! deterministic in the order of commands it applies, replaying the journal
+//! on top of a snapshot (or an empty engine) rebuilds the same book, which
+//! is the basis for audit and disaster recovery.
+
+use crate::MatchingEngine;
+use chrono::{DateTime, Utc};
+use flowex_types::{CancelOrdersFilter, FlowExError, FlowExResult, Order, Trade};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Inbound engine command as recorded in the journal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalCommand {
+    NewOrder {
+        order: Order,
+    },
+    CancelOrder {
+        order_id: Uuid,
+    },
+    CancelAllForUser {
+        user_id: Uuid,
+    },
+    CancelAll {
+        filter: CancelOrdersFilter,
+    },
+    ModifyOrder {
+        order_id: Uuid,
+        price: Option<Decimal>,
+        quantity: Option<Decimal>,
+    },
+    HaltTrading,
+    ResumeTrading,
+    StartAuction,
+    RunAuction,
+    ExpireOrders {
+        now: DateTime<Utc>,
+    },
+    UpdateSession {
+        now: DateTime<Utc>,
+    },
+}
+
+impl JournalCommand {
+    /// Apply the command to an engine and return the trades it produced
+    pub fn apply(self, engine: &mut MatchingEngine) -> FlowExResult<Vec<Trade>> {
+        match self {
+            JournalCommand::NewOrder { order } => engine.add_order(order).map(|report| report.trades),
+            JournalCommand::CancelOrder { order_id } => engine.cancel_order(order_id).map(|_| Vec::new()),
+            JournalCommand::CancelAllForUser { user_id } => {
+                engine.cancel_all_for_user(user_id);
+                Ok(Vec::new())
+            }
+            JournalCommand::CancelAll { filter } => {
+                engine.cancel_all(&filter);
+                Ok(Vec::new())
+            }
+            JournalCommand::ModifyOrder { order_id, price, quantity } => {
+                engine.modify_order(order_id, price, quantity)
+            }
+            JournalCommand::HaltTrading => {
+                engine.halt_trading();
+                Ok(Vec::new())
+            }
+            JournalCommand::ResumeTrading => {
+                engine.resume_trading();
+                Ok(Vec::new())
+            }
+            JournalCommand::StartAuction => {
+                engine.start_auction();
+                Ok(Vec::new())
+            }
+            JournalCommand::RunAuction => engine.run_auction().map(|outcome| outcome.trades),
+            JournalCommand::ExpireOrders { now } => {
+                engine.expire_orders(now);
+                Ok(Vec::new())
+            }
+            JournalCommand::UpdateSession { now } => engine.update_session(now),
+        }
+    }
+}
+
+/// Journal record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub command: JournalCommand,
+}
+
+impl JournalEntry {
+    /// Apply the command to an engine as of the time it was journaled and
+    /// return the trades it produced
+    pub fn apply(self, engine: &mut MatchingEngine) -> FlowExResult<Vec<Trade>> {
+        engine.set_command_time(Some(self.timestamp));
+        let result = self.command.apply(engine);
+        engine.set_command_time(None);
+        result
+    }
+}
+
+/// Append-only command journal backed by a JSON lines file
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    last_sequence: u64,
+}
+
+impl Journal {
+    /// Open a journal for appending, creating the file if needed.
+    ///
+    /// Numbering continues after the last entry already in the file.
+    pub fn open(path: impl AsRef<Path>) -> FlowExResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let last_sequence = read_entries(&path)?.last().map_or(0, |entry| entry.sequence);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| journal_error(&path, e))?;
+
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            last_sequence,
+        })
+    }
+
+    /// Rebuild an engine from the journal and open it for appending.
+    ///
+    /// Entries at or below the engine's sequence (e.g. already covered by a
+    /// restored snapshot) are skipped. Order events and book updates
+    /// produced while replaying are discarded so they are not published a
+    /// second time.
+    pub fn recover(path: impl AsRef<Path>, engine: &mut MatchingEngine) -> FlowExResult<Self> {
+        let journal = Self::open(path)?;
+        let entries = read_entries(&journal.path)?;
+        let replayed = replay(engine, entries)?;
+        engine.drain_events();
+        engine.drain_book_update();
+
+        info!(
+            "Recovered {} engine from journal {}: replayed {} commands, now at sequence {}",
+            engine.symbol,
+            journal.path.display(),
+            replayed,
+            engine.sequence()
+        );
+        Ok(journal)
+    }
+
+    /// Durably append a command and return its journal entry
+    pub fn append(&mut self, command: JournalCommand) -> FlowExResult<JournalEntry> {
+        let entry = JournalEntry {
+            sequence: self.last_sequence + 1,
+            timestamp: Utc::now(),
+            command,
+        };
+
+        let line = serde_json::to_string(&entry)
+            .map_err(|e| FlowExError::Internal(format!("Failed to encode journal entry: {}", e)))?;
+        writeln!(self.writer, "{}", line)
+            .and_then(|_| self.writer.flush())
+            .and_then(|_| self.writer.get_ref().sync_data())
+            .map_err(|e| journal_error(&self.path, e))?;
+
+        self.last_sequence = entry.sequence;
+        Ok(entry)
+    }
+
+    /// Sequence number of the last appended entry, 0 when empty
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Path of the journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Read every entry of a journal file; a missing file is an empty journal.
+///
+/// A truncated final line (a crash mid-append) is ignored.
+pub fn read_entries(path: impl AsRef<Path>) -> FlowExResult<Vec<JournalEntry>> {
+    let path = path.as_ref();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(journal_error(path, e)),
+    };
+
+    let lines: Vec<String> = BufReader::new(file)
+        .lines()
+        .collect::<Result<_, _>>()
+        .map_err(|e| journal_error(path, e))?;
+
+    let mut entries = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<JournalEntry>(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) if index + 1 == lines.len() => {
+                warn!("Ignoring truncated journal tail in {}: {}", path.display(), e);
+            }
+            Err(e) => {
+                return Err(FlowExError::Internal(format!(
+                    "Corrupt journal entry at line {} of {}: {}",
+                    index + 1,
+                    path.display(),
+                    e
+                )))
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Apply journal entries newer than the engine's sequence, in order, each
+/// as of the time it was journaled.
+///
+/// Commands the engine rejects are rejected again, exactly as they were
+/// originally. Returns the number of entries applied; a gap in the sequence
+/// is an error.
+pub fn replay(engine: &mut MatchingEngine, entries: impl IntoIterator<Item = JournalEntry>) -> FlowExResult<usize> {
+    let mut applied = 0;
+
+    for entry in entries {
+        if entry.sequence <= engine.sequence() {
+            continue;
+        }
+        if entry.sequence != engine.sequence() + 1 {
+            return Err(FlowExError::Internal(format!(
+                "Journal gap: expected sequence {}, found {}",
+                engine.sequence() + 1,
+                entry.sequence
+            )));
+        }
+
+        let _ = entry.apply(engine);
+        applied += 1;
+    }
+
+    Ok(applied)
+}
+
+fn journal_error(path: &Path, e: std::io::Error) -> FlowExError {
+    FlowExError::Internal(format!("Journal I/O error on {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+    use flowex_types::OrderSide;
+
+    fn book_state(orders: &[Order]) -> Vec<(Uuid, Option<Decimal>, Decimal, Decimal)> {
+        orders
+            .iter()
+            .map(|o| (o.id, o.price, o.quantity, o.remaining_quantity))
+            .collect()
+    }
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("flowex-journal-{}-{}.jsonl", name, Uuid::new_v4()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    /// 测试：日志重放重建相同的订单簿
+    #[test]
+    fn test_replay_rebuilds_engine() {
+        let path = journal_path("replay");
+        let mut journal = Journal::open(&path).unwrap();
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        let bid = create_limit_order(OrderSide::Buy, 49000, 2);
+        let bid_id = bid.id;
+        let commands = vec![
+            JournalCommand::NewOrder { order: bid },
+            JournalCommand::NewOrder { order: create_limit_order(OrderSide::Buy, 48000, 1) },
+            JournalCommand::NewOrder { order: create_limit_order(OrderSide::Sell, 49000, 1) },
+            JournalCommand::ModifyOrder { order_id: bid_id, price: None, quantity: Some(Decimal::new(15, 1)) },
+            JournalCommand::CancelOrder { order_id: Uuid::new_v4() },
+        ];
+        for command in commands {
+            journal.append(command.clone()).unwrap();
+            let _ = command.apply(&mut engine);
+        }
+        assert_eq!(journal.last_sequence(), 5);
+
+        let mut recovered = MatchingEngine::new("BTCUSDT".to_string());
+        let reopened = Journal::recover(&path, &mut recovered).unwrap();
+        assert_eq!(reopened.last_sequence(), 5);
+        assert_eq!(recovered.sequence(), engine.sequence());
+        assert!(recovered.drain_events().is_empty());
+
+        // Timestamps differ between runs; the book state must not
+        let expected = engine.snapshot();
+        let actual = recovered.snapshot();
+        assert_eq!(book_state(&actual.bids), book_state(&expected.bids));
+        assert_eq!(book_state(&actual.asks), book_state(&expected.asks));
+        assert_eq!(actual.last_trade_price, expected.last_trade_price);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// 测试：熔断窗口按日志记录的命令时间计算，任何时候重放都得到相同的成交
+    #[test]
+    fn test_replay_circuit_breaker_uses_journaled_time() {
+        let start = Utc::now() - chrono::Duration::hours(1);
+        let commands = [
+            (0, create_limit_order(OrderSide::Sell, 100, 1)),
+            (0, create_limit_order(OrderSide::Buy, 100, 1)),
+            (120, create_limit_order(OrderSide::Sell, 120, 1)),
+            (120, create_limit_order(OrderSide::Buy, 120, 1)),
+        ];
+        let entries: Vec<JournalEntry> = commands
+            .into_iter()
+            .enumerate()
+            .map(|(index, (seconds, order))| JournalEntry {
+                sequence: index as u64 + 1,
+                timestamp: start + chrono::Duration::seconds(seconds),
+                command: JournalCommand::NewOrder { order },
+            })
+            .collect();
+
+        // The 100 print left the 60 second window two minutes before 120 traded
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine
+            .set_circuit_breaker(Some(crate::circuit_breaker::CircuitBreakerConfig {
+                max_move: Decimal::new(5, 2),
+                window: std::time::Duration::from_secs(60),
+            }))
+            .unwrap();
+        assert_eq!(replay(&mut engine, entries).unwrap(), 4);
+        assert_eq!(engine.trading_status(), &crate::TradingStatus::Trading);
+        assert_eq!(engine.snapshot().last_trade_price, Some(Decimal::new(120, 0)));
+    }
+
+    /// 测试：按日志时间重放得到完全相同的成交，包括成交ID和成交时间
+    #[test]
+    fn test_replayed_trades_are_identical() {
+        let start = Utc::now() - chrono::Duration::hours(1);
+        let orders = [
+            create_limit_order(OrderSide::Sell, 50000, 1),
+            create_limit_order(OrderSide::Sell, 50100, 1),
+            create_limit_order(OrderSide::Buy, 50100, 2),
+        ];
+        let entries: Vec<JournalEntry> = orders
+            .into_iter()
+            .enumerate()
+            .map(|(index, order)| JournalEntry {
+                sequence: index as u64 + 1,
+                timestamp: start + chrono::Duration::seconds(index as i64),
+                command: JournalCommand::NewOrder { order },
+            })
+            .collect();
+        let run = || {
+            let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+            entries
+                .iter()
+                .cloned()
+                .flat_map(|entry| entry.apply(&mut engine).unwrap())
+                .collect::<Vec<Trade>>()
+        };
+
+        let trades = run();
+        assert_eq!(trades.len(), 2);
+        assert_ne!(trades[0].id, trades[1].id);
+        assert!(trades.iter().all(|trade| trade.timestamp == entries[2].timestamp));
+        let replayed = run();
+        assert_eq!(
+            replayed.iter().map(|t| (t.id, t.sequence, t.timestamp)).collect::<Vec<_>>(),
+            trades.iter().map(|t| (t.id, t.sequence, t.timestamp)).collect::<Vec<_>>()
+        );
+    }
+
+    /// 测试：快照之后仅重放新的日志条目，并检测序号缺口
+    #[test]
+    fn test_replay_after_snapshot_and_gap() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let entries: Vec<JournalEntry> = (1..=3)
+            .map(|sequence| JournalEntry {
+                sequence,
+                timestamp: Utc::now(),
+                command: JournalCommand::NewOrder { order: create_limit_order(OrderSide::Buy, 49000, 1) },
+            })
+            .collect();
+
+        assert_eq!(replay(&mut engine, entries[..2].to_vec()).unwrap(), 2);
+        let snapshot = engine.snapshot();
+
+        let mut restored = MatchingEngine::restore(snapshot).unwrap();
+        assert_eq!(replay(&mut restored, entries.clone()).unwrap(), 1);
+        assert_eq!(restored.sequence(), 3);
+
+        let mut fresh = MatchingEngine::new("BTCUSDT".to_string());
+        assert!(replay(&mut fresh, entries[1..].to_vec()).is_err());
+    }
+}
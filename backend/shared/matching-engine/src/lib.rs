@@ -4,24 +4,134 @@
 //! and comprehensive trade execution capabilities.
 
 use flowex_types::{
-    Order, OrderSide, OrderType, OrderStatus, Trade, OrderBook, OrderBookLevel,
-    FlowExError, FlowExResult,
+    BookDelta, BookUpdate, CancelOrdersFilter, Order, OrderSide, OrderType, OrderStatus, Trade, OrderBook,
+    OrderBookEntry, OrderBookL3, OrderBookLevel, OrderEvent, OrderEventKind, Ticker, TimeInForce, TradingPair,
+    TradingStatus, FlowExError, FlowExResult,
 };
 use rust_decimal::Decimal;
-use std::collections::{BTreeMap, VecDeque};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
 use std::cmp::Ordering;
 use tracing::{info, debug, warn, error};
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+
+pub mod actor;
+pub mod allocation;
+pub mod auction;
+mod book;
+pub mod candles;
+pub mod circuit_breaker;
+pub mod command_queue;
+pub mod fees;
+pub mod fixed;
+pub mod integrity;
+pub mod journal;
+pub mod metrics;
+pub mod position;
+pub mod price_band;
+pub mod protection;
+pub mod session;
+pub mod snapshot;
+pub mod stats;
+pub mod trigger;
+
+use allocation::{Claim, MatchAllocator};
+use book::{OrderStore, PriceKey, PriceLevel};
+use circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use fees::{FeeSchedule, Liquidity};
+use metrics::EngineMetrics;
+use position::{reducible_quantity, PositionSource};
+use price_band::{PriceBand, ReferencePriceSource};
+use protection::MarketProtection;
+use session::SessionSchedule;
+use stats::MarketStats;
+use trigger::{TriggerBook, TriggerDirection};
+
+/// Namespace of the name-based (v5) ids given to trades
+const TRADE_ID_NAMESPACE: Uuid = Uuid::from_u128(0x5a5d4ae5_a14d_4067_9d91_c8edcfda0471);
+
+/// Outcome of submitting an order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionReport {
+    /// Order state after matching; its status tells whether the remainder
+    /// rests (`New`/`PartiallyFilled`), filled, or expired unfilled
+    pub order: Order,
+    /// Quantity the order filled on arrival
+    pub filled_quantity: Decimal,
+    /// Volume-weighted average price of those fills, `None` if nothing filled
+    pub average_price: Option<Decimal>,
+    /// Taker fees charged on those fills; zero without a fee schedule
+    pub fee: Decimal,
+    /// Currency of the fee, `None` when no fee schedule applies
+    pub fee_currency: Option<String>,
+    /// Trades executed, including those of conditional orders it triggered
+    pub trades: Vec<Trade>,
+}
+
+impl ExecutionReport {
+    /// Summarize the fills `order` took as the taker among `trades`
+    pub fn new(order: Order, trades: Vec<Trade>) -> Self {
+        let taken: Vec<&Trade> = trades.iter().filter(|trade| trade.taker_order_id == order.id).collect();
+        let filled_quantity: Decimal = taken.iter().map(|trade| trade.quantity).sum();
+        let notional: Decimal = taken.iter().map(|trade| trade.price * trade.quantity).sum();
+        let average_price = (filled_quantity > Decimal::ZERO).then(|| notional / filled_quantity);
+        let fee = taken.iter().map(|trade| trade.taker_fee).sum();
+        let fee_currency = taken.iter().find_map(|trade| trade.taker_fee_currency.clone());
+
+        Self {
+            order,
+            filled_quantity,
+            average_price,
+            fee,
+            fee_currency,
+            trades,
+        }
+    }
+
+    /// Quantity that expired unfilled instead of resting on the book
+    pub fn expired_quantity(&self) -> Decimal {
+        if self.order.status == OrderStatus::Expired {
+            self.order.remaining_quantity
+        } else {
+            Decimal::ZERO
+        }
+    }
+}
 
 /// Order matching engine for a single trading pair
 #[derive(Debug, Clone)]
 pub struct MatchingEngine {
     symbol: String,
-    buy_orders: BTreeMap<Decimal, VecDeque<Order>>, // Price -> Orders (highest first)
-    sell_orders: BTreeMap<Decimal, VecDeque<Order>>, // Price -> Orders (lowest first)
+    trading_pair: Option<TradingPair>, // Price/quantity constraints, if configured
+    market_protection: MarketProtection, // Slippage and sweep bounds for market orders
+    circuit_breaker: Option<CircuitBreaker>, // Halts matching on excessive price moves
+    price_band: Option<PriceBand>, // Limit price bound around the reference price
+    fee_schedule: Option<Arc<dyn FeeSchedule>>, // Prices maker and taker fees on each trade
+    position_source: Option<Arc<dyn PositionSource>>, // Positions reduce-only orders are checked against
+    metrics: Option<Arc<dyn EngineMetrics>>, // Receives latency, trade and book depth measurements
+    match_allocator: Option<Arc<dyn MatchAllocator>>, // Shares fills within a price level; FIFO if unset
+    reference_price_source: Option<Arc<dyn ReferencePriceSource>>, // Index prices the price band is measured from
+    trading_status: TradingStatus,
+    session_schedule: Option<SessionSchedule>, // Trading hours and maintenance windows
+    session_closed: bool, // Halted by the session schedule rather than an operator
+    session_queue: Vec<Order>, // Orders held until the session opens
+    in_auction: bool, // Orders rest without matching until run_auction()
+    book: OrderStore, // Resting orders: bids and asks by price, in time priority
+    triggers: TriggerBook, // Stop-loss / take-profit orders awaiting activation
+    user_orders: HashMap<Uuid, Vec<Uuid>>, // User -> open (resting or parked) order ids
     last_trade_price: Option<Decimal>,
     total_volume: Decimal,
+    stats: MarketStats, // Rolling 24h ticker statistics; not part of snapshots
+    events: Vec<OrderEvent>, // Order state changes awaiting drain_events()
+    touched_levels: Vec<(OrderSide, Decimal)>, // Price levels changed since drain_book_update()
+    last_book_update: u64, // Sequence of the last book update drained
+    sequence: u64, // Number of commands applied
+    trade_sequence: u64, // Number of trades executed
+    clock: DateTime<Utc>, // Latest timestamp issued; never moves backwards
+    command_time: Option<DateTime<Utc>>, // Journaled time of the command being applied; the wall clock if unset
 }
 
 impl MatchingEngine {
@@ -29,344 +139,1379 @@ impl MatchingEngine {
     pub fn new(symbol: String) -> Self {
         Self {
             symbol,
-            buy_orders: BTreeMap::new(),
-            sell_orders: BTreeMap::new(),
+            trading_pair: None,
+            market_protection: MarketProtection::default(),
+            circuit_breaker: None,
+            price_band: None,
+            fee_schedule: None,
+            position_source: None,
+            metrics: None,
+            match_allocator: None,
+            reference_price_source: None,
+            trading_status: TradingStatus::Trading,
+            session_schedule: None,
+            session_closed: false,
+            session_queue: Vec::new(),
+            in_auction: false,
+            book: OrderStore::new(),
+            triggers: TriggerBook::new(),
+            user_orders: HashMap::new(),
             last_trade_price: None,
             total_volume: Decimal::ZERO,
+            stats: MarketStats::default(),
+            events: Vec::new(),
+            touched_levels: Vec::new(),
+            last_book_update: 0,
+            sequence: 0,
+            trade_sequence: 0,
+            clock: DateTime::<Utc>::MIN_UTC,
+            command_time: None,
+        }
+    }
+
+    /// Create a matching engine that enforces a trading pair's price and
+    /// quantity bounds, tick size and step size
+    pub fn with_trading_pair(trading_pair: TradingPair) -> Self {
+        let mut engine = Self::new(trading_pair.symbol.clone());
+        engine.trading_pair = Some(trading_pair);
+        engine
+    }
+
+    /// Trading pair constraints enforced by the engine, if any
+    pub fn trading_pair(&self) -> Option<&TradingPair> {
+        self.trading_pair.as_ref()
+    }
+
+    /// Bound the price deviation and book sweep of market orders
+    pub fn set_market_protection(&mut self, protection: MarketProtection) -> FlowExResult<()> {
+        if protection
+            .max_deviation
+            .is_some_and(|deviation| deviation <= Decimal::ZERO || deviation >= Decimal::ONE)
+        {
+            return Err(FlowExError::Validation(
+                "Market order max deviation must be between 0 and 1".to_string(),
+            ));
+        }
+        if protection.max_levels == Some(0) {
+            return Err(FlowExError::Validation(
+                "Market order max levels must be positive".to_string(),
+            ));
+        }
+
+        self.market_protection = protection;
+        Ok(())
+    }
+
+    /// Market order protection in effect
+    pub fn market_protection(&self) -> &MarketProtection {
+        &self.market_protection
+    }
+
+    /// Enable (or with `None`, disable) the circuit breaker
+    pub fn set_circuit_breaker(&mut self, config: Option<CircuitBreakerConfig>) -> FlowExResult<()> {
+        if let Some(config) = &config {
+            if config.max_move <= Decimal::ZERO || config.max_move >= Decimal::ONE {
+                return Err(FlowExError::Validation(
+                    "Circuit breaker max move must be between 0 and 1".to_string(),
+                ));
+            }
+            if config.window.is_zero() {
+                return Err(FlowExError::Validation(
+                    "Circuit breaker window must be positive".to_string(),
+                ));
+            }
+        }
+
+        self.circuit_breaker = config.map(CircuitBreaker::new);
+        Ok(())
+    }
+
+    /// Circuit breaker settings, if enabled
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreakerConfig> {
+        self.circuit_breaker.as_ref().map(CircuitBreaker::config)
+    }
+
+    /// Set (or with `None`, clear) the fee schedule applied to new trades
+    pub fn set_fee_schedule(&mut self, fee_schedule: Option<Arc<dyn FeeSchedule>>) {
+        self.fee_schedule = fee_schedule;
+    }
+
+    /// Fee schedule applied to new trades, if any
+    pub fn fee_schedule(&self) -> Option<&dyn FeeSchedule> {
+        self.fee_schedule.as_deref()
+    }
+
+    /// Current trading status of the symbol
+    pub fn trading_status(&self) -> &TradingStatus {
+        &self.trading_status
+    }
+
+    /// Halt matching; new orders and re-priced modifications are rejected
+    /// while cancels are still accepted
+    pub fn halt_trading(&mut self) {
+        self.sequence += 1;
+        warn!("Trading halted for {}", self.symbol);
+        self.trading_status = TradingStatus::Halted;
+        self.session_closed = false;
+    }
+
+    /// Resume matching after a halt, starting a fresh circuit breaker window
+    pub fn resume_trading(&mut self) {
+        self.sequence += 1;
+        if let Some(breaker) = &mut self.circuit_breaker {
+            breaker.reset();
+        }
+        info!("Trading resumed for {}", self.symbol);
+        self.trading_status = TradingStatus::Trading;
+    }
+
+    /// Add an order to the order book, attempt to match, and report the
+    /// resulting order state, its fills and the trades
+    pub fn add_order(&mut self, order: Order) -> FlowExResult<ExecutionReport> {
+        let started = self.metrics.is_some().then(Instant::now);
+        let result = self.place_order(order);
+
+        if let (Some(metrics), Some(started)) = (&self.metrics, started) {
+            metrics.order_added(&self.symbol, started.elapsed());
         }
+        self.book_changed();
+        result
     }
 
-    /// Add an order to the order book and attempt to match
-    pub fn add_order(&mut self, mut order: Order) -> FlowExResult<Vec<Trade>> {
+    fn place_order(&mut self, mut order: Order) -> FlowExResult<ExecutionReport> {
         debug!("Adding order to matching engine: {:?}", order);
 
+        self.sequence += 1;
+
         // Validate order
-        self.validate_order(&order)?;
+        let duplicate = order
+            .client_order_id
+            .as_deref()
+            .and_then(|client_order_id| self.open_order_by_client_id(order.user_id, client_order_id));
+        let validation = if self.open_order(order.id).is_some() {
+            Err(FlowExError::Validation(format!("Order {} is already open", order.id)))
+        } else if let Some(existing) = duplicate {
+            Err(FlowExError::Validation(format!(
+                "Client order id {} is already used by open order {}",
+                existing.client_order_id.as_deref().unwrap_or_default(),
+                existing.id
+            )))
+        } else {
+            self.validate_order(&order)
+        };
+        if let Err(e) = validation {
+            self.reject(order, &e);
+            return Err(e);
+        }
 
-        let mut trades = Vec::new();
+        // Outside the session the order is queued or rejected
+        if self.session_closed {
+            return match self.hold_off_session(order.clone()) {
+                Ok(order) => Ok(ExecutionReport::new(order, Vec::new())),
+                Err(e) => {
+                    self.reject(order, &e);
+                    Err(e)
+                }
+            };
+        }
+        if let Err(e) = self.check_trading() {
+            self.reject(order, &e);
+            return Err(e);
+        }
 
-        match order.order_type {
-            OrderType::Market => {
-                trades = self.execute_market_order(&mut order)?;
+        // Conditional orders wait in the trigger book unless already triggered
+        if let Some(direction) = TriggerDirection::for_order(&order) {
+            let triggered = match (order.stop_price, self.last_trade_price) {
+                (Some(stop_price), Some(last_price)) => direction.is_triggered(stop_price, last_price),
+                _ => false,
+            };
+
+            if !triggered {
+                debug!("Parked conditional order {} until trigger {:?}", order.id, order.stop_price);
+                self.emit(OrderEventKind::Accepted, &order, None, None);
+                self.track_order(&order);
+                self.triggers.insert(order.clone());
+                return Ok(ExecutionReport::new(order, Vec::new()));
             }
-            OrderType::Limit => {
-                trades = self.execute_limit_order(&mut order)?;
+            order = trigger::activate(order);
+        }
+
+        if let Err(e) = self.limit_reduce_only(&mut order) {
+            self.reject(order, &e);
+            return Err(e);
+        }
+        let flag = match self.check_price_band(&order) {
+            Ok(flag) => flag,
+            Err(e) => {
+                self.reject(order, &e);
+                return Err(e);
             }
-            OrderType::StopLoss | OrderType::TakeProfit => {
-                // For now, treat as limit orders
-                // In production, these would be handled by a separate trigger system
-                trades = self.execute_limit_order(&mut order)?;
+        };
+
+        // During an auction orders are collected without matching
+        if self.in_auction {
+            if let Err(e) = Self::check_auction_order(&order) {
+                self.reject(order, &e);
+                return Err(e);
             }
+            self.emit(OrderEventKind::Accepted, &order, None, flag);
+            self.add_to_order_book(order.clone())?;
+            return Ok(ExecutionReport::new(order, Vec::new()));
         }
 
-        // If order is not fully filled, add to order book
-        if order.remaining_quantity > Decimal::ZERO && order.status != OrderStatus::Cancelled {
-            self.add_to_order_book(order)?;
+        if let Err(e) = self.check_executable(&order) {
+            self.reject(order, &e);
+            return Err(e);
         }
+        self.emit(OrderEventKind::Accepted, &order, None, flag);
 
-        Ok(trades)
+        let (order, mut trades) = self.execute_order(order)?;
+        if !trades.is_empty() {
+            trades.extend(self.activate_triggers());
+        }
+
+        Ok(ExecutionReport::new(order, trades))
     }
 
-    /// Cancel an order
-    pub fn cancel_order(&mut self, order_id: Uuid) -> FlowExResult<bool> {
-        // Remove from buy orders
-        for (_, orders) in self.buy_orders.iter_mut() {
-            if let Some(pos) = orders.iter().position(|o| o.id == order_id) {
-                let mut order = orders.remove(pos).unwrap();
-                order.status = OrderStatus::Cancelled;
-                info!("Cancelled buy order: {}", order_id);
-                return Ok(true);
+    /// Activate conditional orders crossed by the last trade price, cascading
+    /// until no further triggers fire. Triggers stay parked while halted.
+    fn activate_triggers(&mut self) -> Vec<Trade> {
+        let mut trades = Vec::new();
+
+        while let Some(last_price) = self.last_trade_price {
+            if self.trading_status != TradingStatus::Trading {
+                break;
+            }
+
+            let triggered = self.triggers.take_triggered(last_price);
+            if triggered.is_empty() {
+                break;
+            }
+
+            for order in triggered {
+                self.untrack_order(&order);
+                let mut order = trigger::activate(order);
+                let order_id = order.id;
+
+                if let Err(e) = self.limit_reduce_only(&mut order).and_then(|_| self.check_executable(&order)) {
+                    warn!("Triggered order {} rejected: {}", order_id, e);
+                    self.reject(order, &e);
+                    continue;
+                }
+
+                match self.execute_order(order) {
+                    Ok((_, executed)) => trades.extend(executed),
+                    Err(e) => warn!("Triggered order {} failed: {}", order_id, e),
+                }
             }
         }
 
-        // Remove from sell orders
-        for (_, orders) in self.sell_orders.iter_mut() {
-            if let Some(pos) = orders.iter().position(|o| o.id == order_id) {
-                let mut order = orders.remove(pos).unwrap();
-                order.status = OrderStatus::Cancelled;
-                info!("Cancelled sell order: {}", order_id);
-                return Ok(true);
+        trades
+    }
+
+    /// Reject orders while the symbol is not trading
+    fn check_trading(&self) -> FlowExResult<()> {
+        if self.trading_status != TradingStatus::Trading {
+            return Err(FlowExError::Trading(format!(
+                "Trading is {:?} for {}",
+                self.trading_status, self.symbol
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that must pass before an order touches the book
+    fn check_executable(&self, order: &Order) -> FlowExResult<()> {
+        // Post-only: reject rather than take liquidity
+        if order.post_only && self.would_cross(order) {
+            info!("Rejected post-only order {}: would cross the spread", order.id);
+            return Err(FlowExError::Trading(
+                "Post-only order would immediately match".to_string(),
+            ));
+        }
+
+        // Fill-or-kill: reject before touching the book unless the full quantity is available
+        if order.time_in_force == TimeInForce::Fok {
+            let available = self.available_liquidity(order, order.quantity);
+            if available < order.quantity {
+                info!(
+                    "Rejected FOK order {}: only {} of {} available",
+                    order.id, available, order.quantity
+                );
+                return Err(FlowExError::Trading(
+                    "Fill-or-kill order cannot be fully filled".to_string(),
+                ));
             }
         }
 
-        warn!("Order not found for cancellation: {}", order_id);
-        Ok(false)
+        Ok(())
     }
 
-    /// Get current order book snapshot
-    pub fn get_order_book(&self, depth: usize) -> OrderBook {
-        let mut bids = Vec::new();
-        let mut asks = Vec::new();
-
-        // Get top bids (highest prices first)
-        for (price, orders) in self.buy_orders.iter().rev().take(depth) {
-            let total_quantity: Decimal = orders.iter().map(|o| o.remaining_quantity).sum();
-            if total_quantity > Decimal::ZERO {
-                bids.push(OrderBookLevel {
-                    price: *price,
-                    quantity: total_quantity,
-                });
+    /// Match an active order and rest or expire any remainder; returns the
+    /// order's final state and its trades
+    fn execute_order(&mut self, mut order: Order) -> FlowExResult<(Order, Vec<Trade>)> {
+        let trades = match order.order_type {
+            OrderType::Market => self.execute_market_order(&mut order)?,
+            OrderType::Limit => self.execute_limit_order(&mut order)?,
+            OrderType::StopLoss | OrderType::TakeProfit => {
+                return Err(FlowExError::Trading(
+                    "Conditional orders must be activated before matching".to_string(),
+                ));
+            }
+        };
+
+        // If order is not fully filled, rest it on the book or expire the remainder
+        if order.remaining_quantity > Decimal::ZERO && !matches!(order.status, OrderStatus::Cancelled | OrderStatus::Expired) {
+            if Self::rests_on_book(&order) {
+                self.add_to_order_book(order.clone())?;
+            } else {
+                order.status = OrderStatus::Expired;
+                order.updated_at = self.now();
+                info!(
+                    "Expired unfilled remainder {} of {:?} {:?} order {}",
+                    order.remaining_quantity, order.order_type, order.time_in_force, order.id
+                );
+                let reason = format!("Unfilled remainder {} not allowed to rest", order.remaining_quantity);
+                self.emit(OrderEventKind::Expired, &order, None, Some(reason));
             }
         }
 
-        // Get top asks (lowest prices first)
-        for (price, orders) in self.sell_orders.iter().take(depth) {
-            let total_quantity: Decimal = orders.iter().map(|o| o.remaining_quantity).sum();
-            if total_quantity > Decimal::ZERO {
-                asks.push(OrderBookLevel {
-                    price: *price,
-                    quantity: total_quantity,
+        Ok((order, trades))
+    }
+
+    /// Price limit applied when matching an order; for market orders this is
+    /// the protection bound, if any
+    fn limit_price(&self, order: &Order) -> Option<Decimal> {
+        match order.order_type {
+            OrderType::Market => {
+                let reference = self.last_trade_price.or(match order.side {
+                    OrderSide::Buy => self.get_best_ask(),
+                    OrderSide::Sell => self.get_best_bid(),
                 });
+                self.market_protection.price_limit(&order.side, reference)
             }
+            _ => order.price,
         }
+    }
 
-        OrderBook {
-            symbol: self.symbol.clone(),
-            bids,
-            asks,
-            timestamp: Utc::now(),
+    /// Maximum number of price levels an order may sweep
+    fn max_levels(&self, order: &Order) -> Option<usize> {
+        match order.order_type {
+            OrderType::Market => self.market_protection.max_levels,
+            _ => None,
         }
     }
 
-    /// Get the best bid price
-    pub fn get_best_bid(&self) -> Option<Decimal> {
-        self.buy_orders.keys().rev().next().copied()
+    /// Whether a resting price is acceptable for an incoming order's limit
+    fn crosses<P: PartialOrd>(side: &OrderSide, price: P, limit_price: Option<P>) -> bool {
+        match (limit_price, side) {
+            (None, _) => true,
+            (Some(limit), OrderSide::Buy) => price <= limit,  // Buy order can match at or below limit price
+            (Some(limit), OrderSide::Sell) => price >= limit, // Sell order can match at or above limit price
+        }
     }
 
-    /// Get the best ask price
-    pub fn get_best_ask(&self) -> Option<Decimal> {
-        self.sell_orders.keys().next().copied()
+    /// Check invariants (debug builds) and report the book's new shape
+    /// after a command changed it
+    fn book_changed(&self) {
+        self.debug_assert_book_valid();
+        self.record_book_metrics();
     }
 
-    /// Get the spread
-    pub fn get_spread(&self) -> Option<Decimal> {
-        match (self.get_best_bid(), self.get_best_ask()) {
-            (Some(bid), Some(ask)) => Some(ask - bid),
-            _ => None,
+    /// Side of the book an order on `side` matches against
+    fn opposite(side: &OrderSide) -> OrderSide {
+        match side {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
         }
     }
 
-    /// Execute a market order
-    fn execute_market_order(&mut self, order: &mut Order) -> FlowExResult<Vec<Trade>> {
-        let mut trades = Vec::new();
-        let opposite_orders = match order.side {
-            OrderSide::Buy => &mut self.sell_orders,
-            OrderSide::Sell => &mut self.buy_orders,
+    /// Whether an order would immediately match against the best opposite price
+    fn would_cross(&self, order: &Order) -> bool {
+        let best_opposite = match order.side {
+            OrderSide::Buy => self.get_best_ask(),
+            OrderSide::Sell => self.get_best_bid(),
         };
 
-        let mut remaining_quantity = order.quantity;
+        best_opposite.is_some_and(|price| Self::crosses(&order.side, price, self.limit_price(order)))
+    }
+
+    /// Quantity on the opposite side of the book an order could match,
+    /// walking levels best price first and stopping once `needed` is reached
+    fn available_liquidity(&self, order: &Order, needed: Decimal) -> Decimal {
+        let side = &order.side;
+        let limit_price = self.limit_price(order);
+        let levels = self.book.levels_best_first(&Self::opposite(side));
 
-        // Iterate through price levels
-        let price_levels: Vec<Decimal> = opposite_orders.keys().copied().collect();
-        
-        for price in price_levels {
-            if remaining_quantity <= Decimal::ZERO {
+        let mut available = Decimal::ZERO;
+        for (price, orders) in levels.take(self.max_levels(order).unwrap_or(usize::MAX)) {
+            if !Self::crosses(side, price, limit_price) {
                 break;
             }
 
-            if let Some(orders_at_price) = opposite_orders.get_mut(&price) {
-                while let Some(mut counter_order) = orders_at_price.pop_front() {
-                    if remaining_quantity <= Decimal::ZERO {
-                        orders_at_price.push_front(counter_order);
-                        break;
-                    }
+            available += orders.map(|o| o.remaining_quantity).sum::<Decimal>();
+            if available >= needed {
+                break;
+            }
+        }
 
-                    let trade_quantity = remaining_quantity.min(counter_order.remaining_quantity);
-                    let trade_price = counter_order.price.unwrap_or(price);
+        available
+    }
 
-                    // Create trade
-                    let trade = self.create_trade(order, &counter_order, trade_price, trade_quantity)?;
-                    trades.push(trade);
+    /// Quantity of a resting order currently shown on the book.
+    ///
+    /// Iceberg slices are aligned to `display_quantity` boundaries of the
+    /// filled quantity; plain orders show their whole remainder.
+    fn visible_quantity(order: &Order) -> Decimal {
+        match order.display_quantity {
+            Some(display) if display > Decimal::ZERO => {
+                let slice = display - order.filled_quantity % display;
+                slice.min(order.remaining_quantity)
+            }
+            _ => order.remaining_quantity,
+        }
+    }
 
-                    // Update quantities
-                    remaining_quantity -= trade_quantity;
-                    counter_order.remaining_quantity -= trade_quantity;
-                    counter_order.filled_quantity += trade_quantity;
+    /// Whether an order's unfilled remainder may rest on the book
+    fn rests_on_book(order: &Order) -> bool {
+        order.order_type != OrderType::Market && order.time_in_force.is_resting()
+    }
 
-                    // Update order status
-                    if counter_order.remaining_quantity <= Decimal::ZERO {
-                        counter_order.status = OrderStatus::Filled;
-                    } else {
-                        counter_order.status = OrderStatus::PartiallyFilled;
-                        orders_at_price.push_front(counter_order);
-                    }
-                }
+    /// Modify a resting order's price and/or total quantity.
+    ///
+    /// A quantity decrease keeps the order's queue position; a price change
+    /// or quantity increase re-submits it at the back of the queue, where it
+    /// may match immediately.
+    pub fn modify_order(
+        &mut self,
+        order_id: Uuid,
+        new_price: Option<Decimal>,
+        new_quantity: Option<Decimal>,
+    ) -> FlowExResult<Vec<Trade>> {
+        self.sequence += 1;
+        let now = self.now();
+        let current = self
+            .book
+            .get(order_id)
+            .ok_or_else(|| FlowExError::Trading(format!("Order not found: {}", order_id)))?;
+        let side = current.side.clone();
+
+        let mut modified = current.clone();
+        if let Some(new_price) = new_price {
+            modified.price = Some(new_price);
+        }
+        if let Some(new_quantity) = new_quantity {
+            if new_quantity <= modified.filled_quantity {
+                return Err(FlowExError::Validation(
+                    "New quantity must exceed the filled quantity".to_string(),
+                ));
+            }
+            modified.quantity = new_quantity;
+            modified.remaining_quantity = new_quantity - modified.filled_quantity;
+        }
+        modified.updated_at = now;
+        self.limit_reduce_only(&mut modified)?;
+        let keeps_priority = modified.price == current.price && modified.quantity <= current.quantity;
 
-                // Remove empty price level
-                if orders_at_price.is_empty() {
-                    opposite_orders.remove(&price);
-                }
+        self.validate_order(&modified)?;
+        if modified.price != current.price {
+            self.check_price_band(&modified)?;
+        }
+
+        // Post-only orders must not cross at their new price; leave the original untouched
+        if !keeps_priority && !self.in_auction {
+            self.check_trading()?;
+            self.check_executable(&modified)?;
+        }
+
+        if keeps_priority {
+            info!("Reduced order {} to {} in place", order_id, modified.quantity);
+            if let Some(price) = modified.price {
+                self.touched_levels.push((side, price));
+            }
+            if let Some(order) = self.book.get_mut(order_id) {
+                *order = modified;
             }
+            self.book_changed();
+            return Ok(Vec::new());
         }
 
-        // Update market order
-        order.filled_quantity = order.quantity - remaining_quantity;
-        order.remaining_quantity = remaining_quantity;
-        
-        if remaining_quantity <= Decimal::ZERO {
-            order.status = OrderStatus::Filled;
-        } else if order.filled_quantity > Decimal::ZERO {
-            order.status = OrderStatus::PartiallyFilled;
+        self.remove_resting_order(order_id);
+
+        if self.in_auction {
+            info!("Re-queued modified order {} for the auction", order_id);
+            self.add_to_order_book(modified)?;
+            self.book_changed();
+            return Ok(Vec::new());
+        }
+
+        info!("Re-submitting modified order {} at {:?} for {}", order_id, modified.price, modified.quantity);
+        let (_, mut trades) = self.execute_order(modified)?;
+        if !trades.is_empty() {
+            trades.extend(self.activate_triggers());
         }
+        self.book_changed();
 
         Ok(trades)
     }
 
-    /// Execute a limit order
-    fn execute_limit_order(&mut self, order: &mut Order) -> FlowExResult<Vec<Trade>> {
-        let mut trades = Vec::new();
-        let order_price = order.price.ok_or_else(|| {
-            FlowExError::Trading("Limit order must have a price".to_string())
-        })?;
-
-        let opposite_orders = match order.side {
-            OrderSide::Buy => &mut self.sell_orders,
-            OrderSide::Sell => &mut self.buy_orders,
-        };
+    /// Remove a resting order from the book, dropping its level if emptied
+    fn remove_resting_order(&mut self, order_id: Uuid) -> Option<Order> {
+        let order = self.book.remove(order_id)?;
+        if let Some(price) = order.price {
+            self.touched_levels.push((order.side.clone(), price));
+        }
+        self.untrack_order(&order);
+        Some(order)
+    }
 
-        let mut remaining_quantity = order.quantity;
+    /// Cancel an order
+    pub fn cancel_order(&mut self, order_id: Uuid) -> FlowExResult<bool> {
+        self.sequence += 1;
 
-        // Find matching orders
-        let price_levels: Vec<Decimal> = opposite_orders.keys().copied().collect();
-        
-        for price in price_levels {
-            if remaining_quantity <= Decimal::ZERO {
-                break;
+        match self.cancel_open_order(order_id) {
+            Some(_) => {
+                self.book_changed();
+                Ok(true)
             }
-
-            // Check if price matches
-            let can_match = match order.side {
-                OrderSide::Buy => price <= order_price,  // Buy order can match at or below limit price
-                OrderSide::Sell => price >= order_price, // Sell order can match at or above limit price
-            };
-
-            if !can_match {
-                continue;
+            None => {
+                warn!("Order not found for cancellation: {}", order_id);
+                Ok(false)
             }
+        }
+    }
 
-            if let Some(orders_at_price) = opposite_orders.get_mut(&price) {
-                while let Some(mut counter_order) = orders_at_price.pop_front() {
-                    if remaining_quantity <= Decimal::ZERO {
-                        orders_at_price.push_front(counter_order);
-                        break;
-                    }
+    /// Cancel every open order of a user, resting and parked alike, and
+    /// return the cancelled orders
+    pub fn cancel_all_for_user(&mut self, user_id: Uuid) -> Vec<Order> {
+        self.cancel_all(&CancelOrdersFilter {
+            user_id: Some(user_id),
+            ..Default::default()
+        })
+    }
 
-                    let trade_quantity = remaining_quantity.min(counter_order.remaining_quantity);
-                    let trade_price = counter_order.price.unwrap_or(price);
+    /// Cancel every open order selected by `filter`, resting and parked
+    /// alike, and return the cancelled orders
+    pub fn cancel_all(&mut self, filter: &CancelOrdersFilter) -> Vec<Order> {
+        self.sequence += 1;
+
+        // A user filter narrows the candidates through the per-user index
+        let candidates: Vec<Uuid> = match filter.user_id {
+            Some(user_id) => self.user_orders.get(&user_id).cloned().unwrap_or_default(),
+            None => self
+                .book
+                .orders_best_first(&OrderSide::Buy)
+                .chain(self.book.orders_best_first(&OrderSide::Sell))
+                .chain(self.triggers.orders())
+                .chain(self.session_queue.iter())
+                .map(|order| order.id)
+                .collect(),
+        };
+        let selected: Vec<Uuid> = candidates
+            .into_iter()
+            .filter(|order_id| self.open_order(*order_id).is_some_and(|order| filter.matches(order)))
+            .collect();
+
+        let cancelled: Vec<Order> = selected
+            .into_iter()
+            .filter_map(|order_id| self.cancel_open_order(order_id))
+            .collect();
+
+        info!("Mass cancel on {} cancelled {} orders ({:?})", self.symbol, cancelled.len(), filter);
+        self.book_changed();
+        cancelled
+    }
 
-                    // Create trade
-                    let trade = self.create_trade(order, &counter_order, trade_price, trade_quantity)?;
-                    trades.push(trade);
+    /// Open orders of a user, resting and parked, in the order they were placed
+    pub fn open_orders_for_user(&self, user_id: Uuid) -> Vec<Order> {
+        self.user_orders
+            .get(&user_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|order_id| self.open_order(*order_id))
+            .cloned()
+            .collect()
+    }
 
-                    // Update quantities
-                    remaining_quantity -= trade_quantity;
-                    counter_order.remaining_quantity -= trade_quantity;
-                    counter_order.filled_quantity += trade_quantity;
+    /// Open order a user placed under `client_order_id`
+    pub fn open_order_by_client_id(&self, user_id: Uuid, client_order_id: &str) -> Option<&Order> {
+        self.user_orders
+            .get(&user_id)?
+            .iter()
+            .filter_map(|order_id| self.open_order(*order_id))
+            .find(|order| order.client_order_id.as_deref() == Some(client_order_id))
+    }
 
-                    // Update order status
-                    if counter_order.remaining_quantity <= Decimal::ZERO {
-                        counter_order.status = OrderStatus::Filled;
-                    } else {
-                        counter_order.status = OrderStatus::PartiallyFilled;
-                        orders_at_price.push_front(counter_order);
-                    }
+    /// Remove an open order from the trigger book or the book and record
+    /// the cancellation
+    fn cancel_open_order(&mut self, order_id: Uuid) -> Option<Order> {
+        // Pending conditional orders first, then the book
+        let mut order = match self.triggers.remove(order_id) {
+            Some(order) => {
+                self.untrack_order(&order);
+                order
+            }
+            None => match self.take_queued(order_id) {
+                Some(order) => {
+                    self.untrack_order(&order);
+                    order
                 }
+                None => self.remove_resting_order(order_id)?,
+            },
+        };
 
-                // Remove empty price level
-                if orders_at_price.is_empty() {
-                    opposite_orders.remove(&price);
-                }
+        order.status = OrderStatus::Cancelled;
+        order.updated_at = self.now();
+        info!("Cancelled {:?} order: {}", order.side, order_id);
+        self.emit(OrderEventKind::Cancelled, &order, None, None);
+        Some(order)
+    }
+
+    /// Look up an open order on the book, in the trigger book or in the
+    /// session queue
+    fn open_order(&self, order_id: Uuid) -> Option<&Order> {
+        self.book
+            .get(order_id)
+            .or_else(|| self.triggers.orders().find(|order| order.id == order_id))
+            .or_else(|| self.session_queue.iter().find(|order| order.id == order_id))
+    }
+
+    /// Add an order to its user's open order index
+    fn track_order(&mut self, order: &Order) {
+        self.user_orders.entry(order.user_id).or_default().push(order.id);
+    }
+
+    /// Remove an order from its user's open order index
+    fn untrack_order(&mut self, order: &Order) {
+        if let Some(order_ids) = self.user_orders.get_mut(&order.user_id) {
+            order_ids.retain(|order_id| *order_id != order.id);
+            if order_ids.is_empty() {
+                self.user_orders.remove(&order.user_id);
             }
         }
+    }
 
-        // Update limit order
-        order.filled_quantity = order.quantity - remaining_quantity;
-        order.remaining_quantity = remaining_quantity;
-        
-        if remaining_quantity <= Decimal::ZERO {
-            order.status = OrderStatus::Filled;
-        } else if order.filled_quantity > Decimal::ZERO {
-            order.status = OrderStatus::PartiallyFilled;
+    /// Remove good-till-date orders whose expiry is at or before `now`, from
+    /// the book and the trigger book, and emit an expiry event for each
+    pub fn expire_orders(&mut self, now: DateTime<Utc>) -> Vec<Order> {
+        self.sequence += 1;
+
+        let is_expired = |order: &Order| order.expires_at.is_some_and(|expires_at| expires_at <= now);
+        let mut expired = self.triggers.take_where(is_expired);
+        let (queued_expired, queued): (Vec<Order>, Vec<Order>) =
+            std::mem::take(&mut self.session_queue).into_iter().partition(|order| is_expired(order));
+        self.session_queue = queued;
+        expired.extend(queued_expired);
+        for order in self.book.remove_where(is_expired) {
+            if let Some(price) = order.price {
+                self.touched_levels.push((order.side.clone(), price));
+            }
+            expired.push(order);
         }
 
-        Ok(trades)
-    }
-
-    /// Add order to the order book
-    fn add_to_order_book(&mut self, order: Order) -> FlowExResult<()> {
-        let price = order.price.ok_or_else(|| {
-            FlowExError::Trading("Order must have a price to be added to order book".to_string())
-        })?;
+        for order in &mut expired {
+            order.status = OrderStatus::Expired;
+            order.updated_at = now;
+            self.untrack_order(order);
+        }
+        for order in &expired {
+            info!("Expired good-till-date order {} at {:?}", order.id, order.expires_at);
+            self.emit(OrderEventKind::Expired, order, None, Some("Good-till-date expiry reached".to_string()));
+        }
+        if !expired.is_empty() {
+            self.book_changed();
+        }
 
-        let order_book = match order.side {
-            OrderSide::Buy => &mut self.buy_orders,
-            OrderSide::Sell => &mut self.sell_orders,
-        };
+        expired
+    }
 
-        order_book.entry(price).or_insert_with(VecDeque::new).push_back(order);
-        
-        debug!("Added order to order book at price: {}", price);
-        Ok(())
+    /// Take all order events emitted since the last call
+    pub fn drain_events(&mut self) -> Vec<OrderEvent> {
+        std::mem::take(&mut self.events)
     }
 
-    /// Create a trade from two matching orders
-    fn create_trade(&mut self, taker_order: &Order, maker_order: &Order, price: Decimal, quantity: Decimal) -> FlowExResult<Trade> {
-        let (buyer_order_id, seller_order_id) = match taker_order.side {
-            OrderSide::Buy => (taker_order.id, maker_order.id),
-            OrderSide::Sell => (maker_order.id, taker_order.id),
-        };
+    /// Take the price level changes made since the last call as a book
+    /// update, or `None` when the book is unchanged
+    pub fn drain_book_update(&mut self) -> Option<BookUpdate> {
+        if self.touched_levels.is_empty() {
+            return None;
+        }
 
-        self.last_trade_price = Some(price);
-        self.total_volume += quantity;
+        let mut deltas: Vec<BookDelta> = Vec::new();
+        for (side, price) in std::mem::take(&mut self.touched_levels) {
+            if deltas.iter().any(|delta| delta.side == side && delta.price == price) {
+                continue;
+            }
+            let quantity = self.book.level(&side, price).map(Self::visible_quantity).sum();
+            deltas.push(BookDelta { side, price, quantity });
+        }
 
-        let trade = Trade {
-            id: Uuid::new_v4(),
+        let previous_sequence = std::mem::replace(&mut self.last_book_update, self.sequence);
+        Some(BookUpdate {
             symbol: self.symbol.clone(),
-            price,
-            quantity,
-            side: taker_order.side.clone(),
-            timestamp: Utc::now(),
+            sequence: self.sequence,
+            previous_sequence,
+            deltas,
+            timestamp: self.now(),
+        })
+    }
+
+    /// Record an order event
+    fn emit(&mut self, kind: OrderEventKind, order: &Order, trade_id: Option<Uuid>, reason: Option<String>) {
+        let timestamp = self.now();
+        self.events.push(OrderEvent {
+            kind,
+            order: order.clone(),
+            trade_id,
+            reason,
+            timestamp,
+        });
+    }
+
+    /// Record a fill event for an order after a trade
+    fn emit_fill(&mut self, order: &Order, trade_id: Uuid) {
+        let kind = if order.remaining_quantity <= Decimal::ZERO {
+            OrderEventKind::Filled
+        } else {
+            OrderEventKind::PartiallyFilled
         };
+        self.emit(kind, order, Some(trade_id), None);
+    }
 
-        info!("Trade executed: {} {} at {} for {}", 
-              self.symbol, quantity, price, trade.id);
+    /// Mark an order rejected and record the event
+    fn reject(&mut self, mut order: Order, error: &FlowExError) {
+        order.status = OrderStatus::Rejected;
+        self.emit(OrderEventKind::Rejected, &order, None, Some(error.to_string()));
+    }
 
-        Ok(trade)
+    /// Get current order book snapshot
+    pub fn get_order_book(&self, depth: usize) -> OrderBook {
+        // Top bids (highest prices first) and asks (lowest prices first)
+        let bids = Self::aggregate_levels(self.book.levels_best_first(&OrderSide::Buy), depth, |price| price);
+        let asks = Self::aggregate_levels(self.book.levels_best_first(&OrderSide::Sell), depth, |price| price);
+        self.order_book_view(bids, asks)
     }
 
-    /// Validate an order before processing
-    fn validate_order(&self, order: &Order) -> FlowExResult<()> {
-        if order.quantity <= Decimal::ZERO {
-            return Err(FlowExError::Validation("Order quantity must be positive".to_string()));
+    /// Get an order book snapshot with levels grouped into price buckets of
+    /// `group_size` (e.g. 10 to show a BTC book in $10 increments).
+    ///
+    /// Bids are rounded down and asks up, so a bucket never looks better
+    /// than the orders in it.
+    pub fn get_order_book_grouped(&self, depth: usize, group_size: Decimal) -> FlowExResult<OrderBook> {
+        if group_size <= Decimal::ZERO {
+            return Err(FlowExError::Validation("Price group size must be positive".to_string()));
         }
 
-        if order.trading_pair != self.symbol {
-            return Err(FlowExError::Validation("Order symbol does not match engine".to_string()));
+        let bids = Self::aggregate_levels(self.book.levels_best_first(&OrderSide::Buy), depth, |price| {
+            (price / group_size).floor() * group_size
+        });
+        let asks = Self::aggregate_levels(self.book.levels_best_first(&OrderSide::Sell), depth, |price| {
+            (price / group_size).ceil() * group_size
+        });
+        Ok(self.order_book_view(bids, asks))
+    }
+
+    /// Get a level-3 snapshot of individual resting orders, in priority
+    /// order, over the top `depth` price levels.
+    ///
+    /// With `user_id` set only that user's orders are listed, and levels
+    /// without any of them do not count towards `depth`.
+    pub fn get_order_book_l3(&self, depth: usize, user_id: Option<Uuid>) -> OrderBookL3 {
+        OrderBookL3 {
+            symbol: self.symbol.clone(),
+            sequence: self.sequence,
+            bids: Self::order_entries(self.book.levels_best_first(&OrderSide::Buy), depth, user_id),
+            asks: Self::order_entries(self.book.levels_best_first(&OrderSide::Sell), depth, user_id),
+            timestamp: Utc::now().max(self.clock),
         }
+    }
 
-        match order.order_type {
-            OrderType::Limit => {
-                if order.price.is_none() || order.price.unwrap() <= Decimal::ZERO {
-                    return Err(FlowExError::Validation("Limit order must have a positive price".to_string()));
-                }
-            }
-            OrderType::Market => {
-                // Market orders don't need price validation
+    /// List orders over the first `depth` price levels holding a match
+    fn order_entries<'a>(
+        levels: impl Iterator<Item = PriceLevel<'a>>,
+        depth: usize,
+        user_id: Option<Uuid>,
+    ) -> Vec<OrderBookEntry> {
+        levels
+            .map(|(price, orders)| {
+                orders
+                    .filter(|order| user_id.is_none_or(|user_id| order.user_id == user_id))
+                    .map(|order| OrderBookEntry {
+                        order_id: order.id,
+                        user_id: order.user_id,
+                        price,
+                        quantity: order.remaining_quantity,
+                        visible_quantity: Self::visible_quantity(order),
+                        timestamp: order.created_at,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|entries| !entries.is_empty())
+            .take(depth)
+            .flatten()
+            .collect()
+    }
+
+    /// Sum visible quantity per bucket over price levels in book order, up
+    /// to `depth` non-empty buckets
+    fn aggregate_levels<'a>(
+        levels: impl Iterator<Item = PriceLevel<'a>>,
+        depth: usize,
+        bucket: impl Fn(Decimal) -> Decimal,
+    ) -> Vec<OrderBookLevel> {
+        let mut aggregated: Vec<OrderBookLevel> = Vec::new();
+
+        for (price, orders) in levels {
+            let quantity: Decimal = orders.map(Self::visible_quantity).sum();
+            if quantity <= Decimal::ZERO {
+                continue;
             }
-            OrderType::StopLoss | OrderType::TakeProfit => {
-                if order.price.is_none() || order.price.unwrap() <= Decimal::ZERO {
-                    return Err(FlowExError::Validation("Stop/Take profit order must have a positive price".to_string()));
-                }
+
+            let price = bucket(price);
+            if let Some(level) = aggregated.last_mut().filter(|level| level.price == price) {
+                level.quantity += quantity;
+            } else if aggregated.len() == depth {
+                break;
+            } else {
+                aggregated.push(OrderBookLevel { price, quantity });
             }
         }
 
-        Ok(())
+        aggregated
     }
-}
 
-#[cfg(test)]
+    fn order_book_view(&self, bids: Vec<OrderBookLevel>, asks: Vec<OrderBookLevel>) -> OrderBook {
+        OrderBook {
+            symbol: self.symbol.clone(),
+            sequence: self.sequence,
+            bids,
+            asks,
+            timestamp: Utc::now().max(self.clock),
+        }
+    }
+
+    /// Rolling 24h ticker from the trades executed by this engine, `None`
+    /// before the first trade
+    pub fn get_ticker(&self) -> Option<Ticker> {
+        self.stats.ticker(&self.symbol, Utc::now().max(self.clock))
+    }
+
+    /// Rolling trade statistics, e.g. for VWAP and TWAP
+    pub fn market_stats(&self) -> &MarketStats {
+        &self.stats
+    }
+
+    /// Get the best bid price
+    pub fn get_best_bid(&self) -> Option<Decimal> {
+        self.book.best_price(&OrderSide::Buy)
+    }
+
+    /// Get the best ask price
+    pub fn get_best_ask(&self) -> Option<Decimal> {
+        self.book.best_price(&OrderSide::Sell)
+    }
+
+    /// Number of commands applied to the engine
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Sequence number of the last trade executed, 0 before the first trade
+    pub fn trade_sequence(&self) -> u64 {
+        self.trade_sequence
+    }
+
+    /// Current time, clamped so engine timestamps never go backwards when
+    /// the wall clock is adjusted
+    fn now(&mut self) -> DateTime<Utc> {
+        self.clock = self.clock.max(Utc::now());
+        self.clock
+    }
+
+    /// Apply the following commands as of `time`, the time they were
+    /// journaled at, so that decisions depending on time (the circuit
+    /// breaker window) come out the same when the journal is replayed;
+    /// `None` goes back to the wall clock
+    pub fn set_command_time(&mut self, time: Option<DateTime<Utc>>) {
+        self.command_time = time;
+    }
+
+    /// Time of the command being applied: its journaled time if set,
+    /// otherwise the current time
+    fn command_now(&mut self) -> DateTime<Utc> {
+        match self.command_time {
+            Some(time) => time,
+            None => self.now(),
+        }
+    }
+
+    /// Id of the trade being executed, unique to its symbol, command and
+    /// trade sequence
+    fn trade_id(&self) -> Uuid {
+        let name = format!("{}/{}/{}", self.symbol, self.sequence, self.trade_sequence);
+        Uuid::new_v5(&TRADE_ID_NAMESPACE, name.as_bytes())
+    }
+
+    /// Number of conditional orders awaiting their trigger
+    pub fn pending_trigger_count(&self) -> usize {
+        self.triggers.len()
+    }
+
+    /// Get the spread
+    pub fn get_spread(&self) -> Option<Decimal> {
+        match (self.get_best_bid(), self.get_best_ask()) {
+            (Some(bid), Some(ask)) => Some(ask - bid),
+            _ => None,
+        }
+    }
+
+    /// Execute a market order
+    fn execute_market_order(&mut self, order: &mut Order) -> FlowExResult<Vec<Trade>> {
+        let limit_price = self.limit_price(order);
+        let max_levels = self.max_levels(order);
+        if limit_price.is_some() || max_levels.is_some() {
+            debug!(
+                "Market order {} protected at {:?} within {:?} levels",
+                order.id, limit_price, max_levels
+            );
+        }
+        self.match_against_book(order, limit_price, max_levels)
+    }
+
+    /// Execute a limit order
+    fn execute_limit_order(&mut self, order: &mut Order) -> FlowExResult<Vec<Trade>> {
+        let order_price = order.price.ok_or_else(|| {
+            FlowExError::Trading("Limit order must have a price".to_string())
+        })?;
+
+        self.match_against_book(order, Some(order_price), None)
+    }
+
+    /// Match an order against the opposite side of the book, best price first.
+    ///
+    /// Levels beyond `limit_price` are not touched; `None` matches at any price.
+    /// At most `max_levels` price levels are swept when set.
+    fn match_against_book(
+        &mut self,
+        order: &mut Order,
+        limit_price: Option<Decimal>,
+        max_levels: Option<usize>,
+    ) -> FlowExResult<Vec<Trade>> {
+        let mut trades = Vec::new();
+        let mut fills: Vec<(Order, Decimal, Decimal)> = Vec::new();
+        let mut stale_reduce_only = Vec::new();
+        let started = self.metrics.is_some().then(Instant::now);
+        let now = self.now();
+        let command_time = self.command_now();
+        let opposite_side = Self::opposite(&order.side);
+        let mut remaining_quantity = order.remaining_quantity;
+        let mut levels_swept = 0;
+        let mut tripped = false;
+        let limit_key = limit_price.map(|limit| self.book.limit_key(&order.side, limit));
+
+        // Best price first: lowest asks for a buy, highest bids for a sell.
+        // Each level is either exhausted (and dropped) or ends the match.
+        while let Some((level, price)) = self.book.best_level(&opposite_side) {
+            if remaining_quantity <= Decimal::ZERO || levels_swept == max_levels.unwrap_or(usize::MAX) {
+                break;
+            }
+            levels_swept += 1;
+
+            // Check if price matches
+            if !Self::crosses(&order.side, level, limit_key) {
+                break;
+            }
+
+            // Trip the circuit breaker instead of trading outside the price
+            // band, which includes the levels this order already swept
+            if self.circuit_breaker.as_ref().is_some_and(|breaker| !breaker.allows(price, command_time)) {
+                warn!(
+                    "Circuit breaker tripped for {}: {} is outside the price band",
+                    self.symbol, price
+                );
+                self.trading_status = TradingStatus::Halted;
+                tripped = true;
+                break;
+            }
+            let filled_before = fills.len();
+
+            // A custom allocator shares the level first; time priority fills
+            // whatever it leaves, so a stalled allocator cannot leave a cross
+            if self.match_allocator.is_some() {
+                remaining_quantity = self.fill_level_allocated(
+                    &opposite_side,
+                    (level, price),
+                    remaining_quantity,
+                    now,
+                    &mut fills,
+                    &mut stale_reduce_only,
+                );
+            }
+
+            while remaining_quantity > Decimal::ZERO {
+                let Some(counter_order) = self.book.front_mut(&opposite_side, level) else {
+                    break;
+                };
+
+                let visible_quantity = Self::visible_quantity(counter_order);
+                let mut fillable = visible_quantity;
+
+                // A resting reduce-only order fills only what its owner's position still allows
+                if let Some(reducible) =
+                    Self::reduce_only_allowance(self.position_source.as_ref(), &self.symbol, counter_order, &fills)
+                {
+                    if reducible <= Decimal::ZERO {
+                        let order_id = counter_order.id;
+                        stale_reduce_only.extend(self.book.remove(order_id));
+                        continue;
+                    }
+                    fillable = fillable.min(reducible);
+                }
+
+                let trade_quantity = remaining_quantity.min(fillable);
+                let trade_price = counter_order.price.unwrap_or(price);
+                remaining_quantity -= trade_quantity;
+                Self::fill_resting(counter_order, trade_quantity);
+
+                // Record fill; trades are created once the book is settled
+                fills.push((counter_order.clone(), trade_price, trade_quantity));
+
+                if counter_order.status == OrderStatus::Filled {
+                    let order_id = counter_order.id;
+                    self.book.remove(order_id);
+                } else if trade_quantity >= visible_quantity {
+                    // Iceberg slice exhausted: refresh it at the back of the queue
+                    counter_order.updated_at = now;
+                    debug!("Refreshed iceberg slice for order {}", counter_order.id);
+                    self.book.rotate_front(&opposite_side, level);
+                }
+            }
+
+            if fills.len() > filled_before {
+                if let Some(breaker) = &mut self.circuit_breaker {
+                    breaker.record(price, command_time);
+                }
+            }
+        }
+
+        for mut order in stale_reduce_only {
+            if let Some(price) = order.price {
+                self.touched_levels.push((order.side.clone(), price));
+            }
+            self.untrack_order(&order);
+            order.status = OrderStatus::Cancelled;
+            order.updated_at = now;
+            info!("Cancelled reduce-only order {}: position already closed", order.id);
+            self.emit(
+                OrderEventKind::Cancelled,
+                &order,
+                None,
+                Some("Reduce-only order would increase the position".to_string()),
+            );
+        }
+
+        for (counter_order, trade_price, trade_quantity) in fills {
+            self.touched_levels.push((counter_order.side.clone(), trade_price));
+            if counter_order.status == OrderStatus::Filled {
+                self.untrack_order(&counter_order);
+            }
+            let trade = self.create_trade(order, &counter_order, trade_price, trade_quantity)?;
+
+            // Update incoming order
+            order.filled_quantity += trade_quantity;
+            order.remaining_quantity -= trade_quantity;
+            order.status = if order.remaining_quantity <= Decimal::ZERO {
+                OrderStatus::Filled
+            } else {
+                OrderStatus::PartiallyFilled
+            };
+
+            self.emit_fill(&counter_order, trade.id);
+            self.emit_fill(order, trade.id);
+            trades.push(trade);
+        }
+
+        // Resting the remainder of an order halted mid-sweep would cross the
+        // book, so it expires instead
+        if tripped && order.remaining_quantity > Decimal::ZERO {
+            order.status = OrderStatus::Expired;
+            order.updated_at = now;
+            info!("Expired remainder {} of order {}: trading halted", order.remaining_quantity, order.id);
+            self.emit(
+                OrderEventKind::Expired,
+                order,
+                None,
+                Some("Circuit breaker halted trading".to_string()),
+            );
+        }
+
+        if let (Some(metrics), Some(started)) = (&self.metrics, started) {
+            metrics.matched(&self.symbol, started.elapsed(), trades.len());
+        }
+        Ok(trades)
+    }
+
+    /// Fill one price level as the installed allocator shares it, in passes
+    /// until the incoming quantity or the level runs out or the allocator
+    /// stops allocating. Returns the incoming quantity left.
+    fn fill_level_allocated(
+        &mut self,
+        side: &OrderSide,
+        (level, price): (PriceKey, Decimal),
+        mut remaining_quantity: Decimal,
+        now: DateTime<Utc>,
+        fills: &mut Vec<(Order, Decimal, Decimal)>,
+        stale_reduce_only: &mut Vec<Order>,
+    ) -> Decimal {
+        let Some(allocator) = self.match_allocator.clone() else {
+            return remaining_quantity;
+        };
+
+        while remaining_quantity > Decimal::ZERO {
+            let mut stale = Vec::new();
+            let mut claims = Vec::new();
+            for order in self.book.queue(side, level) {
+                let mut available = Self::visible_quantity(order);
+                if let Some(reducible) =
+                    Self::reduce_only_allowance(self.position_source.as_ref(), &self.symbol, order, fills)
+                {
+                    // Earlier reduce-only orders of the same owner in this pass claim part of the position
+                    let claimed: Decimal = claims
+                        .iter()
+                        .filter(|claim: &&Claim| claim.order.reduce_only && claim.order.user_id == order.user_id)
+                        .map(|claim| claim.available)
+                        .sum();
+                    if reducible - claimed <= Decimal::ZERO {
+                        stale.push(order.id);
+                        continue;
+                    }
+                    available = available.min(reducible - claimed);
+                }
+                claims.push(Claim { order, available });
+            }
+
+            // Clamp the allocation to what each order shows and the incoming quantity
+            let mut budget = remaining_quantity;
+            let plan: Vec<(Uuid, Decimal, Decimal)> = claims
+                .iter()
+                .zip(allocator.allocate(remaining_quantity, &claims))
+                .map(|(claim, allocation)| {
+                    let quantity = allocation.max(Decimal::ZERO).min(claim.available).min(budget);
+                    budget -= quantity;
+                    (claim.order.id, quantity, claim.available)
+                })
+                .collect();
+
+            let mut progressed = !stale.is_empty();
+            stale_reduce_only.extend(stale.into_iter().filter_map(|order_id| self.book.remove(order_id)));
+
+            for (order_id, trade_quantity, visible_quantity) in plan {
+                if trade_quantity <= Decimal::ZERO {
+                    continue;
+                }
+                let Some(counter_order) = self.book.get_mut(order_id) else {
+                    continue;
+                };
+                progressed = true;
+                remaining_quantity -= trade_quantity;
+                Self::fill_resting(counter_order, trade_quantity);
+                fills.push((counter_order.clone(), counter_order.price.unwrap_or(price), trade_quantity));
+
+                if counter_order.status == OrderStatus::Filled {
+                    self.book.remove(order_id);
+                } else if trade_quantity >= visible_quantity {
+                    counter_order.updated_at = now;
+                    debug!("Refreshed iceberg slice for order {}", order_id);
+                    self.book.move_to_back(order_id);
+                }
+            }
+
+            if !progressed {
+                break;
+            }
+        }
+
+        remaining_quantity
+    }
+
+    /// Quantity a resting reduce-only order may still fill given the fills
+    /// already made in this matching pass, or `None` for other orders
+    fn reduce_only_allowance(
+        position_source: Option<&Arc<dyn PositionSource>>,
+        symbol: &str,
+        order: &Order,
+        fills: &[(Order, Decimal, Decimal)],
+    ) -> Option<Decimal> {
+        if !order.reduce_only {
+            return None;
+        }
+        let position = position_source.map_or(Decimal::ZERO, |source| source.position(order.user_id, symbol));
+        let filled: Decimal = fills
+            .iter()
+            .filter(|(filled, _, _)| filled.user_id == order.user_id && filled.side == order.side)
+            .map(|(_, _, quantity)| *quantity)
+            .sum();
+        Some(reducible_quantity(position, &order.side) - filled)
+    }
+
+    /// Apply a fill of `quantity` to a resting order
+    fn fill_resting(order: &mut Order, quantity: Decimal) {
+        order.remaining_quantity -= quantity;
+        order.filled_quantity += quantity;
+        order.status = if order.remaining_quantity <= Decimal::ZERO {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+    }
+
+    /// Add order to the order book
+    fn add_to_order_book(&mut self, order: Order) -> FlowExResult<()> {
+        let price = order.price.ok_or_else(|| {
+            FlowExError::Trading("Order must have a price to be added to order book".to_string())
+        })?;
+
+        self.touched_levels.push((order.side.clone(), price));
+        self.track_order(&order);
+        self.book.push_back(order);
+
+        debug!("Added order to order book at price: {}", price);
+        Ok(())
+    }
+
+    /// Create a trade from two matching orders.
+    ///
+    /// The trade's id is derived from the symbol, the command sequence and
+    /// the trade sequence, and its time is the command's time, so replaying
+    /// the same commands at their journaled times yields identical trades.
+    fn create_trade(&mut self, taker_order: &Order, maker_order: &Order, price: Decimal, quantity: Decimal) -> FlowExResult<Trade> {
+        self.last_trade_price = Some(price);
+        self.total_volume += quantity;
+        self.trade_sequence += 1;
+
+        let (maker_fee, taker_fee) = match &self.fee_schedule {
+            Some(schedule) => (
+                Some(schedule.fee(maker_order, Liquidity::Maker, price, quantity)),
+                Some(schedule.fee(taker_order, Liquidity::Taker, price, quantity)),
+            ),
+            None => (None, None),
+        };
+
+        let trade = Trade {
+            id: self.trade_id(),
+            symbol: self.symbol.clone(),
+            sequence: self.trade_sequence,
+            price,
+            quantity,
+            side: taker_order.side.clone(),
+            maker_order_id: maker_order.id,
+            taker_order_id: taker_order.id,
+            maker_user_id: maker_order.user_id,
+            taker_user_id: taker_order.user_id,
+            is_buyer_maker: taker_order.side == OrderSide::Sell,
+            maker_fee: maker_fee.as_ref().map_or(Decimal::ZERO, |fee| fee.amount),
+            maker_fee_currency: maker_fee.map(|fee| fee.currency),
+            taker_fee: taker_fee.as_ref().map_or(Decimal::ZERO, |fee| fee.amount),
+            taker_fee_currency: taker_fee.map(|fee| fee.currency),
+            timestamp: self.command_now(),
+        };
+
+        self.stats.record(price, quantity, trade.timestamp);
+
+        info!("Trade executed: {} {} at {} for {}", 
+              self.symbol, quantity, price, trade.id);
+
+        Ok(trade)
+    }
+
+    /// Validate an order before processing
+    fn validate_order(&self, order: &Order) -> FlowExResult<()> {
+        if order.quantity <= Decimal::ZERO {
+            return Err(FlowExError::Validation("Order quantity must be positive".to_string()));
+        }
+
+        if order.trading_pair != self.symbol {
+            return Err(FlowExError::Validation("Order symbol does not match engine".to_string()));
+        }
+
+        if order.post_only {
+            if order.order_type == OrderType::Market {
+                return Err(FlowExError::Validation("Market orders cannot be post-only".to_string()));
+            }
+            if !order.time_in_force.is_resting() {
+                return Err(FlowExError::Validation(
+                    "Post-only orders must be good-till-cancelled or good-till-date".to_string(),
+                ));
+            }
+        }
+
+        if let Some(display_quantity) = order.display_quantity {
+            if display_quantity <= Decimal::ZERO || display_quantity > order.quantity {
+                return Err(FlowExError::Validation(
+                    "Iceberg display quantity must be positive and not exceed the order quantity".to_string(),
+                ));
+            }
+            if order.order_type != OrderType::Limit || !order.time_in_force.is_resting() {
+                return Err(FlowExError::Validation(
+                    "Iceberg orders must be good-till-cancelled or good-till-date limit orders".to_string(),
+                ));
+            }
+        }
+
+        match (order.time_in_force, order.expires_at) {
+            (TimeInForce::Gtd, Some(expires_at)) if expires_at <= order.created_at => {
+                return Err(FlowExError::Validation("Order expiry must be in the future".to_string()));
+            }
+            (TimeInForce::Gtd, None) => {
+                return Err(FlowExError::Validation("Good-till-date orders must set an expiry".to_string()));
+            }
+            (time_in_force, Some(_)) if time_in_force != TimeInForce::Gtd => {
+                return Err(FlowExError::Validation(
+                    "Only good-till-date orders can set an expiry".to_string(),
+                ));
+            }
+            _ => {}
+        }
+
+        match order.order_type {
+            OrderType::Limit => {
+                if order.price.is_none() || order.price.unwrap() <= Decimal::ZERO {
+                    return Err(FlowExError::Validation("Limit order must have a positive price".to_string()));
+                }
+            }
+            OrderType::Market => {
+                // Market orders don't need price validation
+            }
+            OrderType::StopLoss | OrderType::TakeProfit => {
+                if order.stop_price.is_none() || order.stop_price.unwrap() <= Decimal::ZERO {
+                    return Err(FlowExError::Validation("Stop/Take profit order must have a positive stop price".to_string()));
+                }
+                // Without a limit price the order executes as a market order once triggered
+                if order.price.is_some_and(|price| price <= Decimal::ZERO) {
+                    return Err(FlowExError::Validation("Stop/Take profit limit price must be positive".to_string()));
+                }
+            }
+        }
+
+        if let Some(trading_pair) = &self.trading_pair {
+            trading_pair.check_order(order.price, order.quantity)?;
+            if let Some(stop_price) = order.stop_price {
+                trading_pair.check_price(stop_price)?;
+            }
+        }
+
+        if let Some(scale) = self.book.scale() {
+            fixed::check_representable(scale, order)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use flowex_types::OrderConstraintViolation;
     use std::sync::Once;
 
     static INIT: Once = Once::new();
@@ -395,9 +1540,16 @@ mod tests {
             side,
             order_type,
             price,
+            stop_price: None,
             quantity,
             filled_quantity: Decimal::ZERO,
             remaining_quantity: quantity,
+            time_in_force: TimeInForce::Gtc,
+            post_only: false,
+            display_quantity: None,
+            expires_at: None,
+            reduce_only: false,
+            client_order_id: None,
             status: OrderStatus::New,
             created_at: Utc::now(),
             updated_at: Utc::now(),
@@ -411,8 +1563,7 @@ mod tests {
 
         let engine = MatchingEngine::new("BTCUSDT".to_string());
         assert_eq!(engine.symbol, "BTCUSDT");
-        assert!(engine.buy_orders.is_empty());
-        assert!(engine.sell_orders.is_empty());
+        assert_eq!(engine.book.len(), 0);
         assert_eq!(engine.last_trade_price, None);
         assert_eq!(engine.total_volume, Decimal::ZERO);
     }
@@ -455,311 +1606,1319 @@ mod tests {
             OrderSide::Buy,
             OrderType::Limit,
             Some(Decimal::new(50000, 0)),
-            Decimal::ZERO,
+            Decimal::ZERO,
+        );
+        assert!(engine.validate_order(&zero_quantity_order).is_err());
+
+        // 测试交易对不匹配
+        let mut wrong_symbol_order = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        wrong_symbol_order.trading_pair = "ETHUSDT".to_string();
+        assert!(engine.validate_order(&wrong_symbol_order).is_err());
+
+        // 测试限价单没有价格
+        let no_price_order = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            None,
+            Decimal::new(1, 0),
+        );
+        assert!(engine.validate_order(&no_price_order).is_err());
+
+        // 测试限价单价格为零
+        let zero_price_order = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::ZERO),
+            Decimal::new(1, 0),
+        );
+        assert!(engine.validate_order(&zero_price_order).is_err());
+    }
+
+    /// 测试：交易对价格/数量约束（价格区间、最小变动价位、数量步长、最小名义价值）
+    #[test]
+    fn test_trading_pair_constraints() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::with_trading_pair(TradingPair {
+            symbol: "BTCUSDT".to_string(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            status: flowex_types::TradingStatus::Trading,
+            min_price: Decimal::new(1000, 0),
+            max_price: Decimal::new(100000, 0),
+            min_qty: Decimal::new(1, 3), // 0.001
+            max_qty: Decimal::new(100, 0),
+            step_size: Decimal::new(1, 3),
+            tick_size: Decimal::new(5, 1), // 0.5
+            min_notional: Decimal::new(10, 0),
+        });
+
+        let valid = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(500005, 1)), Decimal::new(15, 3));
+        assert!(engine.add_order(valid).is_ok());
+
+        let off_tick = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(500001, 1)), Decimal::ONE);
+        assert!(matches!(
+            engine.add_order(off_tick),
+            Err(FlowExError::OrderConstraint(OrderConstraintViolation::InvalidTickSize { .. }))
+        ));
+
+        let too_cheap = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(500, 0)), Decimal::ONE);
+        assert!(matches!(
+            engine.add_order(too_cheap),
+            Err(FlowExError::OrderConstraint(OrderConstraintViolation::PriceOutOfRange { .. }))
+        ));
+
+        let off_step = create_test_order(OrderSide::Sell, OrderType::Market, None, Decimal::new(15, 4));
+        assert!(matches!(
+            engine.add_order(off_step),
+            Err(FlowExError::OrderConstraint(OrderConstraintViolation::InvalidStepSize { .. }))
+        ));
+
+        let too_large = create_test_order(OrderSide::Sell, OrderType::Market, None, Decimal::new(101, 0));
+        assert!(matches!(
+            engine.add_order(too_large),
+            Err(FlowExError::OrderConstraint(OrderConstraintViolation::QuantityOutOfRange { .. }))
+        ));
+
+        let dust = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(5000, 0)), Decimal::new(1, 3));
+        assert!(matches!(
+            engine.add_order(dust),
+            Err(FlowExError::OrderConstraint(OrderConstraintViolation::BelowMinNotional { .. }))
+        ));
+
+        let mut off_tick_stop = create_test_order(OrderSide::Sell, OrderType::StopLoss, None, Decimal::ONE);
+        off_tick_stop.stop_price = Some(Decimal::new(450002, 1));
+        assert!(engine.add_order(off_tick_stop).is_err());
+        assert_eq!(engine.pending_trigger_count(), 0);
+    }
+
+    /// 测试：限价单匹配 - 完全成交
+    #[test]
+    fn test_limit_order_full_match() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        // 添加卖单到订单簿
+        let sell_order = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        let trades = engine.add_order(sell_order).unwrap().trades;
+        assert!(trades.is_empty()); // 没有匹配，应该加入订单簿
+
+        // 添加匹配的买单
+        let buy_order = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        let trades = engine.add_order(buy_order).unwrap().trades;
+
+        // 验证交易生成
+        assert_eq!(trades.len(), 1);
+        let trade = &trades[0];
+        assert_eq!(trade.symbol, "BTCUSDT");
+        assert_eq!(trade.price, Decimal::new(50000, 0));
+        assert_eq!(trade.quantity, Decimal::new(1, 0));
+        assert_eq!(trade.side, OrderSide::Buy);
+
+        // 验证订单簿为空（订单完全成交）
+        let order_book = engine.get_order_book(10);
+        assert!(order_book.bids.is_empty());
+        assert!(order_book.asks.is_empty());
+    }
+
+    /// 测试：限价单匹配 - 部分成交
+    #[test]
+    fn test_limit_order_partial_match() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        // 添加大额卖单
+        let sell_order = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(2, 0),
+        );
+        engine.add_order(sell_order).unwrap();
+
+        // 添加小额买单
+        let buy_order = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        let trades = engine.add_order(buy_order).unwrap().trades;
+
+        // 验证交易生成
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, Decimal::new(1, 0));
+
+        // 验证订单簿中还有剩余卖单
+        let order_book = engine.get_order_book(10);
+        assert!(order_book.bids.is_empty());
+        assert_eq!(order_book.asks.len(), 1);
+        assert_eq!(order_book.asks[0].quantity, Decimal::new(1, 0));
+    }
+
+    /// 测试：市价单匹配
+    #[test]
+    fn test_market_order_execution() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        // 添加多个限价卖单
+        let sell_order1 = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        engine.add_order(sell_order1).unwrap();
+
+        let sell_order2 = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50100, 0)),
+            Decimal::new(1, 0),
+        );
+        engine.add_order(sell_order2).unwrap();
+
+        // 添加市价买单
+        let market_buy_order = create_test_order(
+            OrderSide::Buy,
+            OrderType::Market,
+            None,
+            Decimal::new(15, 1), // 1.5
+        );
+        let trades = engine.add_order(market_buy_order).unwrap().trades;
+
+        // 验证交易执行
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, Decimal::new(50000, 0));
+        assert_eq!(trades[0].quantity, Decimal::new(1, 0));
+        assert_eq!(trades[1].price, Decimal::new(50100, 0));
+        assert_eq!(trades[1].quantity, Decimal::new(5, 1)); // 0.5
+    }
+
+    /// 测试：订单簿深度获取
+    #[test]
+    fn test_order_book_depth() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        // 添加多个买单和卖单
+        for i in 1..=5 {
+            let buy_price = Decimal::new(50000 - i * 100, 0);
+            let sell_price = Decimal::new(50000 + i * 100, 0);
+
+            let buy_order = create_test_order(
+                OrderSide::Buy,
+                OrderType::Limit,
+                Some(buy_price),
+                Decimal::new(1, 0),
+            );
+            engine.add_order(buy_order).unwrap();
+
+            let sell_order = create_test_order(
+                OrderSide::Sell,
+                OrderType::Limit,
+                Some(sell_price),
+                Decimal::new(1, 0),
+            );
+            engine.add_order(sell_order).unwrap();
+        }
+
+        // 获取订单簿深度
+        let order_book = engine.get_order_book(3);
+
+        // 验证买单按价格降序排列
+        assert_eq!(order_book.bids.len(), 3);
+        assert!(order_book.bids[0].price > order_book.bids[1].price);
+        assert!(order_book.bids[1].price > order_book.bids[2].price);
+
+        // 验证卖单按价格升序排列
+        assert_eq!(order_book.asks.len(), 3);
+        assert!(order_book.asks[0].price < order_book.asks[1].price);
+        assert!(order_book.asks[1].price < order_book.asks[2].price);
+    }
+
+    /// 测试：最佳买卖价获取
+    #[test]
+    fn test_best_bid_ask() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        // 初始状态应该没有最佳价格
+        assert_eq!(engine.get_best_bid(), None);
+        assert_eq!(engine.get_best_ask(), None);
+        assert_eq!(engine.get_spread(), None);
+
+        // 添加买单和卖单
+        let buy_order = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(49900, 0)),
+            Decimal::new(1, 0),
+        );
+        engine.add_order(buy_order).unwrap();
+
+        let sell_order = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50100, 0)),
+            Decimal::new(1, 0),
+        );
+        engine.add_order(sell_order).unwrap();
+
+        // 验证最佳价格
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(49900, 0)));
+        assert_eq!(engine.get_best_ask(), Some(Decimal::new(50100, 0)));
+        assert_eq!(engine.get_spread(), Some(Decimal::new(200, 0)));
+    }
+
+    /// 测试：订单取消
+    #[test]
+    fn test_order_cancellation() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        // 添加订单
+        let order = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        let order_id = order.id;
+        engine.add_order(order).unwrap();
+
+        // 验证订单在订单簿中
+        let order_book = engine.get_order_book(10);
+        assert_eq!(order_book.bids.len(), 1);
+
+        // 取消订单
+        let cancelled = engine.cancel_order(order_id).unwrap();
+        assert!(cancelled);
+
+        // 验证订单已从订单簿中移除
+        let order_book = engine.get_order_book(10);
+        assert!(order_book.bids.is_empty());
+
+        // 尝试取消不存在的订单
+        let not_cancelled = engine.cancel_order(Uuid::new_v4()).unwrap();
+        assert!(!not_cancelled);
+    }
+
+    /// 测试：价格时间优先原则
+    #[test]
+    fn test_price_time_priority() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        // 添加相同价格的多个卖单（时间优先）
+        let sell_order1 = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        let order1_id = sell_order1.id;
+        engine.add_order(sell_order1).unwrap();
+
+        // 稍等一下确保时间不同
+        std::thread::sleep(std::time::Duration::from_millis(1));
+
+        let sell_order2 = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        engine.add_order(sell_order2).unwrap();
+
+        // 添加买单，应该匹配第一个卖单
+        let buy_order = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        let trades = engine.add_order(buy_order).unwrap().trades;
+
+        // 验证交易生成且匹配了第一个订单
+        assert_eq!(trades.len(), 1);
+
+        // 验证第二个订单仍在订单簿中
+        let order_book = engine.get_order_book(10);
+        assert_eq!(order_book.asks.len(), 1);
+    }
+
+    /// 测试：按价格分组聚合深度，买单向下取整、卖单向上取整
+    #[test]
+    fn test_grouped_order_book() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        for (side, price) in [
+            (OrderSide::Buy, 49995),
+            (OrderSide::Buy, 49991),
+            (OrderSide::Buy, 49989),
+            (OrderSide::Buy, 49970),
+            (OrderSide::Sell, 50001),
+            (OrderSide::Sell, 50010),
+            (OrderSide::Sell, 50011),
+        ] {
+            engine.add_order(create_test_order(side, OrderType::Limit, Some(Decimal::new(price, 0)), Decimal::ONE)).unwrap();
+        }
+
+        let book = engine.get_order_book_grouped(2, Decimal::new(10, 0)).unwrap();
+        let levels = |levels: &[OrderBookLevel]| levels.iter().map(|l| (l.price, l.quantity)).collect::<Vec<_>>();
+        assert_eq!(
+            levels(&book.bids),
+            vec![(Decimal::new(49990, 0), Decimal::new(2, 0)), (Decimal::new(49980, 0), Decimal::ONE)]
+        );
+        assert_eq!(
+            levels(&book.asks),
+            vec![(Decimal::new(50010, 0), Decimal::new(2, 0)), (Decimal::new(50020, 0), Decimal::ONE)]
+        );
+
+        // 分组粒度小于价格精度时与原始深度一致
+        let raw = engine.get_order_book(10);
+        let fine = engine.get_order_book_grouped(10, Decimal::new(1, 2)).unwrap();
+        assert_eq!(levels(&fine.bids), levels(&raw.bids));
+        assert_eq!(levels(&fine.asks), levels(&raw.asks));
+
+        assert!(engine.get_order_book_grouped(10, Decimal::ZERO).is_err());
+    }
+
+    /// 测试：L3订单簿按优先级列出单个订单，并可按用户过滤
+    #[test]
+    fn test_order_book_l3() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let maker = Uuid::new_v4();
+
+        let mut first = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(49000, 0)), Decimal::new(5, 0));
+        first.user_id = maker;
+        first.display_quantity = Some(Decimal::ONE);
+        let first_id = first.id;
+        let second = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(49000, 0)), Decimal::ONE);
+        let second_id = second.id;
+        let mut lower = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(48000, 0)), Decimal::ONE);
+        lower.user_id = maker;
+        let lower_id = lower.id;
+        for order in [first, second, lower] {
+            engine.add_order(order).unwrap();
+        }
+        engine.add_order(create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(51000, 0)), Decimal::ONE)).unwrap();
+
+        let book = engine.get_order_book_l3(10, None);
+        assert_eq!(book.sequence, 4);
+        assert_eq!(book.bids.iter().map(|e| e.order_id).collect::<Vec<_>>(), vec![first_id, second_id, lower_id]);
+        assert_eq!(book.bids[0].quantity, Decimal::new(5, 0));
+        assert_eq!(book.bids[0].visible_quantity, Decimal::ONE);
+        assert_eq!(book.asks.len(), 1);
+
+        // 深度按价格档位计算
+        assert_eq!(engine.get_order_book_l3(1, None).bids.len(), 2);
+
+        let own = engine.get_order_book_l3(10, Some(maker));
+        assert_eq!(own.bids.iter().map(|e| e.order_id).collect::<Vec<_>>(), vec![first_id, lower_id]);
+        assert!(own.asks.is_empty());
+        assert_eq!(engine.get_order_book_l3(1, Some(maker)).bids.len(), 1);
+    }
+
+    /// 测试：按用户跟踪挂单并一键撤销
+    #[test]
+    fn test_user_open_orders_and_cancel_all() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let user = Uuid::new_v4();
+        let mut placed = Vec::new();
+        for (side, order_type, price, stop_price) in [
+            (OrderSide::Buy, OrderType::Limit, 49000, None),
+            (OrderSide::Sell, OrderType::Limit, 51000, None),
+            (OrderSide::Sell, OrderType::StopLoss, 45000, Some(46000)),
+            (OrderSide::Sell, OrderType::Limit, 52000, None),
+        ] {
+            let mut order = create_test_order(side, order_type, Some(Decimal::new(price, 0)), Decimal::ONE);
+            order.user_id = user;
+            order.stop_price = stop_price.map(|p| Decimal::new(p, 0));
+            placed.push(order.id);
+            engine.add_order(order).unwrap();
+        }
+        engine.add_order(create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(48000, 0)), Decimal::ONE)).unwrap();
+
+        let ids = |orders: Vec<Order>| orders.into_iter().map(|o| o.id).collect::<Vec<_>>();
+        assert_eq!(ids(engine.open_orders_for_user(user)), placed);
+
+        // 完全成交的订单不再是挂单
+        engine.add_order(create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(51000, 0)), Decimal::ONE)).unwrap();
+        assert_eq!(ids(engine.open_orders_for_user(user)), vec![placed[0], placed[2], placed[3]]);
+
+        // 快照恢复后索引保持一致
+        let restored = MatchingEngine::restore(engine.snapshot()).unwrap();
+        let mut restored_ids = ids(restored.open_orders_for_user(user));
+        restored_ids.sort();
+        let mut expected_ids = vec![placed[0], placed[2], placed[3]];
+        expected_ids.sort();
+        assert_eq!(restored_ids, expected_ids);
+
+        engine.drain_events();
+        let cancelled = engine.cancel_all_for_user(user);
+        assert_eq!(ids(cancelled.clone()), vec![placed[0], placed[2], placed[3]]);
+        assert!(cancelled.iter().all(|o| o.status == OrderStatus::Cancelled));
+        assert_eq!(engine.drain_events().len(), 3);
+        assert!(engine.open_orders_for_user(user).is_empty());
+        assert_eq!(engine.pending_trigger_count(), 0);
+        assert_eq!(engine.get_order_book(10).bids.len(), 1);
+        assert!(engine.cancel_all_for_user(user).is_empty());
+    }
+
+    /// 测试：按方向、价格区间批量撤单
+    #[test]
+    fn test_mass_cancel_with_filters() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        for (side, price) in [
+            (OrderSide::Buy, 48000),
+            (OrderSide::Buy, 49000),
+            (OrderSide::Sell, 51000),
+            (OrderSide::Sell, 52000),
+        ] {
+            engine.add_order(create_test_order(side, OrderType::Limit, Some(Decimal::new(price, 0)), Decimal::ONE)).unwrap();
+        }
+        let mut stop = create_test_order(OrderSide::Sell, OrderType::StopLoss, None, Decimal::ONE);
+        stop.stop_price = Some(Decimal::new(47000, 0));
+        engine.add_order(stop).unwrap();
+
+        let prices = |orders: &[Order]| orders.iter().map(|o| o.price.or(o.stop_price).unwrap()).collect::<Vec<_>>();
+
+        // 止损市价单按触发价匹配价格区间
+        let cancelled = engine.cancel_all(&CancelOrdersFilter {
+            side: Some(OrderSide::Sell),
+            max_price: Some(Decimal::new(51000, 0)),
+            ..Default::default()
+        });
+        assert_eq!(prices(&cancelled), vec![Decimal::new(51000, 0), Decimal::new(47000, 0)]);
+
+        let cancelled = engine.cancel_all(&CancelOrdersFilter {
+            min_price: Some(Decimal::new(48500, 0)),
+            ..Default::default()
+        });
+        assert_eq!(prices(&cancelled), vec![Decimal::new(49000, 0), Decimal::new(52000, 0)]);
+
+        assert_eq!(engine.cancel_all(&CancelOrdersFilter::default()).len(), 1);
+        assert!(engine.get_order_book(10).bids.is_empty());
+        assert_eq!(engine.sequence(), 8);
+    }
+
+    /// 测试：引擎根据成交生成滚动行情
+    #[test]
+    fn test_engine_ticker() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        assert!(engine.get_ticker().is_none());
+
+        engine.add_order(create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(50000, 0)), Decimal::ONE)).unwrap();
+        engine.add_order(create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(51000, 0)), Decimal::ONE)).unwrap();
+        engine.add_order(create_test_order(OrderSide::Buy, OrderType::Market, None, Decimal::new(2, 0))).unwrap();
+
+        let ticker = engine.get_ticker().unwrap();
+        assert_eq!(ticker.symbol, "BTCUSDT");
+        assert_eq!(ticker.price, Decimal::new(51000, 0));
+        assert_eq!(ticker.high, Decimal::new(51000, 0));
+        assert_eq!(ticker.low, Decimal::new(50000, 0));
+        assert_eq!(ticker.change, Decimal::new(1000, 0));
+        assert_eq!(ticker.volume, Decimal::new(2, 0));
+        assert_eq!(ticker.vwap, Some(Decimal::new(50500, 0)));
+    }
+
+    /// 测试：每条命令后产生增量深度变化
+    #[test]
+    fn test_book_update_deltas() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        assert!(engine.drain_book_update().is_none());
+
+        let ask = create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(50000, 0)), Decimal::new(2, 0));
+        let ask_id = ask.id;
+        engine.add_order(ask).unwrap();
+        engine.add_order(create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(50100, 0)), Decimal::ONE)).unwrap();
+        let update = engine.drain_book_update().unwrap();
+        assert_eq!(update.sequence, 2);
+        assert_eq!(update.previous_sequence, 0);
+        assert_eq!(
+            update.deltas,
+            vec![
+                BookDelta { side: OrderSide::Sell, price: Decimal::new(50000, 0), quantity: Decimal::new(2, 0) },
+                BookDelta { side: OrderSide::Sell, price: Decimal::new(50100, 0), quantity: Decimal::ONE },
+            ]
+        );
+
+        // 部分成交后剩余部分挂在买方
+        engine.add_order(create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(50000, 0)), Decimal::new(3, 0))).unwrap();
+        let update = engine.drain_book_update().unwrap();
+        assert_eq!(update.previous_sequence, 2);
+        assert_eq!(
+            update.deltas,
+            vec![
+                BookDelta { side: OrderSide::Sell, price: Decimal::new(50000, 0), quantity: Decimal::ZERO },
+                BookDelta { side: OrderSide::Buy, price: Decimal::new(50000, 0), quantity: Decimal::ONE },
+            ]
+        );
+
+        // 拒绝的命令不改变订单簿
+        assert!(!engine.cancel_order(ask_id).unwrap());
+        assert!(engine.drain_book_update().is_none());
+    }
+
+    /// 测试：原地减量改单保持队列位置，同时产生该价位的深度变化
+    #[test]
+    fn test_reduce_in_place_emits_book_update() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let ask = create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(50000, 0)), Decimal::new(3, 0));
+        let ask_id = ask.id;
+        engine.add_order(ask).unwrap();
+        engine.add_order(create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(50000, 0)), Decimal::ONE)).unwrap();
+        engine.drain_book_update().unwrap();
+
+        assert!(engine.modify_order(ask_id, None, Some(Decimal::ONE)).unwrap().is_empty());
+        let update = engine.drain_book_update().unwrap();
+        assert_eq!(update.sequence, 3);
+        assert_eq!(update.previous_sequence, 2);
+        assert_eq!(
+            update.deltas,
+            vec![BookDelta { side: OrderSide::Sell, price: Decimal::new(50000, 0), quantity: Decimal::new(2, 0) }]
+        );
+        assert_eq!(engine.get_order_book(1).asks[0].quantity, Decimal::new(2, 0));
+        assert_eq!(engine.book.level(&OrderSide::Sell, Decimal::new(50000, 0)).next().unwrap().id, ask_id);
+    }
+
+    /// 测试：成交序号逐笔递增，订单簿快照携带引擎序号
+    #[test]
+    fn test_trade_and_book_sequences() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine.add_order(create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(50000, 0)), Decimal::ONE)).unwrap();
+        engine.add_order(create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(50100, 0)), Decimal::ONE)).unwrap();
+        assert_eq!(engine.trade_sequence(), 0);
+        assert_eq!(engine.get_order_book(10).sequence, 2);
+
+        let mut trades = engine.add_order(create_test_order(OrderSide::Buy, OrderType::Market, None, Decimal::new(2, 0))).unwrap().trades;
+        trades.extend(engine.add_order(create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(49000, 0)), Decimal::ONE)).unwrap().trades);
+        trades.extend(engine.add_order(create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(49000, 0)), Decimal::ONE)).unwrap().trades);
+
+        let sequences: Vec<u64> = trades.iter().map(|t| t.sequence).collect();
+        assert_eq!(sequences, vec![1, 2, 3]);
+        assert!(trades.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        assert_eq!(engine.trade_sequence(), 3);
+
+        let book = engine.get_order_book(10);
+        assert_eq!(book.sequence, 5);
+        assert!(book.timestamp >= trades[2].timestamp);
+    }
+
+    /// 测试：GTD订单到期后被清理并产生过期事件
+    #[test]
+    fn test_gtd_orders_expire() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let now = Utc::now();
+
+        let mut gtd_bid = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(49000, 0)), Decimal::ONE);
+        gtd_bid.time_in_force = TimeInForce::Gtd;
+        gtd_bid.expires_at = Some(now + chrono::Duration::minutes(5));
+        let gtd_bid_id = gtd_bid.id;
+        engine.add_order(gtd_bid).unwrap();
+
+        let gtc_bid = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(49000, 0)), Decimal::ONE);
+        engine.add_order(gtc_bid).unwrap();
+
+        // 到期时间校验
+        let mut missing_expiry = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(49000, 0)), Decimal::ONE);
+        missing_expiry.time_in_force = TimeInForce::Gtd;
+        assert!(engine.add_order(missing_expiry).is_err());
+
+        let mut gtc_with_expiry = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(49000, 0)), Decimal::ONE);
+        gtc_with_expiry.expires_at = Some(now + chrono::Duration::minutes(5));
+        assert!(engine.add_order(gtc_with_expiry).is_err());
+        engine.drain_events();
+
+        assert!(engine.expire_orders(now).is_empty());
+
+        let expired = engine.expire_orders(now + chrono::Duration::minutes(5));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, gtd_bid_id);
+        assert_eq!(expired[0].status, OrderStatus::Expired);
+        assert_eq!(engine.get_order_book(10).bids[0].quantity, Decimal::ONE);
+
+        let events = engine.drain_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, OrderEventKind::Expired);
+        assert!(!engine.cancel_order(gtd_bid_id).unwrap());
+    }
+
+    /// 测试：IOC订单 - 部分成交后剩余部分撤销，不挂单
+    #[test]
+    fn test_ioc_order_cancels_remainder() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        let sell_order = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        engine.add_order(sell_order).unwrap();
+
+        let mut ioc_buy = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(3, 0),
+        );
+        ioc_buy.time_in_force = TimeInForce::Ioc;
+        let trades = engine.add_order(ioc_buy).unwrap().trades;
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, Decimal::new(1, 0));
+
+        // 剩余部分不应进入订单簿
+        let order_book = engine.get_order_book(10);
+        assert!(order_book.bids.is_empty());
+        assert!(order_book.asks.is_empty());
+
+        // 无对手盘的IOC订单直接撤销
+        let mut unmatched_ioc = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(51000, 0)),
+            Decimal::new(1, 0),
+        );
+        unmatched_ioc.time_in_force = TimeInForce::Ioc;
+        assert!(engine.add_order(unmatched_ioc).unwrap().trades.is_empty());
+        assert!(engine.get_order_book(10).asks.is_empty());
+    }
+
+    /// 测试：FOK订单 - 流动性不足时整体拒绝且不改变订单簿
+    #[test]
+    fn test_fok_order_all_or_nothing() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        for (price, quantity) in [(50000, 1), (50100, 1), (50500, 5)] {
+            let sell_order = create_test_order(
+                OrderSide::Sell,
+                OrderType::Limit,
+                Some(Decimal::new(price, 0)),
+                Decimal::new(quantity, 0),
+            );
+            engine.add_order(sell_order).unwrap();
+        }
+
+        // 限价内只有2个可成交，FOK买入3个应被拒绝
+        let mut fok_buy = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(50100, 0)),
+            Decimal::new(3, 0),
+        );
+        fok_buy.time_in_force = TimeInForce::Fok;
+        assert!(engine.add_order(fok_buy).is_err());
+
+        let order_book = engine.get_order_book(10);
+        assert_eq!(order_book.asks.len(), 3);
+        assert!(order_book.bids.is_empty());
+
+        // 限价内流动性充足时全部成交
+        let mut fok_buy = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(50100, 0)),
+            Decimal::new(2, 0),
+        );
+        fok_buy.time_in_force = TimeInForce::Fok;
+        let trades = engine.add_order(fok_buy).unwrap().trades;
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!(engine.get_best_ask(), Some(Decimal::new(50500, 0)));
+    }
+
+    /// 测试：只做Maker订单 - 会立即成交时拒绝，否则挂单
+    #[test]
+    fn test_post_only_order() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        let sell_order = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        engine.add_order(sell_order).unwrap();
+
+        // 会吃单的只做Maker买单被拒绝，卖单保持不变
+        let mut crossing = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        crossing.post_only = true;
+        assert!(engine.add_order(crossing).is_err());
+        assert_eq!(engine.get_order_book(10).asks.len(), 1);
+
+        // 不会吃单的只做Maker买单正常挂单
+        let mut resting = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(49900, 0)),
+            Decimal::new(1, 0),
+        );
+        resting.post_only = true;
+        assert!(engine.add_order(resting).unwrap().trades.is_empty());
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(49900, 0)));
+
+        // 只做Maker与IOC冲突
+        let mut ioc = create_test_order(
+            OrderSide::Buy,
+            OrderType::Limit,
+            Some(Decimal::new(49000, 0)),
+            Decimal::new(1, 0),
+        );
+        ioc.post_only = true;
+        ioc.time_in_force = TimeInForce::Ioc;
+        assert!(engine.validate_order(&ioc).is_err());
+    }
+
+    /// 测试：止损单 - 成交价触及触发价后转为市价单执行
+    #[test]
+    fn test_stop_loss_triggered_by_last_trade() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        // 买盘：49500 与 49000 各1个
+        for price in [49500, 49000] {
+            let buy_order = create_test_order(
+                OrderSide::Buy,
+                OrderType::Limit,
+                Some(Decimal::new(price, 0)),
+                Decimal::new(1, 0),
+            );
+            engine.add_order(buy_order).unwrap();
+        }
+
+        // 止损卖单：价格跌至49500时触发
+        let mut stop_sell = create_test_order(OrderSide::Sell, OrderType::StopLoss, None, Decimal::new(1, 0));
+        stop_sell.stop_price = Some(Decimal::new(49500, 0));
+        assert!(engine.add_order(stop_sell).unwrap().trades.is_empty());
+        assert_eq!(engine.pending_trigger_count(), 1);
+        assert!(engine.get_order_book(10).asks.is_empty());
+
+        // 一笔49500的成交触发止损单，止损单继续吃掉49000的买单
+        let sell_order = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(49500, 0)),
+            Decimal::new(1, 0),
+        );
+        let trades = engine.add_order(sell_order).unwrap().trades;
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, Decimal::new(49500, 0));
+        assert_eq!(trades[1].price, Decimal::new(49000, 0));
+        assert_eq!(engine.pending_trigger_count(), 0);
+        assert!(engine.get_order_book(10).bids.is_empty());
+    }
+
+    /// 测试：止盈单取消与触发价校验
+    #[test]
+    fn test_take_profit_cancel_and_validation() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        // 缺少触发价
+        let no_stop = create_test_order(
+            OrderSide::Sell,
+            OrderType::TakeProfit,
+            Some(Decimal::new(55000, 0)),
+            Decimal::new(1, 0),
+        );
+        assert!(engine.add_order(no_stop).is_err());
+
+        let mut take_profit = create_test_order(
+            OrderSide::Sell,
+            OrderType::TakeProfit,
+            Some(Decimal::new(55000, 0)),
+            Decimal::new(1, 0),
+        );
+        take_profit.stop_price = Some(Decimal::new(55000, 0));
+        let order_id = take_profit.id;
+        engine.add_order(take_profit).unwrap();
+        assert_eq!(engine.pending_trigger_count(), 1);
+
+        assert!(engine.cancel_order(order_id).unwrap());
+        assert_eq!(engine.pending_trigger_count(), 0);
+    }
+
+    /// 测试：冰山订单 - 仅显示切片数量，切片成交后重新排队失去时间优先
+    #[test]
+    fn test_iceberg_order() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        let mut iceberg = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(5, 0),
         );
-        assert!(engine.validate_order(&zero_quantity_order).is_err());
+        iceberg.display_quantity = Some(Decimal::new(2, 0));
+        let iceberg_id = iceberg.id;
+        engine.add_order(iceberg).unwrap();
 
-        // 测试交易对不匹配
-        let mut wrong_symbol_order = create_test_order(
-            OrderSide::Buy,
+        let plain = create_test_order(
+            OrderSide::Sell,
             OrderType::Limit,
             Some(Decimal::new(50000, 0)),
             Decimal::new(1, 0),
         );
-        wrong_symbol_order.trading_pair = "ETHUSDT".to_string();
-        assert!(engine.validate_order(&wrong_symbol_order).is_err());
+        let plain_id = plain.id;
+        engine.add_order(plain).unwrap();
 
-        // 测试限价单没有价格
-        let no_price_order = create_test_order(
+        // 订单簿只显示可见切片（2）与普通订单（1）
+        let order_book = engine.get_order_book(10);
+        assert_eq!(order_book.asks[0].quantity, Decimal::new(3, 0));
+
+        // 吃掉冰山首个切片后，新切片排到普通订单之后
+        let buy_order = create_test_order(
             OrderSide::Buy,
             OrderType::Limit,
-            None,
-            Decimal::new(1, 0),
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(2, 0),
         );
-        assert!(engine.validate_order(&no_price_order).is_err());
+        engine.add_order(buy_order).unwrap();
 
-        // 测试限价单价格为零
-        let zero_price_order = create_test_order(
+        let queue: Vec<&Order> = engine.book.level(&OrderSide::Sell, Decimal::new(50000, 0)).collect();
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue[0].id, plain_id);
+        assert_eq!(queue[1].id, iceberg_id);
+        assert_eq!(queue[1].remaining_quantity, Decimal::new(3, 0));
+        assert_eq!(engine.get_order_book(10).asks[0].quantity, Decimal::new(3, 0));
+
+        // 隐藏数量仍可成交：普通订单1 + 冰山切片2 + 冰山切片1
+        let buy_order = create_test_order(
             OrderSide::Buy,
             OrderType::Limit,
-            Some(Decimal::ZERO),
-            Decimal::new(1, 0),
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(4, 0),
         );
-        assert!(engine.validate_order(&zero_price_order).is_err());
+        let trades = engine.add_order(buy_order).unwrap().trades;
+        assert_eq!(trades.len(), 3);
+        assert!(engine.get_order_book(10).asks.is_empty());
     }
 
-    /// 测试：限价单匹配 - 完全成交
+    /// 测试：改单 - 减量保持时间优先，加量或改价失去优先并可立即成交
     #[test]
-    fn test_limit_order_full_match() {
+    fn test_modify_order_priority() {
         init_test_env();
 
         let mut engine = MatchingEngine::new("BTCUSDT".to_string());
 
-        // 添加卖单到订单簿
-        let sell_order = create_test_order(
+        let first = create_test_order(
             OrderSide::Sell,
             OrderType::Limit,
             Some(Decimal::new(50000, 0)),
-            Decimal::new(1, 0),
+            Decimal::new(2, 0),
         );
-        let trades = engine.add_order(sell_order).unwrap();
-        assert!(trades.is_empty()); // 没有匹配，应该加入订单簿
+        let first_id = first.id;
+        engine.add_order(first).unwrap();
 
-        // 添加匹配的买单
+        let second = create_test_order(
+            OrderSide::Sell,
+            OrderType::Limit,
+            Some(Decimal::new(50000, 0)),
+            Decimal::new(1, 0),
+        );
+        let second_id = second.id;
+        engine.add_order(second).unwrap();
+
+        // 减量：保持队列位置
+        assert!(engine.modify_order(first_id, None, Some(Decimal::new(1, 0))).unwrap().is_empty());
+        let queue: Vec<&Order> = engine.book.level(&OrderSide::Sell, Decimal::new(50000, 0)).collect();
+        assert_eq!(queue[0].id, first_id);
+        assert_eq!(queue[0].remaining_quantity, Decimal::new(1, 0));
+
+        // 加量：排到队尾
+        engine.modify_order(first_id, None, Some(Decimal::new(3, 0))).unwrap();
+        let queue: Vec<&Order> = engine.book.level(&OrderSide::Sell, Decimal::new(50000, 0)).collect();
+        assert_eq!(queue[0].id, second_id);
+        assert_eq!(queue[1].id, first_id);
+
+        // 改价穿越买盘：立即成交
         let buy_order = create_test_order(
             OrderSide::Buy,
             OrderType::Limit,
-            Some(Decimal::new(50000, 0)),
+            Some(Decimal::new(49000, 0)),
             Decimal::new(1, 0),
         );
-        let trades = engine.add_order(buy_order).unwrap();
-
-        // 验证交易生成
+        engine.add_order(buy_order).unwrap();
+        let trades = engine.modify_order(second_id, Some(Decimal::new(49000, 0)), None).unwrap();
         assert_eq!(trades.len(), 1);
-        let trade = &trades[0];
-        assert_eq!(trade.symbol, "BTCUSDT");
-        assert_eq!(trade.price, Decimal::new(50000, 0));
-        assert_eq!(trade.quantity, Decimal::new(1, 0));
-        assert_eq!(trade.side, OrderSide::Buy);
+        assert_eq!(trades[0].price, Decimal::new(49000, 0));
 
-        // 验证订单簿为空（订单完全成交）
-        let order_book = engine.get_order_book(10);
-        assert!(order_book.bids.is_empty());
-        assert!(order_book.asks.is_empty());
+        // 不存在的订单
+        assert!(engine.modify_order(Uuid::new_v4(), None, Some(Decimal::ONE)).is_err());
     }
 
-    /// 测试：限价单匹配 - 部分成交
+    /// 测试：成交记录区分Maker与Taker
     #[test]
-    fn test_limit_order_partial_match() {
+    fn test_trade_maker_taker_attribution() {
         init_test_env();
 
         let mut engine = MatchingEngine::new("BTCUSDT".to_string());
 
-        // 添加大额卖单
-        let sell_order = create_test_order(
-            OrderSide::Sell,
-            OrderType::Limit,
-            Some(Decimal::new(50000, 0)),
-            Decimal::new(2, 0),
-        );
-        engine.add_order(sell_order).unwrap();
-
-        // 添加小额买单
-        let buy_order = create_test_order(
+        let maker = create_test_order(
             OrderSide::Buy,
             OrderType::Limit,
             Some(Decimal::new(50000, 0)),
             Decimal::new(1, 0),
         );
-        let trades = engine.add_order(buy_order).unwrap();
+        let (maker_id, maker_user) = (maker.id, maker.user_id);
+        engine.add_order(maker).unwrap();
 
-        // 验证交易生成
-        assert_eq!(trades.len(), 1);
-        assert_eq!(trades[0].quantity, Decimal::new(1, 0));
+        let taker = create_test_order(OrderSide::Sell, OrderType::Market, None, Decimal::new(1, 0));
+        let (taker_id, taker_user) = (taker.id, taker.user_id);
+        let trades = engine.add_order(taker).unwrap().trades;
 
-        // 验证订单簿中还有剩余卖单
-        let order_book = engine.get_order_book(10);
-        assert!(order_book.bids.is_empty());
-        assert_eq!(order_book.asks.len(), 1);
-        assert_eq!(order_book.asks[0].quantity, Decimal::new(1, 0));
+        assert_eq!(trades.len(), 1);
+        let trade = &trades[0];
+        assert_eq!(trade.maker_order_id, maker_id);
+        assert_eq!(trade.taker_order_id, taker_id);
+        assert_eq!(trade.maker_user_id, maker_user);
+        assert_eq!(trade.taker_user_id, taker_user);
+        assert!(trade.is_buyer_maker);
+        assert_eq!(trade.side, OrderSide::Sell);
     }
 
-    /// 测试：市价单匹配
+    /// 测试：订单事件 - 接受、成交、撤销与拒绝
     #[test]
-    fn test_market_order_execution() {
+    fn test_order_events() {
         init_test_env();
 
         let mut engine = MatchingEngine::new("BTCUSDT".to_string());
 
-        // 添加多个限价卖单
-        let sell_order1 = create_test_order(
+        let maker = create_test_order(
             OrderSide::Sell,
             OrderType::Limit,
             Some(Decimal::new(50000, 0)),
-            Decimal::new(1, 0),
+            Decimal::new(2, 0),
         );
-        engine.add_order(sell_order1).unwrap();
+        let maker_id = maker.id;
+        engine.add_order(maker).unwrap();
+
+        let taker = create_test_order(OrderSide::Buy, OrderType::Market, None, Decimal::new(1, 0));
+        let taker_id = taker.id;
+        let trades = engine.add_order(taker).unwrap().trades;
+
+        engine.cancel_order(maker_id).unwrap();
+
+        let invalid = create_test_order(OrderSide::Buy, OrderType::Limit, None, Decimal::new(1, 0));
+        assert!(engine.add_order(invalid).is_err());
+
+        let events = engine.drain_events();
+        let kinds: Vec<(OrderEventKind, Uuid)> = events.iter().map(|e| (e.kind, e.order.id)).collect();
+        assert_eq!(kinds[0], (OrderEventKind::Accepted, maker_id));
+        assert_eq!(kinds[1], (OrderEventKind::Accepted, taker_id));
+        assert_eq!(kinds[2], (OrderEventKind::PartiallyFilled, maker_id));
+        assert_eq!(kinds[3], (OrderEventKind::Filled, taker_id));
+        assert_eq!(kinds[4], (OrderEventKind::Cancelled, maker_id));
+        assert_eq!(kinds[5].0, OrderEventKind::Rejected);
+        assert_eq!(events[2].trade_id, Some(trades[0].id));
+        assert_eq!(events[2].order.remaining_quantity, Decimal::new(1, 0));
+        assert!(events[5].reason.is_some());
+
+        // 事件被取出后清空
+        assert!(engine.drain_events().is_empty());
+    }
 
-        let sell_order2 = create_test_order(
+    /// 测试：市价单剩余部分过期而不是报错或静默丢弃
+    #[test]
+    fn test_market_order_remainder_not_rested() {
+        init_test_env();
+
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+
+        let sell_order = create_test_order(
             OrderSide::Sell,
             OrderType::Limit,
-            Some(Decimal::new(50100, 0)),
+            Some(Decimal::new(50000, 0)),
             Decimal::new(1, 0),
         );
-        engine.add_order(sell_order2).unwrap();
-
-        // 添加市价买单
-        let market_buy_order = create_test_order(
-            OrderSide::Buy,
-            OrderType::Market,
-            None,
-            Decimal::new(15, 1), // 1.5
-        );
-        let trades = engine.add_order(market_buy_order).unwrap();
+        engine.add_order(sell_order).unwrap();
 
-        // 验证交易执行
-        assert_eq!(trades.len(), 2);
-        assert_eq!(trades[0].price, Decimal::new(50000, 0));
-        assert_eq!(trades[0].quantity, Decimal::new(1, 0));
-        assert_eq!(trades[1].price, Decimal::new(50100, 0));
-        assert_eq!(trades[1].quantity, Decimal::new(5, 1)); // 0.5
+        let market_buy = create_test_order(OrderSide::Buy, OrderType::Market, None, Decimal::new(2, 0));
+        let execution = engine.add_order(market_buy).unwrap();
+
+        assert_eq!(execution.trades.len(), 1);
+        assert!(engine.get_order_book(10).bids.is_empty());
+
+        // 未成交的剩余部分以过期状态返回给调用方
+        assert_eq!(execution.order.status, OrderStatus::Expired);
+        assert_eq!(execution.expired_quantity(), Decimal::new(1, 0));
+        let expired = engine
+            .drain_events()
+            .into_iter()
+            .find(|event| event.kind == OrderEventKind::Expired)
+            .unwrap();
+        assert_eq!(expired.order.id, execution.order.id);
+        assert!(expired.reason.is_some());
     }
 
-    /// 测试：订单簿深度获取
+    /// 测试：执行报告给出吃单成交数量、均价与手续费
     #[test]
-    fn test_order_book_depth() {
+    fn test_execution_report_average_price_and_fee() {
         init_test_env();
 
         let mut engine = MatchingEngine::new("BTCUSDT".to_string());
-
-        // 添加多个买单和卖单
-        for i in 1..=5 {
-            let buy_price = Decimal::new(50000 - i * 100, 0);
-            let sell_price = Decimal::new(50000 + i * 100, 0);
-
-            let buy_order = create_test_order(
-                OrderSide::Buy,
-                OrderType::Limit,
-                Some(buy_price),
-                Decimal::new(1, 0),
-            );
-            engine.add_order(buy_order).unwrap();
-
-            let sell_order = create_test_order(
-                OrderSide::Sell,
-                OrderType::Limit,
-                Some(sell_price),
-                Decimal::new(1, 0),
-            );
+        engine.set_fee_schedule(Some(Arc::new(fees::RateFeeSchedule {
+            maker_rate: Decimal::ZERO,
+            taker_rate: Decimal::new(1, 3), // 0.1%
+            quote_asset: "USDT".to_string(),
+        })));
+        for (price, quantity) in [(100, 1), (110, 3)] {
+            let sell_order = create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(price, 0)), Decimal::new(quantity, 0));
             engine.add_order(sell_order).unwrap();
         }
 
-        // 获取订单簿深度
-        let order_book = engine.get_order_book(3);
-
-        // 验证买单按价格降序排列
-        assert_eq!(order_book.bids.len(), 3);
-        assert!(order_book.bids[0].price > order_book.bids[1].price);
-        assert!(order_book.bids[1].price > order_book.bids[2].price);
-
-        // 验证卖单按价格升序排列
-        assert_eq!(order_book.asks.len(), 3);
-        assert!(order_book.asks[0].price < order_book.asks[1].price);
-        assert!(order_book.asks[1].price < order_book.asks[2].price);
+        // 买入 5，成交 1@100 与 3@110，剩余 1 挂单
+        let buy_order = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(110, 0)), Decimal::new(5, 0));
+        let report = engine.add_order(buy_order).unwrap();
+        assert_eq!(report.trades.len(), 2);
+        assert_eq!(report.filled_quantity, Decimal::new(4, 0));
+        assert_eq!(report.average_price, Some(Decimal::new(1075, 1)));
+        assert_eq!(report.fee, Decimal::new(43, 2));
+        assert_eq!(report.fee_currency.as_deref(), Some("USDT"));
+        assert_eq!(report.order.status, OrderStatus::PartiallyFilled);
+        assert_eq!(report.expired_quantity(), Decimal::ZERO);
+
+        // 未成交的订单没有均价
+        let resting = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(90, 0)), Decimal::ONE);
+        let report = engine.add_order(resting).unwrap();
+        assert_eq!(report.filled_quantity, Decimal::ZERO);
+        assert_eq!(report.average_price, None);
+        assert_eq!(report.order.status, OrderStatus::New);
     }
 
-    /// 测试：最佳买卖价获取
+    /// 测试：客户端订单号在用户的挂单中唯一，原订单撤销后可重新使用
     #[test]
-    fn test_best_bid_ask() {
+    fn test_duplicate_open_client_order_id_is_rejected() {
         init_test_env();
 
         let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let mut order = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(100, 0)), Decimal::ONE);
+        order.client_order_id = Some("client-1".to_string());
+        let original_id = order.id;
+        engine.add_order(order.clone()).unwrap();
+        assert_eq!(
+            engine.open_order_by_client_id(order.user_id, "client-1").map(|order| order.id),
+            Some(original_id)
+        );
 
-        // 初始状态应该没有最佳价格
-        assert_eq!(engine.get_best_bid(), None);
-        assert_eq!(engine.get_best_ask(), None);
-        assert_eq!(engine.get_spread(), None);
+        // 原订单仍挂着时，相同客户端订单号的新订单被拒绝
+        let duplicate = Order { id: Uuid::new_v4(), ..order.clone() };
+        assert!(matches!(engine.add_order(duplicate), Err(FlowExError::Validation(_))));
+        assert_eq!(engine.get_order_book(10).bids[0].quantity, Decimal::ONE);
+
+        // 其他用户可使用相同的客户端订单号
+        let other = Order { id: Uuid::new_v4(), user_id: Uuid::new_v4(), ..order.clone() };
+        engine.add_order(other).unwrap();
+        assert_eq!(engine.get_order_book(10).bids[0].quantity, Decimal::new(2, 0));
+
+        // 撤销后客户端订单号可以重新使用
+        engine.cancel_order(original_id).unwrap();
+        assert!(engine.open_order_by_client_id(order.user_id, "client-1").is_none());
+        let reused = Order { id: Uuid::new_v4(), ..order };
+        engine.add_order(reused).unwrap();
+        assert_eq!(engine.get_order_book(10).bids[0].quantity, Decimal::new(2, 0));
+    }
 
-        // 添加买单和卖单
-        let buy_order = create_test_order(
-            OrderSide::Buy,
-            OrderType::Limit,
-            Some(Decimal::new(49900, 0)),
-            Decimal::new(1, 0),
-        );
-        engine.add_order(buy_order).unwrap();
+    /// 测试：市价单保护 - 最大价格偏离与最大扫单档位
+    #[test]
+    fn test_market_order_protection() {
+        init_test_env();
 
-        let sell_order = create_test_order(
-            OrderSide::Sell,
-            OrderType::Limit,
-            Some(Decimal::new(50100, 0)),
-            Decimal::new(1, 0),
-        );
-        engine.add_order(sell_order).unwrap();
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        for price in [100, 101, 110, 111] {
+            let sell_order = create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(price, 0)), Decimal::ONE);
+            engine.add_order(sell_order).unwrap();
+        }
 
-        // 验证最佳价格
-        assert_eq!(engine.get_best_bid(), Some(Decimal::new(49900, 0)));
-        assert_eq!(engine.get_best_ask(), Some(Decimal::new(50100, 0)));
-        assert_eq!(engine.get_spread(), Some(Decimal::new(200, 0)));
+        assert!(engine
+            .set_market_protection(MarketProtection { max_deviation: Some(Decimal::ONE), max_levels: None })
+            .is_err());
+        assert!(engine
+            .set_market_protection(MarketProtection { max_deviation: None, max_levels: Some(0) })
+            .is_err());
+
+        // Nothing has traded yet: the 5% band is taken from the best ask
+        engine
+            .set_market_protection(MarketProtection { max_deviation: Some(Decimal::new(5, 2)), max_levels: None })
+            .unwrap();
+        let market_buy = create_test_order(OrderSide::Buy, OrderType::Market, None, Decimal::new(4, 0));
+        let trades = engine.add_order(market_buy).unwrap().trades;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].price, Decimal::new(101, 0));
+        assert_eq!(engine.get_best_ask(), Some(Decimal::new(110, 0)));
+
+        // Sweep bound only
+        engine
+            .set_market_protection(MarketProtection { max_deviation: None, max_levels: Some(1) })
+            .unwrap();
+        let market_buy = create_test_order(OrderSide::Buy, OrderType::Market, None, Decimal::new(2, 0));
+        let trades = engine.add_order(market_buy).unwrap().trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(engine.get_best_ask(), Some(Decimal::new(111, 0)));
     }
 
-    /// 测试：订单取消
+    /// 测试：价格波动超过阈值触发熔断，恢复交易后重新撮合
     #[test]
-    fn test_order_cancellation() {
+    fn test_circuit_breaker_halts_and_resumes() {
         init_test_env();
 
         let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine
+            .set_circuit_breaker(Some(CircuitBreakerConfig {
+                max_move: Decimal::new(5, 2), // 5%
+                window: std::time::Duration::from_secs(300),
+            }))
+            .unwrap();
+
+        for price in [100, 101, 120] {
+            let sell_order = create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(price, 0)), Decimal::ONE);
+            engine.add_order(sell_order).unwrap();
+        }
+        let buy_order = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(100, 0)), Decimal::ONE);
+        assert_eq!(engine.add_order(buy_order).unwrap().trades.len(), 1);
+
+        // 101 is within 5% of the last print, 120 is not: matching stops before it
+        let sweep = create_test_order(OrderSide::Buy, OrderType::Market, None, Decimal::new(2, 0));
+        let execution = engine.add_order(sweep).unwrap();
+        assert_eq!(execution.trades.len(), 1);
+        assert_eq!(execution.order.status, OrderStatus::Expired);
+        assert_eq!(engine.trading_status(), &TradingStatus::Halted);
+        assert_eq!(engine.get_best_ask(), Some(Decimal::new(120, 0)));
+
+        // 熔断期间拒绝新订单
+        let rejected = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(120, 0)), Decimal::ONE);
+        assert!(engine.add_order(rejected).is_err());
+
+        engine.resume_trading();
+        let buy_order = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(120, 0)), Decimal::ONE);
+        assert_eq!(engine.add_order(buy_order).unwrap().trades.len(), 1);
+        assert_eq!(engine.trading_status(), &TradingStatus::Trading);
+    }
 
-        // 添加订单
-        let order = create_test_order(
-            OrderSide::Buy,
-            OrderType::Limit,
-            Some(Decimal::new(50000, 0)),
-            Decimal::new(1, 0),
-        );
-        let order_id = order.id;
-        engine.add_order(order).unwrap();
-
-        // 验证订单在订单簿中
-        let order_book = engine.get_order_book(10);
-        assert_eq!(order_book.bids.len(), 1);
+    /// 测试：单笔订单横扫多个价位时逐档检查熔断，熔断后剩余部分过期而不留在交叉的订单簿上，恢复后继续撮合
+    #[test]
+    fn test_circuit_breaker_trips_mid_sweep() {
+        init_test_env();
 
-        // 取消订单
-        let cancelled = engine.cancel_order(order_id).unwrap();
-        assert!(cancelled);
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine
+            .set_circuit_breaker(Some(CircuitBreakerConfig {
+                max_move: Decimal::new(5, 2), // 5%
+                window: std::time::Duration::from_secs(300),
+            }))
+            .unwrap();
+        for price in [100, 102, 104, 106, 108] {
+            let sell_order = create_test_order(OrderSide::Sell, OrderType::Limit, Some(Decimal::new(price, 0)), Decimal::ONE);
+            engine.add_order(sell_order).unwrap();
+        }
 
-        // 验证订单已从订单簿中移除
-        let order_book = engine.get_order_book(10);
-        assert!(order_book.bids.is_empty());
+        // No earlier prints: the band forms from the levels this order sweeps
+        let sweep = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(110, 0)), Decimal::new(5, 0));
+        let execution = engine.add_order(sweep).unwrap();
+        let prices: Vec<Decimal> = execution.trades.iter().map(|t| t.price).collect();
+        assert_eq!(prices, vec![Decimal::new(100, 0), Decimal::new(102, 0), Decimal::new(104, 0)]);
+        assert_eq!(execution.order.status, OrderStatus::Expired);
+        assert_eq!(execution.order.remaining_quantity, Decimal::new(2, 0));
+        assert_eq!(engine.trading_status(), &TradingStatus::Halted);
+        assert_eq!(engine.get_best_bid(), None);
+        assert_eq!(engine.get_best_ask(), Some(Decimal::new(106, 0)));
 
-        // 尝试取消不存在的订单
-        let not_cancelled = engine.cancel_order(Uuid::new_v4()).unwrap();
-        assert!(!not_cancelled);
+        engine.resume_trading();
+        assert!(engine.integrity_violations().is_empty());
+        let buy_order = create_test_order(OrderSide::Buy, OrderType::Limit, Some(Decimal::new(110, 0)), Decimal::new(2, 0));
+        assert_eq!(engine.add_order(buy_order).unwrap().trades.len(), 2);
+        assert_eq!(engine.trading_status(), &TradingStatus::Trading);
+        assert_eq!(engine.get_best_ask(), None);
     }
 
-    /// 测试：价格时间优先原则
+    /// 测试：卖单优先匹配最高买价
     #[test]
-    fn test_price_time_priority() {
+    fn test_sell_matches_best_bid_first() {
         init_test_env();
 
         let mut engine = MatchingEngine::new("BTCUSDT".to_string());
 
-        // 添加相同价格的多个卖单（时间优先）
-        let sell_order1 = create_test_order(
-            OrderSide::Sell,
-            OrderType::Limit,
-            Some(Decimal::new(50000, 0)),
-            Decimal::new(1, 0),
-        );
-        let order1_id = sell_order1.id;
-        engine.add_order(sell_order1).unwrap();
-
-        // 稍等一下确保时间不同
-        std::thread::sleep(std::time::Duration::from_millis(1));
+        for price in [49500, 50000] {
+            let buy_order = create_test_order(
+                OrderSide::Buy,
+                OrderType::Limit,
+                Some(Decimal::new(price, 0)),
+                Decimal::new(1, 0),
+            );
+            engine.add_order(buy_order).unwrap();
+        }
 
-        let sell_order2 = create_test_order(
+        let sell_order = create_test_order(
             OrderSide::Sell,
             OrderType::Limit,
-            Some(Decimal::new(50000, 0)),
-            Decimal::new(1, 0),
-        );
-        engine.add_order(sell_order2).unwrap();
-
-        // 添加买单，应该匹配第一个卖单
-        let buy_order = create_test_order(
-            OrderSide::Buy,
-            OrderType::Limit,
-            Some(Decimal::new(50000, 0)),
+            Some(Decimal::new(49000, 0)),
             Decimal::new(1, 0),
         );
-        let trades = engine.add_order(buy_order).unwrap();
+        let trades = engine.add_order(sell_order).unwrap().trades;
 
-        // 验证交易生成且匹配了第一个订单
         assert_eq!(trades.len(), 1);
-
-        // 验证第二个订单仍在订单簿中
-        let order_book = engine.get_order_book(10);
-        assert_eq!(order_book.asks.len(), 1);
+        assert_eq!(trades[0].price, Decimal::new(50000, 0));
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(49500, 0)));
     }
 
     /// 测试：性能基准
@@ -797,8 +2956,6 @@ mod tests {
         let mut engine = MatchingEngine::new("BTCUSDT".to_string());
 
         // 模拟并发添加订单
-        let mut handles = vec![];
-
         for i in 0..10 {
             let order = create_test_order(
                 OrderSide::Buy,
@@ -808,7 +2965,7 @@ mod tests {
             );
 
             // 在实际并发环境中，这里会使用Arc<Mutex<MatchingEngine>>
-            let trades = engine.add_order(order).unwrap();
+            let trades = engine.add_order(order).unwrap().trades;
             assert!(trades.is_empty()); // 这些订单不应该匹配
         }
 
@@ -831,7 +2988,7 @@ mod tests {
             Some(Decimal::new(50000, 0)),
             Decimal::new(1, 8), // 0.00000001
         );
-        let trades = engine.add_order(tiny_order).unwrap();
+        let trades = engine.add_order(tiny_order).unwrap().trades;
         assert!(trades.is_empty());
 
         // 测试极大数量
@@ -841,8 +2998,10 @@ mod tests {
             Some(Decimal::new(50000, 0)),
             Decimal::new(1000000, 0),
         );
-        let trades = engine.add_order(large_order).unwrap();
-        assert!(trades.is_empty());
+        let execution = engine.add_order(large_order).unwrap();
+        assert_eq!(execution.trades.len(), 1);
+        assert_eq!(execution.trades[0].quantity, Decimal::new(1, 8));
+        assert_eq!(execution.order.remaining_quantity, Decimal::new(1000000, 0) - Decimal::new(1, 8));
 
         // 测试极高价格
         let high_price_order = create_test_order(
@@ -851,8 +3010,9 @@ mod tests {
             Some(Decimal::new(999999999, 0)),
             Decimal::new(1, 0),
         );
-        let trades = engine.add_order(high_price_order).unwrap();
-        assert!(trades.is_empty());
+        let trades = engine.add_order(high_price_order).unwrap().trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, Decimal::new(50000, 0));
     }
 
     /// 测试：错误恢复
@@ -891,7 +3051,7 @@ mod tests {
             Some(Decimal::new(51000, 0)),
             Decimal::new(1, 0),
         );
-        let trades = engine.add_order(another_order).unwrap();
+        let trades = engine.add_order(another_order).unwrap().trades;
         assert!(trades.is_empty());
     }
 }
@@ -0,0 +1,145 @@
+//! Engine metrics
+//!
+//! An `EngineMetrics` sink injected into the engine is told how long each
+//! order submission and matching pass took, how many trades it executed,
+//! and the shape of the book after every command that changes it. The
+//! `flowex-metrics` collector implements the sink and exports everything
+//! per symbol to Prometheus. Timing is skipped entirely when no sink is
+//! installed, and like fee schedules the sink is not part of a snapshot.
+
+use crate::MatchingEngine;
+use flowex_metrics::MetricsCollector;
+use flowex_types::OrderSide;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Shape of the book at a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BookDepth {
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+    /// Orders resting on either side; parked conditional orders excluded
+    pub resting_orders: usize,
+}
+
+/// Receives the engine's latency, throughput and book measurements
+pub trait EngineMetrics: Debug + Send + Sync {
+    /// An order submission was applied, including any matching, in `latency`
+    fn order_added(&self, symbol: &str, latency: Duration);
+
+    /// A matching pass (continuous or auction) ran in `latency` and
+    /// executed `trades` trades
+    fn matched(&self, symbol: &str, latency: Duration, trades: usize);
+
+    /// The book changed and now has this shape
+    fn book_changed(&self, symbol: &str, depth: BookDepth);
+}
+
+impl EngineMetrics for MetricsCollector {
+    fn order_added(&self, symbol: &str, latency: Duration) {
+        self.record_engine_order_add(symbol, latency);
+    }
+
+    fn matched(&self, symbol: &str, latency: Duration, trades: usize) {
+        self.record_engine_match(symbol, latency, trades as u64);
+    }
+
+    fn book_changed(&self, symbol: &str, depth: BookDepth) {
+        self.record_engine_book(symbol, depth.bid_levels, depth.ask_levels, depth.resting_orders);
+    }
+}
+
+impl MatchingEngine {
+    /// Install or remove the sink engine measurements are reported to
+    pub fn set_metrics(&mut self, metrics: Option<Arc<dyn EngineMetrics>>) {
+        self.metrics = metrics;
+    }
+
+    /// Sink engine measurements are reported to, if any
+    pub fn metrics(&self) -> Option<&dyn EngineMetrics> {
+        self.metrics.as_deref()
+    }
+
+    /// Current number of price levels and resting orders
+    pub fn book_depth(&self) -> BookDepth {
+        BookDepth {
+            bid_levels: self.book.level_count(&OrderSide::Buy),
+            ask_levels: self.book.level_count(&OrderSide::Sell),
+            resting_orders: self.book.len(),
+        }
+    }
+
+    /// Report the book's shape to the metrics sink, if any
+    pub(crate) fn record_book_metrics(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.book_changed(&self.symbol, self.book_depth());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct Recorded {
+        orders_added: usize,
+        trades: usize,
+        depth: Option<BookDepth>,
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingMetrics(Mutex<Recorded>);
+
+    impl EngineMetrics for RecordingMetrics {
+        fn order_added(&self, _symbol: &str, _latency: Duration) {
+            self.0.lock().unwrap().orders_added += 1;
+        }
+
+        fn matched(&self, _symbol: &str, _latency: Duration, trades: usize) {
+            self.0.lock().unwrap().trades += trades;
+        }
+
+        fn book_changed(&self, _symbol: &str, depth: BookDepth) {
+            self.0.lock().unwrap().depth = Some(depth);
+        }
+    }
+
+    /// 测试：撮合引擎向指标接收器报告延迟、成交数和订单簿深度
+    #[test]
+    fn test_engine_reports_metrics() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let metrics = Arc::new(RecordingMetrics::default());
+        engine.set_metrics(Some(metrics.clone()));
+        assert!(engine.metrics().is_some());
+
+        engine.add_order(create_limit_order(OrderSide::Sell, 101, 1)).unwrap();
+        engine.add_order(create_limit_order(OrderSide::Sell, 102, 1)).unwrap();
+        let bid = create_limit_order(OrderSide::Buy, 99, 1);
+        let bid_id = bid.id;
+        engine.add_order(bid).unwrap();
+        engine.add_order(create_limit_order(OrderSide::Buy, 102, 2)).unwrap();
+
+        {
+            let recorded = metrics.0.lock().unwrap();
+            assert_eq!(recorded.orders_added, 4);
+            assert_eq!(recorded.trades, 2);
+            assert_eq!(
+                recorded.depth,
+                Some(BookDepth {
+                    bid_levels: 1,
+                    ask_levels: 0,
+                    resting_orders: 1,
+                })
+            );
+        }
+
+        engine.cancel_order(bid_id).unwrap();
+        assert_eq!(metrics.0.lock().unwrap().depth, Some(BookDepth::default()));
+        assert_eq!(engine.book_depth(), BookDepth::default());
+    }
+}
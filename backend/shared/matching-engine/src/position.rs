@@ -0,0 +1,174 @@
+//! Reduce-only orders
+//!
+//! A reduce-only order may only shrink its owner's position, never grow or
+//! flip it. The engine holds no positions itself: a `PositionSource`
+//! injected by the service (backed by the wallet or margin ledger) reports
+//! each user's net position. An order is trimmed to the quantity it can
+//! still reduce when it is submitted, activated or modified, and a resting
+//! reduce-only order is re-checked each time it is about to fill. Like fee
+//! schedules, the source is not part of a snapshot.
+
+use crate::MatchingEngine;
+use flowex_types::{FlowExError, FlowExResult, Order, OrderSide, OrderType};
+use rust_decimal::Decimal;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tracing::info;
+use uuid::Uuid;
+
+/// Reports users' positions to the engine
+pub trait PositionSource: Debug + Send + Sync {
+    /// Net position of `user_id` in `symbol`'s base asset: positive when
+    /// long, negative when short
+    fn position(&self, user_id: Uuid, symbol: &str) -> Decimal;
+}
+
+/// Quantity an order on `side` can trade against `position` without
+/// increasing its size
+pub fn reducible_quantity(position: Decimal, side: &OrderSide) -> Decimal {
+    match side {
+        OrderSide::Sell => position.max(Decimal::ZERO),
+        OrderSide::Buy => (-position).max(Decimal::ZERO),
+    }
+}
+
+impl MatchingEngine {
+    /// Install or remove the source reduce-only orders are checked against
+    pub fn set_position_source(&mut self, position_source: Option<Arc<dyn PositionSource>>) {
+        self.position_source = position_source;
+    }
+
+    /// Source reduce-only orders are checked against, if any
+    pub fn position_source(&self) -> Option<&dyn PositionSource> {
+        self.position_source.as_deref()
+    }
+
+    /// Trim a reduce-only order to the quantity it can still reduce.
+    ///
+    /// Other resting reduce-only orders of the same user and side already
+    /// claim part of the position. Rejects the order when nothing is left
+    /// to reduce or no position source is installed.
+    pub(crate) fn limit_reduce_only(&self, order: &mut Order) -> FlowExResult<()> {
+        if !order.reduce_only {
+            return Ok(());
+        }
+        let source = self.position_source.as_ref().ok_or_else(|| {
+            FlowExError::Trading("Reduce-only orders are not supported without a position source".to_string())
+        })?;
+
+        let committed: Decimal = self
+            .user_orders
+            .get(&order.user_id)
+            .into_iter()
+            .flatten()
+            .filter(|order_id| **order_id != order.id)
+            .filter_map(|order_id| self.open_order(*order_id))
+            .filter(|other| other.reduce_only && other.side == order.side && other.order_type == OrderType::Limit)
+            .map(|other| other.remaining_quantity)
+            .sum();
+        let position = source.position(order.user_id, &self.symbol);
+        let allowed = reducible_quantity(position, &order.side) - committed;
+
+        if allowed <= Decimal::ZERO {
+            info!(
+                "Rejected reduce-only order {}: position {} leaves nothing to reduce",
+                order.id, position
+            );
+            return Err(FlowExError::Trading("Reduce-only order would increase the position".to_string()));
+        }
+        if order.remaining_quantity > allowed {
+            info!(
+                "Trimmed reduce-only order {} from {} to {} remaining",
+                order.id, order.remaining_quantity, allowed
+            );
+            order.quantity -= order.remaining_quantity - allowed;
+            order.remaining_quantity = allowed;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::{create_limit_order, create_user_limit_order};
+    use flowex_types::OrderEventKind;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    fn reduce_only(order: Order) -> Order {
+        Order { reduce_only: true, ..order }
+    }
+
+    #[derive(Debug, Default)]
+    struct Positions(Mutex<HashMap<Uuid, Decimal>>);
+
+    impl Positions {
+        fn set(&self, user_id: Uuid, position: i64) {
+            self.0.lock().unwrap().insert(user_id, Decimal::new(position, 0));
+        }
+    }
+
+    impl PositionSource for Positions {
+        fn position(&self, user_id: Uuid, _symbol: &str) -> Decimal {
+            self.0.lock().unwrap().get(&user_id).copied().unwrap_or_default()
+        }
+    }
+
+    /// 测试：只减仓订单不会增加持仓
+    #[test]
+    fn test_reduce_only_never_increases_position() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let user = Uuid::new_v4();
+
+        // 未配置持仓来源时拒绝只减仓订单
+        let order = reduce_only(create_user_limit_order(user, OrderSide::Sell, 100, 1));
+        assert!(engine.add_order(order).is_err());
+
+        let positions = Arc::new(Positions::default());
+        engine.set_position_source(Some(positions.clone()));
+        positions.set(user, 3);
+
+        // 多头持仓不能再买入
+        let order = reduce_only(create_user_limit_order(user, OrderSide::Buy, 100, 1));
+        assert!(engine.add_order(order).is_err());
+
+        // 卖出数量被裁剪至持仓，已挂出的只减仓订单占用额度
+        let first = reduce_only(create_user_limit_order(user, OrderSide::Sell, 101, 2));
+        engine.add_order(first).unwrap();
+        let second = reduce_only(create_user_limit_order(user, OrderSide::Sell, 102, 5));
+        let execution = engine.add_order(second).unwrap();
+        assert_eq!(execution.order.quantity, Decimal::ONE);
+        assert_eq!(execution.order.remaining_quantity, Decimal::ONE);
+        let third = reduce_only(create_user_limit_order(user, OrderSide::Sell, 103, 1));
+        assert!(engine.add_order(third).is_err());
+    }
+
+    /// 测试：持仓减少后挂单中的只减仓订单在成交前被限制
+    #[test]
+    fn test_resting_reduce_only_rechecked_on_fill() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let user = Uuid::new_v4();
+        let positions = Arc::new(Positions::default());
+        engine.set_position_source(Some(positions.clone()));
+        positions.set(user, 2);
+
+        let resting = reduce_only(create_user_limit_order(user, OrderSide::Sell, 100, 2));
+        let resting_id = resting.id;
+        engine.add_order(resting).unwrap();
+
+        // 持仓在别处部分平仓后，只成交剩余持仓，其余部分被撤销而非成交
+        positions.set(user, 1);
+        engine.drain_events();
+        let trades = engine.add_order(create_limit_order(OrderSide::Buy, 100, 2)).unwrap().trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, Decimal::ONE);
+        assert!(engine.open_orders_for_user(user).is_empty());
+        assert!(engine
+            .drain_events()
+            .iter()
+            .any(|event| event.kind == OrderEventKind::Cancelled && event.order.id == resting_id));
+        assert_eq!(engine.get_best_ask(), None);
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(100, 0)));
+    }
+}
@@ -0,0 +1,197 @@
+//! Limit price bands around a reference index
+//!
+//! A thin book is no guard against fat-finger limit orders: a bid at ten
+//! times the market rests happily, or sweeps whatever asks there are. With a
+//! `ReferencePriceSource` injected, for example an index price aggregated
+//! from other venues, limit orders priced more than the band's maximum
+//! deviation away from the reference are rejected or, in flag mode, accepted
+//! with a warning and an annotated acceptance event. Orders pass unchecked
+//! while the source has no price for the symbol. The band settings are part
+//! of a snapshot; like fee schedules the source is not.
+
+use crate::MatchingEngine;
+use flowex_types::{FlowExError, FlowExResult, Order, OrderType};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Supplies the reference (index) price limit orders are checked against
+pub trait ReferencePriceSource: Debug + Send + Sync {
+    /// Current reference price of `symbol`, or `None` if unavailable
+    fn reference_price(&self, symbol: &str) -> Option<Decimal>;
+}
+
+/// What happens to a limit order outside the band
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PriceBandAction {
+    /// Reject the order
+    #[default]
+    Reject,
+    /// Accept the order but log a warning and note it on the acceptance event
+    Flag,
+}
+
+/// Limit price band settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceBand {
+    /// Maximum fractional deviation from the reference price (0.1 = 10%)
+    pub max_deviation: Decimal,
+    #[serde(default)]
+    pub action: PriceBandAction,
+}
+
+impl PriceBand {
+    /// Fractional distance of `price` from `reference`, or `None` without a
+    /// positive reference
+    pub fn deviation(price: Decimal, reference: Decimal) -> Option<Decimal> {
+        (reference > Decimal::ZERO).then(|| (price - reference).abs() / reference)
+    }
+}
+
+impl MatchingEngine {
+    /// Enable (or with `None`, disable) the limit price band
+    pub fn set_price_band(&mut self, price_band: Option<PriceBand>) -> FlowExResult<()> {
+        if price_band
+            .as_ref()
+            .is_some_and(|band| band.max_deviation <= Decimal::ZERO)
+        {
+            return Err(FlowExError::Validation(
+                "Price band max deviation must be positive".to_string(),
+            ));
+        }
+
+        self.price_band = price_band;
+        Ok(())
+    }
+
+    /// Limit price band in effect, if any
+    pub fn price_band(&self) -> Option<&PriceBand> {
+        self.price_band.as_ref()
+    }
+
+    /// Install or remove the source of reference prices for the price band
+    pub fn set_reference_price_source(&mut self, source: Option<Arc<dyn ReferencePriceSource>>) {
+        self.reference_price_source = source;
+    }
+
+    /// Source of reference prices for the price band, if any
+    pub fn reference_price_source(&self) -> Option<&dyn ReferencePriceSource> {
+        self.reference_price_source.as_deref()
+    }
+
+    /// Check a limit order's price against the band. Returns an error if the
+    /// order must be rejected, or a note for its acceptance event if it is
+    /// only flagged.
+    pub(crate) fn check_price_band(&self, order: &Order) -> FlowExResult<Option<String>> {
+        let (Some(band), Some(source)) = (&self.price_band, &self.reference_price_source) else {
+            return Ok(None);
+        };
+        let (OrderType::Limit, Some(price)) = (&order.order_type, order.price) else {
+            return Ok(None);
+        };
+        let Some(reference) = source.reference_price(&self.symbol) else {
+            return Ok(None);
+        };
+        let Some(deviation) = PriceBand::deviation(price, reference) else {
+            return Ok(None);
+        };
+        if deviation <= band.max_deviation {
+            return Ok(None);
+        }
+
+        let message = format!(
+            "Limit price {} of order {} is {}% away from reference price {}",
+            price,
+            order.id,
+            (deviation * Decimal::ONE_HUNDRED).round_dp(2).normalize(),
+            reference
+        );
+        match band.action {
+            PriceBandAction::Reject => Err(FlowExError::Trading(message)),
+            PriceBandAction::Flag => {
+                warn!("{} on {}", message, self.symbol);
+                Ok(Some(message))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+    use flowex_types::{OrderEventKind, OrderSide};
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct FixedIndex(Mutex<Option<Decimal>>);
+
+    impl ReferencePriceSource for FixedIndex {
+        fn reference_price(&self, _symbol: &str) -> Option<Decimal> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn engine_with_band(action: PriceBandAction) -> (MatchingEngine, Arc<FixedIndex>) {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let index = Arc::new(FixedIndex(Mutex::new(Some(Decimal::new(100, 0)))));
+        engine
+            .set_price_band(Some(PriceBand {
+                max_deviation: Decimal::new(1, 1), // 10%
+                action,
+            }))
+            .unwrap();
+        engine.set_reference_price_source(Some(index.clone()));
+        (engine, index)
+    }
+
+    /// 测试：偏离参考价超过阈值的限价单被拒绝
+    #[test]
+    fn test_band_rejects_far_limit_orders() {
+        let (mut engine, index) = engine_with_band(PriceBandAction::Reject);
+        assert!(engine.reference_price_source().is_some());
+        assert!(engine
+            .set_price_band(Some(PriceBand { max_deviation: Decimal::ZERO, action: PriceBandAction::Reject }))
+            .is_err());
+
+        assert!(engine.add_order(create_limit_order(OrderSide::Sell, 110, 1)).is_ok());
+        assert!(engine.add_order(create_limit_order(OrderSide::Buy, 1000, 1)).is_err());
+        assert!(engine.add_order(create_limit_order(OrderSide::Sell, 80, 1)).is_err());
+        assert_eq!(engine.get_best_ask(), Some(Decimal::new(110, 0)));
+
+        // 改价同样受价格带约束
+        let bid = create_limit_order(OrderSide::Buy, 95, 1);
+        let bid_id = bid.id;
+        engine.add_order(bid).unwrap();
+        assert!(engine.modify_order(bid_id, Some(Decimal::new(50, 0)), None).is_err());
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(95, 0)));
+
+        // 参考价不可用时不做检查
+        *index.0.lock().unwrap() = None;
+        assert!(engine.add_order(create_limit_order(OrderSide::Buy, 50, 1)).is_ok());
+    }
+
+    /// 测试：标记模式下接受订单并在接受事件中注明原因
+    #[test]
+    fn test_band_flags_far_limit_orders() {
+        let (mut engine, _index) = engine_with_band(PriceBandAction::Flag);
+        let order = create_limit_order(OrderSide::Buy, 150, 1);
+        let order_id = order.id;
+        engine.add_order(order).unwrap();
+        assert_eq!(engine.get_best_bid(), Some(Decimal::new(150, 0)));
+
+        let accepted = engine
+            .drain_events()
+            .into_iter()
+            .find(|event| event.order.id == order_id && event.kind == OrderEventKind::Accepted)
+            .unwrap();
+        assert!(accepted.reason.unwrap().contains("50%"));
+
+        // 价格带设置随快照保存
+        let restored = MatchingEngine::restore(engine.snapshot()).unwrap();
+        assert_eq!(restored.price_band(), engine.price_band());
+        assert!(restored.reference_price_source().is_none());
+    }
+}
@@ -0,0 +1,53 @@
+//! Market order protection
+//!
+//! Bounds how far a market order may move the price: a maximum deviation
+//! from a reference price (the last trade, or the best opposite quote when
+//! nothing has traded yet) and a maximum number of price levels swept.
+//! Quantity that cannot be filled within those bounds is cancelled like any
+//! other market order remainder.
+
+use flowex_types::OrderSide;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Market order protection settings; `None` disables a bound
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MarketProtection {
+    /// Maximum fractional deviation from the reference price (0.05 = 5%)
+    pub max_deviation: Option<Decimal>,
+    /// Maximum number of opposite price levels a market order may sweep
+    pub max_levels: Option<usize>,
+}
+
+impl MarketProtection {
+    /// Worst price a market order on `side` may execute at
+    pub fn price_limit(&self, side: &OrderSide, reference: Option<Decimal>) -> Option<Decimal> {
+        let deviation = self.max_deviation?;
+        let reference = reference?;
+
+        Some(match side {
+            OrderSide::Buy => reference * (Decimal::ONE + deviation),
+            OrderSide::Sell => reference * (Decimal::ONE - deviation),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：按参考价与最大偏离计算市价单保护价
+    #[test]
+    fn test_price_limit() {
+        let protection = MarketProtection {
+            max_deviation: Some(Decimal::new(5, 2)), // 5%
+            max_levels: None,
+        };
+        let reference = Some(Decimal::new(100, 0));
+
+        assert_eq!(protection.price_limit(&OrderSide::Buy, reference), Some(Decimal::new(105, 0)));
+        assert_eq!(protection.price_limit(&OrderSide::Sell, reference), Some(Decimal::new(95, 0)));
+        assert_eq!(protection.price_limit(&OrderSide::Buy, None), None);
+        assert_eq!(MarketProtection::default().price_limit(&OrderSide::Buy, reference), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! Trading session schedule
+//!
+//! A symbol may only trade during daily session windows (UTC) and never
+//! during scheduled maintenance. `update_session` is the engine command
+//! that applies the calendar: run periodically like expiry sweeps, it halts
+//! the symbol when a session closes and resumes it when the next one opens.
+//! While closed by the schedule, new orders are either rejected or queued
+//! and submitted, in arrival order, once trading resumes. Because the
+//! transitions are commands rather than reads of the wall clock, replaying
+//! a journal reproduces them exactly. The schedule, the closed state and
+//! queued orders are part of a snapshot.
+
+use crate::MatchingEngine;
+use chrono::{DateTime, NaiveTime, Utc};
+use flowex_types::{FlowExError, FlowExResult, Order, OrderEventKind, Trade, TradingStatus};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+/// A daily trading window in UTC; a close before the open wraps past midnight
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionWindow {
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+}
+
+impl SessionWindow {
+    /// Whether `time` falls within the window, open inclusive, close exclusive
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.open <= self.close {
+            time >= self.open && time < self.close
+        } else {
+            time >= self.open || time < self.close
+        }
+    }
+}
+
+/// A one-off period during which the symbol does not trade
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// What happens to orders submitted while the session is closed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OffSessionPolicy {
+    /// Reject the order
+    #[default]
+    Reject,
+    /// Hold the order and submit it when the session opens
+    Queue,
+}
+
+/// Session calendar of a symbol
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionSchedule {
+    /// Daily trading windows; none means trading around the clock
+    pub sessions: Vec<SessionWindow>,
+    #[serde(default)]
+    pub maintenance: Vec<MaintenanceWindow>,
+    #[serde(default)]
+    pub off_session: OffSessionPolicy,
+}
+
+impl SessionSchedule {
+    /// Whether the symbol trades at `at`
+    pub fn is_open(&self, at: DateTime<Utc>) -> bool {
+        let in_session = self.sessions.is_empty() || self.sessions.iter().any(|window| window.contains(at.time()));
+        let in_maintenance = self
+            .maintenance
+            .iter()
+            .any(|window| at >= window.start && at < window.end);
+        in_session && !in_maintenance
+    }
+}
+
+impl MatchingEngine {
+    /// Set (or with `None`, clear) the session schedule. It takes effect
+    /// on the next `update_session`.
+    pub fn set_session_schedule(&mut self, schedule: Option<SessionSchedule>) -> FlowExResult<()> {
+        if let Some(schedule) = &schedule {
+            if schedule.sessions.iter().any(|window| window.open == window.close) {
+                return Err(FlowExError::Validation(
+                    "Session windows must not open and close at the same time".to_string(),
+                ));
+            }
+            if schedule.maintenance.iter().any(|window| window.start >= window.end) {
+                return Err(FlowExError::Validation(
+                    "Maintenance windows must end after they start".to_string(),
+                ));
+            }
+        }
+
+        self.session_schedule = schedule;
+        Ok(())
+    }
+
+    /// Session schedule, if any
+    pub fn session_schedule(&self) -> Option<&SessionSchedule> {
+        self.session_schedule.as_ref()
+    }
+
+    /// Whether the schedule has closed the symbol
+    pub fn is_session_closed(&self) -> bool {
+        self.session_closed
+    }
+
+    /// Orders waiting for the session to open, in arrival order
+    pub fn queued_orders(&self) -> &[Order] {
+        &self.session_queue
+    }
+
+    /// Apply the session schedule at `now`: halt the symbol when its
+    /// session has closed, resume it when a session is open again and
+    /// submit the queued orders. Returns the trades the queued orders made.
+    ///
+    /// An operator halt while the session is closed keeps the symbol
+    /// halted when the session opens.
+    pub fn update_session(&mut self, now: DateTime<Utc>) -> FlowExResult<Vec<Trade>> {
+        self.sequence += 1;
+        let open = self.session_schedule.as_ref().is_none_or(|schedule| schedule.is_open(now));
+
+        if !open {
+            if !self.session_closed && self.trading_status == TradingStatus::Trading {
+                info!("Trading session closed for {} at {}", self.symbol, now);
+                self.trading_status = TradingStatus::Halted;
+                self.session_closed = true;
+            }
+            return Ok(Vec::new());
+        }
+
+        if self.session_closed {
+            info!("Trading session opened for {} at {}", self.symbol, now);
+            self.session_closed = false;
+            if self.trading_status == TradingStatus::Halted {
+                if let Some(breaker) = &mut self.circuit_breaker {
+                    breaker.reset();
+                }
+                self.trading_status = TradingStatus::Trading;
+            }
+        }
+        if self.trading_status != TradingStatus::Trading || self.session_queue.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let queued = std::mem::take(&mut self.session_queue);
+        info!("Submitting {} orders queued for the {} session", queued.len(), self.symbol);
+        let mut trades = Vec::new();
+        for order in queued {
+            self.untrack_order(&order);
+            // A rejected order gets its rejection event like any submission
+            if let Ok(report) = self.place_order(order) {
+                trades.extend(report.trades);
+            }
+        }
+        self.book_changed();
+
+        Ok(trades)
+    }
+
+    /// Hold an order submitted while the session is closed, or reject it
+    /// per the schedule's policy
+    pub(crate) fn hold_off_session(&mut self, order: Order) -> FlowExResult<Order> {
+        let policy = self
+            .session_schedule
+            .as_ref()
+            .map_or(OffSessionPolicy::Reject, |schedule| schedule.off_session);
+
+        match policy {
+            OffSessionPolicy::Reject => Err(FlowExError::Trading(format!(
+                "Trading session for {} is closed",
+                self.symbol
+            ))),
+            OffSessionPolicy::Queue => {
+                debug!("Queued order {} until the {} session opens", order.id, self.symbol);
+                self.emit(
+                    OrderEventKind::Accepted,
+                    &order,
+                    None,
+                    Some("Queued until the trading session opens".to_string()),
+                );
+                self.track_order(&order);
+                self.session_queue.push(order.clone());
+                Ok(order)
+            }
+        }
+    }
+
+    /// Remove an order from the session queue
+    pub(crate) fn take_queued(&mut self, order_id: Uuid) -> Option<Order> {
+        let index = self.session_queue.iter().position(|order| order.id == order_id)?;
+        Some(self.session_queue.remove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use flowex_test_support::create_limit_order;
+    use flowex_types::OrderSide;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 15, hour, minute, 0).unwrap()
+    }
+
+    fn schedule(off_session: OffSessionPolicy) -> SessionSchedule {
+        SessionSchedule {
+            sessions: vec![SessionWindow {
+                open: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                close: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            }],
+            maintenance: vec![MaintenanceWindow {
+                start: at(12, 0),
+                end: at(12, 30),
+            }],
+            off_session,
+        }
+    }
+
+    /// 测试：交易时段与维护窗口的判定
+    #[test]
+    fn test_schedule_is_open() {
+        let schedule = schedule(OffSessionPolicy::Reject);
+        assert!(!schedule.is_open(at(8, 59)));
+        assert!(schedule.is_open(at(9, 0)));
+        assert!(!schedule.is_open(at(12, 15)));
+        assert!(schedule.is_open(at(12, 30)));
+        assert!(!schedule.is_open(at(17, 0)));
+
+        // 跨越午夜的时段
+        let overnight = SessionWindow {
+            open: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            close: NaiveTime::from_hms_opt(2, 0, 0).unwrap(),
+        };
+        assert!(overnight.contains(NaiveTime::from_hms_opt(23, 0, 0).unwrap()));
+        assert!(overnight.contains(NaiveTime::from_hms_opt(1, 0, 0).unwrap()));
+        assert!(!overnight.contains(NaiveTime::from_hms_opt(3, 0, 0).unwrap()));
+        assert!(SessionSchedule::default().is_open(at(3, 0)));
+    }
+
+    /// 测试：休市期间拒绝订单，开市后恢复交易
+    #[test]
+    fn test_closed_session_rejects_orders() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine.set_session_schedule(Some(schedule(OffSessionPolicy::Reject))).unwrap();
+
+        engine.update_session(at(8, 0)).unwrap();
+        assert!(engine.is_session_closed());
+        assert_eq!(engine.trading_status(), &TradingStatus::Halted);
+        assert!(engine.add_order(create_limit_order(OrderSide::Buy, 100, 1)).is_err());
+
+        engine.update_session(at(9, 0)).unwrap();
+        assert_eq!(engine.trading_status(), &TradingStatus::Trading);
+        assert!(engine.add_order(create_limit_order(OrderSide::Buy, 100, 1)).is_ok());
+
+        // 维护窗口同样暂停交易
+        engine.update_session(at(12, 10)).unwrap();
+        assert_eq!(engine.trading_status(), &TradingStatus::Halted);
+        engine.update_session(at(12, 30) + Duration::seconds(1)).unwrap();
+        assert_eq!(engine.trading_status(), &TradingStatus::Trading);
+
+        assert!(engine
+            .set_session_schedule(Some(SessionSchedule {
+                maintenance: vec![MaintenanceWindow { start: at(13, 0), end: at(12, 0) }],
+                ..Default::default()
+            }))
+            .is_err());
+    }
+
+    /// 测试：休市期间排队的订单在开市后按到达顺序撮合
+    #[test]
+    fn test_queued_orders_submitted_when_session_opens() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine.set_session_schedule(Some(schedule(OffSessionPolicy::Queue))).unwrap();
+        engine.update_session(at(7, 0)).unwrap();
+
+        let sell = create_limit_order(OrderSide::Sell, 100, 1);
+        let report = engine.add_order(sell.clone()).unwrap();
+        assert!(report.trades.is_empty());
+        let cancelled = create_limit_order(OrderSide::Sell, 105, 1);
+        let cancelled_id = cancelled.id;
+        engine.add_order(cancelled).unwrap();
+        engine.add_order(create_limit_order(OrderSide::Buy, 100, 1)).unwrap();
+        assert_eq!(engine.queued_orders().len(), 3);
+        assert_eq!(engine.open_orders_for_user(sell.user_id).len(), 1);
+        assert!(engine.cancel_order(cancelled_id).unwrap());
+
+        // 快照保留排队订单
+        let mut restored = MatchingEngine::restore(engine.snapshot()).unwrap();
+        assert_eq!(restored.queued_orders().len(), 2);
+        assert!(restored.is_session_closed());
+
+        let trades = restored.update_session(at(9, 30)).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, sell.id);
+        assert!(restored.queued_orders().is_empty());
+        assert_eq!(restored.get_best_bid(), None);
+        assert_eq!(restored.get_best_ask(), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Order book snapshots
+//!
+//! Captures the full state of a `MatchingEngine` — resting orders in
+//! priority order, parked conditional orders, the command and trade
+//! sequence numbers and last trade price — as a serializable value, so the
+//! book can be persisted and rebuilt after a restart.
+
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use crate::price_band::PriceBand;
+use crate::protection::MarketProtection;
+use crate::session::SessionSchedule;
+use crate::trigger::{TriggerBook, TriggerDirection};
+use crate::MatchingEngine;
+use chrono::{DateTime, Utc};
+use flowex_types::{FlowExError, FlowExResult, Order, OrderSide, TradingPair, TradingStatus};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Serializable engine state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    pub symbol: String,
+    /// Constraints enforced by the engine, if configured
+    #[serde(default)]
+    pub trading_pair: Option<TradingPair>,
+    /// Whether price levels are keyed by the trading pair's ticks
+    #[serde(default)]
+    pub fixed_point: bool,
+    /// Market order protection in effect
+    #[serde(default)]
+    pub market_protection: MarketProtection,
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Limit price band around the reference price, if enabled
+    #[serde(default)]
+    pub price_band: Option<PriceBand>,
+    /// Trading status; absent means trading
+    #[serde(default)]
+    pub trading_status: Option<TradingStatus>,
+    /// Trading hours and maintenance windows, if scheduled
+    #[serde(default)]
+    pub session_schedule: Option<SessionSchedule>,
+    /// Whether the session schedule has halted the symbol
+    #[serde(default)]
+    pub session_closed: bool,
+    /// Orders held until the session opens, in arrival order
+    #[serde(default)]
+    pub session_queue: Vec<Order>,
+    /// Whether a call auction is open
+    #[serde(default)]
+    pub in_auction: bool,
+    /// Number of commands applied when the snapshot was taken
+    pub sequence: u64,
+    /// Sequence number of the last trade executed
+    #[serde(default)]
+    pub trade_sequence: u64,
+    pub last_trade_price: Option<Decimal>,
+    pub total_volume: Decimal,
+    /// Resting bids, best price first, in time priority within a level
+    pub bids: Vec<Order>,
+    /// Resting asks, best price first, in time priority within a level
+    pub asks: Vec<Order>,
+    /// Conditional orders awaiting their trigger
+    pub triggers: Vec<Order>,
+    pub taken_at: DateTime<Utc>,
+}
+
+impl MatchingEngine {
+    /// Capture the current engine state
+    pub fn snapshot(&self) -> EngineSnapshot {
+        EngineSnapshot {
+            symbol: self.symbol.clone(),
+            trading_pair: self.trading_pair.clone(),
+            fixed_point: self.book.scale().is_some(),
+            market_protection: self.market_protection.clone(),
+            circuit_breaker: self.circuit_breaker().cloned(),
+            price_band: self.price_band.clone(),
+            trading_status: Some(self.trading_status.clone()),
+            session_schedule: self.session_schedule.clone(),
+            session_closed: self.session_closed,
+            session_queue: self.session_queue.clone(),
+            in_auction: self.in_auction,
+            sequence: self.sequence,
+            trade_sequence: self.trade_sequence,
+            last_trade_price: self.last_trade_price,
+            total_volume: self.total_volume,
+            bids: self.book.orders_best_first(&OrderSide::Buy).cloned().collect(),
+            asks: self.book.orders_best_first(&OrderSide::Sell).cloned().collect(),
+            triggers: self.triggers.orders().cloned().collect(),
+            taken_at: Utc::now().max(self.clock),
+        }
+    }
+
+    /// Rebuild an engine from a snapshot.
+    ///
+    /// Pending order events are not part of a snapshot; the restored engine
+    /// starts with an empty event queue.
+    pub fn restore(snapshot: EngineSnapshot) -> FlowExResult<Self> {
+        let mut engine = MatchingEngine::new(snapshot.symbol);
+        engine.trading_pair = snapshot.trading_pair;
+        engine.market_protection = snapshot.market_protection;
+        engine.circuit_breaker = snapshot.circuit_breaker.map(CircuitBreaker::new);
+        engine.price_band = snapshot.price_band;
+        engine.trading_status = snapshot.trading_status.unwrap_or(TradingStatus::Trading);
+        engine.set_session_schedule(snapshot.session_schedule)?;
+        engine.session_closed = snapshot.session_closed;
+        engine.in_auction = snapshot.in_auction;
+        restore_side(&mut engine, OrderSide::Buy, snapshot.bids)?;
+        restore_side(&mut engine, OrderSide::Sell, snapshot.asks)?;
+        engine.set_fixed_point(snapshot.fixed_point)?;
+
+        let mut triggers = TriggerBook::new();
+        for order in snapshot.triggers {
+            check_symbol(&engine.symbol, &order)?;
+            if TriggerDirection::for_order(&order).is_none() || order.stop_price.is_none() {
+                return Err(FlowExError::Validation(format!(
+                    "Snapshot trigger order {} is not a conditional order",
+                    order.id
+                )));
+            }
+            triggers.insert(order);
+        }
+        engine.triggers = triggers;
+
+        for order in &snapshot.session_queue {
+            check_symbol(&engine.symbol, order)?;
+        }
+        engine.session_queue = snapshot.session_queue;
+
+        // Rebuild the per-user open order index in placement order
+        let mut open_orders: Vec<Order> = engine
+            .book
+            .orders_best_first(&OrderSide::Buy)
+            .chain(engine.book.orders_best_first(&OrderSide::Sell))
+            .chain(engine.triggers.orders())
+            .chain(engine.session_queue.iter())
+            .cloned()
+            .collect();
+        open_orders.sort_by_key(|order| order.created_at);
+        for order in &open_orders {
+            engine.track_order(order);
+        }
+
+        engine.sequence = snapshot.sequence;
+        engine.trade_sequence = snapshot.trade_sequence;
+        engine.clock = snapshot.taken_at;
+        engine.last_trade_price = snapshot.last_trade_price;
+        engine.total_volume = snapshot.total_volume;
+        engine.verify_integrity(false)?;
+
+        info!(
+            "Restored {} engine at sequence {} ({} resting orders, {} triggers)",
+            engine.symbol,
+            engine.sequence,
+            engine.book.len(),
+            engine.triggers.len()
+        );
+        Ok(engine)
+    }
+}
+
+/// Rebuild one side of the book; orders within a level keep snapshot order
+fn restore_side(engine: &mut MatchingEngine, side: OrderSide, orders: Vec<Order>) -> FlowExResult<()> {
+    for order in orders {
+        check_symbol(&engine.symbol, &order)?;
+        if order.side != side {
+            return Err(FlowExError::Validation(format!(
+                "Snapshot order {} is on the wrong side of the book",
+                order.id
+            )));
+        }
+        if order.price.is_none_or(|price| price <= Decimal::ZERO) || order.remaining_quantity <= Decimal::ZERO {
+            return Err(FlowExError::Validation(format!(
+                "Snapshot order {} cannot rest on the book",
+                order.id
+            )));
+        }
+        if engine.book.contains(order.id) {
+            return Err(FlowExError::Validation(format!(
+                "Snapshot order {} appears more than once",
+                order.id
+            )));
+        }
+        engine.book.push_back(order);
+    }
+
+    Ok(())
+}
+
+fn check_symbol(symbol: &str, order: &Order) -> FlowExResult<()> {
+    if order.trading_pair != symbol {
+        return Err(FlowExError::Validation(format!(
+            "Snapshot order {} belongs to {}, not {}",
+            order.id, order.trading_pair, symbol
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+    use flowex_types::OrderType;
+
+    fn create_order(side: OrderSide, order_type: OrderType, price: i64, stop_price: Option<i64>) -> Order {
+        Order {
+            order_type,
+            stop_price: stop_price.map(Decimal::from),
+            ..create_limit_order(side, price, 1)
+        }
+    }
+
+    /// 测试：快照经 JSON 往返后恢复出相同的订单簿与优先级
+    #[test]
+    fn test_snapshot_restore_round_trip() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let first_bid = create_order(OrderSide::Buy, OrderType::Limit, 49000, None);
+        let first_bid_id = first_bid.id;
+        engine.add_order(first_bid).unwrap();
+        engine.add_order(create_order(OrderSide::Buy, OrderType::Limit, 49000, None)).unwrap();
+        engine.add_order(create_order(OrderSide::Buy, OrderType::Limit, 48000, None)).unwrap();
+        engine.add_order(create_order(OrderSide::Sell, OrderType::Limit, 51000, None)).unwrap();
+        engine.add_order(create_order(OrderSide::Sell, OrderType::Limit, 50000, None)).unwrap();
+        engine.add_order(create_order(OrderSide::Buy, OrderType::Limit, 50000, None)).unwrap();
+        engine
+            .add_order(create_order(OrderSide::Sell, OrderType::StopLoss, 45000, Some(46000)))
+            .unwrap();
+
+        let snapshot = engine.snapshot();
+        assert_eq!(snapshot.sequence, 7);
+        assert_eq!(snapshot.bids.len(), 3);
+        assert_eq!(snapshot.asks.len(), 1);
+        assert_eq!(snapshot.triggers.len(), 1);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let decoded: EngineSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let mut restored = MatchingEngine::restore(decoded).unwrap();
+        assert_eq!(restored.sequence(), 7);
+        assert_eq!(restored.trade_sequence(), 1);
+        assert_eq!(restored.last_trade_price, Some(Decimal::new(50000, 0)));
+        assert_eq!(restored.pending_trigger_count(), 1);
+        let resnapshot = restored.snapshot();
+        assert_eq!(resnapshot.bids, snapshot.bids);
+        assert_eq!(resnapshot.asks, snapshot.asks);
+        assert_eq!(resnapshot.triggers, snapshot.triggers);
+
+        // Time priority survives the round trip
+        let sell = create_order(OrderSide::Sell, OrderType::Limit, 49000, None);
+        let trades = restored.add_order(sell).unwrap().trades;
+        assert_eq!(trades[0].maker_order_id, first_bid_id);
+    }
+
+    /// 测试：拒绝交易对或方向不一致的快照
+    #[test]
+    fn test_restore_rejects_inconsistent_snapshot() {
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        engine.add_order(create_order(OrderSide::Buy, OrderType::Limit, 49000, None)).unwrap();
+
+        let mut wrong_symbol = engine.snapshot();
+        wrong_symbol.symbol = "ETHUSDT".to_string();
+        assert!(MatchingEngine::restore(wrong_symbol).is_err());
+
+        let mut wrong_side = engine.snapshot();
+        wrong_side.asks = std::mem::take(&mut wrong_side.bids);
+        assert!(MatchingEngine::restore(wrong_side).is_err());
+    }
+}
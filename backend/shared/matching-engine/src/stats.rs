@@ -0,0 +1,230 @@
+//! Rolling market statistics
+//!
+//! `MarketStats` accumulates trades over a sliding window (24 hours by
+//! default) and produces the ticker figures: open, last, high, low, volume
+//! and the volume- and time-weighted average prices. Volume and notional
+//! are kept as running sums and high/low as monotonic queues, so recording
+//! a trade is amortised O(1) however busy the window is.
+
+use chrono::{DateTime, Utc};
+use flowex_types::Ticker;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Default statistics window
+pub const DEFAULT_STATS_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Trade as seen by the statistics window
+#[derive(Debug, Clone, Copy)]
+struct Print {
+    at: DateTime<Utc>,
+    price: Decimal,
+    quantity: Decimal,
+}
+
+/// Sliding-window trade statistics for one symbol
+#[derive(Debug, Clone)]
+pub struct MarketStats {
+    window: Duration,
+    prints: VecDeque<Print>,
+    /// Candidate highs, prices strictly decreasing from the front
+    highs: VecDeque<Print>,
+    /// Candidate lows, prices strictly increasing from the front
+    lows: VecDeque<Print>,
+    volume: Decimal,
+    notional: Decimal,
+    last_price: Option<Decimal>,
+}
+
+impl Default for MarketStats {
+    fn default() -> Self {
+        Self::new(DEFAULT_STATS_WINDOW)
+    }
+}
+
+impl MarketStats {
+    /// Create an empty accumulator over `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            prints: VecDeque::new(),
+            highs: VecDeque::new(),
+            lows: VecDeque::new(),
+            volume: Decimal::ZERO,
+            notional: Decimal::ZERO,
+            last_price: None,
+        }
+    }
+
+    /// Statistics window
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Record a trade; trades must arrive in time order
+    pub fn record(&mut self, price: Decimal, quantity: Decimal, at: DateTime<Utc>) {
+        let print = Print { at, price, quantity };
+
+        while self.highs.back().is_some_and(|high| high.price <= price) {
+            self.highs.pop_back();
+        }
+        self.highs.push_back(print);
+        while self.lows.back().is_some_and(|low| low.price >= price) {
+            self.lows.pop_back();
+        }
+        self.lows.push_back(print);
+
+        self.prints.push_back(print);
+        self.volume += quantity;
+        self.notional += price * quantity;
+        self.last_price = Some(price);
+
+        self.evict(at);
+    }
+
+    /// Drop trades that have left the window ending at `now`
+    pub fn evict(&mut self, now: DateTime<Utc>) {
+        let window_start = self.window_start(now);
+
+        while let Some(print) = self.prints.front().filter(|print| print.at < window_start) {
+            self.volume -= print.quantity;
+            self.notional -= print.price * print.quantity;
+            self.prints.pop_front();
+        }
+        while self.highs.front().is_some_and(|high| high.at < window_start) {
+            self.highs.pop_front();
+        }
+        while self.lows.front().is_some_and(|low| low.at < window_start) {
+            self.lows.pop_front();
+        }
+    }
+
+    /// Last traded price, even if it has left the window
+    pub fn last_price(&self) -> Option<Decimal> {
+        self.last_price
+    }
+
+    /// Base volume traded in the window ending at `now`
+    pub fn volume(&self, now: DateTime<Utc>) -> Decimal {
+        self.window_totals(now).0
+    }
+
+    /// Volume-weighted average price over the window ending at `now`
+    pub fn vwap(&self, now: DateTime<Utc>) -> Option<Decimal> {
+        let (volume, notional) = self.window_totals(now);
+        (volume > Decimal::ZERO).then(|| notional / volume)
+    }
+
+    /// Time-weighted average price over the window ending at `now`, each
+    /// price holding until the next trade
+    pub fn twap(&self, now: DateTime<Utc>) -> Option<Decimal> {
+        let window_start = self.window_start(now);
+        let prints: Vec<&Print> = self.prints.iter().filter(|print| print.at >= window_start).collect();
+        let first = prints.first()?;
+        let span = (now - first.at).num_milliseconds();
+        if span <= 0 {
+            return self.last_price;
+        }
+
+        let ends = prints.iter().skip(1).map(|print| print.at).chain(std::iter::once(now));
+        let weighted: Decimal = prints
+            .iter()
+            .zip(ends)
+            .map(|(print, end)| print.price * Decimal::from((end - print.at).num_milliseconds()))
+            .sum();
+        Some(weighted / Decimal::from(span))
+    }
+
+    /// Ticker for the window ending at `now`.
+    ///
+    /// Change is measured against the first trade in the window and
+    /// `change_percent` is a percentage. With no trade in the window the
+    /// ticker shows the last price unchanged. `None` until the first trade.
+    pub fn ticker(&self, symbol: &str, now: DateTime<Utc>) -> Option<Ticker> {
+        let last_price = self.last_price?;
+        let window_start = self.window_start(now);
+        let in_window = |print: &&Print| print.at >= window_start;
+
+        let open = self.prints.iter().find(in_window).map_or(last_price, |print| print.price);
+        let change = last_price - open;
+        let change_percent = if open.is_zero() {
+            Decimal::ZERO
+        } else {
+            change / open * Decimal::ONE_HUNDRED
+        };
+
+        Some(Ticker {
+            symbol: symbol.to_string(),
+            price: last_price,
+            change,
+            change_percent,
+            high: self.highs.iter().find(in_window).map_or(last_price, |high| high.price),
+            low: self.lows.iter().find(in_window).map_or(last_price, |low| low.price),
+            volume: self.volume(now),
+            vwap: self.vwap(now),
+            timestamp: now,
+        })
+    }
+
+    /// Volume and notional in the window ending at `now`, discounting trades
+    /// not yet evicted
+    fn window_totals(&self, now: DateTime<Utc>) -> (Decimal, Decimal) {
+        let window_start = self.window_start(now);
+        self.prints
+            .iter()
+            .take_while(|print| print.at < window_start)
+            .fold((self.volume, self.notional), |(volume, notional), print| {
+                (volume - print.quantity, notional - print.price * print.quantity)
+            })
+    }
+
+    fn window_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        chrono::Duration::from_std(self.window)
+            .ok()
+            .and_then(|window| now.checked_sub_signed(window))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试：滚动窗口内的最高、最低、成交量与均价
+    #[test]
+    fn test_rolling_window() {
+        let mut stats = MarketStats::new(Duration::from_secs(60));
+        let start = Utc::now();
+        assert!(stats.ticker("BTCUSDT", start).is_none());
+
+        stats.record(Decimal::new(100, 0), Decimal::new(2, 0), start);
+        stats.record(Decimal::new(110, 0), Decimal::ONE, start + chrono::Duration::seconds(10));
+        stats.record(Decimal::new(90, 0), Decimal::ONE, start + chrono::Duration::seconds(30));
+
+        let ticker = stats.ticker("BTCUSDT", start + chrono::Duration::seconds(30)).unwrap();
+        assert_eq!(ticker.price, Decimal::new(90, 0));
+        assert_eq!(ticker.high, Decimal::new(110, 0));
+        assert_eq!(ticker.low, Decimal::new(90, 0));
+        assert_eq!(ticker.volume, Decimal::new(4, 0));
+        assert_eq!(ticker.change, Decimal::new(-10, 0));
+        assert_eq!(ticker.change_percent, Decimal::new(-10, 0));
+        assert_eq!(ticker.vwap, Some(Decimal::new(100, 0)));
+
+        // 100 held 10s, 110 held 20s, 90 held 10s
+        let twap = stats.twap(start + chrono::Duration::seconds(40)).unwrap();
+        assert_eq!(twap, Decimal::new(4100, 0) / Decimal::new(40, 0));
+
+        // The first two prints leave the window
+        let ticker = stats.ticker("BTCUSDT", start + chrono::Duration::seconds(80)).unwrap();
+        assert_eq!(ticker.high, Decimal::new(90, 0));
+        assert_eq!(ticker.volume, Decimal::ONE);
+        assert_eq!(ticker.change, Decimal::ZERO);
+
+        stats.evict(start + chrono::Duration::seconds(200));
+        let ticker = stats.ticker("BTCUSDT", start + chrono::Duration::seconds(200)).unwrap();
+        assert_eq!(ticker.price, Decimal::new(90, 0));
+        assert_eq!(ticker.volume, Decimal::ZERO);
+        assert!(ticker.vwap.is_none());
+    }
+}
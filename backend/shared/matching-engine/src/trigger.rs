@@ -0,0 +1,208 @@
+//! Stop-loss / take-profit trigger book
+//!
+//! Holds conditional orders keyed by trigger price until the last trade
+//! price crosses their trigger. Activated orders are converted into plain
+//! limit orders (when they carry a limit price) or market orders and are
+//! then matched like any other incoming order.
+
+use flowex_types::{Order, OrderSide, OrderType};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, VecDeque};
+use tracing::info;
+use uuid::Uuid;
+
+/// Price movement that fires a trigger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerDirection {
+    /// Fires when the last price rises to or above the trigger
+    Rising,
+    /// Fires when the last price falls to or below the trigger
+    Falling,
+}
+
+impl TriggerDirection {
+    /// Direction for a conditional order, `None` for plain orders
+    pub fn for_order(order: &Order) -> Option<Self> {
+        match (&order.order_type, &order.side) {
+            (OrderType::StopLoss, OrderSide::Sell) | (OrderType::TakeProfit, OrderSide::Buy) => {
+                Some(TriggerDirection::Falling)
+            }
+            (OrderType::StopLoss, OrderSide::Buy) | (OrderType::TakeProfit, OrderSide::Sell) => {
+                Some(TriggerDirection::Rising)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a last trade price fires a trigger at `stop_price`
+    pub fn is_triggered(&self, stop_price: Decimal, last_price: Decimal) -> bool {
+        match self {
+            TriggerDirection::Rising => last_price >= stop_price,
+            TriggerDirection::Falling => last_price <= stop_price,
+        }
+    }
+}
+
+/// Conditional orders waiting for their trigger price
+#[derive(Debug, Clone, Default)]
+pub struct TriggerBook {
+    rising: BTreeMap<Decimal, VecDeque<Order>>,
+    falling: BTreeMap<Decimal, VecDeque<Order>>,
+}
+
+impl TriggerBook {
+    /// Create an empty trigger book
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Park a conditional order until its trigger price is crossed
+    pub fn insert(&mut self, order: Order) {
+        let (Some(direction), Some(stop_price)) = (TriggerDirection::for_order(&order), order.stop_price) else {
+            return;
+        };
+
+        self.side_mut(direction)
+            .entry(stop_price)
+            .or_default()
+            .push_back(order);
+    }
+
+    /// Remove a parked order
+    pub fn remove(&mut self, order_id: Uuid) -> Option<Order> {
+        for book in [&mut self.rising, &mut self.falling] {
+            let found = book.iter_mut().find_map(|(price, orders)| {
+                orders
+                    .iter()
+                    .position(|o| o.id == order_id)
+                    .map(|pos| (*price, orders.remove(pos)))
+            });
+
+            if let Some((price, order)) = found {
+                if book.get(&price).is_some_and(|orders| orders.is_empty()) {
+                    book.remove(&price);
+                }
+                return order;
+            }
+        }
+        None
+    }
+
+    /// Take every order triggered by `last_price`, nearest trigger first
+    pub fn take_triggered(&mut self, last_price: Decimal) -> Vec<Order> {
+        let rising: Vec<Decimal> = self.rising.range(..=last_price).map(|(p, _)| *p).collect();
+        let falling: Vec<Decimal> = self.falling.range(last_price..).rev().map(|(p, _)| *p).collect();
+
+        let mut triggered = Vec::new();
+        for price in rising {
+            triggered.extend(self.rising.remove(&price).unwrap_or_default());
+        }
+        for price in falling {
+            triggered.extend(self.falling.remove(&price).unwrap_or_default());
+        }
+        triggered
+    }
+
+    /// Remove every parked order matching `predicate`
+    pub fn take_where(&mut self, predicate: impl Fn(&Order) -> bool) -> Vec<Order> {
+        let mut taken = Vec::new();
+        for book in [&mut self.rising, &mut self.falling] {
+            for orders in book.values_mut() {
+                let (matched, kept): (VecDeque<Order>, VecDeque<Order>) = orders.drain(..).partition(|o| predicate(o));
+                *orders = kept;
+                taken.extend(matched);
+            }
+            book.retain(|_, orders| !orders.is_empty());
+        }
+        taken
+    }
+
+    /// Iterate parked orders, rising triggers first, each in trigger then arrival order
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.rising.values().chain(self.falling.values()).flatten()
+    }
+
+    /// Number of parked orders
+    pub fn len(&self) -> usize {
+        self.rising.values().chain(self.falling.values()).map(VecDeque::len).sum()
+    }
+
+    /// Whether no orders are parked
+    pub fn is_empty(&self) -> bool {
+        self.rising.is_empty() && self.falling.is_empty()
+    }
+
+    fn side_mut(&mut self, direction: TriggerDirection) -> &mut BTreeMap<Decimal, VecDeque<Order>> {
+        match direction {
+            TriggerDirection::Rising => &mut self.rising,
+            TriggerDirection::Falling => &mut self.falling,
+        }
+    }
+}
+
+/// Convert a triggered conditional order into a limit or market order
+pub fn activate(mut order: Order) -> Order {
+    order.order_type = if order.price.is_some() {
+        OrderType::Limit
+    } else {
+        OrderType::Market
+    };
+
+    info!(
+        "Activated conditional order {} at trigger {:?} as {:?}",
+        order.id, order.stop_price, order.order_type
+    );
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+
+    fn create_stop_order(side: OrderSide, order_type: OrderType, stop_price: i64) -> Order {
+        Order {
+            order_type,
+            price: None,
+            stop_price: Some(Decimal::from(stop_price)),
+            ..create_limit_order(side, 0, 1)
+        }
+    }
+
+    /// 测试：触发方向判定
+    #[test]
+    fn test_trigger_direction() {
+        let stop_sell = create_stop_order(OrderSide::Sell, OrderType::StopLoss, 100);
+        let stop_buy = create_stop_order(OrderSide::Buy, OrderType::StopLoss, 100);
+        let take_profit_sell = create_stop_order(OrderSide::Sell, OrderType::TakeProfit, 100);
+
+        assert_eq!(TriggerDirection::for_order(&stop_sell), Some(TriggerDirection::Falling));
+        assert_eq!(TriggerDirection::for_order(&stop_buy), Some(TriggerDirection::Rising));
+        assert_eq!(TriggerDirection::for_order(&take_profit_sell), Some(TriggerDirection::Rising));
+
+        assert!(TriggerDirection::Falling.is_triggered(Decimal::new(100, 0), Decimal::new(99, 0)));
+        assert!(!TriggerDirection::Falling.is_triggered(Decimal::new(100, 0), Decimal::new(101, 0)));
+        assert!(TriggerDirection::Rising.is_triggered(Decimal::new(100, 0), Decimal::new(100, 0)));
+    }
+
+    /// 测试：按价格取出已触发订单，就近优先
+    #[test]
+    fn test_take_triggered() {
+        let mut book = TriggerBook::new();
+        book.insert(create_stop_order(OrderSide::Sell, OrderType::StopLoss, 95));
+        book.insert(create_stop_order(OrderSide::Sell, OrderType::StopLoss, 98));
+        book.insert(create_stop_order(OrderSide::Buy, OrderType::StopLoss, 105));
+        assert_eq!(book.len(), 3);
+
+        assert!(book.take_triggered(Decimal::new(100, 0)).is_empty());
+
+        let triggered = book.take_triggered(Decimal::new(94, 0));
+        assert_eq!(triggered.len(), 2);
+        assert_eq!(triggered[0].stop_price, Some(Decimal::new(98, 0)));
+        assert_eq!(triggered[1].stop_price, Some(Decimal::new(95, 0)));
+        assert_eq!(book.len(), 1);
+
+        let activated = activate(triggered[0].clone());
+        assert_eq!(activated.order_type, OrderType::Market);
+    }
+}
@@ -3,7 +3,8 @@
 //! Comprehensive metrics collection and monitoring for FlowEx services.
 //! Provides Prometheus-compatible metrics, custom business metrics, and health monitoring.
 
-use metrics::{counter, gauge, histogram, describe_counter, describe_gauge, describe_histogram};
+use metrics::{counter, gauge, histogram, describe_counter, describe_gauge, describe_histogram, Label};
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder};
 use std::time::{Instant, Duration, SystemTime, UNIX_EPOCH};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -11,8 +12,10 @@ use tokio::sync::RwLock;
 use serde::{Serialize, Deserialize};
 use tracing::{info, error, debug};
 
+pub use metrics_exporter_prometheus::PrometheusHandle;
+
 /// Enterprise metrics collector for FlowEx services
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct MetricsCollector {
     start_time: Instant,
     business_metrics: Arc<RwLock<HashMap<String, f64>>>,
@@ -46,13 +49,37 @@ impl MetricsCollector {
         // Trading metrics
         describe_counter!("flowex_orders_total", "Total number of orders");
         describe_counter!("flowex_trades_total", "Total number of trades");
-        describe_counter!("flowex_trade_volume_total", "Total trading volume");
+        describe_gauge!("flowex_trade_volume_total", "Total trading volume");
         describe_gauge!("flowex_order_book_depth", "Order book depth");
 
+        // Matching engine metrics
+        describe_histogram!("flowex_engine_order_add_duration_seconds", "Time to apply an order submission");
+        describe_histogram!("flowex_engine_match_duration_seconds", "Time spent matching an order against the book");
+        describe_counter!("flowex_engine_trades_total", "Trades executed by the matching engine");
+        describe_gauge!("flowex_engine_book_levels", "Price levels on each side of the book");
+        describe_gauge!("flowex_engine_resting_orders", "Orders resting on the book");
+
         // WebSocket metrics
         describe_gauge!("flowex_websocket_connections", "Number of active WebSocket connections");
         describe_counter!("flowex_websocket_messages_sent_total", "Total WebSocket messages sent");
         describe_counter!("flowex_websocket_messages_received_total", "Total WebSocket messages received");
+        describe_counter!("flowex_websocket_messages_dropped_total", "WebSocket messages dropped for slow or rate limited clients");
+        describe_histogram!("flowex_websocket_broadcast_lag_messages", "Messages a connection fell behind the broadcast by");
+        describe_gauge!("flowex_websocket_subscriptions", "WebSocket subscriptions per symbol");
+
+        // Rate limit metrics
+        describe_counter!("flowex_rate_limit_violations_total", "Requests refused for exceeding a rate limit");
+
+        // Risk metrics
+        describe_counter!("flowex_risk_checks_total", "Pre-trade risk checks run, by check and result");
+        describe_histogram!("flowex_risk_check_duration_seconds", "Time to run an order through the pre-trade checks");
+
+        // Wallet metrics
+        describe_gauge!("flowex_wallet_liabilities", "Funds the wallet owes its users, by currency");
+        describe_counter!("flowex_wallet_reconciliations_total", "Ledger reconciliations run, by result");
+        describe_gauge!("flowex_wallet_reconciliation_mismatches", "Mismatches the last ledger reconciliation found");
+        describe_gauge!("flowex_wallet_hot_float", "Funds held in the hot wallet, by currency");
+        describe_counter!("flowex_wallet_float_alerts_total", "Hot wallet float checks outside their thresholds, by currency and action");
 
         // Cache metrics
         describe_counter!("flowex_cache_hits_total", "Total cache hits");
@@ -118,7 +145,7 @@ impl MetricsCollector {
 
     pub fn record_trade(&self, symbol: &str, volume: f64, price: f64) {
         counter!("flowex_trades_total", "symbol" => symbol.to_string()).increment(1);
-        counter!("flowex_trade_volume_total", "symbol" => symbol.to_string()).increment(volume);
+        gauge!("flowex_trade_volume_total", "symbol" => symbol.to_string()).increment(volume);
     }
 
     pub fn record_order_book_depth(&self, symbol: &str, bid_depth: u32, ask_depth: u32) {
@@ -132,6 +159,30 @@ impl MetricsCollector {
             .set(ask_depth as f64);
     }
 
+    // Matching Engine Metrics
+    pub fn record_engine_order_add(&self, symbol: &str, duration: Duration) {
+        histogram!("flowex_engine_order_add_duration_seconds", "symbol" => symbol.to_string())
+            .record(duration.as_secs_f64());
+    }
+
+    pub fn record_engine_match(&self, symbol: &str, duration: Duration, trades: u64) {
+        histogram!("flowex_engine_match_duration_seconds", "symbol" => symbol.to_string())
+            .record(duration.as_secs_f64());
+        counter!("flowex_engine_trades_total", "symbol" => symbol.to_string()).increment(trades);
+    }
+
+    pub fn record_engine_book(&self, symbol: &str, bid_levels: usize, ask_levels: usize, resting_orders: usize) {
+        gauge!("flowex_engine_book_levels",
+               "symbol" => symbol.to_string(),
+               "side" => "bid".to_string())
+            .set(bid_levels as f64);
+        gauge!("flowex_engine_book_levels",
+               "symbol" => symbol.to_string(),
+               "side" => "ask".to_string())
+            .set(ask_levels as f64);
+        gauge!("flowex_engine_resting_orders", "symbol" => symbol.to_string()).set(resting_orders as f64);
+    }
+
     // WebSocket Metrics
     pub fn record_websocket_connections(&self, count: u32) {
         gauge!("flowex_websocket_connections").set(count as f64);
@@ -149,6 +200,63 @@ impl MetricsCollector {
             .increment(1);
     }
 
+    pub fn record_websocket_messages_dropped(&self, reason: &str, count: u64) {
+        counter!("flowex_websocket_messages_dropped_total",
+                "reason" => reason.to_string())
+            .increment(count);
+    }
+
+    pub fn record_websocket_broadcast_lag(&self, skipped: u64) {
+        histogram!("flowex_websocket_broadcast_lag_messages").record(skipped as f64);
+    }
+
+    pub fn record_websocket_subscriptions(&self, symbol: &str, count: usize) {
+        gauge!("flowex_websocket_subscriptions", "symbol" => symbol.to_string()).set(count as f64);
+    }
+
+    // Rate Limit Metrics
+    pub fn record_rate_limit_violation(&self, service: &str, scope: &str) {
+        counter!("flowex_rate_limit_violations_total",
+                "service" => service.to_string(),
+                "scope" => scope.to_string())
+            .increment(1);
+    }
+
+    // Risk Metrics
+    pub fn record_risk_check(&self, check: &str, passed: bool) {
+        counter!("flowex_risk_checks_total",
+                "check" => check.to_string(),
+                "result" => if passed { "pass" } else { "reject" })
+            .increment(1);
+    }
+
+    pub fn record_risk_pipeline_duration(&self, duration: Duration) {
+        histogram!("flowex_risk_check_duration_seconds").record(duration.as_secs_f64());
+    }
+
+    // Wallet Metrics
+    pub fn record_wallet_liabilities(&self, currency: &str, amount: f64) {
+        gauge!("flowex_wallet_liabilities", "currency" => currency.to_string()).set(amount);
+    }
+
+    pub fn record_reconciliation(&self, mismatches: usize) {
+        counter!("flowex_wallet_reconciliations_total",
+                "result" => if mismatches == 0 { "balanced" } else { "mismatch" })
+            .increment(1);
+        gauge!("flowex_wallet_reconciliation_mismatches").set(mismatches as f64);
+    }
+
+    pub fn record_hot_wallet_float(&self, currency: &str, amount: f64) {
+        gauge!("flowex_wallet_hot_float", "currency" => currency.to_string()).set(amount);
+    }
+
+    pub fn record_float_alert(&self, currency: &str, action: &str) {
+        counter!("flowex_wallet_float_alerts_total",
+                "currency" => currency.to_string(),
+                "action" => action.to_string())
+            .increment(1);
+    }
+
     // Cache Metrics
     pub fn record_cache_hit(&self, cache_type: &str) {
         counter!("flowex_cache_hits_total", "type" => cache_type.to_string()).increment(1);
@@ -177,7 +285,6 @@ impl MetricsCollector {
         let uptime = self.start_time.elapsed().as_secs() as f64;
         gauge!("flowex_uptime_seconds").set(uptime);
     }
-}
 
     // Business Metrics
     pub async fn set_business_metric(&self, name: &str, value: f64) {
@@ -277,13 +384,12 @@ impl MetricsTimer {
 
     pub fn record_and_finish(self, metric_name: &str, labels: Vec<(&str, String)>) {
         let duration = self.elapsed();
-        let mut histogram_builder = histogram!(metric_name);
-
-        for (key, value) in labels {
-            histogram_builder = histogram_builder.with_label(key, value);
-        }
+        let labels: Vec<Label> = labels
+            .into_iter()
+            .map(|(key, value)| Label::new(key.to_string(), value))
+            .collect();
 
-        histogram_builder.record(duration.as_secs_f64());
+        histogram!(metric_name.to_string(), labels).record(duration.as_secs_f64());
     }
 }
 
@@ -327,6 +433,12 @@ impl Default for MetricsCollector {
 // Legacy compatibility
 pub type MetricsRecorder = MetricsCollector;
 
+/// Install a global Prometheus recorder for the metrics above. The handle
+/// renders the scrape output, e.g. for a `/metrics` endpoint.
+pub fn install_prometheus_recorder() -> Result<PrometheusHandle, BuildError> {
+    PrometheusBuilder::new().install_recorder()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -416,6 +528,11 @@ mod tests {
         // 记录订单簿深度
         collector.record_order_book_depth("BTCUSDT", 25, 30);
 
+        // 记录撮合引擎指标
+        collector.record_engine_order_add("BTCUSDT", Duration::from_micros(12));
+        collector.record_engine_match("BTCUSDT", Duration::from_micros(8), 3);
+        collector.record_engine_book("BTCUSDT", 120, 98, 1500);
+
         // 验证记录成功
         assert!(true);
     }
@@ -435,6 +552,9 @@ mod tests {
         collector.record_websocket_message_sent("order_update");
         collector.record_websocket_message_received("subscribe");
         collector.record_websocket_message_received("unsubscribe");
+        collector.record_websocket_messages_dropped("queue_full", 3);
+        collector.record_websocket_broadcast_lag(120);
+        collector.record_websocket_subscriptions("BTCUSDT", 42);
 
         // 验证记录成功
         assert!(true);
@@ -0,0 +1,178 @@
+//! Account states of token holders
+//!
+//! A token carries its account's status and token version as they were
+//! when it was signed. An account frozen since, or whose tokens were all
+//! logged out by raising its version, must not go on acting with the
+//! tokens it still holds, so `AccountStates` asks the auth service for the
+//! account's current state and refuses tokens of frozen accounts and those
+//! signed with an older version. The answer is remembered for
+//! `STATE_CACHE_TTL`, so a freeze or forced logout takes effect within
+//! that time.
+//!
+//! When the auth service cannot be reached the token's signed claims
+//! stand, and nothing is remembered: its tokens are short-lived, and every
+//! service refusing all of them while it is down would stop the exchange.
+//!
+//! Services find the auth service at `AUTH_SERVICE_URL`.
+
+use axum::http::StatusCode;
+use flowex_types::{AccountClaims, AccountStatus, ApiResponse};
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
+
+/// How long an account's state is remembered
+const STATE_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Timeout of requests to the auth service
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// States by account with when they were fetched, `None` for accounts the
+/// auth service does not know
+type StateCache = HashMap<Uuid, (Option<AccountClaims>, Instant)>;
+
+/// Looks up the current state of accounts at the auth service
+#[derive(Clone)]
+pub struct AccountStates {
+    http: reqwest::Client,
+    base_url: String,
+    cache: Arc<RwLock<StateCache>>,
+}
+
+impl AccountStates {
+    /// States of accounts of the auth service at `base_url`
+    pub fn new(base_url: &str) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// States of the process, looked up where the environment says
+    pub fn from_env() -> &'static AccountStates {
+        static STATES: OnceLock<AccountStates> = OnceLock::new();
+        STATES.get_or_init(|| {
+            let auth_service_url =
+                std::env::var("AUTH_SERVICE_URL").unwrap_or_else(|_| "http://localhost:8001".to_string());
+            AccountStates::new(&auth_service_url)
+        })
+    }
+
+    /// Check that a token of `user_id` signed with `claims` may still be
+    /// used: 403 if the account is frozen, 401 if it is unknown or the
+    /// token's version was logged out
+    pub async fn check(&self, user_id: Uuid, claims: &AccountClaims) -> Result<(), StatusCode> {
+        if claims.account_status == AccountStatus::Frozen {
+            warn!("Token of frozen account {}", user_id);
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let current = match self.current(user_id).await {
+            Ok(Some(current)) => current,
+            Ok(None) => {
+                warn!("Token of unknown account {}", user_id);
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+            Err(e) => {
+                warn!("Account state lookup for {} failed, trusting the token: {}", user_id, e);
+                return Ok(());
+            }
+        };
+
+        if current.account_status == AccountStatus::Frozen {
+            warn!("Token of account {} frozen since it was signed", user_id);
+            return Err(StatusCode::FORBIDDEN);
+        }
+        if claims.token_version < current.token_version {
+            warn!(
+                "Token of account {} signed at version {}, now {}",
+                user_id, claims.token_version, current.token_version
+            );
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(())
+    }
+
+    /// The current state of `user_id`; `None` if the auth service does not
+    /// know them
+    async fn current(&self, user_id: Uuid) -> Result<Option<AccountClaims>, String> {
+        if let Some((state, fetched_at)) = self.cache.read().await.get(&user_id) {
+            if fetched_at.elapsed() < STATE_CACHE_TTL {
+                return Ok(state.clone());
+            }
+        }
+
+        let url = format!("{}/internal/accounts/{}", self.base_url, user_id);
+        let response = self.http.get(&url).send().await.map_err(|e| e.to_string())?;
+        let state = match response.status() {
+            reqwest::StatusCode::NOT_FOUND => None,
+            status if status.is_success() => {
+                let body: ApiResponse<AccountClaims> = response.json().await.map_err(|e| e.to_string())?;
+                body.data
+            }
+            status => return Err(format!("answered {}", status)),
+        };
+
+        let mut cache = self.cache.write().await;
+        cache.retain(|_, (_, fetched_at)| fetched_at.elapsed() < STATE_CACHE_TTL);
+        cache.insert(user_id, (state.clone(), Instant::now()));
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Path, http::StatusCode as HttpStatus, routing::get, Json, Router};
+
+    /// 测试：冻结账户和版本过期的令牌被拒绝，未知账户被拒绝，认证服务不可达时以令牌声明为准
+    #[tokio::test]
+    async fn test_check_account_state() {
+        let (active, frozen) = (Uuid::new_v4(), Uuid::new_v4());
+        let app = Router::new().route(
+            "/internal/accounts/:user_id",
+            get(move |Path(user_id): Path<Uuid>| async move {
+                let account_status = if user_id == frozen { AccountStatus::Frozen } else { AccountStatus::Active };
+                if user_id != active && user_id != frozen {
+                    return Err(HttpStatus::NOT_FOUND);
+                }
+                Ok(Json(ApiResponse::success(AccountClaims {
+                    account_status,
+                    kyc_tier: None,
+                    token_version: 2,
+                })))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let states = AccountStates::new(&format!("http://{}/", address));
+        let claims = |token_version: u64| AccountClaims {
+            token_version,
+            ..AccountClaims::default()
+        };
+        states.check(active, &claims(2)).await.unwrap();
+        assert_eq!(states.check(active, &claims(1)).await, Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(states.check(frozen, &claims(2)).await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(states.check(Uuid::new_v4(), &claims(2)).await, Err(StatusCode::UNAUTHORIZED));
+        let signed_frozen = AccountClaims {
+            account_status: AccountStatus::Frozen,
+            ..claims(2)
+        };
+        assert_eq!(states.check(active, &signed_frozen).await, Err(StatusCode::FORBIDDEN));
+
+        let unreachable = AccountStates::new("http://127.0.0.1:1");
+        unreachable.check(active, &claims(0)).await.unwrap();
+        assert!(unreachable.cache.read().await.is_empty());
+    }
+}
@@ -2,6 +2,10 @@
 //!
 //! Enterprise-grade JWT authentication and authorization middleware
 //! with comprehensive security features and audit logging.
+//!
+//! A token is only accepted while its account is not frozen and its token
+//! version has not been logged out (see `accounts`), and only if the auth
+//! service issued it for FlowEx users.
 
 use axum::{
     extract::{Request, State},
@@ -10,11 +14,20 @@ use axum::{
     response::Response,
 };
 use flowex_types::{AuthContext, JwtClaims, Permission, Role};
-use jsonwebtoken::{decode, DecodingKey, Validation, Algorithm};
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation, Algorithm};
 use std::collections::HashSet;
 use tracing::{info, warn, error, debug};
 use uuid::Uuid;
 
+use crate::accounts::AccountStates;
+use crate::jwks::Jwks;
+
+/// Issuer of the tokens accepted
+pub const TOKEN_ISSUER: &str = "flowex";
+
+/// Audience of the tokens accepted
+pub const TOKEN_AUDIENCE: &str = "flowex-users";
+
 /// JWT authentication middleware
 pub async fn jwt_auth_middleware(
     headers: HeaderMap,
@@ -27,15 +40,19 @@ pub async fn jwt_auth_middleware(
     let token = extract_jwt_token(&headers)?;
     
     // Validate and decode JWT token
-    let claims = validate_jwt_token(&token)?;
+    let claims = validate_jwt_token(&token).await?;
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| {
+            error!("Invalid user ID in JWT claims: {}", claims.sub);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    // Refuse tokens of frozen accounts and logged out token versions
+    AccountStates::from_env().check(user_id, &claims.account).await?;
     
     // Create authentication context
     let auth_context = AuthContext {
-        user_id: Uuid::parse_str(&claims.sub)
-            .map_err(|_| {
-                error!("Invalid user ID in JWT claims: {}", claims.sub);
-                StatusCode::UNAUTHORIZED
-            })?,
+        user_id,
         email: claims.email.clone(),
         roles: claims.roles.clone(),
         permissions: claims.permissions.clone(),
@@ -89,14 +106,51 @@ fn extract_jwt_token(headers: &HeaderMap) -> Result<String, StatusCode> {
 }
 
 /// Validate JWT token and extract claims
-fn validate_jwt_token(token: &str) -> Result<JwtClaims, StatusCode> {
-    // In production, this should come from environment or secure storage
-    let jwt_secret = std::env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "flowex_enterprise_secret_key_2024".to_string());
-    
-    let decoding_key = DecodingKey::from_secret(jwt_secret.as_ref());
+///
+/// RS256 tokens are verified with the auth service's published key their
+/// header names (see `jwks`). HS256 ones are only accepted where no JWKS
+/// endpoint is configured, with the shared secret in `JWT_SECRET`; without
+/// it they are refused.
+async fn validate_jwt_token(token: &str) -> Result<JwtClaims, StatusCode> {
+    let header = decode_header(token).map_err(|e| {
+        warn!("JWT header is invalid: {}", e);
+        StatusCode::UNAUTHORIZED
+    })?;
+    let decoding_key = match header.alg {
+        Algorithm::RS256 => {
+            let kid = header.kid.ok_or_else(|| {
+                warn!("RS256 token names no key");
+                StatusCode::UNAUTHORIZED
+            })?;
+            Jwks::from_env().key(&kid).await.ok_or_else(|| {
+                warn!("RS256 token signed with unknown key {}", kid);
+                StatusCode::UNAUTHORIZED
+            })?
+        }
+        Algorithm::HS256 => {
+            if std::env::var_os("JWKS_URL").is_some() {
+                warn!("HS256 token refused, tokens are verified against the JWKS");
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+            let jwt_secret = std::env::var("JWT_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty())
+                .ok_or_else(|| {
+                    warn!("HS256 token refused, JWT_SECRET is not set");
+                    StatusCode::UNAUTHORIZED
+                })?;
+            DecodingKey::from_secret(jwt_secret.as_ref())
+        }
+        alg => {
+            warn!("JWT signed with unsupported algorithm {:?}", alg);
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    };
     
-    let mut validation = Validation::new(Algorithm::HS256);
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[TOKEN_ISSUER]);
+    validation.set_audience(&[TOKEN_AUDIENCE]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
     validation.validate_exp = true;
     validation.validate_nbf = true;
     validation.leeway = 60; // 60 seconds leeway for clock skew
@@ -245,29 +299,48 @@ mod tests {
     use super::*;
     use jsonwebtoken::{encode, EncodingKey, Header};
     
-    #[test]
-    fn test_jwt_token_validation() {
+    /// 测试：HS256令牌仅在配置了JWT_SECRET且未配置JWKS时接受，签发者与受众必须匹配
+    #[tokio::test]
+    async fn test_jwt_token_validation() {
         let claims = JwtClaims {
             sub: Uuid::new_v4().to_string(),
             email: "test@flowex.com".to_string(),
+            iss: TOKEN_ISSUER.to_string(),
+            aud: TOKEN_AUDIENCE.to_string(),
             exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
             iat: chrono::Utc::now().timestamp() as usize,
             jti: Uuid::new_v4().to_string(),
             roles: vec!["trader".to_string()],
             permissions: vec!["trading:read".to_string(), "trading:write".to_string()],
+            account: Default::default(),
         };
-        
-        let secret = "test_secret";
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret.as_ref()),
-        ).unwrap();
-        
-        // This test would need to set the JWT_SECRET environment variable
-        // or modify the validation function to accept a secret parameter
-        // For now, it's a placeholder for the test structure
-        assert!(!token.is_empty());
+        let sign = |claims: &JwtClaims| {
+            encode(&Header::default(), claims, &EncodingKey::from_secret("test_secret".as_ref())).unwrap()
+        };
+        let token = sign(&claims);
+
+        // 未配置密钥时拒绝，不回退到默认密钥
+        std::env::remove_var("JWKS_URL");
+        std::env::remove_var("JWT_SECRET");
+        assert_eq!(validate_jwt_token(&token).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+        std::env::set_var("JWT_SECRET", "test_secret");
+        assert_eq!(validate_jwt_token(&token).await.unwrap().sub, claims.sub);
+
+        let foreign_issuer = JwtClaims { iss: "elsewhere".to_string(), ..claims.clone() };
+        assert!(validate_jwt_token(&sign(&foreign_issuer)).await.is_err());
+        let foreign_audience = JwtClaims { aud: "flowex-admins".to_string(), ..claims.clone() };
+        assert!(validate_jwt_token(&sign(&foreign_audience)).await.is_err());
+        let mut unaddressed = serde_json::to_value(&claims).unwrap();
+        unaddressed.as_object_mut().unwrap().remove("aud");
+        let unaddressed = encode(&Header::default(), &unaddressed, &EncodingKey::from_secret("test_secret".as_ref())).unwrap();
+        assert!(validate_jwt_token(&unaddressed).await.is_err());
+
+        // 配置JWKS后只接受RS256令牌
+        std::env::set_var("JWKS_URL", "http://localhost:0/.well-known/jwks.json");
+        assert!(validate_jwt_token(&token).await.is_err());
+        std::env::remove_var("JWKS_URL");
+        std::env::remove_var("JWT_SECRET");
     }
     
     #[test]
@@ -0,0 +1,139 @@
+//! Public keys of RS256 tokens
+//!
+//! The auth service signs tokens with RSA keys it rotates, publishing their
+//! public halves at `/.well-known/jwks.json`. `Jwks` fetches that set and
+//! remembers it, fetching it again when a token names a key it does not
+//! know, at most once every `MIN_REFRESH_INTERVAL` so tokens naming made-up
+//! keys cannot flood the auth service.
+//!
+//! Services find the set at `JWKS_URL`, or at the auth service's at
+//! `AUTH_SERVICE_URL` when that is unset.
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::DecodingKey;
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+/// Least time between two fetches of the key set
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Timeout of requests for the key set
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keys fetched with when they were
+#[derive(Default)]
+struct FetchedKeys {
+    keys: HashMap<String, DecodingKey>,
+    fetched_at: Option<Instant>,
+}
+
+/// Keys tokens are verified with, fetched from a JWKS endpoint
+#[derive(Clone)]
+pub struct Jwks {
+    http: reqwest::Client,
+    url: String,
+    fetched: Arc<RwLock<FetchedKeys>>,
+}
+
+impl Jwks {
+    /// Keys published at `url`
+    pub fn new(url: &str) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            http,
+            url: url.to_string(),
+            fetched: Arc::new(RwLock::new(FetchedKeys::default())),
+        }
+    }
+
+    /// Keys of the process, published where the environment says
+    pub fn from_env() -> &'static Jwks {
+        static JWKS: OnceLock<Jwks> = OnceLock::new();
+        JWKS.get_or_init(|| {
+            let url = std::env::var("JWKS_URL").unwrap_or_else(|_| {
+                let auth_service_url =
+                    std::env::var("AUTH_SERVICE_URL").unwrap_or_else(|_| "http://localhost:8001".to_string());
+                format!("{}/.well-known/jwks.json", auth_service_url.trim_end_matches('/'))
+            });
+            Jwks::new(&url)
+        })
+    }
+
+    /// Key `kid` names; `None` if the endpoint does not publish it
+    pub async fn key(&self, kid: &str) -> Option<DecodingKey> {
+        {
+            let fetched = self.fetched.read().await;
+            if let Some(key) = fetched.keys.get(kid) {
+                return Some(key.clone());
+            }
+            if fetched.fetched_at.is_some_and(|at| at.elapsed() < MIN_REFRESH_INTERVAL) {
+                return None;
+            }
+        }
+
+        let mut fetched = self.fetched.write().await;
+        // Another request may have fetched the set meanwhile
+        if !fetched.keys.contains_key(kid)
+            && fetched.fetched_at.is_none_or(|at| at.elapsed() >= MIN_REFRESH_INTERVAL)
+        {
+            fetched.fetched_at = Some(Instant::now());
+            match self.fetch().await {
+                Ok(keys) => fetched.keys = keys,
+                Err(e) => warn!("Failed to fetch the token keys from {}: {}", self.url, e),
+            }
+        }
+        fetched.keys.get(kid).cloned()
+    }
+
+    async fn fetch(&self) -> Result<HashMap<String, DecodingKey>, reqwest::Error> {
+        let set: JwkSet = self.http.get(&self.url).send().await?.error_for_status()?.json().await?;
+        let keys: HashMap<String, DecodingKey> = set
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                DecodingKey::from_jwk(jwk).ok().map(|key| (kid, key))
+            })
+            .collect();
+        debug!("Fetched {} token keys from {}", keys.len(), self.url);
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Json, Router};
+    use flowex_auth::keys::KeyRing;
+
+    /// 测试：从 JWKS 端点获取公钥，轮换后的新密钥在重新获取时可用，未知密钥返回空
+    #[tokio::test]
+    async fn test_fetch_keys_from_jwks_endpoint() {
+        let ring = KeyRing::generate(chrono::Duration::days(7), chrono::Duration::days(30)).unwrap();
+        let served = ring.clone();
+        let app = Router::new().route("/.well-known/jwks.json", get(move || async move { Json(served.jwks()) }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let jwks = Jwks::new(&format!("http://{}/.well-known/jwks.json", address));
+        let first = ring.jwks().keys[0].common.key_id.clone().unwrap();
+        assert!(jwks.key(&first).await.is_some());
+        assert!(jwks.key("unknown").await.is_none());
+
+        // Refetched only once the minimum interval has passed
+        let second = ring.rotate().unwrap();
+        assert!(jwks.key(&second).await.is_none());
+        jwks.fetched.write().await.fetched_at = Some(Instant::now() - MIN_REFRESH_INTERVAL);
+        assert!(jwks.key(&second).await.is_some());
+        assert!(jwks.key(&first).await.is_some());
+    }
+}
@@ -7,7 +7,11 @@ use axum::{extract::Request, middleware::Next, response::Response};
 use tracing::{info, debug, Span};
 use uuid::Uuid;
 
+pub mod accounts;
+pub mod api_key;
 pub mod auth;
+pub mod jwks;
+pub mod service_auth;
 
 #[cfg(test)]
 mod tests {
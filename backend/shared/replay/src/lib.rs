@@ -0,0 +1,373 @@
+//! FlowEx Replay
+//!
+//! Deterministic replay and backtesting harness for the matching engine.
+//! A recording pairs journaled engine commands with the trades the original
+//! run executed; replaying it against the current `MatchingEngine`, at the
+//! commands' journaled times, must yield exactly the same trades, ids and
+//! timestamps included, which makes recordings usable as regression
+//! fixtures for matching changes. The same command stream can also drive a
+//! backtest with a strategy injecting its own orders.
+
+use chrono::{DateTime, Utc};
+use flowex_matching_engine::journal::{read_entries, JournalCommand, JournalEntry};
+use flowex_matching_engine::MatchingEngine;
+use flowex_types::{FlowExError, FlowExResult, OrderSide, Trade};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::Path;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Trade as a replay compares it: everything but the fees, which depend on
+/// the fee schedule configured rather than on matching
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeFingerprint {
+    pub id: Uuid,
+    pub sequence: u64,
+    pub maker_order_id: Uuid,
+    pub taker_order_id: Uuid,
+    pub maker_user_id: Uuid,
+    pub taker_user_id: Uuid,
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub is_buyer_maker: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl From<&Trade> for TradeFingerprint {
+    fn from(trade: &Trade) -> Self {
+        Self {
+            id: trade.id,
+            sequence: trade.sequence,
+            maker_order_id: trade.maker_order_id,
+            taker_order_id: trade.taker_order_id,
+            maker_user_id: trade.maker_user_id,
+            taker_user_id: trade.taker_user_id,
+            side: trade.side.clone(),
+            price: trade.price,
+            quantity: trade.quantity,
+            is_buyer_maker: trade.is_buyer_maker,
+            timestamp: trade.timestamp,
+        }
+    }
+}
+
+/// Journaled command stream and the trades its original run executed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    pub symbol: String,
+    pub entries: Vec<JournalEntry>,
+    pub trades: Vec<TradeFingerprint>,
+}
+
+impl Recording {
+    /// Pair journal entries with the trades the engine executed when it
+    /// first applied them
+    pub fn new(symbol: &str, entries: Vec<JournalEntry>, trades: &[Trade]) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            entries,
+            trades: trades.iter().map(TradeFingerprint::from).collect(),
+        }
+    }
+
+    /// Recording of a matching engine journal file and the trades the
+    /// engine executed while it was written, such as those kept in the
+    /// trade store
+    pub fn from_journal(symbol: &str, path: impl AsRef<Path>, trades: &[Trade]) -> FlowExResult<Self> {
+        Ok(Self::new(symbol, read_entries(path)?, trades))
+    }
+
+    /// Record the trades the current engine executes for `entries`, to save
+    /// as a new fixture; it only catches changes made after it is saved
+    pub fn capture(symbol: &str, entries: Vec<JournalEntry>) -> Self {
+        let trades = run(symbol, entries.iter().cloned());
+        Self::new(symbol, entries, &trades)
+    }
+
+    /// Load a recording saved with `save`
+    pub fn load(path: impl AsRef<Path>) -> FlowExResult<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| FlowExError::Internal(format!("Failed to read recording {}: {}", path.display(), e)))?;
+        serde_json::from_str(&data)
+            .map_err(|e| FlowExError::Internal(format!("Invalid recording {}: {}", path.display(), e)))
+    }
+
+    /// Save the recording as pretty-printed JSON
+    pub fn save(&self, path: impl AsRef<Path>) -> FlowExResult<()> {
+        let path = path.as_ref();
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|e| FlowExError::Internal(format!("Failed to encode recording: {}", e)))?;
+        std::fs::write(path, data)
+            .map_err(|e| FlowExError::Internal(format!("Failed to write recording {}: {}", path.display(), e)))
+    }
+}
+
+/// Difference between a recorded and a replayed trade
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeMismatch {
+    /// Position in the trade stream
+    pub index: usize,
+    pub expected: Option<TradeFingerprint>,
+    pub actual: Option<TradeFingerprint>,
+}
+
+/// Outcome of replaying a recording
+#[derive(Debug, Clone)]
+pub struct ReplayReport {
+    pub commands: usize,
+    pub trades: Vec<Trade>,
+    pub mismatches: Vec<TradeMismatch>,
+}
+
+impl ReplayReport {
+    /// Whether the replay reproduced the recorded trades exactly
+    pub fn is_identical(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Feed journal entries into a fresh engine, each at its journaled time,
+/// and collect every trade in order.
+///
+/// Rejected commands produce no trades and do not stop the run.
+pub fn run(symbol: &str, entries: impl IntoIterator<Item = JournalEntry>) -> Vec<Trade> {
+    let mut engine = MatchingEngine::new(symbol.to_string());
+    entries
+        .into_iter()
+        .flat_map(|entry| entry.apply(&mut engine).unwrap_or_default())
+        .collect()
+}
+
+/// Replay a recording and compare the trades with the recorded ones
+pub fn replay(recording: &Recording) -> ReplayReport {
+    let trades = run(&recording.symbol, recording.entries.iter().cloned());
+    let actual: Vec<TradeFingerprint> = trades.iter().map(TradeFingerprint::from).collect();
+
+    let mismatches = (0..actual.len().max(recording.trades.len()))
+        .filter_map(|index| {
+            let expected = recording.trades.get(index);
+            let replayed = actual.get(index);
+            (expected != replayed).then(|| TradeMismatch {
+                index,
+                expected: expected.cloned(),
+                actual: replayed.cloned(),
+            })
+        })
+        .collect();
+
+    ReplayReport {
+        commands: recording.entries.len(),
+        trades,
+        mismatches,
+    }
+}
+
+/// Replay a recording and fail if any trade differs
+pub fn assert_identical(recording: &Recording) -> FlowExResult<ReplayReport> {
+    let report = replay(recording);
+
+    if let Some(first) = report.mismatches.first() {
+        warn!(
+            "Replay of {} diverged at trade {}: expected {:?}, got {:?}",
+            recording.symbol, first.index, first.expected, first.actual
+        );
+        return Err(FlowExError::Internal(format!(
+            "Replay diverged from recording at trade {} ({} mismatches)",
+            first.index,
+            report.mismatches.len()
+        )));
+    }
+
+    Ok(report)
+}
+
+/// Trading strategy driven by a backtest
+pub trait Strategy {
+    /// React to the trades produced by the last command.
+    ///
+    /// Returned commands are applied before the next recorded command, and
+    /// the trades they produce are fed back to the strategy as well.
+    fn on_trades(&mut self, trades: &[Trade], engine: &MatchingEngine) -> Vec<JournalCommand>;
+}
+
+/// Aggregate results of a backtest
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BacktestSummary {
+    /// Recorded commands applied
+    pub commands: usize,
+    /// Commands injected by the strategy
+    pub strategy_commands: usize,
+    /// Commands rejected by the engine
+    pub rejected: usize,
+    pub trades: usize,
+    pub volume: Decimal,
+    pub notional: Decimal,
+    pub last_price: Option<Decimal>,
+    /// Volume-weighted average trade price
+    pub vwap: Option<Decimal>,
+}
+
+/// Run a command stream through a fresh engine with a strategy attached
+pub fn backtest<S: Strategy>(
+    symbol: &str,
+    commands: impl IntoIterator<Item = JournalCommand>,
+    strategy: &mut S,
+) -> BacktestSummary {
+    let mut engine = MatchingEngine::new(symbol.to_string());
+    let mut summary = BacktestSummary::default();
+
+    for command in commands {
+        summary.commands += 1;
+        let mut pending = VecDeque::from([command]);
+        let mut from_strategy = false;
+
+        while let Some(command) = pending.pop_front() {
+            if from_strategy {
+                summary.strategy_commands += 1;
+            }
+            from_strategy = true;
+
+            let trades = match command.apply(&mut engine) {
+                Ok(trades) => trades,
+                Err(_) => {
+                    summary.rejected += 1;
+                    continue;
+                }
+            };
+
+            for trade in &trades {
+                summary.trades += 1;
+                summary.volume += trade.quantity;
+                summary.notional += trade.price * trade.quantity;
+                summary.last_price = Some(trade.price);
+            }
+
+            if !trades.is_empty() {
+                pending.extend(strategy.on_trades(&trades, &engine));
+            }
+        }
+    }
+
+    if summary.volume > Decimal::ZERO {
+        summary.vwap = Some(summary.notional / summary.volume);
+    }
+
+    info!(
+        "Backtest of {} finished: {} commands, {} strategy commands, {} trades, volume {}",
+        symbol, summary.commands, summary.strategy_commands, summary.trades, summary.volume
+    );
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::create_limit_order;
+
+    fn new_order(side: OrderSide, price: i64, quantity: i64) -> JournalCommand {
+        JournalCommand::NewOrder {
+            order: create_limit_order(side, price, quantity),
+        }
+    }
+
+    fn sample_commands() -> Vec<JournalCommand> {
+        vec![
+            new_order(OrderSide::Sell, 50100, 2),
+            new_order(OrderSide::Sell, 50000, 1),
+            new_order(OrderSide::Buy, 49900, 1),
+            new_order(OrderSide::Buy, 50100, 2),
+            new_order(OrderSide::Sell, 49800, 3),
+        ]
+    }
+
+    fn sample_entries() -> Vec<JournalEntry> {
+        let start = Utc::now() - chrono::Duration::hours(1);
+        sample_commands()
+            .into_iter()
+            .enumerate()
+            .map(|(index, command)| JournalEntry {
+                sequence: index as u64 + 1,
+                timestamp: start + chrono::Duration::seconds(index as i64),
+                command,
+            })
+            .collect()
+    }
+
+    /// 测试：记录原始运行时写入日志并产生的成交，重放日志得到完全相同的成交（含成交ID与时间）
+    #[test]
+    fn test_replay_matches_the_original_run() {
+        let path = std::env::temp_dir().join(format!("flowex-replay-{}.jsonl", Uuid::new_v4()));
+        let mut journal = flowex_matching_engine::journal::Journal::open(&path).unwrap();
+        let mut engine = MatchingEngine::new("BTCUSDT".to_string());
+        let mut executed = Vec::new();
+        for command in sample_commands() {
+            // As the engine task does: journal the command, then apply it at the journaled time
+            let entry = journal.append(command).unwrap();
+            executed.extend(entry.apply(&mut engine).unwrap());
+        }
+
+        let recording = Recording::from_journal("BTCUSDT", &path, &executed).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(recording.trades.len(), 3);
+
+        let report = assert_identical(&recording).unwrap();
+        assert_eq!(report.commands, 5);
+        let ids = |trades: &[Trade]| trades.iter().map(|trade| (trade.id, trade.timestamp)).collect::<Vec<_>>();
+        assert_eq!(ids(&report.trades), ids(&executed));
+    }
+
+    /// 测试：检入的录制样本重放结果与录制时一致，撮合行为的改变会被发现
+    #[test]
+    fn test_fixture_replays_identically() {
+        let recording = Recording::load(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/btcusdt.json")).unwrap();
+        assert!(!recording.trades.is_empty());
+
+        let report = assert_identical(&recording).unwrap();
+        assert_eq!(report.commands, recording.entries.len());
+    }
+
+    /// 测试：成交不一致时报告差异
+    #[test]
+    fn test_replay_detects_divergence() {
+        let mut recording = Recording::capture("BTCUSDT", sample_entries());
+        recording.trades[1].quantity += Decimal::ONE;
+        recording.trades.pop();
+
+        let report = replay(&recording);
+        assert_eq!(report.mismatches.len(), 2);
+        assert_eq!(report.mismatches[0].index, 1);
+        assert!(report.mismatches[1].expected.is_none());
+        assert!(assert_identical(&recording).is_err());
+    }
+
+    /// Re-quotes one unit on the opposite side of every trade
+    struct Requoter;
+
+    impl Strategy for Requoter {
+        fn on_trades(&mut self, trades: &[Trade], _engine: &MatchingEngine) -> Vec<JournalCommand> {
+            trades
+                .iter()
+                .filter(|trade| trade.side == OrderSide::Buy)
+                .map(|trade| new_order(OrderSide::Sell, 50200, trade.quantity.try_into().unwrap_or(1)))
+                .collect()
+        }
+    }
+
+    /// 测试：回测汇总成交量与策略注入的订单
+    #[test]
+    fn test_backtest_with_strategy() {
+        let summary = backtest("BTCUSDT", sample_commands(), &mut Requoter);
+
+        assert_eq!(summary.commands, 5);
+        assert_eq!(summary.strategy_commands, 2);
+        assert_eq!(summary.rejected, 0);
+        assert_eq!(summary.trades, 3);
+        assert_eq!(summary.volume, Decimal::new(3, 0));
+        assert_eq!(summary.last_price, Some(Decimal::new(49900, 0)));
+        assert!(summary.vwap.is_some());
+    }
+}
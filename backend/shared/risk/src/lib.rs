@@ -0,0 +1,622 @@
+//! FlowEx Risk - Pre-trade risk checks
+//!
+//! Every order is run through a pipeline of checks before it reaches the
+//! matching engine: a blacklist of users barred from trading, symbols
+//! restricted for individual users, a maximum order size per symbol, a
+//! fat-finger cap on an order's notional and a price band around the
+//! symbol's reference price. The checks run in the order the `RiskConfig`
+//! lists them and the first one to fail rejects the order.
+//!
+//! Each check run is counted in `flowex_risk_checks_total`, and every order
+//! checked leaves a `RiskAuditRecord` of the checks it passed or failed.
+
+use chrono::{DateTime, Utc};
+use flowex_metrics::MetricsCollector;
+use flowex_types::{FlowExError, FlowExResult, Order, OrderSide};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Audit records kept, oldest dropped first
+pub const MAX_AUDIT_RECORDS: usize = 10_000;
+
+/// One pre-trade check and its parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "check", rename_all = "snake_case")]
+pub enum RiskCheck {
+    /// Blacklisted users may not place orders
+    Blacklist,
+    /// Users may not place orders on symbols restricted for them
+    RestrictedSymbols,
+    /// Order quantity at most the limit of its symbol; symbols without a
+    /// limit are not checked
+    MaxOrderSize { max_quantity: HashMap<String, Decimal> },
+    /// Order notional, at its limit price or else the reference price, at
+    /// most `max_notional` in the quote asset
+    FatFinger { max_notional: Decimal },
+    /// Limit price within `max_deviation`, as a fraction, of the reference
+    /// price
+    PriceBand { max_deviation: Decimal },
+}
+
+impl RiskCheck {
+    /// Name of the check in metrics and audit records
+    pub fn name(&self) -> &'static str {
+        match self {
+            RiskCheck::Blacklist => "blacklist",
+            RiskCheck::RestrictedSymbols => "restricted_symbols",
+            RiskCheck::MaxOrderSize { .. } => "max_order_size",
+            RiskCheck::FatFinger { .. } => "fat_finger",
+            RiskCheck::PriceBand { .. } => "price_band",
+        }
+    }
+}
+
+/// Checks every order runs through, in order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RiskConfig {
+    pub checks: Vec<RiskCheck>,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            checks: vec![
+                RiskCheck::Blacklist,
+                RiskCheck::RestrictedSymbols,
+                RiskCheck::MaxOrderSize {
+                    max_quantity: HashMap::new(),
+                },
+                RiskCheck::FatFinger {
+                    max_notional: Decimal::new(1_000_000, 0),
+                },
+                RiskCheck::PriceBand {
+                    max_deviation: Decimal::new(1, 1),
+                },
+            ],
+        }
+    }
+}
+
+impl RiskConfig {
+    /// Read a risk configuration from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> FlowExResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| FlowExError::Internal(format!("Failed to read risk configuration {}: {}", path.display(), e)))?;
+        let config: Self = serde_json::from_str(&contents)
+            .map_err(|e| FlowExError::Validation(format!("Invalid risk configuration {}: {}", path.display(), e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that every limit is positive and no check is listed twice
+    pub fn validate(&self) -> FlowExResult<()> {
+        let mut names = HashSet::new();
+        for check in &self.checks {
+            if !names.insert(check.name()) {
+                return Err(FlowExError::Validation(format!("Risk check {} is listed twice", check.name())));
+            }
+            let valid = match check {
+                RiskCheck::Blacklist | RiskCheck::RestrictedSymbols => true,
+                RiskCheck::MaxOrderSize { max_quantity } => max_quantity.values().all(|limit| *limit > Decimal::ZERO),
+                RiskCheck::FatFinger { max_notional } => *max_notional > Decimal::ZERO,
+                RiskCheck::PriceBand { max_deviation } => *max_deviation > Decimal::ZERO,
+            };
+            if !valid {
+                return Err(FlowExError::Validation(format!(
+                    "Limits of risk check {} must be positive",
+                    check.name()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check an order failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskViolation {
+    Blacklisted,
+    RestrictedSymbol { symbol: String },
+    OrderSize { quantity: Decimal, limit: Decimal },
+    Notional { notional: Decimal, limit: Decimal },
+    PriceBand { price: Decimal, reference_price: Decimal, max_deviation: Decimal },
+}
+
+impl RiskViolation {
+    /// Whether the user may not trade at all rather than the order being
+    /// at fault
+    pub fn is_forbidden(&self) -> bool {
+        matches!(self, RiskViolation::Blacklisted | RiskViolation::RestrictedSymbol { .. })
+    }
+}
+
+impl fmt::Display for RiskViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskViolation::Blacklisted => write!(f, "user is blacklisted"),
+            RiskViolation::RestrictedSymbol { symbol } => write!(f, "{} is restricted for the user", symbol),
+            RiskViolation::OrderSize { quantity, limit } => {
+                write!(f, "quantity {} above the maximum order size of {}", quantity, limit)
+            }
+            RiskViolation::Notional { notional, limit } => {
+                write!(f, "notional {} above the fat-finger limit of {}", notional, limit)
+            }
+            RiskViolation::PriceBand {
+                price,
+                reference_price,
+                max_deviation,
+            } => write!(
+                f,
+                "price {} more than {} away from the reference price {}",
+                price, max_deviation, reference_price
+            ),
+        }
+    }
+}
+
+/// Result of one check on an order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckOutcome {
+    pub check: String,
+    pub passed: bool,
+    /// Why the check failed
+    pub reason: Option<String>,
+}
+
+/// Checks an order ran through and whether it was accepted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskAuditRecord {
+    pub order_id: Uuid,
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub price: Option<Decimal>,
+    pub quantity: Decimal,
+    /// Reference price the order was checked against
+    pub reference_price: Option<Decimal>,
+    pub checks: Vec<CheckOutcome>,
+    pub accepted: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Blacklist, per-user symbol restrictions and configuration of the checks
+struct RiskState {
+    config: RwLock<RiskConfig>,
+    blacklist: RwLock<HashSet<Uuid>>,
+    restricted_symbols: RwLock<HashMap<Uuid, HashSet<String>>>,
+    /// Audit records, oldest first
+    audit: Mutex<VecDeque<RiskAuditRecord>>,
+}
+
+/// Runs orders through the pre-trade checks
+#[derive(Clone)]
+pub struct RiskEngine {
+    state: Arc<RiskState>,
+    metrics: MetricsCollector,
+}
+
+impl RiskEngine {
+    pub fn new(config: RiskConfig) -> Self {
+        Self {
+            state: Arc::new(RiskState {
+                config: RwLock::new(config),
+                blacklist: RwLock::new(HashSet::new()),
+                restricted_symbols: RwLock::new(HashMap::new()),
+                audit: Mutex::new(VecDeque::new()),
+            }),
+            metrics: MetricsCollector::new(),
+        }
+    }
+
+    pub fn config(&self) -> RiskConfig {
+        self.state.config.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the checks orders run through
+    pub fn set_config(&self, config: RiskConfig) -> FlowExResult<()> {
+        config.validate()?;
+        info!(
+            "Risk checks set: {}",
+            config.checks.iter().map(RiskCheck::name).collect::<Vec<_>>().join(", ")
+        );
+        *self.state.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+        Ok(())
+    }
+
+    /// Run `order` through the checks, priced against `reference_price`
+    /// where it has no limit price, and record the outcome
+    pub fn check(&self, order: &Order, reference_price: Option<Decimal>) -> Result<(), RiskViolation> {
+        let started = Instant::now();
+        let config = self.config();
+        let mut outcomes = Vec::new();
+        let mut result = Ok(());
+        for check in &config.checks {
+            let outcome = self.run_check(check, order, reference_price);
+            self.metrics.record_risk_check(check.name(), outcome.is_ok());
+            outcomes.push(CheckOutcome {
+                check: check.name().to_string(),
+                passed: outcome.is_ok(),
+                reason: outcome.as_ref().err().map(ToString::to_string),
+            });
+            if outcome.is_err() {
+                result = outcome;
+                break;
+            }
+        }
+        self.metrics.record_risk_pipeline_duration(started.elapsed());
+
+        if let Err(violation) = &result {
+            warn!("Order {} of user {} failed the risk checks: {}", order.id, order.user_id, violation);
+        }
+        self.record(RiskAuditRecord {
+            order_id: order.id,
+            user_id: order.user_id,
+            symbol: order.trading_pair.clone(),
+            side: order.side.clone(),
+            price: order.price,
+            quantity: order.quantity,
+            reference_price,
+            checks: outcomes,
+            accepted: result.is_ok(),
+            timestamp: Utc::now(),
+        });
+        result
+    }
+
+    fn run_check(&self, check: &RiskCheck, order: &Order, reference_price: Option<Decimal>) -> Result<(), RiskViolation> {
+        match check {
+            RiskCheck::Blacklist => {
+                if self.is_blacklisted(order.user_id) {
+                    return Err(RiskViolation::Blacklisted);
+                }
+            }
+            RiskCheck::RestrictedSymbols => {
+                if self.restricted_symbols(order.user_id).contains(&order.trading_pair) {
+                    return Err(RiskViolation::RestrictedSymbol {
+                        symbol: order.trading_pair.clone(),
+                    });
+                }
+            }
+            RiskCheck::MaxOrderSize { max_quantity } => {
+                if let Some(limit) = max_quantity.get(&order.trading_pair).filter(|limit| order.quantity > **limit) {
+                    return Err(RiskViolation::OrderSize {
+                        quantity: order.quantity,
+                        limit: *limit,
+                    });
+                }
+            }
+            RiskCheck::FatFinger { max_notional } => {
+                let notional = order.price.or(reference_price).map(|price| price * order.quantity);
+                if let Some(notional) = notional.filter(|notional| notional > max_notional) {
+                    return Err(RiskViolation::Notional {
+                        notional,
+                        limit: *max_notional,
+                    });
+                }
+            }
+            RiskCheck::PriceBand { max_deviation } => {
+                if let (Some(price), Some(reference_price)) = (order.price, reference_price) {
+                    if reference_price > Decimal::ZERO
+                        && ((price - reference_price) / reference_price).abs() > *max_deviation
+                    {
+                        return Err(RiskViolation::PriceBand {
+                            price,
+                            reference_price,
+                            max_deviation: *max_deviation,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&self, record: RiskAuditRecord) {
+        let mut audit = self.state.audit.lock().unwrap_or_else(|e| e.into_inner());
+        if audit.len() == MAX_AUDIT_RECORDS {
+            audit.pop_front();
+        }
+        audit.push_back(record);
+    }
+
+    /// Latest audit records, of `user_id` only if given, newest first
+    pub fn audit_log(&self, user_id: Option<Uuid>, limit: usize) -> Vec<RiskAuditRecord> {
+        self.state
+            .audit
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .rev()
+            .filter(|record| user_id.is_none_or(|user_id| record.user_id == user_id))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Bar `user_id` from placing orders
+    pub fn blacklist(&self, user_id: Uuid) {
+        info!("User {} blacklisted", user_id);
+        self.state.blacklist.write().unwrap_or_else(|e| e.into_inner()).insert(user_id);
+    }
+
+    /// Lift `user_id`'s bar; false if they were not blacklisted
+    pub fn remove_from_blacklist(&self, user_id: Uuid) -> bool {
+        let removed = self.state.blacklist.write().unwrap_or_else(|e| e.into_inner()).remove(&user_id);
+        if removed {
+            info!("User {} removed from the blacklist", user_id);
+        }
+        removed
+    }
+
+    pub fn is_blacklisted(&self, user_id: Uuid) -> bool {
+        self.state.blacklist.read().unwrap_or_else(|e| e.into_inner()).contains(&user_id)
+    }
+
+    /// Symbols `user_id` may not trade
+    pub fn restricted_symbols(&self, user_id: Uuid) -> HashSet<String> {
+        self.state
+            .restricted_symbols
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&user_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replace the symbols `user_id` may not trade
+    pub fn set_restricted_symbols(&self, user_id: Uuid, symbols: HashSet<String>) {
+        info!("Symbols restricted for user {}: {:?}", user_id, symbols);
+        let mut restricted = self.state.restricted_symbols.write().unwrap_or_else(|e| e.into_inner());
+        if symbols.is_empty() {
+            restricted.remove(&user_id);
+        } else {
+            restricted.insert(user_id, symbols);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_test_support::{create_limit_order, create_market_order};
+
+    fn order(user_id: Uuid, price: Option<Decimal>, quantity: Decimal) -> Order {
+        let order = match price {
+            Some(price) => create_limit_order(OrderSide::Buy, price, quantity),
+            None => create_market_order(OrderSide::Buy, quantity),
+        };
+        Order { user_id, trading_pair: "BTC-USDT".to_string(), ..order }
+    }
+
+    fn engine(check: RiskCheck) -> RiskEngine {
+        RiskEngine::new(RiskConfig { checks: vec![check] })
+    }
+
+    fn price(value: i64, scale: u32) -> Option<Decimal> {
+        Some(Decimal::new(value, scale))
+    }
+
+    /// 测试：黑名单用户被拒绝，其他用户及移出黑名单后不受影响
+    #[test]
+    fn test_blacklist_check() {
+        let risk = engine(RiskCheck::Blacklist);
+        let (barred, other) = (Uuid::new_v4(), Uuid::new_v4());
+        risk.blacklist(barred);
+
+        let violation = risk.check(&order(barred, price(45_000, 0), Decimal::ONE), None).unwrap_err();
+        assert_eq!(violation, RiskViolation::Blacklisted);
+        assert!(violation.is_forbidden());
+        assert_eq!(risk.check(&order(other, price(45_000, 0), Decimal::ONE), None), Ok(()));
+
+        risk.remove_from_blacklist(barred);
+        assert_eq!(risk.check(&order(barred, price(45_000, 0), Decimal::ONE), None), Ok(()));
+    }
+
+    /// 测试：受限交易对只对该用户的该交易对生效
+    #[test]
+    fn test_restricted_symbols_check() {
+        let risk = engine(RiskCheck::RestrictedSymbols);
+        let (restricted, other) = (Uuid::new_v4(), Uuid::new_v4());
+        risk.set_restricted_symbols(restricted, HashSet::from(["BTC-USDT".to_string()]));
+
+        let violation = risk.check(&order(restricted, price(45_000, 0), Decimal::ONE), None).unwrap_err();
+        assert_eq!(violation, RiskViolation::RestrictedSymbol { symbol: "BTC-USDT".to_string() });
+        assert!(violation.is_forbidden());
+        let eth = Order { trading_pair: "ETH-USDT".to_string(), ..order(restricted, price(3_000, 0), Decimal::ONE) };
+        assert_eq!(risk.check(&eth, None), Ok(()));
+        assert_eq!(risk.check(&order(other, price(45_000, 0), Decimal::ONE), None), Ok(()));
+    }
+
+    /// 测试：订单数量恰好等于上限时通过，超出最小单位即被拒绝，未设上限的交易对不检查
+    #[test]
+    fn test_max_order_size_check() {
+        let risk = engine(RiskCheck::MaxOrderSize {
+            max_quantity: HashMap::from([("BTC-USDT".to_string(), Decimal::new(10, 0))]),
+        });
+        let user_id = Uuid::new_v4();
+
+        assert_eq!(risk.check(&order(user_id, price(1, 0), Decimal::new(10, 0)), None), Ok(()));
+        let violation = risk.check(&order(user_id, price(1, 0), Decimal::new(1_000_000_001, 8)), None).unwrap_err();
+        assert_eq!(
+            violation,
+            RiskViolation::OrderSize { quantity: Decimal::new(1_000_000_001, 8), limit: Decimal::new(10, 0) }
+        );
+        assert!(!violation.is_forbidden());
+        // 市价单同样按数量检查
+        assert!(risk.check(&order(user_id, None, Decimal::new(11, 0)), None).is_err());
+
+        let eth = Order { trading_pair: "ETH-USDT".to_string(), ..order(user_id, price(1, 0), Decimal::new(1_000, 0)) };
+        assert_eq!(risk.check(&eth, None), Ok(()));
+    }
+
+    /// 测试：名义价值恰好等于上限时通过、超出即拒绝，市价单按参考价计算，无价格可用时不检查
+    #[test]
+    fn test_fat_finger_check() {
+        let risk = engine(RiskCheck::FatFinger { max_notional: Decimal::new(100_000, 0) });
+        let user_id = Uuid::new_v4();
+
+        assert_eq!(risk.check(&order(user_id, price(50_000, 0), Decimal::TWO), None), Ok(()));
+        assert_eq!(
+            risk.check(&order(user_id, price(5_000_001, 2), Decimal::TWO), None),
+            Err(RiskViolation::Notional { notional: Decimal::new(10_000_002, 2), limit: Decimal::new(100_000, 0) })
+        );
+
+        // 市价单：按参考价 50000 计算
+        let reference = price(50_000, 0);
+        assert_eq!(risk.check(&order(user_id, None, Decimal::TWO), reference), Ok(()));
+        assert!(matches!(
+            risk.check(&order(user_id, None, Decimal::new(200_000_001, 8)), reference),
+            Err(RiskViolation::Notional { .. })
+        ));
+        // 限价单按自身价格而非参考价计算
+        assert_eq!(risk.check(&order(user_id, price(50_000, 0), Decimal::TWO), price(60_000, 0)), Ok(()));
+        assert_eq!(risk.check(&order(user_id, None, Decimal::new(1_000, 0)), None), Ok(()));
+    }
+
+    /// 测试：价格恰好偏离参考价上限时通过，上下两侧超出即拒绝，市价单或无参考价时不检查
+    #[test]
+    fn test_price_band_check() {
+        let risk = engine(RiskCheck::PriceBand { max_deviation: Decimal::new(1, 1) });
+        let user_id = Uuid::new_v4();
+        let reference = price(45_000, 0);
+
+        assert_eq!(risk.check(&order(user_id, price(49_500, 0), Decimal::ONE), reference), Ok(()));
+        assert_eq!(risk.check(&order(user_id, price(40_500, 0), Decimal::ONE), reference), Ok(()));
+        assert_eq!(
+            risk.check(&order(user_id, price(4_950_001, 2), Decimal::ONE), reference),
+            Err(RiskViolation::PriceBand {
+                price: Decimal::new(4_950_001, 2),
+                reference_price: Decimal::new(45_000, 0),
+                max_deviation: Decimal::new(1, 1),
+            })
+        );
+        assert!(risk.check(&order(user_id, price(4_049_999, 2), Decimal::ONE), reference).is_err());
+
+        assert_eq!(risk.check(&order(user_id, None, Decimal::ONE), reference), Ok(()));
+        assert_eq!(risk.check(&order(user_id, price(90_000, 0), Decimal::ONE), None), Ok(()));
+    }
+
+    /// 测试：同时违反多条规则的订单按配置顺序报告第一条失败的检查，审计只记录到该检查为止
+    #[test]
+    fn test_checks_run_in_configured_order() {
+        let size = RiskCheck::MaxOrderSize {
+            max_quantity: HashMap::from([("BTC-USDT".to_string(), Decimal::ONE)]),
+        };
+        let fat_finger = RiskCheck::FatFinger { max_notional: Decimal::new(10_000, 0) };
+        let band = RiskCheck::PriceBand { max_deviation: Decimal::new(1, 1) };
+        let user_id = Uuid::new_v4();
+        let reference = price(45_000, 0);
+        // 数量、名义价值和价格偏离同时超限
+        let breaching = order(user_id, price(60_000, 0), Decimal::TWO);
+
+        let risk = RiskEngine::new(RiskConfig {
+            checks: vec![RiskCheck::Blacklist, size.clone(), fat_finger.clone(), band.clone()],
+        });
+        assert!(matches!(risk.check(&breaching, reference), Err(RiskViolation::OrderSize { .. })));
+        let audit = &risk.audit_log(Some(user_id), 1)[0];
+        let checks: Vec<(&str, bool)> = audit.checks.iter().map(|c| (c.check.as_str(), c.passed)).collect();
+        assert_eq!(checks, vec![("blacklist", true), ("max_order_size", false)]);
+
+        risk.set_config(RiskConfig { checks: vec![band, fat_finger, size] }).unwrap();
+        assert!(matches!(risk.check(&breaching, reference), Err(RiskViolation::PriceBand { .. })));
+        assert_eq!(risk.audit_log(Some(user_id), 1)[0].checks.len(), 1);
+
+        // 默认配置中黑名单排在首位，先于其他违规被报告
+        let risk = RiskEngine::new(RiskConfig::default());
+        risk.blacklist(user_id);
+        risk.set_restricted_symbols(user_id, HashSet::from(["BTC-USDT".to_string()]));
+        assert_eq!(risk.check(&breaching, reference), Err(RiskViolation::Blacklisted));
+        risk.remove_from_blacklist(user_id);
+        assert!(matches!(risk.check(&breaching, reference), Err(RiskViolation::RestrictedSymbol { .. })));
+    }
+
+    /// 测试：风控检查依次拒绝黑名单、受限交易对、超大订单、胖手指和价格偏离
+    #[test]
+    fn test_pre_trade_checks() {
+        let risk = RiskEngine::new(RiskConfig {
+            checks: vec![
+                RiskCheck::Blacklist,
+                RiskCheck::RestrictedSymbols,
+                RiskCheck::MaxOrderSize {
+                    max_quantity: HashMap::from([("BTC-USDT".to_string(), Decimal::new(10, 0))]),
+                },
+                RiskCheck::FatFinger {
+                    max_notional: Decimal::new(100_000, 0),
+                },
+                RiskCheck::PriceBand {
+                    max_deviation: Decimal::new(1, 1),
+                },
+            ],
+        });
+        let user_id = Uuid::new_v4();
+        let reference = Some(Decimal::new(45_000, 0));
+
+        assert_eq!(risk.check(&order(user_id, Some(Decimal::new(46_000, 0)), Decimal::ONE), reference), Ok(()));
+        assert!(matches!(
+            risk.check(&order(user_id, Some(Decimal::new(1_000, 0)), Decimal::new(11, 0)), reference),
+            Err(RiskViolation::OrderSize { .. })
+        ));
+        // 市价单按参考价计算名义价值
+        assert!(matches!(
+            risk.check(&order(user_id, None, Decimal::new(3, 0)), reference),
+            Err(RiskViolation::Notional { .. })
+        ));
+        assert!(matches!(
+            risk.check(&order(user_id, Some(Decimal::new(50_000, 0)), Decimal::ONE), reference),
+            Err(RiskViolation::PriceBand { .. })
+        ));
+        // 没有参考价时不检查价格偏离
+        assert_eq!(risk.check(&order(user_id, Some(Decimal::new(50_000, 0)), Decimal::ONE), None), Ok(()));
+
+        risk.set_restricted_symbols(user_id, HashSet::from(["BTC-USDT".to_string()]));
+        let violation = risk.check(&order(user_id, None, Decimal::ONE), reference).unwrap_err();
+        assert!(violation.is_forbidden());
+        risk.set_restricted_symbols(user_id, HashSet::new());
+
+        risk.blacklist(user_id);
+        assert_eq!(risk.check(&order(user_id, None, Decimal::ONE), reference), Err(RiskViolation::Blacklisted));
+        assert!(risk.remove_from_blacklist(user_id));
+        assert!(!risk.remove_from_blacklist(user_id));
+        assert_eq!(risk.check(&order(user_id, None, Decimal::ONE), reference), Ok(()));
+
+        // 审计记录从新到旧，拒绝的订单只记录到失败的检查为止
+        let audit = risk.audit_log(Some(user_id), 100);
+        assert_eq!(audit.len(), 8);
+        assert!(audit[0].accepted);
+        assert!(!audit[1].accepted);
+        assert_eq!(audit[1].checks.len(), 1);
+        assert_eq!(audit[1].checks[0].check, "blacklist");
+        assert_eq!(audit[1].checks[0].reason.as_deref(), Some("user is blacklisted"));
+        assert_eq!(audit[7].checks.len(), 5);
+        assert!(risk.audit_log(Some(Uuid::new_v4()), 100).is_empty());
+    }
+
+    /// 测试：风控配置拒绝重复检查和非正数限制
+    #[test]
+    fn test_config_validation() {
+        assert!(RiskConfig::default().validate().is_ok());
+        let duplicate = RiskConfig {
+            checks: vec![RiskCheck::Blacklist, RiskCheck::Blacklist],
+        };
+        assert!(duplicate.validate().is_err());
+        let negative = RiskConfig {
+            checks: vec![RiskCheck::PriceBand {
+                max_deviation: Decimal::ZERO,
+            }],
+        };
+        assert!(RiskEngine::new(RiskConfig::default()).set_config(negative).is_err());
+
+        let json = r#"{"checks":[{"check":"fat_finger","max_notional":500},{"check":"blacklist"}]}"#;
+        let config: RiskConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.checks[0].name(), "fat_finger");
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,55 @@
+//! FlowEx Test Support
+//!
+//! Order factories shared by the workspace's tests and benchmarks, so a test
+//! only spells out the fields it cares about. Only ever a dev-dependency.
+
+use chrono::Utc;
+use flowex_types::{Order, OrderSide, OrderStatus, OrderType, TimeInForce};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+/// A new BTCUSDT good-till-cancelled limit order from a fresh user
+pub fn create_limit_order(side: OrderSide, price: impl Into<Decimal>, quantity: impl Into<Decimal>) -> Order {
+    create_user_limit_order(Uuid::new_v4(), side, price, quantity)
+}
+
+/// A new BTCUSDT good-till-cancelled limit order placed by `user_id`
+pub fn create_user_limit_order(
+    user_id: Uuid,
+    side: OrderSide,
+    price: impl Into<Decimal>,
+    quantity: impl Into<Decimal>,
+) -> Order {
+    let quantity = quantity.into();
+    let now = Utc::now();
+    Order {
+        id: Uuid::new_v4(),
+        user_id,
+        trading_pair: "BTCUSDT".to_string(),
+        side,
+        order_type: OrderType::Limit,
+        price: Some(price.into()),
+        stop_price: None,
+        quantity,
+        filled_quantity: Decimal::ZERO,
+        remaining_quantity: quantity,
+        time_in_force: TimeInForce::Gtc,
+        post_only: false,
+        display_quantity: None,
+        expires_at: None,
+        reduce_only: false,
+        client_order_id: None,
+        status: OrderStatus::New,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+/// A new BTCUSDT market order from a fresh user
+pub fn create_market_order(side: OrderSide, quantity: impl Into<Decimal>) -> Order {
+    Order {
+        order_type: OrderType::Market,
+        price: None,
+        ..create_limit_order(side, 0, quantity)
+    }
+}
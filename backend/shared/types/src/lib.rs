@@ -22,7 +22,7 @@ pub struct User {
 }
 
 /// Authentication request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
@@ -32,12 +32,20 @@ pub struct LoginRequest {
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    /// Redeemable once for a new token pair
+    pub refresh_token: String,
     pub user: User,
     pub expires_in: i64,
 }
 
+/// Token refresh or logout request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
 /// User registration request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct RegisterRequest {
     pub email: String,
     pub password: String,
@@ -45,6 +53,27 @@ pub struct RegisterRequest {
     pub last_name: String,
 }
 
+/// Password change request
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Account opened under a master account, which acts for it with API keys
+/// holding the permissions delegated to it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SubAccount {
+    pub user_id: Uuid,
+    pub master_id: Uuid,
+    pub label: String,
+    pub email: String,
+    /// Most an API key of the sub-account may act with
+    pub permissions: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
 /// Trading pair information
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct TradingPair {
@@ -58,6 +87,86 @@ pub struct TradingPair {
     pub max_qty: Decimal,
     pub step_size: Decimal,
     pub tick_size: Decimal,
+    /// Minimum order value (price * quantity) in the quote asset
+    #[serde(default)]
+    pub min_notional: Decimal,
+}
+
+impl TradingPair {
+    /// Check an order's price (if any) and quantity against the pair's
+    /// bounds, increments and minimum notional. A zero tick or step size
+    /// disables that check; orders without a price skip the notional check.
+    pub fn check_order(&self, price: Option<Decimal>, quantity: Decimal) -> Result<(), OrderConstraintViolation> {
+        if let Some(price) = price {
+            self.check_price(price)?;
+            self.check_notional(price, quantity)?;
+        }
+
+        if quantity < self.min_qty || quantity > self.max_qty {
+            return Err(OrderConstraintViolation::QuantityOutOfRange {
+                quantity,
+                min: self.min_qty,
+                max: self.max_qty,
+            });
+        }
+        if self.step_size > Decimal::ZERO && !(quantity % self.step_size).is_zero() {
+            return Err(OrderConstraintViolation::InvalidStepSize {
+                quantity,
+                step_size: self.step_size,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Check that an order's value meets the pair's minimum notional
+    pub fn check_notional(&self, price: Decimal, quantity: Decimal) -> Result<(), OrderConstraintViolation> {
+        let notional = price * quantity;
+        if notional < self.min_notional {
+            return Err(OrderConstraintViolation::BelowMinNotional {
+                notional,
+                min_notional: self.min_notional,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check a price against the pair's price bounds and tick size
+    pub fn check_price(&self, price: Decimal) -> Result<(), OrderConstraintViolation> {
+        if price < self.min_price || price > self.max_price {
+            return Err(OrderConstraintViolation::PriceOutOfRange {
+                price,
+                min: self.min_price,
+                max: self.max_price,
+            });
+        }
+        if self.tick_size > Decimal::ZERO && !(price % self.tick_size).is_zero() {
+            return Err(OrderConstraintViolation::InvalidTickSize {
+                price,
+                tick_size: self.tick_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Trading pair constraint violated by an order
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum OrderConstraintViolation {
+    #[error("price {price} is outside the allowed range [{min}, {max}]")]
+    PriceOutOfRange { price: Decimal, min: Decimal, max: Decimal },
+
+    #[error("price {price} is not a multiple of the tick size {tick_size}")]
+    InvalidTickSize { price: Decimal, tick_size: Decimal },
+
+    #[error("quantity {quantity} is outside the allowed range [{min}, {max}]")]
+    QuantityOutOfRange { quantity: Decimal, min: Decimal, max: Decimal },
+
+    #[error("quantity {quantity} is not a multiple of the step size {step_size}")]
+    InvalidStepSize { quantity: Decimal, step_size: Decimal },
+
+    #[error("order value {notional} is below the minimum notional {min_notional}")]
+    BelowMinNotional { notional: Decimal, min_notional: Decimal },
 }
 
 /// Trading status enumeration
@@ -78,8 +187,30 @@ pub struct Order {
     pub side: OrderSide,
     pub order_type: OrderType,
     pub price: Option<Decimal>,
+    /// Trigger price for stop-loss / take-profit orders
+    #[serde(default)]
+    pub stop_price: Option<Decimal>,
     pub quantity: Decimal,
     pub filled_quantity: Decimal,
+    pub remaining_quantity: Decimal,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    /// Maker-only: rejected instead of crossing the spread
+    #[serde(default)]
+    pub post_only: bool,
+    /// Iceberg slice size: only this much of the remaining quantity is shown on the book
+    #[serde(default)]
+    pub display_quantity: Option<Decimal>,
+    /// Expiry of a good-till-date order
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Reduce-only: may only shrink the owner's position, never grow or flip it
+    #[serde(default)]
+    pub reduce_only: bool,
+    /// Caller-assigned id, unique per user; a retry with the same id returns
+    /// the existing order instead of placing a duplicate
+    #[serde(default)]
+    pub client_order_id: Option<String>,
     pub status: OrderStatus,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -115,14 +246,204 @@ pub enum OrderStatus {
     Expired,
 }
 
+/// Time-in-force enumeration
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TimeInForce {
+    /// Good-till-cancelled: unfilled remainder rests on the book
+    #[default]
+    Gtc,
+    /// Immediate-or-cancel: unfilled remainder is cancelled
+    Ioc,
+    /// Fill-or-kill: the full quantity must fill immediately
+    Fok,
+    /// Good-till-date: rests on the book until `expires_at`
+    Gtd,
+}
+
+impl TimeInForce {
+    /// Whether an unfilled remainder may rest on the book
+    pub fn is_resting(&self) -> bool {
+        matches!(self, TimeInForce::Gtc | TimeInForce::Gtd)
+    }
+}
+
 /// Create order request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateOrderRequest {
     pub trading_pair: String,
     pub side: OrderSide,
     pub order_type: OrderType,
     pub price: Option<Decimal>,
+    #[serde(default)]
+    pub stop_price: Option<Decimal>,
     pub quantity: Decimal,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    #[serde(default)]
+    pub post_only: bool,
+    #[serde(default)]
+    pub display_quantity: Option<Decimal>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub reduce_only: bool,
+    #[serde(default)]
+    pub client_order_id: Option<String>,
+}
+
+/// Order lifecycle event kind
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderEventKind {
+    Accepted,
+    Rejected,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    /// Unfilled remainder of an order that may not rest on the book
+    Expired,
+}
+
+/// Order state change emitted by the matching engine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderEvent {
+    pub kind: OrderEventKind,
+    /// Order state after the change
+    pub order: Order,
+    /// Trade that caused a fill event
+    pub trade_id: Option<Uuid>,
+    /// Reason for a rejection or cancellation, or why an accepted order
+    /// was flagged
+    pub reason: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Modify order request; omitted fields are left unchanged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModifyOrderRequest {
+    pub price: Option<Decimal>,
+    /// New total order quantity, including any already filled quantity
+    pub quantity: Option<Decimal>,
+}
+
+/// Selects open orders for a mass cancel; unset fields match every order
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CancelOrdersFilter {
+    pub side: Option<OrderSide>,
+    /// Lowest price included; stop-market orders are matched on their stop price
+    pub min_price: Option<Decimal>,
+    /// Highest price included
+    pub max_price: Option<Decimal>,
+    pub user_id: Option<Uuid>,
+}
+
+impl CancelOrdersFilter {
+    /// Whether an order is selected by the filter
+    pub fn matches(&self, order: &Order) -> bool {
+        let price = order.price.or(order.stop_price);
+        let in_range = |bound: Option<Decimal>, within: fn(Decimal, Decimal) -> bool| match (bound, price) {
+            (None, _) => true,
+            (Some(bound), Some(price)) => within(price, bound),
+            (Some(_), None) => false,
+        };
+
+        self.side.as_ref().is_none_or(|side| *side == order.side)
+            && self.user_id.is_none_or(|user_id| user_id == order.user_id)
+            && in_range(self.min_price, |price, min| price >= min)
+            && in_range(self.max_price, |price, max| price <= max)
+    }
+}
+
+/// Selects orders from a user's order history; unset fields match every order
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrderHistoryQuery {
+    pub status: Option<OrderStatus>,
+    pub symbol: Option<String>,
+    pub side: Option<OrderSide>,
+    /// Earliest creation time included
+    pub start_time: Option<DateTime<Utc>>,
+    /// Creation time from which orders are excluded
+    pub end_time: Option<DateTime<Utc>>,
+    /// Continue after the last order of a previous page
+    pub cursor: Option<Cursor>,
+    /// Orders per page
+    pub limit: Option<usize>,
+    /// Order of creation time
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+impl OrderHistoryQuery {
+    /// Whether an order is selected by the filters; the cursor and limit
+    /// are applied by `Page::paginate`
+    pub fn matches(&self, order: &Order) -> bool {
+        self.status.as_ref().is_none_or(|status| *status == order.status)
+            && self.symbol.as_ref().is_none_or(|symbol| *symbol == order.trading_pair)
+            && self.side.as_ref().is_none_or(|side| *side == order.side)
+            && self.start_time.is_none_or(|start| order.created_at >= start)
+            && self.end_time.is_none_or(|end| order.created_at < end)
+    }
+}
+
+/// Selects fills from a user's trade history; unset fields match every fill
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradeHistoryQuery {
+    pub symbol: Option<String>,
+    /// Earliest execution time included
+    pub start_time: Option<DateTime<Utc>>,
+    /// Execution time from which fills are excluded
+    pub end_time: Option<DateTime<Utc>>,
+    /// Continue after the last fill of a previous page
+    pub cursor: Option<Cursor>,
+    /// Fills per page
+    pub limit: Option<usize>,
+    /// Order of execution time
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+impl TradeHistoryQuery {
+    /// Whether a fill is selected by the filters; the cursor and limit are
+    /// applied by `Page::paginate`
+    pub fn matches(&self, fill: &Fill) -> bool {
+        self.symbol.as_ref().is_none_or(|symbol| *symbol == fill.symbol)
+            && self.start_time.is_none_or(|start| fill.timestamp >= start)
+            && self.end_time.is_none_or(|end| fill.timestamp < end)
+    }
+}
+
+/// Selects entries of a user's wallet transaction history; unset fields
+/// match every entry
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransactionHistoryQuery {
+    #[serde(rename = "type")]
+    pub transaction_type: Option<TransactionType>,
+    pub currency: Option<String>,
+    pub status: Option<TransactionStatus>,
+    /// Earliest creation time included
+    pub start_time: Option<DateTime<Utc>>,
+    /// Creation time from which entries are excluded
+    pub end_time: Option<DateTime<Utc>>,
+    /// Continue after the last entry of a previous page
+    pub cursor: Option<Cursor>,
+    /// Entries per page
+    pub limit: Option<usize>,
+    /// Order of creation time
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+impl TransactionHistoryQuery {
+    /// Whether an entry is selected by the filters; the cursor and limit
+    /// are applied by `Page::paginate`
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        self.transaction_type.as_ref().is_none_or(|kind| *kind == transaction.transaction_type)
+            && self.currency.as_ref().is_none_or(|currency| currency.eq_ignore_ascii_case(&transaction.currency))
+            && self.status.as_ref().is_none_or(|status| *status == transaction.status)
+            && self.start_time.is_none_or(|start| transaction.created_at >= start)
+            && self.end_time.is_none_or(|end| transaction.created_at < end)
+    }
 }
 
 /// Order book level
@@ -136,11 +457,113 @@ pub struct OrderBookLevel {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBook {
     pub symbol: String,
+    /// Engine sequence the snapshot reflects; increases with every applied command
+    #[serde(default)]
+    pub sequence: u64,
     pub bids: Vec<OrderBookLevel>,
     pub asks: Vec<OrderBookLevel>,
     pub timestamp: DateTime<Utc>,
 }
 
+impl OrderBook {
+    /// Apply an incremental update on top of this book. Returns `false` for
+    /// an update the book already reflects, and an error if the book has
+    /// missed an earlier update and must be resynchronised from a snapshot.
+    pub fn apply_update(&mut self, update: &BookUpdate) -> FlowExResult<bool> {
+        if update.symbol != self.symbol {
+            return Err(FlowExError::Validation(format!(
+                "Update for {} cannot apply to the {} book",
+                update.symbol, self.symbol
+            )));
+        }
+        if update.sequence <= self.sequence {
+            return Ok(false);
+        }
+        if update.previous_sequence > self.sequence {
+            return Err(FlowExError::Validation(format!(
+                "{} book at sequence {} missed updates before {}",
+                self.symbol, self.sequence, update.sequence
+            )));
+        }
+
+        for delta in &update.deltas {
+            let levels = match delta.side {
+                OrderSide::Buy => &mut self.bids,
+                OrderSide::Sell => &mut self.asks,
+            };
+            // Bids best (highest) first, asks best (lowest) first
+            let position = levels.binary_search_by(|level| match delta.side {
+                OrderSide::Buy => delta.price.cmp(&level.price),
+                OrderSide::Sell => level.price.cmp(&delta.price),
+            });
+            match (position, delta.quantity > Decimal::ZERO) {
+                (Ok(index), true) => levels[index].quantity = delta.quantity,
+                (Ok(index), false) => {
+                    levels.remove(index);
+                }
+                (Err(index), true) => levels.insert(
+                    index,
+                    OrderBookLevel {
+                        price: delta.price,
+                        quantity: delta.quantity,
+                    },
+                ),
+                (Err(_), false) => {}
+            }
+        }
+        self.sequence = update.sequence;
+        self.timestamp = update.timestamp;
+        Ok(true)
+    }
+}
+
+/// Change to one aggregated price level; a zero quantity removes the level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookDelta {
+    pub side: OrderSide,
+    pub price: Decimal,
+    /// Visible quantity now at the level
+    pub quantity: Decimal,
+}
+
+/// Incremental order book update produced by the matching engine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookUpdate {
+    pub symbol: String,
+    /// Engine sequence after the changes; applies on top of any snapshot
+    /// with a lower sequence
+    pub sequence: u64,
+    /// Sequence of the symbol's previous update, zero for the first; a
+    /// book behind it has missed an update
+    #[serde(default)]
+    pub previous_sequence: u64,
+    pub deltas: Vec<BookDelta>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Individual resting order in a level-3 order book view
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBookEntry {
+    pub order_id: Uuid,
+    pub user_id: Uuid,
+    pub price: Decimal,
+    /// Unfilled quantity, including any hidden iceberg reserve
+    pub quantity: Decimal,
+    /// Quantity currently shown on the book
+    pub visible_quantity: Decimal,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Level-3 order book snapshot listing individual orders in priority order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBookL3 {
+    pub symbol: String,
+    pub sequence: u64,
+    pub bids: Vec<OrderBookEntry>,
+    pub asks: Vec<OrderBookEntry>,
+    pub timestamp: DateTime<Utc>,
+}
+
 /// Market ticker information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ticker {
@@ -151,6 +574,28 @@ pub struct Ticker {
     pub high: Decimal,
     pub low: Decimal,
     pub volume: Decimal,
+    /// Volume-weighted average price over the ticker window
+    #[serde(default)]
+    pub vwap: Option<Decimal>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Reference price of a symbol, the median of its prices on external
+/// exchanges
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexPrice {
+    pub symbol: String,
+    pub price: Decimal,
+    /// Exchange prices the index was computed from
+    pub components: Vec<IndexComponent>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// One exchange's price in an index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexComponent {
+    pub exchange: String,
+    pub price: Decimal,
     pub timestamp: DateTime<Utc>,
 }
 
@@ -159,12 +604,176 @@ pub struct Ticker {
 pub struct Trade {
     pub id: Uuid,
     pub symbol: String,
+    /// Per-symbol trade number, increasing by one per trade so gaps are detectable
+    #[serde(default)]
+    pub sequence: u64,
     pub price: Decimal,
     pub quantity: Decimal,
+    /// Taker side
     pub side: OrderSide,
+    #[serde(default)]
+    pub maker_order_id: Uuid,
+    #[serde(default)]
+    pub taker_order_id: Uuid,
+    #[serde(default)]
+    pub maker_user_id: Uuid,
+    #[serde(default)]
+    pub taker_user_id: Uuid,
+    /// True when the resting (maker) order was the buy side
+    #[serde(default)]
+    pub is_buyer_maker: bool,
+    /// Fee charged to the maker; negative for a rebate
+    #[serde(default)]
+    pub maker_fee: Decimal,
+    /// Currency of the maker fee, `None` when no fee schedule applies
+    #[serde(default)]
+    pub maker_fee_currency: Option<String>,
+    #[serde(default)]
+    pub taker_fee: Decimal,
+    #[serde(default)]
+    pub taker_fee_currency: Option<String>,
     pub timestamp: DateTime<Utc>,
 }
 
+impl Trade {
+    /// The trade as an execution report for each of its two orders, maker first
+    pub fn fills(&self) -> [Fill; 2] {
+        let (maker_side, taker_side) = if self.is_buyer_maker {
+            (OrderSide::Buy, OrderSide::Sell)
+        } else {
+            (OrderSide::Sell, OrderSide::Buy)
+        };
+        let fill = |order_id, user_id, side, fee, fee_currency: &Option<String>, liquidity| Fill {
+            trade_id: self.id,
+            order_id,
+            user_id,
+            symbol: self.symbol.clone(),
+            side,
+            price: self.price,
+            quantity: self.quantity,
+            fee,
+            fee_currency: fee_currency.clone(),
+            liquidity,
+            timestamp: self.timestamp,
+        };
+
+        [
+            fill(self.maker_order_id, self.maker_user_id, maker_side, self.maker_fee, &self.maker_fee_currency, Liquidity::Maker),
+            fill(self.taker_order_id, self.taker_user_id, taker_side, self.taker_fee, &self.taker_fee_currency, Liquidity::Taker),
+        ]
+    }
+
+    /// The trade as published on public feeds, without either side's
+    /// orders, users or fees
+    pub fn anonymized(&self) -> Trade {
+        Trade {
+            maker_order_id: Uuid::nil(),
+            taker_order_id: Uuid::nil(),
+            maker_user_id: Uuid::nil(),
+            taker_user_id: Uuid::nil(),
+            maker_fee: Decimal::ZERO,
+            maker_fee_currency: None,
+            taker_fee: Decimal::ZERO,
+            taker_fee_currency: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Which side of a trade an order was on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+/// Execution report of one order's part in a trade
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    pub trade_id: Uuid,
+    pub order_id: Uuid,
+    pub user_id: Uuid,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    /// Fee charged for the fill; negative for a rebate
+    pub fee: Decimal,
+    /// Currency of the fee, `None` when no fee schedule applies
+    pub fee_currency: Option<String>,
+    pub liquidity: Liquidity,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Candlestick (kline) interval
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CandleInterval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 4] = [
+        CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::OneHour,
+        CandleInterval::OneDay,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleInterval::OneMinute => "1m",
+            CandleInterval::FiveMinutes => "5m",
+            CandleInterval::OneHour => "1h",
+            CandleInterval::OneDay => "1d",
+        }
+    }
+
+    /// Interval length in seconds
+    pub fn seconds(&self) -> i64 {
+        match self {
+            CandleInterval::OneMinute => 60,
+            CandleInterval::FiveMinutes => 5 * 60,
+            CandleInterval::OneHour => 60 * 60,
+            CandleInterval::OneDay => 24 * 60 * 60,
+        }
+    }
+
+    /// Start of the candle containing `at`, aligned to the Unix epoch (UTC)
+    pub fn open_time(&self, at: DateTime<Utc>) -> DateTime<Utc> {
+        let seconds = at.timestamp();
+        let start = seconds - seconds.rem_euclid(self.seconds());
+        DateTime::from_timestamp(start, 0).unwrap_or(at)
+    }
+}
+
+/// OHLCV candlestick for one interval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    pub symbol: String,
+    pub interval: CandleInterval,
+    /// Inclusive start of the interval
+    pub open_time: DateTime<Utc>,
+    /// Exclusive end of the interval
+    pub close_time: DateTime<Utc>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    /// Base asset volume
+    pub volume: Decimal,
+    /// Quote asset volume (sum of price × quantity)
+    pub quote_volume: Decimal,
+    pub trade_count: u64,
+}
+
 /// Wallet balance information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Balance {
@@ -173,6 +782,81 @@ pub struct Balance {
     pub locked: Decimal,
 }
 
+/// A user's balance in one currency after a wallet operation changed it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceChange {
+    pub user_id: Uuid,
+    pub balance: Balance,
+}
+
+/// Funds locked in a wallet for an open order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub order_id: Uuid,
+    pub user_id: Uuid,
+    pub currency: String,
+    /// Amount still locked; trades settled against the order draw it down
+    pub amount: Decimal,
+    /// Lock the whole available balance instead of `amount`, for an order
+    /// whose cost is only known once it executes
+    #[serde(default)]
+    pub all_available: bool,
+}
+
+/// One side of a trade to settle between wallets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettlementParty {
+    pub user_id: Uuid,
+    /// Order whose reservation pays for the side
+    pub order_id: Uuid,
+    /// Fee owed; negative for a rebate
+    pub fee: Decimal,
+    /// Currency of the fee, `None` when no fee applies
+    pub fee_currency: Option<String>,
+}
+
+/// A trade to settle: the buyer pays `price × quantity` of the quote asset
+/// and receives `quantity` of the base asset, and the seller the reverse
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeSettlement {
+    pub trade_id: Uuid,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub buyer: SettlementParty,
+    pub seller: SettlementParty,
+}
+
+impl TradeSettlement {
+    /// Settlement of `trade` on a pair trading `base_asset` against `quote_asset`
+    pub fn for_trade(trade: &Trade, base_asset: &str, quote_asset: &str) -> Self {
+        let maker = SettlementParty {
+            user_id: trade.maker_user_id,
+            order_id: trade.maker_order_id,
+            fee: trade.maker_fee,
+            fee_currency: trade.maker_fee_currency.clone(),
+        };
+        let taker = SettlementParty {
+            user_id: trade.taker_user_id,
+            order_id: trade.taker_order_id,
+            fee: trade.taker_fee,
+            fee_currency: trade.taker_fee_currency.clone(),
+        };
+        let (buyer, seller) = if trade.is_buyer_maker { (maker, taker) } else { (taker, maker) };
+
+        Self {
+            trade_id: trade.id,
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            price: trade.price,
+            quantity: trade.quantity,
+            buyer,
+            seller,
+        }
+    }
+}
+
 /// Transaction information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
@@ -193,6 +877,8 @@ pub enum TransactionType {
     Withdrawal,
     Trade,
     Fee,
+    /// Internal transfer between two accounts
+    Transfer,
 }
 
 /// Transaction status enumeration
@@ -206,7 +892,7 @@ pub enum TransactionStatus {
 }
 
 /// API response wrapper
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
@@ -234,8 +920,83 @@ impl<T> ApiResponse<T> {
     }
 }
 
+/// Direction of a sorted listing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    /// Newest first
+    #[default]
+    Desc,
+}
+
+/// Position of an item in a listing sorted by time, ties broken by id.
+///
+/// Travels as an opaque string; clients pass back the `next_cursor` of one
+/// page to fetch the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Cursor {
+    pub timestamp: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        let nanos = cursor.timestamp.timestamp_nanos_opt().unwrap_or(i64::MAX);
+        format!("{}.{}", nanos, cursor.id.simple())
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = String;
+
+    fn try_from(cursor: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid cursor: {}", cursor);
+        let (nanos, id) = cursor.split_once('.').ok_or_else(invalid)?;
+        let timestamp = DateTime::from_timestamp_nanos(nanos.parse().map_err(|_| invalid())?);
+        let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+        Ok(Self { timestamp, id })
+    }
+}
+
+/// One page of a cursor-paginated listing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor of the next page, `None` on the last page
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// Sort `items` by their `position` in `sort` order and take at most
+    /// `limit` of those after `after`
+    pub fn paginate(
+        mut items: Vec<T>,
+        position: impl Fn(&T) -> Cursor,
+        sort: SortOrder,
+        after: Option<Cursor>,
+        limit: usize,
+    ) -> Self {
+        items.retain(|item| {
+            after.is_none_or(|after| match sort {
+                SortOrder::Asc => position(item) > after,
+                SortOrder::Desc => position(item) < after,
+            })
+        });
+        items.sort_by_key(&position);
+        if sort == SortOrder::Desc {
+            items.reverse();
+        }
+
+        let next_cursor = (limit > 0 && items.len() > limit).then(|| position(&items[limit - 1]));
+        items.truncate(limit);
+        Self { items, next_cursor }
+    }
+}
+
 /// Health check response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct HealthResponse {
     pub status: String,
     pub service: String,
@@ -261,6 +1022,9 @@ pub enum FlowExError {
     
     #[error("Trading error: {0}")]
     Trading(String),
+
+    #[error("Order constraint violation: {0}")]
+    OrderConstraint(#[from] OrderConstraintViolation),
     
     #[error("Market data error: {0}")]
     MarketData(String),
@@ -291,11 +1055,62 @@ pub struct ServiceConfig {
 pub struct JwtClaims {
     pub sub: String,        // Subject (user ID)
     pub email: String,      // User email
+    pub iss: String,        // Issuer
+    pub aud: String,        // Audience
     pub exp: usize,         // Expiration time
     pub iat: usize,         // Issued at
     pub jti: String,        // JWT ID (for token revocation)
     pub roles: Vec<String>, // User roles
     pub permissions: Vec<String>, // User permissions
+    /// State of the account when the token was issued
+    #[serde(flatten)]
+    pub account: AccountClaims,
+}
+
+/// Whether an account may act
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Refused every request until an administrator unfreezes it
+    Frozen,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+        }
+    }
+}
+
+impl std::str::FromStr for AccountStatus {
+    type Err = FlowExError;
+
+    fn from_str(s: &str) -> FlowExResult<Self> {
+        match s {
+            "active" => Ok(AccountStatus::Active),
+            "frozen" => Ok(AccountStatus::Frozen),
+            _ => Err(FlowExError::Validation(format!("Invalid account status: {}", s))),
+        }
+    }
+}
+
+/// Account state signed into tokens; tokens issued before these fields
+/// existed read as an active account at version 0
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountClaims {
+    #[serde(default)]
+    pub account_status: AccountStatus,
+    /// KYC level the user was verified to, e.g. `L1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kyc_tier: Option<String>,
+    /// Raised on every forced logout; tokens carrying a lower version are
+    /// stale and refused
+    #[serde(default)]
+    pub token_version: u64,
 }
 
 /// Authentication context
@@ -338,6 +1153,25 @@ pub enum Permission {
 }
 
 impl Permission {
+    /// Every permission
+    pub const ALL: [Permission; 15] = [
+        Permission::UserRead,
+        Permission::UserWrite,
+        Permission::UserDelete,
+        Permission::TradingRead,
+        Permission::TradingWrite,
+        Permission::TradingCancel,
+        Permission::WalletRead,
+        Permission::WalletDeposit,
+        Permission::WalletWithdraw,
+        Permission::AdminRead,
+        Permission::AdminWrite,
+        Permission::AdminDelete,
+        Permission::SystemRead,
+        Permission::SystemWrite,
+        Permission::SystemMaintenance,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Permission::UserRead => "user:read",
@@ -370,7 +1204,28 @@ pub enum Role {
     System,
 }
 
+impl std::str::FromStr for Permission {
+    type Err = FlowExError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.as_str() == s)
+            .ok_or_else(|| FlowExError::Validation(format!("Invalid permission: {}", s)))
+    }
+}
+
 impl Role {
+    /// Every built-in role
+    pub const ALL: [Role; 6] = [
+        Role::User,
+        Role::Trader,
+        Role::VipTrader,
+        Role::Admin,
+        Role::SuperAdmin,
+        Role::System,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Role::User => "user",
@@ -443,6 +1298,22 @@ impl Role {
     }
 }
 
+impl std::str::FromStr for Role {
+    type Err = FlowExError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "user" => Ok(Role::User),
+            "trader" => Ok(Role::Trader),
+            "vip_trader" => Ok(Role::VipTrader),
+            "admin" => Ok(Role::Admin),
+            "super_admin" => Ok(Role::SuperAdmin),
+            "system" => Ok(Role::System),
+            _ => Err(FlowExError::Validation(format!("Invalid role: {}", s))),
+        }
+    }
+}
+
 /// Metrics data structure
 #[derive(Debug, Clone, Serialize)]
 pub struct ServiceMetrics {
@@ -475,6 +1346,65 @@ mod tests {
         assert_eq!(user, deserialized);
     }
 
+    #[test]
+    fn test_page_through_cursor() {
+        let start = Utc::now();
+        let positions: Vec<Cursor> = (0..5)
+            .map(|i| Cursor { timestamp: start + chrono::Duration::seconds(i), id: Uuid::new_v4() })
+            .collect();
+
+        let first = Page::paginate(positions.clone(), |cursor| *cursor, SortOrder::Desc, None, 2);
+        assert_eq!(first.items, vec![positions[4], positions[3]]);
+        let cursor: String = first.next_cursor.unwrap().into();
+
+        let after = Cursor::try_from(cursor).unwrap();
+        let second = Page::paginate(positions.clone(), |cursor| *cursor, SortOrder::Desc, Some(after), 2);
+        assert_eq!(second.items, vec![positions[2], positions[1]]);
+
+        let last = Page::paginate(positions.clone(), |cursor| *cursor, SortOrder::Desc, second.next_cursor, 2);
+        assert_eq!(last.items, vec![positions[0]]);
+        assert!(last.next_cursor.is_none());
+
+        let ascending = Page::paginate(positions.clone(), |cursor| *cursor, SortOrder::Asc, None, 10);
+        assert_eq!(ascending.items, positions);
+        assert!(Cursor::try_from("not-a-cursor".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_trade_fills() {
+        let trade = Trade {
+            id: Uuid::new_v4(),
+            symbol: "BTC-USDT".to_string(),
+            sequence: 1,
+            price: Decimal::new(50000, 0),
+            quantity: Decimal::new(2, 1),
+            side: OrderSide::Sell,
+            maker_order_id: Uuid::new_v4(),
+            taker_order_id: Uuid::new_v4(),
+            maker_user_id: Uuid::new_v4(),
+            taker_user_id: Uuid::new_v4(),
+            is_buyer_maker: true,
+            maker_fee: Decimal::new(-1, 0),
+            maker_fee_currency: Some("USDT".to_string()),
+            taker_fee: Decimal::new(5, 0),
+            taker_fee_currency: Some("USDT".to_string()),
+            timestamp: Utc::now(),
+        };
+
+        let [maker, taker] = trade.fills();
+        assert_eq!((maker.order_id, maker.user_id), (trade.maker_order_id, trade.maker_user_id));
+        assert_eq!((maker.side, maker.liquidity, maker.fee), (OrderSide::Buy, Liquidity::Maker, Decimal::new(-1, 0)));
+        assert_eq!((taker.order_id, taker.user_id), (trade.taker_order_id, trade.taker_user_id));
+        assert_eq!((taker.side, taker.liquidity, taker.fee), (OrderSide::Sell, Liquidity::Taker, Decimal::new(5, 0)));
+        assert_eq!(taker.quantity, trade.quantity);
+        assert_eq!(serde_json::to_value(taker.liquidity).unwrap(), "taker");
+
+        let public = trade.anonymized();
+        assert!(public.maker_user_id.is_nil() && public.taker_user_id.is_nil());
+        assert_eq!(public.taker_fee, Decimal::ZERO);
+        assert_eq!((public.price, public.quantity), (trade.price, trade.quantity));
+    }
+
     #[test]
     fn test_api_response() {
         let response = ApiResponse::success("test data");
@@ -487,4 +1417,49 @@ mod tests {
         assert!(error_response.data.is_none());
         assert_eq!(error_response.error, Some("test error".to_string()));
     }
+
+    #[test]
+    fn test_order_book_apply_update() {
+        let level = |price: i64, quantity: i64| OrderBookLevel {
+            price: Decimal::new(price, 0),
+            quantity: Decimal::new(quantity, 0),
+        };
+        let delta = |side: OrderSide, price: i64, quantity: i64| BookDelta {
+            side,
+            price: Decimal::new(price, 0),
+            quantity: Decimal::new(quantity, 0),
+        };
+        let mut book = OrderBook {
+            symbol: "BTCUSDT".to_string(),
+            sequence: 5,
+            bids: vec![level(99, 1), level(97, 1)],
+            asks: vec![level(101, 1)],
+            timestamp: Utc::now(),
+        };
+        let mut update = BookUpdate {
+            symbol: "BTCUSDT".to_string(),
+            sequence: 7,
+            previous_sequence: 4,
+            deltas: vec![
+                delta(OrderSide::Buy, 98, 2),
+                delta(OrderSide::Buy, 99, 0),
+                delta(OrderSide::Sell, 101, 3),
+                delta(OrderSide::Sell, 102, 0),
+            ],
+            timestamp: Utc::now(),
+        };
+
+        assert!(book.apply_update(&update).unwrap());
+        assert_eq!(book.sequence, 7);
+        let bids: Vec<Decimal> = book.bids.iter().map(|level| level.price).collect();
+        assert_eq!(bids, vec![Decimal::new(98, 0), Decimal::new(97, 0)]);
+        assert_eq!(book.asks[0].quantity, Decimal::new(3, 0));
+        assert_eq!(book.asks.len(), 1);
+
+        // Already applied, then a gap
+        assert!(!book.apply_update(&update).unwrap());
+        update.sequence = 10;
+        update.previous_sequence = 9;
+        assert!(book.apply_update(&update).is_err());
+    }
 }
@@ -0,0 +1,128 @@
+//! Scale-out across WebSocket manager instances
+//!
+//! Behind a load balancer each instance holds only some of the clients, so
+//! every event published on one instance must reach the others. A manager
+//! with an `EventBus` forwards the market data and user events it publishes
+//! as `BridgeEvent`s; each instance feeds the events it receives back in
+//! with `WebSocketManager::receive_bridged`, which delivers them to its own
+//! clients without forwarding them again. `RedisBus` and `relay_from_redis`
+//! carry the events over a Redis pub/sub channel through `flowex-cache`.
+//!
+//! User events travel already numbered, and every instance adopts the
+//! origin's sequence, so a client can resume on any instance. Each user's
+//! events must therefore be published by a single instance, as the trading
+//! service does. An instance that starts after a book snapshot was published
+//! drops that book's deltas until the next snapshot.
+
+use crate::{WebSocketManager, WsMessage};
+use flowex_cache::{CacheError, CacheManager};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Redis channel the WebSocket managers share events on
+pub const BRIDGE_CHANNEL: &str = "flowex:ws:events";
+
+/// Events buffered between the Redis connection and the manager
+const RELAY_CAPACITY: usize = 10_000;
+
+/// Expiry of cache entries; the bridge only uses pub/sub
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// An event published on one instance, for the clients of all the others
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BridgeEvent {
+    /// Instance the event was published on
+    pub origin: Uuid,
+    pub payload: BridgePayload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BridgePayload {
+    /// Market data, as broadcast to subscribers
+    Market { message: WsMessage },
+    /// A numbered `UserEvent` for one user's connections
+    User { user_id: Uuid, event: WsMessage },
+}
+
+/// Carries the events a manager publishes to the other instances
+pub trait EventBus: Debug + Send + Sync {
+    /// Send an event on without waiting for delivery
+    fn publish(&self, event: &BridgeEvent);
+}
+
+/// Publishes bridge events on a Redis channel
+#[derive(Debug)]
+pub struct RedisBus {
+    tx: mpsc::UnboundedSender<String>,
+}
+
+impl RedisBus {
+    /// Publish through `cache` on `channel`, in order, from a background task
+    pub fn new(cache: CacheManager, channel: &str) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        let channel = channel.to_string();
+        tokio::spawn(async move {
+            while let Some(payload) = rx.recv().await {
+                if let Err(e) = cache.publish(&channel, &payload).await {
+                    warn!("Failed to publish WebSocket event on {}: {}", channel, e);
+                }
+            }
+        });
+        Self { tx }
+    }
+}
+
+impl EventBus for RedisBus {
+    fn publish(&self, event: &BridgeEvent) {
+        match serde_json::to_string(event) {
+            Ok(payload) => {
+                if self.tx.send(payload).is_err() {
+                    warn!("WebSocket event publisher has stopped");
+                }
+            }
+            Err(e) => warn!("Failed to encode WebSocket event: {}", e),
+        }
+    }
+}
+
+/// Join the instances sharing events through the Redis server at
+/// `redis_url`: the returned manager publishes its events on
+/// `BRIDGE_CHANNEL`, and a background task relays the others' to it
+pub async fn connect_redis(manager: WebSocketManager, redis_url: &str) -> Result<WebSocketManager, CacheError> {
+    let cache = CacheManager::new(redis_url, CACHE_TTL).await?;
+    let manager = manager.with_bus(Arc::new(RedisBus::new(cache.clone(), BRIDGE_CHANNEL)));
+
+    let relay = manager.clone();
+    tokio::spawn(async move {
+        if let Err(e) = relay_from_redis(&cache, BRIDGE_CHANNEL, relay).await {
+            warn!("WebSocket event relay stopped: {}", e);
+        }
+    });
+    info!("WebSocket manager {} bridged over Redis", manager.instance_id());
+    Ok(manager)
+}
+
+/// Deliver the events other instances publish on `channel` to `manager`'s
+/// clients. Runs until the Redis subscription ends.
+pub async fn relay_from_redis(cache: &CacheManager, channel: &str, manager: WebSocketManager) -> Result<(), CacheError> {
+    let mut payloads = cache.subscribe(channel, RELAY_CAPACITY).await?;
+    while let Some(payload) = payloads.recv().await {
+        let event = match serde_json::from_str::<BridgeEvent>(&payload) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Dropping malformed WebSocket event on {}: {}", channel, e);
+                continue;
+            }
+        };
+        if let Err(e) = manager.receive_bridged(event).await {
+            debug!("Failed to deliver bridged WebSocket event: {}", e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,702 @@
+//! Frame encodings
+//!
+//! JSON text frames are the default. At high tick rates serializing JSON
+//! dominates the server's CPU, so a client may instead negotiate binary
+//! frames by offering a WebSocket subprotocol: `flowex.msgpack` for
+//! MessagePack, with the same field names and structure as the JSON, or
+//! `flowex.protobuf` for the `Envelope` message of `proto/flowex_ws.proto`.
+//! The encoding applies to frames the server sends; client requests are
+//! always JSON text frames.
+
+use crate::WsMessage;
+use axum::extract::ws::Message;
+use flowex_types::{BookUpdate, Candle, Fill, IndexPrice, Order, OrderBook, Ticker, Trade};
+use prost::Message as _;
+use serde::Serialize;
+
+/// Encoding of the frames sent to a client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+    Protobuf,
+}
+
+impl Encoding {
+    /// Subprotocols a client may offer, in the server's order of preference
+    pub const SUBPROTOCOLS: [&'static str; 3] = ["flowex.protobuf", "flowex.msgpack", "flowex.json"];
+
+    /// Encoding selected by a negotiated subprotocol
+    pub fn from_subprotocol(protocol: &str) -> Option<Self> {
+        match protocol {
+            "flowex.json" => Some(Self::Json),
+            "flowex.msgpack" => Some(Self::MessagePack),
+            "flowex.protobuf" => Some(Self::Protobuf),
+            _ => None,
+        }
+    }
+
+    /// Encode a message as a WebSocket frame
+    pub fn encode(self, message: &WsMessage) -> Message {
+        match self {
+            Self::Json => Message::Text(serde_json::to_string(message).unwrap_or_default()),
+            Self::MessagePack => {
+                let mut bytes = Vec::new();
+                let mut serializer = rmp_serde::Serializer::new(&mut bytes).with_struct_map().with_human_readable();
+                if message.serialize(&mut serializer).is_err() {
+                    bytes.clear();
+                }
+                Message::Binary(bytes)
+            }
+            Self::Protobuf => Message::Binary(proto::Envelope::from(message).encode_to_vec()),
+        }
+    }
+}
+
+/// The string a unit enum variant serializes to in JSON
+fn name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// Messages of `proto/flowex_ws.proto`
+pub mod proto {
+    use super::name;
+    use crate::WsMessage;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Envelope {
+        #[prost(oneof = "Payload", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19")]
+        pub payload: Option<Payload>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Payload {
+        #[prost(message, tag = "1")]
+        Subscribe(Channels),
+        #[prost(message, tag = "2")]
+        Unsubscribe(Channels),
+        #[prost(message, tag = "3")]
+        Resync(Resync),
+        #[prost(message, tag = "4")]
+        OrderBookUpdate(OrderBook),
+        #[prost(message, tag = "5")]
+        OrderBookSnapshot(OrderBook),
+        #[prost(message, tag = "6")]
+        OrderBookDelta(BookUpdate),
+        #[prost(message, tag = "7")]
+        CandleUpdate(CandleUpdate),
+        #[prost(message, tag = "8")]
+        TickerUpdate(Ticker),
+        #[prost(message, tag = "9")]
+        TradeUpdate(Trade),
+        #[prost(message, tag = "10")]
+        OrderUpdate(Order),
+        #[prost(message, tag = "11")]
+        BalanceUpdate(BalanceUpdate),
+        #[prost(message, tag = "12")]
+        Ping(Empty),
+        #[prost(message, tag = "13")]
+        Pong(Empty),
+        #[prost(message, tag = "14")]
+        ResyncRequired(ResyncRequired),
+        #[prost(message, tag = "15")]
+        Error(Error),
+        #[prost(message, tag = "16")]
+        Success(Success),
+        #[prost(message, tag = "17")]
+        Resume(Resume),
+        #[prost(message, tag = "18")]
+        UserEvent(Box<UserEvent>),
+        #[prost(message, tag = "19")]
+        FillUpdate(Fill),
+        #[prost(message, tag = "20")]
+        IndexPriceUpdate(IndexPrice),
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Empty {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Channels {
+        #[prost(string, repeated, tag = "1")]
+        pub channels: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Resync {
+        #[prost(string, tag = "1")]
+        pub symbol: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Resume {
+        #[prost(uint64, tag = "1")]
+        pub from_seq: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UserEvent {
+        #[prost(uint64, tag = "1")]
+        pub seq: u64,
+        #[prost(message, optional, boxed, tag = "2")]
+        pub event: Option<Box<Envelope>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Level {
+        #[prost(string, tag = "1")]
+        pub price: String,
+        #[prost(string, tag = "2")]
+        pub quantity: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct OrderBook {
+        #[prost(string, tag = "1")]
+        pub symbol: String,
+        #[prost(uint64, tag = "2")]
+        pub sequence: u64,
+        #[prost(message, repeated, tag = "3")]
+        pub bids: Vec<Level>,
+        #[prost(message, repeated, tag = "4")]
+        pub asks: Vec<Level>,
+        #[prost(int64, tag = "5")]
+        pub timestamp: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BookDelta {
+        #[prost(string, tag = "1")]
+        pub side: String,
+        #[prost(string, tag = "2")]
+        pub price: String,
+        #[prost(string, tag = "3")]
+        pub quantity: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BookUpdate {
+        #[prost(string, tag = "1")]
+        pub symbol: String,
+        #[prost(uint64, tag = "2")]
+        pub sequence: u64,
+        #[prost(uint64, tag = "3")]
+        pub previous_sequence: u64,
+        #[prost(message, repeated, tag = "4")]
+        pub deltas: Vec<BookDelta>,
+        #[prost(int64, tag = "5")]
+        pub timestamp: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Candle {
+        #[prost(string, tag = "1")]
+        pub symbol: String,
+        #[prost(string, tag = "2")]
+        pub interval: String,
+        #[prost(int64, tag = "3")]
+        pub open_time: i64,
+        #[prost(int64, tag = "4")]
+        pub close_time: i64,
+        #[prost(string, tag = "5")]
+        pub open: String,
+        #[prost(string, tag = "6")]
+        pub high: String,
+        #[prost(string, tag = "7")]
+        pub low: String,
+        #[prost(string, tag = "8")]
+        pub close: String,
+        #[prost(string, tag = "9")]
+        pub volume: String,
+        #[prost(string, tag = "10")]
+        pub quote_volume: String,
+        #[prost(uint64, tag = "11")]
+        pub trade_count: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CandleUpdate {
+        #[prost(message, optional, tag = "1")]
+        pub candle: Option<Candle>,
+        #[prost(bool, tag = "2")]
+        pub closed: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Ticker {
+        #[prost(string, tag = "1")]
+        pub symbol: String,
+        #[prost(string, tag = "2")]
+        pub price: String,
+        #[prost(string, tag = "3")]
+        pub change: String,
+        #[prost(string, tag = "4")]
+        pub change_percent: String,
+        #[prost(string, tag = "5")]
+        pub high: String,
+        #[prost(string, tag = "6")]
+        pub low: String,
+        #[prost(string, tag = "7")]
+        pub volume: String,
+        #[prost(string, optional, tag = "8")]
+        pub vwap: Option<String>,
+        #[prost(int64, tag = "9")]
+        pub timestamp: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct IndexComponent {
+        #[prost(string, tag = "1")]
+        pub exchange: String,
+        #[prost(string, tag = "2")]
+        pub price: String,
+        #[prost(int64, tag = "3")]
+        pub timestamp: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct IndexPrice {
+        #[prost(string, tag = "1")]
+        pub symbol: String,
+        #[prost(string, tag = "2")]
+        pub price: String,
+        #[prost(message, repeated, tag = "3")]
+        pub components: Vec<IndexComponent>,
+        #[prost(int64, tag = "4")]
+        pub timestamp: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Trade {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub symbol: String,
+        #[prost(uint64, tag = "3")]
+        pub sequence: u64,
+        #[prost(string, tag = "4")]
+        pub price: String,
+        #[prost(string, tag = "5")]
+        pub quantity: String,
+        #[prost(string, tag = "6")]
+        pub side: String,
+        #[prost(string, tag = "7")]
+        pub maker_order_id: String,
+        #[prost(string, tag = "8")]
+        pub taker_order_id: String,
+        #[prost(string, tag = "9")]
+        pub maker_user_id: String,
+        #[prost(string, tag = "10")]
+        pub taker_user_id: String,
+        #[prost(bool, tag = "11")]
+        pub is_buyer_maker: bool,
+        #[prost(string, tag = "12")]
+        pub maker_fee: String,
+        #[prost(string, optional, tag = "13")]
+        pub maker_fee_currency: Option<String>,
+        #[prost(string, tag = "14")]
+        pub taker_fee: String,
+        #[prost(string, optional, tag = "15")]
+        pub taker_fee_currency: Option<String>,
+        #[prost(int64, tag = "16")]
+        pub timestamp: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Order {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub user_id: String,
+        #[prost(string, tag = "3")]
+        pub trading_pair: String,
+        #[prost(string, tag = "4")]
+        pub side: String,
+        #[prost(string, tag = "5")]
+        pub order_type: String,
+        #[prost(string, optional, tag = "6")]
+        pub price: Option<String>,
+        #[prost(string, optional, tag = "7")]
+        pub stop_price: Option<String>,
+        #[prost(string, tag = "8")]
+        pub quantity: String,
+        #[prost(string, tag = "9")]
+        pub filled_quantity: String,
+        #[prost(string, tag = "10")]
+        pub remaining_quantity: String,
+        #[prost(string, tag = "11")]
+        pub time_in_force: String,
+        #[prost(bool, tag = "12")]
+        pub post_only: bool,
+        #[prost(string, optional, tag = "13")]
+        pub display_quantity: Option<String>,
+        #[prost(int64, optional, tag = "14")]
+        pub expires_at: Option<i64>,
+        #[prost(bool, tag = "15")]
+        pub reduce_only: bool,
+        #[prost(string, optional, tag = "16")]
+        pub client_order_id: Option<String>,
+        #[prost(string, tag = "17")]
+        pub status: String,
+        #[prost(int64, tag = "18")]
+        pub created_at: i64,
+        #[prost(int64, tag = "19")]
+        pub updated_at: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Fill {
+        #[prost(string, tag = "1")]
+        pub trade_id: String,
+        #[prost(string, tag = "2")]
+        pub order_id: String,
+        #[prost(string, tag = "3")]
+        pub user_id: String,
+        #[prost(string, tag = "4")]
+        pub symbol: String,
+        #[prost(string, tag = "5")]
+        pub side: String,
+        #[prost(string, tag = "6")]
+        pub price: String,
+        #[prost(string, tag = "7")]
+        pub quantity: String,
+        #[prost(string, tag = "8")]
+        pub fee: String,
+        #[prost(string, optional, tag = "9")]
+        pub fee_currency: Option<String>,
+        #[prost(string, tag = "10")]
+        pub liquidity: String,
+        #[prost(int64, tag = "11")]
+        pub timestamp: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BalanceUpdate {
+        #[prost(string, tag = "1")]
+        pub currency: String,
+        #[prost(string, tag = "2")]
+        pub available: String,
+        #[prost(string, tag = "3")]
+        pub locked: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResyncRequired {
+        #[prost(uint64, tag = "1")]
+        pub skipped: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Error {
+        #[prost(string, tag = "1")]
+        pub code: String,
+        #[prost(string, tag = "2")]
+        pub message: String,
+        #[prost(string, repeated, tag = "3")]
+        pub channels: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Success {
+        #[prost(string, tag = "1")]
+        pub message: String,
+        #[prost(string, repeated, tag = "2")]
+        pub channels: Vec<String>,
+    }
+
+    impl From<&WsMessage> for Envelope {
+        fn from(message: &WsMessage) -> Self {
+            let payload = match message {
+                WsMessage::Subscribe { channels } => Payload::Subscribe(Channels { channels: channels.clone() }),
+                WsMessage::Unsubscribe { channels } => Payload::Unsubscribe(Channels { channels: channels.clone() }),
+                WsMessage::Resync { symbol } => Payload::Resync(Resync { symbol: symbol.clone() }),
+                WsMessage::Resume { from_seq } => Payload::Resume(Resume { from_seq: *from_seq }),
+                WsMessage::OrderBookUpdate(book) => Payload::OrderBookUpdate(book.into()),
+                WsMessage::OrderBookSnapshot(book) => Payload::OrderBookSnapshot(book.into()),
+                WsMessage::OrderBookDelta(update) => Payload::OrderBookDelta(update.into()),
+                WsMessage::CandleUpdate { candle, closed } => Payload::CandleUpdate(CandleUpdate {
+                    candle: Some(candle.into()),
+                    closed: *closed,
+                }),
+                WsMessage::TickerUpdate(ticker) => Payload::TickerUpdate(ticker.into()),
+                WsMessage::TradeUpdate(trade) => Payload::TradeUpdate(trade.into()),
+                WsMessage::IndexPriceUpdate(index) => Payload::IndexPriceUpdate(index.into()),
+                WsMessage::OrderUpdate(order) => Payload::OrderUpdate(order.into()),
+                WsMessage::FillUpdate(fill) => Payload::FillUpdate(fill.into()),
+                WsMessage::BalanceUpdate { currency, available, locked } => Payload::BalanceUpdate(BalanceUpdate {
+                    currency: currency.clone(),
+                    available: available.clone(),
+                    locked: locked.clone(),
+                }),
+                WsMessage::UserEvent { seq, event } => Payload::UserEvent(Box::new(UserEvent {
+                    seq: *seq,
+                    event: Some(Box::new(event.as_ref().into())),
+                })),
+                WsMessage::Ping => Payload::Ping(Empty {}),
+                WsMessage::Pong => Payload::Pong(Empty {}),
+                WsMessage::ResyncRequired { skipped } => Payload::ResyncRequired(ResyncRequired { skipped: *skipped }),
+                WsMessage::Error { code, message, channels } => Payload::Error(Error {
+                    code: name(code),
+                    message: message.clone(),
+                    channels: channels.clone(),
+                }),
+                WsMessage::Success { message, channels } => Payload::Success(Success {
+                    message: message.clone(),
+                    channels: channels.clone(),
+                }),
+            };
+            Self { payload: Some(payload) }
+        }
+    }
+
+    impl From<&super::OrderBook> for OrderBook {
+        fn from(book: &super::OrderBook) -> Self {
+            let levels = |levels: &[flowex_types::OrderBookLevel]| {
+                levels
+                    .iter()
+                    .map(|level| Level {
+                        price: level.price.to_string(),
+                        quantity: level.quantity.to_string(),
+                    })
+                    .collect()
+            };
+            Self {
+                symbol: book.symbol.clone(),
+                sequence: book.sequence,
+                bids: levels(&book.bids),
+                asks: levels(&book.asks),
+                timestamp: book.timestamp.timestamp_millis(),
+            }
+        }
+    }
+
+    impl From<&super::BookUpdate> for BookUpdate {
+        fn from(update: &super::BookUpdate) -> Self {
+            Self {
+                symbol: update.symbol.clone(),
+                sequence: update.sequence,
+                previous_sequence: update.previous_sequence,
+                deltas: update
+                    .deltas
+                    .iter()
+                    .map(|delta| BookDelta {
+                        side: name(&delta.side),
+                        price: delta.price.to_string(),
+                        quantity: delta.quantity.to_string(),
+                    })
+                    .collect(),
+                timestamp: update.timestamp.timestamp_millis(),
+            }
+        }
+    }
+
+    impl From<&super::Candle> for Candle {
+        fn from(candle: &super::Candle) -> Self {
+            Self {
+                symbol: candle.symbol.clone(),
+                interval: name(&candle.interval),
+                open_time: candle.open_time.timestamp_millis(),
+                close_time: candle.close_time.timestamp_millis(),
+                open: candle.open.to_string(),
+                high: candle.high.to_string(),
+                low: candle.low.to_string(),
+                close: candle.close.to_string(),
+                volume: candle.volume.to_string(),
+                quote_volume: candle.quote_volume.to_string(),
+                trade_count: candle.trade_count,
+            }
+        }
+    }
+
+    impl From<&super::Ticker> for Ticker {
+        fn from(ticker: &super::Ticker) -> Self {
+            Self {
+                symbol: ticker.symbol.clone(),
+                price: ticker.price.to_string(),
+                change: ticker.change.to_string(),
+                change_percent: ticker.change_percent.to_string(),
+                high: ticker.high.to_string(),
+                low: ticker.low.to_string(),
+                volume: ticker.volume.to_string(),
+                vwap: ticker.vwap.map(|vwap| vwap.to_string()),
+                timestamp: ticker.timestamp.timestamp_millis(),
+            }
+        }
+    }
+
+    impl From<&super::IndexPrice> for IndexPrice {
+        fn from(index: &super::IndexPrice) -> Self {
+            Self {
+                symbol: index.symbol.clone(),
+                price: index.price.to_string(),
+                components: index
+                    .components
+                    .iter()
+                    .map(|component| IndexComponent {
+                        exchange: component.exchange.clone(),
+                        price: component.price.to_string(),
+                        timestamp: component.timestamp.timestamp_millis(),
+                    })
+                    .collect(),
+                timestamp: index.timestamp.timestamp_millis(),
+            }
+        }
+    }
+
+    impl From<&super::Trade> for Trade {
+        fn from(trade: &super::Trade) -> Self {
+            Self {
+                id: trade.id.to_string(),
+                symbol: trade.symbol.clone(),
+                sequence: trade.sequence,
+                price: trade.price.to_string(),
+                quantity: trade.quantity.to_string(),
+                side: name(&trade.side),
+                maker_order_id: trade.maker_order_id.to_string(),
+                taker_order_id: trade.taker_order_id.to_string(),
+                maker_user_id: trade.maker_user_id.to_string(),
+                taker_user_id: trade.taker_user_id.to_string(),
+                is_buyer_maker: trade.is_buyer_maker,
+                maker_fee: trade.maker_fee.to_string(),
+                maker_fee_currency: trade.maker_fee_currency.clone(),
+                taker_fee: trade.taker_fee.to_string(),
+                taker_fee_currency: trade.taker_fee_currency.clone(),
+                timestamp: trade.timestamp.timestamp_millis(),
+            }
+        }
+    }
+
+    impl From<&super::Order> for Order {
+        fn from(order: &super::Order) -> Self {
+            Self {
+                id: order.id.to_string(),
+                user_id: order.user_id.to_string(),
+                trading_pair: order.trading_pair.clone(),
+                side: name(&order.side),
+                order_type: name(&order.order_type),
+                price: order.price.map(|price| price.to_string()),
+                stop_price: order.stop_price.map(|price| price.to_string()),
+                quantity: order.quantity.to_string(),
+                filled_quantity: order.filled_quantity.to_string(),
+                remaining_quantity: order.remaining_quantity.to_string(),
+                time_in_force: name(&order.time_in_force),
+                post_only: order.post_only,
+                display_quantity: order.display_quantity.map(|quantity| quantity.to_string()),
+                expires_at: order.expires_at.map(|at| at.timestamp_millis()),
+                reduce_only: order.reduce_only,
+                client_order_id: order.client_order_id.clone(),
+                status: name(&order.status),
+                created_at: order.created_at.timestamp_millis(),
+                updated_at: order.updated_at.timestamp_millis(),
+            }
+        }
+    }
+
+    impl From<&super::Fill> for Fill {
+        fn from(fill: &super::Fill) -> Self {
+            Self {
+                trade_id: fill.trade_id.to_string(),
+                order_id: fill.order_id.to_string(),
+                user_id: fill.user_id.to_string(),
+                symbol: fill.symbol.clone(),
+                side: name(&fill.side),
+                price: fill.price.to_string(),
+                quantity: fill.quantity.to_string(),
+                fee: fill.fee.to_string(),
+                fee_currency: fill.fee_currency.clone(),
+                liquidity: name(&fill.liquidity),
+                timestamp: fill.timestamp.timestamp_millis(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WsErrorCode;
+    use chrono::Utc;
+    use flowex_types::OrderSide;
+    use rust_decimal::Decimal;
+    use uuid::Uuid;
+
+    fn trade() -> Trade {
+        Trade {
+            id: Uuid::new_v4(),
+            symbol: "BTCUSDT".to_string(),
+            sequence: 42,
+            price: Decimal::new(5_000_025, 2),
+            quantity: Decimal::new(15, 1),
+            side: OrderSide::Buy,
+            maker_order_id: Uuid::new_v4(),
+            taker_order_id: Uuid::new_v4(),
+            maker_user_id: Uuid::new_v4(),
+            taker_user_id: Uuid::new_v4(),
+            is_buyer_maker: false,
+            maker_fee: Decimal::ZERO,
+            maker_fee_currency: None,
+            taker_fee: Decimal::new(1, 1),
+            taker_fee_currency: Some("USDT".to_string()),
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_subprotocol_negotiation() {
+        for protocol in Encoding::SUBPROTOCOLS {
+            assert!(Encoding::from_subprotocol(protocol).is_some());
+        }
+        assert_eq!(Encoding::from_subprotocol("flowex.msgpack"), Some(Encoding::MessagePack));
+        assert_eq!(Encoding::from_subprotocol("graphql-ws"), None);
+        assert!(matches!(Encoding::default().encode(&WsMessage::Pong), Message::Text(_)));
+    }
+
+    #[test]
+    fn test_msgpack_matches_json_structure() {
+        let message = WsMessage::TradeUpdate(trade());
+        let Message::Binary(bytes) = Encoding::MessagePack.encode(&message) else {
+            panic!("expected a binary frame");
+        };
+        let decoded: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(decoded, serde_json::to_value(&message).unwrap());
+    }
+
+    #[test]
+    fn test_protobuf_encoding() {
+        let trade = trade();
+        let Message::Binary(bytes) = Encoding::Protobuf.encode(&WsMessage::TradeUpdate(trade.clone())) else {
+            panic!("expected a binary frame");
+        };
+        let Some(proto::Payload::TradeUpdate(decoded)) = proto::Envelope::decode(bytes.as_slice()).unwrap().payload else {
+            panic!("expected a trade");
+        };
+        assert_eq!(decoded.id, trade.id.to_string());
+        assert_eq!(decoded.price, "50000.25");
+        assert_eq!(decoded.side, "buy");
+        assert_eq!(decoded.taker_fee_currency.as_deref(), Some("USDT"));
+        assert_eq!(decoded.maker_fee_currency, None);
+        assert_eq!(decoded.timestamp, trade.timestamp.timestamp_millis());
+
+        let error = WsMessage::Error {
+            code: WsErrorCode::RateLimited,
+            message: "slow down".to_string(),
+            channels: Vec::new(),
+        };
+        let Message::Binary(bytes) = Encoding::Protobuf.encode(&error) else {
+            panic!("expected a binary frame");
+        };
+        let envelope = proto::Envelope::decode(bytes.as_slice()).unwrap();
+        assert_eq!(
+            envelope.payload,
+            Some(proto::Payload::Error(proto::Error {
+                code: "rate_limited".to_string(),
+                message: "slow down".to_string(),
+                channels: Vec::new(),
+            }))
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Server-side channel filtering
+//!
+//! A subscription may carry query parameters that thin the stream before it
+//! leaves the server: `orderbook.BTCUSDT?depth=20&interval=100ms` sends the
+//! top 20 levels per side at most every 100ms. Updates arriving within the
+//! interval are conflated, so the client always ends up with the latest
+//! state. Only full-state channels can be thinned out this way: `depth`
+//! applies to `orderbook.<symbol>` and `interval` to `orderbook.<symbol>`
+//! and `ticker.<symbol>` (or `ticker.all`), and to patterns over them.
+//! Incremental books, trades and candles must arrive in full.
+
+use crate::{WsMessage, ORDER_BOOK_DELTA_CHANNEL};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Filtering requested with a subscription
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelOptions {
+    /// Book levels sent per side
+    pub depth: Option<usize>,
+    /// Least time between two messages on the channel
+    pub interval: Option<Duration>,
+}
+
+/// The channel or pattern of a subscription, without its parameters
+pub fn channel_name(subscription: &str) -> &str {
+    subscription.split_once('?').map_or(subscription, |(channel, _)| channel)
+}
+
+/// Split a subscription into its channel and options, or `None` if a
+/// parameter is malformed, unknown or not supported by the channel
+pub fn parse_subscription(subscription: &str) -> Option<(&str, ChannelOptions)> {
+    let Some((channel, query)) = subscription.split_once('?') else {
+        return Some((subscription, ChannelOptions::default()));
+    };
+
+    let full_book = channel.starts_with("orderbook.") && !channel.starts_with(ORDER_BOOK_DELTA_CHANNEL);
+    let ticker = channel.starts_with("ticker.");
+    let mut options = ChannelOptions::default();
+    for param in query.split('&') {
+        match param.split_once('=')? {
+            ("depth", depth) if full_book => {
+                options.depth = Some(depth.parse().ok().filter(|depth| *depth > 0)?);
+            }
+            ("interval", interval) if full_book || ticker => {
+                options.interval = Some(parse_interval(interval)?);
+            }
+            _ => return None,
+        }
+    }
+    Some((channel, options))
+}
+
+/// Parse an interval such as `100ms` or `1s`
+fn parse_interval(interval: &str) -> Option<Duration> {
+    let duration = if let Some(millis) = interval.strip_suffix("ms") {
+        Duration::from_millis(millis.parse().ok()?)
+    } else {
+        Duration::from_secs(interval.strip_suffix('s')?.parse().ok()?)
+    };
+    (!duration.is_zero()).then_some(duration)
+}
+
+/// Keep only the top `depth` levels of each side of a full book message
+pub fn truncate_depth(message: WsMessage, depth: usize) -> WsMessage {
+    match message {
+        WsMessage::OrderBookUpdate(mut book) => {
+            book.bids.truncate(depth);
+            book.asks.truncate(depth);
+            WsMessage::OrderBookUpdate(book)
+        }
+        message => message,
+    }
+}
+
+/// A connection's rate-limited channels: when each last sent a message,
+/// and the latest message held back on each until its interval is up
+#[derive(Debug, Default)]
+pub(crate) struct Throttle {
+    last_sent: HashMap<String, Instant>,
+    pending: HashMap<String, (Instant, WsMessage)>,
+}
+
+impl Throttle {
+    /// Pass `message` on if `channel` has been quiet for `interval`,
+    /// otherwise hold it back in place of any message already held
+    pub(crate) fn offer(&mut self, channel: String, interval: Duration, message: WsMessage, now: Instant) -> Option<WsMessage> {
+        match self.last_sent.get(&channel) {
+            Some(last) if now < *last + interval => {
+                self.pending.insert(channel, (*last + interval, message));
+                None
+            }
+            _ => {
+                self.pending.remove(&channel);
+                self.last_sent.insert(channel, now);
+                Some(message)
+            }
+        }
+    }
+
+    /// When the next held back message is due
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.pending.values().map(|(due, _)| *due).min()
+    }
+
+    /// Take the held back messages that are due by `now`
+    pub(crate) fn take_due(&mut self, now: Instant) -> Vec<WsMessage> {
+        let due: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, (due, _))| *due <= now)
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        due.into_iter()
+            .filter_map(|channel| {
+                let (_, message) = self.pending.remove(&channel)?;
+                self.last_sent.insert(channel, now);
+                Some(message)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subscription() {
+        let (channel, options) = parse_subscription("orderbook.BTCUSDT?depth=20&interval=100ms").unwrap();
+        assert_eq!(channel, "orderbook.BTCUSDT");
+        assert_eq!(options.depth, Some(20));
+        assert_eq!(options.interval, Some(Duration::from_millis(100)));
+
+        assert_eq!(
+            parse_subscription("ticker.*?interval=1s"),
+            Some(("ticker.*", ChannelOptions { depth: None, interval: Some(Duration::from_secs(1)) }))
+        );
+        assert_eq!(parse_subscription("trades.BTCUSDT"), Some(("trades.BTCUSDT", ChannelOptions::default())));
+        assert_eq!(parse_subscription("ticker.BTCUSDT?depth=5"), None);
+        assert_eq!(parse_subscription("orderbook.delta.BTCUSDT?interval=100ms"), None);
+        assert_eq!(parse_subscription("orderbook.BTCUSDT?depth=0"), None);
+        assert_eq!(parse_subscription("orderbook.BTCUSDT?interval=fast"), None);
+        assert_eq!(parse_subscription("orderbook.BTCUSDT?levels=5"), None);
+        assert_eq!(channel_name("orderbook.BTCUSDT?depth=5"), "orderbook.BTCUSDT");
+    }
+
+    #[test]
+    fn test_throttle_conflates_to_latest() {
+        let interval = Duration::from_millis(100);
+        let start = Instant::now();
+        let mut throttle = Throttle::default();
+        let ping = |seq: u64| WsMessage::ResyncRequired { skipped: seq };
+
+        assert!(throttle.offer("ticker.BTCUSDT".to_string(), interval, ping(1), start).is_some());
+        assert!(throttle.offer("ticker.BTCUSDT".to_string(), interval, ping(2), start).is_none());
+        assert!(throttle.offer("ticker.BTCUSDT".to_string(), interval, ping(3), start).is_none());
+        assert_eq!(throttle.next_due(), Some(start + interval));
+        assert!(throttle.take_due(start).is_empty());
+
+        let sent = throttle.take_due(start + interval);
+        assert!(matches!(sent.as_slice(), [WsMessage::ResyncRequired { skipped: 3 }]));
+        assert_eq!(throttle.next_due(), None);
+    }
+}
@@ -2,23 +2,81 @@
 //!
 //! Real-time data streaming service for market data, order updates,
 //! and trading notifications using WebSocket connections.
+//!
+//! The `orderbook.delta.<symbol>` channel streams the matching engine's
+//! incremental book updates. Subscribing sends a snapshot first; a client
+//! buffers deltas until it arrives, drops those at or below the snapshot's
+//! sequence and applies the rest in order. A delta whose previous sequence
+//! is ahead of the client's book means an update was missed: the client
+//! sends `Resync` and receives a fresh snapshot.
+//!
+//! `kline.<interval>.<symbol>` channels (e.g. `kline.1m.BTCUSDT`) carry the
+//! candle in progress as trades update it, and each candle once it closes.
+//!
+//! Private messages of an authenticated user arrive as `UserEvent`s
+//! numbered in a per-user sequence, on every connection of the user. The
+//! latest events are buffered, so a client that reconnects after a drop
+//! sends `Resume` with the first sequence it missed and receives the events
+//! since; a replay may repeat events the live stream also delivers, which
+//! the client drops by sequence.
+//!
+//! The `fills` channel carries an execution report for each trade one of
+//! the user's orders takes part in: order, price, quantity, fee and whether
+//! the order was maker or taker.
+//!
+//! Subscriptions may use `*` wildcards, e.g. `ticker.*` or `trades.BTC-*`;
+//! see [`subscription`]. Full book and ticker subscriptions may ask the
+//! server to trim and throttle them, e.g.
+//! `orderbook.BTCUSDT?depth=20&interval=100ms`; see [`filter`].
+//!
+//! `shutdown` drains the manager for a deploy: new upgrades are refused and
+//! every client gets a close frame with code 1012 (service restart), telling
+//! it to reconnect, once what is already queued for it has been sent.
+//!
+//! Several instances can serve one deployment, sharing the events each
+//! publishes over Redis; see [`bridge`].
+//!
+//! Market data can be recorded and replayed; see [`recording`].
+//!
+//! Connection, traffic and subscription measurements go to an optional
+//! metrics sink; see [`metrics`].
+//!
+//! Frames are JSON unless the client negotiates a binary encoding; see
+//! [`codec`].
 
 use axum::{
-    extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
-    },
-    response::Response,
+    extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
-use dashmap::DashMap;
-use flowex_types::{OrderBook, Ticker, Trade, Order, FlowExError, FlowExResult};
+use dashmap::{DashMap, DashSet};
+use flowex_types::{BookUpdate, Candle, CandleInterval, Fill, IndexPrice, OrderBook, Ticker, Trade, Order, FlowExError, FlowExResult};
 use futures_util::{sink::SinkExt, stream::StreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, watch, Notify};
 use tracing::{info, warn, error, debug};
 use uuid::Uuid;
 
+pub mod bridge;
+pub mod codec;
+pub mod filter;
+pub mod metrics;
+pub mod recording;
+pub mod subscription;
+
+pub use bridge::EventBus;
+pub use codec::Encoding;
+pub use metrics::WsMetrics;
+pub use subscription::Subscriptions;
+use metrics::{channel_of, request_of, DropReason};
+use bridge::{BridgeEvent, BridgePayload};
+use filter::Throttle;
+use subscription::ChannelKind;
+
 /// WebSocket message types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
@@ -26,21 +84,97 @@ pub enum WsMessage {
     // Subscription management
     Subscribe { channels: Vec<String> },
     Unsubscribe { channels: Vec<String> },
+    /// Request a fresh snapshot of a subscribed incremental book
+    Resync { symbol: String },
+    /// Request the buffered user events numbered `from_seq` and later
+    Resume { from_seq: u64 },
     
     // Market data
     OrderBookUpdate(OrderBook),
+    /// Full book an incremental book channel's deltas apply on top of
+    OrderBookSnapshot(OrderBook),
+    OrderBookDelta(BookUpdate),
+    /// Candle in progress, or with `closed` set, a final candle
+    CandleUpdate { candle: Candle, closed: bool },
     TickerUpdate(Ticker),
     TradeUpdate(Trade),
+    /// Reference price of a symbol across external exchanges
+    IndexPriceUpdate(IndexPrice),
     
     // User-specific data
     OrderUpdate(Order),
+    /// Execution report of one of the user's orders filling in a trade
+    FillUpdate(Fill),
     BalanceUpdate { currency: String, available: String, locked: String },
+    /// A user-specific message, numbered in the user's event sequence
+    UserEvent { seq: u64, event: Box<WsMessage> },
     
     // System messages
     Ping,
     Pong,
-    Error { message: String },
-    Success { message: String },
+    /// Market data was dropped because the client fell behind; incremental
+    /// books follow as fresh snapshots
+    ResyncRequired { skipped: u64 },
+    /// A failed request; `channels` lists the channels it concerns
+    Error {
+        #[serde(default)]
+        code: WsErrorCode,
+        message: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        channels: Vec<String>,
+    },
+    /// A completed request; `channels` lists the channels it applied to
+    Success {
+        message: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        channels: Vec<String>,
+    },
+}
+
+/// Machine-readable reason for an error message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WsErrorCode {
+    #[default]
+    Internal,
+    /// The connection sent more messages per second than allowed; the
+    /// message was dropped
+    RateLimited,
+    /// The connection already has the maximum number of subscriptions
+    SubscriptionLimit,
+    /// The request needs a subscription the connection does not have
+    NotSubscribed,
+    /// No such channel
+    UnknownChannel,
+    /// The channel names a symbol that is not listed
+    UnknownSymbol,
+    /// The subscription's parameters are malformed or not supported by
+    /// the channel
+    InvalidOptions,
+    /// The request needs an authenticated connection
+    Unauthenticated,
+    /// Some of the user events asked to be resumed are no longer buffered;
+    /// the client must reload its state
+    ResumeGap,
+}
+
+/// User events buffered per user for `Resume`
+const DEFAULT_USER_EVENT_BUFFER: usize = 1000;
+
+/// Time a closing connection gets to flush its outbound queue
+const OUTBOUND_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Prefix of the incremental order book channels
+pub const ORDER_BOOK_DELTA_CHANNEL: &str = "orderbook.delta.";
+
+/// Channel carrying incremental updates of `symbol`'s book
+pub fn order_book_delta_channel(symbol: &str) -> String {
+    format!("{}{}", ORDER_BOOK_DELTA_CHANNEL, symbol)
+}
+
+/// Channel carrying `symbol`'s candles of one interval
+pub fn kline_channel(interval: CandleInterval, symbol: &str) -> String {
+    format!("kline.{}.{}", interval.as_str(), symbol)
 }
 
 /// WebSocket connection information
@@ -48,9 +182,129 @@ pub enum WsMessage {
 pub struct ConnectionInfo {
     pub id: Uuid,
     pub user_id: Option<Uuid>,
-    pub subscriptions: Vec<String>,
+    pub subscriptions: Subscriptions,
     pub connected_at: chrono::DateTime<chrono::Utc>,
     pub last_ping: chrono::DateTime<chrono::Utc>,
+    /// Heartbeats sent since the client last answered
+    pub missed_pongs: u32,
+    /// Start of the current one-second rate limiting window
+    pub window_start: chrono::DateTime<chrono::Utc>,
+    /// Messages received in the current window
+    pub window_messages: u32,
+    /// Times the connection fell behind the market data broadcast
+    pub lag_events: u32,
+}
+
+/// Server-initiated heartbeat settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// Time between pings sent to each connection
+    pub interval: Duration,
+    /// Unanswered pings after which the connection is dropped
+    pub max_missed_pongs: u32,
+}
+
+/// Per-connection limits on client requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// Messages a client may send per second; the rest are dropped
+    pub max_messages_per_second: u32,
+    /// Channels a connection may be subscribed to at once
+    pub max_subscriptions: usize,
+}
+
+/// Handling of clients that cannot keep up with the data sent to them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowClientPolicy {
+    /// Messages queued for a client before it is disconnected
+    pub queue_capacity: usize,
+    /// Times a client may fall behind the broadcast, and be resynced,
+    /// before it is disconnected
+    pub max_lag_events: u32,
+}
+
+impl Default for SlowClientPolicy {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 1024,
+            max_lag_events: 3,
+        }
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_messages_per_second: 10,
+            max_subscriptions: 50,
+        }
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            max_missed_pongs: 2,
+        }
+    }
+}
+
+/// A user's sequenced stream of private events
+#[derive(Debug)]
+struct UserStream {
+    connections: HashMap<Uuid, broadcast::Sender<WsMessage>>,
+    last_seq: u64,
+    /// Latest `UserEvent`s, oldest first
+    recent: VecDeque<WsMessage>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl UserStream {
+    fn new() -> Self {
+        Self {
+            connections: HashMap::new(),
+            last_seq: 0,
+            recent: VecDeque::new(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+}
+
+/// A connection's bounded queue of frames for the socket writer
+#[derive(Clone)]
+struct Outbound {
+    connection_id: Uuid,
+    encoding: Encoding,
+    tx: mpsc::Sender<Message>,
+    metrics: Option<Arc<dyn WsMetrics>>,
+}
+
+impl Outbound {
+    /// Queue a message for the client. Returns `false` if the queue is
+    /// full or closed and the client must be dropped.
+    fn send(&self, message: &WsMessage) -> bool {
+        let queued = self.send_frame(self.encoding.encode(message));
+        if let (true, Some(metrics)) = (queued, &self.metrics) {
+            metrics.message_sent(channel_of(message));
+        }
+        queued
+    }
+
+    /// Queue a frame for the client, like `send`
+    fn send_frame(&self, frame: Message) -> bool {
+        match self.tx.try_send(frame) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                warn!("Dropping slow WebSocket connection {}: outbound queue full", self.connection_id);
+                if let Some(metrics) = &self.metrics {
+                    metrics.messages_dropped(DropReason::QueueFull, 1);
+                }
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
 }
 
 /// WebSocket manager for handling real-time connections
@@ -58,8 +312,21 @@ pub struct ConnectionInfo {
 pub struct WebSocketManager {
     connections: Arc<DashMap<Uuid, ConnectionInfo>>,
     market_data_tx: broadcast::Sender<WsMessage>,
-    user_data_txs: Arc<DashMap<Uuid, broadcast::Sender<WsMessage>>>,
+    books: Arc<DashMap<String, OrderBook>>, // Books kept current from engine deltas, for new subscribers
+    user_streams: Arc<DashMap<Uuid, UserStream>>,
+    user_event_buffer: usize,
+    symbols: Arc<DashSet<String>>, // Listed symbols; empty accepts any
+    subscription_counts: Arc<DashMap<String, usize>>, // Subscriptions per symbol, `*` for wildcards
+    metrics: Option<Arc<dyn WsMetrics>>,
+    instance_id: Uuid,
+    bus: Option<Arc<dyn EventBus>>, // Shares published events with other instances
     max_connections: usize,
+    heartbeat: HeartbeatConfig,
+    limits: ConnectionLimits,
+    slow_client: SlowClientPolicy,
+    shutting_down: Arc<AtomicBool>,
+    shutdown_tx: Arc<watch::Sender<bool>>, // Tells connections to close
+    connection_closed: Arc<Notify>,
 }
 
 impl WebSocketManager {
@@ -70,28 +337,113 @@ impl WebSocketManager {
         Self {
             connections: Arc::new(DashMap::new()),
             market_data_tx,
-            user_data_txs: Arc::new(DashMap::new()),
+            books: Arc::new(DashMap::new()),
+            user_streams: Arc::new(DashMap::new()),
+            user_event_buffer: DEFAULT_USER_EVENT_BUFFER,
+            symbols: Arc::new(DashSet::new()),
+            subscription_counts: Arc::new(DashMap::new()),
+            metrics: None,
+            instance_id: Uuid::new_v4(),
+            bus: None,
             max_connections,
+            heartbeat: HeartbeatConfig::default(),
+            limits: ConnectionLimits::default(),
+            slow_client: SlowClientPolicy::default(),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            shutdown_tx: Arc::new(watch::channel(false).0),
+            connection_closed: Arc::new(Notify::new()),
         }
     }
 
+    /// Report connection and traffic measurements to `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<dyn WsMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sink measurements are reported to, if any
+    pub fn metrics(&self) -> Option<&dyn WsMetrics> {
+        self.metrics.as_deref()
+    }
+
+    /// Share published market data and user events with the other
+    /// instances over `bus`
+    pub fn with_bus(mut self, bus: Arc<dyn EventBus>) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Identifies this instance's events on the bus
+    pub fn instance_id(&self) -> Uuid {
+        self.instance_id
+    }
+
+    /// Accept market data subscriptions only for the given symbols
+    pub fn with_symbols<S: Into<String>>(self, symbols: impl IntoIterator<Item = S>) -> Self {
+        for symbol in symbols {
+            self.symbols.insert(symbol.into());
+        }
+        self
+    }
+
+    /// Accept market data subscriptions for a newly listed symbol
+    pub fn add_symbol(&self, symbol: impl Into<String>) {
+        self.symbols.insert(symbol.into());
+    }
+
+    /// Buffer up to `capacity` events per user for resuming clients
+    pub fn with_user_event_buffer(mut self, capacity: usize) -> Self {
+        self.user_event_buffer = capacity;
+        self
+    }
+
+    /// Apply the given slow client policy to new connections
+    pub fn with_slow_client_policy(mut self, slow_client: SlowClientPolicy) -> Self {
+        self.slow_client = slow_client;
+        self
+    }
+
+    /// Apply the given request limits to new connections
+    pub fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Use the given heartbeat settings for new connections
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
     /// Handle WebSocket upgrade
     pub async fn handle_websocket(
         &self,
         ws: WebSocketUpgrade,
         user_id: Option<Uuid>,
     ) -> Response {
+        if self.is_shutting_down() {
+            return (StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down").into_response();
+        }
         let manager = self.clone();
         
-        ws.on_upgrade(move |socket| async move {
-            if let Err(e) = manager.handle_connection(socket, user_id).await {
+        ws.protocols(Encoding::SUBPROTOCOLS).on_upgrade(move |socket| async move {
+            let encoding = socket
+                .protocol()
+                .and_then(|protocol| protocol.to_str().ok())
+                .and_then(Encoding::from_subprotocol)
+                .unwrap_or_default();
+            if let Err(e) = manager.handle_connection(socket, user_id, encoding).await {
                 error!("WebSocket connection error: {}", e);
             }
         })
     }
 
     /// Handle a WebSocket connection
-    async fn handle_connection(&self, socket: WebSocket, user_id: Option<Uuid>) -> FlowExResult<()> {
+    async fn handle_connection(&self, socket: WebSocket, user_id: Option<Uuid>, encoding: Encoding) -> FlowExResult<()> {
+        if self.is_shutting_down() {
+            return Err(FlowExError::Internal("Server is shutting down".to_string()));
+        }
+
         // Check connection limit
         if self.connections.len() >= self.max_connections {
             warn!("WebSocket connection limit reached");
@@ -102,14 +454,21 @@ impl WebSocketManager {
         let connection_info = ConnectionInfo {
             id: connection_id,
             user_id,
-            subscriptions: Vec::new(),
+            subscriptions: Subscriptions::default(),
             connected_at: chrono::Utc::now(),
             last_ping: chrono::Utc::now(),
+            missed_pongs: 0,
+            window_start: chrono::Utc::now(),
+            window_messages: 0,
+            lag_events: 0,
         };
 
         // Add connection to manager
         self.connections.insert(connection_id, connection_info);
-        info!("New WebSocket connection: {} (user: {:?})", connection_id, user_id);
+        if let Some(metrics) = &self.metrics {
+            metrics.connections(self.connections.len());
+        }
+        info!("New WebSocket connection: {} (user: {:?}, encoding: {:?})", connection_id, user_id, encoding);
 
         // Split socket into sender and receiver
         let (mut sender, mut receiver) = socket.split();
@@ -118,32 +477,51 @@ impl WebSocketManager {
         let mut market_data_rx = self.market_data_tx.subscribe();
 
         // Subscribe to user data if authenticated
-        let mut user_data_rx = if let Some(uid) = user_id {
-            let (tx, rx) = broadcast::channel(100);
-            self.user_data_txs.insert(uid, tx);
-            Some(rx)
-        } else {
-            None
+        let mut user_data_rx = user_id.map(|uid| self.register_user_connection(uid, connection_id));
+
+        // Everything bound for the client goes through one bounded queue; a
+        // client that cannot keep it drained is disconnected
+        let (outbound_tx, mut outbound_rx) = mpsc::channel::<Message>(self.slow_client.queue_capacity.max(1));
+        let outbound = Outbound {
+            connection_id,
+            encoding,
+            tx: outbound_tx,
+            metrics: self.metrics.clone(),
         };
 
+        // Write queued messages to the socket
+        let mut writer_task = tokio::spawn(async move {
+            while let Some(message) = outbound_rx.recv().await {
+                if sender.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
         // Handle incoming messages
-        let connections = self.connections.clone();
-        let incoming_task = tokio::spawn(async move {
+        let manager = self.clone();
+        let replies = outbound.clone();
+        let mut incoming_task = tokio::spawn(async move {
             while let Some(msg) = receiver.next().await {
                 match msg {
                     Ok(Message::Text(text)) => {
-                        if let Err(e) = Self::handle_incoming_message(&connections, connection_id, &text).await {
-                            error!("Error handling incoming message: {}", e);
+                        match manager.handle_incoming_message(connection_id, &text).await {
+                            Ok(messages) => {
+                                if !messages.iter().all(|reply| replies.send(reply)) {
+                                    return;
+                                }
+                            }
+                            Err(e) => error!("Error handling incoming message: {}", e),
                         }
                     }
                     Ok(Message::Ping(data)) => {
-                        if sender.send(Message::Pong(data)).await.is_err() {
+                        if !replies.send_frame(Message::Pong(data)) {
                             break;
                         }
-                        // Update last ping time
-                        if let Some(mut conn) = connections.get_mut(&connection_id) {
-                            conn.last_ping = chrono::Utc::now();
-                        }
+                        Self::record_alive(&manager.connections, connection_id);
+                    }
+                    Ok(Message::Pong(_)) => {
+                        Self::record_alive(&manager.connections, connection_id);
                     }
                     Ok(Message::Close(_)) => {
                         info!("WebSocket connection closed: {}", connection_id);
@@ -158,17 +536,84 @@ impl WebSocketManager {
             }
         });
 
-        // Handle outgoing messages
-        let outgoing_task = tokio::spawn(async move {
+        // Fan market data, user data and heartbeats out to the client
+        let connections = self.connections.clone();
+        let books = self.books.clone();
+        let heartbeat = self.heartbeat;
+        let slow_client = self.slow_client;
+        let metrics = self.metrics.clone();
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        let mut fan_out_task = tokio::spawn(async move {
+            let mut heartbeats = tokio::time::interval_at(
+                tokio::time::Instant::now() + heartbeat.interval,
+                heartbeat.interval,
+            );
+            let mut throttle = Throttle::default();
             loop {
+                let next_throttled = throttle.next_due();
                 tokio::select! {
+                    // Close behind what is already queued, asking the client to reconnect
+                    _ = shutdown_rx.wait_for(|shutting_down| *shutting_down) => {
+                        outbound.send_frame(Message::Close(Some(CloseFrame {
+                            code: close_code::RESTART,
+                            reason: "Server restarting, please reconnect".into(),
+                        })));
+                        break;
+                    }
+
+                    _ = heartbeats.tick() => {
+                        if !Self::record_heartbeat(&connections, connection_id, heartbeat.max_missed_pongs) {
+                            warn!("Dropping WebSocket connection {}: no pong to {} pings", connection_id, heartbeat.max_missed_pongs);
+                            outbound.send_frame(Message::Close(None));
+                            break;
+                        }
+                        if !outbound.send_frame(Message::Ping(Vec::new())) {
+                            break;
+                        }
+                    }
+
                     // Market data messages
-                    Ok(msg) = market_data_rx.recv() => {
-                        if Self::should_send_message(&connections, connection_id, &msg) {
-                            let json = serde_json::to_string(&msg).unwrap_or_default();
-                            if sender.send(Message::Text(json)).await.is_err() {
-                                break;
+                    result = market_data_rx.recv() => {
+                        let messages = match result {
+                            Ok(msg) if Self::should_send_message(&connections, connection_id, &msg) => vec![msg],
+                            Ok(_) => Vec::new(),
+                            // Messages were dropped: tell the client and resend every subscribed book
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                if let Some(metrics) = &metrics {
+                                    metrics.broadcast_lag(skipped);
+                                    metrics.messages_dropped(DropReason::Lagged, skipped);
+                                }
+                                if !Self::record_lag(&connections, connection_id, slow_client.max_lag_events) {
+                                    warn!("Dropping chronically slow WebSocket connection {}", connection_id);
+                                    break;
+                                }
+                                warn!("Connection {} lagged by {} messages, resending books", connection_id, skipped);
+                                let mut messages = vec![WsMessage::ResyncRequired { skipped }];
+                                messages.extend(Self::subscribed_snapshots(&connections, &books, connection_id));
+                                messages
                             }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+                        if !messages
+                            .into_iter()
+                            .filter_map(|msg| Self::filter_message(&connections, connection_id, &mut throttle, msg))
+                            .all(|msg| outbound.send(&msg))
+                        {
+                            break;
+                        }
+                    }
+
+                    // Throttled channels whose interval is up send their latest message
+                    _ = tokio::time::sleep_until(next_throttled.unwrap_or_else(tokio::time::Instant::now)),
+                        if next_throttled.is_some() =>
+                    {
+                        if !throttle
+                            .take_due(tokio::time::Instant::now())
+                            .into_iter()
+                            .filter(|msg| Self::should_send_message(&connections, connection_id, msg))
+                            .all(|msg| outbound.send(&msg))
+                        {
+                            break;
                         }
                     }
                     
@@ -180,8 +625,7 @@ impl WebSocketManager {
                             std::future::pending().await
                         }
                     } => {
-                        let json = serde_json::to_string(&msg).unwrap_or_default();
-                        if sender.send(Message::Text(json)).await.is_err() {
+                        if !outbound.send(&msg) {
                             break;
                         }
                     }
@@ -191,59 +635,346 @@ impl WebSocketManager {
             }
         });
 
-        // Wait for either task to complete
+        // Wait for any task to complete, then stop the others
         tokio::select! {
-            _ = incoming_task => {},
-            _ = outgoing_task => {},
+            _ = &mut incoming_task => {},
+            _ = &mut fan_out_task => {},
+            _ = &mut writer_task => {},
+        }
+        incoming_task.abort();
+        fan_out_task.abort();
+        // Flush what is already queued, such as a close frame, unless the client stalls
+        if tokio::time::timeout(OUTBOUND_FLUSH_TIMEOUT, &mut writer_task).await.is_err() {
+            writer_task.abort();
         }
 
         // Clean up connection
-        self.connections.remove(&connection_id);
+        self.remove_connection(connection_id);
         if let Some(uid) = user_id {
-            self.user_data_txs.remove(&uid);
+            self.unregister_user_connection(uid, connection_id);
         }
         info!("WebSocket connection cleaned up: {}", connection_id);
 
         Ok(())
     }
 
-    /// Handle incoming WebSocket message
-    async fn handle_incoming_message(
-        connections: &DashMap<Uuid, ConnectionInfo>,
-        connection_id: Uuid,
-        text: &str,
-    ) -> FlowExResult<()> {
+    /// Handle incoming WebSocket message and return the replies to send
+    /// back on the connection
+    async fn handle_incoming_message(&self, connection_id: Uuid, text: &str) -> FlowExResult<Vec<WsMessage>> {
+        let (connections, books, limits) = (&self.connections, &self.books, &self.limits);
+        if !Self::allow_message(connections, connection_id, limits.max_messages_per_second) {
+            debug!("Connection {} is rate limited", connection_id);
+            if let Some(metrics) = &self.metrics {
+                metrics.messages_dropped(DropReason::RateLimited, 1);
+            }
+            return Ok(vec![WsMessage::Error {
+                code: WsErrorCode::RateLimited,
+                message: format!("At most {} messages per second are accepted", limits.max_messages_per_second),
+                channels: Vec::new(),
+            }]);
+        }
+
         let message: WsMessage = serde_json::from_str(text)
             .map_err(|e| FlowExError::Validation(format!("Invalid message format: {}", e)))?;
+        if let Some(metrics) = &self.metrics {
+            metrics.message_received(request_of(&message));
+        }
 
+        let mut replies = Vec::new();
         match message {
             WsMessage::Subscribe { channels } => {
                 if let Some(mut conn) = connections.get_mut(&connection_id) {
-                    for channel in channels {
-                        if !conn.subscriptions.contains(&channel) {
-                            conn.subscriptions.push(channel.clone());
+                    let authenticated = conn.user_id.is_some();
+                    let mut accepted = Vec::new();
+                    let mut rejected = Vec::new();
+                    let mut snapshots = Vec::new();
+                    for requested in channels {
+                        let Some((channel, options)) = filter::parse_subscription(&requested) else {
+                            rejected.push((WsErrorCode::InvalidOptions, requested));
+                            continue;
+                        };
+                        if let Err(code) = self.check_channel(channel, authenticated) {
+                            rejected.push((code, requested));
+                            continue;
+                        }
+                        if !conn.subscriptions.contains(channel) {
+                            if conn.subscriptions.len() >= limits.max_subscriptions {
+                                rejected.push((WsErrorCode::SubscriptionLimit, requested));
+                                continue;
+                            }
+                            conn.subscriptions.insert(channel.to_string());
+                            self.count_subscription(channel, true);
                             debug!("Connection {} subscribed to {}", connection_id, channel);
                         }
+                        conn.subscriptions.set_options(channel, options);
+                        // The subscription is recorded before the snapshot is
+                        // taken, so every later delta reaches the client
+                        if subscription::is_pattern(channel) {
+                            snapshots.extend(
+                                books
+                                    .iter()
+                                    .filter(|book| {
+                                        subscription::channel_matches(channel, &order_book_delta_channel(book.key()))
+                                    })
+                                    .map(|book| WsMessage::OrderBookSnapshot(book.clone())),
+                            );
+                        } else if let Some(book) = channel
+                            .strip_prefix(ORDER_BOOK_DELTA_CHANNEL)
+                            .and_then(|symbol| books.get(symbol))
+                        {
+                            snapshots.push(WsMessage::OrderBookSnapshot(book.clone()));
+                        }
+                        accepted.push(requested);
+                    }
+
+                    if !accepted.is_empty() {
+                        replies.push(WsMessage::Success {
+                            message: format!("Subscribed to {}", accepted.join(", ")),
+                            channels: accepted,
+                        });
                     }
+                    replies.extend(self.rejections(rejected));
+                    replies.extend(snapshots);
                 }
             }
+            WsMessage::Resync { symbol } => {
+                let subscribed = connections
+                    .get(&connection_id)
+                    .is_some_and(|conn| conn.subscriptions.matches(&order_book_delta_channel(&symbol)));
+                replies.push(match books.get(&symbol) {
+                    Some(book) if subscribed => WsMessage::OrderBookSnapshot(book.clone()),
+                    _ => WsMessage::Error {
+                        code: WsErrorCode::NotSubscribed,
+                        message: format!("Not subscribed to an order book for {}", symbol),
+                        channels: vec![order_book_delta_channel(&symbol)],
+                    },
+                });
+            }
             WsMessage::Unsubscribe { channels } => {
                 if let Some(mut conn) = connections.get_mut(&connection_id) {
-                    for channel in channels {
-                        conn.subscriptions.retain(|c| c != &channel);
-                        debug!("Connection {} unsubscribed from {}", connection_id, channel);
+                    let (removed, unknown): (Vec<String>, Vec<String>) = channels
+                        .into_iter()
+                        .partition(|channel| conn.subscriptions.remove(filter::channel_name(channel)));
+                    for channel in &removed {
+                        self.count_subscription(filter::channel_name(channel), false);
+                    }
+                    debug!("Connection {} unsubscribed from {:?}", connection_id, removed);
+
+                    if !removed.is_empty() {
+                        replies.push(WsMessage::Success {
+                            message: format!("Unsubscribed from {}", removed.join(", ")),
+                            channels: removed,
+                        });
                     }
+                    replies.extend(self.rejections(unknown.into_iter().map(|channel| (WsErrorCode::NotSubscribed, channel))));
+                }
+            }
+            WsMessage::Resume { from_seq } => {
+                match connections.get(&connection_id).and_then(|conn| conn.user_id) {
+                    Some(user_id) => replies.extend(Self::replay_user_events(&self.user_streams, user_id, from_seq)),
+                    None => replies.push(WsMessage::Error {
+                        code: WsErrorCode::Unauthenticated,
+                        message: "Resuming user events requires an authenticated connection".to_string(),
+                        channels: Vec::new(),
+                    }),
                 }
             }
             WsMessage::Ping => {
-                // Ping will be handled by the message loop
+                Self::record_alive(connections, connection_id);
+                replies.push(WsMessage::Pong);
             }
             _ => {
                 warn!("Unexpected message type from client: {:?}", message);
             }
         }
 
-        Ok(())
+        Ok(replies)
+    }
+
+    /// Check that a connection may subscribe to a channel. Patterns are
+    /// accepted as long as they are not empty.
+    fn check_channel(&self, channel: &str, authenticated: bool) -> Result<(), WsErrorCode> {
+        if subscription::is_pattern(channel) {
+            return Ok(());
+        }
+        match subscription::parse_channel(channel) {
+            None => Err(WsErrorCode::UnknownChannel),
+            Some(ChannelKind::Private) if !authenticated => Err(WsErrorCode::Unauthenticated),
+            Some(ChannelKind::Market { symbol }) if !self.symbols.is_empty() && !self.symbols.contains(symbol) => {
+                Err(WsErrorCode::UnknownSymbol)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// One error per reason, listing the channels rejected for it
+    fn rejections(&self, rejected: impl IntoIterator<Item = (WsErrorCode, String)>) -> Vec<WsMessage> {
+        let mut by_code: Vec<(WsErrorCode, Vec<String>)> = Vec::new();
+        for (code, channel) in rejected {
+            match by_code.iter_mut().find(|(c, _)| *c == code) {
+                Some((_, channels)) => channels.push(channel),
+                None => by_code.push((code, vec![channel])),
+            }
+        }
+
+        by_code
+            .into_iter()
+            .map(|(code, channels)| {
+                let list = channels.join(", ");
+                let message = match code {
+                    WsErrorCode::SubscriptionLimit => format!(
+                        "At most {} subscriptions are allowed; not subscribed to {}",
+                        self.limits.max_subscriptions, list
+                    ),
+                    WsErrorCode::UnknownSymbol => format!("Unknown symbol in {}", list),
+                    WsErrorCode::InvalidOptions => format!("Unsupported channel parameters in {}", list),
+                    WsErrorCode::Unauthenticated => format!("Private channels need an authenticated connection: {}", list),
+                    WsErrorCode::NotSubscribed => format!("Not subscribed to {}", list),
+                    _ => format!("Unknown channel {}", list),
+                };
+                WsMessage::Error { code, message, channels }
+            })
+            .collect()
+    }
+
+    /// Count a message against the connection's one-second window.
+    /// Returns `false` if it exceeds `max_per_second` and must be dropped.
+    fn allow_message(connections: &DashMap<Uuid, ConnectionInfo>, connection_id: Uuid, max_per_second: u32) -> bool {
+        let Some(mut conn) = connections.get_mut(&connection_id) else {
+            return false;
+        };
+        let now = chrono::Utc::now();
+        if now - conn.window_start >= chrono::Duration::seconds(1) {
+            conn.window_start = now;
+            conn.window_messages = 0;
+        }
+        conn.window_messages += 1;
+        conn.window_messages <= max_per_second
+    }
+
+    /// Forget a closed connection and its subscriptions
+    fn remove_connection(&self, connection_id: Uuid) {
+        if let Some((_, conn)) = self.connections.remove(&connection_id) {
+            for channel in conn.subscriptions.iter() {
+                self.count_subscription(channel, false);
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.connections(self.connections.len());
+        }
+        self.connection_closed.notify_waiters();
+    }
+
+    /// Whether `shutdown` has been called
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Stop accepting connections, close every open one with a reconnect
+    /// hint and wait up to `timeout` for them to flush their queues and
+    /// go. Fails if connections are still open when the time is up.
+    pub async fn shutdown(&self, timeout: Duration) -> FlowExResult<()> {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.shutdown_tx.send_replace(true);
+        info!("Draining {} WebSocket connections", self.connections.len());
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Registered before the check, so no close is missed in between
+            let closed = self.connection_closed.notified();
+            if self.connections.is_empty() {
+                info!("All WebSocket connections drained");
+                return Ok(());
+            }
+            if tokio::time::timeout_at(deadline, closed).await.is_err() {
+                return Err(FlowExError::Internal(format!(
+                    "{} WebSocket connections still open after {:?}",
+                    self.connections.len(),
+                    timeout
+                )));
+            }
+        }
+    }
+
+    /// Track a subscription being added or removed in the per-symbol counts
+    fn count_subscription(&self, channel: &str, added: bool) {
+        let symbol = if subscription::is_pattern(channel) {
+            "*"
+        } else {
+            match subscription::parse_channel(channel) {
+                Some(ChannelKind::Market { symbol }) => symbol,
+                Some(ChannelKind::AllSymbols) => "*",
+                _ => return,
+            }
+        };
+
+        let count = {
+            let mut count = self.subscription_counts.entry(symbol.to_string()).or_insert(0);
+            *count = if added { *count + 1 } else { count.saturating_sub(1) };
+            *count
+        };
+        if count == 0 {
+            self.subscription_counts.remove_if(symbol, |_, count| *count == 0);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.subscriptions(symbol, count);
+        }
+    }
+
+    /// Subscriptions per symbol across all connections; wildcard and
+    /// all-symbol subscriptions count under `*`
+    pub fn subscriptions_by_symbol(&self) -> HashMap<String, usize> {
+        self.subscription_counts
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    /// Count a broadcast lag. Returns `false` once the connection has lagged
+    /// more than `max_lag_events` times, or is gone.
+    fn record_lag(connections: &DashMap<Uuid, ConnectionInfo>, connection_id: Uuid, max_lag_events: u32) -> bool {
+        let Some(mut conn) = connections.get_mut(&connection_id) else {
+            return false;
+        };
+        conn.lag_events += 1;
+        conn.lag_events <= max_lag_events
+    }
+
+    /// Note that the client answered or pinged, resetting its missed pongs
+    fn record_alive(connections: &DashMap<Uuid, ConnectionInfo>, connection_id: Uuid) {
+        if let Some(mut conn) = connections.get_mut(&connection_id) {
+            conn.last_ping = chrono::Utc::now();
+            conn.missed_pongs = 0;
+        }
+    }
+
+    /// Count a heartbeat about to be sent. Returns `false` once the
+    /// connection has left `max_missed_pongs` pings unanswered, or is gone.
+    fn record_heartbeat(connections: &DashMap<Uuid, ConnectionInfo>, connection_id: Uuid, max_missed_pongs: u32) -> bool {
+        let Some(mut conn) = connections.get_mut(&connection_id) else {
+            return false;
+        };
+        if conn.missed_pongs >= max_missed_pongs {
+            return false;
+        }
+        conn.missed_pongs += 1;
+        true
+    }
+
+    /// Snapshots of every incremental book a connection is subscribed to
+    fn subscribed_snapshots(
+        connections: &DashMap<Uuid, ConnectionInfo>,
+        books: &DashMap<String, OrderBook>,
+        connection_id: Uuid,
+    ) -> Vec<WsMessage> {
+        let Some(conn) = connections.get(&connection_id) else {
+            return Vec::new();
+        };
+        books
+            .iter()
+            .filter(|book| conn.subscriptions.matches(&order_book_delta_channel(book.key())))
+            .map(|book| WsMessage::OrderBookSnapshot(book.clone()))
+            .collect()
     }
 
     /// Check if a message should be sent to a connection
@@ -255,17 +986,30 @@ impl WebSocketManager {
         if let Some(conn) = connections.get(&connection_id) {
             match message {
                 WsMessage::OrderBookUpdate(order_book) => {
-                    conn.subscriptions.contains(&format!("orderbook.{}", order_book.symbol))
+                    conn.subscriptions.matches(&format!("orderbook.{}", order_book.symbol))
+                }
+                WsMessage::OrderBookSnapshot(order_book) => {
+                    conn.subscriptions.matches(&order_book_delta_channel(&order_book.symbol))
+                }
+                WsMessage::OrderBookDelta(update) => {
+                    conn.subscriptions.matches(&order_book_delta_channel(&update.symbol))
+                }
+                WsMessage::CandleUpdate { candle, .. } => {
+                    conn.subscriptions.matches(&kline_channel(candle.interval, &candle.symbol))
                 }
                 WsMessage::TickerUpdate(ticker) => {
-                    conn.subscriptions.contains(&format!("ticker.{}", ticker.symbol))
+                    conn.subscriptions.matches(&format!("ticker.{}", ticker.symbol))
                         || conn.subscriptions.contains("ticker.all")
                 }
                 WsMessage::TradeUpdate(trade) => {
-                    conn.subscriptions.contains(&format!("trades.{}", trade.symbol))
+                    conn.subscriptions.matches(&format!("trades.{}", trade.symbol))
                         || conn.subscriptions.contains("trades.all")
                 }
-                WsMessage::OrderUpdate(_) | WsMessage::BalanceUpdate { .. } => {
+                WsMessage::IndexPriceUpdate(index) => conn.subscriptions.matches(&format!("index.{}", index.symbol)),
+                WsMessage::OrderUpdate(_)
+                | WsMessage::FillUpdate(_)
+                | WsMessage::BalanceUpdate { .. }
+                | WsMessage::UserEvent { .. } => {
                     // User-specific messages are always sent if user is authenticated
                     conn.user_id.is_some()
                 }
@@ -276,24 +1020,220 @@ impl WebSocketManager {
         }
     }
 
-    /// Broadcast market data to all subscribed connections
+    /// Apply the depth and interval a connection subscribed to a full book
+    /// or ticker channel with. Returns `None` while the message is held
+    /// back by the channel's interval.
+    fn filter_message(
+        connections: &DashMap<Uuid, ConnectionInfo>,
+        connection_id: Uuid,
+        throttle: &mut Throttle,
+        message: WsMessage,
+    ) -> Option<WsMessage> {
+        let (channel, options) = {
+            let conn = connections.get(&connection_id)?;
+            match &message {
+                WsMessage::OrderBookUpdate(book) => {
+                    let channel = format!("orderbook.{}", book.symbol);
+                    let options = conn.subscriptions.options(&channel);
+                    (channel, options)
+                }
+                WsMessage::TickerUpdate(ticker) => {
+                    let channel = format!("ticker.{}", ticker.symbol);
+                    let options = conn.subscriptions.options(&channel).or_else(|| conn.subscriptions.options("ticker.all"));
+                    (channel, options)
+                }
+                _ => return Some(message),
+            }
+        };
+        let options = options.unwrap_or_default();
+
+        let message = match options.depth {
+            Some(depth) => filter::truncate_depth(message, depth),
+            None => message,
+        };
+        match options.interval {
+            Some(interval) => throttle.offer(channel, interval, message, tokio::time::Instant::now()),
+            None => Some(message),
+        }
+    }
+
+    /// Broadcast market data to all subscribed connections, on every
+    /// instance when a bus is installed
     pub async fn broadcast_market_data(&self, message: WsMessage) -> FlowExResult<()> {
+        self.forward(BridgePayload::Market { message: message.clone() });
+        self.deliver_market_data(message)
+    }
+
+    /// Receive every market data message this instance broadcasts,
+    /// including those bridged from other instances
+    pub fn subscribe_market_data(&self) -> broadcast::Receiver<WsMessage> {
+        self.market_data_tx.subscribe()
+    }
+
+    /// Replace the book incremental subscribers of its symbol build on,
+    /// e.g. after an engine restart, and send it to them
+    pub async fn publish_book_snapshot(&self, book: OrderBook) -> FlowExResult<()> {
+        self.broadcast_market_data(WsMessage::OrderBookSnapshot(book)).await
+    }
+
+    /// Apply a matching engine book update and stream it to subscribers.
+    ///
+    /// Updates for a symbol without a snapshot are dropped. An update that
+    /// reveals a gap replaces nothing: subscribers see the gap in the
+    /// sequence numbers and resync, and a new snapshot must be published.
+    pub async fn publish_book_update(&self, update: BookUpdate) -> FlowExResult<()> {
+        self.broadcast_market_data(WsMessage::OrderBookDelta(update)).await
+    }
+
+    /// Keep the books new subscribers start from current, then send the
+    /// message to this instance's subscribers
+    fn deliver_market_data(&self, message: WsMessage) -> FlowExResult<()> {
+        match &message {
+            WsMessage::OrderBookSnapshot(book) => {
+                self.books.insert(book.symbol.clone(), book.clone());
+            }
+            WsMessage::OrderBookDelta(update) => {
+                let Some(mut book) = self.books.get_mut(&update.symbol) else {
+                    debug!("No {} order book snapshot yet, dropping update {}", update.symbol, update.sequence);
+                    return Ok(());
+                };
+                if !book.apply_update(update)? {
+                    return Ok(());
+                }
+            }
+            _ => {}
+        }
+
         if self.market_data_tx.send(message).is_err() {
             warn!("No active market data subscribers");
         }
         Ok(())
     }
 
-    /// Send user-specific data
+    /// Stream a candle to its kline channel: the candle in progress after
+    /// a trade, or with `closed`, a candle that has closed
+    pub async fn publish_candle(&self, candle: Candle, closed: bool) -> FlowExResult<()> {
+        self.broadcast_market_data(WsMessage::CandleUpdate { candle, closed }).await
+    }
+
+    /// Stream a symbol's index price to its index channel
+    pub async fn publish_index_price(&self, index: IndexPrice) -> FlowExResult<()> {
+        self.broadcast_market_data(WsMessage::IndexPriceUpdate(index)).await
+    }
+
+    /// Number user-specific data in the user's event sequence, buffer it
+    /// for resuming clients and send it to every connection of the user
     pub async fn send_user_data(&self, user_id: Uuid, message: WsMessage) -> FlowExResult<()> {
-        if let Some(tx) = self.user_data_txs.get(&user_id) {
-            if tx.send(message).is_err() {
-                warn!("Failed to send user data to user: {}", user_id);
+        let event = {
+            let mut stream = self.user_streams.entry(user_id).or_insert_with(UserStream::new);
+            let event = WsMessage::UserEvent {
+                seq: stream.last_seq + 1,
+                event: Box::new(message),
+            };
+            self.deliver_user_event(&mut stream, user_id, event.clone());
+            event
+        };
+        self.forward(BridgePayload::User { user_id, event });
+        Ok(())
+    }
+
+    /// Buffer a numbered user event and send it to the user's connections
+    /// on this instance
+    fn deliver_user_event(&self, stream: &mut UserStream, user_id: Uuid, event: WsMessage) {
+        if let WsMessage::UserEvent { seq, .. } = &event {
+            stream.last_seq = stream.last_seq.max(*seq);
+        }
+        stream.recent.push_back(event.clone());
+        while stream.recent.len() > self.user_event_buffer {
+            stream.recent.pop_front();
+        }
+        stream.updated_at = chrono::Utc::now();
+
+        for (connection_id, tx) in stream.connections.iter() {
+            if tx.send(event.clone()).is_err() {
+                warn!("Failed to send user data to user {} on connection {}", user_id, connection_id);
+            }
+        }
+    }
+
+    /// Pass an event published here on to the other instances
+    fn forward(&self, payload: BridgePayload) {
+        if let Some(bus) = &self.bus {
+            bus.publish(&BridgeEvent {
+                origin: self.instance_id,
+                payload,
+            });
+        }
+    }
+
+    /// Deliver an event another instance published to this instance's
+    /// clients. The instance's own events, echoed back by the bus, are
+    /// ignored.
+    pub async fn receive_bridged(&self, event: BridgeEvent) -> FlowExResult<()> {
+        if event.origin == self.instance_id {
+            return Ok(());
+        }
+        match event.payload {
+            BridgePayload::Market { message } => self.deliver_market_data(message),
+            BridgePayload::User { user_id, event } => {
+                let mut stream = self.user_streams.entry(user_id).or_insert_with(UserStream::new);
+                self.deliver_user_event(&mut stream, user_id, event);
+                Ok(())
             }
         }
+    }
+
+    /// Send each user whose orders took part in `trades` their fills
+    pub async fn publish_fills(&self, trades: &[Trade]) -> FlowExResult<()> {
+        for fill in trades.iter().flat_map(Trade::fills) {
+            self.send_user_data(fill.user_id, WsMessage::FillUpdate(fill)).await?;
+        }
         Ok(())
     }
 
+    /// Buffered events of a user numbered `from_seq` and later, preceded
+    /// by an error if earlier ones asked for were already dropped
+    fn replay_user_events(user_streams: &DashMap<Uuid, UserStream>, user_id: Uuid, from_seq: u64) -> Vec<WsMessage> {
+        let Some(stream) = user_streams.get(&user_id) else {
+            return Vec::new();
+        };
+        let seq = |event: &WsMessage| match event {
+            WsMessage::UserEvent { seq, .. } => *seq,
+            _ => 0,
+        };
+
+        let mut replies = Vec::new();
+        let oldest = stream.recent.front().map_or(stream.last_seq + 1, seq);
+        if from_seq < oldest && oldest > 1 {
+            replies.push(WsMessage::Error {
+                code: WsErrorCode::ResumeGap,
+                message: format!("User events before {} are no longer available", oldest),
+                channels: Vec::new(),
+            });
+        }
+        replies.extend(stream.recent.iter().filter(|event| seq(event) >= from_seq).cloned());
+        replies
+    }
+
+    /// Open a user data channel for one of the user's connections
+    fn register_user_connection(&self, user_id: Uuid, connection_id: Uuid) -> broadcast::Receiver<WsMessage> {
+        let (tx, rx) = broadcast::channel(100);
+        let mut stream = self.user_streams.entry(user_id).or_insert_with(UserStream::new);
+        stream.connections.insert(connection_id, tx);
+        stream.updated_at = chrono::Utc::now();
+        rx
+    }
+
+    /// Close a connection's user data channel, leaving the user's other
+    /// connections subscribed. The user's buffered events are kept for a
+    /// reconnect until `cleanup_stale_connections` drops them.
+    fn unregister_user_connection(&self, user_id: Uuid, connection_id: Uuid) {
+        if let Some(mut stream) = self.user_streams.get_mut(&user_id) {
+            stream.connections.remove(&connection_id);
+            stream.updated_at = chrono::Utc::now();
+        }
+    }
+
     /// Get connection statistics
     pub fn get_stats(&self) -> ConnectionStats {
         let total_connections = self.connections.len();
@@ -322,9 +1262,13 @@ impl WebSocketManager {
         }
 
         for connection_id in stale_connections {
-            self.connections.remove(&connection_id);
+            self.remove_connection(connection_id);
             info!("Removed stale WebSocket connection: {}", connection_id);
         }
+
+        // Forget the events of users who have been gone as long
+        self.user_streams
+            .retain(|_, stream| !stream.connections.is_empty() || stream.updated_at >= cutoff);
     }
 }
 
@@ -366,4 +1310,586 @@ mod tests {
             _ => panic!("Unexpected message type"),
         }
     }
+
+    fn book(sequence: u64) -> OrderBook {
+        OrderBook {
+            symbol: "BTCUSDT".to_string(),
+            sequence,
+            bids: Vec::new(),
+            asks: Vec::new(),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    fn connect(manager: &WebSocketManager) -> Uuid {
+        let connection_id = Uuid::new_v4();
+        manager.connections.insert(
+            connection_id,
+            ConnectionInfo {
+                id: connection_id,
+                user_id: None,
+                subscriptions: Subscriptions::default(),
+                connected_at: chrono::Utc::now(),
+                last_ping: chrono::Utc::now(),
+                missed_pongs: 0,
+                window_start: chrono::Utc::now(),
+                window_messages: 0,
+                lag_events: 0,
+            },
+        );
+        connection_id
+    }
+
+    #[tokio::test]
+    async fn test_slow_clients_are_dropped() {
+        let manager = WebSocketManager::new(100).with_slow_client_policy(SlowClientPolicy {
+            queue_capacity: 2,
+            max_lag_events: 1,
+        });
+        let connection_id = connect(&manager);
+
+        // A full outbound queue means the client is not keeping up
+        let (tx, _rx) = mpsc::channel(manager.slow_client.queue_capacity);
+        let outbound = Outbound {
+            connection_id,
+            encoding: Encoding::Json,
+            tx,
+            metrics: None,
+        };
+        assert!(outbound.send(&WsMessage::Pong));
+        assert!(outbound.send_frame(Message::Ping(Vec::new())));
+        assert!(!outbound.send(&WsMessage::Pong));
+
+        // Lagging the broadcast is tolerated up to the limit
+        assert!(WebSocketManager::record_lag(&manager.connections, connection_id, 1));
+        assert!(!WebSocketManager::record_lag(&manager.connections, connection_id, 1));
+
+        let json = serde_json::to_string(&WsMessage::ResyncRequired { skipped: 7 }).unwrap();
+        assert_eq!(json, r#"{"type":"ResyncRequired","data":{"skipped":7}}"#);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_and_subscription_cap() {
+        let manager = WebSocketManager::new(100).with_limits(ConnectionLimits {
+            max_messages_per_second: 3,
+            max_subscriptions: 2,
+        });
+        let connection_id = connect(&manager);
+
+        let subscribe = serde_json::to_string(&WsMessage::Subscribe {
+            channels: vec!["ticker.BTCUSDT".to_string(), "ticker.ETHUSDT".to_string(), "trades.BTCUSDT".to_string()],
+        })
+        .unwrap();
+        let replies = manager.handle_incoming_message(connection_id, &subscribe)
+            .await
+            .unwrap();
+        assert!(matches!(
+            replies.as_slice(),
+            [WsMessage::Success { .. }, WsMessage::Error { code: WsErrorCode::SubscriptionLimit, channels, .. }]
+                if channels == &["trades.BTCUSDT"]
+        ));
+        assert_eq!(manager.connections.get(&connection_id).unwrap().subscriptions.len(), 2);
+
+        // Messages beyond the per-second budget are dropped with an error
+        let ping = serde_json::to_string(&WsMessage::Ping).unwrap();
+        for _ in 0..2 {
+            let replies = manager.handle_incoming_message(connection_id, &ping)
+                .await
+                .unwrap();
+            assert!(matches!(replies.as_slice(), [WsMessage::Pong]));
+        }
+        let replies = manager.handle_incoming_message(connection_id, &ping)
+            .await
+            .unwrap();
+        assert!(matches!(replies.as_slice(), [WsMessage::Error { code: WsErrorCode::RateLimited, .. }]));
+
+        let json = serde_json::to_string(&replies[0]).unwrap();
+        assert!(json.contains("\"code\":\"rate_limited\""));
+    }
+
+    #[tokio::test]
+    async fn test_subscription_acknowledgements() {
+        let manager = WebSocketManager::new(100).with_symbols(["BTCUSDT", "ETHUSDT"]);
+        let connection_id = connect(&manager);
+
+        let subscribe = serde_json::to_string(&WsMessage::Subscribe {
+            channels: vec![
+                "ticker.BTCUSDT".to_string(),
+                "ticker.DOGEUSDT".to_string(),
+                "orders".to_string(),
+                "news.BTCUSDT".to_string(),
+                "trades.*".to_string(),
+            ],
+        })
+        .unwrap();
+        let replies = manager.handle_incoming_message(connection_id, &subscribe).await.unwrap();
+        let [WsMessage::Success { channels: accepted, .. }, errors @ ..] = replies.as_slice() else {
+            panic!("unexpected replies {:?}", replies);
+        };
+        assert_eq!(accepted, &["ticker.BTCUSDT", "trades.*"]);
+        let rejected: Vec<(WsErrorCode, &[String])> = errors
+            .iter()
+            .map(|error| match error {
+                WsMessage::Error { code, channels, .. } => (*code, channels.as_slice()),
+                other => panic!("unexpected reply {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                (WsErrorCode::UnknownSymbol, &["ticker.DOGEUSDT".to_string()][..]),
+                (WsErrorCode::Unauthenticated, &["orders".to_string()][..]),
+                (WsErrorCode::UnknownChannel, &["news.BTCUSDT".to_string()][..]),
+            ]
+        );
+
+        // Newly listed symbols become subscribable
+        manager.add_symbol("DOGEUSDT");
+        let replies = manager.handle_incoming_message(connection_id, &subscribe).await.unwrap();
+        assert!(matches!(&replies[0], WsMessage::Success { channels, .. } if channels.len() == 3));
+
+        let unsubscribe = serde_json::to_string(&WsMessage::Unsubscribe {
+            channels: vec!["ticker.BTCUSDT".to_string(), "ticker.SOLUSDT".to_string()],
+        })
+        .unwrap();
+        let replies = manager.handle_incoming_message(connection_id, &unsubscribe).await.unwrap();
+        assert!(matches!(
+            replies.as_slice(),
+            [WsMessage::Success { .. }, WsMessage::Error { code: WsErrorCode::NotSubscribed, channels, .. }]
+                if channels == &["ticker.SOLUSDT"]
+        ));
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingBus(std::sync::Mutex<Vec<BridgeEvent>>);
+
+    impl EventBus for RecordingBus {
+        fn publish(&self, event: &BridgeEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_bridged_between_instances() {
+        let bus = Arc::new(RecordingBus::default());
+        let origin = WebSocketManager::new(100).with_bus(bus.clone());
+        let replica = WebSocketManager::new(100);
+        let user_id = Uuid::new_v4();
+        let mut replica_user_rx = replica.register_user_connection(user_id, Uuid::new_v4());
+
+        origin.publish_book_snapshot(book(3)).await.unwrap();
+        origin.send_user_data(user_id, WsMessage::Pong).await.unwrap();
+        origin.send_user_data(user_id, WsMessage::Pong).await.unwrap();
+        let events = bus.0.lock().unwrap().clone();
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|event| event.origin == origin.instance_id()));
+
+        // Bridged through JSON, as over Redis
+        for event in events {
+            let event: BridgeEvent = serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+            replica.receive_bridged(event.clone()).await.unwrap();
+            // The origin ignores its own events coming back
+            origin.receive_bridged(event).await.unwrap();
+        }
+        assert_eq!(replica.books.get("BTCUSDT").unwrap().sequence, 3);
+        assert!(matches!(replica_user_rx.try_recv(), Ok(WsMessage::UserEvent { seq: 1, .. })));
+        assert!(matches!(replica_user_rx.try_recv(), Ok(WsMessage::UserEvent { seq: 2, .. })));
+        assert_eq!(origin.user_streams.get(&user_id).unwrap().recent.len(), 2);
+
+        // The replica continues the origin's sequence, so clients resume anywhere
+        replica.send_user_data(user_id, WsMessage::Pong).await.unwrap();
+        assert!(matches!(replica_user_rx.try_recv(), Ok(WsMessage::UserEvent { seq: 3, .. })));
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingMetrics(std::sync::Mutex<Vec<String>>);
+
+    impl WsMetrics for RecordingMetrics {
+        fn connections(&self, open: usize) {
+            self.0.lock().unwrap().push(format!("connections {}", open));
+        }
+        fn message_sent(&self, channel: &str) {
+            self.0.lock().unwrap().push(format!("sent {}", channel));
+        }
+        fn message_received(&self, request: &str) {
+            self.0.lock().unwrap().push(format!("received {}", request));
+        }
+        fn messages_dropped(&self, reason: DropReason, count: u64) {
+            self.0.lock().unwrap().push(format!("dropped {} {}", reason.as_str(), count));
+        }
+        fn broadcast_lag(&self, skipped: u64) {
+            self.0.lock().unwrap().push(format!("lag {}", skipped));
+        }
+        fn subscriptions(&self, symbol: &str, count: usize) {
+            self.0.lock().unwrap().push(format!("subscriptions {} {}", symbol, count));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_depth_and_interval_filtering() {
+        let manager = WebSocketManager::new(100);
+        let connection_id = connect(&manager);
+        let subscribe = serde_json::to_string(&WsMessage::Subscribe {
+            channels: vec![
+                "orderbook.BTCUSDT?depth=1&interval=100ms".to_string(),
+                "trades.BTCUSDT?interval=100ms".to_string(),
+            ],
+        })
+        .unwrap();
+        let replies = manager.handle_incoming_message(connection_id, &subscribe).await.unwrap();
+        assert!(matches!(
+            replies.as_slice(),
+            [WsMessage::Success { .. }, WsMessage::Error { code: WsErrorCode::InvalidOptions, .. }]
+        ));
+
+        let level = |price: i64| flowex_types::OrderBookLevel {
+            price: price.into(),
+            quantity: 1.into(),
+        };
+        let mut deep = book(1);
+        deep.bids = vec![level(99), level(98)];
+        deep.asks = vec![level(101), level(102)];
+
+        // The first update goes out trimmed; the next ones are conflated
+        let mut throttle = Throttle::default();
+        let filter = |throttle: &mut Throttle, book: &OrderBook| {
+            WebSocketManager::filter_message(&manager.connections, connection_id, throttle, WsMessage::OrderBookUpdate(book.clone()))
+        };
+        let Some(WsMessage::OrderBookUpdate(sent)) = filter(&mut throttle, &deep) else {
+            panic!("expected the first update to be sent");
+        };
+        assert_eq!((sent.bids.len(), sent.asks.len()), (1, 1));
+        assert!(filter(&mut throttle, &book(2)).is_none());
+        assert!(filter(&mut throttle, &book(3)).is_none());
+        let due = throttle.take_due(tokio::time::Instant::now() + Duration::from_millis(100));
+        assert!(matches!(due.as_slice(), [WsMessage::OrderBookUpdate(book)] if book.sequence == 3));
+
+        let unsubscribe = serde_json::to_string(&WsMessage::Unsubscribe {
+            channels: vec!["orderbook.BTCUSDT?depth=1".to_string()],
+        })
+        .unwrap();
+        manager.handle_incoming_message(connection_id, &unsubscribe).await.unwrap();
+        assert!(manager.connections.get(&connection_id).unwrap().subscriptions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_and_subscription_counts() {
+        let recorder = Arc::new(RecordingMetrics::default());
+        let manager = WebSocketManager::new(100)
+            .with_metrics(recorder.clone())
+            .with_slow_client_policy(SlowClientPolicy { queue_capacity: 1, max_lag_events: 3 });
+        let (first, second) = (connect(&manager), connect(&manager));
+
+        let subscribe = |channels: &[&str]| {
+            serde_json::to_string(&WsMessage::Subscribe {
+                channels: channels.iter().map(|channel| channel.to_string()).collect(),
+            })
+            .unwrap()
+        };
+        manager.handle_incoming_message(first, &subscribe(&["ticker.BTCUSDT", "trades.*"])).await.unwrap();
+        manager.handle_incoming_message(second, &subscribe(&["kline.1m.BTCUSDT"])).await.unwrap();
+        assert_eq!(manager.subscriptions_by_symbol().get("BTCUSDT"), Some(&2));
+        assert_eq!(manager.subscriptions_by_symbol().get("*"), Some(&1));
+
+        // Closing a connection releases its subscriptions
+        manager.remove_connection(first);
+        assert_eq!(manager.subscriptions_by_symbol(), HashMap::from([("BTCUSDT".to_string(), 1)]));
+
+        let (tx, _rx) = mpsc::channel(1);
+        let outbound = Outbound {
+            connection_id: second,
+            encoding: Encoding::Json,
+            tx,
+            metrics: manager.metrics.clone(),
+        };
+        assert!(outbound.send(&WsMessage::TickerUpdate(Ticker {
+            symbol: "BTCUSDT".to_string(),
+            price: Default::default(),
+            change: Default::default(),
+            change_percent: Default::default(),
+            high: Default::default(),
+            low: Default::default(),
+            volume: Default::default(),
+            vwap: None,
+            timestamp: chrono::Utc::now(),
+        })));
+        assert!(!outbound.send(&WsMessage::Pong));
+
+        let recorded = recorder.0.lock().unwrap().clone();
+        for expected in [
+            "received subscribe",
+            "subscriptions BTCUSDT 2",
+            "subscriptions * 0",
+            "connections 1",
+            "sent ticker",
+            "dropped queue_full 1",
+        ] {
+            assert!(recorded.iter().any(|line| line == expected), "missing {}", expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_connections_to_close() {
+        let manager = WebSocketManager::new(100);
+        let connection_id = connect(&manager);
+        let mut shutdown_rx = manager.shutdown_tx.subscribe();
+
+        // A connection that never closes makes the drain time out
+        assert!(manager.shutdown(Duration::from_millis(20)).await.is_err());
+        assert!(manager.is_shutting_down());
+        assert!(*shutdown_rx.borrow_and_update());
+
+        let closer = manager.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            closer.remove_connection(connection_id);
+        });
+        manager.shutdown(Duration::from_secs(5)).await.unwrap();
+        assert!(manager.connections.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_drops_silent_connections() {
+        let manager = WebSocketManager::new(100).with_heartbeat(HeartbeatConfig {
+            interval: Duration::from_secs(5),
+            max_missed_pongs: 2,
+        });
+        assert_eq!(manager.heartbeat.interval, Duration::from_secs(5));
+        let connection_id = connect(&manager);
+
+        assert!(WebSocketManager::record_heartbeat(&manager.connections, connection_id, 2));
+        assert!(WebSocketManager::record_heartbeat(&manager.connections, connection_id, 2));
+        assert!(!WebSocketManager::record_heartbeat(&manager.connections, connection_id, 2));
+
+        // A pong (or client ping) keeps the connection alive
+        WebSocketManager::record_alive(&manager.connections, connection_id);
+        assert!(WebSocketManager::record_heartbeat(&manager.connections, connection_id, 2));
+
+        let ping = serde_json::to_string(&WsMessage::Ping).unwrap();
+        let replies = manager.handle_incoming_message(connection_id, &ping)
+            .await
+            .unwrap();
+        assert!(matches!(replies.as_slice(), [WsMessage::Pong]));
+        assert_eq!(manager.connections.get(&connection_id).unwrap().missed_pongs, 0);
+
+        assert!(!WebSocketManager::record_heartbeat(&manager.connections, Uuid::new_v4(), 2));
+    }
+
+    #[tokio::test]
+    async fn test_delta_subscription_starts_with_snapshot() {
+        let manager = WebSocketManager::new(100);
+        manager.publish_book_snapshot(book(3)).await.unwrap();
+        let connection_id = connect(&manager);
+
+        let subscribe = serde_json::to_string(&WsMessage::Subscribe {
+            channels: vec![order_book_delta_channel("BTCUSDT")],
+        })
+        .unwrap();
+        let replies = manager.handle_incoming_message(connection_id, &subscribe)
+            .await
+            .unwrap();
+        assert!(matches!(replies.as_slice(), [WsMessage::Success { .. }, WsMessage::OrderBookSnapshot(book)] if book.sequence == 3));
+
+        // Deltas for the subscribed symbol are delivered and applied
+        let update = BookUpdate {
+            symbol: "BTCUSDT".to_string(),
+            sequence: 5,
+            previous_sequence: 3,
+            deltas: Vec::new(),
+            timestamp: chrono::Utc::now(),
+        };
+        let delta = WsMessage::OrderBookDelta(update.clone());
+        assert!(WebSocketManager::should_send_message(&manager.connections, connection_id, &delta));
+        manager.publish_book_update(update).await.unwrap();
+
+        // Resync returns the book at the latest applied sequence
+        let resync = serde_json::to_string(&WsMessage::Resync { symbol: "BTCUSDT".to_string() }).unwrap();
+        let replies = manager.handle_incoming_message(connection_id, &resync)
+            .await
+            .unwrap();
+        assert!(matches!(replies.as_slice(), [WsMessage::OrderBookSnapshot(book)] if book.sequence == 5));
+    }
+
+    #[tokio::test]
+    async fn test_user_data_reaches_every_connection() {
+        let manager = WebSocketManager::new(100);
+        let user_id = Uuid::new_v4();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut first_rx = manager.register_user_connection(user_id, first);
+        let mut second_rx = manager.register_user_connection(user_id, second);
+
+        manager.send_user_data(user_id, WsMessage::Pong).await.unwrap();
+        assert!(matches!(first_rx.try_recv(), Ok(WsMessage::UserEvent { seq: 1, .. })));
+        assert!(matches!(second_rx.try_recv(), Ok(WsMessage::UserEvent { seq: 1, .. })));
+
+        // Closing one connection leaves the other subscribed
+        manager.unregister_user_connection(user_id, first);
+        manager.send_user_data(user_id, WsMessage::Pong).await.unwrap();
+        assert!(matches!(second_rx.try_recv(), Ok(WsMessage::UserEvent { seq: 2, .. })));
+        assert!(first_rx.try_recv().is_err());
+
+        manager.unregister_user_connection(user_id, second);
+        assert!(manager.user_streams.get(&user_id).unwrap().connections.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fills_reach_both_sides_of_a_trade() {
+        let manager = WebSocketManager::new(100);
+        let (maker, taker) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut maker_rx = manager.register_user_connection(maker, Uuid::new_v4());
+        let mut taker_rx = manager.register_user_connection(taker, Uuid::new_v4());
+        let trade = Trade {
+            id: Uuid::new_v4(),
+            symbol: "BTCUSDT".to_string(),
+            sequence: 1,
+            price: Default::default(),
+            quantity: Default::default(),
+            side: flowex_types::OrderSide::Buy,
+            maker_order_id: Uuid::new_v4(),
+            taker_order_id: Uuid::new_v4(),
+            maker_user_id: maker,
+            taker_user_id: taker,
+            is_buyer_maker: false,
+            maker_fee: Default::default(),
+            maker_fee_currency: None,
+            taker_fee: Default::default(),
+            taker_fee_currency: None,
+            timestamp: chrono::Utc::now(),
+        };
+
+        manager.publish_fills(std::slice::from_ref(&trade)).await.unwrap();
+        for (rx, order_id, liquidity) in [
+            (&mut maker_rx, trade.maker_order_id, flowex_types::Liquidity::Maker),
+            (&mut taker_rx, trade.taker_order_id, flowex_types::Liquidity::Taker),
+        ] {
+            let Ok(WsMessage::UserEvent { seq: 1, event }) = rx.try_recv() else {
+                panic!("expected a user event");
+            };
+            let WsMessage::FillUpdate(fill) = *event else {
+                panic!("expected a fill");
+            };
+            assert_eq!((fill.order_id, fill.liquidity), (order_id, liquidity));
+        }
+
+        assert!(manager.check_channel("fills", true).is_ok());
+        assert_eq!(manager.check_channel("fills", false), Err(WsErrorCode::Unauthenticated));
+    }
+
+    #[tokio::test]
+    async fn test_resume_replays_missed_user_events() {
+        let manager = WebSocketManager::new(100).with_user_event_buffer(3);
+        let user_id = Uuid::new_v4();
+        let connection_id = connect(&manager);
+        manager.connections.get_mut(&connection_id).unwrap().user_id = Some(user_id);
+
+        let balance = |available: &str| WsMessage::BalanceUpdate {
+            currency: "USDT".to_string(),
+            available: available.to_string(),
+            locked: "0".to_string(),
+        };
+        for available in ["1", "2", "3", "4"] {
+            manager.send_user_data(user_id, balance(available)).await.unwrap();
+        }
+
+        let resume = |from_seq: u64| serde_json::to_string(&WsMessage::Resume { from_seq }).unwrap();
+        let replies = manager.handle_incoming_message(connection_id, &resume(3))
+            .await
+            .unwrap();
+        let seqs: Vec<u64> = replies
+            .iter()
+            .map(|reply| match reply {
+                WsMessage::UserEvent { seq, .. } => *seq,
+                other => panic!("unexpected reply {:?}", other),
+            })
+            .collect();
+        assert_eq!(seqs, vec![3, 4]);
+
+        // The first event has left the buffer
+        let replies = manager.handle_incoming_message(connection_id, &resume(1))
+            .await
+            .unwrap();
+        assert!(matches!(replies[0], WsMessage::Error { code: WsErrorCode::ResumeGap, .. }));
+        assert_eq!(replies.len(), 4);
+
+        let anonymous = connect(&manager);
+        let replies = manager.handle_incoming_message(anonymous, &resume(1))
+            .await
+            .unwrap();
+        assert!(matches!(replies.as_slice(), [WsMessage::Error { code: WsErrorCode::Unauthenticated, .. }]));
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_subscriptions() {
+        let manager = WebSocketManager::new(100);
+        manager.publish_book_snapshot(book(3)).await.unwrap();
+        let connection_id = connect(&manager);
+
+        let subscribe = serde_json::to_string(&WsMessage::Subscribe {
+            channels: vec!["orderbook.delta.*".to_string(), "kline.*.BTC*".to_string()],
+        })
+        .unwrap();
+        let replies = manager.handle_incoming_message(connection_id, &subscribe)
+            .await
+            .unwrap();
+        assert!(matches!(
+            replies.as_slice(),
+            [WsMessage::Success { .. }, WsMessage::OrderBookSnapshot(book)] if book.symbol == "BTCUSDT"
+        ));
+
+        let update = |symbol: &str| {
+            WsMessage::OrderBookDelta(BookUpdate {
+                symbol: symbol.to_string(),
+                sequence: 5,
+                previous_sequence: 3,
+                deltas: Vec::new(),
+                timestamp: chrono::Utc::now(),
+            })
+        };
+        assert!(WebSocketManager::should_send_message(&manager.connections, connection_id, &update("ETHUSDT")));
+        assert_eq!(WebSocketManager::subscribed_snapshots(&manager.connections, &manager.books, connection_id).len(), 1);
+
+        let unsubscribe = serde_json::to_string(&WsMessage::Unsubscribe {
+            channels: vec!["orderbook.delta.*".to_string()],
+        })
+        .unwrap();
+        manager.handle_incoming_message(connection_id, &unsubscribe)
+            .await
+            .unwrap();
+        assert!(!WebSocketManager::should_send_message(&manager.connections, connection_id, &update("ETHUSDT")));
+    }
+
+    #[tokio::test]
+    async fn test_candles_routed_by_interval_and_symbol() {
+        let manager = WebSocketManager::new(100);
+        let connection_id = connect(&manager);
+        manager
+            .connections
+            .get_mut(&connection_id)
+            .unwrap()
+            .subscriptions
+            .insert(kline_channel(CandleInterval::OneMinute, "BTCUSDT"));
+
+        let now = chrono::Utc::now();
+        let candle = |interval: CandleInterval| Candle {
+            symbol: "BTCUSDT".to_string(),
+            interval,
+            open_time: interval.open_time(now),
+            close_time: interval.open_time(now) + chrono::Duration::seconds(interval.seconds()),
+            open: Default::default(),
+            high: Default::default(),
+            low: Default::default(),
+            close: Default::default(),
+            volume: Default::default(),
+            quote_volume: Default::default(),
+            trade_count: 1,
+        };
+        let one_minute = WsMessage::CandleUpdate { candle: candle(CandleInterval::OneMinute), closed: false };
+        let one_hour = WsMessage::CandleUpdate { candle: candle(CandleInterval::OneHour), closed: true };
+        assert!(WebSocketManager::should_send_message(&manager.connections, connection_id, &one_minute));
+        assert!(!WebSocketManager::should_send_message(&manager.connections, connection_id, &one_hour));
+
+        let json = serde_json::to_string(&one_minute).unwrap();
+        assert!(json.contains("\"type\":\"CandleUpdate\""));
+        assert_eq!(kline_channel(CandleInterval::OneMinute, "BTCUSDT"), "kline.1m.BTCUSDT");
+    }
 }
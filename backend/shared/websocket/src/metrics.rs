@@ -0,0 +1,110 @@
+//! WebSocket metrics
+//!
+//! A `WsMetrics` sink installed on the manager is told how many connections
+//! are open, every message sent (by channel) and received (by request),
+//! messages dropped for slow or chatty clients, how far connections fall
+//! behind the market data broadcast, and how many subscriptions each symbol
+//! has; wildcard and all-symbol subscriptions count under `*`. The
+//! `flowex-metrics` collector implements the sink and exports everything to
+//! Prometheus.
+
+use crate::WsMessage;
+use flowex_metrics::MetricsCollector;
+use std::fmt::Debug;
+
+/// Why messages bound for or from a client were dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// The connection's outbound queue was full
+    QueueFull,
+    /// The connection fell behind the market data broadcast
+    Lagged,
+    /// The client sent more requests than its rate limit allows
+    RateLimited,
+}
+
+impl DropReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::QueueFull => "queue_full",
+            DropReason::Lagged => "lagged",
+            DropReason::RateLimited => "rate_limited",
+        }
+    }
+}
+
+/// Receives the WebSocket manager's connection and traffic measurements
+pub trait WsMetrics: Debug + Send + Sync {
+    /// The number of open connections changed
+    fn connections(&self, open: usize);
+
+    /// A message was queued for a client on `channel` (see `channel_of`)
+    fn message_sent(&self, channel: &str);
+
+    /// A client request (see `request_of`) was received
+    fn message_received(&self, request: &str);
+
+    /// `count` messages were dropped
+    fn messages_dropped(&self, reason: DropReason, count: u64);
+
+    /// A connection fell `skipped` messages behind the broadcast
+    fn broadcast_lag(&self, skipped: u64);
+
+    /// `symbol` now has `count` subscriptions across all connections
+    fn subscriptions(&self, symbol: &str, count: usize);
+}
+
+impl WsMetrics for MetricsCollector {
+    fn connections(&self, open: usize) {
+        self.record_websocket_connections(open as u32);
+    }
+
+    fn message_sent(&self, channel: &str) {
+        self.record_websocket_message_sent(channel);
+    }
+
+    fn message_received(&self, request: &str) {
+        self.record_websocket_message_received(request);
+    }
+
+    fn messages_dropped(&self, reason: DropReason, count: u64) {
+        self.record_websocket_messages_dropped(reason.as_str(), count);
+    }
+
+    fn broadcast_lag(&self, skipped: u64) {
+        self.record_websocket_broadcast_lag(skipped);
+    }
+
+    fn subscriptions(&self, symbol: &str, count: usize) {
+        self.record_websocket_subscriptions(symbol, count);
+    }
+}
+
+/// Channel a message is sent on, as a metrics label
+pub fn channel_of(message: &WsMessage) -> &'static str {
+    match message {
+        WsMessage::OrderBookUpdate(_) => "orderbook",
+        WsMessage::OrderBookSnapshot(_) | WsMessage::OrderBookDelta(_) => "orderbook.delta",
+        WsMessage::CandleUpdate { .. } => "kline",
+        WsMessage::TickerUpdate(_) => "ticker",
+        WsMessage::TradeUpdate(_) => "trades",
+        WsMessage::IndexPriceUpdate(_) => "index",
+        WsMessage::OrderUpdate(_)
+        | WsMessage::FillUpdate(_)
+        | WsMessage::BalanceUpdate { .. }
+        | WsMessage::UserEvent { .. } => "user",
+        _ => "system",
+    }
+}
+
+/// Type of a client request, as a metrics label
+pub fn request_of(message: &WsMessage) -> &'static str {
+    match message {
+        WsMessage::Subscribe { .. } => "subscribe",
+        WsMessage::Unsubscribe { .. } => "unsubscribe",
+        WsMessage::Resync { .. } => "resync",
+        WsMessage::Resume { .. } => "resume",
+        WsMessage::Ping => "ping",
+        _ => "other",
+    }
+}
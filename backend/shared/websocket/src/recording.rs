@@ -0,0 +1,359 @@
+//! Market data recording and replay
+//!
+//! A `MarketRecorder` appends every market data message the manager
+//! broadcasts (trades, tickers, full books, book snapshots and deltas,
+//! candles) to JSON lines files partitioned by hour,
+//! `<dir>/<YYYY-MM-DD>/<HH>.jsonl`, each line stamped with the time it was
+//! recorded. `replay` streams a recorded time range back at the original
+//! pace, or scaled by a speed factor, for debugging and backtesting;
+//! `handle_replay` serves a replay over a WebSocket.
+
+use crate::{Encoding, WebSocketManager, WsMessage};
+use axum::{
+    extract::ws::{close_code, CloseFrame, Message, WebSocketUpgrade},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration as ChronoDuration, DurationRound, Utc};
+use flowex_types::{FlowExError, FlowExResult};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info, warn};
+
+/// Longest time range one replay may cover
+pub const MAX_REPLAY_RANGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Messages read ahead of a paced replay
+const REPLAY_BUFFER: usize = 1024;
+
+/// A market data message as recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    pub recorded_at: DateTime<Utc>,
+    pub message: WsMessage,
+}
+
+/// Whether a message is market data, and so recorded
+pub fn is_market_data(message: &WsMessage) -> bool {
+    market_symbol(message).is_some()
+}
+
+/// Symbol a market data message is about
+pub fn market_symbol(message: &WsMessage) -> Option<&str> {
+    match message {
+        WsMessage::OrderBookUpdate(book) | WsMessage::OrderBookSnapshot(book) => Some(&book.symbol),
+        WsMessage::OrderBookDelta(update) => Some(&update.symbol),
+        WsMessage::CandleUpdate { candle, .. } => Some(&candle.symbol),
+        WsMessage::TickerUpdate(ticker) => Some(&ticker.symbol),
+        WsMessage::TradeUpdate(trade) => Some(&trade.symbol),
+        WsMessage::IndexPriceUpdate(index) => Some(&index.symbol),
+        _ => None,
+    }
+}
+
+/// File holding the messages recorded in the hour starting at `hour`
+pub fn partition_path(dir: &Path, hour: DateTime<Utc>) -> PathBuf {
+    dir.join(hour.format("%Y-%m-%d").to_string())
+        .join(format!("{}.jsonl", hour.format("%H")))
+}
+
+fn start_of_hour(at: DateTime<Utc>) -> DateTime<Utc> {
+    at.duration_trunc(ChronoDuration::hours(1)).unwrap_or(at)
+}
+
+/// Appends market data to hourly partitions under a directory
+#[derive(Debug)]
+pub struct MarketRecorder {
+    dir: PathBuf,
+    partition: Option<(DateTime<Utc>, BufWriter<File>)>,
+}
+
+impl MarketRecorder {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            partition: None,
+        }
+    }
+
+    /// Append a message recorded at `at`. Messages that are not market
+    /// data are skipped; returns whether the message was recorded.
+    pub fn record(&mut self, message: &WsMessage, at: DateTime<Utc>) -> FlowExResult<bool> {
+        if !is_market_data(message) {
+            return Ok(false);
+        }
+
+        let hour = start_of_hour(at);
+        if self.partition.as_ref().map(|(open, _)| *open) != Some(hour) {
+            let path = partition_path(&self.dir, hour);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| recording_error(parent, e))?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| recording_error(&path, e))?;
+            self.partition = Some((hour, BufWriter::new(file)));
+        }
+        let Some((_, writer)) = self.partition.as_mut() else {
+            return Ok(false);
+        };
+
+        let line = serde_json::to_string(&RecordedMessage {
+            recorded_at: at,
+            message: message.clone(),
+        })
+        .map_err(|e| FlowExError::Internal(format!("Failed to encode recorded message: {}", e)))?;
+        writeln!(writer, "{}", line)
+            .and_then(|_| writer.flush())
+            .map_err(|e| recording_error(&self.dir, e))?;
+        Ok(true)
+    }
+
+    /// Record everything `manager` broadcasts from now on, on a blocking
+    /// thread, until the manager is dropped
+    pub fn spawn(mut self, manager: &WebSocketManager) -> tokio::task::JoinHandle<()> {
+        let mut rx = manager.subscribe_market_data();
+        info!("Recording market data to {}", self.dir.display());
+        tokio::task::spawn_blocking(move || loop {
+            match rx.blocking_recv() {
+                Ok(message) => {
+                    if let Err(e) = self.record(&message, Utc::now()) {
+                        error!("Failed to record market data: {}", e);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Market data recorder fell behind, {} messages not recorded", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        })
+    }
+}
+
+/// Read the messages recorded in the hour starting at `hour`; a missing
+/// partition is empty and a truncated final line is ignored
+pub fn read_partition(dir: &Path, hour: DateTime<Utc>) -> FlowExResult<Vec<RecordedMessage>> {
+    let path = partition_path(dir, hour);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(recording_error(&path, e)),
+    };
+
+    let lines: Vec<String> = BufReader::new(file)
+        .lines()
+        .collect::<Result<_, _>>()
+        .map_err(|e| recording_error(&path, e))?;
+
+    let mut messages = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<RecordedMessage>(line) {
+            Ok(message) => messages.push(message),
+            Err(e) if index + 1 == lines.len() => {
+                warn!("Ignoring truncated recording tail in {}: {}", path.display(), e);
+            }
+            Err(e) => {
+                return Err(FlowExError::Internal(format!(
+                    "Corrupt recorded message at line {} of {}: {}",
+                    index + 1,
+                    path.display(),
+                    e
+                )))
+            }
+        }
+    }
+    Ok(messages)
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+/// A time range to replay
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplayRequest {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Playback speed relative to the recording: 2.0 replays twice as
+    /// fast; 0 sends everything without pauses
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+    /// Replay only this symbol's messages
+    #[serde(default)]
+    pub symbol: Option<String>,
+}
+
+impl ReplayRequest {
+    pub fn validate(&self) -> FlowExResult<()> {
+        if self.to <= self.from {
+            return Err(FlowExError::Validation("Replay range must end after it starts".to_string()));
+        }
+        if (self.to - self.from).to_std().map_or(true, |range| range > MAX_REPLAY_RANGE) {
+            return Err(FlowExError::Validation(format!(
+                "Replay range may cover at most {} hours",
+                MAX_REPLAY_RANGE.as_secs() / 3600
+            )));
+        }
+        if !self.speed.is_finite() || self.speed < 0.0 {
+            return Err(FlowExError::Validation("Replay speed must be zero or positive".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Send the messages recorded under `dir` in the requested range to `tx`,
+/// spaced as recorded and scaled by the requested speed. Stops early when
+/// the receiver goes away; returns the number of messages sent.
+pub async fn replay(dir: &Path, request: &ReplayRequest, tx: &mpsc::Sender<WsMessage>) -> FlowExResult<usize> {
+    request.validate()?;
+
+    let started = tokio::time::Instant::now();
+    let mut first: Option<DateTime<Utc>> = None;
+    let mut sent = 0;
+    let mut hour = start_of_hour(request.from);
+    while hour < request.to {
+        let partition_dir = dir.to_path_buf();
+        let messages = tokio::task::spawn_blocking(move || read_partition(&partition_dir, hour))
+            .await
+            .map_err(|e| FlowExError::Internal(format!("Recording reader failed: {}", e)))??;
+
+        for recorded in messages {
+            if recorded.recorded_at < request.from || recorded.recorded_at >= request.to {
+                continue;
+            }
+            if request.symbol.as_deref().is_some_and(|symbol| market_symbol(&recorded.message) != Some(symbol)) {
+                continue;
+            }
+
+            if request.speed > 0.0 {
+                let offset = (recorded.recorded_at - *first.get_or_insert(recorded.recorded_at))
+                    .to_std()
+                    .unwrap_or_default();
+                tokio::time::sleep_until(started + offset.div_f64(request.speed)).await;
+            }
+            if tx.send(recorded.message).await.is_err() {
+                return Ok(sent);
+            }
+            sent += 1;
+        }
+        hour += ChronoDuration::hours(1);
+    }
+    Ok(sent)
+}
+
+/// Stream a replay of the recordings under `dir` to a WebSocket client as
+/// JSON frames, closing the socket when the range is done
+pub async fn handle_replay(ws: WebSocketUpgrade, dir: PathBuf, request: ReplayRequest) -> Response {
+    if let Err(e) = request.validate() {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+
+    ws.on_upgrade(move |mut socket| async move {
+        let (tx, mut rx) = mpsc::channel(REPLAY_BUFFER);
+        let reader = tokio::spawn(async move { replay(&dir, &request, &tx).await });
+
+        while let Some(message) = rx.recv().await {
+            if socket.send(Encoding::Json.encode(&message)).await.is_err() {
+                break;
+            }
+        }
+        drop(rx);
+
+        match reader.await {
+            Ok(Ok(sent)) => info!("Replayed {} recorded market data messages", sent),
+            Ok(Err(e)) => warn!("Market data replay failed: {}", e),
+            Err(e) => warn!("Market data replay task failed: {}", e),
+        }
+        let _ = socket
+            .send(Message::Close(Some(CloseFrame {
+                code: close_code::NORMAL,
+                reason: "Replay complete".into(),
+            })))
+            .await;
+    })
+}
+
+fn recording_error(path: &Path, e: std::io::Error) -> FlowExError {
+    FlowExError::Internal(format!("Recording I/O error on {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flowex_types::{OrderSide, Trade};
+    use uuid::Uuid;
+
+    fn trade(symbol: &str) -> WsMessage {
+        WsMessage::TradeUpdate(Trade {
+            id: Uuid::new_v4(),
+            symbol: symbol.to_string(),
+            sequence: 1,
+            price: Default::default(),
+            quantity: Default::default(),
+            side: OrderSide::Buy,
+            maker_order_id: Uuid::new_v4(),
+            taker_order_id: Uuid::new_v4(),
+            maker_user_id: Uuid::new_v4(),
+            taker_user_id: Uuid::new_v4(),
+            is_buyer_maker: false,
+            maker_fee: Default::default(),
+            maker_fee_currency: None,
+            taker_fee: Default::default(),
+            taker_fee_currency: None,
+            timestamp: Utc::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay_range() {
+        let dir = std::env::temp_dir().join(format!("flowex-recording-{}", Uuid::new_v4()));
+        let start = "2024-03-01T09:59:58Z".parse::<DateTime<Utc>>().unwrap();
+        let mut recorder = MarketRecorder::new(&dir);
+        let second = |n: i64| start + ChronoDuration::seconds(n);
+
+        assert!(recorder.record(&trade("BTCUSDT"), second(0)).unwrap());
+        assert!(recorder.record(&trade("ETHUSDT"), second(1)).unwrap());
+        assert!(!recorder.record(&WsMessage::Pong, second(1)).unwrap());
+        // Crosses into the next hour's partition
+        assert!(recorder.record(&trade("BTCUSDT"), second(3)).unwrap());
+        assert!(recorder.record(&trade("BTCUSDT"), second(9)).unwrap());
+        assert_eq!(read_partition(&dir, start_of_hour(second(0))).unwrap().len(), 2);
+        assert_eq!(read_partition(&dir, start_of_hour(second(3))).unwrap().len(), 2);
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let request = ReplayRequest {
+            from: second(0),
+            to: second(5),
+            speed: 0.0,
+            symbol: Some("BTCUSDT".to_string()),
+        };
+        assert_eq!(replay(&dir, &request, &tx).await.unwrap(), 2);
+        while let Ok(message) = rx.try_recv() {
+            assert_eq!(market_symbol(&message), Some("BTCUSDT"));
+        }
+
+        // Paced at 10x, three seconds of recording take 300ms
+        let request = ReplayRequest {
+            from: second(0),
+            to: second(5),
+            speed: 10.0,
+            symbol: None,
+        };
+        let started = std::time::Instant::now();
+        assert_eq!(replay(&dir, &request, &tx).await.unwrap(), 3);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+
+        let backwards = ReplayRequest { from: second(5), to: second(0), speed: 1.0, symbol: None };
+        assert!(backwards.validate().is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
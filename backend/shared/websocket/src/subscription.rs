@@ -0,0 +1,238 @@
+//! Channel subscriptions
+//!
+//! A subscription names a channel, or is a pattern in which `*` matches any
+//! run of characters: `ticker.*` covers every symbol's ticker and
+//! `trades.BTC-*` the trades of every BTC pair, so a client need not
+//! enumerate hundreds of symbols. A connection's subscriptions are kept
+//! compiled: channel names in a hash set and patterns pre-split at their
+//! wildcards, so routing a message never re-parses a subscription. Each
+//! also keeps the filtering options it was made with; see [`crate::filter`].
+//!
+//! Market data channels are `ticker.<symbol>`, `trades.<symbol>`,
+//! `orderbook.<symbol>`, `orderbook.delta.<symbol>`, `index.<symbol>` and
+//! `kline.<interval>.<symbol>`, plus `ticker.all` and `trades.all`. The
+//! private `orders`, `balances` and `fills` channels need an authenticated
+//! connection.
+
+use crate::filter::ChannelOptions;
+use crate::ORDER_BOOK_DELTA_CHANNEL;
+use flowex_types::CandleInterval;
+use std::collections::{HashMap, HashSet};
+
+/// Channels carrying an authenticated user's own data
+pub const PRIVATE_CHANNELS: [&str; 3] = ["orders", "balances", "fills"];
+
+/// What a channel name refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind<'a> {
+    /// Market data of one symbol
+    Market { symbol: &'a str },
+    /// Market data of every symbol, e.g. `ticker.all`
+    AllSymbols,
+    /// Data of the connection's user
+    Private,
+}
+
+/// Parse a channel name, or `None` if there is no such channel
+pub fn parse_channel(channel: &str) -> Option<ChannelKind<'_>> {
+    if PRIVATE_CHANNELS.contains(&channel) {
+        return Some(ChannelKind::Private);
+    }
+    if let Some(rest) = channel.strip_prefix("kline.") {
+        let (interval, symbol) = rest.split_once('.')?;
+        return CandleInterval::ALL
+            .iter()
+            .any(|candidate| candidate.as_str() == interval)
+            .then(|| market(symbol))
+            .flatten();
+    }
+    if let Some(symbol) = channel.strip_prefix(ORDER_BOOK_DELTA_CHANNEL) {
+        return market(symbol);
+    }
+    match channel.split_once('.')? {
+        ("ticker" | "trades", "all") => Some(ChannelKind::AllSymbols),
+        ("ticker" | "trades" | "orderbook" | "index", symbol) => market(symbol),
+        _ => None,
+    }
+}
+
+fn market(symbol: &str) -> Option<ChannelKind<'_>> {
+    (!symbol.is_empty()).then_some(ChannelKind::Market { symbol })
+}
+
+/// A channel pattern, split into the literal runs between its wildcards
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChannelPattern {
+    pattern: String,
+    parts: Vec<String>,
+}
+
+impl ChannelPattern {
+    fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            parts: pattern.split('*').map(str::to_string).collect(),
+        }
+    }
+
+    fn matches(&self, channel: &str) -> bool {
+        // A pattern has at least one wildcard, so at least two parts
+        let (Some((first, rest)), Some(last)) = (self.parts.split_first(), self.parts.last()) else {
+            return false;
+        };
+        let Some(mut remaining) = channel.strip_prefix(first.as_str()) else {
+            return false;
+        };
+        for part in &rest[..rest.len() - 1] {
+            match remaining.find(part.as_str()) {
+                Some(index) => remaining = &remaining[index + part.len()..],
+                None => return false,
+            }
+        }
+        remaining.ends_with(last.as_str())
+    }
+}
+
+/// Whether a subscription is a wildcard pattern rather than a channel name
+pub fn is_pattern(subscription: &str) -> bool {
+    subscription.contains('*')
+}
+
+/// Whether `channel` is covered by `subscription`, a channel name or pattern
+pub fn channel_matches(subscription: &str, channel: &str) -> bool {
+    if is_pattern(subscription) {
+        ChannelPattern::new(subscription).matches(channel)
+    } else {
+        subscription == channel
+    }
+}
+
+/// A connection's subscriptions, compiled for matching
+#[derive(Debug, Clone, Default)]
+pub struct Subscriptions {
+    /// Subscriptions as the client gave them, in order
+    subscriptions: Vec<String>,
+    channels: HashSet<String>,
+    patterns: Vec<ChannelPattern>,
+    /// Options of the subscriptions made with any
+    options: HashMap<String, ChannelOptions>,
+}
+
+impl Subscriptions {
+    /// Add a subscription. Returns `false` if it was already present.
+    pub fn insert(&mut self, subscription: String) -> bool {
+        if self.contains(&subscription) {
+            return false;
+        }
+        if is_pattern(&subscription) {
+            self.patterns.push(ChannelPattern::new(&subscription));
+        } else {
+            self.channels.insert(subscription.clone());
+        }
+        self.subscriptions.push(subscription);
+        true
+    }
+
+    /// Remove a subscription, as given when subscribing. Channels a removed
+    /// name shares with a remaining pattern stay covered by the pattern.
+    pub fn remove(&mut self, subscription: &str) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions.retain(|s| s != subscription);
+        self.channels.remove(subscription);
+        self.patterns.retain(|pattern| pattern.pattern != subscription);
+        self.options.remove(subscription);
+        self.subscriptions.len() != before
+    }
+
+    /// Whether the connection has exactly this subscription
+    pub fn contains(&self, subscription: &str) -> bool {
+        self.subscriptions.iter().any(|s| s == subscription)
+    }
+
+    /// Whether messages on `channel` reach the connection
+    pub fn matches(&self, channel: &str) -> bool {
+        self.channels.contains(channel) || self.patterns.iter().any(|pattern| pattern.matches(channel))
+    }
+
+    /// Replace the filtering options of a subscription
+    pub fn set_options(&mut self, subscription: &str, options: ChannelOptions) {
+        if options == ChannelOptions::default() {
+            self.options.remove(subscription);
+        } else if self.contains(subscription) {
+            self.options.insert(subscription.to_string(), options);
+        }
+    }
+
+    /// Options messages on `channel` are filtered with: those of the
+    /// channel's own subscription, else of the first pattern matching it.
+    /// `None` if the channel is not subscribed.
+    pub fn options(&self, channel: &str) -> Option<ChannelOptions> {
+        let subscription = if self.channels.contains(channel) {
+            channel
+        } else {
+            &self.patterns.iter().find(|pattern| pattern.matches(channel))?.pattern
+        };
+        Some(self.options.get(subscription).copied().unwrap_or_default())
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.subscriptions.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_patterns() {
+        assert!(channel_matches("ticker.*", "ticker.BTCUSDT"));
+        assert!(!channel_matches("ticker.*", "trades.BTCUSDT"));
+        assert!(channel_matches("trades.BTC-*", "trades.BTC-USDT"));
+        assert!(!channel_matches("trades.BTC-*", "trades.ETH-BTC"));
+        assert!(channel_matches("kline.*.BTC*", "kline.1m.BTCUSDT"));
+        assert!(!channel_matches("kline.*.BTC*", "kline.1m.ETHUSDT"));
+        assert!(channel_matches("*USDT", "ticker.ETHUSDT"));
+        assert!(!channel_matches("a*a", "a"));
+        assert!(channel_matches("trades.ETHUSDT", "trades.ETHUSDT"));
+    }
+
+    #[test]
+    fn test_parse_channel() {
+        assert_eq!(parse_channel("ticker.BTCUSDT"), Some(ChannelKind::Market { symbol: "BTCUSDT" }));
+        assert_eq!(parse_channel("orderbook.delta.ETHUSDT"), Some(ChannelKind::Market { symbol: "ETHUSDT" }));
+        assert_eq!(parse_channel("kline.5m.BTCUSDT"), Some(ChannelKind::Market { symbol: "BTCUSDT" }));
+        assert_eq!(parse_channel("index.BTCUSDT"), Some(ChannelKind::Market { symbol: "BTCUSDT" }));
+        assert_eq!(parse_channel("trades.all"), Some(ChannelKind::AllSymbols));
+        assert_eq!(parse_channel("orders"), Some(ChannelKind::Private));
+        assert_eq!(parse_channel("kline.7m.BTCUSDT"), None);
+        assert_eq!(parse_channel("ticker."), None);
+        assert_eq!(parse_channel("news.BTCUSDT"), None);
+    }
+
+    #[test]
+    fn test_subscriptions_insert_and_remove() {
+        let mut subscriptions = Subscriptions::default();
+        assert!(subscriptions.insert("ticker.*".to_string()));
+        assert!(subscriptions.insert("trades.ETHUSDT".to_string()));
+        assert!(!subscriptions.insert("ticker.*".to_string()));
+        assert_eq!(subscriptions.len(), 2);
+
+        assert!(subscriptions.matches("ticker.SOLUSDT"));
+        assert!(subscriptions.matches("trades.ETHUSDT"));
+        assert!(!subscriptions.matches("trades.SOLUSDT"));
+
+        assert!(subscriptions.remove("ticker.*"));
+        assert!(!subscriptions.remove("ticker.*"));
+        assert!(!subscriptions.matches("ticker.SOLUSDT"));
+        assert_eq!(subscriptions.iter().collect::<Vec<_>>(), vec!["trades.ETHUSDT"]);
+    }
+}